@@ -0,0 +1,150 @@
+#!/usr/bin/env python3
+"""Writes the Go sysmet databases the importer is tested against to lib/metrics/fixtures/gosysmet.
+
+Written without the Go toolchain, they follow the layout of the files of the Go tool: a bbolt file whose
+`snapshots` bucket holds one MessagePack snapshot per RFC3339 key, made of the gopsutil structs with their Go
+field names.
+"""
+import datetime
+import os
+import struct
+import sys
+
+PAGE_SIZE = 4096
+MAGIC = 0xED0CDAED
+BRANCH, LEAF, META, FREELIST = 0x01, 0x02, 0x04, 0x10
+BUCKET_LEAF = 0x01
+START = datetime.datetime(2023, 1, 2, 3, 4, 0, tzinfo=datetime.timezone.utc)
+
+
+def msgpack(value):
+    if value is None:
+        return b"\xc0"
+    if isinstance(value, bool):
+        return b"\xc3" if value else b"\xc2"
+    if isinstance(value, int):
+        if 0 <= value < 0x80:
+            return bytes([value])
+        return b"\xd3" + struct.pack(">q", value) if value < 0 else b"\xcf" + struct.pack(">Q", value)
+    if isinstance(value, float):
+        return b"\xcb" + struct.pack(">d", value)
+    if isinstance(value, str):
+        data = value.encode()
+        return b"\xdb" + struct.pack(">I", len(data)) + data
+    if isinstance(value, bytes):
+        return value
+    if isinstance(value, list):
+        return b"\xdd" + struct.pack(">I", len(value)) + b"".join(map(msgpack, value))
+    if isinstance(value, dict):
+        return b"\xdf" + struct.pack(">I", len(value)) + b"".join(
+            msgpack(key) + msgpack(item) for key, item in value.items()
+        )
+    raise TypeError(value)
+
+
+def timestamp(time):
+    """MessagePack timestamp 96, the way Go encodes `time.Time`."""
+    return b"\xc7\x0c\xff" + struct.pack(">Iq", 0, int(time.timestamp()))
+
+
+def snapshot(index, cpus=True):
+    time = START + datetime.timedelta(minutes=index)
+    record = {
+        "Time": timestamp(time),
+        "Memory": {
+            "Total": 8 << 30,
+            "Available": 6 << 30,
+            "Used": (2 << 30) + index * (1 << 20),
+            "Free": 5 << 30,
+            "UsedPercent": 25.0 + index,
+            "Cached": 1 << 30,
+        },
+        "Swap": {"Total": 1 << 30, "Used": 0, "Free": 1 << 30, "UsedPercent": 0.0, "Sin": 0, "Sout": 0},
+        "Network": [
+            {"Name": "eth0", "BytesSent": 1000 * index, "BytesRecv": 4000 * index, "PacketsSent": index},
+            {"Name": "lo", "BytesSent": 50 * index, "BytesRecv": 50 * index},
+        ],
+        "Disks": [{"Path": "/", "Total": 100 << 30, "Used": 40 << 30, "Free": 60 << 30, "UsedPercent": 40.0}],
+        "Load": {"Load1": 0.5 + index / 10, "Load5": 0.4, "Load15": 0.3},
+        "Uptime": 3600 + index * 60,
+    }
+    if cpus:
+        record["CPUs"] = [
+            {"CPU": "cpu0", "User": 100.0 + 30 * index, "System": 20.0 + 6 * index, "Idle": 1000.0 + 24 * index}
+        ]
+    return time.strftime("%Y-%m-%dT%H:%M:%SZ").encode(), msgpack(record)
+
+
+def page(id, flags, count, body):
+    data = struct.pack("<QHHI", id, flags, count, 0) + body
+    assert len(data) <= PAGE_SIZE, "page overflow"
+    return data.ljust(PAGE_SIZE, b"\0")
+
+
+def leaf(id, elements):
+    """Leaf page of (flags, key, value), the position of each key being relative to its element."""
+    headers, payload = b"", b""
+    for index, (flags, key, value) in enumerate(elements):
+        position = (len(elements) - index) * 16 + len(payload)
+        headers += struct.pack("<IIII", flags, position, len(key), len(value))
+        payload += key + value
+    return page(id, LEAF, len(elements), headers + payload)
+
+
+def branch(id, children):
+    """Branch page of (first key, child page), the position of each key being relative to its element."""
+    headers, payload = b"", b""
+    for index, (key, child) in enumerate(children):
+        position = (len(children) - index) * 16 + len(payload)
+        headers += struct.pack("<IIQ", position, len(key), child)
+        payload += key
+    return page(id, BRANCH, len(children), headers + payload)
+
+
+def meta(id, root, pages, txid):
+    body = struct.pack("<IIIIQQQQQ", MAGIC, 2, PAGE_SIZE, 0, root, 0, 2, pages, txid)
+    checksum = 0xCBF29CE484222325
+    for byte in body:
+        checksum = ((checksum ^ byte) * 0x100000001B3) & 0xFFFFFFFFFFFFFFFF
+    return page(id, META, 0, body + struct.pack("<Q", checksum))
+
+
+def database(leaves, missing_child=False):
+    """bbolt file with the root bucket on page 3, the `snapshots` bucket on the branch page 4 and its
+    `leaves` from page 5, one more child past the end of the file with `missing_child`."""
+    children = [(elements[0][1], 5 + index) for index, elements in enumerate(leaves)]
+    if missing_child:
+        children.append((b"~", 99))
+    pages = 5 + len(leaves)
+    return b"".join(
+        [
+            meta(0, 3, pages, 4),
+            # NOTE: The newest meta page wins, the other one being left from the previous transaction
+            meta(1, 3, pages, 5),
+            page(2, FREELIST, 0, b""),
+            leaf(3, [(BUCKET_LEAF, b"snapshots", struct.pack("<QQ", 4, 0))]),
+            branch(4, children),
+            *(leaf(5 + index, elements) for index, elements in enumerate(leaves)),
+        ]
+    )
+
+
+def main():
+    directory = os.path.join(os.path.dirname(__file__), "..", "lib", "metrics", "fixtures", "gosysmet")
+    os.makedirs(directory, exist_ok=True)
+    records = [(0, *snapshot(index)) for index in range(6)]
+    # NOTE: 6 snapshots, one corrupt value, one snapshot without CPUs and a missing page
+    garbage = (0, b"2023-01-02T03:06:30Z", b"\xc1\xc1 not MessagePack")
+    without_cpus = (0, *snapshot(6, cpus=False))
+    files = {
+        "sysmet.db": database([records[:3], records[3:]]),
+        "partially-corrupt.db": database([records[:3] + [garbage], records[3:] + [without_cpus]], True),
+    }
+    for name, data in files.items():
+        with open(os.path.join(directory, name), "wb") as file:
+            file.write(data)
+        print(f"{name}: {len(data)} bytes", file=sys.stderr)
+
+
+if __name__ == "__main__":
+    main()
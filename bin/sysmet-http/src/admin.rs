@@ -0,0 +1,643 @@
+//! Download and upload of the raw database file for backup tooling, behind the `--admin-token`.
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::UNIX_EPOCH,
+};
+
+use axum::{
+    body::Body,
+    extract::{Extension, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use log::{debug, info, tracing, warn};
+use metrics::prelude::{BackupPolicy, Database, DatabaseKey, Error as MetricsError};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::Notify};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    api::{api_error, ApiError},
+    generator::{SharedState, SharedStats},
+};
+
+type AdminResult<T> = Result<T, (StatusCode, Json<ApiError>)>;
+
+/// Credentials and permissions of the admin routes, `--admin-token` and `--allow-upload`.
+#[derive(Clone)]
+pub struct AdminAccess {
+    pub token: String,
+    pub allow_upload: bool,
+    /// Backup of the database taken before an upload replaces it, `None` with `--no-backup`.
+    pub backup: Option<BackupPolicy>,
+}
+
+impl std::fmt::Debug for AdminAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminAccess")
+            .field("token", &"********")
+            .field("allow_upload", &self.allow_upload)
+            .field("backup", &self.backup)
+            .finish()
+    }
+}
+
+/// Database served by the admin routes and how to refresh the charts once it is replaced.
+#[derive(Debug)]
+pub(crate) struct DatabaseAdmin {
+    /// `None` without `--admin-token`, the admin routes then answer 404.
+    pub access: Option<AdminAccess>,
+    pub database: String,
+    pub key: Option<DatabaseKey>,
+    pub actualize_now: Arc<Notify>,
+    /// Only one upload is staged at a time.
+    pub uploading: tokio::sync::Mutex<()>,
+    /// Suffix of the copies of the database streamed by the downloads.
+    pub downloads: AtomicU64,
+}
+
+pub(crate) type SharedAdmin = Arc<DatabaseAdmin>;
+
+/// Whether `Authorization: Bearer <token>` holds the admin token.
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    let Some(given) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // NOTE: Compared in constant time so the token can't be guessed byte by byte
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (byte, expected)| diff | (byte ^ expected))
+            == 0
+}
+
+/// Access of an authorized request, answering 404 without `--admin-token` so the routes look missing.
+#[allow(clippy::result_large_err)]
+fn authorize<'a>(
+    admin: &'a DatabaseAdmin,
+    headers: &HeaderMap,
+) -> Result<&'a AdminAccess, Response> {
+    let access = admin.access.as_ref().ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "No admin token, see `--admin-token`").into_response()
+    })?;
+    if !is_authorized(headers, &access.token) {
+        debug!("Refused an admin request without the admin token");
+        return Err((
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            api_error(
+                StatusCode::UNAUTHORIZED,
+                "Expected `Authorization: Bearer <admin token>`",
+            ),
+        )
+            .into_response());
+    }
+
+    Ok(access)
+}
+
+/// Metadata of the database file, sharded databases being a directory of files rather than one.
+fn database_file(database: &str) -> AdminResult<Metadata> {
+    let metadata = fs::metadata(database).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => api_error(StatusCode::NOT_FOUND, "The database has no file yet"),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+    })?;
+    if metadata.is_dir() {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            "Sharded databases are a directory, back up its shards instead",
+        ));
+    }
+
+    Ok(metadata)
+}
+
+/// Tag of the content of the file from its modification time and size.
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    format!("\"{modified:x}-{:x}\"", metadata.len())
+}
+
+/// `If-None-Match` holds `etag` or `*`.
+pub(crate) fn matches_etag(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Copy of the database streamed by a download, removed once the response is dropped.
+struct StagedDownload(PathBuf);
+
+impl Drop for StagedDownload {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.0) {
+            warn!("Failed to remove the download copy {:?}: {err}", self.0);
+        }
+    }
+}
+
+#[tracing::instrument(skip(headers))]
+pub async fn download(
+    Extension(admin): Extension<SharedAdmin>,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    authorize(&admin, &headers)?;
+    let metadata = database_file(&admin.database).map_err(IntoResponse::into_response)?;
+    let tag = etag(&metadata);
+    if matches_etag(&headers, &tag) {
+        debug!(tag, "Database unchanged since the last download");
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, tag)]).into_response());
+    }
+
+    // NOTE: sysmet-update rewrites the file in place, a copy taken under the lock is streamed instead so a
+    // write during a slow download can't tear it
+    let copy = PathBuf::from(format!(
+        "{}.download.{}",
+        admin.database,
+        admin.downloads.fetch_add(1, Ordering::Relaxed)
+    ));
+    let (metadata, file) = {
+        let database = admin.database.clone();
+        let staging = copy.clone();
+        tokio::task::spawn_blocking(move || -> Result<_, MetricsError> {
+            let metadata = Database::copy_locked(&database, &staging)?;
+            let file = fs::File::open(&staging).map_err(MetricsError::FailedToOpenFile)?;
+            Ok((metadata, file))
+        })
+        .await
+        .map_err(|err| api_error(StatusCode::INTERNAL_SERVER_ERROR, err).into_response())?
+        .map_err(|err| {
+            fs::remove_file(&copy).ok();
+            database_error(err).into_response()
+        })?
+    };
+    let staged = StagedDownload(copy);
+    let tag = etag(&metadata);
+    debug!(tag, bytes = metadata.len(), "Streaming the database");
+
+    let stream = ReaderStream::new(tokio::fs::File::from_std(file)).map(move |chunk| {
+        // NOTE: Moved in the stream so the copy is only removed once it was sent
+        let _copy = &staged;
+        chunk
+    });
+    let filename = PathBuf::from(&admin.database).file_name().map_or_else(
+        || "sysmet.db".to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_LENGTH, metadata.len().to_string()),
+            (header::ETAG, tag),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    force: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadResponse {
+    snapshots: usize,
+    /// Newest snapshot of the uploaded database, `None` when it has none.
+    newest: Option<DateTime<Utc>>,
+}
+
+/// Status of a failed read or replacement of the database.
+fn database_error(err: MetricsError) -> (StatusCode, Json<ApiError>) {
+    match err {
+        MetricsError::LockFileTimeout(_) => api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The database is locked by sysmet-update, try again",
+        ),
+        err => api_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+#[tracing::instrument(skip(headers, body))]
+pub async fn upload(
+    Extension(admin): Extension<SharedAdmin>,
+    Extension(shared_state): Extension<SharedState>,
+    Extension(stats): Extension<SharedStats>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<UploadResponse>, Response> {
+    let access = authorize(&admin, &headers)?;
+    if !access.allow_upload {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            "Uploads are disabled, see `--allow-upload`",
+        )
+        .into_response());
+    }
+    let force = query.force.unwrap_or(false);
+    upload_database(&admin, access, &shared_state, &stats, force, body)
+        .await
+        .map(Json)
+        .map_err(IntoResponse::into_response)
+}
+
+async fn upload_database(
+    admin: &DatabaseAdmin,
+    access: &AdminAccess,
+    shared_state: &SharedState,
+    stats: &SharedStats,
+    force: bool,
+    body: Body,
+) -> AdminResult<UploadResponse> {
+    if fs::metadata(&admin.database).is_ok_and(|metadata| metadata.is_dir()) {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            "Sharded databases are a directory, restore its shards instead",
+        ));
+    }
+    // NOTE: The guard against older data compares with the loaded database, there is none before the first load
+    if stats.checked_at().is_none() {
+        return Err(api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The database was not loaded yet, try again",
+        ));
+    }
+    let _uploading = admin
+        .uploading
+        .try_lock()
+        .ok()
+        .ok_or_else(|| api_error(StatusCode::CONFLICT, "Another upload is in progress"))?;
+
+    // NOTE: Written next to the database so replacing it is a rename on the same filesystem
+    let staged = PathBuf::from(format!("{}.upload", admin.database));
+    let written = match write_body(&staged, body).await {
+        Ok(written) => written,
+        Err(err) => {
+            tokio::fs::remove_file(&staged).await.ok();
+            return Err(err);
+        }
+    };
+    debug!(written, "Staged the uploaded database");
+
+    let database = admin.database.clone();
+    let key = admin.key.clone();
+    let policy = access.backup.clone();
+    let current_newest = shared_state
+        .load()
+        .database
+        .time_range()
+        .map(|(_, newest)| newest);
+    let replaced = tokio::task::spawn_blocking(move || {
+        // NOTE: Decoded once then dropped before replacing the file, so it is never held twice
+        let uploaded = fs::File::open(&staged)
+            .map_err(|err| api_error(StatusCode::INTERNAL_SERVER_ERROR, err))
+            .and_then(|file| {
+                Database::load_replacement(file, key.as_ref()).map_err(|err| {
+                    api_error(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("Invalid database: {err}"),
+                    )
+                })
+            });
+        let uploaded = match uploaded {
+            Ok(uploaded) => uploaded,
+            Err(err) => {
+                fs::remove_file(&staged).ok();
+                return Err(err);
+            }
+        };
+        let response = UploadResponse {
+            snapshots: uploaded.len(),
+            newest: uploaded.time_range().map(|(_, newest)| newest),
+        };
+        drop(uploaded);
+
+        let older = current_newest.filter(|current| !force && response.newest < Some(*current));
+        if let Some(current) = older {
+            fs::remove_file(&staged).ok();
+            return Err(api_error(
+                StatusCode::CONFLICT,
+                format!(
+                    "The uploaded database ends before the current one ({current}), add `?force=true` to \
+                     replace it anyway"
+                ),
+            ));
+        }
+        Database::replace_with_staged(&staged, &database, policy.as_ref())
+            .map_err(database_error)?;
+
+        Ok(response)
+    })
+    .await
+    .map_err(|err| api_error(StatusCode::INTERNAL_SERVER_ERROR, err))??;
+
+    info!(
+        snapshots = replaced.snapshots,
+        force, "Replaced the database with an upload"
+    );
+    admin.actualize_now.notify_one();
+
+    Ok(replaced)
+}
+
+/// Stream the body to `path` chunk by chunk, returns its number of bytes.
+async fn write_body(path: &Path, body: Body) -> AdminResult<u64> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|err| api_error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let mut stream = body.into_data_stream();
+    let mut written = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|err| api_error(StatusCode::BAD_REQUEST, err))?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|err| api_error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+        written += chunk.len() as u64;
+    }
+    file.sync_all()
+        .await
+        .map_err(|err| api_error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::Request, Router};
+    use chrono::{Duration, TimeZone};
+    use metrics::demo::{DemoOptions, DEFAULT_DEMO_SEED};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::generator::SharedData;
+
+    const TOKEN: &str = "secret";
+
+    /// Demo database whose newest snapshot is `days` after the current one of the tests.
+    fn demo(days: i64, key: Option<&DatabaseKey>) -> Database {
+        let mut database = Database::demo(&DemoOptions {
+            end: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + Duration::days(days),
+            span: Duration::minutes(10),
+            interval: Duration::seconds(10),
+            seed: DEFAULT_DEMO_SEED,
+        })
+        .unwrap();
+        database.set_checksums(true);
+        database.set_key(key.cloned());
+        database
+    }
+
+    /// Bytes of the database as written to its file.
+    fn file_bytes(mut database: Database, dir: &TempDir) -> Vec<u8> {
+        let path = dir.path().join("written.db").to_string_lossy().into_owned();
+        database.write_to_file(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    struct Fixture {
+        dir: TempDir,
+        admin: SharedAdmin,
+        app: Router,
+    }
+
+    impl Fixture {
+        /// Server with the current database loaded, encrypted with `key` when given.
+        fn new(key: Option<DatabaseKey>) -> Self {
+            let dir = TempDir::new().unwrap();
+            let database = dir.path().join("sysmet.db").to_string_lossy().into_owned();
+            let mut current = demo(0, key.as_ref());
+            current.write_to_file(&database).unwrap();
+
+            let admin = SharedAdmin::new(DatabaseAdmin {
+                access: Some(AdminAccess {
+                    token: TOKEN.to_string(),
+                    allow_upload: true,
+                    backup: None,
+                }),
+                database,
+                key,
+                actualize_now: Arc::new(Notify::new()),
+                uploading: Default::default(),
+                downloads: Default::default(),
+            });
+            let shared_state = SharedState::default();
+            shared_state.store(SharedData {
+                database: Arc::new(current),
+                ..Default::default()
+            });
+            let stats = SharedStats::default();
+            stats
+                .checked_at
+                .store(Utc::now().timestamp(), Ordering::Relaxed);
+            let app = crate::routes::router()
+                .layer(Extension(admin.clone()))
+                .layer(Extension(shared_state))
+                .layer(Extension(stats));
+
+            Self { dir, admin, app }
+        }
+
+        fn database(&self) -> Vec<u8> {
+            fs::read(&self.admin.database).unwrap()
+        }
+
+        async fn send(&self, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+            let response = self.app.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, headers, body.to_vec())
+        }
+
+        async fn download(&self, if_none_match: Option<&str>) -> (StatusCode, HeaderMap, Vec<u8>) {
+            let mut request = Request::get("/admin/db/download")
+                .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"));
+            if let Some(tag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, tag);
+            }
+            self.send(request.body(Body::empty()).unwrap()).await
+        }
+
+        async fn upload(&self, query: &str, bytes: Vec<u8>) -> (StatusCode, Vec<u8>) {
+            let request = Request::put(format!("/admin/db/upload{query}"))
+                .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+                .body(Body::from(bytes))
+                .unwrap();
+            let (status, _, body) = self.send(request).await;
+            (status, body)
+        }
+
+        /// Whether the upload asked for the charts to be refreshed.
+        async fn refreshed(&self) -> bool {
+            tokio::time::timeout(
+                std::time::Duration::from_millis(10),
+                self.admin.actualize_now.notified(),
+            )
+            .await
+            .is_ok()
+        }
+
+        /// Files left next to the database, e.g. a staged upload.
+        fn leftovers(&self) -> Vec<String> {
+            fs::read_dir(self.dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| name != "sysmet.db")
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_have_caching_headers() {
+        let fixture = Fixture::new(None);
+
+        let (status, headers, body) = fixture.download(None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, fixture.database());
+        assert_eq!(
+            headers[header::CONTENT_LENGTH],
+            fixture.database().len().to_string()
+        );
+        assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+        let tag = headers[header::ETAG].to_str().unwrap().to_string();
+        assert!(tag.starts_with('"') && tag.ends_with('"'), "{tag}");
+        assert_eq!(fixture.leftovers(), Vec::<String>::new());
+
+        let (status, headers, body) = fixture.download(Some(&tag)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers[header::ETAG], tag.as_str());
+        assert!(body.is_empty());
+        let (status, ..) = fixture.download(Some("\"other\", W/*")).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        let (status, ..) = fixture.download(Some("\"other\"")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_the_token() {
+        let fixture = Fixture::new(None);
+
+        let request = Request::get("/admin/db/download")
+            .header(header::AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, _) = fixture.send(request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[header::WWW_AUTHENTICATE], "Bearer");
+
+        let request = Request::put("/admin/db/upload")
+            .body(Body::from(fixture.database()))
+            .unwrap();
+        let (status, ..) = fixture.send(request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn valid_uploads_replace_the_database() {
+        let fixture = Fixture::new(None);
+        let uploaded = file_bytes(demo(1, None), &fixture.dir);
+
+        let (status, body) = fixture.upload("", uploaded.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["snapshots"], demo(1, None).len());
+        assert_eq!(fixture.database(), uploaded);
+        assert!(fixture.refreshed().await);
+        assert_eq!(fixture.leftovers(), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn corrupt_uploads_are_rejected() {
+        let fixture = Fixture::new(None);
+        let current = fixture.database();
+
+        let (status, _) = fixture.upload("", b"not a database".to_vec()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // NOTE: The last byte is in the payload of the last snapshot, which is skipped when loading it
+        let mut corrupt = file_bytes(demo(1, None), &fixture.dir);
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        let (status, body) = fixture.upload("?force=true", corrupt).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            String::from_utf8_lossy(&body).contains("corrupt"),
+            "{}",
+            String::from_utf8_lossy(&body)
+        );
+
+        assert_eq!(fixture.database(), current);
+        assert!(!fixture.refreshed().await);
+        assert_eq!(fixture.leftovers(), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn older_uploads_need_force() {
+        let fixture = Fixture::new(None);
+        let current = fixture.database();
+        let older = file_bytes(demo(-1, None), &fixture.dir);
+
+        let (status, _) = fixture.upload("", older.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(fixture.database(), current);
+        assert!(!fixture.refreshed().await);
+
+        let (status, _) = fixture.upload("?force=true", older.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fixture.database(), older);
+    }
+
+    #[tokio::test]
+    async fn plaintext_uploads_are_rejected_with_a_key() {
+        let key = DatabaseKey::new("passphrase").unwrap();
+        let fixture = Fixture::new(Some(key.clone()));
+        let current = fixture.database();
+
+        let (status, body) = fixture
+            .upload("", file_bytes(demo(1, None), &fixture.dir))
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            String::from_utf8_lossy(&body).contains("not encrypted"),
+            "{}",
+            String::from_utf8_lossy(&body)
+        );
+        assert_eq!(fixture.database(), current);
+
+        let encrypted = file_bytes(demo(1, Some(&key)), &fixture.dir);
+        let (status, _) = fixture.upload("", encrypted.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fixture.database(), encrypted);
+    }
+}
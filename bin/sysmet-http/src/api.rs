@@ -0,0 +1,1316 @@
+use std::{collections::BTreeMap, sync::atomic::Ordering};
+
+use crate::{
+    fleet::{aggregate_step, HostSelection, Overlay, SharedFleet},
+    generator::{
+        chart_csv, points_per_snapshot, ChartsData, SharedCoalescer, SharedState, SharedStats,
+        ACTUALIZATION_INTERVAL, DEFAULT_TIME_RANGE,
+    },
+    query::{range_params, theme_param, units_params, Notices, Severity},
+    reload::SharedConfig,
+    routes::{Route, ROUTES},
+    slowlog::{SharedSlowLog, SlowOperation},
+    svg::{timestamp_to_date, TimeRange},
+    units::{AxisScale, ChartUnits},
+    usagelog::{SharedUsageLog, UsageSummary},
+    Theme, BUILD_INFO,
+};
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use env::version::BuildInfo;
+use log::{debug, tracing, warn};
+use metrics::{
+    aggregate::{aggregate_metric, Aggregate, Combine, AGGREGATE_METRICS},
+    prelude::{CollectionCounts, Database, LastWrite, MetricKind, Point, SnapShot},
+    schema::{self, HealthV1, SeriesPointV1, HEALTH_VERSION},
+};
+use serde::{Deserialize, Serialize, Serializer};
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    error: String,
+    /// Parameters of the query that could not be used.
+    #[serde(skip_serializing_if = "Notices::is_empty")]
+    notices: Notices,
+}
+
+pub(crate) fn api_error(status: StatusCode, error: impl ToString) -> (StatusCode, Json<ApiError>) {
+    (
+        status,
+        Json(ApiError {
+            error: error.to_string(),
+            notices: Notices::default(),
+        }),
+    )
+}
+
+/// Error of a query with parameters that can't be used, the API answering nothing rather than defaults.
+fn invalid_params(notices: Notices) -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ApiError {
+            error: "Invalid query parameters, see `notices`".to_string(),
+            notices,
+        }),
+    )
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotResponse {
+    requested_time: DateTime<Utc>,
+    time: DateTime<Utc>,
+    delta_seconds: i64,
+    snapshot: SnapShot,
+}
+
+impl SnapshotResponse {
+    fn new(requested_time: DateTime<Utc>, snapshot: &SnapShot) -> Self {
+        Self {
+            requested_time,
+            time: snapshot.time,
+            delta_seconds: (snapshot.time - requested_time).num_seconds(),
+            snapshot: snapshot.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotQuery {
+    at: String,
+    tolerance: Option<String>,
+}
+
+/// Accept RFC3339 dates or unix timestamps.
+pub(crate) fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    match value.parse::<i64>() {
+        Ok(timestamp) => Utc.timestamp_opt(timestamp, 0).single(),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|date| date.with_timezone(&Utc)),
+    }
+}
+
+/// Accept humantime durations or a number of seconds.
+fn parse_tolerance(value: &str) -> Option<chrono::Duration> {
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(std::time::Duration::from_secs(seconds)),
+        Err(_) => humantime::parse_duration(value).ok(),
+    }
+    .and_then(|duration| chrono::Duration::from_std(duration).ok())
+}
+
+#[tracing::instrument]
+pub async fn snapshot(
+    Query(query): Query<SnapshotQuery>,
+    Extension(shared_state): Extension<SharedState>,
+) -> ApiResult<SnapshotResponse> {
+    let requested_time = parse_time(&query.at).ok_or_else(|| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            "`at` must be a RFC3339 date or a unix timestamp",
+        )
+    })?;
+    let tolerance = query
+        .tolerance
+        .as_deref()
+        .map(|tolerance| {
+            parse_tolerance(tolerance).ok_or_else(|| {
+                api_error(
+                    StatusCode::BAD_REQUEST,
+                    "`tolerance` must be a duration or a number of seconds",
+                )
+            })
+        })
+        .transpose()?;
+
+    let shared_data = shared_state.load();
+    // NOTE: With a single snapshot there is no interval, only an exact match is accepted
+    let tolerance = tolerance
+        .or_else(|| shared_data.database.median_interval())
+        .unwrap_or_else(chrono::Duration::zero);
+    debug!(%requested_time, %tolerance);
+    let database =
+        shared_data.database_between(requested_time - tolerance, requested_time + tolerance);
+
+    database
+        .nearest_snapshot(requested_time, tolerance)
+        .map(|snapshot| Json(SnapshotResponse::new(requested_time, snapshot)))
+        .ok_or_else(|| {
+            api_error(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "No snapshot within {}s of {requested_time}",
+                    tolerance.num_seconds()
+                ),
+            )
+        })
+}
+
+#[tracing::instrument]
+pub async fn latest_snapshot(
+    Extension(shared_state): Extension<SharedState>,
+) -> ApiResult<SnapshotResponse> {
+    let shared_data = shared_state.load();
+
+    shared_data
+        .database
+        .snapshots()
+        .last()
+        .map(|snapshot| Json(SnapshotResponse::new(Utc::now(), snapshot)))
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "The database has no snapshot"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NowQuery {
+    /// `json` for a JSON object, `key=value` lines otherwise.
+    format: Option<String>,
+}
+
+/// Latest values as (key, value), serialized as a flat object in the same order.
+#[derive(Debug)]
+pub struct NowValues(Vec<(String, f64)>);
+
+impl Serialize for NowValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+impl NowValues {
+    /// Values of the newest snapshot of `database`, the ones it doesn't have being left out.
+    fn latest(database: &Database, now: DateTime<Utc>) -> Self {
+        let Some(snapshot) = database.snapshots().last() else {
+            return Self(Vec::new());
+        };
+        // NOTE: Rates need the snapshot preceding the newest one, which `series` looks up
+        let latest = |metric| {
+            database
+                .series(
+                    metric,
+                    snapshot.time..snapshot.time + chrono::Duration::seconds(1),
+                    None,
+                )
+                .last()
+                .map(|(_, value)| value)
+        };
+        let (load1, load5, load15) = snapshot.get_load();
+        let label = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let mut disks = snapshot.get_disks_size_usage();
+        disks.sort_by(|(mount, _), (other, _)| mount.cmp(other));
+        let mut disks_bytes = snapshot.get_disks_bytes_usage();
+        disks_bytes.sort_by(|(mount, _), (other, _)| mount.cmp(other));
+        let mut temperatures = snapshot.get_temperatures();
+        temperatures.sort_by(|(sensor, _), (other, _)| sensor.cmp(other));
+
+        let values = [
+            ("cpu_percent".to_string(), latest(MetricKind::CpuPercent)),
+            ("ram_percent".to_string(), latest(MetricKind::RamPercent)),
+            ("swap_percent".to_string(), latest(MetricKind::SwapPercent)),
+            ("load1".to_string(), Some(load1)),
+            ("load5".to_string(), Some(load5)),
+            ("load15".to_string(), Some(load15)),
+            ("net_rx_rate".to_string(), latest(MetricKind::NetRxRate)),
+            ("net_tx_rate".to_string(), latest(MetricKind::NetTxRate)),
+        ]
+        .into_iter()
+        .chain(disks.into_iter().map(|(mount, usage)| {
+            (
+                format!("disk_used_percent{{mount=\"{}\"}}", label(&mount)),
+                Some(usage),
+            )
+        }))
+        .chain(disks_bytes.into_iter().map(|(mount, usage)| {
+            (
+                format!("disk_free_bytes{{mount=\"{}\"}}", label(&mount)),
+                Some(usage.free as f64),
+            )
+        }))
+        .chain(temperatures.into_iter().map(|(sensor, celsius)| {
+            (
+                format!("temperature_celsius{{sensor=\"{}\"}}", label(&sensor)),
+                Some(celsius),
+            )
+        }))
+        .chain(std::iter::once((
+            "snapshot_age_seconds".to_string(),
+            Some((now - snapshot.time).num_seconds() as f64),
+        )))
+        .filter_map(|(key, value)| {
+            // NOTE: Two decimals are enough for a status bar and keep the lines short
+            let value = value.filter(|value| value.is_finite())?;
+            Some((key, (value * 100.0).round() / 100.0))
+        })
+        .collect();
+
+        Self(values)
+    }
+
+    fn to_text(&self) -> String {
+        self.0
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect()
+    }
+}
+
+/// Latest values of the cached database, as `key=value` lines or a JSON object with `format=json`.
+#[tracing::instrument]
+pub async fn now(
+    Query(query): Query<NowQuery>,
+    Extension(shared_state): Extension<SharedState>,
+) -> Response {
+    let shared_data = shared_state.load();
+    let values = NowValues::latest(&shared_data.database, Utc::now());
+    debug!(values = values.0.len());
+
+    match query.format.as_deref() {
+        Some("json") => ([(header::CACHE_CONTROL, "no-cache")], Json(values)).into_response(),
+        _ => (
+            [
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            values.to_text(),
+        )
+            .into_response(),
+    }
+}
+
+#[tracing::instrument]
+pub async fn health(
+    Extension(shared_state): Extension<SharedState>,
+    Extension(stats): Extension<SharedStats>,
+) -> (StatusCode, Json<HealthV1>) {
+    let shared_data = shared_state.load();
+    let checked_at = stats.checked_at();
+    // NOTE: Missing two checks in a row means the file is unreadable or the task is stuck, a check in the
+    //       future is not late
+    let is_ok = checked_at.is_some_and(|checked_at| {
+        (Utc::now() - checked_at)
+            .to_std()
+            .map_or(true, |age| age <= ACTUALIZATION_INTERVAL * 2)
+    });
+    debug!(?checked_at, is_ok, ready = shared_data.ready);
+
+    (
+        if is_ok && shared_data.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(HealthV1 {
+            schema_version: HEALTH_VERSION,
+            // NOTE: Only with `--strict-readiness`, the server is otherwise ready from the start
+            // NOTE: Degraded is still served, the charts only lag behind
+            status: match (shared_data.ready, is_ok) {
+                (false, _) => "starting",
+                (true, true) if stats.overran() => "degraded",
+                (true, true) => "ok",
+                (true, false) => "unavailable",
+            }
+            .to_string(),
+            checked_at,
+            last_snapshot_time: shared_data.charts.last_snapshot_time,
+            stale_since: shared_data.charts.stale_since(),
+            estimated_bytes: shared_data.charts.estimated_bytes(),
+            possibly_stale: shared_data.charts.restored_from.is_some(),
+            restored_from: shared_data.charts.restored_from,
+            last_cycle_ms: stats.last_cycle_ms.load(Ordering::Relaxed),
+        }),
+    )
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    checks: u64,
+    skipped: u64,
+    unchanged: u64,
+    reloads: u64,
+    refreshes: u64,
+    failures: u64,
+    checked_at: Option<DateTime<Utc>>,
+    snapshots: usize,
+    /// Items of each collection of the database in memory, `snapshots` included.
+    collections: CollectionCounts,
+    /// Snapshots of the last read file removed to stay within the memory budget.
+    trimmed_snapshots: u64,
+    /// Points kept in memory across the series of every chart.
+    retained_points: usize,
+    /// Rough size of the cached charts of the default range.
+    estimated_bytes: usize,
+    /// Build which last wrote the database, `None` for databases written before it was recorded.
+    last_write: Option<LastWrite>,
+    /// Page requests that shared the chart generation of an identical one.
+    coalesced_requests: u64,
+    /// Lines of the default range charts that reused points of their previous generation.
+    polyline_hits: u64,
+    /// Lines of the default range charts computed from scratch.
+    polyline_misses: u64,
+    /// Last operations slower than `--slow-op-threshold`, from the oldest.
+    slow_operations: Vec<SlowOperation>,
+    /// Duration of the last cycle of the actualization task.
+    last_cycle_ms: u64,
+    max_cycle_ms: u64,
+    /// Cycles longer than the 2 minutes between two checks of the database.
+    cycle_overruns: u64,
+    /// How late the last cycle started.
+    lag_ms: u64,
+    /// Summary of the end of the `--usage-log`, `None` without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<UsageSummary>,
+}
+
+#[tracing::instrument]
+pub async fn stats(
+    Extension(shared_state): Extension<SharedState>,
+    Extension(stats): Extension<SharedStats>,
+    Extension(coalescer): Extension<SharedCoalescer>,
+    Extension(slow_log): Extension<SharedSlowLog>,
+    Extension(usage_log): Extension<SharedUsageLog>,
+) -> Json<StatsResponse> {
+    let shared_data = shared_state.load();
+    // NOTE: Only the end of the log is read, a failure leaves the summary out
+    let usage = tokio::task::spawn_blocking(move || {
+        usage_log.as_ref().as_ref().and_then(|usage_log| {
+            usage_log
+                .summary()
+                .map_err(|err| warn!("Failed to read the usage log: {err}"))
+                .ok()
+        })
+    })
+    .await
+    .ok()
+    .flatten();
+    Json(StatsResponse {
+        checks: stats.checks.load(Ordering::Relaxed),
+        skipped: stats.skipped.load(Ordering::Relaxed),
+        unchanged: stats.unchanged.load(Ordering::Relaxed),
+        reloads: stats.reloads.load(Ordering::Relaxed),
+        refreshes: stats.refreshes.load(Ordering::Relaxed),
+        failures: stats.failures.load(Ordering::Relaxed),
+        checked_at: stats.checked_at(),
+        snapshots: shared_data.database.len(),
+        collections: shared_data.database.collection_counts(),
+        trimmed_snapshots: stats.trimmed_snapshots.load(Ordering::Relaxed),
+        retained_points: shared_data.database.len() * points_per_snapshot(),
+        estimated_bytes: shared_data.charts.estimated_bytes(),
+        last_write: shared_data.database.last_write().cloned(),
+        coalesced_requests: coalescer.coalesced(),
+        polyline_hits: stats.polyline_hits.load(Ordering::Relaxed),
+        polyline_misses: stats.polyline_misses.load(Ordering::Relaxed),
+        slow_operations: slow_log.operations(),
+        last_cycle_ms: stats.last_cycle_ms.load(Ordering::Relaxed),
+        max_cycle_ms: stats.max_cycle_ms.load(Ordering::Relaxed),
+        cycle_overruns: stats.cycle_overruns.load(Ordering::Relaxed),
+        lag_ms: stats.lag_ms.load(Ordering::Relaxed),
+        usage,
+    })
+}
+
+#[tracing::instrument]
+pub async fn version() -> Json<BuildInfo> {
+    Json(BUILD_INFO)
+}
+
+/// JSON Schema of each versioned payload, see `metrics::schema`.
+#[tracing::instrument]
+pub async fn schema() -> Json<BTreeMap<&'static str, serde_json::Value>> {
+    Json(schema::json_schemas())
+}
+
+/// Every route with its parameters, see `routes::ROUTES`.
+#[tracing::instrument]
+pub async fn routes() -> Json<&'static [Route]> {
+    Json(ROUTES)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    t: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    /// `true` for every snapshot instead of the points drawn on the chart.
+    raw: Option<String>,
+    /// `bits` or `bytes`, overrides `--network-units`.
+    network_units: Option<String>,
+    /// `si` or `iec`, overrides `--disk-units`.
+    disk_units: Option<String>,
+}
+
+/// Keep the characters usable in a file name.
+fn filename_part(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[tracing::instrument]
+pub async fn download_csv(
+    Path(file): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    Extension(shared_state): Extension<SharedState>,
+    Extension(shared_config): Extension<SharedConfig>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiError>)> {
+    let id = file
+        .strip_suffix(".csv")
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "Downloads are CSV files"))?;
+    let shared_data = shared_state.load();
+
+    // NOTE: Same range as the page, `from` and `to` take precedence over `t`
+    let mut notices = Notices::default();
+    let range = range_params(
+        &mut notices,
+        query.t.as_deref(),
+        query.from.as_deref(),
+        query.to.as_deref(),
+        DEFAULT_TIME_RANGE,
+    );
+    let range = range
+        .explicit
+        .unwrap_or_else(|| TimeRange::ending_at(shared_data.charts.range.end, range.duration));
+    let raw = notices
+        .parse(
+            "raw",
+            query.raw.as_deref(),
+            |raw| raw.parse::<bool>().ok(),
+            "expected `true` or `false`",
+            "the points drawn on the chart are written",
+        )
+        .unwrap_or(false);
+    let units = units_params(
+        &mut notices,
+        query.network_units.as_deref(),
+        query.disk_units.as_deref(),
+        shared_config.load().units,
+    );
+    if notices.has_warnings() {
+        return Err(invalid_params(notices));
+    }
+    debug!(id, ?range, raw, ?units);
+
+    let dates = range.dates();
+    let database = shared_data.database_between(dates.start, dates.end);
+    let csv = chart_csv(&database, &range, id, raw, units)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("No chart {id}")))?;
+
+    let format = |timestamp| {
+        Utc.timestamp_opt(timestamp, 0)
+            .single()
+            .map(|time| time.format("%Y%m%dT%H%MZ").to_string())
+            .unwrap_or_default()
+    };
+    let filename = format!(
+        "{}-{}-{}-{}.csv",
+        filename_part(shared_data.database.hostname().unwrap_or("sysmet")),
+        filename_part(id),
+        format(range.start),
+        format(range.end)
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        csv,
+    ))
+}
+
+/// Metric of the fleet pages without `metric`.
+const DEFAULT_AGGREGATE_METRIC: &str = "cpu";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AggregateQuery {
+    /// One of `AGGREGATE_METRICS`, `DEFAULT_AGGREGATE_METRIC` by default. A comma separated list of them
+    /// with `overlay`, all of them by default.
+    pub metric: Option<String>,
+    pub t: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// `off` leaves the line of each host out of the page, a comma separated list of hosts only reads them.
+    pub hosts: Option<String>,
+    /// `on` draws a chart per metric with a line per host instead of combining the hosts.
+    pub overlay: Option<String>,
+    pub theme: Option<String>,
+    /// Tag of the locale of the page, e.g. `de`, the API answering numbers and dates in a machine format.
+    pub locale: Option<String>,
+    /// Set by the form of the page, its unchecked checkboxes being missing from the query.
+    pub form: Option<String>,
+}
+
+/// Values of the parameters of the fleet pages, those that can't be used being replaced by their default.
+#[derive(Debug, Clone)]
+pub(crate) struct AggregateParams {
+    /// Aggregated metric, or the overlaid ones, as (name, metric).
+    pub metrics: Vec<(String, MetricKind)>,
+    pub range: TimeRange,
+    pub selection: HostSelection,
+    /// The line of each host is drawn, always when the hosts are listed.
+    pub with_hosts: bool,
+    pub overlay: bool,
+    pub theme: Theme,
+}
+
+impl AggregateQuery {
+    /// Parameters of the query with a notice for each that can't be used, the range ending now as the hosts
+    /// are read on each request.
+    pub(crate) fn parse(&self, notices: &mut Notices) -> AggregateParams {
+        let overlay = notices
+            .switch("overlay", self.overlay.as_deref(), "the hosts are combined")
+            .unwrap_or(false);
+        let metric = self
+            .metric
+            .as_deref()
+            .filter(|metric| !metric.trim().is_empty());
+        let metrics = if overlay {
+            let names = metric.map_or_else(
+                || AGGREGATE_METRICS.to_vec(),
+                |metric| {
+                    metric
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                },
+            );
+            let unknown = names
+                .iter()
+                .filter(|name| aggregate_metric(name).is_none())
+                .copied()
+                .collect::<Vec<_>>();
+            let mut metrics = names
+                .into_iter()
+                .filter_map(|name| Some((name.to_string(), aggregate_metric(name)?)))
+                .collect::<Vec<_>>();
+            if let (Some(metric), false) = (metric, unknown.is_empty()) {
+                let fallback = if metrics.is_empty() {
+                    "every metric is shown"
+                } else {
+                    "the known metrics are shown"
+                };
+                notices.push(
+                    Severity::Warning,
+                    "metric",
+                    metric,
+                    format!("no metric {}", unknown.join(", ")),
+                    fallback,
+                );
+            }
+            if metrics.is_empty() {
+                metrics = AGGREGATE_METRICS
+                    .iter()
+                    .filter_map(|name| Some((name.to_string(), aggregate_metric(name)?)))
+                    .collect();
+            }
+            metrics
+        } else {
+            let name = notices
+                .parse(
+                    "metric",
+                    metric,
+                    |metric| {
+                        AGGREGATE_METRICS
+                            .iter()
+                            .find(|name| **name == metric)
+                            .copied()
+                    },
+                    &format!("expected one of {}", AGGREGATE_METRICS.join(", ")),
+                    "the CPU usage is shown",
+                )
+                .unwrap_or(DEFAULT_AGGREGATE_METRIC);
+            vec![(
+                name.to_string(),
+                aggregate_metric(name).unwrap_or(MetricKind::CpuPercent),
+            )]
+        };
+
+        let range = range_params(
+            notices,
+            self.t.as_deref(),
+            self.from.as_deref(),
+            self.to.as_deref(),
+            DEFAULT_TIME_RANGE,
+        );
+        let selection = match self.hosts.as_deref().map(str::trim) {
+            None | Some("" | "on" | "off") => HostSelection::All,
+            Some(hosts) => HostSelection::Only(
+                hosts
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|host| !host.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            ),
+        };
+        let with_hosts = match (&selection, self.form.as_deref()) {
+            (HostSelection::Only(_), _) => true,
+            (HostSelection::All, Some("on")) => self.hosts.as_deref() == Some("on"),
+            (HostSelection::All, _) => self.hosts.as_deref() != Some("off"),
+        };
+
+        AggregateParams {
+            metrics,
+            range: range
+                .explicit
+                .unwrap_or_else(|| TimeRange::ending_at(Utc::now().timestamp(), range.duration)),
+            selection,
+            with_hosts,
+            overlay,
+            theme: theme_param(notices, self.theme.as_deref()),
+        }
+    }
+}
+
+/// Charts over `range` of every host of the fleet, `None` for an unreadable database, or no host without a
+/// fleet.
+#[allow(clippy::type_complexity)]
+#[tracing::instrument(skip(fleet))]
+pub(crate) async fn load_host_charts(
+    fleet: SharedFleet,
+    range: TimeRange,
+    units: ChartUnits,
+    scale: AxisScale,
+) -> Result<Vec<(String, Option<ChartsData>)>, (StatusCode, String)> {
+    tokio::task::spawn_blocking(move || {
+        let Some(fleet) = fleet.as_ref().as_ref() else {
+            return Ok(Vec::new());
+        };
+        let hosts = fleet
+            .hosts()
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))?;
+        Ok(hosts
+            .into_iter()
+            .map(|(hostname, database)| {
+                let charts = database.map(|database| {
+                    ChartsData::generate(&database, range, None, false, None, None, units, scale)
+                });
+                (hostname, charts)
+            })
+            .collect())
+    })
+    .await
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+}
+
+fn no_fleet() -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        "No fleet directory, see `--fleet-dir`".to_string(),
+    )
+}
+
+/// Metric of the fleet over the range of a query, read from every database again.
+#[derive(Debug)]
+pub(crate) struct FleetAggregate {
+    pub metric: String,
+    pub mode: Combine,
+    pub step: std::time::Duration,
+    pub aggregate: Aggregate,
+}
+
+/// Aggregate of the first metric of `params`, the error being the status and the message answered.
+#[tracing::instrument(skip(fleet))]
+pub(crate) async fn load_aggregate(
+    fleet: SharedFleet,
+    params: &AggregateParams,
+) -> Result<FleetAggregate, (StatusCode, String)> {
+    let (metric, kind) = params.metrics[0].clone();
+    let range = params.range;
+    let selection = params.selection.clone();
+    let mode = Combine::of(&kind);
+    let step = aggregate_step(std::time::Duration::from_secs(
+        range.duration().max(0) as u64
+    ));
+    debug!(%metric, ?range, ?mode, ?step, ?selection);
+
+    let aggregate = tokio::task::spawn_blocking(move || {
+        let fleet = fleet.as_ref().as_ref().ok_or_else(no_fleet)?;
+        fleet
+            .aggregate(&kind, range.dates(), mode, &selection)
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))
+    })
+    .await
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))??;
+
+    Ok(FleetAggregate {
+        metric,
+        mode,
+        step,
+        aggregate,
+    })
+}
+
+/// Metrics of `params` for each selected host of the fleet, read from every database again.
+#[tracing::instrument(skip(fleet))]
+pub(crate) async fn load_overlay(
+    fleet: SharedFleet,
+    params: &AggregateParams,
+) -> Result<Overlay, (StatusCode, String)> {
+    let metrics = params.metrics.clone();
+    let range = params.range;
+    let selection = params.selection.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let fleet = fleet.as_ref().as_ref().ok_or_else(no_fleet)?;
+        fleet
+            .overlay(&metrics, range.dates(), &selection)
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))
+    })
+    .await
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+}
+
+#[derive(Debug, Serialize)]
+pub struct AggregateHost {
+    hostname: String,
+    points: Vec<SeriesPointV1>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AggregateResponse {
+    metric: String,
+    /// `sum` or `average` of the values of the hosts in each bucket.
+    combine: &'static str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    /// Length of the buckets the hosts are aligned on, each point being timestamped with its start.
+    step_seconds: u64,
+    points: Vec<SeriesPointV1>,
+    hosts: Vec<AggregateHost>,
+    /// Hosts without data in the range, or whose database could not be read.
+    excluded: Vec<String>,
+    /// Parameters of the query that were left out, e.g. `t` with `from` and `to`.
+    #[serde(skip_serializing_if = "Notices::is_empty")]
+    notices: Notices,
+}
+
+/// Metric combined over the hosts of `--fleet-dir`.
+#[tracing::instrument(skip(fleet))]
+pub async fn aggregate(
+    Query(query): Query<AggregateQuery>,
+    Extension(fleet): Extension<SharedFleet>,
+) -> ApiResult<AggregateResponse> {
+    // NOTE: The overlay is only drawn by the page, the API answers the aggregate of a single metric
+    let query = AggregateQuery {
+        overlay: None,
+        ..query
+    };
+    let mut notices = Notices::default();
+    let params = query.parse(&mut notices);
+    if notices.has_warnings() {
+        return Err(invalid_params(notices));
+    }
+    let range = params.range;
+    let FleetAggregate {
+        metric,
+        mode,
+        step,
+        aggregate,
+    } = load_aggregate(fleet, &params)
+        .await
+        .map_err(|(status, error)| api_error(status, error))?;
+    let points = |points: Vec<Point>| -> Vec<SeriesPointV1> {
+        points.into_iter().map(SeriesPointV1::from).collect()
+    };
+
+    Ok(Json(AggregateResponse {
+        metric,
+        combine: mode.as_str(),
+        from: timestamp_to_date(range.start),
+        to: timestamp_to_date(range.end),
+        step_seconds: step.as_secs(),
+        points: points(aggregate.points),
+        hosts: aggregate
+            .hosts
+            .into_iter()
+            .map(|(hostname, host_points)| AggregateHost {
+                hostname,
+                points: points(host_points),
+            })
+            .collect(),
+        excluded: aggregate.excluded,
+        notices,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use metrics::prelude::Writer;
+
+    use super::*;
+    use crate::{
+        generator::SharedData,
+        locale,
+        reload::RuntimeConfig,
+        testing::{self, Server},
+    };
+
+    #[tokio::test]
+    async fn stats_expose_the_actualization_counters() {
+        let server = Server::new();
+        let stats = &server.extensions.stats;
+        stats.checks.store(6, Ordering::Relaxed);
+        stats.skipped.store(3, Ordering::Relaxed);
+        stats.unchanged.store(1, Ordering::Relaxed);
+        stats.reloads.store(2, Ordering::Relaxed);
+        stats.polyline_hits.store(40, Ordering::Relaxed);
+        stats.polyline_misses.store(5, Ordering::Relaxed);
+
+        let (status, _, body) = server.get("/stats", &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+        for (counter, value) in [
+            ("checks", 6),
+            ("skipped", 3),
+            ("unchanged", 1),
+            ("reloads", 2),
+            ("polyline_hits", 40),
+            ("polyline_misses", 5),
+        ] {
+            assert_eq!(stats[counter], value, "{counter}");
+        }
+        assert_eq!(stats["snapshots"], testing::database().len());
+        assert_eq!(
+            stats["retained_points"],
+            testing::database().len() * points_per_snapshot()
+        );
+        assert!(stats["estimated_bytes"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn charts_are_downloaded_as_csv() {
+        let server = Server::new();
+
+        let (status, headers, body) = server
+            .get(
+                "/download/cpu.csv?from=2024-05-03T11:30:00Z&to=2024-05-03T12:00:00Z",
+                &[],
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            format!(
+                "attachment; filename=\"{}-cpu-20240503T1130Z-20240503T1200Z.csv\"",
+                testing::database().hostname().unwrap_or("sysmet")
+            )
+        );
+        let rows = testing::parse_csv(&body);
+        assert_eq!(rows[0], ["timestamp", "Busy", "IO wait", "Steal"]);
+        assert!(rows.len() > 20, "{body}");
+        for row in &rows[1..] {
+            assert_eq!(row.len(), 4, "{row:?}");
+            let time = DateTime::parse_from_rfc3339(&row[0]).unwrap();
+            assert!(
+                time >= testing::end() - chrono::Duration::minutes(30) && time <= testing::end()
+            );
+            assert!(row[1].parse::<f64>().is_ok(), "{row:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_keep_a_machine_format_whatever_the_locale() {
+        let server = Server::new();
+        let uri = "/download/ram.csv?from=2024-05-03T11:30:00Z&to=2024-05-03T12:00:00Z";
+        let (_, _, iso) = server.get(uri, &[]).await;
+
+        let config = server.extensions.config.load();
+        server.extensions.config.store(RuntimeConfig {
+            locale: locale::DE,
+            ..(*config).clone()
+        });
+        let (status, _, de) = server.get(uri, &[]).await;
+        assert_eq!(status, StatusCode::OK, "{de}");
+        assert_eq!(de, iso);
+        let (_, _, fr) = server.get(&format!("{uri}&locale=fr"), &[]).await;
+        assert_eq!(fr, iso);
+
+        let rows = testing::parse_csv(&iso);
+        assert!(rows.len() > 20, "{iso}");
+        for row in &rows[1..] {
+            assert!(
+                row[1..]
+                    .iter()
+                    .all(|value| value.parse::<f64>().is_ok() && !value.contains(',')),
+                "{row:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn raw_downloads_have_every_snapshot() {
+        let server = Server::new();
+
+        let (status, _, body) = server.get("/download/load.csv?t=1h&raw=true", &[]).await;
+
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let rows = testing::parse_csv(&body);
+        assert_eq!(rows.len() - 1, testing::database().len(), "{body}");
+    }
+
+    #[tokio::test]
+    async fn unknown_charts_are_not_found() {
+        let server = Server::new();
+
+        for uri in ["/download/nothing.csv", "/download/cpu.json"] {
+            let (status, _, _) = server.get(uri, &[]).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+        let (status, _, body) = server.get("/download/cpu.csv?raw=maybe", &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    }
+
+    #[tokio::test]
+    async fn versions_describe_the_build_serving() {
+        let server = Server::new();
+
+        let (status, _, body) = server.get("/version", &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let version: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(version["name"], "sysmet-http");
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        for field in ["commit", "rustc_version", "build_timestamp"] {
+            assert!(
+                version[field]
+                    .as_str()
+                    .is_some_and(|value| !value.is_empty()),
+                "{field} of {body}"
+            );
+        }
+        assert_eq!(version.as_object().unwrap().len(), 5, "{body}");
+    }
+
+    #[tokio::test]
+    async fn stats_name_the_last_writer() {
+        let server = Server::new();
+        let (_, _, body) = server.get("/stats", &[]).await;
+        let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(stats["last_write"].is_null(), "{body}");
+
+        let mut database = testing::database();
+        database.set_writer(Writer {
+            name: "sysmet-update".to_string(),
+            version: "0.3.1".to_string(),
+            commit: "abc1234".to_string(),
+        });
+        database
+            .write_to_file(&server.dir.path().join("sysmet.db").to_string_lossy())
+            .unwrap();
+        server.extensions.state.store(SharedData {
+            database: Arc::new(database),
+            charts: Arc::new(testing::charts()),
+            ready: true,
+            ..Default::default()
+        });
+        let (_, _, body) = server.get("/stats", &[]).await;
+
+        let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            stats["last_write"]["writer"],
+            serde_json::json!({
+                "name": "sysmet-update",
+                "version": "0.3.1",
+                "commit": "abc1234",
+            })
+        );
+        assert!(stats["last_write"]["time"].is_string(), "{body}");
+    }
+
+    #[tokio::test]
+    async fn health_marks_restored_charts_as_possibly_stale() {
+        let server = Server::new();
+        let (_, _, body) = server.get("/health", &[]).await;
+        let health: HealthV1 = serde_json::from_str(&body).unwrap();
+        assert!(
+            !health.possibly_stale && health.restored_from.is_none(),
+            "{body}"
+        );
+
+        let path = server.dir.path().join("sysmet.db.viewcache");
+        crate::viewcache::save(&path, &testing::charts()).unwrap();
+        let restored = crate::viewcache::load(&path).unwrap();
+        let written_at = restored.restored_from;
+        server.extensions.state.store(SharedData {
+            database: server.extensions.state.load().database.clone(),
+            charts: Arc::new(restored),
+            ready: true,
+            ..Default::default()
+        });
+        let (status, _, body) = server.get("/health", &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let health: HealthV1 = serde_json::from_str(&body).unwrap();
+        assert!(health.possibly_stale, "{body}");
+        assert_eq!(health.restored_from, written_at);
+    }
+
+    #[tokio::test]
+    async fn stats_list_the_slow_operations() {
+        let server = Server::new();
+        let slow_log = &server.extensions.slow_log;
+        slow_log.set_threshold(std::time::Duration::from_millis(100));
+        slow_log.record("database load", std::time::Duration::from_millis(300), None);
+        slow_log.record("chart cpu", std::time::Duration::from_millis(50), None);
+
+        let (_, _, body) = server.get("/stats", &[]).await;
+
+        let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let operations = stats["slow_operations"].as_array().unwrap();
+        assert_eq!(operations.len(), 1, "{body}");
+        assert_eq!(operations[0]["name"], "database load");
+        assert_eq!(operations[0]["duration_ms"], 300);
+        assert!(operations[0]["range"].is_null());
+    }
+
+    #[test]
+    fn overlays_list_their_metrics_and_hosts() {
+        let parse = |metric: Option<&str>| {
+            let mut notices = Notices::default();
+            let params = AggregateQuery {
+                metric: metric.map(str::to_string),
+                hosts: Some("standby, primary".to_string()),
+                overlay: Some("on".to_string()),
+                ..AggregateQuery::default()
+            }
+            .parse(&mut notices);
+            let names = params
+                .metrics
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            (
+                names,
+                params,
+                notices.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            )
+        };
+
+        let (names, params, notices) = parse(Some("cpu,net-rx"));
+        assert_eq!(names, ["cpu", "net-rx"]);
+        assert!(params.overlay);
+        assert_eq!(
+            params.selection,
+            HostSelection::Only(vec!["standby".into(), "primary".into()])
+        );
+        assert!(notices.is_empty(), "{notices:?}");
+
+        assert_eq!(parse(None).0, AGGREGATE_METRICS);
+
+        let (names, _, notices) = parse(Some("cpu,gpu"));
+        assert_eq!(names, ["cpu"]);
+        assert_eq!(
+            notices,
+            ["metric=cpu,gpu: no metric gpu, the known metrics are shown."]
+        );
+        let (names, _, notices) = parse(Some("gpu"));
+        assert_eq!(names, AGGREGATE_METRICS);
+        assert_eq!(
+            notices,
+            ["metric=gpu: no metric gpu, every metric is shown."]
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_the_nearest_within_the_tolerance() {
+        let server = Server::new();
+        let snapshot = |uri: String| {
+            let server = &server;
+            async move {
+                let (status, _, body) = server.get(&uri, &[]).await;
+                (
+                    status,
+                    serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+        let at = |time: &str| format!("/api/snapshot?at={time}");
+
+        let half_past = Utc.with_ymd_and_hms(2024, 5, 3, 11, 30, 0).unwrap();
+        for uri in [
+            at("2024-05-03T11:30:00Z"),
+            at(&half_past.timestamp().to_string()),
+        ] {
+            let (status, body) = snapshot(uri).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["time"], "2024-05-03T11:30:00Z");
+            assert_eq!(body["delta_seconds"], 0);
+            assert_eq!(body["snapshot"]["time"], "2024-05-03T11:30:00Z");
+        }
+
+        for (requested, time, delta) in [
+            ("2024-05-03T11:30:20Z", "2024-05-03T11:30:00Z", -20),
+            ("2024-05-03T11:30:40Z", "2024-05-03T11:31:00Z", 20),
+            ("2024-05-03T12:00:50Z", "2024-05-03T12:00:00Z", -50),
+        ] {
+            let (status, body) = snapshot(at(requested)).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["requested_time"], requested);
+            assert_eq!(body["time"], time, "{requested}");
+            assert_eq!(body["delta_seconds"], delta, "{requested}");
+        }
+
+        // NOTE: The tolerance defaults to the median interval, a minute
+        for uri in [
+            at("2024-05-03T12:01:10Z"),
+            at("2024-05-03T10:00:00Z"),
+            format!("{}&tolerance=10", at("2024-05-03T11:30:20Z")),
+            format!("{}&tolerance=10s", at("2024-05-03T11:30:40Z")),
+        ] {
+            let (status, body) = snapshot(uri.clone()).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            assert!(
+                body["error"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("No snapshot within "),
+                "{body}"
+            );
+        }
+        let (status, body) = snapshot(format!("{}&tolerance=1h", at("2024-05-03T13:00:00Z"))).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["time"], "2024-05-03T12:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn empty_databases_have_no_snapshot() {
+        let server = Server::new();
+        server.extensions.state.store(SharedData {
+            ready: true,
+            ..Default::default()
+        });
+
+        for uri in [
+            "/api/snapshot?at=2024-05-03T12:00:00Z",
+            "/api/snapshot?at=2024-05-03T12:00:00Z&tolerance=1d",
+            "/api/snapshot/latest",
+        ] {
+            let (status, _, body) = server.get(uri, &[]).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert!(body["error"].is_string(), "{body}");
+        }
+    }
+
+    #[test]
+    fn now_keys_are_the_ones_of_the_fixture() {
+        let values = NowValues::latest(
+            &testing::database(),
+            testing::end() + chrono::Duration::seconds(30),
+        );
+
+        testing::assert_golden("now.txt", &values.to_text());
+        testing::assert_golden("now.json", &serde_json::to_string_pretty(&values).unwrap());
+    }
+
+    #[test]
+    fn temperatures_are_left_out_without_sensors() {
+        // NOTE: Whether the older snapshots then the newest one have a sensor
+        let keys = |older: bool, newest: bool| {
+            let sensors = serde_json::json!([{
+                "unit": "coretemp",
+                "label": "Package",
+                "current": { "celsius": 51.5 },
+                "max": null,
+                "crit": null,
+            }]);
+            let mut database = Database::default();
+            let snapshots = testing::database().snapshots().to_vec();
+            let count = snapshots.len();
+            for (idx, mut snapshot) in snapshots.into_iter().enumerate() {
+                if (idx + 1 == count && newest) || (idx + 1 < count && older) {
+                    snapshot.temps = serde_json::from_value(sensors.clone()).unwrap();
+                }
+                database.push_snapshot(snapshot);
+            }
+            NowValues::latest(&database, testing::end())
+                .0
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        };
+
+        let without = keys(false, false);
+        assert!(without
+            .iter()
+            .all(|key| !key.starts_with("temperature_celsius")));
+        let mut with = keys(false, true);
+        assert_eq!(
+            with.pop().as_deref(),
+            Some("snapshot_age_seconds"),
+            "{with:?}"
+        );
+        assert_eq!(
+            with.pop().as_deref(),
+            Some("temperature_celsius{sensor=\"coretemp Package\"}")
+        );
+        assert_eq!(with, without[..without.len() - 1]);
+        assert_eq!(keys(true, false), without);
+    }
+
+    #[tokio::test]
+    async fn now_is_plain_text_or_json_and_never_cached() {
+        let server = Server::new();
+
+        let (status, headers, text) = server.get("/now", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+        let lines = text
+            .lines()
+            .map(|line| line.rsplit_once('=').unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines[0].0, "cpu_percent");
+        assert_eq!(lines.last().unwrap().0, "snapshot_age_seconds");
+
+        let (status, headers, json) = server.get("/now?format=json", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+        let json: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let mut keys = lines.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(json.keys().map(String::as_str).collect::<Vec<_>>(), keys);
+    }
+
+    #[tokio::test]
+    async fn health_is_degraded_while_the_last_cycle_overran() {
+        let server = Server::new();
+        let status = || async {
+            let (_, _, body) = server.get("/health", &[]).await;
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()["status"].clone()
+        };
+        assert_eq!(status().await, "ok");
+
+        server
+            .extensions
+            .stats
+            .last_overran
+            .store(true, Ordering::Relaxed);
+
+        assert_eq!(status().await, "degraded");
+    }
+}
@@ -0,0 +1,245 @@
+//! Ticks of the time axis at round wall-clock times (UTC), e.g. every 15 minutes or at midnight, rather than
+//! at fractions of the range.
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    locale::LocaleSpec,
+    svg::{timestamp_to_x, TimeRange},
+};
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+/// Steps between two ticks in seconds, the smallest one giving at most `MAX_TICKS` ticks is used.
+///
+/// Each step is at most twice the previous one so at least 4 ticks fit in a range of 8 minutes up to 32 weeks.
+const TICK_STEPS: &[i64] = &[
+    MINUTE,
+    2 * MINUTE,
+    3 * MINUTE,
+    5 * MINUTE,
+    10 * MINUTE,
+    15 * MINUTE,
+    30 * MINUTE,
+    HOUR,
+    2 * HOUR,
+    3 * HOUR,
+    6 * HOUR,
+    12 * HOUR,
+    DAY,
+    2 * DAY,
+    4 * DAY,
+    WEEK,
+    2 * WEEK,
+    4 * WEEK,
+];
+pub(crate) const MAX_TICKS: i64 = 8;
+/// The unix epoch is a Thursday, the weeks start on the Monday after it.
+const WEEK_OFFSET: i64 = 4 * DAY;
+
+/// Step of the ticks of `range`, the largest one of the ladder for the ranges too long for it.
+pub(crate) fn tick_step(range: &TimeRange) -> i64 {
+    TICK_STEPS
+        .iter()
+        .copied()
+        .find(|step| tick_count(range, *step) <= MAX_TICKS)
+        .unwrap_or(TICK_STEPS[TICK_STEPS.len() - 1])
+}
+
+/// First round time of `range` every `step` seconds, it may be after the end of the range.
+fn first_tick(range: &TimeRange, step: i64) -> i64 {
+    // NOTE: Weeks are aligned on Mondays rather than on the Thursday of the epoch
+    let offset = if step % WEEK == 0 { WEEK_OFFSET } else { 0 };
+    let first = (range.start - offset).div_euclid(step) * step + offset;
+    if first < range.start {
+        first + step
+    } else {
+        first
+    }
+}
+
+fn tick_count(range: &TimeRange, step: i64) -> i64 {
+    let first = first_tick(range, step);
+    if first > range.end {
+        0
+    } else {
+        (range.end - first) / step + 1
+    }
+}
+
+/// Timestamps of the round times of `range` every `step` seconds, both bounds included.
+pub(crate) fn tick_times(range: &TimeRange, step: i64) -> Vec<i64> {
+    if step <= 0 {
+        return Vec::new();
+    }
+    let first = first_tick(range, step);
+    (0..tick_count(range, step))
+        .map(|index| first + index * step)
+        .collect()
+}
+
+/// Label of a tick every `step` seconds, its time of day below a day and its date otherwise.
+fn tick_label(timestamp: i64, step: i64, locale: &LocaleSpec) -> String {
+    let Some(time) = Utc.timestamp_opt(timestamp, 0).single() else {
+        return String::new();
+    };
+    if step < DAY {
+        time.format("%H:%M").to_string()
+    } else {
+        locale.short_date(time)
+    }
+}
+
+/// Ticks of the time axis of a chart, labelled when it is rendered so each request gets its locale.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeAxis {
+    /// Seconds between two ticks.
+    pub step: i64,
+    /// Ticks as (x coordinate, timestamp).
+    pub ticks: Vec<(f64, i64)>,
+}
+
+impl TimeAxis {
+    /// Ticks as (x coordinate, label).
+    pub fn labels(&self, locale: &LocaleSpec) -> Vec<(f64, String)> {
+        self.ticks
+            .iter()
+            .map(|(x, time)| (*x, tick_label(*time, self.step, locale)))
+            .collect()
+    }
+}
+
+/// Ticks of the time axis of `range`, at the same coordinates as the data.
+pub(crate) fn time_axis(range: &TimeRange) -> TimeAxis {
+    let step = tick_step(range);
+    TimeAxis {
+        step,
+        ticks: tick_times(range, step)
+            .into_iter()
+            .map(|time| (timestamp_to_x(time, range), time))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, Timelike, Weekday};
+
+    use super::*;
+    use crate::{
+        locale::{DE, ISO},
+        svg::{CHART_MAX_X, CHART_MIN_X},
+        testing,
+    };
+
+    fn range(start: i64, duration: i64) -> TimeRange {
+        TimeRange {
+            start,
+            end: start + duration,
+        }
+    }
+
+    #[test]
+    fn ticks_fall_on_round_times_within_the_range() {
+        let base = testing::end().timestamp();
+        let durations = [
+            10 * MINUTE,
+            HOUR,
+            3 * HOUR,
+            6 * HOUR,
+            12 * HOUR,
+            DAY,
+            2 * DAY,
+            WEEK,
+            30 * DAY,
+            90 * DAY,
+        ];
+        // NOTE: Ranges starting on a round time and in the middle of any step
+        let offsets = [
+            0,
+            7 * MINUTE + 13,
+            13 * HOUR + 47 * MINUTE,
+            3 * DAY + 5 * HOUR + 1,
+        ];
+
+        for duration in durations {
+            for offset in offsets {
+                let range = range(base + offset, duration);
+                let step = tick_step(&range);
+                let ticks = tick_times(&range, step);
+
+                let case = format!("{duration}s from {}: {ticks:?} every {step}s", range.start);
+                assert!((4..=MAX_TICKS as usize).contains(&ticks.len()), "{case}");
+                assert!(
+                    ticks
+                        .iter()
+                        .all(|tick| (range.start..=range.end).contains(tick)),
+                    "{case}"
+                );
+                let offset = if step % WEEK == 0 { WEEK_OFFSET } else { 0 };
+                assert!(
+                    ticks.iter().all(|tick| (tick - offset) % step == 0),
+                    "{case}"
+                );
+                assert!(
+                    ticks.windows(2).all(|pair| pair[1] - pair[0] == step),
+                    "{case}"
+                );
+                // NOTE: No round time is left out at either end
+                assert!(ticks[0] - step < range.start, "{case}");
+                assert!(ticks[ticks.len() - 1] + step > range.end, "{case}");
+            }
+        }
+    }
+
+    #[test]
+    fn six_hours_are_ticked_on_the_hours() {
+        // NOTE: 2024-05-03 13:47 to 19:47
+        let start = testing::end().timestamp() + HOUR + 47 * MINUTE;
+        let range = range(start, 6 * HOUR);
+
+        let axis = time_axis(&range);
+
+        assert_eq!(axis.step, HOUR);
+        let labels = axis
+            .labels(&ISO)
+            .into_iter()
+            .map(|(_, label)| label)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            ["14:00", "15:00", "16:00", "17:00", "18:00", "19:00"]
+        );
+    }
+
+    #[test]
+    fn days_are_ticked_at_midnight_and_weeks_on_mondays() {
+        let base = testing::end().timestamp();
+
+        let days = time_axis(&range(base, WEEK));
+        assert_eq!(days.step, DAY);
+        assert_eq!(days.labels(&DE)[0].1, "04.05.");
+
+        for (_, time) in time_axis(&range(base, 90 * DAY)).ticks {
+            let time = Utc.timestamp_opt(time, 0).unwrap();
+            assert_eq!(time.weekday(), Weekday::Mon, "{time}");
+            assert_eq!(time.num_seconds_from_midnight(), 0, "{time}");
+        }
+    }
+
+    #[test]
+    fn ticks_are_at_the_coordinates_of_the_data() {
+        let range = range(testing::end().timestamp() + 7 * MINUTE, 3 * HOUR);
+
+        let axis = time_axis(&range);
+
+        assert_eq!(axis.step, 30 * MINUTE);
+        for (x, time) in &axis.ticks {
+            assert_eq!(*x, timestamp_to_x(*time, &range));
+            assert!((CHART_MIN_X..=CHART_MAX_X).contains(x), "{x}");
+        }
+        assert!(axis.ticks.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+}
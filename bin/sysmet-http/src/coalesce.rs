@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use log::{trace, tracing};
+use tokio::{sync::OnceCell, time::Instant};
+
+/// Identical requests arriving this long after a generation started reuse its result.
+pub(crate) const COALESCE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Generation shared by the requests of a key, with the time it started.
+type Generation<V> = (Instant, Arc<OnceCell<V>>);
+
+/// Requests for the same key share a single generation started less than `COALESCE_WINDOW` ago.
+#[derive(Debug)]
+pub struct Coalescer<K, V> {
+    in_flight: Mutex<HashMap<K, Generation<V>>>,
+    coalesced: AtomicU64,
+}
+
+impl<K, V> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> Coalescer<K, V> {
+    /// Value of `key`, only generated when no generation of it can be shared.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn get_or_generate<F, Fut>(&self, key: K, generate: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let cell = {
+            // NOTE: The lock is never held across an await so a panicking generation cannot poison it
+            let mut in_flight = self
+                .in_flight
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            // NOTE: Generations abandoned by a cancelled request stay uninitialized, so they are
+            //       dropped with the finished ones once the window is over
+            in_flight.retain(|_, (started, _)| now.duration_since(*started) < COALESCE_WINDOW);
+            if let Some((_, cell)) = in_flight.get(&key) {
+                self.coalesced.fetch_add(1, Ordering::Relaxed);
+                trace!("Request coalesced");
+                cell.clone()
+            } else {
+                let cell = Arc::new(OnceCell::new());
+                in_flight.insert(key, (now, cell.clone()));
+                cell
+            }
+        };
+
+        cell.get_or_init(generate).await.clone()
+    }
+
+    /// Number of requests that shared the generation of another one.
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_generation() {
+        let coalescer = Arc::new(Coalescer::<&str, u32>::default());
+        let generations = Arc::new(AtomicUsize::new(0));
+
+        let requests = (0..20).map(|_| {
+            let coalescer = coalescer.clone();
+            let generations = generations.clone();
+            tokio::spawn(async move {
+                coalescer
+                    .get_or_generate("oneshot", || async move {
+                        generations.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        42
+                    })
+                    .await
+            })
+        });
+        for request in futures_util::future::join_all(requests).await {
+            assert_eq!(request.unwrap(), 42);
+        }
+
+        assert_eq!(generations.load(Ordering::SeqCst), 1);
+        assert_eq!(coalescer.coalesced(), 19);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abandoned_generations_are_dropped_after_the_window() {
+        let coalescer = Coalescer::<&str, u32>::default();
+
+        // The request is cancelled before its generation finishes
+        let abandoned = coalescer.get_or_generate("abandoned", std::future::pending);
+        assert!(tokio::time::timeout(Duration::from_millis(10), abandoned)
+            .await
+            .is_err());
+
+        tokio::time::advance(COALESCE_WINDOW).await;
+        assert_eq!(coalescer.get_or_generate("other", || async { 1 }).await, 1);
+        assert_eq!(coalescer.in_flight.lock().unwrap().len(), 1);
+        assert_eq!(
+            coalescer.get_or_generate("abandoned", || async { 2 }).await,
+            2
+        );
+    }
+}
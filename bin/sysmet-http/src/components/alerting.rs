@@ -0,0 +1,259 @@
+use std::{fs, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use maud::{html, Markup};
+use metrics::thresholds::state::{AlertStatus, HostState, NotifyState, SendResult};
+use typed_builder::TypedBuilder;
+
+use crate::locale::LocaleSpec;
+
+/// Age of the last check after which the state is shown as stale, `sysmet-notify` no longer running.
+pub const NOTIFY_STATE_STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// State file of `sysmet-notify` as read for the panel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifyStateFile {
+    /// The file can't be read or parsed, with the reason.
+    Missing(String),
+    Read(NotifyState),
+}
+
+impl NotifyStateFile {
+    /// Read `path` again, it is rewritten by each check.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_or_else(|err| Self::Missing(format!("invalid ({err})")), Self::Read),
+            Err(err) => Self::Missing(err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct AlertingContext {
+    pub state: NotifyStateFile,
+    #[builder(default = Utc::now())]
+    pub now: DateTime<Utc>,
+    #[builder(default = NOTIFY_STATE_STALE_AFTER)]
+    pub stale_after: Duration,
+    #[builder(default)]
+    pub locale: LocaleSpec,
+}
+
+fn format_time(time: DateTime<Utc>, locale: &LocaleSpec) -> String {
+    format!("{} UTC", locale.date_time(time))
+}
+
+/// Outcome of the last mail about a host.
+fn last_attempt_text(host: &HostState, locale: &LocaleSpec) -> String {
+    match &host.last_attempt {
+        None => "no mail sent yet".to_string(),
+        Some(attempt) => match &attempt.result {
+            SendResult::Sent => format!("mail sent {}", format_time(attempt.at, locale)),
+            SendResult::Failed { error } => {
+                format!("mail failed {}: {error}", format_time(attempt.at, locale))
+            }
+        },
+    }
+}
+
+/// State of the alerts of `sysmet-notify` per host and metric, with the end of each cooldown and the outcome
+/// of the last mail.
+pub fn AlertingPanel(context: &AlertingContext) -> Markup {
+    let stale_after =
+        chrono::Duration::from_std(context.stale_after).unwrap_or(chrono::Duration::max_value());
+    let locale = &context.locale;
+    html! {
+        section.alerting {
+            h2 { "Alerting" }
+            @match &context.state {
+                NotifyStateFile::Missing(reason) => {
+                    p.banner.notify-missing {
+                        (format!("Notify state file missing: {reason}. Alerts may not be checked at all."))
+                    }
+                }
+                NotifyStateFile::Read(state) => {
+                    @if state.is_stale(context.now, stale_after) {
+                        p.banner.notify-stale {
+                            (match state.checked_at {
+                                Some(checked_at) => format!("Notify state is stale, last check {}: sysmet-notify may no longer run.", format_time(checked_at, locale)),
+                                None => "Notify state is stale, no check recorded: sysmet-notify may no longer run.".to_string(),
+                            })
+                        }
+                    }
+                    @if state.hosts.is_empty() {
+                        p { "No host checked yet." }
+                    }
+                    @for (hostname, host) in &state.hosts {
+                        h3 { (hostname) }
+                        p.alerting-host {
+                            (match state.next_eligible(hostname, context.now) {
+                                Some(end_of_cooldown) => format!("In cooldown, next alert possible {}", format_time(end_of_cooldown, locale)),
+                                None => "An alert can be sent on the next check".to_string(),
+                            })
+                            " — " (last_attempt_text(host, locale))
+                        }
+                        @if let Some(problem) = &host.problem {
+                            p.banner { (format!("Not checked: {problem}.")) }
+                        }
+                        @if !host.metrics.is_empty() {
+                            table.alerting-metrics {
+                                thead { tr { th { "Metric" } th { "State" } th { "Last alert" } } }
+                                tbody {
+                                    @for (metric, metric_state) in &host.metrics {
+                                        @let is_alert = metric_state.status == AlertStatus::Alert;
+                                        tr.alert[is_alert] {
+                                            td { (metric) }
+                                            td { @if is_alert { "ALERT" } @else { "OK" } }
+                                            td { (metric_state.last_alert.map_or_else(|| "never".to_string(), |time| format_time(time, locale))) }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{locale::ISO, testing};
+
+    /// Panel of the state file holding `content`, checked at `testing::end()`.
+    fn panel(content: Option<&str>) -> String {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notify-state.json");
+        if let Some(content) = content {
+            fs::write(&path, content).unwrap();
+        }
+        AlertingPanel(
+            &AlertingContext::builder()
+                .state(NotifyStateFile::load(&path))
+                .now(testing::end())
+                .locale(ISO)
+                .build(),
+        )
+        .into_string()
+    }
+
+    /// State file checked `minutes_ago` with a cooldown of an hour.
+    fn state_file(minutes_ago: Option<i64>, hosts: Value) -> String {
+        json!({
+            "schema_version": 1,
+            "checked_at": minutes_ago.map(minutes_before_end),
+            "cooldown_seconds": 3600,
+            "hosts": hosts,
+        })
+        .to_string()
+    }
+
+    fn minutes_before_end(minutes: i64) -> DateTime<Utc> {
+        testing::end() - chrono::Duration::minutes(minutes)
+    }
+
+    /// Sections of the panel after the title of each host.
+    fn hosts(panel: &str) -> Vec<&str> {
+        panel.split("<h3>").skip(1).collect()
+    }
+
+    #[test]
+    fn missing_and_invalid_state_files_are_shown() {
+        let missing = panel(None);
+        assert!(missing.contains("Notify state file missing: "), "{missing}");
+        assert!(missing.contains("Alerts may not be checked at all."));
+
+        let invalid = panel(Some("{\"schema_version\":"));
+        assert!(
+            invalid.contains("Notify state file missing: invalid ("),
+            "{invalid}"
+        );
+        assert!(hosts(&invalid).is_empty());
+    }
+
+    #[test]
+    fn stale_states_are_shown_with_their_last_check() {
+        let fresh = panel(Some(&state_file(Some(5), json!({}))));
+        assert!(!fresh.contains("notify-stale"), "{fresh}");
+        assert!(fresh.contains("No host checked yet."));
+
+        let stale = panel(Some(&state_file(Some(90), json!({}))));
+        assert!(
+            stale.contains(&format!(
+                "Notify state is stale, last check {}: sysmet-notify may no longer run.",
+                format_time(minutes_before_end(90), &ISO)
+            )),
+            "{stale}"
+        );
+
+        let never_checked = panel(Some(&state_file(None, json!({}))));
+        assert!(never_checked.contains(
+            "Notify state is stale, no check recorded: sysmet-notify may no longer run."
+        ));
+    }
+
+    #[test]
+    fn hosts_show_their_cooldown_last_mail_and_metrics() {
+        let panel = panel(Some(&state_file(
+            Some(5),
+            json!({
+                "cooling": {
+                    "last_sent": minutes_before_end(10),
+                    "metrics": {
+                        "cpu": { "status": "alert", "last_alert": minutes_before_end(10) },
+                        "ram": { "status": "ok", "last_alert": null },
+                    },
+                    "last_attempt": {
+                        "at": minutes_before_end(5),
+                        "result": { "status": "failed", "error": "connection refused" },
+                    },
+                },
+                "eligible": {
+                    "last_sent": minutes_before_end(120),
+                    "metrics": {
+                        "cpu": { "status": "ok", "last_alert": minutes_before_end(120) },
+                    },
+                    "problem": "database older than 10 minutes",
+                    "last_attempt": {
+                        "at": minutes_before_end(120),
+                        "result": { "status": "sent" },
+                    },
+                },
+                "quiet": {},
+            }),
+        )));
+        let hosts = hosts(&panel);
+        let [cooling, eligible, quiet] = hosts[..] else {
+            panic!("{panel}")
+        };
+
+        assert!(cooling.starts_with("cooling</h3>"));
+        assert!(cooling.contains(&format!(
+            "In cooldown, next alert possible {} — mail failed {}: connection refused",
+            format_time(minutes_before_end(10) + chrono::Duration::hours(1), &ISO),
+            format_time(minutes_before_end(5), &ISO)
+        )));
+        assert!(cooling.contains(&format!(
+            "<tr class=\"alert\"><td>cpu</td><td>ALERT</td><td>{}</td></tr>",
+            format_time(minutes_before_end(10), &ISO)
+        )));
+        assert!(cooling.contains("<td>ram</td><td>OK</td><td>never</td>"));
+        assert!(!cooling.contains("Not checked"));
+
+        assert!(eligible.contains(&format!(
+            "An alert can be sent on the next check — mail sent {}",
+            format_time(minutes_before_end(120), &ISO)
+        )));
+        assert!(eligible.contains("Not checked: database older than 10 minutes."));
+        assert!(eligible.contains("<td>cpu</td><td>OK</td>"));
+
+        assert!(quiet.contains("An alert can be sent on the next check — no mail sent yet"));
+        assert!(!quiet.contains("<table"));
+    }
+}
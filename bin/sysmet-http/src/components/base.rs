@@ -1,19 +1,72 @@
+use std::time::Duration;
+
 use maud::{html, Markup, DOCTYPE};
 use typed_builder::TypedBuilder;
 
-use crate::{components::Head, HeadContext, WEBSITE_TITLE};
+use crate::{components::Head, embedded_stylesheets, HeadContext, CRITICAL_CSS, WEBSITE_TITLE};
+
+/// Color scheme of the pages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Theme of a `theme` query parameter, `None` for an unknown one.
+    pub fn from_query(value: &str) -> Option<Self> {
+        match value {
+            "light" => Some(Self::Light),
+            "dark" => Some(Self::Dark),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Light => "light",
+            Self::Dark => "dark",
+        }
+    }
+
+    /// Background color of the pages, the one of the styles.
+    pub fn background(self) -> &'static str {
+        match self {
+            Self::Light => "#fff",
+            Self::Dark => "#161616",
+        }
+    }
+}
 
-#[derive(Debug, Default, TypedBuilder)]
+#[derive(Debug, TypedBuilder)]
 pub struct BaseContext {
+    /// Reload the page after this long.
+    #[builder(default)]
+    pub refresh_every: Option<Duration>,
+    #[builder(default = WEBSITE_TITLE.to_string(), setter(into))]
+    pub title: String,
+    #[builder(default)]
+    pub theme: Theme,
+    /// Prefix of the links of the page, e.g. `/sysmet`.
+    #[builder(default, setter(into))]
+    pub base_path: String,
+    /// Stylesheets as (path under `{base_path}/css`, integrity hash).
+    #[builder(default = embedded_stylesheets())]
+    pub stylesheets: Vec<(String, String)>,
+    /// Styles inlined ahead of the linked stylesheets.
+    #[builder(default = CRITICAL_CSS.as_str())]
+    pub critical_css: &'static str,
+    /// Embed the stylesheets in the page instead of linking them.
     #[builder(default = false)]
-    pub refresh_every_minute: bool,
+    pub inline_stylesheets: bool,
 }
 
 pub fn Base(context: BaseContext, children: Markup) -> Markup {
     html! {
         (DOCTYPE)
-        html {
-            (Head(HeadContext::builder().refresh_every_minute(context.refresh_every_minute).build(), WEBSITE_TITLE))
+        html data-theme=(context.theme.as_str()) {
+            (Head(HeadContext::builder().refresh_every(context.refresh_every).base_path(context.base_path).stylesheets(context.stylesheets).critical_css(context.critical_css).inline_stylesheets(context.inline_stylesheets).build(), &context.title))
             body {
                 main .container { (children) }
             }
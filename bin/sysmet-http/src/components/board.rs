@@ -0,0 +1,115 @@
+use maud::{html, Markup};
+use typed_builder::TypedBuilder;
+
+use crate::{
+    locale::LocaleSpec,
+    svg::{CHART_MIN_X, CHART_MIN_Y, CHART_X_RATIO, CHART_Y_RATIO},
+    ChartContext, ChartKind, ChartsData,
+};
+
+/// Columns of the board without `cols`.
+pub const DEFAULT_BOARD_COLUMNS: usize = 4;
+/// Most columns of the board, past it the tiles are too small to read from afar.
+pub const MAX_BOARD_COLUMNS: usize = 12;
+/// Percent from which a tile of a chart in percent is shown as a warning.
+const WARNING_PERCENT: f64 = 75.0;
+/// Percent from which a tile of a chart in percent is shown as critical.
+const CRITICAL_PERCENT: f64 = 90.0;
+
+/// State of a tile, shown by its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileStatus {
+    Ok,
+    Warning,
+    Critical,
+    /// The end of the range has no data.
+    Stale,
+}
+
+impl TileStatus {
+    /// Status of a chart, only the charts in percent can be warning or critical as the others have no max.
+    pub fn of(ctx: &ChartContext) -> Self {
+        if ctx.no_data_from.is_some() {
+            return Self::Stale;
+        }
+        let current = ctx
+            .data
+            .iter()
+            .filter_map(|line| line.stats)
+            .map(|stats| stats.current)
+            .fold(f64::MIN, f64::max);
+        match ctx.unit.as_str() {
+            "%" if current >= CRITICAL_PERCENT => Self::Critical,
+            "%" if current >= WARNING_PERCENT => Self::Warning,
+            _ => Self::Ok,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+            Self::Stale => "stale",
+        }
+    }
+}
+
+/// Lines of a chart without its grid and labels, stretched to the size of its tile.
+pub fn Sparkline(ctx: &ChartContext) -> Markup {
+    html! {
+        svg.sparkline xmlns="http://www.w3.org/2000/svg" viewBox=(format!("{CHART_MIN_X} {CHART_MIN_Y} {CHART_X_RATIO} {CHART_Y_RATIO}")) preserveAspectRatio="none" aria-hidden="true" {
+            @for line in &ctx.collections {
+                @match ctx.kind {
+                    ChartKind::Lines => {
+                        polyline fill="none" stroke=(line.color.as_str()) stroke-width="3" vector-effect="non-scaling-stroke" points=(line.points.to_polyline()) {}
+                    }
+                    ChartKind::StackedArea => {
+                        path fill=(line.color.as_str()) fill-opacity="0.6" stroke="none" d=(line.points.to_path()) {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, TypedBuilder)]
+pub struct BoardContext<'a> {
+    pub data: &'a ChartsData,
+    #[builder(default = DEFAULT_BOARD_COLUMNS)]
+    pub columns: usize,
+    #[builder(default)]
+    pub locale: LocaleSpec,
+}
+
+/// Grid of one tile per chart with data, each with the current values, the sparkline of the range and the
+/// status color.
+pub fn Board(ctx: BoardContext<'_>) -> Markup {
+    let value = |value: f64, unit: &str| format!("{}{unit}", ctx.locale.number(value, 1));
+
+    html! {
+        div.board style=(format!("grid-template-columns: repeat({}, minmax(0, 1fr))", ctx.columns.clamp(1, MAX_BOARD_COLUMNS))) {
+            @for (_title, chart) in ctx.data.metrics.iter().filter(|(_, chart)| chart.has_data()) {
+                @let status = TileStatus::of(chart);
+                @let lines = chart.data.iter().filter_map(|line| Some((line, line.stats?))).collect::<Vec<_>>();
+                article.tile.(status.as_str()) id=(chart.id) aria-label=(chart.summary(&ctx.locale)) {
+                    h2 { (chart.title) }
+                    @if let Some((_, stats)) = lines.first() {
+                        p.tile-value { (value(stats.current, &chart.unit)) }
+                    } @else {
+                        p.tile-value { "-" }
+                    }
+                    // NOTE: The first line is the big value, the label of each one tells them apart
+                    @if lines.len() > 1 {
+                        ul.tile-lines {
+                            @for (line, stats) in &lines {
+                                li { (line.label) " " (value(stats.current, &chart.unit)) }
+                            }
+                        }
+                    }
+                    (Sparkline(chart))
+                }
+            }
+        }
+    }
+}
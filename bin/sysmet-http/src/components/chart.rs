@@ -1,51 +1,889 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, mem::size_of};
 
+use chrono::{TimeZone, Utc};
 use log::tracing;
 use maud::{html, Markup};
+use metrics::stats::time_weighted_mean;
+use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 
-use crate::svg::{
-    round_to_len, CHART_MIN_X, LABELS_OFFSET, SVG_MAX_X, SVG_MAX_Y, SVG_MIN_X, SVG_MIN_Y,
+use crate::{
+    axis::TimeAxis,
+    locale::LocaleSpec,
+    palette::LineColor,
+    svg::{
+        coordinate_decimals, round_to_len, simplify_points, CHART_MAX_X, CHART_MAX_Y, CHART_MIN_X,
+        CHART_MIN_Y, CHART_Y_RATIO, LABELS_OFFSET, POINT_TOLERANCE, SVG_MAX_X, SVG_MAX_Y,
+        SVG_MIN_X, SVG_MIN_Y,
+    },
+    units::UnitSystem,
 };
 
 pub type ChartValue<T> = (f64, i64, T);
-pub type ChartLine = (String, Option<String>, String);
+/// Coordinates of the points of a line, or of the outline of an area, formatted by the chart.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChartPoints(pub Vec<(f64, f64)>);
 
-#[derive(Debug, Default, Clone, TypedBuilder)]
+impl ChartPoints {
+    /// Points without those drawn over by their neighbours within `POINT_TOLERANCE`, rounded to the decimals
+    /// of `coordinate_decimals` for the number of points before dropping any.
+    pub fn compact(self) -> Self {
+        let decimals = coordinate_decimals(self.0.len());
+        Self(
+            simplify_points(&self.0, POINT_TOLERANCE)
+                .into_iter()
+                .map(|(x, y)| (round_to_len(x, decimals), round_to_len(y, decimals)))
+                .collect(),
+        )
+    }
+
+    /// Points of a polyline, e.g. `144,15 1000,150`.
+    pub fn to_polyline(&self) -> String {
+        self.0
+            .iter()
+            .map(|(x, y)| format!("{x},{y}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Closed path going through the points, e.g. `M 144,15 L 1000,150 L 1000,285 Z`.
+    pub fn to_path(&self) -> String {
+        format!(
+            "M {} Z",
+            self.0
+                .iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect::<Vec<_>>()
+                .join(" L ")
+        )
+    }
+
+    /// Points of the `points` of a polyline or of the `d` of a path made of `M`, `L` and `Z` commands.
+    pub fn parse(svg: &str) -> Result<Self, String> {
+        svg.split_whitespace()
+            .filter(|part| !matches!(*part, "M" | "L" | "Z"))
+            .map(|point| {
+                point
+                    .split_once(',')
+                    .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+                    .ok_or_else(|| format!("{point} is not a x,y point"))
+            })
+            .collect::<Result<_, _>>()
+            .map(ChartPoints)
+    }
+}
+
+/// Line of a chart, its color being validated and its label escaped like any text of the markup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChartLine {
+    pub color: LineColor,
+    pub label: Option<String>,
+    /// Points of the polyline, or outline of the area of stacked charts.
+    pub points: ChartPoints,
+    /// Anomalous points when they were searched.
+    pub anomalies: Option<Vec<(f64, f64)>>,
+    /// Points of the line before smoothing, drawn faintly behind it.
+    #[serde(default)]
+    pub raw: Option<ChartPoints>,
+    /// Points above the axis clipped to the top of the chart as (x, y, value before clipping).
+    #[serde(default)]
+    pub clipped: Vec<(f64, f64, f64)>,
+}
+
+impl ChartLine {
+    pub fn new(color: LineColor, label: Option<String>, points: ChartPoints) -> Self {
+        Self {
+            color,
+            label,
+            points,
+            anomalies: None,
+            raw: None,
+            clipped: Vec::new(),
+        }
+    }
+}
+
+/// Line given as (color, label, svg points or path, anomalous points), the former form of the lines.
+impl TryFrom<(String, Option<String>, String, Option<Vec<(f64, f64)>>)> for ChartLine {
+    type Error = String;
+
+    fn try_from(
+        (color, label, points, anomalies): (
+            String,
+            Option<String>,
+            String,
+            Option<Vec<(f64, f64)>>,
+        ),
+    ) -> Result<Self, Self::Error> {
+        Ok(Self {
+            color: color.parse()?,
+            label,
+            points: ChartPoints::parse(&points)?,
+            anomalies,
+            raw: None,
+            clipped: Vec::new(),
+        })
+    }
+}
+
+/// Min/max band of a line as (color, svg path).
+pub type ChartBand = (String, String);
+
+/// Max number of rows of the data table of a chart.
+pub const MAX_TABLE_ROWS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LineStats {
+    /// Last value of the line.
+    pub current: f64,
+    pub peak: f64,
+    /// Weighted by the time each value covers, see `metrics::stats::time_weighted_mean`.
+    pub average: f64,
+    // NOTE: Missing from the view caches written before it was introduced, until the next generation
+    #[serde(default)]
+    pub min: f64,
+}
+
+impl LineStats {
+    /// Stats of the values as (value, timestamp) sorted by time.
+    pub fn from_values(values: &[(f64, i64)]) -> Option<Self> {
+        let (current, _) = *values.last()?;
+        let samples = values
+            .iter()
+            .filter_map(|(value, timestamp)| {
+                Some((*value, Utc.timestamp_opt(*timestamp, 0).single()?))
+            })
+            .collect::<Vec<_>>();
+        let values = values.iter().map(|(value, _)| *value);
+        Some(Self {
+            current,
+            peak: values.clone().fold(f64::MIN, f64::max),
+            average: time_weighted_mean(&samples, None)?,
+            min: values.fold(f64::MAX, f64::min),
+        })
+    }
+}
+
+/// Values of a line kept for the text alternatives of the chart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LineData {
+    pub label: String,
+    /// Computed on every value of the line.
+    pub stats: Option<LineStats>,
+    /// Values as (value, timestamp), strided to at most `MAX_TABLE_ROWS`.
+    pub values: Vec<(f64, i64)>,
+}
+
+/// Every n-th item so at most `max` items are kept, the last one always being kept.
+pub fn stride<T: Copy>(values: &[T], max: usize) -> Vec<T> {
+    if values.len() <= max {
+        return values.to_vec();
+    }
+    if max == 0 {
+        return Vec::new();
+    }
+
+    let step = values.len().div_ceil(max);
+    // NOTE: Walked from the end so the current value is part of the table
+    let mut result = values
+        .iter()
+        .rev()
+        .step_by(step)
+        .copied()
+        .collect::<Vec<_>>();
+    result.reverse();
+    result
+}
+
+/// How the collections of a chart are drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChartKind {
+    /// Each collection is a polyline.
+    #[default]
+    Lines,
+    /// Each collection is a closed path stacked on top of the previous ones.
+    StackedArea,
+}
+
+#[derive(Debug, Default, TypedBuilder, Serialize, Deserialize)]
 pub struct ChartContext {
+    #[builder(default)]
+    pub kind: ChartKind,
     pub collections: Vec<ChartLine>,
     pub max_value: f64,
     #[builder(default = "%".to_string(), setter(into))]
     pub unit: String,
+    /// Unit system the values were converted to, `None` for charts not measured in bytes.
+    #[builder(default)]
+    pub unit_system: Option<UnitSystem>,
+    /// X coordinate from which the end of the range has no data.
+    #[builder(default)]
+    pub no_data_from: Option<f64>,
+    /// Collections of the compared range, drawn under the current ones.
+    #[builder(default)]
+    pub compared_collections: Vec<ChartLine>,
+    #[builder(default)]
+    pub compared_label: Option<String>,
+    /// Identifier of the chart in URLs, e.g. `cpu`.
+    #[builder(default, setter(into))]
+    pub id: String,
+    #[builder(default, setter(into))]
+    pub title: String,
+    /// Note shown under the title, e.g. the changes of the number of CPUs during the range.
+    #[builder(default)]
+    #[serde(default)]
+    pub subtitle: Option<String>,
+    /// Displayed range, e.g. "3 hours".
+    #[builder(default, setter(into))]
+    pub range_label: String,
+    #[builder(default)]
+    pub data: Vec<LineData>,
+    /// Events of the range as (x coordinate, description).
+    #[builder(default)]
+    pub annotations: Vec<(f64, String)>,
+    /// Suspensions of the host during the range as (start x, end x, description), shaded like missing data.
+    #[builder(default)]
+    #[serde(default)]
+    pub suspensions: Vec<(f64, f64, String)>,
+    /// Round times of the range, see `axis::time_axis`.
+    #[builder(default)]
+    #[serde(default)]
+    pub time_axis: TimeAxis,
+    /// Draw the band between the min and max of the values averaged in each point.
+    #[builder(default = false)]
+    pub show_band: bool,
+    /// Bands of the current collections, only generated with `show_band`.
+    #[builder(default)]
+    pub bands: Vec<ChartBand>,
+    /// Lowest rate telling more than the steps of the counters it is computed from, the rates below it are
+    /// drawn at zero under a baseline at its level.
+    #[builder(default)]
+    #[serde(default)]
+    pub noise_floor: Option<f64>,
+}
+
+impl ChartContext {
+    /// Whether a line has values in the range or in the compared one.
+    pub fn has_data(&self) -> bool {
+        !self.collections.is_empty() || !self.compared_collections.is_empty()
+    }
+
+    /// Rough number of heap bytes held by the chart, the markup it renders to is not kept.
+    pub fn estimated_bytes(&self) -> usize {
+        let line_bytes = |line: &ChartLine| {
+            line.color.as_str().len()
+                + line.label.as_ref().map_or(0, String::capacity)
+                + line.points.0.capacity() * size_of::<(f64, f64)>()
+                + line.anomalies.as_ref().map_or(0, |anomalies| {
+                    anomalies.capacity() * size_of::<(f64, f64)>()
+                })
+                + line
+                    .raw
+                    .as_ref()
+                    .map_or(0, |raw| raw.0.capacity() * size_of::<(f64, f64)>())
+                + line.clipped.capacity() * size_of::<(f64, f64, f64)>()
+        };
+
+        size_of::<Self>()
+            + self
+                .collections
+                .iter()
+                .chain(&self.compared_collections)
+                .map(|line| size_of::<ChartLine>() + line_bytes(line))
+                .sum::<usize>()
+            + self
+                .data
+                .iter()
+                .map(|line| {
+                    size_of::<LineData>()
+                        + line.label.capacity()
+                        + line.values.capacity() * size_of::<(f64, i64)>()
+                })
+                .sum::<usize>()
+            + self
+                .annotations
+                .iter()
+                .map(|(_, text)| size_of::<(f64, String)>() + text.capacity())
+                .sum::<usize>()
+            + self
+                .suspensions
+                .iter()
+                .map(|(_, _, text)| size_of::<(f64, f64, String)>() + text.capacity())
+                .sum::<usize>()
+            + self.time_axis.ticks.capacity() * size_of::<(f64, i64)>()
+            + self
+                .bands
+                .iter()
+                .map(|(color, path)| size_of::<ChartBand>() + color.capacity() + path.capacity())
+                .sum::<usize>()
+            + self.unit.capacity()
+            + self.id.capacity()
+            + self.title.capacity()
+            + self.subtitle.as_ref().map_or(0, String::capacity)
+            + self.range_label.capacity()
+    }
+
+    fn spoken_value(&self, value: f64, locale: &LocaleSpec) -> String {
+        match self.unit.as_str() {
+            "%" => format!("{} percent", locale.number(value, 1)),
+            "" => locale.number(value, 1),
+            unit => format!("{} {unit}", locale.number(value, 1)),
+        }
+    }
+
+    /// Text alternative of the chart, e.g. "CPU Usage, last 3 hours, currently 23 percent, peak 91 percent".
+    pub fn summary(&self, locale: &LocaleSpec) -> String {
+        let lines = self
+            .data
+            .iter()
+            .filter_map(|line| line.stats.map(|stats| (line, stats)))
+            .map(|(line, stats)| {
+                let values = format!(
+                    "currently {}, peak {}",
+                    self.spoken_value(stats.current, locale),
+                    self.spoken_value(stats.peak, locale)
+                );
+                // NOTE: The label is redundant when there is a single line
+                if self.data.len() == 1 {
+                    values
+                } else {
+                    format!("{} {values}", line.label)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let anomalies = self.anomalies_count();
+        let mut summary = self.title.clone();
+        if !self.range_label.is_empty() {
+            summary.push_str(&format!(", last {}", self.range_label));
+        }
+        if lines.is_empty() {
+            summary.push_str(", no data");
+        } else {
+            summary.push_str(", ");
+            summary.push_str(&lines.join("; "));
+        }
+        if anomalies > 0 {
+            let plural = if anomalies == 1 { "" } else { "s" };
+            summary.push_str(&format!(", {anomalies} anomalous point{plural}"));
+        }
+        let clipped = self
+            .collections
+            .iter()
+            .flat_map(|line| line.clipped.iter().map(|(_, _, value)| *value))
+            .collect::<Vec<_>>();
+        if let Some(highest) = clipped.iter().copied().reduce(f64::max) {
+            let plural = if clipped.len() == 1 { "" } else { "s" };
+            summary.push_str(&format!(
+                ", {} point{plural} above the chart up to {}",
+                clipped.len(),
+                self.spoken_value(highest, locale)
+            ));
+        }
+        if let Some(noise_floor) = self.noise_floor {
+            summary.push_str(&format!(
+                ", rates below {} are not measurable",
+                self.spoken_value(noise_floor, locale)
+            ));
+        }
+        summary
+    }
+
+    /// Noise floor with its y coordinate, `None` without one or when it is above the chart.
+    fn noise_floor_line(&self) -> Option<(f64, f64)> {
+        self.noise_floor
+            .filter(|noise_floor| self.max_value > 0.0 && *noise_floor <= self.max_value)
+            .map(|noise_floor| {
+                (
+                    noise_floor,
+                    CHART_MAX_Y - noise_floor / self.max_value * CHART_Y_RATIO,
+                )
+            })
+    }
+
+    /// Number of anomalous points of the current collections.
+    pub fn anomalies_count(&self) -> usize {
+        self.collections
+            .iter()
+            .filter_map(|line| line.anomalies.as_ref())
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Rows of the data table as (timestamp, value of each line), at most `MAX_TABLE_ROWS`.
+    pub fn table_rows(&self) -> Vec<(i64, Vec<Option<f64>>)> {
+        let mut timestamps = self
+            .data
+            .iter()
+            .flat_map(|line| line.values.iter().map(|(_, timestamp)| *timestamp))
+            .collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        timestamps.dedup();
+
+        stride(&timestamps, MAX_TABLE_ROWS)
+            .into_iter()
+            .map(|timestamp| {
+                let values = self
+                    .data
+                    .iter()
+                    .map(|line| {
+                        line.values
+                            .iter()
+                            .find(|(_, time)| *time == timestamp)
+                            .map(|(value, _)| *value)
+                    })
+                    .collect();
+                (timestamp, values)
+            })
+            .collect()
+    }
+}
+
+/// SVG of the chart alone, its presentation attributes style it without the stylesheet.
+#[tracing::instrument(level = "debug", skip(ctx), fields(unit = ctx.unit))]
+pub fn ChartSvg(ctx: &ChartContext, locale: &LocaleSpec) -> Markup {
+    let mid_value = round_to_len(ctx.max_value / 2.0, 2);
+    let summary = ctx.summary(locale);
+    html! {
+        svg.chart xmlns="http://www.w3.org/2000/svg" viewBox=(format!("{SVG_MIN_X} {SVG_MIN_Y} {SVG_MAX_X} {SVG_MAX_Y}")) role="img" aria-label=(summary) {
+            desc { (summary) }
+            g.grid.x-grid stroke="#aaa" stroke-width="1.5" {
+                line x1=(CHART_MIN_X) y1="5%" x2="100%" y2="5%" {}
+                line x1=(CHART_MIN_X) y1="50%" x2="100%" y2="50%" {}
+                line x1=(CHART_MIN_X) y1="95%" x2="100%" y2="95%" {}
+            }
+            g.grid.time-grid stroke="#aaa" stroke-width="1" stroke-opacity="0.5" {
+                @for (x, _) in &ctx.time_axis.ticks {
+                    line x1=(x) y1=(CHART_MIN_Y) x2=(x) y2=(CHART_MAX_Y) {}
+                }
+            }
+            g.labels.time-labels text-anchor="middle" font-size="12" {
+                @for (x, label) in ctx.time_axis.labels(locale) {
+                    text x=(x) y=(SVG_MAX_Y) dy="-1" { (label) }
+                }
+            }
+            g.labels.x-labels text-anchor="end" {
+                text x=(LABELS_OFFSET) y="5%" dy="6" { (format!("{}{}", locale.number(ctx.max_value, 2), ctx.unit)) }
+                text x=(LABELS_OFFSET) y="50%" dy="6" { (format!("{}{}", locale.number(mid_value, 2), ctx.unit)) }
+                text x=(LABELS_OFFSET) y="95%" dy="6" { (format!("0{}", ctx.unit)) }
+            }
+            g.annotations {
+                @for (x, text) in &ctx.annotations {
+                    line.annotation x1=(x) y1=(CHART_MIN_Y) x2=(x) y2=(CHART_MAX_Y) stroke="#555" stroke-width="2" stroke-dasharray="2 4" {
+                        title { (text) }
+                    }
+                }
+            }
+            @if let Some(no_data_from) = ctx.no_data_from {
+                rect.no-data x=(no_data_from) y=(CHART_MIN_Y) width=((CHART_MAX_X - no_data_from)) height=(CHART_Y_RATIO) fill="#888" fill-opacity="0.15" {
+                    title { "No data" }
+                }
+            }
+            @for (start, end, text) in &ctx.suspensions {
+                rect.no-data.suspended x=(start) y=(CHART_MIN_Y) width=((end - start)) height=(CHART_Y_RATIO) fill="#888" fill-opacity="0.15" {
+                    title { (text) }
+                }
+            }
+            @match ctx.kind {
+                ChartKind::Lines => {
+                    g.lines.compared {
+                        @for line in &ctx.compared_collections {
+                            polyline.dataline fill="none" stroke=(line.color.as_str()) stroke-width="2" stroke-opacity="0.5" stroke-dasharray="8 6" points=(line.points.to_polyline()) {}
+                        }
+                    }
+                    @if ctx.show_band {
+                        g.bands {
+                            @for (color, path) in &ctx.bands {
+                                path.databand fill=(color) fill-opacity="0.5" stroke="none" d=(path) {}
+                            }
+                        }
+                    }
+                    g.lines.raw {
+                        @for line in &ctx.collections {
+                            @if let Some(raw) = &line.raw {
+                                polyline.dataline fill="none" stroke=(line.color.as_str()) stroke-width="1" stroke-opacity="0.35" points=(raw.to_polyline()) {}
+                            }
+                        }
+                    }
+                    g.lines {
+                        @for line in &ctx.collections {
+                            polyline.dataline fill="none" stroke=(line.color.as_str()) stroke-width="2" points=(line.points.to_polyline()) {}
+                        }
+                    }
+                }
+                ChartKind::StackedArea => {
+                    // NOTE: Compared areas are only outlined so the current ones stay readable
+                    g.areas.compared {
+                        @for line in &ctx.compared_collections {
+                            path.dataarea fill="none" stroke=(line.color.as_str()) stroke-width="2" stroke-opacity="0.5" stroke-dasharray="8 6" d=(line.points.to_path()) {}
+                        }
+                    }
+                    g.areas {
+                        @for line in &ctx.collections {
+                            path.dataarea fill=(line.color.as_str()) fill-opacity="0.6" stroke=(line.color.as_str()) stroke-width="1" d=(line.points.to_path()) {}
+                        }
+                    }
+                    // NOTE: Over the areas, they would hide the bands along their top edge
+                    @if ctx.show_band {
+                        g.bands {
+                            @for (color, path) in &ctx.bands {
+                                path.databand fill=(color) fill-opacity="0.5" stroke="none" d=(path) {}
+                            }
+                        }
+                    }
+                }
+            }
+            // NOTE: Faint so it reads as a limit of the measure rather than a threshold
+            @if let Some((noise_floor, y)) = ctx.noise_floor_line() {
+                line.noise-floor x1=(CHART_MIN_X) y1=(y) x2=(CHART_MAX_X) y2=(y) stroke="#888" stroke-width="1" stroke-opacity="0.6" stroke-dasharray="1 3" {
+                    title { (format!("Below measurable rate ({})", ctx.spoken_value(noise_floor, locale))) }
+                }
+            }
+            g.anomalies {
+                @for line in &ctx.collections {
+                    @for (x, y) in line.anomalies.iter().flatten() {
+                        circle.anomaly cx=(x) cy=(y) r="4" fill="#e00" stroke="#fff" stroke-width="1" {
+                            title { (format!("Anomalous {}", line.label.as_deref().unwrap_or("value"))) }
+                        }
+                    }
+                }
+            }
+            // NOTE: Pointing up from the top of the chart, the true value being off the axis
+            g.clipped {
+                @for line in &ctx.collections {
+                    @for (x, y, value) in &line.clipped {
+                        path.clipped d=(format!("M {},{} L {x},{y} L {},{} Z", x - 6.0, y + 10.0, x + 6.0, y + 10.0)) fill=(line.color.as_str()) stroke="#fff" stroke-width="1" {
+                            title {
+                                (format!("{} at {}{}, above the chart", line.label.as_deref().unwrap_or("Value"), locale.number(*value, 2), ctx.unit))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Standalone SVG markup of the chart, e.g. to embed it in another page.
+pub fn render_chart_svg(ctx: &ChartContext, locale: &LocaleSpec) -> String {
+    ChartSvg(ctx, locale).into_string()
 }
 
 // TODO: Add hover on dates
 #[tracing::instrument(level = "debug", skip(ctx), fields(unit = ctx.unit))]
-pub fn Chart(ctx: ChartContext) -> Markup {
+pub fn Chart(ctx: &ChartContext, locale: &LocaleSpec) -> Markup {
     if ctx.collections.is_empty() {
         html! {
             p { "No data available." }
         }
     } else {
-        let mid_value = round_to_len(ctx.max_value / 2.0, 2);
         html! {
-            svg.chart viewBox=(format!("{SVG_MIN_X} {SVG_MIN_Y} {SVG_MAX_X} {SVG_MAX_Y}")) {
-                g.grid.x-grid {
-                    line x1=(CHART_MIN_X) y1="5%" x2="100%" y2="5%" {}
-                    line x1=(CHART_MIN_X) y1="50%" x2="100%" y2="50%" {}
-                    line x1=(CHART_MIN_X) y1="95%" x2="100%" y2="95%" {}
+            (ChartSvg(ctx, locale))
+            @if ctx.collections.iter().any(|line| line.label.is_some()) {
+                ul.legend {
+                    @for line in &ctx.collections {
+                        @if let Some(label) = &line.label {
+                            li {
+                                span.legend-color style=(format!("background-color: {}", line.color)) {}
+                                (label)
+                            }
+                        }
+                    }
                 }
-                g.labels.x-labels {
-                    text x=(LABELS_OFFSET) y="5%" dy="6" { (format!("{}{}", round_to_len(ctx.max_value, 2), ctx.unit)) }
-                    text x=(LABELS_OFFSET) y="50%" dy="6" { (format!("{}{}", round_to_len(mid_value, 2), ctx.unit)) }
-                    text x=(LABELS_OFFSET) y="95%" dy="6" { (format!("0{}", ctx.unit)) }
+            }
+            @if let (Some(compared_label), false) = (&ctx.compared_label, ctx.compared_collections.is_empty()) {
+                ul.legend {
+                    li {
+                        span.legend-line {}
+                        "current"
+                    }
+                    li {
+                        span.legend-line.compared {}
+                        (compared_label)
+                    }
                 }
-                g.lines {
-                    @for (color, _label, polyline) in ctx.collections {
-                        polyline.dataline fill="none" stroke=(color) stroke-width="2" points=(polyline) {}
+            }
+            @if !ctx.data.is_empty() {
+                details.data-table {
+                    summary { "Data table" }
+                    table {
+                        thead {
+                            tr {
+                                th scope="col" { "Time (UTC)" }
+                                @for line in &ctx.data {
+                                    th scope="col" { (line.label) }
+                                }
+                            }
+                        }
+                        tbody {
+                            @for (timestamp, values) in ctx.table_rows() {
+                                tr {
+                                    th scope="row" {
+                                        (Utc.timestamp_opt(timestamp, 0).single().map(|time| locale.date_time(time)).unwrap_or_default())
+                                    }
+                                    @for value in values {
+                                        td {
+                                            @if let Some(value) = value {
+                                                (format!("{}{}", locale.number(value, 2), ctx.unit))
+                                            } @else {
+                                                "-"
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{axis::time_axis, locale::ISO, svg::TimeRange, testing};
+
+    /// CPU chart over `count` minutes, its line going up to 91% and ending at 23%.
+    fn cpu_chart(count: i64) -> ChartContext {
+        let values = (0..count)
+            .map(|minute| {
+                let value = if minute == count / 2 { 91.0 } else { 23.0 };
+                (value, 1_700_000_000 + minute * 60)
+            })
+            .collect::<Vec<_>>();
+        ChartContext::builder()
+            .collections(vec![ChartLine::new(
+                LineColor::FALLBACK,
+                None,
+                ChartPoints(vec![(CHART_MIN_X, CHART_MAX_Y), (CHART_MAX_X, CHART_MIN_Y)]),
+            )])
+            .max_value(100.0)
+            .title("CPU Usage")
+            .range_label("3 hours")
+            .data(vec![LineData {
+                label: "CPU".to_string(),
+                stats: LineStats::from_values(&values),
+                values,
+            }])
+            .build()
+    }
+
+    /// Rows of the data tables of the markup.
+    fn table_rows(markup: &str) -> usize {
+        markup
+            .split("<tbody>")
+            .skip(1)
+            .map(|table| {
+                table[..table.find("</tbody>").unwrap()]
+                    .matches("<tr>")
+                    .count()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn charts_are_described() {
+        let chart = cpu_chart(180);
+        let summary = "CPU Usage, last 3 hours, currently 23 percent, peak 91 percent";
+        assert_eq!(chart.summary(&ISO), summary);
+
+        let markup = Chart(&chart, &ISO).into_string();
+
+        assert!(markup.contains(&format!("role=\"img\" aria-label=\"{summary}\"")));
+        assert!(markup.contains(&format!("<desc>{summary}</desc>")));
+        assert!(markup.contains("<th scope=\"col\">CPU</th>"));
+    }
+
+    #[test]
+    fn tables_of_large_series_are_capped() {
+        // NOTE: A week of values every minute, not strided beforehand
+        let chart = cpu_chart(7 * 24 * 60);
+
+        let rows = chart.table_rows();
+        let markup = Chart(&chart, &ISO).into_string();
+
+        assert_eq!(rows.len(), MAX_TABLE_ROWS);
+        assert_eq!(table_rows(&markup), MAX_TABLE_ROWS);
+        // NOTE: The current value is always part of the table
+        let (last, _) = chart.data[0].values.last().unwrap();
+        assert_eq!(rows.last().unwrap().1, vec![Some(*last)]);
+    }
+
+    #[test]
+    fn tables_join_the_times_of_every_line() {
+        let mut chart = cpu_chart(3);
+        chart.data.push(LineData {
+            label: "Other".to_string(),
+            stats: None,
+            values: vec![(5.0, 1_700_000_060), (6.0, 1_700_000_090)],
+        });
+
+        let rows = chart.table_rows();
+
+        assert_eq!(
+            rows,
+            vec![
+                (1_700_000_000, vec![Some(23.0), None]),
+                (1_700_000_060, vec![Some(91.0), Some(5.0)]),
+                (1_700_000_090, vec![None, Some(6.0)]),
+                (1_700_000_120, vec![Some(23.0), None]),
+            ]
+        );
+        assert!(Chart(&chart, &ISO).into_string().contains("<td>-</td>"));
+    }
+
+    #[test]
+    fn strides_keep_the_last_value() {
+        let values = (0..1000).collect::<Vec<_>>();
+
+        let strided = stride(&values, MAX_TABLE_ROWS);
+
+        assert!(strided.len() <= MAX_TABLE_ROWS);
+        assert_eq!(strided.last(), Some(&999));
+        assert!(strided.windows(2).all(|pair| pair[1] - pair[0] == 20));
+        assert_eq!(stride(&values[..10], MAX_TABLE_ROWS), &values[..10]);
+        assert!(stride(&values, 0).is_empty());
+    }
+
+    #[test]
+    fn generated_charts_are_described_with_capped_tables() {
+        let charts = testing::charts();
+
+        for (slug, chart) in charts.metrics.iter().filter(|(_, chart)| chart.has_data()) {
+            let markup = Chart(chart, &ISO).into_string();
+            let summary = chart.summary(&ISO);
+            assert!(markup.contains("role=\"img\""), "{slug}");
+            assert!(
+                markup.contains(&format!("<desc>{summary}</desc>")),
+                "{slug}"
+            );
+            assert!(table_rows(&markup) <= MAX_TABLE_ROWS, "{slug}");
+        }
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        let mut chart = cpu_chart(3);
+        let label = "<script>alert(\"sent & received\")</script>";
+        chart.collections[0].label = Some(label.to_string());
+
+        let markup = Chart(&chart, &ISO).into_string();
+
+        assert!(!markup.contains("<script>"), "{markup}");
+        assert!(
+            markup.contains("&lt;script&gt;alert(&quot;sent &amp; received&quot;)&lt;/script&gt;"),
+            "{markup}"
+        );
+    }
+
+    #[test]
+    fn points_are_formatted_by_the_chart() {
+        let points = ChartPoints(vec![(144.0, 15.5), (1000.0, 150.0)]);
+
+        assert_eq!(points.to_polyline(), "144,15.5 1000,150");
+        assert_eq!(points.to_path(), "M 144,15.5 L 1000,150 Z");
+        assert_eq!(
+            ChartPoints::parse(&points.to_polyline()),
+            Ok(points.clone())
+        );
+        assert_eq!(ChartPoints::parse(&points.to_path()), Ok(points));
+        assert!(ChartPoints::parse("144,15 \"/><script>").is_err());
+    }
+
+    #[test]
+    fn former_lines_are_validated() {
+        let line = ChartLine::try_from((
+            "#E00".to_string(),
+            Some("Busy".to_string()),
+            "144,15 1000,150".to_string(),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(line.color.as_str(), "#e00");
+        assert_eq!(
+            line.points,
+            ChartPoints(vec![(144.0, 15.0), (1000.0, 150.0)])
+        );
+
+        for (color, points) in [
+            ("#e00\" onload=\"alert(1)", "144,15"),
+            ("#e00", "144,15\" onload=\"alert(1)"),
+        ] {
+            assert!(
+                ChartLine::try_from((color.to_string(), None, points.to_string(), None)).is_err(),
+                "{color} {points}"
+            );
+        }
+    }
+
+    #[test]
+    fn clipped_points_are_marked_and_described() {
+        let mut chart = cpu_chart(180);
+        chart.collections[0].label = Some("Busy".to_string());
+        chart.collections[0].clipped = vec![(500.0, CHART_MIN_Y, 4000.0)];
+
+        let summary = chart.summary(&ISO);
+        let markup = Chart(&chart, &ISO).into_string();
+
+        assert!(
+            summary.ends_with(", 1 point above the chart up to 4000 percent"),
+            "{summary}"
+        );
+        assert_eq!(markup.matches("<path class=\"clipped\"").count(), 1);
+        assert!(markup.contains("<title>Busy at 4000%, above the chart</title>"));
+    }
+
+    #[test]
+    fn noise_floors_are_drawn_as_a_baseline_within_the_chart() {
+        let mut chart = cpu_chart(180);
+        chart.unit = "pg/s".to_string();
+        chart.noise_floor = Some(25.0);
+
+        let markup = Chart(&chart, &ISO).into_string();
+
+        assert!(
+            chart
+                .summary(&ISO)
+                .ends_with(", rates below 25 pg/s are not measurable"),
+            "{}",
+            chart.summary(&ISO)
+        );
+        let y = CHART_MAX_Y - 0.25 * CHART_Y_RATIO;
+        assert!(
+            markup.contains(&format!(
+                "class=\"noise-floor\" x1=\"{CHART_MIN_X}\" y1=\"{y}\""
+            )),
+            "{markup}"
+        );
+        assert!(markup.contains("<title>Below measurable rate (25 pg/s)</title>"));
+
+        // NOTE: Above the chart, the baseline would be drawn outside of it
+        chart.noise_floor = Some(250.0);
+        assert!(!Chart(&chart, &ISO).into_string().contains("noise-floor"));
+        chart.noise_floor = None;
+        assert!(!chart.summary(&ISO).contains("measurable"));
+    }
+
+    #[test]
+    fn time_ticks_get_a_grid_line_and_a_label() {
+        let range = TimeRange {
+            start: 1_700_000_000,
+            end: 1_700_000_000 + 3 * 60 * 60,
+        };
+        let mut chart = cpu_chart(180);
+        chart.time_axis = time_axis(&range);
+
+        let markup = ChartSvg(&chart, &ISO).into_string();
+
+        assert!(!chart.time_axis.ticks.is_empty());
+        for (x, label) in chart.time_axis.labels(&ISO) {
+            assert!(
+                markup.contains(&format!("<line x1=\"{x}\" y1=\"{CHART_MIN_Y}\" x2=\"{x}\"")),
+                "{x}"
+            );
+            assert!(markup.contains(&format!(">{label}</text>")), "{label}");
+        }
+    }
+}
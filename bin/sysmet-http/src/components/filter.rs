@@ -0,0 +1,211 @@
+use chrono::{SecondsFormat, TimeZone, Utc};
+use maud::{html, Markup};
+
+use crate::{
+    generator::CompareWith, locale::LOCALES, parse_range, query::DashboardState, range_to_query,
+    units::UnitSystem, Smoothing, RANGE_PRESETS, SMOOTHING_PRESETS,
+};
+
+/// Parameters with a visible control in the form, every other one of the state being kept in a hidden input.
+const VISIBLE_PARAMS: &[&str] = &[
+    "t",
+    "from",
+    "to",
+    "compare",
+    "refresh",
+    "anomalies",
+    "bands",
+    "smooth",
+    "network_units",
+    "disk_units",
+    "locale",
+];
+
+/// RFC3339 date of a unix timestamp, the timestamp itself when it is out of range.
+fn timestamp_to_rfc3339(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0).single().map_or_else(
+        || timestamp.to_string(),
+        |time| time.to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
+
+/// Presets of the range and form of the dashboard, submitting it keeps every parameter of `state`.
+pub fn FilterForm(state: &DashboardState) -> Markup {
+    let hidden = state
+        .params()
+        .into_iter()
+        .filter(|(name, _)| !VISIBLE_PARAMS.contains(name))
+        .collect::<Vec<_>>();
+    let presets = RANGE_PRESETS
+        .iter()
+        .filter_map(|(value, _)| Some((*value, parse_range(value)?)));
+    let compare_offset = match state.compare {
+        Some(CompareWith::Offset(offset)) => Some(offset),
+        _ => None,
+    };
+
+    html! {
+        // NOTE: Presets keep the other parameters of the page
+        nav.presets {
+            @for (value, duration) in presets {
+                @let selected = state.range.explicit.is_none() && duration == state.range.duration;
+                a.selected[selected] href=(format!("?{}", state.with_duration(duration).to_query())) aria-current=[selected.then_some("true")] { (value) }
+            }
+        }
+        form {
+            div {
+                @if let Some(explicit) = state.range.explicit {
+                    label {
+                        span { "From:" }
+                        input name="from" value=(timestamp_to_rfc3339(explicit.start));
+                    }
+                    label {
+                        span { "to:" }
+                        input name="to" value=(timestamp_to_rfc3339(explicit.end));
+                        span { "(clear both to use a duration)" }
+                    }
+                } @else {
+                    label {
+                        span { "Time range:" }
+                        input name="t" value=(range_to_query(state.range.duration));
+                        span { "ago to now." }
+                    }
+                }
+                // NOTE: An explicit compared range is kept by hidden inputs, the offset taking its place once given
+                label {
+                    span { "Compare with:" }
+                    input name="compare" placeholder="7d" value=(compare_offset.map(range_to_query).unwrap_or_default());
+                    span { "earlier." }
+                }
+                label {
+                    input type="checkbox" name="refresh" checked[state.refresh];
+                    span { "Auto-refresh every minute" }
+                }
+                label {
+                    input type="checkbox" name="anomalies" checked[state.anomalies];
+                    span { "Highlight anomalies" }
+                }
+                label {
+                    span { "Min/max bands:" }
+                    select name="bands" {
+                        option value="" selected[state.bands.is_none()] { "default" }
+                        option value="on" selected[state.bands == Some(true)] { "on" }
+                        option value="off" selected[state.bands == Some(false)] { "off" }
+                    }
+                }
+                label {
+                    span { "Smoothing:" }
+                    select name="smooth" {
+                        option value="" selected[state.smooth.is_none()] { "off" }
+                        @for points in SMOOTHING_PRESETS {
+                            option value=(points) selected[state.smooth == Some(Smoothing::Points(points))] { (format!("{points} points")) }
+                        }
+                        // NOTE: Kept selected when given in the URL so submitting the form doesn't drop it
+                        @if let Some(smooth) = state.smooth.filter(|smooth| !SMOOTHING_PRESETS.iter().any(|points| *smooth == Smoothing::Points(*points))) {
+                            option value=(smooth.to_query()) selected { (smooth.to_query()) }
+                        }
+                    }
+                }
+                label {
+                    span { "Network in:" }
+                    select name="network_units" {
+                        @for units in [UnitSystem::IecBytes, UnitSystem::Bits] {
+                            option value=(units.network_units()) selected[state.units.network == units] { (units.network_units()) }
+                        }
+                    }
+                }
+                label {
+                    span { "Disks in:" }
+                    select name="disk_units" {
+                        @for units in [UnitSystem::IecBytes, UnitSystem::SiBytes] {
+                            option value=(units.disk_units()) selected[state.units.disk == units] { (units.disk_units().to_uppercase()) }
+                        }
+                    }
+                }
+                label title="Numbers and dates of the page, the CSV keeps the machine format" {
+                    span { "Locale:" }
+                    select name="locale" {
+                        @for locale in LOCALES {
+                            option value=(locale.tag) selected[state.locale == locale] { (locale.tag) }
+                        }
+                    }
+                }
+            }
+            @for (name, value) in &hidden {
+                input type="hidden" name=(name) value=(value);
+            }
+            input type="submit" { "Change" }
+            button type="submit" name="save_view" value="on" title="Shown when opening the dashboard without parameters, in this browser" { "Save as default view" }
+            " "
+            a.reset-view href="./" { "Reset to defaults" }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, http::Uri};
+
+    use super::*;
+    use crate::{
+        generator::CHART_SLUGS, locale::LocaleSpec, query::Notices, testing, units::ChartUnits,
+        HomeQuery,
+    };
+
+    /// States loaded with every parameter, the lite view leaving out the anomalies and the bands.
+    const LOADED_QUERIES: [&str; 2] = [
+        "from=2024-05-03T10:00:00Z&to=2024-05-03T12:00:00Z&compare_from=2024-05-02T10:00:00Z\
+         &compare_to=2024-05-02T12:00:00Z&refresh=on&bands=off&open=compute,network&network_units=bits\
+         &disk_units=si&locale=de&theme=dark&smooth=15&fullscale=on&lite=on",
+        "t=2d&compare=7d&anomalies=on&bands=on&smooth=10m&locale=fr&theme=dark",
+    ];
+
+    /// State of the dashboard of `query`, failing on any notice.
+    fn state(query: &str) -> DashboardState {
+        let uri = format!("/?{query}").parse::<Uri>().unwrap();
+        let Query(query) = Query::<HomeQuery>::try_from_uri(&uri).unwrap();
+        let mut notices = Notices::default();
+        let params = query.parse(ChartUnits::default(), LocaleSpec::default(), &mut notices);
+        assert!(notices.is_empty(), "{uri}: {notices:?}");
+        params.state
+    }
+
+    #[test]
+    fn submitting_the_form_keeps_every_parameter() {
+        let focus = CHART_SLUGS[2].1;
+        for query in LOADED_QUERIES {
+            let loaded = state(&format!("{query}&focus={focus}"));
+            assert_ne!(loaded, DashboardState::default());
+
+            let submitted = testing::submitted_query(&FilterForm(&loaded).into_string());
+
+            assert_eq!(state(&submitted), loaded, "{submitted}");
+        }
+        let submitted =
+            testing::submitted_query(&FilterForm(&DashboardState::default()).into_string());
+        assert_eq!(state(&submitted), DashboardState::default());
+    }
+
+    #[test]
+    fn only_the_parameters_without_a_control_are_hidden() {
+        let form = FilterForm(&state(LOADED_QUERIES[0])).into_string();
+        let hidden = form
+            .split("<input type=\"hidden\" name=\"")
+            .skip(1)
+            .map(|input| &input[..input.find('"').unwrap()])
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            hidden,
+            [
+                "compare_from",
+                "compare_to",
+                "lite",
+                "fullscale",
+                "open",
+                "theme"
+            ]
+        );
+        assert!(form.contains("<a class=\"reset-view\" href=\"./\">Reset to defaults</a>"));
+    }
+}
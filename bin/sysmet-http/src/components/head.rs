@@ -1,12 +1,90 @@
-use maud::{html, Markup};
+use std::time::Duration;
+
+use maud::{html, Markup, PreEscaped};
+use once_cell::sync::Lazy;
 use typed_builder::TypedBuilder;
 
-use crate::CSS_HASHES;
+use crate::{CSS_DIR, CSS_HASHES};
+
+/// Prefix of the embedded stylesheets inlined in every page, the others being loaded without blocking its
+/// first paint.
+const CRITICAL_CSS_PREFIX: &str = "critical-";
+/// Most bytes of critical CSS, past it the head no longer fits in the first round trip of a slow link.
+pub const CRITICAL_CSS_BUDGET: usize = 14 * 1024;
+
+/// Contents of the critical stylesheets sorted by path, inlined in the head of every page.
+pub static CRITICAL_CSS: Lazy<String> = Lazy::new(|| {
+    critical_css(CSS_DIR.files().filter_map(|file| {
+        Some((
+            file.path().to_string_lossy().into_owned(),
+            file.contents_utf8()?,
+        ))
+    }))
+});
+
+fn is_critical(path: &str) -> bool {
+    path.starts_with(CRITICAL_CSS_PREFIX)
+}
+
+/// Contents of the critical ones of the (path, contents) `stylesheets`, sorted by path.
+fn critical_css<'a>(stylesheets: impl IntoIterator<Item = (String, &'a str)>) -> String {
+    let mut stylesheets = stylesheets
+        .into_iter()
+        .filter(|(path, _)| is_critical(path))
+        .collect::<Vec<_>>();
+    stylesheets.sort();
+    stylesheets
+        .into_iter()
+        .map(|(_, css)| css)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Stylesheets embedded in the binary as (path under `/css`, integrity hash), sorted by path, without the
+/// critical ones which are inlined.
+pub fn embedded_stylesheets() -> Vec<(String, String)> {
+    let mut stylesheets = CSS_HASHES
+        .iter()
+        .filter(|(path, _)| !is_critical(path))
+        .map(|(path, (_real_path, hash))| (path.clone(), hash.clone()))
+        .collect::<Vec<_>>();
+    // NOTE: Sorted so the page does not depend on the iteration order of the map
+    stylesheets.sort();
+    stylesheets
+}
+
+/// Contents of the embedded stylesheets, the critical ones first then in the order of
+/// `embedded_stylesheets`.
+pub fn embedded_css() -> Vec<&'static str> {
+    std::iter::once(CRITICAL_CSS.as_str())
+        .chain(
+            embedded_stylesheets()
+                .iter()
+                .filter_map(|(path, _hash)| CSS_HASHES.get(path))
+                .filter_map(|(real_path, _hash)| CSS_DIR.get_file(real_path))
+                .filter_map(|file| file.contents_utf8()),
+        )
+        .filter(|css| !css.is_empty())
+        .collect()
+}
 
 #[derive(Debug, TypedBuilder)]
 pub struct HeadContext {
+    /// Reload the page after this long, e.g. every minute with the auto-refresh of the dashboard.
+    #[builder(default)]
+    pub refresh_every: Option<Duration>,
+    /// Prefix of the stylesheet links, e.g. `/sysmet`.
+    #[builder(default, setter(into))]
+    pub base_path: String,
+    /// Stylesheets as (path under `{base_path}/css`, integrity hash).
+    #[builder(default = embedded_stylesheets())]
+    pub stylesheets: Vec<(String, String)>,
+    /// Styles inlined ahead of the linked stylesheets, the critical ones by default.
+    #[builder(default = CRITICAL_CSS.as_str())]
+    pub critical_css: &'static str,
+    /// Embed the stylesheets in the page instead of linking them, e.g. for a page opened without the server.
     #[builder(default = false)]
-    pub refresh_every_minute: bool,
+    pub inline_stylesheets: bool,
 }
 
 pub fn Head(context: HeadContext, title: &str) -> Markup {
@@ -14,13 +92,99 @@ pub fn Head(context: HeadContext, title: &str) -> Markup {
         head {
             meta charset="utf-8";
             meta name="viewport" content="width=device-width, initial-scale=1";
-            @if context.refresh_every_minute {
-                meta http-equiv="refresh" content="60";
+            @if let Some(refresh) = context.refresh_every {
+                meta http-equiv="refresh" content=(refresh.as_secs());
             }
             title { (title) }
-            @for (path, (_real_path, hash)) in CSS_HASHES.iter() {
-                link rel="stylesheet" href=(format!("/css/{path}")) type="text/css" crossorigin="anonymous" integrity=(hash);
+            @if context.inline_stylesheets {
+                @for css in embedded_css() {
+                    style { (PreEscaped(css)) }
+                }
+            } @else {
+                @if !context.critical_css.is_empty() {
+                    style { (PreEscaped(context.critical_css)) }
+                }
+                // NOTE: Loaded for print so they don't block the first paint, then applied to the screen
+                @for (path, hash) in &context.stylesheets {
+                    link rel="stylesheet" href=(format!("{}/css/{path}", context.base_path)) type="text/css"
+                        crossorigin="anonymous" integrity=(hash) media="print" onload="this.media='all'";
+                }
+                noscript {
+                    @for (path, hash) in &context.stylesheets {
+                        link rel="stylesheet" href=(format!("{}/css/{path}", context.base_path)) type="text/css"
+                            crossorigin="anonymous" integrity=(hash);
+                    }
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn critical_stylesheets_are_picked_by_their_prefix() {
+        let css = critical_css([
+            ("main.css".to_string(), ".chart{}"),
+            ("critical-typography.css".to_string(), "body{}"),
+            ("critical-layout.css".to_string(), ".container{}"),
+        ]);
+        assert_eq!(css, ".container{}\nbody{}");
+    }
+
+    #[test]
+    fn critical_css_is_inlined_ahead_of_the_deferred_stylesheets() {
+        let head = Head(
+            HeadContext::builder()
+                .stylesheets(testing::stylesheets())
+                .critical_css(testing::CRITICAL_CSS)
+                .build(),
+            "sysmet",
+        )
+        .into_string();
+
+        let inlined = head
+            .find(&format!("<style>{}</style>", testing::CRITICAL_CSS))
+            .unwrap();
+        assert!(inlined < head.find("<link").unwrap(), "{head}");
+        let (deferred, noscript) = head.split_once("<noscript>").unwrap();
+        assert_eq!(deferred.matches("rel=\"stylesheet\"").count(), 1);
+        assert!(deferred.contains("integrity=\"sha256-fixture\" media=\"print\""));
+        assert!(noscript.contains("integrity=\"sha256-fixture\">"));
+
+        let without = Head(
+            HeadContext::builder()
+                .stylesheets(testing::stylesheets())
+                .critical_css("")
+                .build(),
+            "sysmet",
+        )
+        .into_string();
+        assert!(!without.contains("<style>"));
+    }
+
+    #[test]
+    fn critical_css_stays_under_its_budget() {
+        // NOTE: Empty unless built with yarn, the sources are checked to catch a grown one without it
+        assert!(
+            CRITICAL_CSS.len() <= CRITICAL_CSS_BUDGET,
+            "{}",
+            CRITICAL_CSS.len()
+        );
+        let styles = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("css/styles");
+        let sources = fs::read_dir(styles)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| is_critical(&entry.file_name().to_string_lossy()))
+            .map(|entry| fs::read_to_string(entry.path()).unwrap())
+            .collect::<Vec<_>>();
+        assert!(!sources.is_empty());
+        let size = sources.iter().map(String::len).sum::<usize>();
+        assert!(size <= CRITICAL_CSS_BUDGET, "{size}");
+    }
+}
@@ -1,7 +1,17 @@
 #![allow(non_snake_case)]
+mod alerting;
+pub use alerting::*;
 mod base;
 pub use base::*;
+mod board;
+pub use board::*;
 mod head;
 pub use head::*;
+mod notices;
+pub use notices::*;
 mod chart;
 pub use chart::*;
+mod filter;
+pub use filter::*;
+mod range;
+pub use range::*;
@@ -0,0 +1,20 @@
+use maud::{html, Markup};
+
+use crate::query::Notices;
+
+/// Parameters of the query that could not be used as given, each with what was used instead. Nothing is
+/// rendered without notices.
+pub fn NoticeBar(notices: &Notices) -> Markup {
+    html! {
+        @if !notices.is_empty() {
+            section.notice-bar role="status" {
+                @for notice in notices.iter() {
+                    p class=(format!("notice notice-{}", notice.severity.as_str())) {
+                        code { (notice.param) "=" (notice.value) }
+                        (format!(": {}, {}.", notice.reason, notice.fallback))
+                    }
+                }
+            }
+        }
+    }
+}
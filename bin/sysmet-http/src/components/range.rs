@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+
+use crate::locale::LocaleSpec;
+
+/// Ranges offered as shortcuts, as (`t` value, label).
+pub const RANGE_PRESETS: [(&str, &str); 7] = [
+    ("30m", "30 minutes"),
+    ("1h", "1 hour"),
+    ("3h", "3 hours"),
+    ("12h", "12 hours"),
+    ("1d", "1 day"),
+    ("7d", "7 days"),
+    ("30d", "30 days"),
+];
+
+/// Longest range of `t`, in days.
+pub const MAX_RANGE_DAYS: u64 = 3650;
+/// Longest duration of a `t` value, longer ones being refused like an invalid one.
+pub const MAX_RANGE: Duration = Duration::from_secs(MAX_RANGE_DAYS * 24 * 60 * 60);
+
+/// Smoothing windows offered by the form, in points.
+pub const SMOOTHING_PRESETS: [usize; 3] = [5, 15, 60];
+
+/// Window of the moving average smoothing the lines of the charts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Smoothing {
+    /// Number of drawn points averaged together.
+    Points(usize),
+    /// Duration averaged together, converted to points from the interval between the drawn points.
+    Duration(Duration),
+}
+
+impl Smoothing {
+    /// Smoothing of a `smooth` value, a number of points or a humantime duration, `None` for `off` or an
+    /// invalid value.
+    pub fn from_query(value: &str) -> Option<Self> {
+        let value = value.trim();
+        match value.parse::<usize>() {
+            Ok(points) => (points > 1).then_some(Self::Points(points)),
+            Err(_) => parse_range(value)
+                .filter(|duration| !duration.is_zero())
+                .map(Self::Duration),
+        }
+    }
+
+    /// `smooth` value of the smoothing.
+    pub fn to_query(self) -> String {
+        match self {
+            Self::Points(points) => points.to_string(),
+            Self::Duration(duration) => range_to_query(duration),
+        }
+    }
+}
+
+/// Parse a `t` value, either a preset shorthand or any humantime duration up to `MAX_RANGE`.
+pub fn parse_range(value: &str) -> Option<Duration> {
+    humantime::parse_duration(value.trim())
+        .ok()
+        .filter(|duration| *duration <= MAX_RANGE)
+}
+
+/// Short `t` value of a duration, the preset one when it matches a preset.
+pub fn range_to_query(duration: Duration) -> String {
+    RANGE_PRESETS
+        .iter()
+        .find(|(value, _)| parse_range(value) == Some(duration))
+        .map_or_else(
+            || {
+                humantime::format_duration(duration)
+                    .to_string()
+                    .replace(' ', "")
+            },
+            |&(value, _)| value.to_string(),
+        )
+}
+
+/// Human label of a duration, the preset one when it matches a preset.
+pub fn range_to_label(duration: Duration) -> String {
+    RANGE_PRESETS
+        .iter()
+        .find(|(value, _)| parse_range(value) == Some(duration))
+        .map_or_else(
+            || humantime::format_duration(duration).to_string(),
+            |&(_, label)| label.to_string(),
+        )
+}
+
+/// Sentence describing the displayed range, e.g. "Showing 12 hours up to 2024-05-03 14:02 UTC, 713 snapshots."
+pub fn range_summary(
+    range: Duration,
+    end: i64,
+    snapshots_count: usize,
+    locale: &LocaleSpec,
+) -> String {
+    let end = Utc
+        .timestamp_opt(end, 0)
+        .single()
+        .map(|end| format!("{} UTC", locale.date_time(end)))
+        .unwrap_or_default();
+    let plural = if snapshots_count == 1 { "" } else { "s" };
+
+    format!(
+        "Showing {} up to {end}, {} snapshot{plural}.",
+        range_to_label(range),
+        locale.integer(snapshots_count)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        locale::EN,
+        query::{range_params, DashboardState, Notices},
+        FilterForm, DEFAULT_TIME_RANGE,
+    };
+
+    /// Dashboard state of a `t` value, as read from the query.
+    fn state(t: &str) -> DashboardState {
+        let mut notices = Notices::default();
+        let range = range_params(&mut notices, Some(t), None, None, DEFAULT_TIME_RANGE);
+        assert!(notices.is_empty(), "{t}");
+        DashboardState {
+            range,
+            ..DashboardState::default()
+        }
+    }
+
+    /// Values of the presets shown as selected by the form.
+    fn selected(form: &str) -> Vec<&str> {
+        form.split("aria-current=\"true\">")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('<').unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn presets_round_trip() {
+        for (value, label) in RANGE_PRESETS {
+            let duration = parse_range(value).unwrap();
+            assert_eq!(range_to_query(duration), value);
+            assert_eq!(range_to_label(duration), label);
+
+            let state = state(value);
+            assert_eq!(state.range.duration, duration);
+            assert!(state.to_query().starts_with(&format!("t={value}&")));
+            let form = FilterForm(&state).into_string();
+            assert_eq!(selected(&form), [value], "{value}");
+            assert!(form.contains(&format!("name=\"t\" value=\"{value}\"")));
+        }
+    }
+
+    #[test]
+    fn humantime_values_are_normalized() {
+        for (value, normalized) in [
+            ("3h0m0s", "3h"),
+            ("180m", "3h"),
+            (" 2d ", "2days"),
+            ("90m", "1h30m"),
+            ("1day 12h", "1day12h"),
+        ] {
+            let duration = parse_range(value).unwrap();
+            assert_eq!(range_to_query(duration), normalized, "{value}");
+            assert_eq!(parse_range(&range_to_query(duration)), Some(duration));
+        }
+        assert_eq!(selected(&FilterForm(&state("180m")).into_string()), ["3h"]);
+    }
+
+    #[test]
+    fn custom_ranges_select_no_preset() {
+        let form = FilterForm(&state("90m")).into_string();
+        assert!(selected(&form).is_empty());
+        assert!(form.contains("name=\"t\" value=\"1h30m\""));
+
+        let state = DashboardState {
+            range: crate::query::RangeParams {
+                explicit: Some(crate::TimeRange::ending_at(
+                    1_700_000_000,
+                    Duration::from_secs(3600),
+                )),
+                duration: Duration::from_secs(3600),
+            },
+            ..DashboardState::default()
+        };
+        assert!(selected(&FilterForm(&state).into_string()).is_empty());
+    }
+
+    #[test]
+    fn invalid_ranges_are_refused() {
+        for value in ["", "soon", "-1h", "3651d"] {
+            assert_eq!(parse_range(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn smoothing_values_round_trip() {
+        for (value, smoothing) in [
+            ("5", Smoothing::Points(5)),
+            ("60", Smoothing::Points(60)),
+            ("10m", Smoothing::Duration(Duration::from_secs(600))),
+            ("1h", Smoothing::Duration(Duration::from_secs(3600))),
+        ] {
+            assert_eq!(Smoothing::from_query(value), Some(smoothing), "{value}");
+            assert_eq!(smoothing.to_query(), value);
+        }
+        // NOTE: A single point is the raw line
+        for value in ["", "off", "0", "1", "0s", "-5"] {
+            assert_eq!(Smoothing::from_query(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn smoothing_is_selected_in_the_form() {
+        let form = |smooth| {
+            FilterForm(&DashboardState {
+                smooth,
+                ..DashboardState::default()
+            })
+            .into_string()
+        };
+
+        assert!(form(None).contains("<option value=\"\" selected>off</option>"));
+        assert!(form(Some(Smoothing::Points(15)))
+            .contains("<option value=\"15\" selected>15 points</option>"));
+        // NOTE: Values of the URL outside of the presets are kept by the form
+        let custom = form(Some(Smoothing::Duration(Duration::from_secs(600))));
+        assert!(
+            custom.contains("<option value=\"10m\" selected>10m</option>"),
+            "{custom}"
+        );
+        assert!(custom.contains("<option value=\"\">off</option>"));
+    }
+
+    #[test]
+    fn summaries_describe_the_range() {
+        let end = Utc
+            .with_ymd_and_hms(2024, 5, 3, 14, 2, 0)
+            .unwrap()
+            .timestamp();
+        assert_eq!(
+            range_summary(Duration::from_secs(12 * 3600), end, 1713, &EN),
+            "Showing 12 hours up to 05/03/2024 14:02 UTC, 1,713 snapshots."
+        );
+        assert_eq!(
+            range_summary(Duration::from_secs(5400), end, 1, &EN),
+            "Showing 1h 30m up to 05/03/2024 14:02 UTC, 1 snapshot."
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Contrast ratio of two colors as defined by WCAG 2, see <https://www.w3.org/TR/WCAG21/#dfn-contrast-ratio>.
+
+/// Value of an sRGB channel on the linear scale, from 0 to 1.
+pub fn linear_channel(channel: u8) -> f64 {
+    let channel = channel as f64 / 255.0;
+    // NOTE: The sRGB threshold, WCAG 2.1 still quotes the 0.03928 of an older draft which gives the same
+    // result for every 8-bit channel
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Relative luminance of a color, from 0 for black to 1 for white.
+pub fn relative_luminance([r, g, b]: [u8; 3]) -> f64 {
+    0.2126 * linear_channel(r) + 0.7152 * linear_channel(g) + 0.0722 * linear_channel(b)
+}
+
+/// Contrast ratio of two colors in either order, from 1 for the same luminance to 21 for black on white.
+pub fn contrast_ratio(color: [u8; 3], other: [u8; 3]) -> f64 {
+    let (color, other) = (relative_luminance(color), relative_luminance(other));
+    (color.max(other) + 0.05) / (color.min(other) + 0.05)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ratio` rounded to the 2 decimals WCAG tools show.
+    fn rounded(ratio: f64) -> f64 {
+        (ratio * 100.0).round() / 100.0
+    }
+
+    #[test]
+    fn channels_are_linearized_along_the_srgb_curve() {
+        assert_eq!(linear_channel(0), 0.0);
+        assert_eq!(linear_channel(255), 1.0);
+        // NOTE: On the linear segment below the threshold, then on the curve
+        assert!((linear_channel(10) - 10.0 / 255.0 / 12.92).abs() < 1e-12);
+        assert!((linear_channel(128) - 0.2158605).abs() < 1e-6);
+    }
+
+    #[test]
+    fn luminances_go_from_black_to_white() {
+        assert_eq!(relative_luminance([0, 0, 0]), 0.0);
+        assert!((relative_luminance([255, 255, 255]) - 1.0).abs() < 1e-12);
+        // NOTE: The coefficients of each primary
+        assert!((relative_luminance([255, 0, 0]) - 0.2126).abs() < 1e-12);
+        assert!((relative_luminance([0, 255, 0]) - 0.7152).abs() < 1e-12);
+        assert!((relative_luminance([0, 0, 255]) - 0.0722).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ratios_match_the_wcag_references() {
+        let white = [255, 255, 255];
+
+        assert_eq!(contrast_ratio([0, 0, 0], white), 21.0);
+        assert_eq!(contrast_ratio(white, white), 1.0);
+        // NOTE: #767676 is the lightest gray passing AA for text on white, #777777 the darkest failing it
+        assert_eq!(rounded(contrast_ratio([0x76, 0x76, 0x76], white)), 4.54);
+        assert_eq!(rounded(contrast_ratio([0x77, 0x77, 0x77], white)), 4.48);
+        assert_eq!(rounded(contrast_ratio([0, 0, 255], white)), 8.59);
+        assert_eq!(rounded(contrast_ratio([255, 0, 0], white)), 4.0);
+        assert_eq!(rounded(contrast_ratio([0, 0x80, 0], white)), 5.14);
+    }
+
+    #[test]
+    fn ratios_ignore_the_order_of_the_colors() {
+        let (color, other) = ([0x08, 0x80, 0xaa], [0x16, 0x16, 0x16]);
+        assert_eq!(contrast_ratio(color, other), contrast_ratio(other, color));
+        assert!(contrast_ratio(color, other) > 1.0);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+
+/// Quote a field containing a separator, a quote or a line break (RFC 4180).
+pub fn escape_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// CSV with a timestamp column then one column per line, lines being aligned on their timestamps.
+///
+/// A line without a value at a timestamp has an empty cell.
+pub fn series_to_csv(labels: &[&str], series: &[Vec<(f64, i64)>]) -> String {
+    let mut rows: BTreeMap<i64, Vec<Option<f64>>> = BTreeMap::new();
+    for (idx, values) in series.iter().enumerate() {
+        for (value, timestamp) in values {
+            rows.entry(*timestamp)
+                .or_insert_with(|| vec![None; series.len()])[idx] = Some(*value);
+        }
+    }
+
+    let mut csv = std::iter::once("timestamp")
+        .chain(labels.iter().copied())
+        .map(escape_field)
+        .collect::<Vec<_>>()
+        .join(",");
+    csv.push_str("\r\n");
+    for (timestamp, values) in rows {
+        let time = Utc.timestamp_opt(timestamp, 0).single().map_or_else(
+            || timestamp.to_string(),
+            |time| time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+        csv.push_str(&time);
+        for value in values {
+            csv.push(',');
+            if let Some(value) = value {
+                csv.push_str(&value.to_string());
+            }
+        }
+        csv.push_str("\r\n");
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse_csv;
+
+    #[test]
+    fn fields_with_separators_are_quoted() {
+        assert_eq!(escape_field("Busy"), "Busy");
+        assert_eq!(escape_field("Disk, root"), "\"Disk, root\"");
+        assert_eq!(escape_field("the \"fast\" one"), "\"the \"\"fast\"\" one\"");
+        assert_eq!(escape_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn lines_are_aligned_on_their_timestamps() {
+        let csv = series_to_csv(
+            &["Received, eth0", "Sent \"eth0\"", "Errors"],
+            &[
+                vec![
+                    (1.5, 1_714_737_600),
+                    (2.0, 1_714_737_660),
+                    (2.5, 1_714_737_720),
+                ],
+                vec![(3.0, 1_714_737_660)],
+                vec![(0.0, 1_714_737_540), (4.0, 1_714_737_720)],
+            ],
+        );
+
+        assert!(csv.ends_with("\r\n"));
+        assert_eq!(
+            parse_csv(&csv),
+            [
+                ["timestamp", "Received, eth0", "Sent \"eth0\"", "Errors"],
+                ["2024-05-03T11:59:00Z", "", "", "0"],
+                ["2024-05-03T12:00:00Z", "1.5", "", ""],
+                ["2024-05-03T12:01:00Z", "2", "3", ""],
+                ["2024-05-03T12:02:00Z", "2.5", "", "4"],
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_series_only_have_the_header() {
+        assert_eq!(series_to_csv(&["Busy"], &[vec![]]), "timestamp,Busy\r\n");
+    }
+}
@@ -0,0 +1,433 @@
+//! Databases of several hosts, combined or overlaid on `/aggregate`.
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use log::{debug, tracing, warn};
+use metrics::{
+    aggregate::{combine, Aggregate, Combine},
+    prelude::*,
+};
+
+use crate::Result;
+
+/// Extension of the databases of the fleet directory.
+const DATABASE_EXTENSION: &str = "db";
+/// Most buckets of an aggregate, whatever its range.
+pub const MAX_AGGREGATE_BUCKETS: u64 = 300;
+/// Shortest bucket of an aggregate, below it most hosts have no snapshot in a bucket.
+pub const MIN_AGGREGATE_STEP: Duration = Duration::from_secs(60);
+
+/// Directory of one `*.db` database per host, e.g. synced from each of them.
+#[derive(Debug, Clone)]
+pub struct Fleet {
+    pub dir: PathBuf,
+    pub key: Option<DatabaseKey>,
+}
+
+pub(crate) type SharedFleet = Arc<Option<Fleet>>;
+
+/// Length of the buckets the hosts are aligned on for a range lasting `duration`.
+pub fn aggregate_step(duration: Duration) -> Duration {
+    (duration / MAX_AGGREGATE_BUCKETS as u32).max(MIN_AGGREGATE_STEP)
+}
+
+/// Hosts of the fleet a query is about.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HostSelection {
+    #[default]
+    All,
+    /// Hostnames or database names, in the order of the query.
+    Only(Vec<String>),
+}
+
+/// Series of each host as (hostname, points).
+pub type HostSeries = Vec<(String, Vec<Point>)>;
+
+/// Series of several metrics with a line per host, drawn on the same charts to compare the hosts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overlay {
+    /// Hosts found, in the order of the selection so each keeps the same color on every chart.
+    pub hosts: Vec<String>,
+    /// Series of each host with points in the range, per metric name.
+    pub metrics: Vec<(String, HostSeries)>,
+    /// Selected hosts without a database in the fleet directory.
+    pub missing: Vec<String>,
+}
+
+impl Fleet {
+    fn databases(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|extension| extension == DATABASE_EXTENSION)
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        debug!(databases = ?paths);
+
+        Ok(paths)
+    }
+
+    /// Hostname, database name and database of each selected host, `None` for an unreadable database, with
+    /// the selected hosts not found.
+    ///
+    /// A host is selected by its hostname or by the name of its database without the extension.
+    #[allow(clippy::type_complexity)]
+    fn open(
+        &self,
+        selection: &HostSelection,
+    ) -> Result<(Vec<(String, String, Option<Database>)>, Vec<String>)> {
+        let mut opened = Vec::new();
+        for path in self.databases()? {
+            let stem = hostname_from_path(&path);
+            let (hostname, database) = match Database::open_readonly_streamed(
+                &path.to_string_lossy(),
+                self.key.as_ref(),
+            ) {
+                Ok(database) => (
+                    database
+                        .hostname()
+                        .map_or_else(|| stem.clone(), ToString::to_string),
+                    Some(database),
+                ),
+                Err(err) => {
+                    warn!("Failed to load {path:?}, its host is left out: {err}");
+                    (stem.clone(), None)
+                }
+            };
+            let position = match selection {
+                HostSelection::All => Some(opened.len()),
+                HostSelection::Only(names) => names
+                    .iter()
+                    .position(|name| *name == hostname || *name == stem),
+            };
+            if let Some(position) = position {
+                opened.push((position, hostname, stem, database));
+            }
+        }
+        opened.sort_by_key(|(position, ..)| *position);
+
+        let missing = match selection {
+            HostSelection::All => Vec::new(),
+            HostSelection::Only(names) => names
+                .iter()
+                .filter(|name| {
+                    !opened
+                        .iter()
+                        .any(|(_, hostname, stem, _)| hostname == *name || stem == *name)
+                })
+                .cloned()
+                .collect(),
+        };
+        Ok((
+            opened
+                .into_iter()
+                .map(|(_, hostname, stem, database)| (hostname, stem, database))
+                .collect(),
+            missing,
+        ))
+    }
+
+    /// Hostname and database of every host, `None` for an unreadable database.
+    pub fn hosts(&self) -> Result<Vec<(String, Option<Database>)>> {
+        Ok(self
+            .open(&HostSelection::All)?
+            .0
+            .into_iter()
+            .map(|(hostname, _, database)| (hostname, database))
+            .collect())
+    }
+
+    /// Series of `metric` over `range` of the selected hosts combined with `mode`, reading each database
+    /// again.
+    ///
+    /// An unreadable database is logged and its host listed as excluded, as are the selected hosts not found.
+    #[tracing::instrument(level = "debug")]
+    pub fn aggregate(
+        &self,
+        metric: &MetricKind,
+        range: Range<DateTime<Utc>>,
+        mode: Combine,
+        selection: &HostSelection,
+    ) -> Result<Aggregate> {
+        let (opened, missing) = self.open(selection)?;
+        let per_host = opened
+            .into_iter()
+            .map(|(hostname, _, database)| {
+                let points = database.map_or_else(Vec::new, |database| {
+                    database
+                        .series(metric.clone(), range.clone(), None)
+                        .collect()
+                });
+                (hostname, points)
+            })
+            .collect();
+        let step = (range.end - range.start)
+            .to_std()
+            .map_or(MIN_AGGREGATE_STEP, aggregate_step);
+
+        let mut aggregate = combine(per_host, range, chrono::Duration::from_std(step)?, mode);
+        aggregate.excluded.extend(missing);
+        Ok(aggregate)
+    }
+
+    /// Series of each of `metrics` (name, metric) over `range` for each selected host, reading each database
+    /// again.
+    ///
+    /// The rates are computed from the counters of each host before the hosts are put together, and the
+    /// series are not bucketed so each host keeps its own collection interval. Hosts sharing a hostname are
+    /// named after their database.
+    #[tracing::instrument(level = "debug")]
+    pub fn overlay(
+        &self,
+        metrics: &[(String, MetricKind)],
+        range: Range<DateTime<Utc>>,
+        selection: &HostSelection,
+    ) -> Result<Overlay> {
+        let (opened, missing) = self.open(selection)?;
+        let opened = opened
+            .iter()
+            .map(|(hostname, stem, database)| {
+                let shared = opened
+                    .iter()
+                    .filter(|(other, ..)| other == hostname)
+                    .count()
+                    > 1;
+                (if shared { stem } else { hostname }.as_str(), database)
+            })
+            .collect::<Vec<_>>();
+        let overlay = Overlay {
+            hosts: opened
+                .iter()
+                .map(|(hostname, _)| hostname.to_string())
+                .collect(),
+            metrics: metrics
+                .iter()
+                .map(|(name, metric)| {
+                    let per_host = opened
+                        .iter()
+                        .filter_map(|(hostname, database)| {
+                            let points = database
+                                .as_ref()?
+                                .series(metric.clone(), range.clone(), None)
+                                .collect::<Vec<_>>();
+                            (!points.is_empty()).then(|| (hostname.to_string(), points))
+                        })
+                        .collect();
+                    (name.clone(), per_host)
+                })
+                .collect(),
+            missing,
+        };
+        debug!(hosts = ?overlay.hosts, missing = ?overlay.missing);
+
+        Ok(overlay)
+    }
+}
+
+fn hostname_from_path(path: &Path) -> String {
+    path.file_stem().map_or_else(
+        || path.to_string_lossy().to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Fleet of `names` databases, each a copy of the test database but `broken.db`.
+    fn fleet(names: &[&str]) -> (tempfile::TempDir, Fleet) {
+        let dir = tempfile::tempdir().unwrap();
+        for name in names {
+            let path = dir.path().join(name);
+            if *name == "broken.db" {
+                fs::write(path, "not a database").unwrap();
+            } else {
+                testing::database()
+                    .write_to_file(&path.to_string_lossy())
+                    .unwrap();
+            }
+        }
+        let fleet = Fleet {
+            dir: dir.path().to_path_buf(),
+            key: None,
+        };
+        (dir, fleet)
+    }
+
+    fn range() -> Range<DateTime<Utc>> {
+        testing::end() - chrono::Duration::hours(1)..testing::end()
+    }
+
+    #[test]
+    fn steps_keep_aggregates_under_the_max_buckets() {
+        assert_eq!(
+            aggregate_step(Duration::from_secs(3600)),
+            MIN_AGGREGATE_STEP
+        );
+        assert_eq!(
+            aggregate_step(Duration::from_secs(7 * 24 * 3600)),
+            Duration::from_secs(7 * 24 * 3600 / MAX_AGGREGATE_BUCKETS)
+        );
+    }
+
+    #[test]
+    fn rates_of_the_hosts_are_summed_and_gauges_averaged() {
+        let (_dir, fleet) = fleet(&["a.db", "b.db", "notes.txt"]);
+
+        for (metric, mode, factor) in [
+            (MetricKind::NetTxRate, Combine::Sum, 2.0),
+            (MetricKind::CpuPercent, Combine::Average, 1.0),
+        ] {
+            let aggregate = fleet
+                .aggregate(&metric, range(), mode, &HostSelection::All)
+                .unwrap();
+
+            assert_eq!(aggregate.hosts.len(), 2);
+            assert!(aggregate.excluded.is_empty());
+            let host = &aggregate.hosts[0].1;
+            assert!(!host.is_empty());
+            assert_eq!(aggregate.points.len(), host.len());
+            for ((time, value), (host_time, host_value)) in aggregate.points.iter().zip(host) {
+                assert_eq!(time, host_time);
+                assert!(
+                    (value - host_value * factor).abs() < 1e-9,
+                    "{metric:?} at {time}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unreadable_and_missing_hosts_are_excluded() {
+        let (_dir, fleet) = fleet(&["a.db", "b.db", "broken.db"]);
+
+        let all = fleet
+            .aggregate(
+                &MetricKind::CpuPercent,
+                range(),
+                Combine::Average,
+                &HostSelection::All,
+            )
+            .unwrap();
+        assert_eq!(all.hosts.len(), 2);
+        assert_eq!(all.excluded, ["broken"]);
+
+        let selected = fleet
+            .aggregate(
+                &MetricKind::CpuPercent,
+                range(),
+                Combine::Average,
+                &HostSelection::Only(vec!["b".into(), "gone".into()]),
+            )
+            .unwrap();
+        assert_eq!(selected.hosts.len(), 1);
+        assert_eq!(selected.excluded, ["gone"]);
+
+        let later = fleet
+            .aggregate(
+                &MetricKind::CpuPercent,
+                testing::end() + chrono::Duration::minutes(1)
+                    ..testing::end() + chrono::Duration::hours(1),
+                Combine::Average,
+                &HostSelection::All,
+            )
+            .unwrap();
+        assert!(later.points.is_empty());
+        assert_eq!(later.excluded.len(), 3);
+    }
+
+    /// Demo database of the hour before `end` with a snapshot every `interval`.
+    fn demo(end: DateTime<Utc>, interval: chrono::Duration) -> Database {
+        Database::demo(&metrics::demo::DemoOptions {
+            end,
+            span: chrono::Duration::hours(1),
+            interval,
+            seed: metrics::demo::DEFAULT_DEMO_SEED,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn overlays_keep_the_rates_and_intervals_of_each_host() {
+        let dir = tempfile::tempdir().unwrap();
+        // NOTE: The demo databases share their hostname, so each host goes by its database name
+        let hosts = [
+            ("alpha", demo(testing::end(), chrono::Duration::minutes(1))),
+            ("beta", demo(testing::end(), chrono::Duration::minutes(5))),
+            (
+                "old",
+                demo(
+                    testing::end() - chrono::Duration::hours(2),
+                    chrono::Duration::minutes(1),
+                ),
+            ),
+        ];
+        for (name, database) in &hosts {
+            database
+                .clone()
+                .write_to_file(&dir.path().join(format!("{name}.db")).to_string_lossy())
+                .unwrap();
+        }
+        let fleet = Fleet {
+            dir: dir.path().to_path_buf(),
+            key: None,
+        };
+        let metrics = [
+            ("cpu".to_string(), MetricKind::CpuPercent),
+            ("net-rx".to_string(), MetricKind::NetRxRate),
+        ];
+
+        let overlay = fleet
+            .overlay(
+                &metrics,
+                range(),
+                &HostSelection::Only(vec![
+                    "beta".into(),
+                    "old".into(),
+                    "alpha".into(),
+                    "gone".into(),
+                ]),
+            )
+            .unwrap();
+
+        assert_eq!(overlay.hosts, ["beta", "old", "alpha"]);
+        assert_eq!(overlay.missing, ["gone"]);
+        assert_eq!(overlay.metrics.len(), 2);
+        for ((name, per_host), (_, metric)) in overlay.metrics.iter().zip(&metrics) {
+            // NOTE: `old` has no snapshot in the range so no series
+            let names = per_host
+                .iter()
+                .map(|(host, _)| host.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names, ["beta", "alpha"], "{name}");
+            for (host, points) in per_host {
+                let database = &hosts.iter().find(|(name, _)| name == host).unwrap().1;
+                let expected = database
+                    .series(metric.clone(), range(), None)
+                    .collect::<Vec<_>>();
+                assert_eq!(points, &expected, "{name} of {host}");
+            }
+            let count = |host: &str| {
+                per_host
+                    .iter()
+                    .find(|(name, _)| name == host)
+                    .unwrap()
+                    .1
+                    .len()
+            };
+            assert!(count("alpha") > 4 * count("beta"), "{name}");
+        }
+    }
+}
@@ -1,252 +1,3747 @@
-use std::{fmt::Debug, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 
-use log::{debug, trace, tracing};
-use metrics::prelude::*;
+use chrono::{DateTime, TimeZone, Utc};
+use log::{debug, error, trace, tracing, warn};
+use metrics::{
+    aggregate::{Aggregate, Combine},
+    clock::{clock_skew, ClockSkew, RECENT_SNAPSHOTS},
+    prelude::*,
+    rates,
+    stats::{downsample, percentile},
+    suspend::suspension_text,
+};
+use once_cell::sync::Lazy;
 use tokio::{
-    sync::{oneshot::Receiver, RwLock},
-    time::Instant,
+    sync::{oneshot::Receiver, Notify},
+    time::{Instant, MissedTickBehavior},
 };
 use typed_builder::TypedBuilder;
 
-use crate::{svg::values_to_polyline, ChartContext, ChartLine, ChartValue};
+use crate::{
+    axis::time_axis,
+    coalesce::Coalescer,
+    csv::series_to_csv,
+    fleet::Overlay,
+    palette::{band_color, host_color, LineColor},
+    polyline::PolylineCache,
+    range_to_label, range_to_query,
+    reload::SharedConfig,
+    slowlog::{timed, SharedSlowLog, SlowLog},
+    stride,
+    svg::{
+        align_values, stack_values, timestamp_to_date, timestamp_to_x, value_to_point,
+        values_to_area_points, values_to_band_path, values_to_polyline, TimeRange, CHART_MIN_X,
+    },
+    swap::SwapCell,
+    units::{AxisScale, ChartUnits, UnitSystem},
+    viewcache, ChartBand, ChartContext, ChartKind, ChartLine, ChartPoints, ChartValue, LineData,
+    LineStats, Smoothing, MAX_TABLE_ROWS,
+};
 
-const ACTUALIZATION_INTERVAL: Duration = Duration::from_secs(120);
+pub(crate) const ACTUALIZATION_INTERVAL: Duration = Duration::from_secs(120);
+// NOTE: Past this age charts of an unchanged database are regenerated so the range and stale detection follow the time
+const MAX_CHARTS_AGE: Duration = Duration::from_secs(10 * 60);
+pub(crate) const DEFAULT_TIME_RANGE: Duration = Duration::from_secs(3 * 60 * 60);
+// NOTE: Data is considered missing when the last snapshot is older than this fraction of the range
+const STALE_RANGE_RATIO: f64 = 0.05;
+/// Trailing window of the rolling statistics anomalies are detected with.
+const ANOMALY_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Standard deviations above the rolling mean from which a point is anomalous.
+const ANOMALY_SIGMAS: f64 = 3.0;
+// NOTE: Around the number of horizontal pixels of a chart, more points would be drawn over each other
+const MAX_CHART_POINTS: usize = 500;
+/// Max number of points per line of the lite charts, enough for the width of a phone.
+const LITE_CHART_POINTS: usize = 100;
+/// Percentile of the values of a chart taken as their bulk by `AxisScale::Robust`.
+const OUTLIER_PERCENTILE: f64 = 99.5;
+/// Max error of a rate in fraction of its value, the rates less accurate than it are drawn at zero.
+const RATE_MAX_RELATIVE_ERROR: f64 = 0.5;
+/// Default max number of points kept in memory across the series of every chart.
+pub const DEFAULT_MAX_MEMORY_POINTS: usize = 1_500_000;
 
 const CPU_USAGE_TITLE: &str = "CPU Usage";
+const CPU_FREQUENCY_TITLE: &str = "CPU Frequency";
 const RAM_USAGE_TITLE: &str = "RAM Usage";
+const SWAP_ACTIVITY_TITLE: &str = "Swap Activity";
 const LOAD_AVERAGE_TITLE: &str = "Load Average";
 const NETWORK_TITLE: &str = "Network";
 const DISKS_SPEED_TITLE: &str = "Disks Speed Usage";
 const DISKS_MEMORY_TITLE: &str = "Disks Memory Usage";
+const COLLECTION_DURATION_TITLE: &str = "Collector Health";
+const KERNEL_TITLE: &str = "Kernel";
+const NETWORK_UTILIZATION_TITLE: &str = "Network Utilization";
+
+const CPU_USAGE_SLUG: &str = "cpu";
+const CPU_FREQUENCY_SLUG: &str = "cpu-frequency";
+const RAM_USAGE_SLUG: &str = "ram";
+const SWAP_ACTIVITY_SLUG: &str = "swap-activity";
+const LOAD_AVERAGE_SLUG: &str = "load";
+const NETWORK_SLUG: &str = "network";
+const DISKS_SPEED_SLUG: &str = "disks-speed";
+const DISKS_MEMORY_SLUG: &str = "disks-memory";
+const COLLECTION_DURATION_SLUG: &str = "collection-duration";
+const KERNEL_SLUG: &str = "kernel";
+const NETWORK_UTILIZATION_SLUG: &str = "network-utilization";
+
+/// Slug of each built-in chart as (title, slug), its identifier in the page anchors, the `focus` and
+/// `open` parameters and the CSV downloads. Changing one breaks the links shared before.
+pub const CHART_SLUGS: [(&str, &str); 11] = [
+    (CPU_USAGE_TITLE, CPU_USAGE_SLUG),
+    (CPU_FREQUENCY_TITLE, CPU_FREQUENCY_SLUG),
+    (RAM_USAGE_TITLE, RAM_USAGE_SLUG),
+    (SWAP_ACTIVITY_TITLE, SWAP_ACTIVITY_SLUG),
+    (LOAD_AVERAGE_TITLE, LOAD_AVERAGE_SLUG),
+    (NETWORK_TITLE, NETWORK_SLUG),
+    (DISKS_SPEED_TITLE, DISKS_SPEED_SLUG),
+    (DISKS_MEMORY_TITLE, DISKS_MEMORY_SLUG),
+    (COLLECTION_DURATION_TITLE, COLLECTION_DURATION_SLUG),
+    (KERNEL_TITLE, KERNEL_SLUG),
+    (NETWORK_UTILIZATION_TITLE, NETWORK_UTILIZATION_SLUG),
+];
+
+/// Gauge of `KERNEL_GAUGES`, color and label of each line of the kernel chart.
+const KERNEL_GAUGE_LINES: [(&str, &str, &str); 4] = [
+    ("entropy", "#099", "Entropy"),
+    ("file-handles", "#a50", "File handles"),
+    ("conntrack", "#e0e", "Conntrack entries"),
+    ("threads", "#08a", "Threads"),
+];
+
+/// Colors of the lines of the network utilization chart, one per interface in name order and cycled.
+const NIC_COLORS: [&str; 6] = ["#e66", "#77e", "#099", "#a50", "#e0e", "#08a"];
+
+/// Slug of a chart title, the one of `CHART_SLUGS` for a built-in chart, otherwise the title lowercased
+/// with every run of characters other than letters and digits replaced by a `-`, e.g. `gpu-temp` for
+/// `GPU temp.`.
+pub fn chart_slug(title: &str) -> Cow<'static, str> {
+    if let Some((_, slug)) = CHART_SLUGS
+        .iter()
+        .find(|(chart_title, _)| *chart_title == title)
+    {
+        return Cow::Borrowed(slug);
+    }
+
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    Cow::Owned(slug.trim_end_matches('-').to_string())
+}
+
+/// Collapsible group of charts on the home page.
+#[derive(Debug)]
+pub struct ChartGroup {
+    /// Identifier used in the `open` query parameter, e.g. `compute`.
+    pub id: &'static str,
+    pub title: &'static str,
+    /// Identifiers of the member charts, in display order.
+    pub charts: &'static [&'static str],
+    /// Also holds the chart of every custom metric.
+    pub custom: bool,
+}
+
+/// Prefix of the identifiers of the custom metric charts, followed by the metric key.
+pub const CUSTOM_CHART_PREFIX: &str = "custom-";
+
+pub const CHART_GROUPS: [ChartGroup; 6] = [
+    ChartGroup {
+        id: "compute",
+        title: "Compute",
+        charts: &[
+            CPU_USAGE_SLUG,
+            CPU_FREQUENCY_SLUG,
+            LOAD_AVERAGE_SLUG,
+            COLLECTION_DURATION_SLUG,
+            KERNEL_SLUG,
+        ],
+        custom: false,
+    },
+    ChartGroup {
+        id: "memory",
+        title: "Memory",
+        charts: &[RAM_USAGE_SLUG, SWAP_ACTIVITY_SLUG],
+        custom: false,
+    },
+    ChartGroup {
+        id: "storage",
+        title: "Storage",
+        charts: &[DISKS_MEMORY_SLUG, DISKS_SPEED_SLUG],
+        custom: false,
+    },
+    ChartGroup {
+        id: "network",
+        title: "Network",
+        charts: &[NETWORK_SLUG, NETWORK_UTILIZATION_SLUG],
+        custom: false,
+    },
+    // NOTE: No sensor is recorded yet, the group stays hidden until one is
+    ChartGroup {
+        id: "sensors",
+        title: "Sensors",
+        charts: &[],
+        custom: false,
+    },
+    ChartGroup {
+        id: "custom",
+        title: "Custom",
+        charts: &[],
+        custom: true,
+    },
+];
 
 #[derive(Debug, TypedBuilder)]
 pub struct ChartsData {
     pub last_updated_time: Instant,
-    pub metrics: Vec<(&'static str, ChartContext)>,
+    pub range: TimeRange,
+    pub last_snapshot_time: Option<DateTime<Utc>>,
+    /// Number of snapshots taken in the range.
+    pub snapshots_count: usize,
+    /// Number of snapshots taken in the compared range, `None` without comparison.
+    pub compared_snapshots_count: Option<usize>,
+    /// Units the charts measured in bytes were generated with.
+    #[builder(default)]
+    pub units: ChartUnits,
+    /// Scale of the y axis the lines charts were generated with.
+    #[builder(default)]
+    pub scale: AxisScale,
+    #[builder(default)]
+    pub clock_mismatch: Option<ClockMismatch>,
+    /// The last snapshot of the range was taken in a container.
+    #[builder(default = false)]
+    pub container: bool,
+    pub metrics: Vec<(Cow<'static, str>, ChartContext)>,
+    /// When the charts were written to the view cache they were restored from at startup, `None` once
+    /// generated from the database.
+    #[builder(default)]
+    pub restored_from: Option<DateTime<Utc>>,
 }
 
 impl Default for ChartsData {
     fn default() -> Self {
         ChartsData {
             last_updated_time: Instant::now(),
+            range: TimeRange::ending_at(Utc::now().timestamp(), DEFAULT_TIME_RANGE),
+            last_snapshot_time: None,
+            snapshots_count: 0,
+            compared_snapshots_count: None,
+            units: ChartUnits::default(),
+            scale: AxisScale::default(),
+            clock_mismatch: None,
+            container: false,
             metrics: Vec::new(),
+            restored_from: None,
+        }
+    }
+}
+
+/// Newest snapshot disagreeing with the clock of this host, likely a clock or timezone mismatch between hosts.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockMismatch {
+    pub skew: ClockSkew,
+    pub newest_snapshot: DateTime<Utc>,
+    pub server_time: DateTime<Utc>,
+}
+
+impl ClockMismatch {
+    fn detect(database: &Database, now: DateTime<Utc>) -> Option<Self> {
+        let times = database
+            .snapshots()
+            .iter()
+            .rev()
+            .take(RECENT_SNAPSHOTS)
+            .rev()
+            .map(|snap| snap.time)
+            .collect::<Vec<_>>();
+        let result = clock_skew(&times, now)
+            .zip(times.last())
+            .map(|(skew, newest)| Self {
+                skew,
+                newest_snapshot: *newest,
+                server_time: now,
+            });
+        debug!(clock_mismatch = ?result);
+        result
+    }
+
+    pub fn text(&self) -> String {
+        let newest = self.newest_snapshot.format("%Y-%m-%d %H:%M:%S UTC");
+        let now = self.server_time.format("%Y-%m-%d %H:%M:%S UTC");
+        match self.skew {
+            ClockSkew::Future(ahead) => format!(
+                "The newest snapshot ({newest}) is {} min ahead of this server ({now}), the clocks or timezones of the collector and dashboard hosts likely disagree.",
+                ahead.num_minutes()
+            ),
+            ClockSkew::WholeHours(hours) => format!(
+                "The newest snapshot ({newest}) is almost exactly {hours} h behind this server ({now}) while snapshots are usually close together, the clock or timezone of the collector or dashboard host is likely off."
+            ),
+        }
+    }
+}
+
+impl ChartsData {
+    /// Time of the last snapshot when the end of the range has no data.
+    pub fn stale_since(&self) -> Option<DateTime<Utc>> {
+        self.last_snapshot_time.filter(|last_snapshot_time| {
+            (self.range.end - last_snapshot_time.timestamp()) as f64
+                > self.range.duration() as f64 * STALE_RANGE_RATIO
+        })
+    }
+
+    /// Charts of a group having data in the range, in the order of the group then the custom metrics.
+    pub fn group_charts(&self, group: &ChartGroup) -> Vec<&ChartContext> {
+        let custom_charts = self
+            .metrics
+            .iter()
+            .filter(|_| group.custom)
+            .filter(|(_, chart)| chart.id.starts_with(CUSTOM_CHART_PREFIX));
+
+        group
+            .charts
+            .iter()
+            .filter_map(|id| self.metrics.iter().find(|(_, chart)| chart.id == *id))
+            .chain(custom_charts)
+            .map(|(_, chart)| chart)
+            .filter(|chart| chart.has_data())
+            .collect()
+    }
+
+    /// Rough number of heap bytes held by the charts.
+    pub fn estimated_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self
+                .metrics
+                .iter()
+                .map(|(_, chart)| chart.estimated_bytes())
+                .sum::<usize>()
+    }
+}
+
+/// Earlier range to draw over the displayed one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompareWith {
+    /// Same duration, shifted back by the offset.
+    Offset(Duration),
+    /// Explicit range, scaled to the displayed one whatever its duration.
+    Range(TimeRange),
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub range: TimeRange,
+    pub label: String,
+}
+
+impl CompareWith {
+    fn comparison(self, range: &TimeRange) -> Comparison {
+        match self {
+            CompareWith::Offset(offset) => Comparison {
+                range: range.shifted_back(offset),
+                label: format!("compared (\u{2212}{})", range_to_query(offset)),
+            },
+            CompareWith::Range(compared_range) => {
+                let format = |timestamp| {
+                    Utc.timestamp_opt(timestamp, 0)
+                        .single()
+                        .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_default()
+                };
+                Comparison {
+                    range: compared_range,
+                    label: format!(
+                        "compared ({} \u{2192} {})",
+                        format(compared_range.start),
+                        format(compared_range.end)
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Shards of a sharded database, read again for the ranges the loaded snapshots do not cover.
+#[derive(Debug)]
+pub struct ShardSource {
+    sharded: ShardedDatabase,
+    key: Option<DatabaseKey>,
+    /// Start of the first loaded shard, every later snapshot is loaded.
+    loaded_since: DateTime<Utc>,
+}
+
+/// Last loaded database with the charts of the default range already generated.
+#[derive(Debug, Default)]
+pub struct SharedData {
+    pub database: Arc<Database>,
+    pub charts: Arc<ChartsData>,
+    /// `None` for a single file, which is always loaded whole.
+    pub shards: Option<Arc<ShardSource>>,
+    /// Charts were generated from the database at least once, only `false` with `--strict-readiness` until
+    /// the first load succeeds.
+    pub ready: bool,
+}
+
+pub type SharedState = Arc<SwapCell<SharedData>>;
+
+impl SharedData {
+    /// Database with every snapshot between `from` and `to`, the loaded one unless older shards are needed.
+    pub fn database_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Arc<Database> {
+        match &self.shards {
+            Some(shards) if from < shards.loaded_since => {
+                match with_retry(&RetryPolicy::INTERACTIVE, || {
+                    shards.sharded.load_between(from, to, shards.key.as_ref())
+                }) {
+                    Ok(database) => Arc::new(database),
+                    Err(err) => {
+                        warn!("Failed to load the shards between {from} and {to}: {err}");
+                        self.database.clone()
+                    }
+                }
+            }
+            _ => self.database.clone(),
+        }
+    }
+
+    /// Whether the charts of the default range are the ones requested.
+    #[allow(clippy::too_many_arguments)]
+    fn has_cached_charts(
+        &self,
+        range: TimeRange,
+        compare: Option<CompareWith>,
+        anomalies: bool,
+        bands: Option<bool>,
+        lite: bool,
+        smooth: Option<Smoothing>,
+        units: ChartUnits,
+        scale: AxisScale,
+    ) -> bool {
+        range == self.charts.range
+            && compare.is_none()
+            && !anomalies
+            && bands.is_none()
+            && !lite
+            && smooth.is_none()
+            && units == self.charts.units
+            && scale == self.charts.scale
+    }
+
+    /// Range of the `duration` preceding the last actualization.
+    pub fn range_for(&self, duration: Duration) -> TimeRange {
+        TimeRange::ending_at(self.charts.range.end, duration)
+    }
+
+    /// Charts for the `range`, optionally compared with an earlier range and with their anomalous points.
+    ///
+    /// `bands` shows or hides the min/max bands of every chart, `None` keeping the default of each chart.
+    /// `lite` draws fewer points per line, e.g. for a phone.
+    #[allow(clippy::too_many_arguments)]
+    pub fn charts_for(
+        &self,
+        range: TimeRange,
+        compare: Option<CompareWith>,
+        anomalies: bool,
+        bands: Option<bool>,
+        lite: bool,
+        smooth: Option<Smoothing>,
+        units: ChartUnits,
+        scale: AxisScale,
+        slow_log: &SlowLog,
+    ) -> Arc<ChartsData> {
+        if self.has_cached_charts(range, compare, anomalies, bands, lite, smooth, units, scale) {
+            self.charts.clone()
+        } else {
+            let comparison = compare.map(|compare| compare.comparison(&range));
+            let start = comparison.as_ref().map_or(range.start, |comparison| {
+                comparison.range.start.min(range.start)
+            });
+            // NOTE: Only reads the disk for the shards older than the loaded ones
+            let database = timed(Some(slow_log), "database load", Some(range), || {
+                self.database_between(timestamp_to_date(start), timestamp_to_date(range.end))
+            });
+            let mut charts = ChartsData::generate_with_polylines(
+                &database,
+                range,
+                comparison,
+                anomalies,
+                bands,
+                lite,
+                smooth,
+                units,
+                scale,
+                None,
+                Some(slow_log),
+            );
+            // NOTE: Snapshots in the future are out of the range the database was loaded for
+            charts.clock_mismatch = self.charts.clock_mismatch;
+            Arc::new(charts)
+        }
+    }
+}
+
+/// Everything the charts of a request are generated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChartsKey {
+    range: TimeRange,
+    compare: Option<CompareWith>,
+    anomalies: bool,
+    bands: Option<bool>,
+    lite: bool,
+    smooth: Option<Smoothing>,
+    units: ChartUnits,
+    scale: AxisScale,
+}
+
+pub type SharedCoalescer = Arc<Coalescer<ChartsKey, Arc<ChartsData>>>;
+
+/// Charts of a request, identical requests arriving together share a single generation.
+#[tracing::instrument(level = "debug", skip(shared_data, coalescer, slow_log))]
+#[allow(clippy::too_many_arguments)]
+pub async fn coalesced_charts_for(
+    shared_data: Arc<SharedData>,
+    coalescer: &SharedCoalescer,
+    slow_log: SharedSlowLog,
+    range: TimeRange,
+    compare: Option<CompareWith>,
+    anomalies: bool,
+    bands: Option<bool>,
+    lite: bool,
+    smooth: Option<Smoothing>,
+    units: ChartUnits,
+    scale: AxisScale,
+) -> Arc<ChartsData> {
+    if shared_data.has_cached_charts(range, compare, anomalies, bands, lite, smooth, units, scale) {
+        return shared_data.charts.clone();
+    }
+
+    // NOTE: Relative ranges end at the last actualization so a reload never shares older charts
+    let key = ChartsKey {
+        range,
+        compare,
+        anomalies,
+        bands,
+        lite,
+        smooth,
+        units,
+        scale,
+    };
+    coalescer
+        .get_or_generate(key, || async move {
+            // NOTE: A generation that panicked shows the charts of the last actualization instead
+            let fallback = shared_data.charts.clone();
+            tokio::task::spawn_blocking(move || {
+                shared_data.charts_for(
+                    range, compare, anomalies, bands, lite, smooth, units, scale, &slow_log,
+                )
+            })
+            .await
+            .unwrap_or_else(|err| {
+                error!("Failed to generate the charts: {err}");
+                fallback
+            })
+        })
+        .await
+}
+
+/// Identity of the database file, it is only read again when this changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileFingerprint {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileFingerprint {
+    fn of(path: &str) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        if !metadata.is_dir() {
+            return Ok(Self {
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            });
+        }
+
+        // NOTE: Writing to any shard changes the newest modification time or the total size
+        let mut fingerprint = Self {
+            modified: None,
+            len: 0,
+        };
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            if entry
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "db")
+            {
+                let metadata = entry.metadata()?;
+                fingerprint.modified = fingerprint.modified.max(metadata.modified().ok());
+                fingerprint.len += metadata.len();
+            }
+        }
+
+        Ok(fingerprint)
+    }
+}
+
+/// Counters of the actualization task, exposed on `/stats`.
+#[derive(Debug, Default)]
+pub struct ActualizationStats {
+    pub checks: AtomicU64,
+    /// Checks that found the file unchanged and did not read it.
+    pub skipped: AtomicU64,
+    /// Reads whose snapshots were the same as the loaded ones.
+    pub unchanged: AtomicU64,
+    /// Reads followed by a regeneration of the charts.
+    pub reloads: AtomicU64,
+    /// Regenerations of the charts of an unchanged database so the range keeps up with the time.
+    pub refreshes: AtomicU64,
+    pub failures: AtomicU64,
+    /// Snapshots of the last read file dropped or thinned out to stay within the memory budget.
+    pub trimmed_snapshots: AtomicU64,
+    /// Lines of the default range charts that reused points of their previous generation.
+    pub polyline_hits: AtomicU64,
+    /// Lines of the default range charts computed from scratch.
+    pub polyline_misses: AtomicU64,
+    /// Unix timestamp of the last successful check of the file, 0 before the first one.
+    pub checked_at: AtomicI64,
+    /// Milliseconds taken by the last cycle of the task, from the check of the file to the view cache.
+    pub last_cycle_ms: AtomicU64,
+    pub max_cycle_ms: AtomicU64,
+    /// Cycles longer than their interval, delaying the next ones.
+    pub cycle_overruns: AtomicU64,
+    /// The last cycle took longer than its interval.
+    pub last_overran: AtomicBool,
+    /// Milliseconds the last cycle started after its scheduled time.
+    pub lag_ms: AtomicU64,
+}
+
+impl ActualizationStats {
+    pub fn checked_at(&self) -> Option<DateTime<Utc>> {
+        match self.checked_at.load(Ordering::Relaxed) {
+            0 => None,
+            timestamp => Utc.timestamp_opt(timestamp, 0).single(),
+        }
+    }
+
+    /// The last cycle took longer than the interval of the task, `ACTUALIZATION_INTERVAL`.
+    pub fn overran(&self) -> bool {
+        self.last_overran.load(Ordering::Relaxed)
+    }
+
+    fn record_cycle(&self, lag: Duration, duration: Duration, interval: Duration) {
+        let duration_ms = duration.as_millis() as u64;
+        self.lag_ms.store(lag.as_millis() as u64, Ordering::Relaxed);
+        self.last_cycle_ms.store(duration_ms, Ordering::Relaxed);
+        self.max_cycle_ms.fetch_max(duration_ms, Ordering::Relaxed);
+        let overran = duration > interval;
+        self.last_overran.store(overran, Ordering::Relaxed);
+        if overran {
+            self.cycle_overruns.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                duration_ms,
+                "Actualization took {}, longer than its {} interval, the next one is delayed",
+                humantime::format_duration(Duration::from_millis(duration_ms)),
+                humantime::format_duration(interval)
+            );
+        }
+    }
+}
+
+pub type SharedStats = Arc<ActualizationStats>;
+
+/// Whether both databases end with the same snapshots.
+fn same_snapshots(loaded: &Database, read: &Database) -> bool {
+    loaded.len() == read.len()
+        && loaded.snapshots().last().map(|snap| snap.time)
+            == read.snapshots().last().map(|snap| snap.time)
+}
+
+/// Number of points a snapshot adds to the series of the charts.
+pub fn points_per_snapshot() -> usize {
+    static POINTS: Lazy<usize> = Lazy::new(|| {
+        chart_sections(&Database::default(), ChartUnits::default(), &[])
+            .iter()
+            .map(|section| section.series.len())
+            .sum()
+    });
+    *POINTS
+}
+
+/// Keep at most `max_snapshots` snapshots, returning the number of removed ones.
+///
+/// Snapshots older than `keep_since` are thinned out first, keeping the most recent ones of each
+/// stride, then the oldest of the remaining ones are trimmed.
+fn apply_memory_budget(
+    database: &mut Database,
+    max_snapshots: usize,
+    keep_since: DateTime<Utc>,
+) -> usize {
+    let total = database.len();
+    if total <= max_snapshots {
+        return 0;
+    }
+
+    let older = database
+        .snapshots()
+        .partition_point(|snapshot| snapshot.time < keep_since);
+    let recent = total - older;
+    if recent >= max_snapshots {
+        database.drop_oldest(total - max_snapshots);
+        warn!(
+            "Memory budget exceeded by the default range alone, trimmed the {} oldest snapshots and kept the {max_snapshots} most recent",
+            total - max_snapshots
+        );
+    } else {
+        let kept_older = max_snapshots - recent;
+        let step = older.div_ceil(kept_older);
+        let mut idx = 0;
+        // NOTE: Counted from the most recent so the snapshot next to the default range is kept
+        database.retain_snapshots(|_| {
+            let keep = idx >= older || (older - 1 - idx) % step == 0;
+            idx += 1;
+            keep
+        });
+        warn!(
+            "Memory budget exceeded, thinned the {older} snapshots before {keep_since} to one in {step}, {} removed",
+            total - database.len()
+        );
+    }
+
+    total - database.len()
+}
+
+/// Whole database of a file, or the shards from the last one or the default range for a directory.
+///
+/// Reads failing because of `sysmet-update` (e.g. holding the lock) are attempted again following `retry`.
+fn load_database(
+    path: &str,
+    key: Option<&DatabaseKey>,
+    retry: &RetryPolicy,
+) -> Result<(Database, Option<ShardSource>), Error> {
+    if !std::path::Path::new(path).is_dir() {
+        return Ok((
+            with_retry(retry, || Database::open_readonly_streamed(path, key))?,
+            None,
+        ));
+    }
+
+    let sharded = with_retry(retry, || Database::open_sharded(std::path::Path::new(path)))?;
+    let now = Utc::now();
+    let default_since = now - chrono::Duration::from_std(DEFAULT_TIME_RANGE).unwrap();
+    // NOTE: The last shard is always loaded so a stopped update still shows since when data is missing
+    let since = sharded
+        .shards()
+        .last()
+        .map_or(default_since, |last| last.start.min(default_since));
+    let database = with_retry(retry, || sharded.load_between(since, now, key))?;
+    let loaded_since = sharded
+        .shards_between(since, now)
+        .next()
+        .map_or(since, |first| first.start);
+    debug!(
+        "Loaded {} shards since {loaded_since}",
+        sharded.shards_between(since, now).count()
+    );
+
+    Ok((
+        database,
+        Some(ShardSource {
+            sharded,
+            key: key.cloned(),
+            loaded_since,
+        }),
+    ))
+}
+
+/// Charts of the `duration` ending now, the database being read once without keeping any state.
+#[tracing::instrument(level = "debug", skip(key))]
+pub fn generate_once(
+    path: &str,
+    key: Option<&DatabaseKey>,
+    duration: Duration,
+    units: ChartUnits,
+    scale: AxisScale,
+) -> Result<ChartsData, Error> {
+    let (database, shards) = load_database(path, key, &RetryPolicy::BACKGROUND)?;
+    let range = TimeRange::ending_at(Utc::now().timestamp(), duration);
+    let shared_data = SharedData {
+        database: Arc::new(database),
+        shards: shards.map(Arc::new),
+        ..Default::default()
+    };
+    // NOTE: Ranges longer than the default one need older shards than the loaded ones
+    let dates = range.dates();
+    let database = shared_data.database_between(dates.start, dates.end);
+
+    Ok(ChartsData::generate(
+        &database, range, None, false, None, None, units, scale,
+    ))
+}
+
+fn store_generated(
+    shared_state: &SharedState,
+    database: Arc<Database>,
+    shards: Option<Arc<ShardSource>>,
+    units: ChartUnits,
+    scale: AxisScale,
+    polylines: &mut PolylineCache,
+    slow_log: &SlowLog,
+) {
+    let range = TimeRange::ending_at(Utc::now().timestamp(), DEFAULT_TIME_RANGE);
+    polylines.start_generation();
+    // NOTE: Generated before swapping so readers never wait for it
+    let charts = Arc::new(ChartsData::generate_with_polylines(
+        &database,
+        range,
+        None,
+        false,
+        None,
+        false,
+        None,
+        units,
+        scale,
+        Some(polylines),
+        Some(slow_log),
+    ));
+    // NOTE: Every later store comes from here, the readiness gate stays open once opened
+    shared_state.store(SharedData {
+        database,
+        charts,
+        shards,
+        ready: true,
+    });
+}
+
+/// `force` reads the database and generates the charts again even when unchanged, e.g. after a reload of
+/// the configuration.
+#[tracing::instrument(level = "debug", skip(shared_state, stats, slow_log, key, polylines))]
+#[allow(clippy::too_many_arguments)]
+fn actualize(
+    shared_state: &SharedState,
+    stats: &ActualizationStats,
+    slow_log: &SlowLog,
+    path: &str,
+    key: Option<&DatabaseKey>,
+    max_memory_points: usize,
+    units: ChartUnits,
+    scale: AxisScale,
+    fingerprint: &mut Option<FileFingerprint>,
+    polylines: &mut PolylineCache,
+    force: bool,
+) {
+    stats.checks.fetch_add(1, Ordering::Relaxed);
+    let current = match FileFingerprint::of(path) {
+        Ok(current) => current,
+        Err(err) => {
+            stats.failures.fetch_add(1, Ordering::Relaxed);
+            warn!("Failed to check the database: {err}");
+            return;
+        }
+    };
+    stats
+        .checked_at
+        .store(Utc::now().timestamp(), Ordering::Relaxed);
+
+    let shared_data = shared_state.load();
+    if *fingerprint == Some(current) && !force {
+        if shared_data.charts.last_updated_time.elapsed() < MAX_CHARTS_AGE {
+            stats.skipped.fetch_add(1, Ordering::Relaxed);
+            debug!("Database unchanged, skipping");
+        } else {
+            stats.refreshes.fetch_add(1, Ordering::Relaxed);
+            debug!("Database unchanged, refreshing the charts range");
+            store_generated(
+                shared_state,
+                shared_data.database.clone(),
+                shared_data.shards.clone(),
+                units,
+                scale,
+                polylines,
+                slow_log,
+            );
+        }
+        return;
+    }
+
+    // NOTE: The fingerprint is taken before reading so a write during the read is picked up next time
+    // NOTE: Nobody waits for the actualization, it can wait for a write to end rather than skip a turn
+    match timed(Some(slow_log), "database load", None, || {
+        load_database(path, key, &RetryPolicy::BACKGROUND)
+    }) {
+        Ok((mut database, shards)) => {
+            *fingerprint = Some(current);
+            // NOTE: The default range is kept whole so its cached charts never lose points
+            let keep_since = Utc::now() - chrono::Duration::from_std(DEFAULT_TIME_RANGE).unwrap();
+            let trimmed = apply_memory_budget(
+                &mut database,
+                (max_memory_points / points_per_snapshot()).max(1),
+                keep_since,
+            );
+            stats
+                .trimmed_snapshots
+                .store(trimmed as u64, Ordering::Relaxed);
+            // NOTE: Rewriting the same snapshots (e.g. touching the file) only changes the fingerprint
+            if !force
+                && same_snapshots(&shared_data.database, &database)
+                && shared_data.charts.last_updated_time.elapsed() < MAX_CHARTS_AGE
+            {
+                stats.unchanged.fetch_add(1, Ordering::Relaxed);
+                debug!("Database rewritten with the same snapshots, skipping");
+            } else {
+                stats.reloads.fetch_add(1, Ordering::Relaxed);
+                store_generated(
+                    shared_state,
+                    Arc::new(database),
+                    shards.map(Arc::new),
+                    units,
+                    scale,
+                    polylines,
+                    slow_log,
+                );
+            }
+        }
+        Err(err) => {
+            stats.failures.fetch_add(1, Ordering::Relaxed);
+            warn!("Failed to load the database: {err}");
         }
     }
 }
 
-#[tracing::instrument(level = "debug")]
+/// Read the database every `ACTUALIZATION_INTERVAL`, or right away when `actualize_now` is notified.
+#[tracing::instrument(level = "debug", skip(stats, slow_log, shared_config, actualize_now))]
+#[allow(clippy::too_many_arguments)]
 pub async fn actualization_task(
-    shared_chart_data: Arc<RwLock<ChartsData>>,
+    shared_state: SharedState,
+    stats: SharedStats,
+    slow_log: SharedSlowLog,
+    shared_config: SharedConfig,
     database: String,
-    mut db_rx: Receiver<()>,
+    key: Option<DatabaseKey>,
+    view_cache: Option<PathBuf>,
+    actualize_now: Arc<Notify>,
+    db_rx: Receiver<()>,
 ) {
     debug!("Spawned actualization task");
-    let mut interval = tokio::time::interval(ACTUALIZATION_INTERVAL);
+    let mut fingerprint = None;
+    let mut polylines = PolylineCache::default();
 
-    loop {
-        let interval = interval.tick();
-        tokio::pin!(interval);
-
-        tokio::select! {
-            _ = &mut interval => {
-                if let Ok(database) = Database::from_file(&database) {
-                    let mut chart_data = shared_chart_data.write().await;
-                    *chart_data = database.into();
+    run_cycles(
+        ACTUALIZATION_INTERVAL,
+        &stats,
+        &actualize_now,
+        db_rx,
+        |forced| {
+            let previous = shared_state.load().charts.clone();
+            // NOTE: Loaded each cycle so a reload applies from the next one
+            let config = shared_config.load();
+            actualize(
+                &shared_state,
+                &stats,
+                &slow_log,
+                &database,
+                key.as_ref(),
+                config.max_memory_points,
+                config.units,
+                config.scale,
+                &mut fingerprint,
+                &mut polylines,
+                forced,
+            );
+            stats.polyline_hits.store(polylines.hits, Ordering::Relaxed);
+            stats
+                .polyline_misses
+                .store(polylines.misses, Ordering::Relaxed);
+            let charts = shared_state.load().charts.clone();
+            // NOTE: Only written when the charts were generated again
+            if let Some(path) = view_cache
+                .as_deref()
+                .filter(|_| !Arc::ptr_eq(&previous, &charts))
+            {
+                if let Err(err) = viewcache::save(path, &charts) {
+                    warn!("{err:#}");
                 }
             }
+        },
+    )
+    .await;
+
+    debug!("Finished actualization task");
+}
+
+/// Run `cycle` every `period`, or right away (forced) when `actualize_now` is notified, until `db_rx`
+/// fires, recording the lag and duration of each cycle in `stats`.
+async fn run_cycles(
+    period: Duration,
+    stats: &ActualizationStats,
+    actualize_now: &Notify,
+    mut db_rx: Receiver<()>,
+    mut cycle: impl FnMut(bool),
+) {
+    let mut interval = tokio::time::interval(period);
+    // NOTE: The default burst would read the database again right after a slow cycle, for each missed tick
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let (scheduled, forced) = tokio::select! {
+            scheduled = interval.tick() => (scheduled, false),
+            _ = actualize_now.notified() => {
+                debug!("Actualizing right away");
+                (Instant::now(), true)
+            }
             _ = &mut db_rx => {
                 break;
             }
+        };
+        // NOTE: The next cycle is a whole interval after this one
+        if forced {
+            interval.reset();
         }
 
+        let started = Instant::now();
+        cycle(forced);
+        stats.record_cycle(
+            started.saturating_duration_since(scheduled),
+            started.elapsed(),
+            period,
+        );
+
         trace!("Looped through actualization process");
     }
-
-    debug!("Finished actualization task");
 }
 
-impl From<Database> for ChartsData {
-    fn from(chart_data: Database) -> Self {
-        let snapshots_len = chart_data.snapshots.len();
+/// Values of one line of a chart as (color, label, values).
+type ChartSeries<T = ()> = (&'static str, Option<Cow<'static, str>>, Vec<ChartValue<T>>);
+/// Series as drawn, each value with the (min, max) of the values it averages.
+type DisplayedSeries = ChartSeries<(f64, f64)>;
 
-        let cpus_usages: Vec<ChartValue<_>> = chart_data.get_cpu_usage().into_iter().fold(
-            Vec::with_capacity(snapshots_len),
-            |mut cpus_usages, (snap, timestamp)| {
-                cpus_usages.push((snap, timestamp.timestamp(), ()) as ChartValue<_>);
-                cpus_usages
-            },
-        );
-        let cpu_chart = build_chart(vec![("#e00", None, cpus_usages)]);
+/// Series of a chart section before being scaled to a range.
+struct ChartSection {
+    /// Stable identifier used in URLs, e.g. `cpu` or `custom-gpu` for a custom metric.
+    id: Cow<'static, str>,
+    title: Cow<'static, str>,
+    kind: ChartKind,
+    unit: &'static str,
+    /// Unit system of the series measured in bytes, `None` for the other units.
+    unit_system: Option<UnitSystem>,
+    /// Whether the min/max bands are shown by default, for the charts whose spikes matter.
+    band: bool,
+    /// Lowest rate telling more than the steps of the counters it is computed from, `None` for the charts
+    /// that aren't rates.
+    noise_floor: Option<f64>,
+    series: Vec<ChartSeries>,
+}
 
-        let (ram_usages, swap_usages): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
-            chart_data.get_ram_usage().into_iter().fold(
-                (
-                    Vec::with_capacity(snapshots_len),
-                    Vec::with_capacity(snapshots_len),
-                ),
-                |(mut ram_usages, mut swap_usages), ((ram, swap), timestamp)| {
-                    let time = timestamp.timestamp();
-                    ram_usages.push((ram, time, ()) as ChartValue<_>);
-                    swap_usages.push((swap, time, ()) as ChartValue<_>);
-
-                    (ram_usages, swap_usages)
-                },
-            );
-        let ram_chart = build_chart(vec![
-            ("#0e0", Some("RAM"), ram_usages),
-            ("#e0e", Some("Swap"), swap_usages),
-        ]);
-
-        let (load_avgs_one, load_avgs_five, load_avgs_fiveteen): (
-            Vec<ChartValue<_>>,
-            Vec<ChartValue<_>>,
-            Vec<ChartValue<_>>,
-        ) = chart_data.get_load().into_iter().fold(
-            (
-                Vec::with_capacity(snapshots_len),
-                Vec::with_capacity(snapshots_len),
-                Vec::with_capacity(snapshots_len),
-            ),
-            |(mut load_avgs_one, mut load_avgs_five, mut load_avgs_fiveteen),
-             ((load_avg_one, load_avg_five, load_avg_fiveteen), timestamp)| {
-                let time = timestamp.timestamp();
-                load_avgs_one.push((load_avg_one, time, ()) as ChartValue<_>);
-                load_avgs_five.push((load_avg_five, time, ()) as ChartValue<_>);
-                load_avgs_fiveteen.push((load_avg_fiveteen, time, ()) as ChartValue<_>);
+impl ChartSection {
+    /// Convert the series measured in bytes, the prefix being chosen from the max of both sections so they
+    /// share the same unit.
+    fn scale_bytes(&mut self, compared: Option<&mut ChartSection>) {
+        let Some(unit_system) = self.unit_system else {
+            return;
+        };
+        let max_bytes = self
+            .series
+            .iter()
+            .chain(compared.iter().flat_map(|compared| compared.series.iter()))
+            .flat_map(|(_, _, values)| values.iter().map(|(value, _, _)| *value))
+            .fold(0f64, f64::max);
+        let scale = unit_system.scale(max_bytes);
 
-                (load_avgs_one, load_avgs_five, load_avgs_fiveteen)
-            },
-        );
-        let load_avg_chart = build_chart(vec![
-            ("#a0a", Some("1 minutes"), load_avgs_one),
-            ("#0a0", Some("5 minutes"), load_avgs_five),
-            ("#00e", Some("15 minutes"), load_avgs_fiveteen),
-        ]);
+        for section in std::iter::once(self).chain(compared) {
+            section.unit = scale.unit;
+            for (_, _, values) in section.series.iter_mut() {
+                for (value, _, _) in values.iter_mut() {
+                    *value = scale.convert(*value);
+                }
+            }
+        }
+    }
+}
 
-        let (network_recv_usage, network_sent_usage): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
-            chart_data.get_network().into_iter().fold(
-                (
-                    Vec::with_capacity(snapshots_len),
-                    Vec::with_capacity(snapshots_len),
-                ),
-                |(mut network_recv_usage, mut network_sent_usage), ((recv, sent), timestamp)| {
-                    let time = timestamp.timestamp();
-                    network_recv_usage.push((recv, time, ()) as ChartValue<_>);
-                    network_sent_usage.push((sent, time, ()) as ChartValue<_>);
+impl ChartsData {
+    /// Charts of the `range`, `smooth` drawing the lines as their moving average over their raw values and
+    /// `scale` choosing the max of their y axis.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate(
+        database: &Database,
+        range: TimeRange,
+        comparison: Option<Comparison>,
+        anomalies: bool,
+        bands: Option<bool>,
+        smooth: Option<Smoothing>,
+        units: ChartUnits,
+        scale: AxisScale,
+    ) -> Self {
+        Self::generate_with_polylines(
+            database, range, comparison, anomalies, bands, false, smooth, units, scale, None, None,
+        )
+    }
 
-                    (network_recv_usage, network_sent_usage)
-                },
-            );
-        let network_chart = build_chart(vec![
-            ("#faa", Some("Received"), network_recv_usage),
-            ("#aaf", Some("Sent"), network_sent_usage),
-        ]);
+    /// Same as `generate`, the polylines of the lines charts being built with `polylines` and the slow
+    /// steps being kept in `slow_log`, `lite` keeping at most `LITE_CHART_POINTS` points per line.
+    #[tracing::instrument(level = "debug", skip(database, polylines, slow_log))]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn generate_with_polylines(
+        database: &Database,
+        range: TimeRange,
+        comparison: Option<Comparison>,
+        anomalies: bool,
+        bands: Option<bool>,
+        lite: bool,
+        smooth: Option<Smoothing>,
+        units: ChartUnits,
+        scale: AxisScale,
+        mut polylines: Option<&mut PolylineCache>,
+        slow_log: Option<&SlowLog>,
+    ) -> Self {
+        let max_points = if lite {
+            LITE_CHART_POINTS
+        } else {
+            MAX_CHART_POINTS
+        };
+        let chart_data =
+            database.filter_between(timestamp_to_date(range.start), timestamp_to_date(range.end));
+        let snapshots_len = chart_data.len();
+        // NOTE: Snapshots before the range still tell since when the data is missing
+        let last_snapshot_time = database
+            .snapshots()
+            .iter()
+            .rev()
+            .map(|snap| snap.time)
+            .find(|time| time.timestamp() <= range.end);
 
-        let (disk_speed_read, disk_speed_write): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
-            chart_data.get_disks_speed_usage().into_iter().fold(
-                (
-                    Vec::with_capacity(snapshots_len),
-                    Vec::with_capacity(snapshots_len),
+        // NOTE: Taken from both ranges so their sections stay in the same order
+        let custom_keys = custom_keys(database);
+        let mut compared_sections = comparison.as_ref().map(|comparison| {
+            let compared_data = database.filter_between(
+                timestamp_to_date(comparison.range.start),
+                timestamp_to_date(comparison.range.end),
+            );
+            debug!("{} snapshots in the compared range", compared_data.len());
+            (
+                compared_data.len(),
+                timed(
+                    slow_log,
+                    "compared series extraction",
+                    Some(comparison.range),
+                    || chart_sections(&compared_data, units, &custom_keys),
                 ),
-                |(mut disk_speed_read, mut disk_speed_write), ((read, write), timestamp)| {
-                    let time = timestamp.timestamp();
-                    disk_speed_read.push((read, time, ()) as ChartValue<_>);
-                    disk_speed_write.push((write, time, ()) as ChartValue<_>);
-                    (disk_speed_read, disk_speed_write)
-                },
-            );
-        let disk_speed_chart = build_chart(vec![
-            ("#afa", Some("Read"), disk_speed_read),
-            ("#faf", Some("Write"), disk_speed_write),
-        ]);
-
-        let disk_memory_usage: Vec<ChartValue<_>> =
-            chart_data.get_disk_memory_usage().into_iter().fold(
-                Vec::with_capacity(snapshots_len),
-                |mut disk_memory_usage, (usage, timestamp)| {
-                    let time = timestamp.timestamp();
-                    disk_memory_usage.push((usage, time, ()) as ChartValue<_>);
-                    disk_memory_usage
-                },
+            )
+        });
+        let mut sections = timed(slow_log, "series extraction", Some(range), || {
+            chart_sections(&chart_data, units, &custom_keys)
+        });
+        // NOTE: Sections are always built in the same order for both ranges
+        for (idx, section) in sections.iter_mut().enumerate() {
+            section.scale_bytes(
+                compared_sections
+                    .as_mut()
+                    .map(|(_, compared)| &mut compared[idx]),
             );
-        let disk_memory_chart = build_chart(vec![("#a4f", Some("Usage"), disk_memory_usage)]);
+        }
+        let compared_sections =
+            comparison
+                .as_ref()
+                .zip(compared_sections)
+                .map(|(comparison, (count, compared))| {
+                    let displayed = compared
+                        .iter()
+                        .map(|section| {
+                            displayed_series(&section.series, &comparison.range, max_points)
+                        })
+                        .collect::<Vec<_>>();
+                    (count, displayed)
+                });
 
-        let chart_sections = vec![
-            (
-                CPU_USAGE_TITLE,
-                ChartContext::builder()
-                    .max_value(cpu_chart.0)
-                    .collections(cpu_chart.1)
-                    .build(),
-            ),
-            (
-                RAM_USAGE_TITLE,
-                ChartContext::builder()
-                    .max_value(ram_chart.0)
-                    .collections(ram_chart.1)
-                    .build(),
-            ),
-            (
-                LOAD_AVERAGE_TITLE,
-                ChartContext::builder()
-                    .max_value(load_avg_chart.0)
-                    .collections(load_avg_chart.1)
-                    .build(),
-            ),
-            (
-                NETWORK_TITLE,
-                ChartContext::builder()
-                    .unit("MiB") // TODO: MiB and if > 1024 GiB
-                    .max_value(network_chart.0)
-                    .collections(network_chart.1)
-                    .build(),
-            ),
-            (
-                DISKS_SPEED_TITLE,
-                ChartContext::builder()
-                    .unit("MiB")
-                    .max_value(disk_speed_chart.0)
-                    .collections(disk_speed_chart.1)
-                    .build(),
-            ),
-            (
-                DISKS_MEMORY_TITLE,
-                ChartContext::builder()
-                    .unit("MiB")
-                    .max_value(disk_memory_chart.0)
-                    .collections(disk_memory_chart.1)
-                    .build(),
-            ),
-        ];
+        let annotations = annotation_markers(&chart_data, &range);
+        let suspensions = suspension_spans(&chart_data, &range);
+        let axis = time_axis(&range);
+        let cpu_count_changes = cpu_count_changes(&chart_data);
+        let chart_sections = sections
+            .into_iter()
+            .enumerate()
+            .map(|(idx, section)| {
+                let compared = comparison.as_ref().zip(compared_sections.as_ref()).map(
+                    |(comparison, (_, sections))| (&comparison.range, sections[idx].as_slice()),
+                );
+                let displayed = displayed_series(&section.series, &range, max_points);
+                // NOTE: Stacked areas have no line to draw the raw values behind
+                let smoothed = smooth
+                    .filter(|_| section.kind == ChartKind::Lines)
+                    .map(|smooth| smooth_series(&displayed, smooth));
+                let show_band = bands.unwrap_or(section.band);
+                let (max_value, mut collections, compared_collections, chart_bands) = timed(
+                    slow_log,
+                    format!("chart {}", section.id),
+                    Some(range),
+                    || match section.kind {
+                        ChartKind::Lines => build_chart(
+                            &range,
+                            smoothed.as_deref().unwrap_or(&displayed),
+                            smoothed.is_some().then_some(displayed.as_slice()),
+                            compared,
+                            show_band,
+                            scale,
+                            // NOTE: The cache only holds the points of raw lines at full resolution
+                            polylines
+                                .as_deref_mut()
+                                .filter(|_| smoothed.is_none() && !lite)
+                                .map(|cache| (cache, &*section.id)),
+                        ),
+                        ChartKind::StackedArea => {
+                            build_stacked_chart(&range, &displayed, compared, show_band)
+                        }
+                    },
+                );
+                if anomalies {
+                    let points = anomaly_points(section.kind, &displayed, max_value, &range);
+                    // NOTE: Both skip the empty series so they stay aligned
+                    for (line, points) in collections.iter_mut().zip(points) {
+                        line.anomalies = Some(points);
+                    }
+                }
+
+                // NOTE: Both are in percent of the CPUs of each snapshot, the note explains the step
+                let subtitle = cpu_count_changes
+                    .clone()
+                    .filter(|_| matches!(&*section.id, CPU_USAGE_SLUG | LOAD_AVERAGE_SLUG));
+
+                (
+                    section.title.clone(),
+                    ChartContext::builder()
+                        .id(section.id)
+                        .title(section.title)
+                        .subtitle(subtitle)
+                        .range_label(range_to_label(Duration::from_secs(
+                            range.duration().max(0) as u64
+                        )))
+                        .data(line_data(&section.series))
+                        .annotations(annotations.clone())
+                        .suspensions(suspensions.clone())
+                        .time_axis(axis.clone())
+                        .kind(section.kind)
+                        .unit(section.unit)
+                        .unit_system(section.unit_system)
+                        .max_value(max_value)
+                        .collections(collections)
+                        .compared_collections(compared_collections)
+                        .show_band(show_band)
+                        .bands(chart_bands)
+                        .noise_floor(section.noise_floor)
+                        .compared_label(
+                            comparison
+                                .as_ref()
+                                .map(|comparison| comparison.label.clone()),
+                        )
+                        .build(),
+                )
+            })
+            .collect::<Vec<_>>();
 
-        ChartsData::builder()
+        let mut charts = ChartsData::builder()
             .last_updated_time(Instant::now())
+            .range(range)
+            .last_snapshot_time(last_snapshot_time)
+            .snapshots_count(snapshots_len)
+            .compared_snapshots_count(compared_sections.map(|(count, _)| count))
+            .units(units)
+            .scale(scale)
+            .clock_mismatch(ClockMismatch::detect(database, Utc::now()))
+            .container(
+                chart_data
+                    .snapshots()
+                    .last()
+                    .is_some_and(|snapshot| snapshot.container),
+            )
             .metrics(chart_sections)
-            .build()
+            .build();
+
+        if let Some(stale_since) = charts.stale_since() {
+            debug!("No snapshot since {stale_since}");
+            let no_data_from = timestamp_to_x(stale_since.timestamp(), &range).max(CHART_MIN_X);
+            for (_title, context) in charts.metrics.iter_mut() {
+                context.no_data_from = Some(no_data_from);
+            }
+        }
+
+        charts
     }
 }
 
-#[allow(clippy::type_complexity)]
-fn build_chart<T: Debug>(
-    collections: Vec<(&str, Option<&str>, Vec<ChartValue<T>>)>,
-) -> (f64, Vec<ChartLine>) {
-    let max_value = collections
+/// Series smoothed by the moving average of `smooth`, the min and max of each point being kept.
+///
+/// A duration is converted to points from the median interval between the points of each series.
+fn smooth_series(series: &[DisplayedSeries], smooth: Smoothing) -> Vec<DisplayedSeries> {
+    series
         .iter()
-        .flat_map(|(_, _, values)| values.iter().map(|(val, _, _)| val))
-        .fold(0f64, |max, x| max.max(*x));
-    trace!(max_value);
-    let collections = collections
+        .map(|(color, label, values)| {
+            let mut intervals = values
+                .windows(2)
+                .map(|window| window[1].1 - window[0].1)
+                .collect::<Vec<_>>();
+            intervals.sort_unstable();
+            let interval = intervals
+                .get(intervals.len() / 2)
+                .copied()
+                .unwrap_or(1)
+                .max(1);
+            let window = match smooth {
+                Smoothing::Points(points) => points,
+                Smoothing::Duration(duration) => (duration.as_secs() as i64 / interval) as usize,
+            };
+
+            // NOTE: Runs separated by a gap (e.g. a counter reset dropping the rate of its interval) are
+            // smoothed apart so the gap is never averaged away
+            let mut smoothed = Vec::with_capacity(values.len());
+            for run in values.chunk_by(|previous, next| next.1 - previous.1 <= 2 * interval) {
+                let averages = metrics::stats::moving_average(
+                    &run.iter().map(|(value, _, _)| *value).collect::<Vec<_>>(),
+                    window,
+                );
+                smoothed.extend(
+                    run.iter()
+                        .zip(averages)
+                        .map(|(&(_, timestamp, extremes), value)| (value, timestamp, extremes)),
+                );
+            }
+
+            (*color, label.clone(), smoothed)
+        })
+        .collect()
+}
+
+/// Series as drawn on the charts, with at most `max_points` points per line.
+fn displayed_series(
+    series: &[ChartSeries],
+    range: &TimeRange,
+    max_points: usize,
+) -> Vec<DisplayedSeries> {
+    series
+        .iter()
+        .map(|(color, label, values)| {
+            let samples = values
+                .iter()
+                .map(|(value, timestamp, _)| (*value, *timestamp))
+                .collect::<Vec<_>>();
+            let values = downsample(&samples, range.start, range.end, max_points);
+            (*color, label.clone(), values)
+        })
+        .collect()
+}
+
+/// CSV of the lines of the chart `id` over the range, as drawn unless `raw`, `None` for an unknown chart.
+///
+/// Values measured in bytes are in the unit of the chart, given in the column labels.
+#[tracing::instrument(level = "debug", skip(database))]
+pub fn chart_csv(
+    database: &Database,
+    range: &TimeRange,
+    id: &str,
+    raw: bool,
+    units: ChartUnits,
+) -> Option<String> {
+    let dates = range.dates();
+    let chart_data = database.filter_between(dates.start, dates.end);
+    let mut section = chart_sections(&chart_data, units, &custom_keys(&chart_data))
         .into_iter()
-        .filter_map(|(color, label, values)| {
-            values_to_polyline(&values, (0f64, max_value)).map(|polyline| {
-                (
-                    color.to_string(),
-                    label.map(|label| label.to_string()),
-                    polyline,
-                )
-            })
+        .find(|section| section.id == id)?;
+    section.scale_bytes(None);
+    fn to_samples<T>(values: &[ChartValue<T>]) -> Vec<(f64, i64)> {
+        values
+            .iter()
+            .map(|(value, timestamp, _)| (*value, *timestamp))
+            .collect()
+    }
+    // NOTE: The min and max of the drawn points are left out, the raw values have them all
+    let values = if raw {
+        section
+            .series
+            .iter()
+            .map(|(_, _, values)| to_samples(values))
+            .collect::<Vec<_>>()
+    } else {
+        displayed_series(&section.series, range, MAX_CHART_POINTS)
+            .iter()
+            .map(|(_, _, values)| to_samples(values))
+            .collect::<Vec<_>>()
+    };
+    let labels = section
+        .series
+        .iter()
+        .map(|(_, label, _)| match section.unit_system {
+            Some(_) => format!("{} ({})", label.as_deref().unwrap_or("Value"), section.unit),
+            None => label.as_deref().unwrap_or("Value").to_string(),
         })
         .collect::<Vec<_>>();
 
-    (max_value, collections)
+    Some(series_to_csv(
+        &labels.iter().map(String::as_str).collect::<Vec<_>>(),
+        &values,
+    ))
+}
+
+/// Title of each metric of `AGGREGATE_METRICS`, as (name, title).
+const AGGREGATE_TITLES: [(&str, &str); 10] = [
+    ("cpu", "CPU Usage"),
+    ("ram", "RAM Usage"),
+    ("swap", "Swap Usage"),
+    ("load1", "Load Average (1 minute)"),
+    ("load5", "Load Average (5 minutes)"),
+    ("load15", "Load Average (15 minutes)"),
+    ("net-rx", "Network Received"),
+    ("net-tx", "Network Sent"),
+    ("disk-read", "Disks Read"),
+    ("disk-write", "Disks Written"),
+];
+/// Color of the line of each host, faint so the combined line stands out.
+const AGGREGATE_HOST_COLOR: &str = "#ccc";
+
+/// Section of the `metric` of the fleet, of one of `AGGREGATE_METRICS`, with the bytes scaled.
+fn fleet_section(metric: &str, units: ChartUnits, series: Vec<ChartSeries>) -> ChartSection {
+    let unit_system = match metric {
+        "net-rx" | "net-tx" => Some(units.network),
+        "disk-read" | "disk-write" => Some(units.disk),
+        _ => None,
+    };
+    let unit = match metric {
+        _ if unit_system.is_some() => "B",
+        "load1" | "load5" | "load15" => "",
+        _ => "%",
+    };
+    let title = AGGREGATE_TITLES
+        .iter()
+        .find_map(|(name, title)| (*name == metric).then_some(*title))
+        .unwrap_or(metric);
+
+    let mut section = ChartSection {
+        id: metric.to_string().into(),
+        title: title.to_string().into(),
+        kind: ChartKind::Lines,
+        unit,
+        unit_system,
+        band: false,
+        noise_floor: None,
+        series,
+    };
+    section.scale_bytes(None);
+    section
+}
+
+/// Chart of a section of the fleet, its lines drawn over the whole `range` whatever their points.
+fn fleet_chart(section: ChartSection, range: &TimeRange) -> ChartContext {
+    let displayed = displayed_series(&section.series, range, MAX_CHART_POINTS);
+    let (max_value, collections, _, _) =
+        build_chart(range, &displayed, None, None, false, AxisScale::Full, None);
+    ChartContext::builder()
+        .id(section.id)
+        .title(section.title)
+        .range_label(range_to_label(Duration::from_secs(
+            range.duration().max(0) as u64
+        )))
+        .data(line_data(&section.series))
+        .time_axis(time_axis(range))
+        .unit(section.unit)
+        .unit_system(section.unit_system)
+        .max_value(max_value)
+        .collections(collections)
+        .build()
+}
+
+fn points_to_values(points: &[Point]) -> Vec<ChartValue<()>> {
+    points
+        .iter()
+        .map(|(time, value)| (*value, time.timestamp(), ()))
+        .collect()
+}
+
+/// Chart of the `metric` of the fleet, the line of each host being drawn behind the combined one when
+/// `with_hosts`.
+#[tracing::instrument(level = "debug", skip(aggregate))]
+pub(crate) fn aggregate_chart(
+    aggregate: &Aggregate,
+    metric: &str,
+    mode: Combine,
+    range: &TimeRange,
+    with_hosts: bool,
+    units: ChartUnits,
+) -> ChartContext {
+    let label = match mode {
+        Combine::Sum => "Sum",
+        Combine::Average => "Average",
+    };
+
+    // NOTE: The hosts come first so the combined line is drawn over them
+    let mut series = Vec::new();
+    if with_hosts {
+        series.extend(aggregate.hosts.iter().map(|(host, points)| {
+            (
+                AGGREGATE_HOST_COLOR,
+                Some(Cow::Owned(host.clone())),
+                points_to_values(points),
+            )
+        }));
+    }
+    series.push((
+        "#08a",
+        Some(label.into()),
+        points_to_values(&aggregate.points),
+    ));
+
+    fleet_chart(fleet_section(metric, units, series), range)
+}
+
+/// Chart of each metric of the overlay with a line per host, in the order of the metrics.
+///
+/// Each host keeps the color of its position in `overlay.hosts` on every chart, a host without points for a
+/// metric having no line on its chart.
+#[tracing::instrument(level = "debug", skip(overlay))]
+pub(crate) fn overlay_charts(
+    overlay: &Overlay,
+    range: &TimeRange,
+    units: ChartUnits,
+) -> Vec<ChartContext> {
+    overlay
+        .metrics
+        .iter()
+        .map(|(metric, per_host)| {
+            let series = per_host
+                .iter()
+                .map(|(host, points)| {
+                    let position = overlay
+                        .hosts
+                        .iter()
+                        .position(|hostname| hostname == host)
+                        .unwrap_or_default();
+                    (
+                        host_color(position),
+                        Some(Cow::Owned(host.clone())),
+                        points_to_values(points),
+                    )
+                })
+                .collect();
+            fleet_chart(fleet_section(metric, units, series), range)
+        })
+        .collect()
+}
+
+/// Sections of the built-in charts then one per custom metric key, in that order.
+fn chart_sections(
+    chart_data: &Database,
+    units: ChartUnits,
+    custom_keys: &[String],
+) -> Vec<ChartSection> {
+    let snapshots_len = chart_data.len();
+
+    let (cpu_busy, cpu_iowait, cpu_steal): (
+        Vec<ChartValue<_>>,
+        Vec<ChartValue<_>>,
+        Vec<ChartValue<_>>,
+    ) = chart_data.get_cpu_breakdown().into_iter().fold(
+        (
+            Vec::with_capacity(snapshots_len),
+            Vec::with_capacity(snapshots_len),
+            Vec::with_capacity(snapshots_len),
+        ),
+        |(mut cpu_busy, mut cpu_iowait, mut cpu_steal),
+         ((busy, iowait, steal, _idle), timestamp)| {
+            let time = timestamp.timestamp();
+            cpu_busy.push((busy, time, ()) as ChartValue<_>);
+            // NOTE: Platforms without iowait or steal end up with an empty line that is omitted
+            if let Some(iowait) = iowait {
+                cpu_iowait.push((iowait, time, ()) as ChartValue<_>);
+            }
+            if let Some(steal) = steal {
+                cpu_steal.push((steal, time, ()) as ChartValue<_>);
+            }
+
+            (cpu_busy, cpu_iowait, cpu_steal)
+        },
+    );
+
+    // NOTE: Platforms without the CPU frequency end up with an empty line, the chart is hidden
+    let cpu_frequencies: Vec<ChartValue<_>> = chart_data
+        .get_cpu_frequency()
+        .into_iter()
+        .map(|(frequency, timestamp)| (frequency, timestamp.timestamp(), ()))
+        .collect();
+
+    // NOTE: Snapshots taken before the duration was recorded end up with an empty line, the chart is hidden
+    let collection_durations: Vec<ChartValue<_>> = chart_data
+        .get_collection_durations()
+        .into_iter()
+        .map(|(duration, timestamp)| (duration, timestamp.timestamp(), ()))
+        .collect();
+
+    // NOTE: Gauges appearing partway through the range (e.g. conntrack once its module is loaded) start
+    // their line from there, the chart is hidden outside of Linux
+    let kernel_gauges = chart_data
+        .get_kernel_gauges()
+        .into_iter()
+        .filter_map(|(gauge, values)| {
+            let &(_, color, label) = KERNEL_GAUGE_LINES
+                .iter()
+                .find(|(name, _, _)| *name == gauge)?;
+            Some((
+                color,
+                Some(label.into()),
+                values
+                    .into_iter()
+                    .map(|(percent, timestamp)| (percent, timestamp.timestamp(), ()))
+                    .collect(),
+            ))
+        })
+        .collect::<Vec<ChartSeries>>();
+
+    // NOTE: Only interfaces with a link speed are drawn, the chart is hidden on hosts with virtual ones only
+    let network_utilizations = chart_data
+        .get_network_utilization_percent(true)
+        .into_iter()
+        .zip(NIC_COLORS.iter().cycle())
+        .map(|((nic, values), color)| {
+            (
+                *color,
+                nic.map(Cow::Owned),
+                values
+                    .into_iter()
+                    .map(|(percent, timestamp)| (percent, timestamp.timestamp(), ()))
+                    .collect(),
+            )
+        })
+        .collect::<Vec<ChartSeries>>();
+
+    let (ram_usages, swap_usages): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
+        chart_data.get_ram_usage().into_iter().fold(
+            (
+                Vec::with_capacity(snapshots_len),
+                Vec::with_capacity(snapshots_len),
+            ),
+            |(mut ram_usages, mut swap_usages), ((ram, swap), timestamp)| {
+                let time = timestamp.timestamp();
+                ram_usages.push((ram, time, ()) as ChartValue<_>);
+                swap_usages.push((swap, time, ()) as ChartValue<_>);
+
+                (ram_usages, swap_usages)
+            },
+        );
+
+    // NOTE: Both directions are counted in pages over the same intervals, so they have the same errors
+    let swap_errors = chart_data
+        .rate_errors(MetricKind::SwapInRate)
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    let swap_noise_floor = MetricKind::SwapInRate
+        .counter_resolution()
+        .zip(chart_data.median_interval())
+        .map(|(resolution, interval)| {
+            rates::noise_floor(
+                resolution,
+                interval.num_milliseconds() as f64 / 1000.0,
+                RATE_MAX_RELATIVE_ERROR,
+            )
+        });
+    // NOTE: Platforms without swap counters end up with empty lines, shown as no data
+    let (swapped_in_rates, swapped_out_rates): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
+        chart_data.get_swap_activity_rate().into_iter().fold(
+            (
+                Vec::with_capacity(snapshots_len),
+                Vec::with_capacity(snapshots_len),
+            ),
+            |(mut swapped_in_rates, mut swapped_out_rates),
+             ((swapped_in, swapped_out), timestamp)| {
+                let time = timestamp.timestamp();
+                // NOTE: A page or two over a short interval is a rate the counters can't tell from none
+                let error = swap_errors.get(&timestamp).copied().unwrap_or_default();
+                let measurable = |rate: f64| {
+                    if error > rate * RATE_MAX_RELATIVE_ERROR {
+                        0.0
+                    } else {
+                        rate
+                    }
+                };
+                swapped_in_rates.push((measurable(swapped_in), time, ()) as ChartValue<_>);
+                swapped_out_rates.push((measurable(swapped_out), time, ()) as ChartValue<_>);
+
+                (swapped_in_rates, swapped_out_rates)
+            },
+        );
+
+    let (load_avgs_one, load_avgs_five, load_avgs_fiveteen): (
+        Vec<ChartValue<_>>,
+        Vec<ChartValue<_>>,
+        Vec<ChartValue<_>>,
+    ) = chart_data.get_load().into_iter().fold(
+        (
+            Vec::with_capacity(snapshots_len),
+            Vec::with_capacity(snapshots_len),
+            Vec::with_capacity(snapshots_len),
+        ),
+        |(mut load_avgs_one, mut load_avgs_five, mut load_avgs_fiveteen),
+         ((load_avg_one, load_avg_five, load_avg_fiveteen), timestamp)| {
+            let time = timestamp.timestamp();
+            load_avgs_one.push((load_avg_one, time, ()) as ChartValue<_>);
+            load_avgs_five.push((load_avg_five, time, ()) as ChartValue<_>);
+            load_avgs_fiveteen.push((load_avg_fiveteen, time, ()) as ChartValue<_>);
+
+            (load_avgs_one, load_avgs_five, load_avgs_fiveteen)
+        },
+    );
+
+    let (network_recv_usage, network_sent_usage): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
+        chart_data.get_network().into_iter().fold(
+            (
+                Vec::with_capacity(snapshots_len),
+                Vec::with_capacity(snapshots_len),
+            ),
+            |(mut network_recv_usage, mut network_sent_usage), ((recv, sent), timestamp)| {
+                let time = timestamp.timestamp();
+                network_recv_usage.push((recv, time, ()) as ChartValue<_>);
+                network_sent_usage.push((sent, time, ()) as ChartValue<_>);
+
+                (network_recv_usage, network_sent_usage)
+            },
+        );
+
+    let (disk_speed_read, disk_speed_write): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
+        chart_data.get_disks_speed_usage().into_iter().fold(
+            (
+                Vec::with_capacity(snapshots_len),
+                Vec::with_capacity(snapshots_len),
+            ),
+            |(mut disk_speed_read, mut disk_speed_write), ((read, write), timestamp)| {
+                let time = timestamp.timestamp();
+                disk_speed_read.push((read, time, ()) as ChartValue<_>);
+                disk_speed_write.push((write, time, ()) as ChartValue<_>);
+                (disk_speed_read, disk_speed_write)
+            },
+        );
+
+    let (disk_memory_used, disk_memory_free): (Vec<ChartValue<_>>, Vec<ChartValue<_>>) =
+        chart_data.get_disk_memory_usage().into_iter().fold(
+            (
+                Vec::with_capacity(snapshots_len),
+                Vec::with_capacity(snapshots_len),
+            ),
+            |(mut used, mut free), ((used_bytes, free_bytes), timestamp)| {
+                let time = timestamp.timestamp();
+                used.push((used_bytes, time, ()));
+                free.push((free_bytes, time, ()));
+                (used, free)
+            },
+        );
+
+    let mut sections = vec![
+        ChartSection {
+            id: CPU_USAGE_SLUG.into(),
+            title: CPU_USAGE_TITLE.into(),
+            kind: ChartKind::StackedArea,
+            unit: "%",
+            unit_system: None,
+            band: true,
+            noise_floor: None,
+            series: vec![
+                ("#e00", Some("Busy".into()), cpu_busy),
+                ("#b70", Some("IO wait".into()), cpu_iowait),
+                ("#08a", Some("Steal".into()), cpu_steal),
+            ],
+        },
+        ChartSection {
+            id: CPU_FREQUENCY_SLUG.into(),
+            title: CPU_FREQUENCY_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "%",
+            unit_system: None,
+            band: false,
+            noise_floor: None,
+            series: vec![("#e60", Some("Of max frequency".into()), cpu_frequencies)],
+        },
+        ChartSection {
+            id: RAM_USAGE_SLUG.into(),
+            title: RAM_USAGE_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "%",
+            unit_system: None,
+            band: false,
+            noise_floor: None,
+            series: vec![
+                ("#2a2", Some("RAM".into()), ram_usages),
+                ("#e0e", Some("Swap".into()), swap_usages),
+            ],
+        },
+        ChartSection {
+            id: SWAP_ACTIVITY_SLUG.into(),
+            title: SWAP_ACTIVITY_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "pg/s",
+            unit_system: None,
+            band: false,
+            noise_floor: swap_noise_floor,
+            series: vec![
+                ("#099", Some("Swapped in".into()), swapped_in_rates),
+                ("#a50", Some("Swapped out".into()), swapped_out_rates),
+            ],
+        },
+        ChartSection {
+            id: LOAD_AVERAGE_SLUG.into(),
+            title: LOAD_AVERAGE_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "%",
+            unit_system: None,
+            band: true,
+            noise_floor: None,
+            series: vec![
+                ("#b3b", Some("1 minutes".into()), load_avgs_one),
+                ("#0a0", Some("5 minutes".into()), load_avgs_five),
+                ("#46f", Some("15 minutes".into()), load_avgs_fiveteen),
+            ],
+        },
+        ChartSection {
+            id: NETWORK_SLUG.into(),
+            title: NETWORK_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "B",
+            unit_system: Some(units.network),
+            band: false,
+            noise_floor: None,
+            series: vec![
+                ("#e66", Some("Received".into()), network_recv_usage),
+                ("#77e", Some("Sent".into()), network_sent_usage),
+            ],
+        },
+        ChartSection {
+            id: NETWORK_UTILIZATION_SLUG.into(),
+            title: NETWORK_UTILIZATION_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "%",
+            unit_system: None,
+            band: true,
+            noise_floor: None,
+            series: network_utilizations,
+        },
+        ChartSection {
+            id: DISKS_SPEED_SLUG.into(),
+            title: DISKS_SPEED_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "B",
+            unit_system: Some(units.disk),
+            band: false,
+            noise_floor: None,
+            series: vec![
+                ("#3a3", Some("Read".into()), disk_speed_read),
+                ("#c5c", Some("Write".into()), disk_speed_write),
+            ],
+        },
+        ChartSection {
+            id: DISKS_MEMORY_SLUG.into(),
+            title: DISKS_MEMORY_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "B",
+            unit_system: Some(units.disk),
+            band: false,
+            noise_floor: None,
+            series: vec![
+                ("#a4f", Some("Used".into()), disk_memory_used),
+                ("#099", Some("Free".into()), disk_memory_free),
+            ],
+        },
+        ChartSection {
+            id: COLLECTION_DURATION_SLUG.into(),
+            title: COLLECTION_DURATION_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "ms",
+            unit_system: None,
+            band: false,
+            noise_floor: None,
+            series: vec![(
+                "#888",
+                Some("Collection duration".into()),
+                collection_durations,
+            )],
+        },
+        ChartSection {
+            id: KERNEL_SLUG.into(),
+            title: KERNEL_TITLE.into(),
+            kind: ChartKind::Lines,
+            unit: "%",
+            unit_system: None,
+            band: false,
+            noise_floor: None,
+            series: kernel_gauges,
+        },
+    ];
+
+    // NOTE: Keys missing from the range end up with an empty line, shown as no data
+    sections.extend(custom_keys.iter().map(|key| {
+        let values = chart_data
+            .snapshots()
+            .iter()
+            .filter_map(|snap| {
+                snap.custom
+                    .get(key)
+                    .map(|value| (*value, snap.time.timestamp(), ()) as ChartValue<_>)
+            })
+            .collect();
+        ChartSection {
+            id: format!("{CUSTOM_CHART_PREFIX}{}", chart_slug(key)).into(),
+            title: key.clone().into(),
+            kind: ChartKind::Lines,
+            unit: "",
+            unit_system: None,
+            band: false,
+            noise_floor: None,
+            series: vec![("#08a", None, values)],
+        }
+    }));
+
+    sections
+}
+
+/// Keys of the custom metrics of every snapshot, sorted.
+fn custom_keys(database: &Database) -> Vec<String> {
+    database
+        .snapshots()
+        .iter()
+        .flat_map(|snap| snap.custom.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .cloned()
+        .collect()
+}
+
+/// Changes of the number of CPUs during the range, e.g. "CPU count changed 4 → 8",
+/// `None` without any.
+fn cpu_count_changes(chart_data: &Database) -> Option<String> {
+    let changes = chart_data
+        .annotations
+        .iter()
+        .filter(|annotation| annotation.kind == AnnotationKind::CpuCount)
+        .map(|annotation| annotation.text.as_str())
+        .collect::<Vec<_>>();
+    (!changes.is_empty()).then(|| changes.join("; "))
+}
+
+/// Annotations of the range as (x coordinate, description), those noticed together being joined.
+fn annotation_markers(chart_data: &Database, range: &TimeRange) -> Vec<(f64, String)> {
+    let mut markers: Vec<(i64, String)> = Vec::new();
+    for annotation in &chart_data.annotations {
+        let time = annotation.time.timestamp();
+        match markers.last_mut() {
+            Some((last_time, text)) if *last_time == time => {
+                text.push_str(", ");
+                text.push_str(&annotation.text);
+            }
+            _ => markers.push((time, annotation.text.clone())),
+        }
+    }
+
+    markers
+        .into_iter()
+        .map(|(time, text)| {
+            let date = Utc
+                .timestamp_opt(time, 0)
+                .single()
+                .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            (timestamp_to_x(time, range), format!("{date}: {text}"))
+        })
+        .collect()
+}
+
+/// Suspensions of the host during the range as (start x, end x, description), from the snapshot before each
+/// one to the snapshot taken when the host resumed.
+fn suspension_spans(chart_data: &Database, range: &TimeRange) -> Vec<(f64, f64, String)> {
+    chart_data
+        .snapshots()
+        .windows(2)
+        .filter(|window| window[1].suspended)
+        .map(|window| {
+            let (previous, resumed) = (&window[0], &window[1]);
+            (
+                timestamp_to_x(previous.time.timestamp(), range).max(CHART_MIN_X),
+                timestamp_to_x(resumed.time.timestamp(), range),
+                format!(
+                    "No data, host {}",
+                    suspension_text(resumed.time - previous.time)
+                ),
+            )
+        })
+        .collect()
+}
+
+fn line_data(series: &[ChartSeries]) -> Vec<LineData> {
+    series
+        .iter()
+        .filter(|(_, _, values)| !values.is_empty())
+        .map(|(_, label, values)| {
+            let values = values
+                .iter()
+                .map(|(value, timestamp, _)| (*value, *timestamp))
+                .collect::<Vec<_>>();
+            LineData {
+                label: label.as_deref().unwrap_or("Value").to_string(),
+                stats: LineStats::from_values(&values),
+                values: stride(&values, MAX_TABLE_ROWS),
+            }
+        })
+        .collect()
+}
+
+/// Coordinates of the anomalous points of each non-empty series, stacked areas having them on their top edge.
+#[tracing::instrument(level = "trace", skip(series))]
+fn anomaly_points(
+    kind: ChartKind,
+    series: &[DisplayedSeries],
+    max_value: f64,
+    range: &TimeRange,
+) -> Vec<Vec<(f64, f64)>> {
+    let series = series
+        .iter()
+        .filter(|(_, _, values)| !values.is_empty())
+        .collect::<Vec<_>>();
+    let stacked = match kind {
+        ChartKind::Lines => Vec::new(),
+        ChartKind::StackedArea => stack_values(
+            &series
+                .iter()
+                .map(|(_, _, values)| values.as_slice())
+                .collect::<Vec<_>>(),
+        ),
+    };
+    let window = chrono::Duration::from_std(ANOMALY_WINDOW).unwrap();
+
+    series
+        .iter()
+        .enumerate()
+        .map(|(idx, (_, _, values))| {
+            let samples = values
+                .iter()
+                .map(|(value, timestamp, _)| (*value, timestamp_to_date(*timestamp)))
+                .collect::<Vec<_>>();
+
+            metrics::stats::rolling_outliers(&samples, window, ANOMALY_SIGMAS)
+                .into_iter()
+                .map(|outlier| {
+                    let (value, timestamp, _) = values[outlier];
+                    let value = stacked
+                        .get(idx)
+                        .and_then(|top| top.iter().find(|(_, time)| *time == timestamp))
+                        .map_or(value, |(cumulated, _)| *cumulated);
+                    value_to_point(value, timestamp, (0f64, max_value), range)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn to_chart_lines(
+    collections: &[DisplayedSeries],
+    mut to_points: impl FnMut(usize, &DisplayedSeries) -> Option<ChartPoints>,
+) -> Vec<ChartLine> {
+    collections
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, series)| {
+            let (color, label, _) = series;
+            to_points(idx, series).map(|points| {
+                // NOTE: Drawn in gray rather than left out so the lines stay aligned with their anomalies
+                let color = color.parse::<LineColor>().unwrap_or_else(|err| {
+                    error!(
+                        ?label,
+                        "{err}, the line is drawn in {}",
+                        LineColor::FALLBACK
+                    );
+                    LineColor::FALLBACK
+                });
+                ChartLine::new(color, label.as_ref().map(|label| label.to_string()), points)
+            })
+        })
+        .collect()
+}
+
+/// Min/max bands of the collections, each drawn around `values` of the same index.
+fn to_chart_bands(
+    collections: &[DisplayedSeries],
+    values: &[Vec<ChartValue<(f64, f64)>>],
+    max_value: f64,
+    range: &TimeRange,
+) -> Vec<ChartBand> {
+    collections
+        .iter()
+        .zip(values)
+        .filter_map(|(&(color, _, _), values)| {
+            values_to_band_path(values, (0f64, max_value), range)
+                .map(|path| (band_color(color), path))
+        })
+        .collect()
+}
+
+/// Max of the values of the bands, 0 without bands.
+fn bands_max(values: &[Vec<ChartValue<(f64, f64)>>]) -> f64 {
+    values
+        .iter()
+        .flatten()
+        .map(|(_, _, (_, max))| *max)
+        .fold(0f64, f64::max)
+}
+
+/// Max of the y axis of values whose max is `max_value`, their `OUTLIER_PERCENTILE` when `scale` finds the
+/// max too far above it.
+fn axis_max(values: &[f64], max_value: f64, scale: AxisScale) -> f64 {
+    let AxisScale::Robust { factor } = scale else {
+        return max_value;
+    };
+
+    match percentile(values, OUTLIER_PERCENTILE) {
+        Some(bulk) if bulk > 0.0 && max_value > bulk * factor as f64 => bulk,
+        _ => max_value,
+    }
+}
+
+/// Series with their values and extremes above `max_value` lowered to it.
+fn clip_series(collections: &[DisplayedSeries], max_value: f64) -> Vec<DisplayedSeries> {
+    collections
+        .iter()
+        .map(|(color, label, values)| {
+            let values = values
+                .iter()
+                .map(|(value, timestamp, (min, max))| {
+                    (
+                        value.min(max_value),
+                        *timestamp,
+                        (min.min(max_value), max.min(max_value)),
+                    )
+                })
+                .collect();
+            (*color, label.clone(), values)
+        })
+        .collect()
+}
+
+/// Values of each non-empty series above `max_value` as (x, y of the top of the chart, value).
+fn clipped_points(
+    collections: &[DisplayedSeries],
+    max_value: f64,
+    range: &TimeRange,
+) -> Vec<Vec<(f64, f64, f64)>> {
+    collections
+        .iter()
+        .filter(|(_, _, values)| !values.is_empty())
+        .map(|(_, _, values)| {
+            values
+                .iter()
+                .filter(|(value, _, _)| *value > max_value)
+                .map(|(value, timestamp, _)| {
+                    let (x, y) = value_to_point(max_value, *timestamp, (0f64, max_value), range);
+                    (x, y, *value)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Lines of the range and of the compared range, both scaled to the max value of all the lines,
+/// with the min/max bands of the range when `with_bands`.
+///
+/// Each range is scaled on the whole x axis so the compared range lines up with the displayed one, `raw` being
+/// the series `collections` were smoothed from, drawn faintly behind them. When `scale` ends the axis below
+/// the max, every line is clipped to the top of the chart and the clipped values of the current lines are
+/// kept with them.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn build_chart(
+    range: &TimeRange,
+    collections: &[DisplayedSeries],
+    raw: Option<&[DisplayedSeries]>,
+    compared: Option<(&TimeRange, &[DisplayedSeries])>,
+    with_bands: bool,
+    scale: AxisScale,
+    polylines: Option<(&mut PolylineCache, &str)>,
+) -> (f64, Vec<ChartLine>, Vec<ChartLine>, Vec<ChartBand>) {
+    // NOTE: Devices appearing or disappearing leave each line with its own timestamps
+    let align = |collections: &[DisplayedSeries]| {
+        let values = collections
+            .iter()
+            .map(|(_, _, values)| values.as_slice())
+            .collect::<Vec<_>>();
+        collections
+            .iter()
+            .zip(align_values(&values))
+            .map(|((color, label, _), values)| (*color, label.clone(), values))
+            .collect::<Vec<_>>()
+    };
+    let mut collections = align(collections);
+    let mut raw = raw.map(align);
+    let mut compared = compared.map(|(compared_range, compared)| (compared_range, align(compared)));
+    let band_values = |collections: &[DisplayedSeries]| {
+        if with_bands {
+            collections
+                .iter()
+                .map(|(_, _, values)| values.clone())
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        }
+    };
+    let values = collections
+        .iter()
+        .chain(raw.iter().flatten())
+        .chain(compared.iter().flat_map(|(_, compared)| compared.iter()))
+        .flat_map(|(_, _, values)| values.iter().map(|(val, _, _)| *val))
+        .collect::<Vec<_>>();
+    // NOTE: The bands are part of the max so their spikes stay in the chart
+    let true_max = values
+        .iter()
+        .fold(bands_max(&band_values(&collections)), |max, x| max.max(*x));
+    let max_value = axis_max(&values, true_max, scale);
+    trace!(max_value, true_max);
+
+    let clipped = if max_value < true_max {
+        let clipped = clipped_points(&collections, max_value, range);
+        collections = clip_series(&collections, max_value);
+        raw = raw.map(|raw| clip_series(&raw, max_value));
+        compared = compared
+            .map(|(compared_range, compared)| (compared_range, clip_series(&compared, max_value)));
+        clipped
+    } else {
+        Vec::new()
+    };
+    let band_values = band_values(&collections);
+    let collections = collections.as_slice();
+    let compared = compared
+        .as_ref()
+        .map(|(compared_range, compared)| (*compared_range, compared.as_slice()));
+
+    let lines = |range: &TimeRange, collections: &[DisplayedSeries]| {
+        to_chart_lines(collections, |_, (_, _, values)| {
+            values_to_polyline(values, (0f64, max_value), range)
+        })
+    };
+    let compared_lines = compared
+        .map(|(compared_range, compared)| lines(compared_range, compared))
+        .unwrap_or_default();
+    let mut current_lines = match polylines {
+        Some((cache, id)) => to_chart_lines(collections, |idx, (_, _, values)| {
+            cache.polyline(id, idx, values, (0f64, max_value), range)
+        }),
+        None => lines(range, collections),
+    };
+    // NOTE: Smoothing keeps the number of points so both skip the same empty series
+    if let Some(raw) = &raw {
+        for (line, raw_line) in current_lines.iter_mut().zip(lines(range, raw)) {
+            line.raw = Some(raw_line.points);
+        }
+    }
+    // NOTE: Both skip the empty series so they stay aligned
+    for (line, clipped) in current_lines.iter_mut().zip(clipped) {
+        line.clipped = clipped;
+    }
+    let bands = to_chart_bands(collections, &band_values, max_value, range);
+
+    (max_value, current_lines, compared_lines, bands)
+}
+
+/// Values of each stacked collection moved to the top edge of its layer, their min and max moved with them.
+fn stacked_band_values(
+    collections: &[DisplayedSeries],
+    stacked: &[Vec<(f64, i64)>],
+) -> Vec<Vec<ChartValue<(f64, f64)>>> {
+    collections
+        .iter()
+        .zip(stacked)
+        .map(|((_, _, values), top)| {
+            values
+                .iter()
+                .filter_map(|(value, timestamp, (min, max))| {
+                    let (cumulated, _) = top.iter().find(|(_, time)| time == timestamp)?;
+                    let offset = cumulated - value;
+                    Some((*cumulated, *timestamp, (min + offset, max + offset)))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Same as `build_chart` with each collection stacked on top of the previous ones, the bands following
+/// the top edge of each layer.
+#[allow(clippy::type_complexity)]
+fn build_stacked_chart(
+    range: &TimeRange,
+    collections: &[DisplayedSeries],
+    compared: Option<(&TimeRange, &[DisplayedSeries])>,
+    with_bands: bool,
+) -> (f64, Vec<ChartLine>, Vec<ChartLine>, Vec<ChartBand>) {
+    // NOTE: Empty collections are left out so they don't add an empty layer
+    let stack = |collections: &[DisplayedSeries]| {
+        let collections = collections
+            .iter()
+            .filter(|(_, _, values)| !values.is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        let stacked = stack_values(
+            &collections
+                .iter()
+                .map(|(_, _, values)| values.as_slice())
+                .collect::<Vec<_>>(),
+        );
+        (collections, stacked)
+    };
+    let current = stack(collections);
+    let compared = compared.map(|(compared_range, compared)| (compared_range, stack(compared)));
+    let band_values = if with_bands {
+        stacked_band_values(&current.0, &current.1)
+    } else {
+        Vec::new()
+    };
+
+    let max_value = std::iter::once(&current.1)
+        .chain(compared.iter().map(|(_, (_, stacked))| stacked))
+        .filter_map(|stacked| stacked.last())
+        .flat_map(|top| top.iter().map(|(value, _)| *value))
+        .fold(bands_max(&band_values), f64::max);
+    trace!(max_value);
+
+    let areas =
+        |range: &TimeRange,
+         (collections, stacked): &(Vec<DisplayedSeries>, Vec<Vec<(f64, i64)>>)| {
+            to_chart_lines(collections, |idx, _| {
+                let baseline = idx.checked_sub(1).map(|idx| stacked[idx].as_slice());
+                values_to_area_points(&stacked[idx], baseline, (0f64, max_value), range)
+            })
+        };
+    let compared_areas = compared
+        .map(|(compared_range, compared)| areas(compared_range, &compared))
+        .unwrap_or_default();
+    let bands = to_chart_bands(&current.0, &band_values, max_value, range);
+
+    (max_value, areas(range, &current), compared_areas, bands)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        components::ChartSvg,
+        locale::ISO,
+        svg::{CHART_MAX_X, CHART_MAX_Y, CHART_MIN_X, CHART_MIN_Y},
+        testing,
+    };
+
+    /// Actualization task of a database file, run one cycle at a time.
+    struct Actualizer {
+        dir: TempDir,
+        state: SharedState,
+        stats: ActualizationStats,
+        slow_log: SlowLog,
+        fingerprint: Option<FileFingerprint>,
+        polylines: PolylineCache,
+        max_memory_points: usize,
+    }
+
+    impl Actualizer {
+        fn new(database: &mut Database) -> Self {
+            let actualizer = Self {
+                dir: TempDir::new().unwrap(),
+                state: SharedState::default(),
+                stats: ActualizationStats::default(),
+                slow_log: SlowLog::default(),
+                fingerprint: None,
+                polylines: PolylineCache::default(),
+                max_memory_points: DEFAULT_MAX_MEMORY_POINTS,
+            };
+            actualizer.write(database);
+            actualizer
+        }
+
+        fn path(&self) -> String {
+            self.dir
+                .path()
+                .join("sysmet.db")
+                .to_string_lossy()
+                .into_owned()
+        }
+
+        fn write(&self, database: &mut Database) {
+            database.write_to_file(&self.path()).unwrap();
+        }
+
+        fn actualize(&mut self, force: bool) -> Arc<ChartsData> {
+            actualize(
+                &self.state,
+                &self.stats,
+                &self.slow_log,
+                &self.path(),
+                None,
+                self.max_memory_points,
+                ChartUnits::default(),
+                AxisScale::default(),
+                &mut self.fingerprint,
+                &mut self.polylines,
+                force,
+            );
+            self.state.load().charts.clone()
+        }
+
+        /// (skipped, unchanged, reloads, failures) counters.
+        fn counters(&self) -> (u64, u64, u64, u64) {
+            (
+                self.stats.skipped.load(Ordering::Relaxed),
+                self.stats.unchanged.load(Ordering::Relaxed),
+                self.stats.reloads.load(Ordering::Relaxed),
+                self.stats.failures.load(Ordering::Relaxed),
+            )
+        }
+
+        fn newest(&self) -> Option<DateTime<Utc>> {
+            self.state
+                .load()
+                .database
+                .snapshots()
+                .last()
+                .map(|snapshot| snapshot.time)
+        }
+    }
+
+    #[test]
+    fn unchanged_databases_are_not_read_again() {
+        let mut actualizer = Actualizer::new(&mut testing::database());
+        let loaded = actualizer.actualize(false);
+        assert_eq!(actualizer.counters(), (0, 0, 1, 0));
+        assert!(actualizer.stats.checked_at().is_some());
+
+        let charts = actualizer.actualize(false);
+
+        assert_eq!(actualizer.counters(), (1, 0, 1, 0));
+        assert!(Arc::ptr_eq(&loaded, &charts));
+    }
+
+    #[test]
+    fn touched_databases_are_read_without_regenerating() {
+        let mut actualizer = Actualizer::new(&mut testing::database());
+        let loaded = actualizer.actualize(false);
+
+        let file = File::options().write(true).open(actualizer.path()).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(3600))
+            .unwrap();
+        let charts = actualizer.actualize(false);
+
+        assert_eq!(actualizer.counters(), (0, 1, 1, 0));
+        assert!(Arc::ptr_eq(&loaded, &charts));
+        // NOTE: The new fingerprint is kept, the next check skips the read
+        actualizer.actualize(false);
+        assert_eq!(actualizer.counters(), (1, 1, 1, 0));
+    }
+
+    #[test]
+    fn appended_snapshots_regenerate_the_charts() {
+        let mut database = testing::database();
+        let mut actualizer = Actualizer::new(&mut database);
+        let loaded = actualizer.actualize(false);
+
+        let mut snapshot = database.snapshots().last().unwrap().clone();
+        snapshot.time += chrono::Duration::minutes(1);
+        database.push_snapshot(snapshot);
+        actualizer.write(&mut database);
+        let charts = actualizer.actualize(false);
+
+        assert_eq!(actualizer.counters(), (0, 0, 2, 0));
+        assert!(!Arc::ptr_eq(&loaded, &charts));
+        assert_eq!(actualizer.state.load().database.len(), database.len());
+        assert_eq!(
+            actualizer.newest(),
+            Some(testing::end() + chrono::Duration::minutes(1))
+        );
+    }
+
+    #[test]
+    fn older_databases_are_reloaded_whole() {
+        let mut actualizer = Actualizer::new(&mut testing::database());
+        actualizer.actualize(false);
+
+        // NOTE: A backup put back in place, with fewer and older snapshots
+        let mut older = Database::demo(&metrics::demo::DemoOptions {
+            end: testing::end() - chrono::Duration::hours(2),
+            span: chrono::Duration::minutes(30),
+            interval: chrono::Duration::minutes(1),
+            seed: metrics::demo::DEFAULT_DEMO_SEED,
+        })
+        .unwrap();
+        actualizer.write(&mut older);
+        actualizer.actualize(false);
+
+        assert_eq!(actualizer.counters(), (0, 0, 2, 0));
+        assert_eq!(actualizer.state.load().database.len(), older.len());
+        assert_eq!(
+            actualizer.newest(),
+            Some(testing::end() - chrono::Duration::hours(2))
+        );
+    }
+
+    #[test]
+    fn forced_actualizations_always_regenerate() {
+        let mut actualizer = Actualizer::new(&mut testing::database());
+        let loaded = actualizer.actualize(false);
+
+        let charts = actualizer.actualize(true);
+
+        assert_eq!(actualizer.counters(), (0, 0, 2, 0));
+        assert!(!Arc::ptr_eq(&loaded, &charts));
+    }
+
+    #[test]
+    fn missing_databases_are_failures() {
+        let mut actualizer = Actualizer::new(&mut testing::database());
+        actualizer.actualize(false);
+
+        std::fs::remove_file(actualizer.path()).unwrap();
+        actualizer.actualize(false);
+
+        assert_eq!(actualizer.counters(), (0, 0, 1, 1));
+        // NOTE: The last loaded database is still served
+        assert_eq!(actualizer.newest(), Some(testing::end()));
+    }
+
+    /// Demo database of a snapshot every minute for `minutes` up to `testing::end()`.
+    fn minutes(minutes: i32) -> Database {
+        Database::demo(&metrics::demo::DemoOptions {
+            end: testing::end(),
+            span: chrono::Duration::minutes(minutes.into()) - chrono::Duration::minutes(1),
+            interval: chrono::Duration::minutes(1),
+            seed: metrics::demo::DEFAULT_DEMO_SEED,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn databases_within_the_budget_are_kept_whole() {
+        let mut database = minutes(100);
+
+        assert_eq!(apply_memory_budget(&mut database, 100, testing::end()), 0);
+        assert_eq!(database.len(), 100);
+    }
+
+    #[test]
+    fn older_snapshots_are_thinned_out_first() {
+        let mut database = minutes(1000);
+        let times = database
+            .snapshots()
+            .iter()
+            .map(|snapshot| snapshot.time)
+            .collect::<Vec<_>>();
+        let keep_since = times[900];
+
+        let trimmed = apply_memory_budget(&mut database, 300, keep_since);
+
+        assert_eq!(trimmed, 1000 - database.len());
+        assert!(database.len() <= 300, "{} snapshots kept", database.len());
+        let kept = database
+            .snapshots()
+            .iter()
+            .map(|snapshot| snapshot.time)
+            .collect::<Vec<_>>();
+        // NOTE: The default range is kept whole, along with the snapshot right before it
+        assert!(kept.ends_with(&times[899..]));
+        // NOTE: The older ones are evenly spread back to the start of the database
+        let older = &kept[..kept.len() - 101];
+        assert!(older.len() > 150, "{} older snapshots kept", older.len());
+        assert!(older[0] - times[0] < chrono::Duration::minutes(5));
+        assert!(older
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] == chrono::Duration::minutes(5)));
+    }
+
+    #[test]
+    fn the_newest_snapshots_are_kept_when_the_default_range_exceeds_the_budget() {
+        let mut database = minutes(1000);
+        let newest = database.snapshots()[950..]
+            .iter()
+            .map(|snapshot| snapshot.time)
+            .collect::<Vec<_>>();
+
+        let trimmed = apply_memory_budget(
+            &mut database,
+            50,
+            testing::end() - chrono::Duration::hours(10),
+        );
+
+        assert_eq!(trimmed, 950);
+        assert_eq!(
+            database
+                .snapshots()
+                .iter()
+                .map(|snapshot| snapshot.time)
+                .collect::<Vec<_>>(),
+            newest
+        );
+    }
+
+    #[test]
+    fn actualizations_stay_within_the_budget() {
+        let mut actualizer = Actualizer::new(&mut minutes(2000));
+        actualizer.max_memory_points = 500 * points_per_snapshot();
+
+        actualizer.actualize(false);
+
+        let retained = actualizer.state.load().database.len();
+        assert!(retained <= 500, "{retained} snapshots kept");
+        assert_eq!(
+            actualizer.stats.trimmed_snapshots.load(Ordering::Relaxed),
+            2000 - retained as u64
+        );
+        assert_eq!(actualizer.newest(), Some(testing::end()));
+    }
+
+    #[test]
+    fn estimated_sizes_grow_with_the_points() {
+        let charts = |minutes: u64| {
+            ChartsData::generate(
+                &testing::database(),
+                TimeRange::ending_at(
+                    testing::end().timestamp(),
+                    Duration::from_secs(minutes * 60),
+                ),
+                None,
+                false,
+                None,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+            )
+        };
+
+        let (quarter, hour) = (charts(15).estimated_bytes(), charts(60).estimated_bytes());
+        assert!(quarter > 0);
+        assert!(
+            hour > quarter,
+            "{hour} bytes for an hour, {quarter} for a quarter"
+        );
+    }
+
+    #[test]
+    fn charts_and_csv_share_the_network_unit() {
+        let database = testing::database();
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+        let values = |network| {
+            let units = ChartUnits {
+                network,
+                ..Default::default()
+            };
+            let charts = ChartsData::generate(
+                &database,
+                range,
+                None,
+                false,
+                None,
+                None,
+                units,
+                AxisScale::Full,
+            );
+            let (_, chart) = charts
+                .metrics
+                .iter()
+                .find(|(_, chart)| chart.id == NETWORK_SLUG)
+                .unwrap();
+            let rows = testing::parse_csv(
+                &chart_csv(&database, &range, NETWORK_SLUG, true, units).unwrap(),
+            );
+            assert_eq!(
+                rows[0][1..],
+                [
+                    format!("Received ({})", chart.unit),
+                    format!("Sent ({})", chart.unit)
+                ]
+            );
+            rows[1..]
+                .iter()
+                .map(|row| row[1].parse::<f64>().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let bytes = values(UnitSystem::SiBytes);
+        let bits = values(UnitSystem::Bits);
+        assert_eq!(bytes.len(), bits.len());
+        // NOTE: Each value is 8 times more in bits, its prefix being of a power of 1000 apart at most
+        let max = bytes.iter().copied().fold(0f64, f64::max);
+        let ratio = bits.iter().copied().fold(0f64, f64::max) / max;
+        assert!(max > 0.0);
+        let powers = (ratio / 8.0).log10() / 3.0;
+        assert!((powers - powers.round()).abs() < 1e-9, "ratio {ratio}");
+        for (bytes, bits) in bytes.iter().zip(&bits) {
+            assert!(
+                (bits - bytes * ratio).abs() <= 1e-6 * bits.abs(),
+                "{bits} {bytes}"
+            );
+        }
+    }
+
+    #[test]
+    fn custom_metrics_get_a_chart_each() {
+        let mut database = Database::default();
+        for (idx, snapshot) in testing::database().snapshots().iter().enumerate() {
+            let mut snapshot = snapshot.clone();
+            snapshot.custom.insert("GPU temp.".to_string(), idx as f64);
+            // NOTE: Collected for the last 10 minutes only, e.g. a collector added since
+            if idx >= 50 {
+                snapshot.custom.insert("queue".to_string(), 7.0);
+            }
+            database.push_snapshot(snapshot);
+        }
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+
+        let charts = ChartsData::generate(
+            &database,
+            range,
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        );
+
+        let group = CHART_GROUPS.iter().find(|group| group.custom).unwrap();
+        let custom = charts
+            .group_charts(group)
+            .iter()
+            .map(|chart| (chart.id.as_str(), chart.title.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            custom,
+            [("custom-gpu-temp", "GPU temp."), ("custom-queue", "queue")]
+        );
+        let rows = testing::parse_csv(
+            &chart_csv(
+                &database,
+                &range,
+                "custom-queue",
+                true,
+                ChartUnits::default(),
+            )
+            .unwrap(),
+        );
+        assert_eq!(rows[0], ["timestamp", "Value"]);
+        assert_eq!(rows.len() - 1, database.snapshots().len() - 50);
+        assert!(rows[1..].iter().all(|row| row[1] == "7"), "{rows:?}");
+    }
+
+    #[test]
+    fn clock_mismatches_are_detected_against_the_server_time() {
+        let database = testing::database();
+        let detect = |now| ClockMismatch::detect(&database, now);
+
+        assert!(detect(testing::end()).is_none());
+        assert!(detect(testing::end() + chrono::Duration::minutes(90)).is_none());
+
+        let ahead = detect(testing::end() - chrono::Duration::minutes(5)).unwrap();
+        assert_eq!(ahead.skew, ClockSkew::Future(chrono::Duration::minutes(5)));
+        assert_eq!(ahead.newest_snapshot, testing::end());
+        assert_eq!(
+            ahead.text(),
+            "The newest snapshot (2024-05-03 12:00:00 UTC) is 5 min ahead of this server (2024-05-03 11:55:00 UTC), the clocks or timezones of the collector and dashboard hosts likely disagree."
+        );
+
+        let behind = detect(testing::end() + chrono::Duration::hours(2)).unwrap();
+        assert_eq!(behind.skew, ClockSkew::WholeHours(2));
+        assert!(
+            behind.text().contains(
+                "(2024-05-03 12:00:00 UTC) is almost exactly 2 h behind this server (2024-05-03 14:00:00 UTC)"
+            ),
+            "{}",
+            behind.text()
+        );
+    }
+
+    #[test]
+    fn bands_are_shown_by_default_on_cpu_load_and_utilization() {
+        let database = testing::database();
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+        let charts = |bands| {
+            ChartsData::generate(
+                &database,
+                range,
+                None,
+                false,
+                bands,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+            )
+        };
+        let shown = |bands| {
+            charts(bands)
+                .metrics
+                .iter()
+                .filter(|(_, chart)| chart.show_band)
+                .map(|(_, chart)| chart.id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            shown(None),
+            [CPU_USAGE_SLUG, LOAD_AVERAGE_SLUG, NETWORK_UTILIZATION_SLUG]
+        );
+        assert!(shown(Some(false)).is_empty());
+        assert_eq!(shown(Some(true)).len(), charts(None).metrics.len());
+    }
+
+    #[test]
+    fn bands_of_clipped_spikes_stay_within_the_chart() {
+        // NOTE: A day of snapshots every minute, downsampled into buckets of about 3 snapshots
+        let mut database = Database::default();
+        for (idx, snapshot) in minutes(24 * 60).snapshots().iter().enumerate() {
+            let mut snapshot = snapshot.clone();
+            // NOTE: A spike far above the bulk of the values, clipped by the robust axis
+            snapshot.load_avgs.one = if idx == 700 { 500.0 } else { 0.5 };
+            database.push_snapshot(snapshot);
+        }
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(24 * 60 * 60),
+        );
+
+        for scale in [AxisScale::default(), AxisScale::Full] {
+            let charts = ChartsData::generate(
+                &database,
+                range,
+                None,
+                false,
+                Some(true),
+                None,
+                ChartUnits::default(),
+                scale,
+            );
+            assert!(charts
+                .metrics
+                .iter()
+                .any(|(_, chart)| !chart.bands.is_empty()));
+            for (_, chart) in &charts.metrics {
+                for (_, path) in &chart.bands {
+                    let points = ChartPoints::parse(path).unwrap();
+                    assert!(
+                        points.0.iter().all(|(x, y)| {
+                            (CHART_MIN_X..=CHART_MAX_X).contains(x)
+                                && (CHART_MIN_Y..=CHART_MAX_Y).contains(y)
+                        }),
+                        "{} {scale:?}: {path}",
+                        chart.id
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frequency_charts_are_hidden_without_frequencies() {
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+        let compute = |database: &Database| {
+            let charts = ChartsData::generate(
+                database,
+                range,
+                None,
+                false,
+                None,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+            );
+            charts
+                .group_charts(&CHART_GROUPS[0])
+                .iter()
+                .map(|chart| chart.id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let database = testing::database();
+        assert!(compute(&database).contains(&CPU_FREQUENCY_SLUG.to_string()));
+
+        let mut without = Database::default();
+        for snapshot in database.snapshots() {
+            let mut snapshot = snapshot.clone();
+            snapshot.cpu_freq = None;
+            without.push_snapshot(snapshot);
+        }
+        let charts = compute(&without);
+        assert!(!charts.contains(&CPU_FREQUENCY_SLUG.to_string()));
+        assert!(charts.contains(&CPU_USAGE_SLUG.to_string()));
+    }
+
+    #[test]
+    fn lines_of_invalid_colors_are_drawn_in_gray() {
+        let collections: Vec<DisplayedSeries> = vec![
+            ("#faa", Some("Received".into()), Vec::new()),
+            ("#faa\" onload=\"alert(1)", Some("Sent".into()), Vec::new()),
+        ];
+
+        let lines = to_chart_lines(&collections, |_, _| Some(ChartPoints::default()));
+
+        let colors = lines
+            .iter()
+            .map(|line| line.color.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(colors, ["#faa", LineColor::FALLBACK.as_str()]);
+    }
+
+    #[test]
+    fn restored_charts_are_replaced_by_the_first_actualization() {
+        let mut actualizer = Actualizer::new(&mut minutes(60));
+        let path = actualizer.dir.path().join("sysmet.db.viewcache");
+        viewcache::save(&path, &testing::charts()).unwrap();
+        actualizer.state.store(SharedData {
+            charts: Arc::new(viewcache::load(&path).unwrap()),
+            ..Default::default()
+        });
+        assert!(actualizer.state.load().charts.restored_from.is_some());
+
+        let charts = actualizer.actualize(false);
+
+        assert!(charts.restored_from.is_none());
+        assert_eq!(actualizer.newest(), Some(testing::end()));
+    }
+
+    #[test]
+    fn kernel_gauges_appearing_partway_start_their_line_there() {
+        use metrics::psutil::{KernelGauge, KernelGauges};
+
+        let gauge = |value, max| KernelGauge {
+            value,
+            max: Some(max),
+        };
+        let mut database = Database::default();
+        for (idx, snapshot) in testing::database().snapshots().iter().enumerate() {
+            let mut snapshot = snapshot.clone();
+            let mut gauges = KernelGauges::default();
+            gauges.0.insert("entropy".to_string(), gauge(256.0, 4096.0));
+            // NOTE: The conntrack module loaded for the last 10 minutes only
+            if idx >= 50 {
+                gauges.0.insert("conntrack".to_string(), gauge(90.0, 100.0));
+            }
+            snapshot.kernel = Some(gauges);
+            database.push_snapshot(snapshot);
+        }
+        let generate = |database: &Database| {
+            ChartsData::generate(
+                database,
+                TimeRange::ending_at(
+                    testing::end().timestamp(),
+                    std::time::Duration::from_secs(60 * 60),
+                ),
+                None,
+                false,
+                None,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+            )
+        };
+
+        let charts = generate(&database);
+
+        let kernel = charts
+            .group_charts(&CHART_GROUPS[0])
+            .into_iter()
+            .find(|chart| chart.id == KERNEL_SLUG)
+            .unwrap();
+        let labels = kernel
+            .data
+            .iter()
+            .map(|line| line.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["Entropy", "Conntrack entries"]);
+        assert_eq!(kernel.data[1].values.len(), 11);
+        let since = database.snapshots()[50].time.timestamp();
+        assert!(kernel.data[1]
+            .values
+            .iter()
+            .all(|(percent, timestamp)| *percent == 90.0 && *timestamp >= since));
+        assert!(!generate(&testing::database())
+            .group_charts(&CHART_GROUPS[0])
+            .iter()
+            .any(|chart| chart.id == KERNEL_SLUG));
+    }
+
+    #[test]
+    fn generation_steps_are_kept_in_the_slow_log_past_its_threshold() {
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+        let generate = |slow_log: &SlowLog| {
+            ChartsData::generate_with_polylines(
+                &testing::database(),
+                range,
+                None,
+                false,
+                None,
+                false,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+                None,
+                Some(slow_log),
+            )
+        };
+
+        let slow_log = SlowLog::new(std::time::Duration::ZERO);
+        generate(&slow_log);
+
+        let operations = slow_log.operations();
+        let names = operations
+            .iter()
+            .map(|operation| operation.name.as_ref())
+            .collect::<Vec<_>>();
+        for name in ["series extraction", "chart cpu", "chart network"] {
+            assert!(names.contains(&name), "{name} missing from {names:?}");
+        }
+        assert!(operations
+            .iter()
+            .all(|operation| operation.range == Some(range)));
+
+        let slow_log = SlowLog::new(std::time::Duration::from_secs(60 * 60));
+        generate(&slow_log);
+        assert!(slow_log.operations().is_empty());
+    }
+
+    #[test]
+    fn smoothed_lines_are_drawn_over_their_raw_points() {
+        let generate = |smooth| {
+            ChartsData::generate(
+                &testing::database(),
+                TimeRange::ending_at(
+                    testing::end().timestamp(),
+                    std::time::Duration::from_secs(60 * 60),
+                ),
+                None,
+                false,
+                None,
+                smooth,
+                ChartUnits::default(),
+                AxisScale::default(),
+            )
+        };
+        let chart = |charts: &ChartsData, id: &str| {
+            let (_, chart) = charts
+                .metrics
+                .iter()
+                .find(|(_, chart)| chart.id == id)
+                .unwrap();
+            chart
+                .collections
+                .iter()
+                .map(|line| {
+                    (
+                        line.points.to_polyline(),
+                        line.raw.as_ref().map(ChartPoints::to_polyline),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (raw, smoothed) = (generate(None), generate(Some(Smoothing::Points(5))));
+
+        let (raw_load, smoothed_load) = (
+            chart(&raw, LOAD_AVERAGE_SLUG),
+            chart(&smoothed, LOAD_AVERAGE_SLUG),
+        );
+        assert!(!raw_load.is_empty());
+        for ((raw_points, none), (smoothed_points, behind)) in raw_load.iter().zip(&smoothed_load) {
+            assert!(none.is_none());
+            assert_eq!(behind.as_ref(), Some(raw_points));
+            assert_ne!(smoothed_points, raw_points);
+        }
+        // NOTE: Stacked areas are never smoothed
+        assert_eq!(
+            chart(&smoothed, CPU_USAGE_SLUG),
+            chart(&raw, CPU_USAGE_SLUG)
+        );
+    }
+
+    #[test]
+    fn smoothing_never_averages_across_gaps() {
+        let series: Vec<DisplayedSeries> = vec![(
+            "#000",
+            None,
+            [(1.0, 0), (2.0, 60), (3.0, 120), (10.0, 600), (20.0, 660)]
+                .into_iter()
+                .map(|(value, timestamp)| (value, timestamp, (value, value)))
+                .collect(),
+        )];
+        let values = |smooth| {
+            smooth_series(&series, smooth)[0]
+                .2
+                .iter()
+                .map(|(value, timestamp, _)| (*value, *timestamp))
+                .collect::<Vec<_>>()
+        };
+
+        let expected = [(1.5, 0), (2.0, 60), (2.5, 120), (15.0, 600), (15.0, 660)];
+        assert_eq!(values(Smoothing::Points(3)), expected);
+        // NOTE: 3 minutes are 3 points of the 1 minute interval
+        assert_eq!(
+            values(Smoothing::Duration(std::time::Duration::from_secs(180))),
+            expected
+        );
+    }
+
+    #[test]
+    fn utilization_charts_need_link_speeds() {
+        let ids = |database: &Database| {
+            ChartsData::generate(
+                database,
+                TimeRange::ending_at(
+                    testing::end().timestamp(),
+                    std::time::Duration::from_secs(60 * 60),
+                ),
+                None,
+                false,
+                None,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+            )
+            .group_charts(
+                CHART_GROUPS
+                    .iter()
+                    .find(|group| group.id == NETWORK_SLUG)
+                    .unwrap(),
+            )
+            .iter()
+            .map(|chart| chart.id.clone())
+            .collect::<Vec<_>>()
+        };
+
+        let database = testing::database();
+        let shown = ids(&database);
+        assert!(shown.contains(&NETWORK_UTILIZATION_SLUG.to_string()));
+
+        // NOTE: Virtual interfaces only, their rates are still charted
+        let mut virtual_only = Database::default();
+        for snapshot in database.snapshots() {
+            let mut snapshot = snapshot.clone();
+            for link in snapshot.nic_info.values_mut() {
+                link.speed_mbps = None;
+            }
+            virtual_only.push_snapshot(snapshot);
+        }
+        let shown = ids(&virtual_only);
+        assert!(!shown.contains(&NETWORK_UTILIZATION_SLUG.to_string()));
+        assert!(shown.contains(&NETWORK_SLUG.to_string()));
+    }
+
+    #[test]
+    fn the_first_actualization_opens_the_readiness_gate_for_good() {
+        let mut actualizer = Actualizer::new(&mut minutes(60));
+        actualizer.state.store(SharedData {
+            ready: false,
+            ..Default::default()
+        });
+
+        actualizer.actualize(false);
+        assert!(actualizer.state.load().ready);
+
+        // NOTE: A later failed read keeps serving the loaded charts
+        std::fs::write(actualizer.path(), b"not a database").unwrap();
+        actualizer.actualize(true);
+        assert!(actualizer.state.load().ready);
+    }
+
+    #[test]
+    fn compact_lines_shrink_a_day_of_charts_by_at_least_40_percent() {
+        let database = minutes(24 * 60);
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(24 * 60 * 60),
+        );
+
+        let (mut rounded, mut compact) = (0, 0);
+        for section in chart_sections(&database, ChartUnits::default(), &[]) {
+            for (_, _, values) in displayed_series(&section.series, &range, MAX_CHART_POINTS) {
+                let max = values
+                    .iter()
+                    .map(|(value, _, _)| *value)
+                    .fold(1.0, f64::max);
+                // NOTE: Every point rounded to whole units, as drawn before the lines were compacted
+                let points = values
+                    .iter()
+                    .map(|(value, date, _)| value_to_point(*value, *date, (0.0, max), &range))
+                    .collect();
+                rounded += ChartPoints(points).to_polyline().len();
+                compact += values_to_polyline(&values, (0.0, max), &range)
+                    .map_or(0, |points| points.to_polyline().len());
+            }
+        }
+
+        assert!(rounded > 0);
+        assert!(compact * 10 <= rounded * 6, "{compact} of {rounded} bytes");
+    }
+
+    #[test]
+    fn charts_note_a_last_snapshot_taken_in_a_container() {
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+        // NOTE: The snapshots of the fixture, only the one at `from_end` from the newest taken in a container
+        let container = |from_end: Option<usize>| {
+            let snapshots = testing::database().snapshots().to_vec();
+            let mut database = Database::default();
+            for (idx, mut snapshot) in snapshots.iter().cloned().enumerate() {
+                snapshot.container = Some(snapshots.len() - 1 - idx) == from_end;
+                database.push_snapshot(snapshot);
+            }
+            ChartsData::generate(
+                &database,
+                range,
+                None,
+                false,
+                None,
+                None,
+                ChartUnits::default(),
+                AxisScale::default(),
+            )
+            .container
+        };
+
+        assert!(!container(None));
+        assert!(container(Some(0)));
+        // NOTE: Only the last snapshot tells where the collector runs now
+        assert!(!container(Some(1)));
+    }
+
+    #[test]
+    fn built_in_lines_use_the_checked_palette() {
+        let default = &crate::palette::PALETTES[0];
+        let mut colors = chart_sections(&testing::database(), ChartUnits::default(), &[])
+            .into_iter()
+            .flat_map(|section| section.series.into_iter().map(|(color, _, _)| color))
+            .chain(KERNEL_GAUGE_LINES.map(|(_, color, _)| color))
+            .chain(NIC_COLORS)
+            .collect::<Vec<_>>();
+        colors.sort_unstable();
+        colors.dedup();
+
+        assert!(colors.len() > 10);
+        for color in colors {
+            assert!(
+                default.light.contains(&color) && default.dark.contains(&color),
+                "{color} is not in the {} palette",
+                default.name
+            );
+        }
+    }
+
+    /// Series of a value every minute wavering between 50 and 54, with `outlier` at the given position.
+    fn outlier_series(outlier: Option<(usize, f64)>) -> (TimeRange, Vec<DisplayedSeries>) {
+        let end = testing::end().timestamp();
+        let values = (0..1000)
+            .map(|idx| {
+                let value = match outlier {
+                    Some((position, value)) if position == idx => value,
+                    _ => 50.0 + (idx % 5) as f64,
+                };
+                (value, end - (999 - idx as i64) * 60, (value, value))
+            })
+            .collect();
+        let range = TimeRange::ending_at(end, std::time::Duration::from_secs(1000 * 60));
+        (range, vec![("#e00", Some("Busy".into()), values)])
+    }
+
+    #[test]
+    fn single_outliers_are_clipped_to_the_bulk_of_the_values() {
+        let (range, series) = outlier_series(Some((500, 4000.0)));
+
+        let (max_value, lines, _, _) = build_chart(
+            &range,
+            &series,
+            None,
+            None,
+            false,
+            AxisScale::default(),
+            None,
+        );
+
+        assert!((50.0..=54.0).contains(&max_value), "{max_value}");
+        assert_eq!(lines[0].clipped.len(), 1);
+        let (x, y, value) = lines[0].clipped[0];
+        assert_eq!(value, 4000.0);
+        assert!((y - CHART_MIN_Y).abs() < 1e-9, "{y}");
+        assert!((CHART_MIN_X..=CHART_MAX_X).contains(&x));
+        assert!(lines[0]
+            .points
+            .0
+            .iter()
+            .all(|(_, y)| (CHART_MIN_Y..=CHART_MAX_Y).contains(y)));
+    }
+
+    #[test]
+    fn full_scales_keep_the_outliers_in_the_chart() {
+        let (range, series) = outlier_series(Some((500, 4000.0)));
+
+        let (max_value, lines, _, _) =
+            build_chart(&range, &series, None, None, false, AxisScale::Full, None);
+
+        assert_eq!(max_value, 4000.0);
+        assert!(lines[0].clipped.is_empty());
+    }
+
+    #[test]
+    fn maxes_close_to_the_bulk_are_kept() {
+        // NOTE: Twice the bulk, under the default factor of 3
+        for outlier in [None, Some((500, 100.0))] {
+            let (range, series) = outlier_series(outlier);
+
+            let (max_value, lines, _, _) = build_chart(
+                &range,
+                &series,
+                None,
+                None,
+                false,
+                AxisScale::default(),
+                None,
+            );
+
+            let expected = outlier.map_or(54.0, |(_, value)| value);
+            assert_eq!(max_value, expected);
+            assert!(lines[0].clipped.is_empty());
+        }
+        assert_eq!(axis_max(&[0.0, 0.0, 5.0], 5.0, AxisScale::default()), 5.0);
+    }
+
+    // NOTE: Swap activity is only counted in pages on Linux
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unmeasurable_swap_rates_are_drawn_at_zero_under_the_noise_floor() {
+        // NOTE: A page a minute for the first half hour, then 10 pages/s
+        let mut database = Database::default();
+        let mut swapped_in = 0;
+        for (idx, snapshot) in minutes(60).snapshots().iter().enumerate() {
+            swapped_in += if idx < 30 { 1 } else { 600 };
+            let mut snapshot = snapshot.clone();
+            let mut swap = serde_json::to_value(&snapshot.swap).unwrap();
+            swap["swapped_in"] = swapped_in.into();
+            snapshot.swap = serde_json::from_value(swap).unwrap();
+            database.push_snapshot(snapshot);
+        }
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(60 * 60),
+        );
+
+        let charts = ChartsData::generate(
+            &database,
+            range,
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        );
+
+        let (_, chart) = charts
+            .metrics
+            .iter()
+            .find(|(_, chart)| chart.id == SWAP_ACTIVITY_SLUG)
+            .unwrap();
+        // NOTE: A page over a minute, with half of it as error
+        let noise_floor = chart.noise_floor.unwrap();
+        assert!((noise_floor - 2.0 / 60.0).abs() < 1e-9, "{noise_floor}");
+        let swapped_in = &chart.data[0].values;
+        let (quiet, busy): (Vec<_>, Vec<_>) = swapped_in.iter().partition(|(_, time)| {
+            *time <= (testing::end() - chrono::Duration::minutes(30)).timestamp()
+        });
+        assert!(!quiet.is_empty() && !busy.is_empty());
+        assert!(quiet.iter().all(|(value, _)| *value == 0.0), "{quiet:?}");
+        assert!(
+            busy.iter().all(|(value, _)| (value - 10.0).abs() < 1e-9),
+            "{busy:?}"
+        );
+        for (_, chart) in &charts.metrics {
+            assert_eq!(
+                chart.noise_floor.is_some(),
+                chart.id == SWAP_ACTIVITY_SLUG,
+                "{}",
+                chart.id
+            );
+        }
+    }
+
+    #[test]
+    fn cpu_count_changes_are_noted_under_the_cpu_and_load_charts() {
+        // NOTE: A CPU unplugged halfway
+        let mut database = Database::default();
+        for (idx, snapshot) in minutes(30).snapshots().iter().enumerate() {
+            let mut snapshot = snapshot.clone();
+            if idx >= 15 {
+                snapshot.cpus.pop();
+            }
+            database.push_snapshot(snapshot);
+        }
+        let cpus = database.snapshots()[0].get_cpu_count();
+        let range = TimeRange::ending_at(
+            testing::end().timestamp(),
+            std::time::Duration::from_secs(30 * 60),
+        );
+
+        let charts = ChartsData::generate(
+            &database,
+            range,
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        );
+
+        let note = format!("CPU count changed {cpus} \u{2192} {}", cpus - 1);
+        for (_, chart) in &charts.metrics {
+            let noted = matches!(chart.id.as_str(), CPU_USAGE_SLUG | LOAD_AVERAGE_SLUG);
+            assert_eq!(
+                chart.subtitle.as_deref(),
+                noted.then_some(note.as_str()),
+                "{}",
+                chart.id
+            );
+        }
+        let (_, cpu) = charts
+            .metrics
+            .iter()
+            .find(|(_, chart)| chart.id == CPU_USAGE_SLUG)
+            .unwrap();
+        assert!(cpu.data[0].values.iter().all(|(busy, _)| *busy <= 100.0));
+        assert!(testing::charts()
+            .metrics
+            .iter()
+            .all(|(_, chart)| chart.subtitle.is_none()));
+    }
+
+    #[test]
+    fn overlays_draw_a_line_per_host_over_the_same_time_axis() {
+        let beta = Database::demo(&metrics::demo::DemoOptions {
+            end: testing::end(),
+            span: chrono::Duration::hours(1),
+            interval: chrono::Duration::minutes(5),
+            seed: metrics::demo::DEFAULT_DEMO_SEED,
+        })
+        .unwrap();
+        let databases = [("alpha", minutes(61)), ("beta", beta)];
+        let range = TimeRange::ending_at(testing::end().timestamp(), Duration::from_secs(60 * 60));
+        let series = |metric: MetricKind, hosts: &[&str]| {
+            hosts
+                .iter()
+                .map(|host| {
+                    let database = &databases.iter().find(|(name, _)| name == host).unwrap().1;
+                    let points = database
+                        .series(metric.clone(), range.dates(), None)
+                        .collect();
+                    (host.to_string(), points)
+                })
+                .collect::<Vec<_>>()
+        };
+        // NOTE: Without a load series for `alpha`, only `beta` is drawn on its chart in its color of the other
+        let overlay = Overlay {
+            hosts: vec!["alpha".to_string(), "beta".to_string()],
+            metrics: vec![
+                (
+                    "cpu".to_string(),
+                    series(MetricKind::CpuPercent, &["alpha", "beta"]),
+                ),
+                ("load1".to_string(), series(MetricKind::Load1, &["beta"])),
+            ],
+            missing: Vec::new(),
+        };
+
+        let charts = overlay_charts(&overlay, &range, ChartUnits::default());
+
+        let [cpu, load] = &charts[..] else {
+            panic!("{} charts", charts.len())
+        };
+        assert_eq!((cpu.id.as_str(), load.id.as_str()), ("cpu", "load1"));
+        let lines = |chart: &ChartContext| {
+            chart
+                .collections
+                .iter()
+                .map(|line| (line.label.clone().unwrap(), line.color.as_str().to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(cpu),
+            [
+                ("alpha".to_string(), host_color(0).to_string()),
+                ("beta".to_string(), host_color(1).to_string())
+            ]
+        );
+        assert_eq!(
+            lines(load),
+            [("beta".to_string(), host_color(1).to_string())]
+        );
+        let labels = cpu
+            .data
+            .iter()
+            .map(|data| data.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["alpha", "beta"]);
+
+        // NOTE: Both hosts share the x axis of the range whatever their interval, the first rate of each being at
+        // its second snapshot
+        let x = |minutes: f64| CHART_MIN_X + (CHART_MAX_X - CHART_MIN_X) * minutes / 60.0;
+        let [alpha, beta] = &cpu.collections[..] else {
+            unreachable!()
+        };
+        assert!(alpha.points.0.len() > 4 * beta.points.0.len());
+        for (line, first, last) in [(alpha, 1.0, 59.0), (beta, 5.0, 55.0)] {
+            let (first_x, last_x) = (line.points.0[0].0, line.points.0.last().unwrap().0);
+            assert!((first_x - x(first)).abs() < 0.1, "{first_x}");
+            assert!((last_x - x(last)).abs() < 0.1, "{last_x}");
+        }
+    }
+
+    #[test]
+    fn data_ending_halfway_leaves_the_rest_of_the_range_empty() {
+        // NOTE: 30 minutes of data in the first half of an hour
+        let end = testing::end().timestamp();
+        let range = TimeRange {
+            start: end - 30 * 60,
+            end: end + 30 * 60,
+        };
+        let middle = CHART_MIN_X + (CHART_MAX_X - CHART_MIN_X) / 2.0;
+
+        let charts = ChartsData::generate(
+            &minutes(31),
+            range,
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        );
+
+        assert_eq!(charts.stale_since(), Some(testing::end()));
+        let mut drawn = 0;
+        for (_, chart) in charts.metrics.iter().filter(|(_, chart)| chart.has_data()) {
+            let last_x = chart
+                .collections
+                .iter()
+                .flat_map(|line| line.points.0.iter().map(|(x, _)| *x))
+                .fold(f64::MIN, f64::max);
+            assert!((last_x - middle).abs() <= 1.0, "{}: {last_x}", chart.id);
+
+            let no_data_from = chart.no_data_from.unwrap();
+            assert!((no_data_from - middle).abs() <= 1.0, "{}", chart.id);
+            let markup = ChartSvg(chart, &ISO).into_string();
+            assert!(
+                markup.contains(&format!(
+                    "<rect class=\"no-data\" x=\"{no_data_from}\" y=\"{CHART_MIN_Y}\" width=\"{}\"",
+                    CHART_MAX_X - no_data_from
+                )),
+                "{}",
+                chart.id
+            );
+            drawn += 1;
+        }
+        assert!(drawn > 0);
+    }
+
+    #[test]
+    fn data_up_to_the_end_of_the_range_is_not_stale() {
+        let end = testing::end().timestamp();
+        let range = TimeRange {
+            start: end - 60 * 60,
+            // NOTE: Within the tolerance of a few percent of the range
+            end: end + 60,
+        };
+
+        let charts = ChartsData::generate(
+            &minutes(60),
+            range,
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        );
+
+        assert_eq!(charts.stale_since(), None);
+        assert!(charts
+            .metrics
+            .iter()
+            .all(|(_, chart)| chart.no_data_from.is_none()));
+        let markup = ChartSvg(&charts.metrics[0].1, &ISO).into_string();
+        assert!(!markup.contains("class=\"no-data\""));
+    }
+
+    #[test]
+    fn suspensions_are_shaded_with_their_duration() {
+        // NOTE: The second half of the snapshots taken after a 9 hour sleep, the CPU times not advancing
+        let mut database = Database::default();
+        for (idx, snapshot) in minutes(30).snapshots().iter().enumerate() {
+            let mut snapshot = snapshot.clone();
+            if idx >= 15 {
+                snapshot.time += chrono::Duration::hours(9);
+            }
+            database.push_snapshot(snapshot);
+        }
+        let end = database.snapshots().last().unwrap().time;
+        let range = TimeRange::ending_at(
+            end.timestamp(),
+            std::time::Duration::from_secs(10 * 60 * 60),
+        );
+
+        let charts = ChartsData::generate(
+            &database,
+            range,
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        );
+
+        let snapshots = database.snapshots();
+        assert!(snapshots[15].suspended);
+        assert_eq!(snapshots.iter().filter(|snap| snap.suspended).count(), 1);
+        let (_, chart) = &charts.metrics[0];
+        let [(start, end, text)] = chart.suspensions.as_slice() else {
+            panic!("{:?}", chart.suspensions);
+        };
+        assert_eq!(text, "No data, host suspended ~9h");
+        assert_eq!(
+            *start,
+            timestamp_to_x(snapshots[14].time.timestamp(), &range)
+        );
+        assert_eq!(*end, timestamp_to_x(snapshots[15].time.timestamp(), &range));
+
+        let markup = ChartSvg(chart, &ISO).into_string();
+        assert!(markup.contains(&format!(
+            "<rect class=\"no-data suspended\" x=\"{start}\" y=\"{CHART_MIN_Y}\" width=\"{}\"",
+            end - start
+        )));
+        assert!(markup.contains("<title>No data, host suspended ~9h</title>"));
+    }
+
+    #[test]
+    fn chart_slugs_are_unique_and_stable() {
+        let slugs = CHART_SLUGS.map(|(_, slug)| slug);
+        // NOTE: Shared in links, a renamed slug breaks the ones already sent
+        assert_eq!(
+            slugs,
+            [
+                "cpu",
+                "cpu-frequency",
+                "ram",
+                "swap-activity",
+                "load",
+                "network",
+                "disks-speed",
+                "disks-memory",
+                "collection-duration",
+                "kernel",
+                "network-utilization",
+            ]
+        );
+        for values in [slugs, CHART_SLUGS.map(|(title, _)| title)] {
+            let mut unique = values.to_vec();
+            unique.sort_unstable();
+            unique.dedup();
+            assert_eq!(unique.len(), values.len(), "{values:?}");
+        }
+        for (title, slug) in CHART_SLUGS {
+            assert_eq!(chart_slug(title), slug);
+        }
+        assert_eq!(chart_slug("GPU temp."), "gpu-temp");
+        assert_eq!(chart_slug("  Queue / depth "), "queue-depth");
+
+        let ids = |charts: &ChartsData| {
+            charts
+                .metrics
+                .iter()
+                .map(|(_, context)| context.id.clone())
+                .collect::<Vec<_>>()
+        };
+        let charts = testing::charts();
+        for (_, context) in &charts.metrics {
+            assert_eq!(chart_slug(&context.title), context.id);
+        }
+        assert_eq!(ids(&testing::charts()), ids(&charts));
+    }
+
+    #[tokio::test]
+    async fn slow_cycles_delay_the_next_ones_instead_of_bursting() {
+        let stats = ActualizationStats::default();
+        let actualize_now = Notify::new();
+        let (stop, db_rx) = tokio::sync::oneshot::channel();
+        let mut stop = Some(stop);
+        let period = Duration::from_millis(50);
+        let slow = Duration::from_millis(150);
+        let mut cycles = 0;
+        // NOTE: Lag of each cycle, read at the start of the next one
+        let mut lags = Vec::new();
+
+        run_cycles(period, &stats, &actualize_now, db_rx, |forced| {
+            assert!(!forced);
+            cycles += 1;
+            if cycles > 1 {
+                lags.push(stats.lag_ms.load(Ordering::Relaxed));
+            }
+            std::thread::sleep(slow);
+            if cycles == 4 {
+                stop.take().unwrap().send(()).unwrap();
+            }
+        })
+        .await;
+
+        assert!(cycles >= 4, "{cycles}");
+        lags.push(stats.lag_ms.load(Ordering::Relaxed));
+        // NOTE: The first tick is right away, the next ones are late by the overrun of the previous cycle
+        //       instead of piling up
+        assert!(lags[0] < 50, "{lags:?}");
+        for lag in &lags[1..] {
+            assert!((50..150).contains(lag), "{lags:?}");
+        }
+        assert_eq!(stats.cycle_overruns.load(Ordering::Relaxed), cycles);
+        assert!(stats.overran());
+        assert!(stats.last_cycle_ms.load(Ordering::Relaxed) >= 150);
+        let max_cycle_ms = stats.max_cycle_ms.load(Ordering::Relaxed);
+        assert!(max_cycle_ms >= 150);
+
+        stats.record_cycle(Duration::ZERO, Duration::from_millis(10), period);
+        assert!(!stats.overran());
+        assert_eq!(stats.lag_ms.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.cycle_overruns.load(Ordering::Relaxed), cycles);
+        assert_eq!(stats.max_cycle_ms.load(Ordering::Relaxed), max_cycle_ms);
+    }
+
+    #[test]
+    fn offset_comparisons_never_overflow() {
+        let range = TimeRange::ending_at(1_700_000_000, DEFAULT_TIME_RANGE);
+        for offset in [Duration::MAX, crate::MAX_RANGE] {
+            let comparison = CompareWith::Offset(offset).comparison(&range);
+            assert_eq!(comparison.range.duration(), range.duration());
+            assert!(comparison.range.end <= range.start);
+        }
+    }
 }
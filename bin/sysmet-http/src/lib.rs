@@ -1,50 +1,239 @@
 use axum::{
-    extract::{Extension, Query},
-    routing::get,
-    Router, Server,
+    extract::{Extension, Query, RawQuery, Request},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    Router,
 };
+use color_eyre::eyre::WrapErr;
 pub use color_eyre::Result;
+use env::version::BuildInfo;
 use include_dir::{include_dir, Dir};
-use log::{debug, info, trace, tracing};
-use maud::{html, Markup};
+use log::{debug, info, trace, tracing, warn};
+use metrics::prelude::DatabaseKey;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
-use tokio::sync::RwLock;
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
+pub mod admin;
+pub(crate) mod api;
+pub(crate) mod axis;
+pub(crate) mod coalesce;
 mod components;
+pub(crate) mod contrast;
+pub(crate) mod csv;
 pub use components::*;
+pub mod fleet;
 pub(crate) mod generator;
+pub mod listen;
+pub mod locale;
 pub(crate) mod macros;
+mod pages;
+pub use pages::*;
+pub(crate) mod palette;
+pub(crate) mod polyline;
+pub mod proxy;
+pub mod query;
+pub mod reload;
+pub(crate) mod routes;
+pub mod signing;
+pub mod slowlog;
 pub(crate) mod svg;
+pub(crate) mod swap;
+#[cfg(test)]
+pub(crate) mod testing;
+pub(crate) mod text;
+#[cfg(unix)]
+pub mod uds;
+pub mod units;
+pub mod usagelog;
+pub(crate) mod viewcache;
 
-use generator::ChartsData;
+use admin::{AdminAccess, DatabaseAdmin, SharedAdmin};
+use fleet::{Fleet, HostSelection, SharedFleet};
+pub use generator::{chart_slug, ChartsData, CHART_SLUGS, DEFAULT_MAX_MEMORY_POINTS};
+use generator::{
+    CompareWith, SharedCoalescer, SharedData, SharedState, SharedStats, CHART_GROUPS,
+    CUSTOM_CHART_PREFIX, DEFAULT_TIME_RANGE,
+};
+use locale::LocaleSpec;
+pub use palette::{palettes_report, validate as validate_palettes, ContrastIssue, LineColor};
+use query::{
+    explicit_range, locale_param, range_params, theme_param, units_params, DashboardState, Notices,
+    Severity,
+};
+use reload::{Reloader, RuntimeConfig, SharedConfig};
+use sha2::{Digest, Sha256};
+use signing::CookieSecret;
+use slowlog::{timed, SharedSlowLog, SlowLog};
+pub use svg::TimeRange;
+use units::{AxisScale, ChartUnits};
+use usagelog::{SharedUsageLog, UsageLog};
 
 pub(crate) const SOURCE_URL: &str = "https://github.com/joxcat/sysmet";
 pub(crate) const WEBSITE_TITLE: &str = "Ferrous System Metrics";
+pub const BUILD_INFO: BuildInfo = BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+/// Seconds clients are told to wait before retrying while `--strict-readiness` holds the requests.
+const WARMING_UP_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// Range of the sparklines of the status board.
+const BOARD_RANGE: Duration = Duration::from_secs(60 * 60);
+/// Cookie keeping the view saved with the "Save as default view" button, signed by the cookie secret.
+const SAVED_VIEW_COOKIE: &str = "sysmet_view";
+/// How long browsers keep the saved view.
+const SAVED_VIEW_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
 
 pub(crate) const CSS_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/css/exports");
 pub(crate) static CSS_HASHES: Lazy<HashMap<String, (PathBuf, String)>> =
     generate_hashes!(CSS_HASHES, CSS_DIR);
 static_files_server!(css_assets, CSS_DIR, CSS_HASHES, "text/css");
 
-#[tracing::instrument]
-pub async fn run_server(addr: SocketAddr, database: &str) -> Result<()> {
-    let chart_data = RwLock::new(ChartsData::default());
-    let shared_chart_data = Arc::new(chart_data);
+/// Where the server listens, every listener serves the same pages.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    pub tcp: Vec<SocketAddr>,
+    #[cfg(unix)]
+    pub unix_socket: Option<uds::UnixSocket>,
+}
+
+/// State of the server the handlers extract, each part being added to the requests as an extension.
+#[derive(Clone)]
+pub(crate) struct SharedExtensions {
+    pub config: SharedConfig,
+    pub state: SharedState,
+    pub stats: SharedStats,
+    pub coalescer: SharedCoalescer,
+    pub slow_log: SharedSlowLog,
+    pub cookie_secret: Arc<CookieSecret>,
+    pub fleet: SharedFleet,
+    pub usage_log: SharedUsageLog,
+    pub admin: SharedAdmin,
+}
+
+/// Router of every page and endpoint with the extensions and middlewares their handlers rely on.
+pub(crate) fn app(extensions: SharedExtensions) -> Router {
+    routes::router()
+        .fallback(not_found)
+        .layer(middleware::map_response(method_not_allowed))
+        .layer(middleware::from_fn(proxy::forwarded))
+        .layer(Extension(extensions.config))
+        .layer(Extension(extensions.state))
+        .layer(Extension(extensions.stats))
+        .layer(Extension(extensions.coalescer))
+        .layer(Extension(extensions.slow_log))
+        .layer(Extension(extensions.cookie_secret))
+        .layer(Extension(extensions.fleet))
+        .layer(Extension(extensions.usage_log))
+        .layer(Extension(extensions.admin))
+}
+
+#[tracing::instrument(skip(reloader))]
+#[allow(clippy::too_many_arguments)]
+pub async fn run_server(
+    listen: Listeners,
+    database: &str,
+    key: Option<DatabaseKey>,
+    config: RuntimeConfig,
+    view_cache: bool,
+    strict_readiness: bool,
+    cookie_secret: CookieSecret,
+    reloader: Option<Reloader>,
+    fleet: Option<Fleet>,
+    usage_log: Option<UsageLog>,
+    admin: Option<AdminAccess>,
+) -> Result<()> {
+    // NOTE: Everything is bound before starting so a single unavailable address stops the server
+    let listeners = listen
+        .tcp
+        .iter()
+        .map(|addr| TcpListener::bind(addr).wrap_err_with(|| format!("Failed to listen on {addr}")))
+        .collect::<Result<Vec<_>>>()?;
+    #[cfg(unix)]
+    let unix_listener = listen
+        .unix_socket
+        .as_ref()
+        .map(|socket| uds::bind(socket).map(|listener| (listener, socket.path.clone())))
+        .transpose()?;
+
+    // NOTE: Split once at startup rather than on the first page
+    if CRITICAL_CSS.len() > CRITICAL_CSS_BUDGET {
+        warn!(
+            "The critical stylesheets are {} bytes, over the budget of {CRITICAL_CSS_BUDGET} bytes inlined in \
+             every page",
+            CRITICAL_CSS.len()
+        );
+    }
+
+    let view_cache = view_cache.then(|| viewcache::path_for(database));
+    let shared_state = SharedState::default();
+    shared_state.store(SharedData {
+        ready: !strict_readiness,
+        ..Default::default()
+    });
+    // NOTE: Served until the first generation, which takes a while for large databases
+    if let Some(charts) = view_cache
+        .as_deref()
+        .and_then(viewcache::load)
+        .filter(|charts| charts.units == config.units && charts.scale == config.scale)
+    {
+        info!(written_at = ?charts.restored_from, "Restored the charts of the view cache");
+        shared_state.store(SharedData {
+            charts: Arc::new(charts),
+            ready: !strict_readiness,
+            ..Default::default()
+        });
+    }
+    let stats = SharedStats::default();
+    let coalescer = SharedCoalescer::default();
+    let slow_log = SharedSlowLog::new(SlowLog::new(config.slow_op_threshold));
+    let shared_config = SharedConfig::default();
+    shared_config.store(config);
+    let actualize_now = Arc::new(tokio::sync::Notify::new());
+    let admin = SharedAdmin::new(DatabaseAdmin {
+        access: admin,
+        database: database.to_string(),
+        key: key.clone(),
+        actualize_now: actualize_now.clone(),
+        uploading: Default::default(),
+        downloads: Default::default(),
+    });
 
     let (db_tx, db_rx) = tokio::sync::oneshot::channel::<()>();
-    let (server_tx, server_rx) = tokio::sync::oneshot::channel::<()>();
+    let (server_tx, server_rx) = tokio::sync::watch::channel(false);
     let handle = {
-        let shared_chart_data = shared_chart_data.clone();
+        let shared_state = shared_state.clone();
+        let stats = stats.clone();
+        let slow_log = slow_log.clone();
+        let shared_config = shared_config.clone();
         let database = database.to_string();
+        let actualize_now = actualize_now.clone();
 
         tokio::spawn(generator::actualization_task(
-            shared_chart_data,
+            shared_state,
+            stats,
+            slow_log,
+            shared_config,
             database,
+            key,
+            view_cache,
+            actualize_now,
             db_rx,
         ))
     };
+    if let Some(reloader) = reloader {
+        tokio::spawn(reload::reload_on_hangup(
+            reloader,
+            shared_config.clone(),
+            slow_log.clone(),
+            actualize_now,
+        ));
+    }
 
     {
         tokio::spawn(async move {
@@ -63,89 +252,1596 @@ pub async fn run_server(addr: SocketAddr, database: &str) -> Result<()> {
                 .expect("Error while waiting for the end of the actualization task");
             trace!("Actualization task ended");
             server_tx
-                .send(())
-                .expect("Failed to send stop signal to the server task");
+                .send(true)
+                .expect("Failed to send stop signal to the server tasks");
 
             debug!("Finished Ctrl-C gracefull shutdown");
         });
     }
 
-    let app = Router::new()
-        .route("/", get(home))
-        .route("/css/:path", get(css_assets))
-        .layer(Extension(shared_chart_data));
+    let app = app(SharedExtensions {
+        config: shared_config,
+        state: shared_state,
+        stats,
+        coalescer,
+        slow_log,
+        cookie_secret: Arc::new(cookie_secret),
+        fleet: SharedFleet::new(fleet),
+        usage_log: SharedUsageLog::new(usage_log),
+        admin,
+    });
+
+    let mut servers = tokio::task::JoinSet::new();
+    #[cfg(unix)]
+    if let Some((listener, path)) = unix_listener {
+        servers.spawn(uds::serve(listener, path, app.clone(), server_rx.clone()));
+    }
+    for listener in listeners {
+        servers.spawn(serve_tcp(listener, app.clone(), server_rx.clone())?);
+    }
+
+    // NOTE: Dropping the set on the first failure aborts the other servers
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+/// Server of `app` on a bound TCP listener, stopping gracefully once `server_rx` changes.
+pub(crate) fn serve_tcp(
+    listener: TcpListener,
+    app: Router,
+    mut server_rx: tokio::sync::watch::Receiver<bool>,
+) -> Result<impl Future<Output = Result<()>>> {
+    let addr = listener.local_addr()?;
+    listener
+        .set_nonblocking(true)
+        .wrap_err_with(|| format!("Failed to listen on {addr}"))?;
+    let listener = tokio::net::TcpListener::from_std(listener)
+        .wrap_err_with(|| format!("Failed to listen on {addr}"))?;
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    );
 
     info!("Listening on {}", addr);
-    Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(async {
-            server_rx.await.ok();
-            trace!("Received server stop signal");
-        })
-        .await?;
+    Ok(async move {
+        server
+            .with_graceful_shutdown(async move {
+                server_rx.changed().await.ok();
+                trace!("Received server stop signal on {addr}");
+            })
+            .await
+            .wrap_err_with(|| format!("Server on {addr} failed"))
+    })
+}
+
+/// Write the dashboard of the `range` ending now to `path` as a self-contained page, e.g. from a cron job
+/// archiving reports.
+#[tracing::instrument(skip(key, opts))]
+pub fn render_to_file(
+    database: &str,
+    key: Option<&DatabaseKey>,
+    range: Duration,
+    units: ChartUnits,
+    scale: AxisScale,
+    opts: &RenderOptions,
+    path: &Path,
+) -> Result<()> {
+    let charts = generator::generate_once(database, key, range, units, scale)
+        .wrap_err_with(|| format!("Failed to read the database {database}"))?;
+    let opts = RenderOptions {
+        static_page: true,
+        ..opts.clone()
+    };
+    std::fs::write(path, render_dashboard(&charts, &opts))
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    info!(
+        "Wrote the dashboard of the last {} to {}",
+        range_to_label(range),
+        path.display()
+    );
 
     Ok(())
 }
 
+/// Page of the paths without a route, the assets route answering its own empty 404 for unknown files.
+async fn not_found() -> (StatusCode, Html<String>) {
+    (
+        StatusCode::NOT_FOUND,
+        Html(
+            ErrorPage(
+                "Page not found",
+                "There is no page at this address, it may have moved or been mistyped.",
+                "",
+            )
+            .into_string(),
+        ),
+    )
+}
+
+/// Answer with a warming up page until the database was loaded once, see `--strict-readiness`.
+async fn readiness_gate(
+    Extension(shared_state): Extension<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if shared_state.load().ready {
+        return next.run(request).await;
+    }
+
+    debug!("Not ready yet, refused {}", request.uri().path());
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(
+            header::RETRY_AFTER,
+            WARMING_UP_RETRY_AFTER.as_secs().to_string(),
+        )],
+        Html(
+            ErrorPage(
+                "Warming up",
+                "The metrics are still being loaded, this page will be available in a few seconds.",
+                "",
+            )
+            .into_string(),
+        ),
+    )
+        .into_response()
+}
+
+/// Empty 405 of axum for a known path with another method replaced by a page, keeping its `Allow` header.
+async fn method_not_allowed(response: Response) -> Response {
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    let (parts, _) = response.into_parts();
+    (
+        parts,
+        Html(
+            ErrorPage(
+                "Method not allowed",
+                "This page exists but can't be requested this way.",
+                "",
+            )
+            .into_string(),
+        ),
+    )
+        .into_response()
+}
+
+/// Known group identifiers of a comma separated list joined back, unknown ones are dropped.
+pub fn known_groups(open: &str) -> String {
+    open.split(',')
+        .map(str::trim)
+        .filter(|id| CHART_GROUPS.iter().any(|group| group.id == *id))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The `User-Agent` header looks like the one of a phone browser, which all have `Mobi` in it.
+pub fn looks_mobile(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .is_some_and(|user_agent| user_agent.contains("Mobi"))
+}
+
 #[derive(Debug, Deserialize)]
 struct HomeQuery {
     t: Option<String>,
+    /// Start of an explicit range, RFC3339 date or unix timestamp, `from` and `to` take precedence over `t`.
+    from: Option<String>,
+    to: Option<String>,
     refresh: Option<String>,
+    /// Offset of the compared range, e.g. `7d`.
+    compare: Option<String>,
+    compare_from: Option<String>,
+    compare_to: Option<String>,
+    /// Highlight the anomalous points when `on`.
+    anomalies: Option<String>,
+    /// `on` or `off` to show or hide the min/max bands of every chart, each chart has its default without it.
+    bands: Option<String>,
+    /// Comma separated identifiers of the open chart groups, e.g. `compute,network`.
+    open: Option<String>,
+    /// `bits` or `bytes`, overrides `--network-units`.
+    network_units: Option<String>,
+    /// `si` or `iec`, overrides `--disk-units`.
+    disk_units: Option<String>,
+    /// Tag of the locale of the numbers and dates, e.g. `de`, overrides `--locale`.
+    locale: Option<String>,
+    /// `light` or `dark`.
+    theme: Option<String>,
+    /// Slug of the chart to scroll to and highlight, e.g. `load`.
+    focus: Option<String>,
+    /// Moving average window of the lines, in points (e.g. `15`) or as a duration (e.g. `10m`).
+    smooth: Option<String>,
+    /// `on` to save the view in the cookie shown by the bare dashboard.
+    save_view: Option<String>,
+    /// `on` to forget the saved view.
+    reset_view: Option<String>,
+    /// `txt` for the plain text dashboard, also served when the `Accept` header prefers `text/plain`.
+    format: Option<String>,
+    /// `on` to scale the y axis of the charts to the max of their values, even far above the others.
+    fullscale: Option<String>,
+    /// `on` for the lite view, e.g. on a phone: fewer points per line, no bands nor anomalies and only the
+    /// first chart groups open.
+    lite: Option<String>,
 }
 
-#[tracing::instrument]
-async fn home(
-    time_from_now: Query<HomeQuery>,
-    Extension(chart_data): Extension<Arc<RwLock<ChartsData>>>,
-) -> Markup {
-    let time_from_now = time_from_now.0;
-    let _time = time_from_now // TODO: Filter results directly on the svg
-        .t
-        .clone()
-        .and_then(|ref t| humantime::parse_duration(t).ok());
-    let refresh = time_from_now.refresh.is_some() && time_from_now.refresh.clone().unwrap() == "on";
-
-    let chart_sections = {
-        let data = chart_data.read().await;
-        data.metrics.clone()
-    };
+/// Parameters of the dashboard: its state and what to do with it.
+#[derive(Debug)]
+struct HomeParams {
+    state: DashboardState,
+    save_view: bool,
+    /// `format=txt`, the `Accept` header deciding without it.
+    text: bool,
+}
 
-    Base(
-        BaseContext::builder().refresh_every_minute(refresh).build(),
-        html! {
-            section {
-                h1 { "sysmet faster" }
-                form {
-                    div {
-                        label {
-                            span { "Time range:" }
-                            input name="t" value=(time_from_now.t.unwrap_or_else(|| "3h0m0s".to_string()));
-                            span { "ago to now." }
-                        }
-                        label {
-                            @if refresh {
-                                input type="checkbox" name="refresh" checked;
-                            } @else {
-                                input type="checkbox" name="refresh";
-                            }
-                            span { "Auto-refresh every minute" }
-                        }
-                    }
-                    input type="submit" { "Change" }
-                }
+impl HomeQuery {
+    /// Parameters of the query with a notice for each that can't be used, `units` and `locale` being those of
+    /// the flags.
+    fn parse(&self, units: ChartUnits, locale: LocaleSpec, notices: &mut Notices) -> HomeParams {
+        let range = range_params(
+            notices,
+            self.t.as_deref(),
+            self.from.as_deref(),
+            self.to.as_deref(),
+            DEFAULT_TIME_RANGE,
+        );
+        let refresh = notices
+            .switch(
+                "refresh",
+                self.refresh.as_deref(),
+                "the page is not reloaded",
+            )
+            .unwrap_or(false);
+        let lite = notices
+            .switch("lite", self.lite.as_deref(), "the full view is shown")
+            .unwrap_or(false);
+        let fullscale = notices
+            .switch(
+                "fullscale",
+                self.fullscale.as_deref(),
+                "the axes end at the bulk of the values",
+            )
+            .unwrap_or(false);
+
+        let anomalies = notices
+            .switch(
+                "anomalies",
+                self.anomalies.as_deref(),
+                "the anomalies are not highlighted",
+            )
+            .unwrap_or(false);
+        let bands = notices.switch(
+            "bands",
+            self.bands.as_deref(),
+            "each chart has its default bands",
+        );
+        for (param, given) in [("anomalies", anomalies), ("bands", bands == Some(true))] {
+            if lite && given {
+                notices.push(
+                    Severity::Info,
+                    param,
+                    "on",
+                    "not drawn in the lite view",
+                    "the lines are drawn alone",
+                );
             }
-            section {
-                @for (title, context) in chart_sections {
-                    section {
-                        h2 { (title) }
-                        (Chart(context))
-                    }
+        }
+
+        let explicit_compare = explicit_range(
+            notices,
+            ("compare_from", self.compare_from.as_deref()),
+            ("compare_to", self.compare_to.as_deref()),
+            "the offset of `compare` is used",
+        );
+        let compare = match explicit_compare {
+            Some(compared) => {
+                if let Some(compare) = self
+                    .compare
+                    .as_deref()
+                    .filter(|compare| !compare.trim().is_empty())
+                {
+                    notices.push(
+                        Severity::Info,
+                        "compare",
+                        compare,
+                        "`compare_from` and `compare_to` take precedence",
+                        "the range of their dates is compared",
+                    );
                 }
+                Some(CompareWith::Range(compared))
             }
-            section {
-                a href=(SOURCE_URL) referer="none" target="_blank" { "Source code" }
-                span { " - Licensed under the AGPL v3.0." }
+            None => notices
+                .parse(
+                    "compare",
+                    self.compare.as_deref(),
+                    parse_range,
+                    &format!("not a duration of at most {MAX_RANGE_DAYS} days, e.g. `7d`"),
+                    "nothing is compared",
+                )
+                .map(CompareWith::Offset),
+        };
+
+        let open = match self.open.as_deref() {
+            Some(open) => {
+                let known = known_groups(open);
+                let unknown = open
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty() && !known.split(',').any(|known| known == *id))
+                    .collect::<Vec<_>>();
+                if !unknown.is_empty() {
+                    notices.push(
+                        Severity::Info,
+                        "open",
+                        open,
+                        format!("no chart group {}", unknown.join(", ")),
+                        "the known groups are open",
+                    );
+                }
+                Some(known)
             }
-        },
+            None => None,
+        };
+        let focus = self
+            .focus
+            .as_deref()
+            .filter(|focus| !focus.trim().is_empty())
+            .and_then(|focus| {
+                let known = focus.starts_with(CUSTOM_CHART_PREFIX)
+                    || CHART_SLUGS.iter().any(|(_, slug)| *slug == focus);
+                if !known {
+                    notices.push(
+                        Severity::Info,
+                        "focus",
+                        focus,
+                        "no such chart",
+                        "no chart is highlighted",
+                    );
+                }
+                known.then(|| focus.to_string())
+            });
+
+        let units = units_params(
+            notices,
+            self.network_units.as_deref(),
+            self.disk_units.as_deref(),
+            units,
+        );
+        let locale = locale_param(notices, self.locale.as_deref(), locale);
+        let theme = theme_param(notices, self.theme.as_deref());
+        let smooth = notices.parse(
+            "smooth",
+            self.smooth.as_deref(),
+            Smoothing::from_query,
+            "expected a number of points above 1 or a duration, e.g. `15` or `10m`",
+            "the lines are not smoothed",
+        );
+        let save_view = notices
+            .switch(
+                "save_view",
+                self.save_view.as_deref(),
+                "the view is not saved",
+            )
+            .unwrap_or(false);
+        // NOTE: `reset_view=on` is answered before the query is parsed, only its other values are left
+        notices.switch(
+            "reset_view",
+            self.reset_view.as_deref(),
+            "the saved view is kept",
+        );
+        let text = notices
+            .parse(
+                "format",
+                self.format.as_deref(),
+                |format| match format {
+                    "txt" => Some(true),
+                    "html" => Some(false),
+                    _ => None,
+                },
+                "expected `html` or `txt`",
+                "the format of the `Accept` header is served",
+            )
+            .unwrap_or(false);
+
+        HomeParams {
+            state: DashboardState {
+                range,
+                refresh,
+                compare,
+                anomalies,
+                bands,
+                open,
+                units,
+                locale,
+                theme,
+                focus,
+                smooth,
+                fullscale,
+                lite,
+            },
+            save_view,
+            text,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BoardQuery {
+    /// Number of columns of the grid, 4 by default.
+    cols: Option<String>,
+    /// `off` to stop reloading the board every 30 seconds.
+    refresh: Option<String>,
+    /// `light` or `dark`.
+    theme: Option<String>,
+    /// Tag of the locale of the numbers and dates, e.g. `de`, overrides `--locale`.
+    locale: Option<String>,
+}
+
+#[tracing::instrument]
+async fn board(
+    Query(query): Query<BoardQuery>,
+    Extension(shared_state): Extension<SharedState>,
+    Extension(coalescer): Extension<SharedCoalescer>,
+    Extension(slow_log): Extension<SharedSlowLog>,
+    Extension(shared_config): Extension<SharedConfig>,
+    Extension(fleet): Extension<SharedFleet>,
+) -> (StatusCode, Html<String>) {
+    let shared_data = shared_state.load();
+    let range = shared_data.range_for(BOARD_RANGE);
+    // NOTE: With a fleet the board has a row per host instead of the tiles of the database of the dashboard
+    let hosts = match api::load_host_charts(
+        fleet,
+        range,
+        shared_config.load().units,
+        shared_config.load().scale,
+    )
+    .await
+    {
+        Ok(hosts) => hosts,
+        Err((status, message)) => {
+            return (
+                status,
+                Html(
+                    ErrorPage(status.canonical_reason().unwrap_or("Error"), &message, "")
+                        .into_string(),
+                ),
+            )
+        }
+    };
+    let charts = generator::coalesced_charts_for(
+        shared_data,
+        &coalescer,
+        slow_log.clone(),
+        range,
+        None,
+        false,
+        None,
+        false,
+        None,
+        shared_config.load().units,
+        shared_config.load().scale,
+    )
+    .await;
+
+    let mut notices = Notices::default();
+    let opts = BoardOptions::builder()
+        .theme(theme_param(&mut notices, query.theme.as_deref()))
+        .locale(locale_param(
+            &mut notices,
+            query.locale.as_deref(),
+            shared_config.load().locale,
+        ))
+        .refresh(
+            notices
+                .switch("refresh", query.refresh.as_deref(), "the board is reloaded")
+                .unwrap_or(true),
+        )
+        .columns(
+            notices
+                .parse(
+                    "cols",
+                    query.cols.as_deref(),
+                    |cols| {
+                        cols.trim()
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|cols| (1..=MAX_BOARD_COLUMNS).contains(cols))
+                    },
+                    &format!("expected a number of columns from 1 to {MAX_BOARD_COLUMNS}"),
+                    &format!("the board has {DEFAULT_BOARD_COLUMNS} columns"),
+                )
+                .unwrap_or(DEFAULT_BOARD_COLUMNS),
+        )
+        .notices(notices)
+        .build();
+    (
+        StatusCode::OK,
+        Html(timed(Some(&slow_log), "render board", Some(range), || {
+            BoardPage(&charts, &hosts, &opts).into_string()
+        })),
+    )
+}
+
+#[tracing::instrument(skip(fleet))]
+async fn aggregate(
+    Query(query): Query<api::AggregateQuery>,
+    Extension(fleet): Extension<SharedFleet>,
+    Extension(slow_log): Extension<SharedSlowLog>,
+    Extension(shared_config): Extension<SharedConfig>,
+) -> (StatusCode, Html<String>) {
+    let error_page = |status: StatusCode, message: String| {
+        (
+            status,
+            Html(
+                ErrorPage(status.canonical_reason().unwrap_or("Error"), &message, "").into_string(),
+            ),
+        )
+    };
+    let mut notices = Notices::default();
+    let params = query.parse(&mut notices);
+    let selected = match &params.selection {
+        HostSelection::All => None,
+        HostSelection::Only(hosts) => Some(hosts.join(",")),
+    };
+    let units = shared_config.load().units;
+    let range = params.range;
+    let options = AggregateOptions::builder()
+        .theme(params.theme)
+        .locale(locale_param(
+            &mut notices,
+            query.locale.as_deref(),
+            shared_config.load().locale,
+        ))
+        .range(Duration::from_secs(range.duration().max(0) as u64))
+        .selected(selected)
+        .notices(notices);
+
+    let (charts, opts) = if params.overlay {
+        let overlay = match api::load_overlay(fleet, &params).await {
+            Ok(overlay) => overlay,
+            Err((status, message)) => return error_page(status, message),
+        };
+        let charts = generator::overlay_charts(&overlay, &range, units);
+        // NOTE: Hosts with data for some of the metrics only are left out of the charts of the others
+        let excluded = overlay
+            .missing
+            .iter()
+            .chain(overlay.hosts.iter().filter(|host| {
+                overlay
+                    .metrics
+                    .iter()
+                    .all(|(_, per_host)| per_host.iter().all(|(hostname, _)| hostname != *host))
+            }))
+            .cloned()
+            .collect::<Vec<_>>();
+        // NOTE: Every metric is shown without `metric`, the form selecting "all" for it
+        let metric = match query.metric.as_deref().map(str::trim) {
+            None | Some("") => String::new(),
+            Some(_) => params
+                .metrics
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        let opts = options
+            .metric(metric)
+            .hosts(params.with_hosts)
+            .overlay(true)
+            .excluded(excluded)
+            .build();
+        (charts, opts)
+    } else {
+        let loaded = match api::load_aggregate(fleet, &params).await {
+            Ok(loaded) => loaded,
+            Err((status, message)) => return error_page(status, message),
+        };
+        let chart = generator::aggregate_chart(
+            &loaded.aggregate,
+            &loaded.metric,
+            loaded.mode,
+            &range,
+            params.with_hosts,
+            units,
+        );
+        let opts = options
+            .metric(loaded.metric)
+            .hosts(params.with_hosts)
+            .overlay(false)
+            .excluded(loaded.aggregate.excluded)
+            .build();
+        (vec![chart], opts)
+    };
+
+    (
+        StatusCode::OK,
+        Html(timed(
+            Some(&slow_log),
+            "render aggregate",
+            Some(range),
+            || AggregatePage(&charts, &opts).into_string(),
+        )),
+    )
+}
+
+/// Parameters of the view saved in the cookie, `None` when there is none or it was tampered with.
+fn read_saved_view(headers: &HeaderMap, cookie_secret: &CookieSecret) -> Option<HomeQuery> {
+    let signed = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find_map(|(name, value)| (name == SAVED_VIEW_COOKIE).then_some(value))?;
+    let query = cookie_secret.verify(signed)?;
+    let uri = format!("/?{query}").parse::<Uri>().ok()?;
+    Query::<HomeQuery>::try_from_uri(&uri)
+        .ok()
+        .map(|view| view.0)
+}
+
+/// Redirection to `location` setting the saved view cookie.
+fn saved_view_redirect(location: String, cookie: String) -> Response {
+    (
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, location), (header::SET_COOKIE, cookie)],
     )
+        .into_response()
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(headers, cookie_secret))]
+async fn home(
+    time_from_now: Query<HomeQuery>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    Extension(shared_state): Extension<SharedState>,
+    Extension(coalescer): Extension<SharedCoalescer>,
+    Extension(slow_log): Extension<SharedSlowLog>,
+    Extension(shared_config): Extension<SharedConfig>,
+    Extension(cookie_secret): Extension<Arc<CookieSecret>>,
+) -> Response {
+    if time_from_now.reset_view.as_deref() == Some("on") {
+        return saved_view_redirect(
+            "./".to_string(),
+            format!("{SAVED_VIEW_COOKIE}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax"),
+        );
+    }
+    // NOTE: Only the bare dashboard shows the saved view, links with parameters show the view they describe
+    let saved_view = query
+        .as_deref()
+        .is_none_or(str::is_empty)
+        .then(|| read_saved_view(&headers, &cookie_secret))
+        .flatten();
+    let is_saved_view = saved_view.is_some();
+    let time_from_now = saved_view.unwrap_or(time_from_now.0);
+    let shared_data = shared_state.load();
+    let hostname = shared_data.database.hostname().map(str::to_string);
+    let mut notices = Notices::default();
+    let HomeParams {
+        state,
+        save_view,
+        text,
+    } = time_from_now.parse(
+        shared_config.load().units,
+        shared_config.load().locale,
+        &mut notices,
+    );
+    let range = state
+        .range
+        .explicit
+        .unwrap_or_else(|| shared_data.range_for(state.range.duration));
+    let scale = if state.fullscale {
+        AxisScale::Full
+    } else {
+        shared_config.load().scale
+    };
+
+    if save_view {
+        let view_query = state.view_query();
+        return saved_view_redirect(
+            format!("./?{view_query}"),
+            format!(
+                "{SAVED_VIEW_COOKIE}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+                cookie_secret.sign(&view_query),
+                SAVED_VIEW_MAX_AGE.as_secs()
+            ),
+        );
+    }
+
+    let charts = generator::coalesced_charts_for(
+        shared_data,
+        &coalescer,
+        slow_log.clone(),
+        range,
+        state.compare,
+        state.shows_anomalies(),
+        state.shown_bands(),
+        state.lite,
+        state.smooth,
+        state.units,
+        scale,
+    )
+    .await;
+
+    if text || text::prefers_text(&headers) {
+        return (
+            [
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+                (header::VARY, "Accept"),
+            ],
+            timed(Some(&slow_log), "render text", Some(range), || {
+                text::render_text(&charts, hostname.as_deref(), &notices, &state.locale)
+            }),
+        )
+            .into_response();
+    }
+    let locale = state.locale;
+    let opts = RenderOptions::builder()
+        .suggest_lite(!state.lite && looks_mobile(&headers))
+        .state(state)
+        .saved_view(is_saved_view)
+        .notices(notices)
+        .alerting(shared_config.load().notify_state.as_deref().map(|path| {
+            AlertingContext::builder()
+                .state(NotifyStateFile::load(path))
+                .locale(locale)
+                .build()
+        }))
+        .build();
+    let page = timed(Some(&slow_log), "render dashboard", Some(range), || {
+        render_dashboard(&charts, &opts)
+    });
+    let tag = page_etag(&page);
+    let response_headers = [
+        (header::VARY, "Accept, User-Agent".to_string()),
+        (header::ETAG, format!("W/{tag}")),
+    ];
+    if admin::matches_etag(&headers, &tag) {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+    (response_headers, Html(page)).into_response()
+}
+
+/// Tag of a rendered page, the same state of the dashboard over the same charts rendering the same page.
+fn page_etag(page: &str) -> String {
+    let hash = Sha256::digest(page.as_bytes())
+        .iter()
+        .take(16)
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("\"{hash}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+
+    use super::*;
+    use crate::testing::Server;
+
+    const SAVED_NOTICE: &str = "Using your saved view";
+
+    /// Cookie of the `Set-Cookie` header, e.g. `sysmet_view=...`.
+    fn cookie(headers: &HeaderMap) -> String {
+        let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn saved_views_are_applied_to_the_bare_dashboard() {
+        let server = Server::new();
+        let (status, headers, _) = server.get("/?t=6h&theme=dark&save_view=on", &[]).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        // NOTE: Normalized, with the units and locale the view was saved with
+        assert_eq!(
+            headers[header::LOCATION],
+            "./?t=6h&network_units=bytes&disk_units=iec&locale=iso&theme=dark"
+        );
+        let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly"), "{set_cookie}");
+        let cookie = cookie(&headers);
+
+        let (status, _, body) = server.get("/", &[("cookie", &cookie)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(SAVED_NOTICE));
+        assert!(body.contains("data-theme=\"dark\""));
+        // NOTE: Among other cookies
+        let (_, _, body) = server
+            .get("/", &[("cookie", &format!("lang=fr; {cookie}; other=1"))])
+            .await;
+        assert!(body.contains(SAVED_NOTICE));
+
+        // NOTE: Links with parameters show the view they describe
+        let (_, _, body) = server.get("/?t=1h", &[("cookie", &cookie)]).await;
+        assert!(!body.contains(SAVED_NOTICE));
+        assert!(body.contains("data-theme=\"light\""));
+    }
+
+    #[tokio::test]
+    async fn saved_views_are_reset() {
+        let server = Server::new();
+        let (status, headers, _) = server.get("/?reset_view=on", &[]).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(headers[header::LOCATION], "./");
+        let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.starts_with(&format!("{SAVED_VIEW_COOKIE}=;")));
+        assert!(set_cookie.contains("Max-Age=0"));
+        assert_eq!(cookie(&headers), format!("{SAVED_VIEW_COOKIE}="));
+
+        let (_, _, body) = server.get("/", &[("cookie", &cookie(&headers))]).await;
+        assert!(!body.contains(SAVED_NOTICE));
+    }
+
+    #[tokio::test]
+    async fn tampered_cookies_are_ignored() {
+        let server = Server::new();
+        let (_, headers, _) = server.get("/?theme=dark&save_view=on", &[]).await;
+        let cookie = cookie(&headers);
+        let (_, signed) = cookie.split_once('=').unwrap();
+        let (_, mac) = signed.split_once('.').unwrap();
+        let forged = CookieSecret::new("another secret").sign("theme=dark&t=30d");
+
+        for tampered in [
+            format!("{SAVED_VIEW_COOKIE}=dGhlbWU9ZGFyayZ0PTMwZA.{mac}"),
+            format!("{SAVED_VIEW_COOKIE}={forged}"),
+            format!("{SAVED_VIEW_COOKIE}={}", &signed[1..]),
+            format!("{SAVED_VIEW_COOKIE}=garbage"),
+        ] {
+            let (status, _, body) = server.get("/", &[("cookie", &tampered)]).await;
+            assert_eq!(status, StatusCode::OK, "{tampered}");
+            assert!(!body.contains(SAVED_NOTICE), "{tampered}");
+            assert!(body.contains("data-theme=\"light\""), "{tampered}");
+        }
+
+        // NOTE: Signed with the secret of the server but not a query of the dashboard
+        let unparseable = server.extensions.cookie_secret.sign("t=%%&&==");
+        let (status, _, _) = server
+            .get(
+                "/",
+                &[("cookie", &format!("{SAVED_VIEW_COOKIE}={unparseable}"))],
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unknown_groups_are_dropped_from_the_open_ones() {
+        let server = Server::new();
+
+        let (status, _, body) = server.get("/?open=network,gpu", &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("no chart group gpu"), "{body}");
+        assert!(body.contains("<details class=\"chart-group\" id=\"group-network\" open>"));
+        assert!(body.contains("<details class=\"chart-group\" id=\"group-compute\">"));
+        assert!(body.contains("<input type=\"hidden\" name=\"open\" value=\"network\">"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn dashboards_are_served_while_the_charts_are_swapped() {
+        let server = Server::new();
+        let data = server.extensions.state.load();
+        let (database, charts) = (data.database.clone(), data.charts.clone());
+
+        let state = server.extensions.state.clone();
+        let writer = tokio::spawn(async move {
+            for _ in 0..200 {
+                state.store(SharedData {
+                    database: database.clone(),
+                    charts: charts.clone(),
+                    ready: true,
+                    ..Default::default()
+                });
+                tokio::task::yield_now().await;
+            }
+        });
+        let server = Arc::new(server);
+        let requests = (0..50)
+            .map(|_| {
+                let server = server.clone();
+                tokio::spawn(async move { server.get("/", &[]).await })
+            })
+            .collect::<Vec<_>>();
+
+        for request in requests {
+            let (status, _, body) = request.await.unwrap();
+            assert_eq!(status, StatusCode::OK);
+            assert!(body.trim_end().ends_with("</html>"));
+        }
+        writer.await.unwrap();
+        assert!(Arc::ptr_eq(
+            &server.extensions.state.load().charts,
+            &data.charts
+        ));
+    }
+
+    /// Status and body of `/health` fetched over TCP from `addr`.
+    async fn fetch_health(addr: SocketAddr) -> (StatusCode, serde_json::Value) {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+        let response = sender
+            .send_request(
+                Request::get("/health")
+                    .header("host", addr.to_string())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn every_listener_serves_the_same_router() {
+        let server = Server::new();
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        let mut servers = tokio::task::JoinSet::new();
+        let mut addrs = Vec::new();
+        for addr in ["127.0.0.1:0", "[::1]:0"] {
+            let listener = TcpListener::bind(addr).unwrap();
+            addrs.push(listener.local_addr().unwrap());
+            servers.spawn(serve_tcp(listener, server.app.clone(), stop_rx.clone()).unwrap());
+        }
+
+        for addr in &addrs {
+            let (status, health) = fetch_health(*addr).await;
+            assert_eq!(status, StatusCode::OK, "{addr}");
+            assert_eq!(health["status"], "ok", "{addr}");
+        }
+        assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+
+        stop_tx.send(true).unwrap();
+        while let Some(result) = servers.join_next().await {
+            result.unwrap().unwrap();
+        }
+        for addr in addrs {
+            assert!(
+                tokio::net::TcpStream::connect(addr).await.is_err(),
+                "{addr}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unavailable_addresses_stop_the_server() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = taken.local_addr().unwrap();
+        let free = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = Server::new();
+        let database = server.dir.path().join("sysmet.db");
+
+        let err = run_server(
+            Listeners {
+                tcp: vec![free, taken],
+                ..Listeners::default()
+            },
+            &database.to_string_lossy(),
+            None,
+            RuntimeConfig::default(),
+            false,
+            false,
+            CookieSecret::new("secret of the tests"),
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), format!("Failed to listen on {taken}"));
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_get_a_page_in_the_site_layout() {
+        let server = Server::new();
+
+        let (status, headers, body) = server.get("/nowhere?token=secret", &[]).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        for part in [
+            format!("<title>Page not found - {WEBSITE_TITLE}</title>"),
+            "<h1>Page not found</h1>".to_string(),
+            "<a href=\"/\">Back to the dashboard</a>".to_string(),
+        ] {
+            assert!(body.contains(&part), "{part:?} missing from {body}");
+        }
+        assert!(body.trim_end().ends_with("</html>"));
+        assert!(
+            !body.contains("nowhere") && !body.contains("secret"),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_methods_get_a_page_keeping_the_allowed_ones() {
+        let server = Server::new();
+
+        let (status, headers, body) = server
+            .send(Request::delete("/health").body(Body::empty()).unwrap())
+            .await;
+
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert!(headers[header::ALLOW].to_str().unwrap().contains("GET"));
+        assert!(body.contains("<h1>Method not allowed</h1>"), "{body}");
+        assert!(body.contains("<a href=\"/\">Back to the dashboard</a>"));
+    }
+
+    #[tokio::test]
+    async fn dashboards_are_laid_out_before_the_linked_stylesheets_load() {
+        let server = Server::new();
+        let (status, _, page) = server.get("/", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (head, body) = page.split_once("</head>").unwrap();
+        // NOTE: Only the stylesheets built before the server are inlined
+        if !CRITICAL_CSS.is_empty() {
+            assert!(head.contains(&format!("<style>{}</style>", CRITICAL_CSS.as_str())));
+        }
+        let (deferred, _) = head.split_once("<noscript>").unwrap();
+        assert_eq!(
+            deferred.matches("rel=\"stylesheet\"").count(),
+            deferred
+                .matches("media=\"print\" onload=\"this.media='all'\"")
+                .count()
+        );
+        assert!(body.contains("<main class=\"container\">"));
+        assert!(body.contains("<svg class=\"chart\""));
+    }
+
+    #[tokio::test]
+    async fn unknown_stylesheets_stay_empty_and_uncached() {
+        let server = Server::new();
+
+        let (status, headers, body) = server.get("/css/unknown.css", &[]).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[header::CACHE_CONTROL], "no-store");
+        assert!(body.is_empty(), "{body}");
+    }
+
+    #[tokio::test]
+    async fn strict_readiness_holds_the_data_routes_until_the_first_load() {
+        let server = Server::new();
+        let loaded = server.extensions.state.load();
+        server.extensions.state.store(SharedData {
+            ready: false,
+            ..Default::default()
+        });
+
+        for path in ["/", "/board", "/now", "/api/snapshot/latest"] {
+            let (status, headers, body) = server.get(path, &[]).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{path}");
+            assert_eq!(
+                headers[header::RETRY_AFTER],
+                WARMING_UP_RETRY_AFTER.as_secs().to_string(),
+                "{path}"
+            );
+            assert!(body.contains("<h1>Warming up</h1>"), "{path}: {body}");
+        }
+        let (status, _, body) = server.get("/health", &[]).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("\"status\":\"starting\""), "{body}");
+        // NOTE: The warming up page is styled, the stylesheets only exist once built with yarn
+        let stylesheet = CSS_HASHES
+            .keys()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| "main.css".to_string());
+        let (status, _, _) = server.get(&format!("/css/{stylesheet}"), &[]).await;
+        let expected = if CSS_HASHES.is_empty() {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::OK
+        };
+        assert_eq!(status, expected);
+
+        server.extensions.state.store(SharedData {
+            database: loaded.database.clone(),
+            charts: loaded.charts.clone(),
+            ready: true,
+            ..Default::default()
+        });
+
+        let (status, _, body) = server.get("/", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains("Warming up"));
+        let (status, _, body) = server.get("/health", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"status\":\"ok\""), "{body}");
+    }
+
+    /// Tiles of the board the testing database has over the last hour, one per chart with data.
+    fn board_tiles() -> usize {
+        ChartsData::generate(
+            &testing::database(),
+            TimeRange::ending_at(testing::end().timestamp(), BOARD_RANGE),
+            None,
+            false,
+            None,
+            None,
+            ChartUnits::default(),
+            AxisScale::default(),
+        )
+        .metrics
+        .iter()
+        .filter(|(_, chart)| chart.has_data())
+        .count()
+    }
+
+    #[tokio::test]
+    async fn boards_have_a_tile_per_chart_with_data() {
+        let server = Server::new();
+
+        let (status, _, body) = server.get("/board", &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(board_tiles() > 0);
+        assert_eq!(
+            body.matches("<article class=\"tile ").count(),
+            board_tiles()
+        );
+        assert!(body.contains("grid-template-columns: repeat(4, minmax(0, 1fr))"));
+        assert!(!body.contains("board-host"));
+
+        let (_, _, body) = server.get("/board?cols=6", &[]).await;
+        assert!(body.contains("grid-template-columns: repeat(6, minmax(0, 1fr))"));
+        let (_, _, body) = server.get("/board?cols=13", &[]).await;
+        assert!(body.contains("grid-template-columns: repeat(4, minmax(0, 1fr))"));
+        assert!(
+            body.contains("expected a number of columns from 1 to 12"),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn boards_reload_unless_asked_not_to() {
+        let server = Server::new();
+        let refresh = "<meta http-equiv=\"refresh\" content=\"30\">";
+
+        assert!(server.get("/board", &[]).await.2.contains(refresh));
+        assert!(!server
+            .get("/board?refresh=off", &[])
+            .await
+            .2
+            .contains(refresh));
+        assert!(server
+            .get("/board?refresh=on", &[])
+            .await
+            .2
+            .contains(refresh));
+    }
+
+    #[tokio::test]
+    async fn boards_follow_the_theme() {
+        let server = Server::new();
+
+        for (uri, theme) in [
+            ("/board", "light"),
+            ("/board?theme=dark", "dark"),
+            ("/board?theme=light", "light"),
+        ] {
+            let (_, _, body) = server.get(uri, &[]).await;
+            assert!(body.contains(&format!("data-theme=\"{theme}\"")), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fleet_boards_have_a_row_per_host() {
+        let mut server = Server::new();
+        let fleet = server.dir.path().join("fleet");
+        std::fs::create_dir(&fleet).unwrap();
+        for name in ["a.db", "b.db"] {
+            testing::database()
+                .write_to_file(&fleet.join(name).to_string_lossy())
+                .unwrap();
+        }
+        std::fs::write(fleet.join("broken.db"), "not a database").unwrap();
+        server.extensions.fleet = Arc::new(Some(Fleet {
+            dir: fleet,
+            key: None,
+        }));
+        server.app = app(server.extensions.clone());
+
+        let (status, _, body) = server.get("/board", &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let hosts = body
+            .split("<section class=\"board-host\"><h2>")
+            .skip(1)
+            .map(|row| row.split_once("</h2>").unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(hosts, ["demo", "demo", "broken"]);
+        assert_eq!(
+            body.matches("<article class=\"tile ").count(),
+            2 * board_tiles()
+        );
+        assert!(body.contains("The database of this host could not be read."));
+    }
+
+    #[tokio::test]
+    async fn aggregates_list_the_hosts_left_out() {
+        let mut server = Server::new();
+        let fleet = server.dir.path().join("fleet");
+        std::fs::create_dir(&fleet).unwrap();
+        for name in ["a.db", "b.db"] {
+            testing::database()
+                .write_to_file(&fleet.join(name).to_string_lossy())
+                .unwrap();
+        }
+        server.extensions.fleet = Arc::new(Some(Fleet {
+            dir: fleet,
+            key: None,
+        }));
+        server.app = app(server.extensions.clone());
+        let range = "from=2024-05-03T11:00:00Z&to=2024-05-03T12:00:00Z";
+
+        let (status, _, body) = server
+            .get(
+                &format!("/api/aggregate?metric=net-tx&{range}&hosts=a,gone"),
+                &[],
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["combine"], "sum");
+        assert_eq!(json["hosts"].as_array().unwrap().len(), 1);
+        assert_eq!(json["excluded"], serde_json::json!(["gone"]));
+        assert!(!json["points"].as_array().unwrap().is_empty());
+
+        let (status, _, body) = server
+            .get(&format!("/aggregate?metric=cpu&{range}&hosts=a,gone"), &[])
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Without data in the range: gone"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn overlays_draw_each_metric_once_with_a_line_per_host() {
+        let mut server = Server::new();
+        let fleet = server.dir.path().join("fleet");
+        std::fs::create_dir(&fleet).unwrap();
+        for name in ["primary.db", "standby.db"] {
+            testing::database()
+                .write_to_file(&fleet.join(name).to_string_lossy())
+                .unwrap();
+        }
+        server.extensions.fleet = Arc::new(Some(Fleet {
+            dir: fleet,
+            key: None,
+        }));
+        server.app = app(server.extensions.clone());
+
+        let (status, _, body) = server
+            .get(
+                "/aggregate?metric=cpu,net-rx&from=2024-05-03T11:00:00Z&to=2024-05-03T12:00:00Z\
+                 &hosts=standby,primary,gone&overlay=on",
+                &[],
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<title>Overlay - Fleet"), "{body}");
+        assert_eq!(body.matches("<ul class=\"legend\">").count(), 2);
+        for (position, host) in ["standby", "primary"].into_iter().enumerate() {
+            let legend = format!(
+                "<li><span class=\"legend-color\" style=\"background-color: {}\"></span>{host}</li>",
+                palette::host_color(position)
+            );
+            assert_eq!(body.matches(&legend).count(), 2, "{legend}");
+        }
+        assert!(body.contains("Without data in the range: gone"));
+    }
+
+    /// `param=value` of each notice of the notice bar of `page`.
+    fn notices_of(page: &str) -> Vec<String> {
+        page.split("<p class=\"notice notice-")
+            .skip(1)
+            .map(|notice| {
+                let (_, code) = notice.split_once("<code>").unwrap();
+                code.split_once("</code>").unwrap().0.to_string()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn each_bad_parameter_gets_a_single_notice() {
+        let server = Server::new();
+        let bad = [
+            ("t", "forever"),
+            ("refresh", "yes"),
+            ("lite", "maybe"),
+            ("compare", "-1d"),
+            ("open", "compute,bogus"),
+            ("focus", "nope"),
+            ("network_units", "nibbles"),
+            ("locale", "xx"),
+            ("theme", "neon"),
+            ("smooth", "1"),
+            ("format", "pdf"),
+        ];
+        let query = bad
+            .iter()
+            .map(|(param, value)| format!("{param}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let (status, _, page) = server.get(&format!("/?{query}"), &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page.matches("class=\"notice-bar\"").count(), 1);
+        assert_eq!(notices_of(&page), query.split('&').collect::<Vec<_>>());
+
+        // NOTE: Each bad parameter falls back to its default, the known groups being kept
+        let (_, _, fallback) = server.get("/?open=compute", &[]).await;
+        assert!(!fallback.contains("notice-bar"), "{fallback}");
+        assert_eq!(
+            testing::submitted_query(&page),
+            testing::submitted_query(&fallback)
+        );
+
+        let (status, _, body) = server
+            .get(
+                "/download/cpu.csv?t=forever&raw=maybe&network_units=nibbles&disk_units=kb",
+                &[],
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        let notices = error["notices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|notice| notice["param"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(notices, ["t", "raw", "network_units", "disk_units"]);
+    }
+
+    #[tokio::test]
+    async fn submitting_the_dashboard_form_loses_nothing() {
+        let server = Server::new();
+        let query = "t=2d&compare=7d&anomalies=on&bands=on&open=compute,network&network_units=bits\
+                     &disk_units=si&locale=de&theme=dark&focus=load&smooth=10m&fullscale=on&refresh=on";
+
+        let (status, _, page) = server.get(&format!("/?{query}"), &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!page.contains("notice-bar"), "{page}");
+        let submitted = testing::submitted_query(&page);
+        let (status, _, resubmitted) = server.get(&format!("/?{submitted}"), &[]).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(testing::submitted_query(&resubmitted), submitted);
+        for param in query.split('&') {
+            let (name, _) = param.split_once('=').unwrap();
+            assert!(
+                submitted
+                    .split('&')
+                    .any(|submitted| submitted.starts_with(&format!("{name}="))),
+                "{name} lost in {submitted}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_dashboards_are_not_sent_again() {
+        let server = Server::new();
+
+        let (status, headers, page) = server.get("/?t=1h", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let etag = headers[header::ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/\""), "{etag}");
+
+        let (status, headers, body) = server.get("/?t=1h", &[("if-none-match", &etag)]).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers[header::ETAG], etag.as_str());
+        assert!(body.is_empty());
+
+        let (status, headers, other) = server
+            .get("/?t=1h&theme=dark", &[("if-none-match", &etag)])
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(headers[header::ETAG], etag.as_str());
+        assert_ne!(other, page);
+    }
+
+    #[tokio::test]
+    async fn terminals_get_the_dashboard_as_text() {
+        let server = Server::new();
+
+        for (uri, headers) in [
+            ("/", &[("accept", "text/plain")][..]),
+            ("/?format=txt", &[][..]),
+            ("/?format=txt", &[("accept", "text/html")][..]),
+        ] {
+            let (status, headers, body) = server.get(uri, headers).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+            assert!(body.starts_with(WEBSITE_TITLE), "{body}");
+            assert!(body.contains("\nCPU"), "{body}");
+            assert!(!body.contains("<html"));
+        }
+    }
+
+    #[tokio::test]
+    async fn browsers_get_the_dashboard_as_html() {
+        let server = Server::new();
+
+        for headers in [
+            &[][..],
+            &[("accept", "*/*")][..],
+            &[(
+                "accept",
+                "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+            )][..],
+        ] {
+            let (status, response, body) = server.get("/", headers).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                response[header::CONTENT_TYPE],
+                "text/html; charset=utf-8",
+                "{headers:?}"
+            );
+            assert!(body.starts_with("<!DOCTYPE html>"), "{headers:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn the_alerting_panel_is_shown_with_a_notify_state() {
+        let server = Server::new();
+        let (_, _, body) = server.get("/", &[]).await;
+        assert!(!body.contains("class=\"alerting\""));
+
+        let path = server.dir.path().join("notify-state.json");
+        server.extensions.config.store(RuntimeConfig {
+            notify_state: Some(path.clone()),
+            ..RuntimeConfig::default()
+        });
+        let (_, _, body) = server.get("/", &[]).await;
+        assert!(body.contains("Notify state file missing"), "{body}");
+
+        // NOTE: Read again on each request, as sysmet-notify rewrites it after each check
+        let state = metrics::thresholds::state::NotifyState {
+            checked_at: Some(chrono::Utc::now()),
+            ..Default::default()
+        };
+        std::fs::write(&path, serde_json::to_string(&state).unwrap()).unwrap();
+        let (_, _, body) = server.get("/", &[]).await;
+        assert!(body.contains("No host checked yet."), "{body}");
+        assert!(!body.contains("Notify state file missing"));
+        assert!(!body.contains("notify-stale"));
+    }
+
+    #[test]
+    fn rendered_files_are_self_contained() {
+        use chrono::Utc;
+        use metrics::{
+            demo::{DemoOptions, DEFAULT_DEMO_SEED},
+            prelude::Database,
+        };
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let database = dir.path().join("sysmet.db").to_string_lossy().into_owned();
+        // NOTE: The dashboard ends now, so does the database
+        Database::demo(&DemoOptions {
+            end: Utc::now(),
+            span: chrono::Duration::hours(1),
+            interval: chrono::Duration::minutes(1),
+            seed: DEFAULT_DEMO_SEED,
+        })
+        .unwrap()
+        .write_to_file(&database)
+        .unwrap();
+        let path = dir.path().join("report.html");
+        let range = Duration::from_secs(60 * 60);
+
+        render_to_file(
+            &database,
+            None,
+            range,
+            ChartUnits::default(),
+            AxisScale::default(),
+            &RenderOptions::builder().build(),
+            &path,
+        )
+        .unwrap();
+
+        let page = std::fs::read_to_string(&path).unwrap();
+        let charts = generator::generate_once(
+            &database,
+            None,
+            range,
+            ChartUnits::default(),
+            AxisScale::default(),
+        )
+        .unwrap();
+        let shown = generator::CHART_GROUPS
+            .iter()
+            .map(|group| charts.group_charts(group).len())
+            .sum::<usize>();
+        assert!(shown > 5, "{shown}");
+        assert_eq!(page.matches("<svg class=\"chart\"").count(), shown);
+        let (head, _) = page.split_once("</head>").unwrap();
+        // NOTE: Only the stylesheets built before the server are embedded
+        for css in components::embedded_css() {
+            assert!(head.contains(&format!("<style>{css}</style>")));
+        }
+        for reference in [
+            "<link",
+            "<script",
+            "<img",
+            "src=",
+            "href=\"/",
+            "http-equiv=\"refresh\"",
+        ] {
+            assert!(!page.contains(reference), "{reference} in {page}");
+        }
+    }
+
+    /// Most bytes the lite dashboard of a day of snapshots a minute apart can weigh, its inlined styles
+    /// included.
+    const LITE_PAGE_BUDGET: usize = 128 * 1024;
+
+    #[tokio::test]
+    async fn lite_dashboards_stay_under_their_budget() {
+        use chrono::Utc;
+        use metrics::{
+            demo::{DemoOptions, DEFAULT_DEMO_SEED},
+            prelude::Database,
+        };
+
+        let server = Server::new();
+        // NOTE: The dashboard ends now, so does the database
+        let database = Database::demo(&DemoOptions {
+            end: Utc::now(),
+            span: chrono::Duration::days(1),
+            interval: chrono::Duration::minutes(1),
+            seed: DEFAULT_DEMO_SEED,
+        })
+        .unwrap();
+        server.extensions.state.store(SharedData {
+            database: Arc::new(database),
+            ready: true,
+            ..Default::default()
+        });
+
+        let (status, _, full) = server.get("/?t=24h&anomalies=on", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, _, lite) = server.get("/?t=24h&anomalies=on&lite=on", &[]).await;
+        assert!(
+            lite.len() <= LITE_PAGE_BUDGET,
+            "{} bytes over the budget of {LITE_PAGE_BUDGET}",
+            lite.len()
+        );
+        assert!(
+            lite.len() * 2 < full.len(),
+            "{} of {}",
+            lite.len(),
+            full.len()
+        );
+
+        let (_, _, off) = server.get("/?t=24h&anomalies=on&lite=off", &[]).await;
+        assert_eq!(off, full);
+    }
 }
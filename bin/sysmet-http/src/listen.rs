@@ -0,0 +1,269 @@
+//! Listen addresses of `--listen`, the `HOST` and `PORT` environment variables and their defaults, resolved
+//! once the arguments are parsed so a wrong value is reported before anything starts.
+use std::{
+    env::var,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+};
+
+use color_eyre::eyre::{eyre, WrapErr};
+use log::{debug, tracing};
+
+use crate::Result;
+
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// `HOST` and `PORT`, as set in the environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListenEnv {
+    pub host: Option<String>,
+    pub port: Option<String>,
+    /// `::1` can be listened on, hosts with IPv6 disabled have none.
+    pub ipv6_loopback: bool,
+}
+
+impl ListenEnv {
+    pub fn from_env() -> Self {
+        Self {
+            host: var("HOST").ok().filter(|host| !host.is_empty()),
+            port: var("PORT").ok().filter(|port| !port.is_empty()),
+            ipv6_loopback: TcpListener::bind("[::1]:0").is_ok(),
+        }
+    }
+}
+
+/// Host and port of an address, e.g. `localhost:8080`, `[::1]:8080`, `::1`, `example.org` or a bare `8080`.
+fn split_host_port(value: &str) -> (Option<&str>, Option<&str>) {
+    let value = value.trim();
+    if value.chars().all(|c| c.is_ascii_digit()) {
+        return (None, Some(value));
+    }
+    if let Some(bracketed) = value.strip_prefix('[') {
+        return match bracketed.split_once(']') {
+            Some((host, rest)) => (Some(host), rest.strip_prefix(':')),
+            None => (Some(value), None),
+        };
+    }
+    match value.rsplit_once(':') {
+        // NOTE: An IPv6 literal without brackets has no port
+        Some(_) if value.matches(':').count() > 1 => (Some(value), None),
+        Some(("", port)) => (None, Some(port)),
+        Some((host, port)) => (Some(host), Some(port)),
+        None => (Some(value), None),
+    }
+}
+
+fn parse_port(port: &str, origin: &str, value: &str) -> Result<u16> {
+    port.parse::<u16>().map_err(|err| {
+        eyre!("Invalid port {port:?} in {origin}={value:?}, expected a number up to 65535: {err}")
+    })
+}
+
+/// Socket address strings of a host, the loopbacks without any.
+fn with_port(host: Option<&str>, port: u16, ipv6_loopback: bool) -> Vec<String> {
+    match host {
+        Some(host) if host.contains(':') => vec![format!("[{host}]:{port}")],
+        Some(host) => vec![format!("{host}:{port}")],
+        None if ipv6_loopback => vec![format!("127.0.0.1:{port}"), format!("[::1]:{port}")],
+        None => vec![format!("127.0.0.1:{port}")],
+    }
+}
+
+/// Resolve the addresses to listen on, hostnames may give several socket addresses (e.g. `localhost:8080`).
+///
+/// Each of `addresses` (`--listen` and the positional address) may leave its host or its port out: the
+/// loopbacks are listened on without a host and `PORT` (8080 without it) is used without a port, a port given
+/// with the address taking precedence. `HOST` is only used without any address.
+#[tracing::instrument]
+pub fn resolve_listen_addresses(addresses: &[String], env: &ListenEnv) -> Result<Vec<SocketAddr>> {
+    let default_port = env
+        .port
+        .as_deref()
+        .map(|port| parse_port(port, "PORT", port))
+        .transpose()?
+        .unwrap_or(DEFAULT_PORT);
+
+    let mut candidates = Vec::new();
+    if addresses.is_empty() {
+        let host = env.host.as_deref().map(|host| {
+            let host = host.trim();
+            host.strip_prefix('[')
+                .and_then(|host| host.strip_suffix(']'))
+                .unwrap_or(host)
+        });
+        for candidate in with_port(host, default_port, env.ipv6_loopback) {
+            candidates.push(("HOST", env.host.clone().unwrap_or_default(), candidate));
+        }
+    }
+    for value in addresses {
+        let (host, port) = split_host_port(value);
+        let port = match port {
+            Some(port) => parse_port(port, "--listen", value)?,
+            None => default_port,
+        };
+        for candidate in with_port(host, port, env.ipv6_loopback) {
+            candidates.push(("--listen", value.clone(), candidate));
+        }
+    }
+
+    let mut addrs = Vec::new();
+    for (origin, value, candidate) in candidates {
+        let resolved = candidate
+            .to_socket_addrs()
+            .wrap_err_with(|| format!("Failed to resolve the listen address {origin}={value:?}"))?;
+        for addr in resolved {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    debug!(?addrs);
+
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(host: Option<&str>, port: Option<&str>) -> ListenEnv {
+        ListenEnv {
+            host: host.map(str::to_string),
+            port: port.map(str::to_string),
+            ipv6_loopback: true,
+        }
+    }
+
+    fn resolve(addresses: &[&str], env: &ListenEnv) -> Result<Vec<String>> {
+        let addresses = addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>();
+        resolve_listen_addresses(&addresses, env)
+            .map(|addrs| addrs.iter().map(SocketAddr::to_string).collect())
+    }
+
+    #[test]
+    fn the_default_is_dual_stack() {
+        assert_eq!(
+            resolve(&[], &env(None, None)).unwrap(),
+            ["127.0.0.1:8080", "[::1]:8080"]
+        );
+        assert_eq!(
+            resolve(&[], &env(None, Some("9000"))).unwrap(),
+            ["127.0.0.1:9000", "[::1]:9000"]
+        );
+
+        let ipv4_only = ListenEnv {
+            ipv6_loopback: false,
+            ..env(None, None)
+        };
+        assert_eq!(resolve(&[], &ipv4_only).unwrap(), ["127.0.0.1:8080"]);
+    }
+
+    #[test]
+    fn hosts_are_used_without_addresses() {
+        assert_eq!(
+            resolve(&[], &env(Some("0.0.0.0"), None)).unwrap(),
+            ["0.0.0.0:8080"]
+        );
+        assert_eq!(
+            resolve(&[], &env(Some("[::]"), Some("81"))).unwrap(),
+            ["[::]:81"]
+        );
+        assert_eq!(
+            resolve(&["10.0.0.1"], &env(Some("0.0.0.0"), None)).unwrap(),
+            ["10.0.0.1:8080"]
+        );
+    }
+
+    #[test]
+    fn addresses_may_leave_out_their_host_or_port() {
+        let env = env(None, Some("9000"));
+
+        let addrs = resolve(
+            &[
+                "127.0.0.1:1234",
+                "::1",
+                "[::1]:81",
+                "8081",
+                ":8082",
+                "127.0.0.1",
+            ],
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            addrs,
+            [
+                "127.0.0.1:1234",
+                "[::1]:9000",
+                "[::1]:81",
+                "127.0.0.1:8081",
+                "[::1]:8081",
+                "127.0.0.1:8082",
+                "[::1]:8082",
+                "127.0.0.1:9000",
+            ]
+        );
+    }
+
+    #[test]
+    fn hostnames_are_resolved_once_per_address() {
+        let addrs = resolve(&["localhost:8080", "127.0.0.1:8080"], &env(None, None)).unwrap();
+
+        assert!(addrs.contains(&"127.0.0.1:8080".to_string()));
+        assert_eq!(
+            addrs
+                .iter()
+                .filter(|addr| *addr == "127.0.0.1:8080")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn invalid_ports_are_errors() {
+        let err = resolve(&["127.0.0.1:99999"], &env(None, None)).unwrap_err();
+        assert!(
+            err.to_string().contains("--listen=\"127.0.0.1:99999\""),
+            "{err}"
+        );
+
+        let err = resolve(&[], &env(None, Some("http"))).unwrap_err();
+        assert!(err.to_string().contains("PORT=\"http\""), "{err}");
+
+        let err = resolve(&["host.invalid:80"], &env(None, None)).unwrap_err();
+        assert!(err.to_string().contains("\"host.invalid:80\""), "{err}");
+    }
+
+    #[test]
+    fn hosts_may_be_hostnames() {
+        let addrs = resolve(&[], &env(Some("localhost"), Some("9000"))).unwrap();
+        assert!(addrs.contains(&"127.0.0.1:9000".to_string()), "{addrs:?}");
+        assert!(
+            addrs.iter().all(|addr| addr.ends_with(":9000")),
+            "{addrs:?}"
+        );
+
+        let err = resolve(&[], &env(Some("host.invalid"), None)).unwrap_err();
+        assert!(err.to_string().contains("HOST=\"host.invalid\""), "{err}");
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_environment() {
+        let set = env(Some("0.0.0.0"), Some("9000"));
+
+        assert_eq!(resolve(&["127.0.0.1:81"], &set).unwrap(), ["127.0.0.1:81"]);
+        assert_eq!(resolve(&["127.0.0.1"], &set).unwrap(), ["127.0.0.1:9000"]);
+        assert_eq!(resolve(&["[::1]"], &set).unwrap(), ["[::1]:9000"]);
+        assert_eq!(
+            resolve(&["81"], &set).unwrap(),
+            ["127.0.0.1:81", "[::1]:81"]
+        );
+
+        // NOTE: An invalid PORT is reported even when every address has its port
+        let err = resolve(&["127.0.0.1:81"], &env(None, Some("eightyeighty"))).unwrap_err();
+        assert!(err.to_string().contains("PORT=\"eightyeighty\""), "{err}");
+    }
+}
@@ -0,0 +1,234 @@
+//! Decimal separator, digit grouping and date order of the numbers and dates shown to people, the few rules
+//! needed being written here rather than pulling an i18n library. The CSV and the API keep a machine format.
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+
+use crate::svg::round_to_len;
+
+/// How numbers and dates are written for a language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocaleSpec {
+    /// Value of `--locale` and of the `locale` parameter, e.g. `de`.
+    pub tag: &'static str,
+    pub decimal_separator: char,
+    /// Separator of the groups of three digits of the integer part, `None` to write them in a row.
+    pub group_separator: Option<char>,
+    /// Chrono format of a date, e.g. `%d.%m.%Y`.
+    pub date_format: &'static str,
+    /// Chrono format of a date without its year, e.g. on the time axis.
+    pub short_date_format: &'static str,
+}
+
+/// Format of the machines, `1234.5` and `2024-05-03`, and of the pages before `--locale`.
+pub const ISO: LocaleSpec = LocaleSpec {
+    tag: "iso",
+    decimal_separator: '.',
+    group_separator: None,
+    date_format: "%Y-%m-%d",
+    short_date_format: "%m-%d",
+};
+/// `1,234.5` and `05/03/2024`.
+pub const EN: LocaleSpec = LocaleSpec {
+    tag: "en",
+    decimal_separator: '.',
+    group_separator: Some(','),
+    date_format: "%m/%d/%Y",
+    short_date_format: "%b %d",
+};
+/// `1.234,5` and `03.05.2024`.
+pub const DE: LocaleSpec = LocaleSpec {
+    tag: "de",
+    decimal_separator: ',',
+    group_separator: Some('.'),
+    date_format: "%d.%m.%Y",
+    short_date_format: "%d.%m.",
+};
+/// `1 234,5` (with a narrow no-break space) and `03/05/2024`.
+pub const FR: LocaleSpec = LocaleSpec {
+    tag: "fr",
+    decimal_separator: ',',
+    group_separator: Some('\u{202f}'),
+    date_format: "%d/%m/%Y",
+    short_date_format: "%d/%m",
+};
+pub const LOCALES: [LocaleSpec; 4] = [ISO, EN, DE, FR];
+
+impl Default for LocaleSpec {
+    fn default() -> Self {
+        ISO
+    }
+}
+
+impl fmt::Display for LocaleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.tag)
+    }
+}
+
+impl FromStr for LocaleSpec {
+    type Err = String;
+
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        Self::from_tag(tag).ok_or_else(|| format!("unknown locale {tag:?}, expected {}", tags()))
+    }
+}
+
+/// Tags of `LOCALES`, e.g. for the messages of an unknown one.
+pub fn tags() -> String {
+    LOCALES
+        .iter()
+        .map(|locale| locale.tag)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl LocaleSpec {
+    /// Locale of a language tag, its region being ignored, e.g. `de-AT` or `fr_CH`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        LOCALES.into_iter().find(|locale| locale.tag == language)
+    }
+
+    /// `value` rounded to at most `decimals` decimals, e.g. `1.234,5` for 1234.5 in German.
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        if !value.is_finite() {
+            return value.to_string();
+        }
+        // NOTE: A small negative value rounds to -0, which reads as a sign error
+        let rounded = round_to_len(value, decimals) + 0.0;
+        let plain = rounded.to_string();
+        let (sign, digits) = match plain.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", plain.as_str()),
+        };
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+
+        let mut number = sign.to_string();
+        match self.group_separator {
+            Some(separator) => {
+                for (idx, digit) in integer.chars().enumerate() {
+                    if idx > 0 && (integer.len() - idx) % 3 == 0 {
+                        number.push(separator);
+                    }
+                    number.push(digit);
+                }
+            }
+            None => number.push_str(integer),
+        }
+        if let Some(fraction) = fraction {
+            number.push(self.decimal_separator);
+            number.push_str(fraction);
+        }
+        number
+    }
+
+    /// Whole number, e.g. a count of snapshots.
+    pub fn integer(&self, value: usize) -> String {
+        self.number(value as f64, 0)
+    }
+
+    pub fn date(&self, time: DateTime<Utc>) -> String {
+        time.format(self.date_format).to_string()
+    }
+
+    /// Date and time of day on 24 hours, e.g. `03.05.2024 14:02`.
+    pub fn date_time(&self, time: DateTime<Utc>) -> String {
+        format!("{} {}", self.date(time), time.format("%H:%M"))
+    }
+
+    pub fn short_date(&self, time: DateTime<Utc>) -> String {
+        time.format(self.short_date_format).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn numbers_follow_each_locale() {
+        let numbers = [
+            (1234.5, 1),
+            (-1234.5, 1),
+            (0.000123, 6),
+            (-0.001, 2),
+            (12_345_678.9, 1),
+            (999.0, 2),
+        ];
+        for (locale, expected) in [
+            (
+                ISO,
+                ["1234.5", "-1234.5", "0.000123", "0", "12345678.9", "999"],
+            ),
+            (
+                EN,
+                [
+                    "1,234.5",
+                    "-1,234.5",
+                    "0.000123",
+                    "0",
+                    "12,345,678.9",
+                    "999",
+                ],
+            ),
+            (
+                DE,
+                [
+                    "1.234,5",
+                    "-1.234,5",
+                    "0,000123",
+                    "0",
+                    "12.345.678,9",
+                    "999",
+                ],
+            ),
+            (
+                FR,
+                [
+                    "1\u{202f}234,5",
+                    "-1\u{202f}234,5",
+                    "0,000123",
+                    "0",
+                    "12\u{202f}345\u{202f}678,9",
+                    "999",
+                ],
+            ),
+        ] {
+            let formatted = numbers.map(|(value, decimals)| locale.number(value, decimals));
+            assert_eq!(formatted, expected, "{locale}");
+        }
+        assert_eq!(DE.integer(1_000_000), "1.000.000");
+        assert_eq!(DE.number(f64::NAN, 2), "NaN");
+    }
+
+    #[test]
+    fn dates_follow_each_locale() {
+        let time = Utc.with_ymd_and_hms(2024, 5, 3, 14, 2, 0).unwrap();
+        for (locale, date_time, short_date) in [
+            (ISO, "2024-05-03 14:02", "05-03"),
+            (EN, "05/03/2024 14:02", "May 03"),
+            (DE, "03.05.2024 14:02", "03.05."),
+            (FR, "03/05/2024 14:02", "03/05"),
+        ] {
+            assert_eq!(locale.date_time(time), date_time, "{locale}");
+            assert_eq!(locale.short_date(time), short_date, "{locale}");
+        }
+    }
+
+    #[test]
+    fn tags_are_matched_without_their_region() {
+        assert_eq!(LocaleSpec::from_tag("de-AT"), Some(DE));
+        assert_eq!(LocaleSpec::from_tag(" FR_ch "), Some(FR));
+        assert_eq!(LocaleSpec::from_tag("xx"), None);
+        assert!("xx"
+            .parse::<LocaleSpec>()
+            .unwrap_err()
+            .contains("iso, en, de, fr"));
+    }
+}
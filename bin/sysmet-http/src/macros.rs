@@ -5,14 +5,16 @@ macro_rules! static_files_server {
             ::axum::extract::Path(path): ::axum::extract::Path<String>,
         ) -> impl ::axum::response::IntoResponse {
             use axum::{
-                body::{self, Empty, Full},
+                body::Body,
                 http::{header, HeaderValue, Response, StatusCode},
             };
 
             let path = path.trim_start_matches('/');
+            // NOTE: Not cached so a file of the next deployment is found as soon as it's there
             let not_found = Response::builder()
                 .status(StatusCode::NOT_FOUND)
-                .body(body::boxed(Empty::new()))
+                .header(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))
+                .body(Body::empty())
                 .unwrap();
 
             if !$hashes.contains_key(path) {
@@ -32,7 +34,7 @@ macro_rules! static_files_server {
                         header::CONTENT_TYPE,
                         HeaderValue::from_str($content_type).unwrap(),
                     )
-                    .body(body::boxed(Full::from(file.contents())))
+                    .body(Body::from(file.contents()))
                     .unwrap(),
             }
         }
@@ -43,13 +45,13 @@ macro_rules! static_files_server {
 macro_rules! generate_hashes {
     ($name:ident, $dir:ident) => {
         ::once_cell::sync::Lazy::new(|| {
-            use base64::encode;
+            use base64::{engine::general_purpose::STANDARD, Engine};
             use sha2::{Digest, Sha256};
 
             $dir.files()
                 .map(|file| {
                     let path = file.path().to_path_buf();
-                    let hash = encode(Sha256::digest(file.contents()));
+                    let hash = STANDARD.encode(Sha256::digest(file.contents()));
                     let mut asset_path = path.clone();
                     asset_path.set_extension(
                         [
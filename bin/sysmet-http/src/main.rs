@@ -1,37 +1,461 @@
 #![forbid(unsafe_code)]
 
-use std::env::{set_var, var};
-
-use clap::{ArgAction, Parser};
-use once_cell::sync::Lazy;
-use sysmet_http::{run_server, Result};
-
-// NOTE: Use HOST and PORT env variables as defaults (runtime)
-static DEFAULT_ADDRESS: Lazy<String> = Lazy::new(|| {
-    format!(
-        "{}:{}",
-        var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-        var("PORT").unwrap_or_else(|_| "8080".to_string())
-    )
-});
+use std::{
+    env::{args_os, remove_var, set_var, temp_dir},
+    ffi::OsString,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::Utc;
+use clap::{builder::ArgPredicate, ArgAction, Parser};
+use env::{
+    config::{Config, DatabaseSection, EnvSection, HttpSection, UpdateSection, CONFIG_ENV},
+    legacy::{replace_legacy_flags, strict_flags, LegacyFlag},
+    version::is_verbose_version,
+};
+use log::{info, warn};
+use metrics::{
+    backup,
+    demo::{DemoOptions, DEFAULT_DEMO_SEED},
+    prelude::{BackupPolicy, Database, DatabaseKey},
+};
+use sysmet_http::admin::AdminAccess;
+use sysmet_http::fleet::Fleet;
+use sysmet_http::locale::LocaleSpec;
+use sysmet_http::proxy::TrustedProxies;
+use sysmet_http::query::DashboardState;
+use sysmet_http::reload::{Reloaded, Reloader, RuntimeConfig};
+use sysmet_http::signing::{self, CookieSecret};
+#[cfg(unix)]
+use sysmet_http::uds::{parse_mode, UnixSocket};
+use sysmet_http::usagelog::UsageLog;
+use sysmet_http::{
+    known_groups,
+    listen::{resolve_listen_addresses, ListenEnv},
+    palettes_report, render_to_file, run_server,
+    units::{AxisScale, ChartUnits, DEFAULT_OUTLIER_FACTOR},
+    validate_palettes, Listeners, RenderOptions, Result, Theme, BUILD_INFO,
+    DEFAULT_MAX_MEMORY_POINTS,
+};
 
-#[derive(Parser)]
+/// Flags of the original sysmet still accepted with a deprecation warning.
+const LEGACY_FLAGS: &[LegacyFlag] = &[("-db", "--database")];
+
+#[derive(Parser, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
-    #[clap(long, visible_alias = "db", value_name = "FILE")]
+    #[clap(long, env = CONFIG_ENV, value_name = "FILE", help = "Path to the optional TOML config file")]
+    config: Option<PathBuf>,
+    #[clap(long, help = "Print the effective configuration and exit")]
+    print_config: bool,
+    #[clap(
+        long,
+        help = "Print the contrast ratio of each palette color with the background of its theme and exit"
+    )]
+    list_palettes: bool,
+    #[clap(
+        long,
+        visible_alias = "db",
+        env = "SYSMET_DATABASE",
+        value_name = "PATH",
+        // NOTE: Not an `Option` so the flag is only required without `--demo` or `--list-palettes`
+        required = false,
+        required_unless_present_any = ["demo", "list_palettes"],
+        default_value_if("demo", ArgPredicate::IsPresent, Some("")),
+        default_value_if("list_palettes", ArgPredicate::IsPresent, Some("")),
+        help = "Database file, or directory of a database sharded by sysmet-update --database-dir"
+    )]
     database: String,
-    #[clap(value_name = "LISTENING ADDRESS", default_value = DEFAULT_ADDRESS.as_str())]
-    address: String,
+    #[clap(
+        long,
+        help = "Serve a generated week of demo snapshots instead of --database, written to a temporary directory"
+    )]
+    demo: bool,
+    #[clap(
+        long,
+        env = "SYSMET_DB_KEY",
+        hide_env_values = true,
+        value_name = "PASSPHRASE",
+        help = "Passphrase used to decrypt the database, prefer the environment variable"
+    )]
+    db_key: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_DB_KEY_FILE",
+        value_name = "FILE",
+        help = "File containing the database passphrase"
+    )]
+    db_key_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_ADDRESS",
+        value_name = "ADDRESS",
+        value_delimiter = ',',
+        help = "Address to listen on, can be repeated or comma separated, a host alone taking PORT [default: HOST, or 127.0.0.1 and [::1], on PORT or 8080]"
+    )]
+    listen: Vec<String>,
+    #[clap(value_name = "LISTENING ADDRESS", help = "Same as --listen")]
+    address: Option<String>,
+    #[cfg(unix)]
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_UDS",
+        value_name = "PATH",
+        help = "Unix socket to listen on, alone or along the addresses"
+    )]
+    listen_uds: Option<PathBuf>,
+    #[cfg(unix)]
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_UDS_MODE",
+        value_name = "MODE",
+        value_parser = parse_mode,
+        default_value = "0660",
+        help = "Octal file mode of the unix socket"
+    )]
+    uds_mode: u32,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_MAX_MEMORY_POINTS",
+        value_name = "POINTS",
+        default_value_t = DEFAULT_MAX_MEMORY_POINTS,
+        help = "Max number of points kept in memory across every chart, older snapshots are thinned out beyond"
+    )]
+    max_memory_points: usize,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_NETWORK_UNITS",
+        default_value = "bytes",
+        value_parser = ["bits", "bytes"],
+        help = "Unit of the network chart, overridden by the network_units query parameter"
+    )]
+    network_units: String,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_DISK_UNITS",
+        default_value = "iec",
+        value_parser = ["si", "iec"],
+        help = "Powers of 1000 (si) or 1024 (iec) for the disks charts, overridden by the disk_units query parameter"
+    )]
+    disk_units: String,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_LOCALE",
+        default_value = "iso",
+        value_parser = LocaleSpec::from_str,
+        help = "Decimal separator, digit grouping and date order of the pages: iso, en, de or fr, overridden by the locale query parameter"
+    )]
+    locale: LocaleSpec,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_OUTLIER_FACTOR",
+        value_name = "FACTOR",
+        default_value_t = DEFAULT_OUTLIER_FACTOR,
+        help = "Times the max of a chart must exceed the 99.5th percentile of its values for the axis to end there, the points above being clipped and marked, 0 to never clip"
+    )]
+    outlier_factor: u32,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_VIEW_CACHE",
+        help = "Keep the generated charts in <database>.viewcache to show them right away after a restart"
+    )]
+    view_cache: bool,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_SLOW_OP_THRESHOLD",
+        value_name = "DURATION",
+        default_value = "250ms",
+        value_parser = humantime::parse_duration,
+        help = "Operations slower than this (database load, chart generation, rendering) are logged and listed on /stats"
+    )]
+    slow_op_threshold: Duration,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_TRUSTED_PROXIES",
+        value_name = "CIDR,...",
+        value_parser = TrustedProxies::from_str,
+        help = "Proxies whose X-Forwarded-For, -Proto and -Host headers are believed, e.g. 127.0.0.1,10.0.0.0/8 (unix for the unix socket)"
+    )]
+    trusted_proxies: Option<TrustedProxies>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_STRICT_READINESS",
+        help = "Answer 503 on the dashboard and API routes, and starting on /health, until the database was loaded once"
+    )]
+    strict_readiness: bool,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_COOKIE_SECRET",
+        hide_env_values = true,
+        value_name = "SECRET",
+        help = "Secret signing the saved views cookies [default: generated in <database>.cookie-secret]"
+    )]
+    cookie_secret: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_FLEET_DIR",
+        value_name = "DIR",
+        help = "Directory of one <host>.db database per host, combined on /aggregate with the key of --database"
+    )]
+    fleet_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_USAGE_LOG",
+        value_name = "FILE",
+        help = "Append a JSON line per dashboard or API request (range, charts, response time and size) to the file, summarized on /stats"
+    )]
+    usage_log: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_USAGE_LOG_CLIENTS",
+        help = "Also write the address and the user agent of the clients to the --usage-log"
+    )]
+    usage_log_clients: bool,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_NOTIFY_STATE",
+        value_name = "FILE",
+        help = "State file of sysmet-notify (its --state-path), shown in an alerting panel of the dashboard"
+    )]
+    notify_state: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_ADMIN_TOKEN",
+        hide_env_values = true,
+        value_name = "TOKEN",
+        help = "Bearer token of /admin/db/download and /admin/db/upload, which answer 404 without it"
+    )]
+    admin_token: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_HTTP_ALLOW_UPLOAD",
+        requires = "admin_token",
+        help = "Let /admin/db/upload replace the database with the uploaded file"
+    )]
+    allow_upload: bool,
+    #[clap(
+        long,
+        env = "SYSMET_BACKUP_DIR",
+        value_name = "DIR",
+        help = "Directory of the backups taken before an upload replaces the database, defaults to the database directory"
+    )]
+    backup_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_BACKUP_KEEP",
+        default_value = "3",
+        value_parser = backup::parse_keep,
+        value_name = "NUMBER OF BACKUPS",
+        help = "Number of backups to keep, at least 1"
+    )]
+    backup_keep: usize,
+    #[clap(
+        long,
+        help = "Do not back up the database before an upload replaces it"
+    )]
+    no_backup: bool,
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Write the dashboard to a self-contained HTML file and exit instead of serving it"
+    )]
+    render_to: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "3h",
+        value_parser = humantime::parse_duration,
+        help = "Range of the dashboard written by --render-to, e.g. 7d"
+    )]
+    range: Duration,
+    #[clap(
+        long,
+        default_value = "light",
+        value_parser = ["light", "dark"],
+        help = "Theme of the dashboard written by --render-to"
+    )]
+    theme: String,
+    #[clap(
+        long,
+        value_name = "GROUPS",
+        help = "Comma separated chart groups open in the dashboard written by --render-to, e.g. compute,network [default: every group]"
+    )]
+    open: Option<String>,
     #[clap(short, long = "verbose", action = ArgAction::Count)]
     verbosity: u8,
 }
 
+impl Cli {
+    /// Addresses given with `--listen` and the positional address, the defaults of `HOST` and `PORT` apart.
+    fn listen_addresses(&self) -> Vec<String> {
+        self.listen
+            .iter()
+            .chain(self.address.iter())
+            .cloned()
+            .collect()
+    }
+
+    fn tcp_addresses(&self) -> Result<Vec<SocketAddr>> {
+        let addresses = self.listen_addresses();
+        // NOTE: A unix socket alone does not also listen on the default addresses
+        #[cfg(unix)]
+        if addresses.is_empty() && self.listen_uds.is_some() {
+            return Ok(Vec::new());
+        }
+        resolve_listen_addresses(&addresses, &ListenEnv::from_env())
+    }
+
+    /// Settings applied again on SIGHUP.
+    fn runtime_config(&self) -> RuntimeConfig {
+        RuntimeConfig {
+            max_memory_points: self.max_memory_points,
+            // NOTE: Both are restricted to the valid values by clap
+            units: ChartUnits::default()
+                .with_overrides(Some(&self.network_units), Some(&self.disk_units)),
+            locale: self.locale,
+            scale: AxisScale::from_factor(self.outlier_factor),
+            slow_op_threshold: self.slow_op_threshold,
+            trusted_proxies: self.trusted_proxies.clone().unwrap_or_default(),
+            notify_state: self.notify_state.clone(),
+        }
+    }
+
+    /// Settings only read at startup that differ in `new`.
+    fn restart_required(&self, new: &Self) -> Vec<&'static str> {
+        let mut changed = vec![
+            ("database", self.database != new.database),
+            (
+                "database key",
+                self.db_key != new.db_key || self.db_key_file != new.db_key_file,
+            ),
+            (
+                "listen addresses",
+                self.listen_addresses() != new.listen_addresses(),
+            ),
+            ("view cache", self.view_cache != new.view_cache),
+            (
+                "strict readiness",
+                self.strict_readiness != new.strict_readiness,
+            ),
+            ("cookie secret", self.cookie_secret != new.cookie_secret),
+            ("fleet directory", self.fleet_dir != new.fleet_dir),
+            (
+                "usage log",
+                self.usage_log != new.usage_log || self.usage_log_clients != new.usage_log_clients,
+            ),
+            (
+                "admin token",
+                self.admin_token != new.admin_token || self.allow_upload != new.allow_upload,
+            ),
+            (
+                "backups",
+                self.backup_dir != new.backup_dir
+                    || self.backup_keep != new.backup_keep
+                    || self.no_backup != new.no_backup,
+            ),
+        ];
+        #[cfg(unix)]
+        changed.push((
+            "unix socket",
+            self.listen_uds != new.listen_uds || self.uds_mode != new.uds_mode,
+        ));
+
+        changed
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Access to the admin routes, `None` without `--admin-token`.
+    fn admin_access(&self) -> Option<AdminAccess> {
+        self.admin_token.clone().map(|token| AdminAccess {
+            token,
+            allow_upload: self.allow_upload,
+            backup: (!self.no_backup).then(|| BackupPolicy {
+                dir: self.backup_dir.clone(),
+                keep: self.backup_keep,
+            }),
+        })
+    }
+
+    fn effective_config(&self) -> Config {
+        Config {
+            database: DatabaseSection {
+                path: Some(self.database.clone()),
+                key: self.db_key.clone(),
+                key_file: self.db_key_file.clone(),
+                ..Default::default()
+            },
+            // NOTE: The backups share their settings with sysmet-update
+            update: UpdateSection {
+                backup_dir: self.backup_dir.clone(),
+                backup_keep: Some(self.backup_keep),
+                ..Default::default()
+            },
+            http: HttpSection {
+                address: Some(self.listen_addresses().join(","))
+                    .filter(|address| !address.is_empty()),
+                #[cfg(unix)]
+                unix_socket: self.listen_uds.clone(),
+                #[cfg(unix)]
+                unix_socket_mode: Some(format!("{:04o}", self.uds_mode)),
+                max_memory_points: Some(self.max_memory_points),
+                network_units: Some(self.network_units.clone()),
+                disk_units: Some(self.disk_units.clone()),
+                locale: Some(self.locale.to_string()),
+                outlier_factor: Some(self.outlier_factor),
+                view_cache: Some(self.view_cache),
+                strict_readiness: Some(self.strict_readiness),
+                cookie_secret: self.cookie_secret.clone(),
+                fleet_dir: self.fleet_dir.clone(),
+                usage_log: self.usage_log.clone(),
+                usage_log_clients: Some(self.usage_log_clients),
+                notify_state: self.notify_state.clone(),
+                admin_token: self.admin_token.clone(),
+                allow_upload: Some(self.allow_upload),
+                trusted_proxies: self.trusted_proxies.as_ref().map(TrustedProxies::to_string),
+                slow_op_threshold: Some(
+                    humantime::format_duration(self.slow_op_threshold).to_string(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
     color_eyre::install()?;
+    // NOTE: The env and the config file must be loaded before parsing as clap reads them as environment variables
     env::setup_env();
+    let config = Config::from_args()?;
+    let mut from_config = Vec::new();
+    if let Some(config) = &config {
+        from_config.extend(config.database.apply());
+        from_config.extend(config.http.apply());
+    }
 
-    let app = Cli::parse();
+    let legacy_args = replace_legacy_flags(args_os(), LEGACY_FLAGS, strict_flags())?;
+    if is_verbose_version(&legacy_args.args) {
+        println!("{}", BUILD_INFO.verbose());
+        return Ok(());
+    }
+    let app = Cli::parse_from(legacy_args.args);
+    // NOTE: Resolved before the logger and any task so a wrong HOST, PORT or --listen fails right away
+    let serves = !app.print_config && !app.list_palettes && app.render_to.is_none();
+    let tcp = if serves {
+        app.tcp_addresses()?
+    } else {
+        Vec::new()
+    };
 
     if app.verbosity > 2 {
         set_var("LOG_LEVEL", "trace");
@@ -43,7 +467,261 @@ async fn main() -> Result<()> {
 
     let _logfiles_writer_handle = log::setup_logger_with_logfiles(env!("CARGO_PKG_NAME"));
 
-    run_server(app.address.parse()?, &app.database).await?;
+    for (legacy, current) in legacy_args.used {
+        warn!("{legacy} is deprecated, use {current} instead");
+    }
+
+    if let Some(unknown_keys) = config
+        .map(|config| config.unknown_keys())
+        .filter(|keys| !keys.is_empty())
+    {
+        warn!(
+            "Unknown keys in the config file: {}",
+            unknown_keys.join(", ")
+        );
+    }
+    if app.print_config {
+        println!("{}", app.effective_config().masked().to_toml()?);
+        return Ok(());
+    }
+    if app.list_palettes {
+        print!("{}", palettes_report());
+        let issues = validate_palettes();
+        if !issues.is_empty() {
+            println!(
+                "{} colors below the min ratio of their palette",
+                issues.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let key = DatabaseKey::resolve(app.db_key.as_deref(), app.db_key_file.as_deref())?;
+    // NOTE: The demo database is written unencrypted, the key of the environment being left aside
+    let (database, key) = if app.demo {
+        (write_demo_database()?, None)
+    } else {
+        (app.database.clone(), key)
+    };
+    let runtime_config = app.runtime_config();
+
+    if let Some(path) = &app.render_to {
+        let opts = RenderOptions::builder()
+            .state(DashboardState {
+                theme: Theme::from_query(&app.theme).unwrap_or_default(),
+                open: app.open.as_deref().map(known_groups),
+                locale: runtime_config.locale,
+                ..Default::default()
+            })
+            .build();
+        return render_to_file(
+            &database,
+            key.as_ref(),
+            app.range,
+            runtime_config.units,
+            runtime_config.scale,
+            &opts,
+            path,
+        );
+    }
+
+    let cookie_secret = match &app.cookie_secret {
+        Some(secret) => CookieSecret::new(secret),
+        None => CookieSecret::load_or_generate(&signing::path_for(&database))?,
+    };
+    let listeners = Listeners {
+        tcp,
+        #[cfg(unix)]
+        unix_socket: app.listen_uds.clone().map(|path| UnixSocket {
+            path,
+            mode: app.uds_mode,
+        }),
+    };
+    let fleet = app.fleet_dir.clone().map(|dir| Fleet {
+        dir,
+        key: key.clone(),
+    });
+    let usage_log = app
+        .usage_log
+        .as_deref()
+        .map(|path| UsageLog::open(path, app.usage_log_clients))
+        .transpose()?;
+    run_server(
+        listeners,
+        &database,
+        key,
+        runtime_config,
+        app.view_cache,
+        app.strict_readiness,
+        cookie_secret,
+        Some(reloader(app.clone(), args_os().collect(), from_config)),
+        fleet,
+        usage_log,
+        app.admin_access(),
+    )
+    .await?;
 
     Ok(())
 }
+
+/// Write a week of demo snapshots up to now in a new temporary directory, returns the path of the database.
+fn write_demo_database() -> Result<String> {
+    let dir = temp_dir().join(format!("sysmet-demo-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("demo.db").to_string_lossy().to_string();
+    let mut database = Database::demo(&DemoOptions {
+        end: Utc::now(),
+        span: chrono::Duration::days(7),
+        interval: chrono::Duration::minutes(1),
+        seed: DEFAULT_DEMO_SEED,
+    })?;
+    database.write_to_file(&path)?;
+    info!("Serving {} demo snapshots from {path}", database.len());
+    Ok(path)
+}
+
+/// Parse the arguments again with the current config file, the settings of the environment and the
+/// arguments taking precedence as at startup.
+///
+/// `args` are the arguments of the process and `from_config` the environment variables set from the previous
+/// config file, which are replaced by the values of the new one.
+fn reloader(started: Cli, args: Vec<OsString>, from_config: Vec<&'static str>) -> Reloader {
+    let from_config = Mutex::new(from_config);
+    Box::new(move || {
+        // NOTE: Parsed before dropping anything so an invalid file keeps the previous settings
+        let config = Config::from_arg_list(args.clone())?;
+        let legacy_args = replace_legacy_flags(args.iter().cloned(), LEGACY_FLAGS, strict_flags())?;
+
+        let mut from_config = from_config.lock().unwrap();
+        // NOTE: A key removed from the file falls back to its default
+        for name in from_config.drain(..) {
+            remove_var(name);
+        }
+        if let Some(config) = &config {
+            from_config.extend(config.database.apply());
+            from_config.extend(config.http.apply());
+        }
+        let app = Cli::try_parse_from(legacy_args.args)?;
+
+        Ok(Reloaded {
+            config: app.runtime_config(),
+            restart_required: started.restart_required(&app),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::var, sync::PoisonError};
+
+    use super::*;
+
+    #[test]
+    fn at_least_one_backup_is_kept() {
+        let keep = |value: &str| {
+            Cli::try_parse_from([
+                env!("CARGO_PKG_NAME"),
+                "--database",
+                "sysmet.db",
+                "--backup-keep",
+                value,
+            ])
+            .map(|app| app.backup_keep)
+        };
+        assert!(keep("0").is_err());
+        assert_eq!(keep("1").unwrap(), 1);
+    }
+
+    /// Effective config of the command line once its legacy flags are replaced, and the legacy flags used.
+    fn parsed(args: &[&str]) -> (String, Vec<LegacyFlag>) {
+        let legacy = replace_legacy_flags(
+            std::iter::once(env!("CARGO_PKG_NAME"))
+                .chain(args.iter().copied())
+                .map(OsString::from),
+            LEGACY_FLAGS,
+            false,
+        )
+        .unwrap();
+        let app = Cli::try_parse_from(legacy.args).unwrap();
+        (app.effective_config().to_toml().unwrap(), legacy.used)
+    }
+
+    // NOTE: The reloads set the environment variables read when parsing
+    static ENV: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn legacy_flags_give_the_same_config() {
+        let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        let (legacy, used) = parsed(&["-db", "sysmet.db"]);
+        let (current, none) = parsed(&["--database=sysmet.db"]);
+
+        assert_eq!(legacy, current);
+        assert!(legacy.contains("\"sysmet.db\""), "{legacy}");
+        assert!(none.is_empty());
+        assert_eq!(used, LEGACY_FLAGS);
+    }
+
+    #[test]
+    fn palettes_are_listed_without_a_database() {
+        let app = Cli::try_parse_from([env!("CARGO_PKG_NAME"), "--list-palettes"]).unwrap();
+        assert!(app.list_palettes);
+
+        assert!(Cli::try_parse_from([env!("CARGO_PKG_NAME")]).is_err());
+    }
+
+    #[test]
+    fn reloads_read_the_changed_config_file() {
+        let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sysmet.toml");
+        let args: Vec<OsString> = [
+            env!("CARGO_PKG_NAME"),
+            "--database",
+            "sysmet.db",
+            "--config",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .chain([path.clone().into_os_string()])
+        .collect();
+        let locale = |value: &str| value.parse::<LocaleSpec>().unwrap();
+
+        fs::write(
+            &path,
+            "[http]\nlocale = \"de\"\nslow_op_threshold = \"1s\"\n",
+        )
+        .unwrap();
+        let from_config = Config::from_arg_list(args.clone())
+            .unwrap()
+            .unwrap()
+            .http
+            .apply();
+        let started = Cli::try_parse_from(&args).unwrap();
+        assert_eq!(started.runtime_config().locale, locale("de"));
+        let reload = reloader(started, args, from_config);
+
+        fs::write(
+            &path,
+            "[http]\nlocale = \"fr\"\naddress = \"127.0.0.1:9999\"\n",
+        )
+        .unwrap();
+        let reloaded = reload().unwrap();
+        assert_eq!(reloaded.config.locale, locale("fr"));
+        // NOTE: Removed from the file, back to its default
+        assert_eq!(
+            reloaded.config.slow_op_threshold,
+            Duration::from_millis(250)
+        );
+        assert_eq!(reloaded.restart_required, ["listen addresses"]);
+
+        fs::write(&path, "[http\nlocale = \"it\"\n").unwrap();
+        assert!(reload().is_err());
+        assert_eq!(var("SYSMET_HTTP_LOCALE").as_deref(), Ok("fr"));
+
+        fs::write(&path, "").unwrap();
+        let reloaded = reload().unwrap();
+        assert_eq!(reloaded.config.locale, LocaleSpec::default());
+        assert!(var("SYSMET_HTTP_LOCALE").is_err());
+        assert!(var("SYSMET_HTTP_ADDRESS").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use maud::{html, Markup};
+use metrics::aggregate::AGGREGATE_METRICS;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    locale::LocaleSpec, query::Notices, range_to_query, Base, BaseContext, Chart, ChartContext,
+    NoticeBar, Theme, RANGE_PRESETS, WEBSITE_TITLE,
+};
+
+#[derive(Debug, TypedBuilder)]
+pub struct AggregateOptions {
+    #[builder(default)]
+    pub theme: Theme,
+    #[builder(default)]
+    pub locale: LocaleSpec,
+    /// Name of the aggregated metric, one of `AGGREGATE_METRICS`. With `overlay`, the comma separated names
+    /// of the overlaid metrics, empty for all of them.
+    #[builder(setter(into))]
+    pub metric: String,
+    pub range: Duration,
+    /// The faint line of each host is drawn behind the aggregate one.
+    #[builder(default = true)]
+    pub hosts: bool,
+    /// Comma separated hosts of the query, `None` for every host of the fleet.
+    #[builder(default)]
+    pub selected: Option<String>,
+    /// A chart per metric with a line per host instead of the aggregate.
+    #[builder(default)]
+    pub overlay: bool,
+    /// Hosts without data in the range, or not found in the fleet directory.
+    #[builder(default)]
+    pub excluded: Vec<String>,
+    /// Parameters of the query that could not be used.
+    #[builder(default)]
+    pub notices: Notices,
+}
+
+/// Chart of a metric combined over the hosts of the fleet directory, or of each overlaid metric.
+pub fn AggregatePage(charts: &[ChartContext], opts: &AggregateOptions) -> Markup {
+    let range = range_to_query(opts.range);
+    let title = match charts {
+        [chart] if !opts.overlay => chart.title.clone(),
+        _ => "Overlay".to_string(),
+    };
+    let listed = !opts.metric.is_empty() && !AGGREGATE_METRICS.contains(&opts.metric.as_str());
+    let json = format!(
+        "/api/aggregate?metric={}&t={range}{}",
+        opts.metric,
+        opts.selected
+            .as_ref()
+            .map_or_else(String::new, |hosts| format!("&hosts={hosts}"))
+    );
+    Base(
+        BaseContext::builder()
+            .title(format!("{title} - Fleet - {WEBSITE_TITLE}"))
+            .theme(opts.theme)
+            .build(),
+        html! {
+            section.aggregate-page {
+                h1 { "Fleet" }
+                (NoticeBar(&opts.notices))
+                form method="get" action="/aggregate" {
+                    label { "Metric "
+                        select name="metric" {
+                            @if opts.overlay {
+                                option value="" selected[opts.metric.is_empty()] { "all" }
+                            }
+                            @if listed {
+                                option value=(opts.metric) selected { (opts.metric) }
+                            }
+                            @for metric in AGGREGATE_METRICS {
+                                option value=(metric) selected[metric == opts.metric] { (metric) }
+                            }
+                        }
+                    }
+                    " "
+                    label { "Range "
+                        select name="t" {
+                            @for (value, label) in RANGE_PRESETS {
+                                option value=(value) selected[value == range] { (label) }
+                            }
+                        }
+                    }
+                    " "
+                    // NOTE: The lines of listed hosts are always drawn, the checkbox would send `hosts` twice
+                    @if let Some(selected) = &opts.selected {
+                        input type="hidden" name="hosts" value=(selected);
+                    } @else {
+                        label {
+                            input type="checkbox" name="hosts" value="on" checked[opts.hosts];
+                            " Each host"
+                        }
+                    }
+                    " "
+                    label {
+                        input type="checkbox" name="overlay" value="on" checked[opts.overlay];
+                        " Overlay"
+                    }
+                    // NOTE: An unchecked checkbox is not sent, this tells the form apart from a bare link
+                    input type="hidden" name="form" value="on";
+                    input type="hidden" name="locale" value=(opts.locale.tag);
+                    " "
+                    button type="submit" { "Show" }
+                }
+                @if let Some(selected) = &opts.selected {
+                    p { "Hosts: " (selected) }
+                }
+                @for chart in charts {
+                    (Chart(chart, &opts.locale))
+                }
+                @if !opts.excluded.is_empty() {
+                    p.excluded { "Without data in the range: " (opts.excluded.join(", ")) }
+                }
+                @if !opts.overlay {
+                    p { a href=(json) { "JSON" } }
+                }
+            }
+        },
+    )
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use maud::{html, Markup};
+use typed_builder::TypedBuilder;
+
+use crate::{
+    locale::LocaleSpec, query::Notices, range_summary, Base, BaseContext, Board, BoardContext,
+    ChartsData, NoticeBar, Theme, DEFAULT_BOARD_COLUMNS, WEBSITE_TITLE,
+};
+
+/// Time between two reloads of the board.
+pub const BOARD_REFRESH: Duration = Duration::from_secs(30);
+
+#[derive(Debug, TypedBuilder)]
+pub struct BoardOptions {
+    #[builder(default)]
+    pub theme: Theme,
+    #[builder(default)]
+    pub locale: LocaleSpec,
+    #[builder(default = true)]
+    pub refresh: bool,
+    #[builder(default = DEFAULT_BOARD_COLUMNS)]
+    pub columns: usize,
+    /// Parameters of the query that could not be used.
+    #[builder(default)]
+    pub notices: Notices,
+}
+
+/// Status board for wall displays, without the form and the details of the dashboard.
+///
+/// With `hosts` the board has a row of tiles per host of the fleet instead of the tiles of `data`, a host
+/// without charts having an unreadable database.
+pub fn BoardPage(
+    data: &ChartsData,
+    hosts: &[(String, Option<ChartsData>)],
+    opts: &BoardOptions,
+) -> Markup {
+    let board = |data| {
+        Board(
+            BoardContext::builder()
+                .data(data)
+                .columns(opts.columns)
+                .locale(opts.locale)
+                .build(),
+        )
+    };
+    let time = Duration::from_secs(data.range.duration().max(0) as u64);
+    Base(
+        BaseContext::builder()
+            .title(format!("Status board - {WEBSITE_TITLE}"))
+            .theme(opts.theme)
+            .refresh_every(opts.refresh.then_some(BOARD_REFRESH))
+            .build(),
+        html! {
+            section.board-page {
+                (NoticeBar(&opts.notices))
+                @if hosts.is_empty() {
+                    (board(data))
+                }
+                @for (hostname, charts) in hosts {
+                    section.board-host {
+                        h2 { (hostname) }
+                        @match charts {
+                            Some(charts) => (board(charts)),
+                            None => p.warning { "The database of this host could not be read." },
+                        }
+                    }
+                }
+                p.summary { (range_summary(time, data.range.end, data.snapshots_count, &opts.locale)) }
+            }
+        },
+    )
+}
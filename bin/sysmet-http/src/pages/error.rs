@@ -0,0 +1,21 @@
+use maud::{html, Markup};
+
+use crate::{Base, BaseContext, WEBSITE_TITLE};
+
+/// Page of a request the server can't answer, e.g. an unknown path.
+pub fn ErrorPage(title: &str, message: &str, base_path: &str) -> Markup {
+    Base(
+        BaseContext::builder()
+            .title(format!("{title} - {WEBSITE_TITLE}"))
+            .base_path(base_path)
+            .build(),
+        html! {
+            section {
+                h1 { (title) }
+                p { (message) }
+                // NOTE: Nothing of the request is kept, the link always opens the default dashboard
+                p { a href=(format!("{base_path}/")) { "Back to the dashboard" } }
+            }
+        },
+    )
+}
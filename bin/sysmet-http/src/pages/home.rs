@@ -0,0 +1,419 @@
+use std::time::Duration;
+
+use maud::{html, Markup};
+use typed_builder::TypedBuilder;
+
+use crate::{
+    embedded_stylesheets,
+    generator::{ChartsData, CHART_GROUPS},
+    query::{DashboardState, Notices},
+    range_summary, range_to_query, AlertingContext, AlertingPanel, Base, BaseContext, Chart,
+    ChartContext, FilterForm, NoticeBar, CRITICAL_CSS, SOURCE_URL, WEBSITE_TITLE,
+};
+
+/// Time between two reloads of the dashboard with the auto-refresh.
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(60);
+
+/// Everything the dashboard depends on besides its charts.
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct RenderOptions {
+    #[builder(default = WEBSITE_TITLE.to_string(), setter(into))]
+    pub title: String,
+    /// Prefix of the links of the page without trailing slash, e.g. `/sysmet` when served under it.
+    #[builder(default, setter(into))]
+    pub base_path: String,
+    /// Parameters of the dashboard, kept by the filter form, the permalinks and the lite links.
+    #[builder(default)]
+    pub state: DashboardState,
+    /// Stylesheets as (path under `{base_path}/css`, integrity hash), the embedded ones by default.
+    #[builder(default = embedded_stylesheets())]
+    pub stylesheets: Vec<(String, String)>,
+    /// Styles inlined ahead of the linked stylesheets, the critical ones by default.
+    #[builder(default = CRITICAL_CSS.as_str())]
+    pub critical_css: &'static str,
+    /// Offer the lite view with a banner, e.g. to a phone browser.
+    #[builder(default = false)]
+    pub suggest_lite: bool,
+    /// Self-contained page written to a file, with its stylesheets inlined and without the refresh, the
+    /// range form and the links to the server.
+    #[builder(default = false)]
+    pub static_page: bool,
+    /// The view comes from the saved view cookie, a notice offers to reset it.
+    #[builder(default = false)]
+    pub saved_view: bool,
+    /// State of `sysmet-notify` shown in the alerting panel, which is left out without it.
+    #[builder(default)]
+    pub alerting: Option<AlertingContext>,
+    /// Parameters of the query that could not be used, shown above the charts.
+    #[builder(default)]
+    pub notices: Notices,
+}
+
+pub fn Dashboard(data: &ChartsData, opts: &RenderOptions) -> Markup {
+    let time = Duration::from_secs(data.range.duration().max(0) as u64);
+    let units = data.units;
+    let state = &opts.state;
+    let csv_range = if state.range.explicit.is_some() {
+        format!("from={}&to={}", data.range.start, data.range.end)
+    } else {
+        format!("t={}", range_to_query(time))
+    };
+    let is_focused = |id: &str| state.focus.as_deref() == Some(id);
+    let open_groups = state.open_groups();
+    let is_open = |id: &str, charts: &[&ChartContext]| {
+        charts.iter().any(|context| is_focused(&context.id))
+            || open_groups
+                .as_deref()
+                .is_none_or(|open| open.split(',').any(|open_id| open_id == id))
+    };
+    let stale_since = data.stale_since();
+    let query = state.to_query();
+    let lite_query = DashboardState {
+        lite: true,
+        ..state.clone()
+    }
+    .to_query();
+    let full_query = DashboardState {
+        lite: false,
+        ..state.clone()
+    }
+    .to_query();
+
+    Base(
+        BaseContext::builder()
+            .refresh_every((state.refresh && !opts.static_page).then_some(DASHBOARD_REFRESH))
+            .title(opts.title.clone())
+            .theme(state.theme)
+            .base_path(opts.base_path.clone())
+            .stylesheets(opts.stylesheets.clone())
+            .critical_css(opts.critical_css)
+            .inline_stylesheets(opts.static_page)
+            .build(),
+        html! {
+            section {
+                h1 { "sysmet faster" }
+                @if !opts.static_page {
+                    (FilterForm(state))
+                }
+            }
+            section {
+                (NoticeBar(&opts.notices))
+                p.summary { (range_summary(time, data.range.end, data.snapshots_count, &state.locale)) }
+                @if opts.suggest_lite && !opts.static_page {
+                    p.banner.lite-suggestion {
+                        "On a phone? "
+                        a href=(format!("?{lite_query}")) { "Switch to the lite view" }
+                        ", lighter and with fewer charts open."
+                    }
+                }
+                @if state.lite && !opts.static_page {
+                    p.saved-view {
+                        "Lite view — "
+                        a href=(format!("?{full_query}")) { "full view" }
+                    }
+                }
+                @if opts.saved_view {
+                    p.saved-view {
+                        "Using your saved view — "
+                        a href="?reset_view=on" { "reset" }
+                    }
+                }
+                @if let Some(stale_since) = stale_since {
+                    p.banner {
+                        (format!("No snapshot since {} UTC, the end of the range has no data.", state.locale.date_time(stale_since)))
+                    }
+                }
+                @if data.container {
+                    p.container-notice { "Collected in a container, the memory and CPU usages are relative to its limits when it has some." }
+                }
+                @if let Some(clock_mismatch) = &data.clock_mismatch {
+                    p.banner.clock-skew { (clock_mismatch.text()) }
+                }
+                @if data.compared_snapshots_count == Some(0) {
+                    p.banner { "No snapshot in the compared range, only the current data is shown." }
+                }
+                @if let Some(alerting) = &opts.alerting {
+                    (AlertingPanel(alerting))
+                }
+                // NOTE: Groups without data in the range are not shown at all
+                @for group in &CHART_GROUPS {
+                    @let group_charts = data.group_charts(group);
+                    @if !group_charts.is_empty() {
+                        // NOTE: Prefixed so a group never has the id of one of its charts, e.g. `network`
+                        details.chart-group id=(format!("group-{}", group.id)) open[is_open(group.id, &group_charts)] {
+                            summary { h2 { (group.title) } }
+                            @for context in group_charts {
+                                section.focused[is_focused(&context.id)] id=(context.id) tabindex=[is_focused(&context.id).then_some("-1")] autofocus[is_focused(&context.id)] {
+                                    h3 {
+                                        (context.title)
+                                        // NOTE: A static page has no server to link to
+                                        @if !opts.static_page {
+                                            " "
+                                            a.permalink href=(format!("{}/?{}#{}", opts.base_path, query, context.id)) title="Link to this chart" { "¶" }
+                                            " "
+                                            a.csv-link href=(format!("{}/download/{}.csv?{csv_range}{}", opts.base_path, context.id, units.to_query())) title="RFC3339 dates and `.` decimals, whatever the locale" download { "CSV" }
+                                        }
+                                    }
+                                    @if let Some(subtitle) = &context.subtitle {
+                                        p.chart-subtitle { (subtitle) }
+                                    }
+                                    (Chart(context, &state.locale))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            section {
+                a href=(SOURCE_URL) referer="none" target="_blank" { "Source code" }
+                span { " - Licensed under the AGPL v3.0." }
+            }
+        },
+    )
+}
+
+/// Full HTML of the dashboard showing `data`.
+pub fn render_dashboard(data: &ChartsData, opts: &RenderOptions) -> String {
+    Dashboard(data, opts).into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics::clock::ClockSkew;
+
+    use super::*;
+    use crate::{generator::ClockMismatch, locale::ISO, render_chart_svg, testing, Theme};
+
+    fn options(theme: Theme) -> RenderOptions {
+        RenderOptions::builder()
+            .state(DashboardState {
+                theme,
+                ..DashboardState::default()
+            })
+            .stylesheets(testing::stylesheets())
+            .critical_css(testing::CRITICAL_CSS)
+            .build()
+    }
+
+    #[test]
+    fn dashboards_match_their_golden_files() {
+        let charts = testing::charts();
+        for theme in [Theme::Light, Theme::Dark] {
+            let rendered = render_dashboard(&charts, &options(theme));
+            assert!(rendered.contains(&format!("data-theme=\"{}\"", theme.as_str())));
+            testing::assert_golden(&format!("dashboard-{}.html", theme.as_str()), &rendered);
+        }
+    }
+
+    #[test]
+    fn chart_svgs_match_their_golden_files() {
+        let charts = testing::charts();
+        for id in ["cpu", "ram"] {
+            let (_, chart) = charts
+                .metrics
+                .iter()
+                .find(|(_, chart)| chart.id == id)
+                .unwrap();
+            testing::assert_golden(&format!("chart-{id}.svg"), &render_chart_svg(chart, &ISO));
+        }
+    }
+
+    #[test]
+    fn renderings_are_pure() {
+        let options = options(Theme::Dark);
+        assert_eq!(
+            render_dashboard(&testing::charts(), &options),
+            render_dashboard(&testing::charts(), &options)
+        );
+    }
+
+    #[test]
+    fn clock_mismatches_are_shown_in_a_banner() {
+        let mut charts = testing::charts();
+        let options = options(Theme::Light);
+        assert!(!render_dashboard(&charts, &options).contains("clock-skew"));
+
+        let mismatch = ClockMismatch {
+            skew: ClockSkew::WholeHours(2),
+            newest_snapshot: testing::end(),
+            server_time: testing::end() + chrono::Duration::hours(2),
+        };
+        charts.clock_mismatch = Some(mismatch);
+
+        let rendered = render_dashboard(&charts, &options);
+        assert!(
+            rendered.contains(&format!(
+                "<p class=\"banner clock-skew\">{}</p>",
+                mismatch.text()
+            )),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn containers_are_noted_above_the_charts() {
+        let mut charts = testing::charts();
+        let options = options(Theme::Light);
+        assert!(!render_dashboard(&charts, &options).contains("container-notice"));
+
+        charts.container = true;
+
+        let rendered = render_dashboard(&charts, &options);
+        let notice = rendered
+            .find("<p class=\"container-notice\">Collected in a container")
+            .unwrap();
+        assert!(notice < rendered.find("chart-group").unwrap(), "{rendered}");
+    }
+
+    #[test]
+    fn chart_subtitles_are_shown_under_their_title() {
+        let mut charts = testing::charts();
+        let options = options(Theme::Light);
+        assert!(!render_dashboard(&charts, &options).contains("chart-subtitle"));
+
+        charts.metrics[0].1.subtitle = Some("CPU count changed 4 \u{2192} 8".to_string());
+
+        let rendered = render_dashboard(&charts, &options);
+        assert_eq!(rendered.matches("chart-subtitle").count(), 1);
+        assert!(rendered.contains("<p class=\"chart-subtitle\">CPU count changed 4 \u{2192} 8</p>"));
+    }
+
+    /// Identifiers of the groups of `rendered`, as (id, open).
+    fn groups(rendered: &str) -> Vec<(&str, bool)> {
+        rendered
+            .split("<details class=\"chart-group\" id=\"group-")
+            .skip(1)
+            .map(|rest| {
+                let (id, rest) = rest.split_once('"').unwrap();
+                (id, rest.starts_with(" open>"))
+            })
+            .collect()
+    }
+
+    fn with_state(state: DashboardState) -> String {
+        render_dashboard(
+            &testing::charts(),
+            &RenderOptions::builder()
+                .state(state)
+                .stylesheets(testing::stylesheets())
+                .critical_css(testing::CRITICAL_CSS)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn groups_without_data_are_hidden() {
+        let rendered = with_state(DashboardState::default());
+
+        // NOTE: The demo database has neither sensors nor custom metrics
+        assert_eq!(
+            groups(&rendered),
+            [
+                ("compute", true),
+                ("memory", true),
+                ("storage", true),
+                ("network", true)
+            ]
+        );
+        assert!(rendered.contains("<summary><h2>Compute</h2></summary><section"));
+        assert!(!rendered.contains("group-sensors"));
+    }
+
+    #[test]
+    fn only_the_groups_asked_for_are_open() {
+        let rendered = with_state(DashboardState {
+            open: Some("memory,network".to_string()),
+            ..DashboardState::default()
+        });
+
+        assert_eq!(
+            groups(&rendered),
+            [
+                ("compute", false),
+                ("memory", true),
+                ("storage", false),
+                ("network", true)
+            ]
+        );
+        // NOTE: Collapsed groups still hold their charts
+        assert!(rendered.contains("id=\"cpu\""));
+    }
+
+    #[test]
+    fn focused_charts_open_their_group() {
+        let rendered = with_state(DashboardState {
+            open: Some(String::new()),
+            focus: Some("load".to_string()),
+            ..DashboardState::default()
+        });
+
+        assert_eq!(
+            groups(&rendered),
+            [
+                ("compute", true),
+                ("memory", false),
+                ("storage", false),
+                ("network", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn lite_views_open_the_first_groups() {
+        let rendered = with_state(DashboardState {
+            lite: true,
+            ..DashboardState::default()
+        });
+
+        assert_eq!(
+            groups(&rendered),
+            [
+                ("compute", true),
+                ("memory", true),
+                ("storage", false),
+                ("network", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn open_groups_round_trip_through_the_form() {
+        let state = DashboardState {
+            open: Some("compute,network".to_string()),
+            ..DashboardState::default()
+        };
+
+        let rendered = with_state(state.clone());
+
+        assert!(
+            rendered.contains("<input type=\"hidden\" name=\"open\" value=\"compute,network\">")
+        );
+        let query = state.to_query();
+        assert!(query.contains("&open=compute,network&"), "{query}");
+        // NOTE: The presets and permalinks keep them too, escaped in the attributes
+        let href = |query: String| format!("href=\"{}\"", query.replace('&', "&amp;"));
+        assert!(rendered.contains(&href(format!("/?{query}#cpu"))));
+        let day = state.with_duration(Duration::from_secs(24 * 60 * 60));
+        assert!(rendered.contains(&href(format!("?{}", day.to_query()))));
+    }
+
+    #[test]
+    fn charts_are_anchored_by_their_slug() {
+        let charts = testing::charts();
+        let options = options(Theme::Light);
+        let rendered = render_dashboard(&charts, &options);
+
+        // NOTE: Charts without data in the range are not shown, e.g. the kernel gauges of the demo
+        let shown = CHART_GROUPS
+            .iter()
+            .flat_map(|group| charts.group_charts(group))
+            .collect::<Vec<_>>();
+        assert!(shown.len() > 5, "{shown:?}");
+        for context in shown {
+            let id = format!(" id=\"{}\"", context.id);
+            assert_eq!(rendered.matches(&id).count(), 1, "{id}");
+            assert!(rendered.contains(&format!("#{}\" title=\"Link to this chart\"", context.id)));
+        }
+        assert_eq!(render_dashboard(&testing::charts(), &options), rendered);
+    }
+}
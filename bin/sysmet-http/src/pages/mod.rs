@@ -0,0 +1,9 @@
+#![allow(non_snake_case)]
+mod aggregate;
+pub use aggregate::*;
+mod board;
+pub use board::*;
+mod error;
+pub use error::*;
+mod home;
+pub use home::*;
@@ -0,0 +1,359 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{contrast::contrast_ratio, Theme};
+
+/// Share of white mixed into a line color to get the color of its min/max band.
+const BAND_LIGHTENING: f64 = 0.5;
+/// Least contrast ratio of a line with the background, the one of WCAG for graphical objects.
+pub const MIN_CONTRAST_RATIO: f64 = 3.0;
+/// Least contrast ratio of the lines of the high contrast palette, the one of WCAG AAA for text.
+pub const HIGH_CONTRAST_RATIO: f64 = 7.0;
+
+/// Colors of the lines of the built-in charts.
+const DEFAULT_LINE_COLORS: [&str; 17] = [
+    "#e00", "#b70", "#08a", "#e60", "#2a2", "#e0e", "#099", "#a50", "#b3b", "#0a0", "#46f", "#e66",
+    "#77e", "#3a3", "#c5c", "#a4f", "#888",
+];
+
+/// Colors of the lines drawn on each theme, checked against its background by `validate`.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub name: &'static str,
+    pub min_ratio: f64,
+    pub light: &'static [&'static str],
+    pub dark: &'static [&'static str],
+}
+
+pub const PALETTES: [Palette; 2] = [
+    Palette {
+        name: "default",
+        min_ratio: MIN_CONTRAST_RATIO,
+        light: &DEFAULT_LINE_COLORS,
+        dark: &DEFAULT_LINE_COLORS,
+    },
+    // NOTE: No color is 7:1 with both backgrounds, each theme has its own
+    Palette {
+        name: "high-contrast",
+        min_ratio: HIGH_CONTRAST_RATIO,
+        light: &[
+            "#a00000", "#005a00", "#0000c0", "#6a0080", "#00566b", "#7a3d00", "#4a4a4a", "#8a005a",
+        ],
+        dark: &[
+            "#ff8a8a", "#7dff7d", "#9ab8ff", "#e0a0ff", "#5fe8e8", "#ffc060", "#d0d0d0", "#ff8ad8",
+        ],
+    },
+];
+
+impl Palette {
+    /// Contrast ratio of each color with the background of its theme.
+    pub fn ratios(&self) -> Vec<(Theme, &'static str, f64)> {
+        [(Theme::Light, self.light), (Theme::Dark, self.dark)]
+            .into_iter()
+            .flat_map(|(theme, colors)| {
+                let background = parse_hex(theme.background()).unwrap_or_default();
+                colors.iter().map(move |color| {
+                    let ratio =
+                        parse_hex(color).map_or(1.0, |color| contrast_ratio(color, background));
+                    (theme, *color, ratio)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Color of a palette below the min ratio of the palette with the background of its theme.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContrastIssue {
+    pub palette: &'static str,
+    pub theme: Theme,
+    pub color: &'static str,
+    pub ratio: f64,
+}
+
+impl fmt::Display for ContrastIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of the {} palette is {:.2}:1 on the {} theme",
+            self.color,
+            self.palette,
+            self.ratio,
+            self.theme.as_str()
+        )
+    }
+}
+
+/// Color of the lines of the host at `position` in a list of hosts, the colors being reused past the last one.
+pub fn host_color(position: usize) -> &'static str {
+    DEFAULT_LINE_COLORS[position % DEFAULT_LINE_COLORS.len()]
+}
+
+/// Colors of every palette that are too close to the background of their theme.
+pub fn validate() -> Vec<ContrastIssue> {
+    PALETTES
+        .iter()
+        .flat_map(|palette| {
+            palette
+                .ratios()
+                .into_iter()
+                .filter(|(_, _, ratio)| *ratio < palette.min_ratio)
+                .map(|(theme, color, ratio)| ContrastIssue {
+                    palette: palette.name,
+                    theme,
+                    color,
+                    ratio,
+                })
+        })
+        .collect()
+}
+
+/// Each palette with the contrast ratio of its colors, those below its min ratio being marked.
+pub fn palettes_report() -> String {
+    let mut report = String::new();
+    for palette in &PALETTES {
+        report.push_str(&format!(
+            "{} (at least {}:1)\n",
+            palette.name, palette.min_ratio
+        ));
+        for (theme, color, ratio) in palette.ratios() {
+            report.push_str(&format!(
+                "  {:<5}  {color:<7}  {ratio:>5.2}:1{}\n",
+                theme.as_str(),
+                if ratio < palette.min_ratio {
+                    "  too low"
+                } else {
+                    ""
+                }
+            ));
+        }
+    }
+
+    report
+}
+
+/// Colors lines may be given by name besides `#rgb` and `#rrggbb`, with their hexadecimal value.
+pub const NAMED_COLORS: [(&str, &str); 8] = [
+    ("red", "#e00"),
+    ("orange", "#fa0"),
+    ("yellow", "#ee0"),
+    ("green", "#0a0"),
+    ("teal", "#0aa"),
+    ("blue", "#00e"),
+    ("purple", "#a0a"),
+    ("gray", "#888"),
+];
+
+/// Color of a line, always a lowercase `#rgb` or `#rrggbb` so it can't break out of the svg attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LineColor(Cow<'static, str>);
+
+impl LineColor {
+    /// Neutral color of the lines whose color is invalid.
+    pub const FALLBACK: LineColor = LineColor(Cow::Borrowed("#888"));
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for LineColor {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let color = value.trim().to_ascii_lowercase();
+        if let Some((_, hex)) = NAMED_COLORS.iter().find(|(name, _)| *name == color) {
+            return Ok(LineColor(Cow::Borrowed(hex)));
+        }
+        match parse_hex(&color) {
+            Some(_) => Ok(LineColor(Cow::Owned(color))),
+            None => Err(format!(
+                "{value} is not a color, expected #rgb, #rrggbb or one of {}",
+                NAMED_COLORS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for LineColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<LineColor> for String {
+    fn from(color: LineColor) -> Self {
+        color.0.into_owned()
+    }
+}
+
+impl fmt::Display for LineColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Red, green and blue of a `#rgb` or `#rrggbb` color.
+fn parse_hex(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+    match hex.len() {
+        // NOTE: Each digit of the short form is doubled, `#e00` is `#ee0000`
+        3 => {
+            let mut rgb = [0; 3];
+            for (idx, digit) in hex.char_indices() {
+                rgb[idx] = channel(&digit.to_string())? * 0x11;
+            }
+            Some(rgb)
+        }
+        6 => Some([
+            channel(hex.get(0..2)?)?,
+            channel(hex.get(2..4)?)?,
+            channel(hex.get(4..6)?)?,
+        ]),
+        _ => None,
+    }
+}
+
+/// `color` mixed with `amount` (0 to 1) of white, colors that are not hexadecimal are returned as is.
+pub fn lighten(color: &str, amount: f64) -> String {
+    let Some(rgb) = parse_hex(color) else {
+        return color.to_string();
+    };
+    let amount = amount.clamp(0.0, 1.0);
+    let [r, g, b] =
+        rgb.map(|channel| (channel as f64 + (255.0 - channel as f64) * amount).round() as u8);
+
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Color of the min/max band drawn behind a line of `color`.
+pub fn band_color(color: &str) -> String {
+    lighten(color, BAND_LIGHTENING)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_mixed_with_white() {
+        assert_eq!(lighten("#000000", 0.5), "#808080");
+        assert_eq!(lighten("#e00", 0.5), "#f78080");
+        assert_eq!(lighten("#123456", 0.0), "#123456");
+        assert_eq!(lighten("#123456", 1.0), "#ffffff");
+        // NOTE: Amounts out of 0 to 1 are clamped
+        assert_eq!(lighten("#123456", 2.0), "#ffffff");
+        assert_eq!(lighten("#123456", -1.0), "#123456");
+    }
+
+    #[test]
+    fn colors_that_are_not_hexadecimal_are_kept() {
+        for color in ["red", "#12", "#12345", "#ggg", "rgb(0, 0, 0)"] {
+            assert_eq!(lighten(color, 0.5), color);
+        }
+    }
+
+    #[test]
+    fn bands_are_lighter_than_their_line() {
+        assert_eq!(band_color("#faa"), "#ffd5d5");
+        assert_eq!(band_color("#08a"), "#80c4d5");
+    }
+
+    #[test]
+    fn line_colors_are_hexadecimal_or_named() {
+        for (given, color) in [
+            ("#E00", "#e00"),
+            (" #08aBcD ", "#08abcd"),
+            ("Red", "#e00"),
+            ("gray", "#888"),
+        ] {
+            assert_eq!(
+                given.parse::<LineColor>().unwrap().as_str(),
+                color,
+                "{given}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_line_colors_are_refused() {
+        for color in [
+            "",
+            "#12345",
+            "#ggg",
+            "crimson",
+            "#e00\" onload=\"alert(1)",
+            "url(javascript:alert(1))",
+            "#e00;fill:url(#x)",
+        ] {
+            let err = color.parse::<LineColor>().unwrap_err();
+            assert!(err.starts_with(&format!("{color} is not a color")), "{err}");
+        }
+        assert!(serde_json::from_str::<LineColor>("\"<script>\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<LineColor>("\"#ABC\"").unwrap(),
+            LineColor(Cow::Borrowed("#abc"))
+        );
+    }
+
+    #[test]
+    fn every_palette_contrasts_with_the_backgrounds() {
+        let issues = validate();
+
+        assert!(
+            issues.is_empty(),
+            "{}",
+            issues
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    #[test]
+    fn high_contrast_colors_are_7_to_1_on_their_theme() {
+        let palette = PALETTES
+            .iter()
+            .find(|palette| palette.name == "high-contrast")
+            .unwrap();
+
+        let ratios = palette.ratios();
+
+        assert_eq!(ratios.len(), palette.light.len() + palette.dark.len());
+        for (theme, color, ratio) in ratios {
+            assert!(
+                ratio >= HIGH_CONTRAST_RATIO,
+                "{color} on {theme:?}: {ratio}"
+            );
+        }
+    }
+
+    #[test]
+    fn low_contrasts_are_reported() {
+        let issue = ContrastIssue {
+            palette: "default",
+            theme: Theme::Light,
+            color: "#afa",
+            ratio: 1.19,
+        };
+        assert_eq!(
+            issue.to_string(),
+            "#afa of the default palette is 1.19:1 on the light theme"
+        );
+
+        let report = palettes_report();
+        assert!(report.starts_with("default (at least 3:1)\n  light  #e00      4.53:1\n"));
+        assert!(report.contains("\nhigh-contrast (at least 7:1)\n"));
+        assert!(!report.contains("too low"), "{report}");
+    }
+}
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use log::{trace, tracing};
+
+use crate::{
+    svg::{value_to_exact_point, TimeRange},
+    ChartPoints, ChartValue,
+};
+
+// NOTE: A max moved by more than floating point noise moves the points
+const MAX_VALUE_TOLERANCE: f64 = 1e-9;
+
+/// Points of a line as last generated, with the values and scales they were computed from.
+#[derive(Debug)]
+struct CachedPolyline {
+    range: TimeRange,
+    value_range: (f64, f64),
+    values: Vec<(f64, i64)>,
+    points: Vec<(f64, f64)>,
+}
+
+impl CachedPolyline {
+    /// Whether the points were computed on the same axes, the old ones then being still valid.
+    fn same_scales(&self, value_range: (f64, f64), range: &TimeRange) -> bool {
+        let close = |cached: f64, current: f64| {
+            (cached - current).abs() <= MAX_VALUE_TOLERANCE * cached.abs().max(1.0)
+        };
+
+        self.range == *range
+            && close(self.value_range.0, value_range.0)
+            && close(self.value_range.1, value_range.1)
+    }
+}
+
+/// Polylines of the last generated charts, only the points of new values are computed when the scales did
+/// not change.
+///
+/// Owned by the actualization task, lines not generated again are dropped at the next generation.
+#[derive(Debug, Default)]
+pub struct PolylineCache {
+    entries: HashMap<(String, usize), CachedPolyline>,
+    previous: HashMap<(String, usize), CachedPolyline>,
+    /// Lines that reused at least one point of their previous generation.
+    pub hits: u64,
+    /// Lines computed from scratch.
+    pub misses: u64,
+}
+
+impl PolylineCache {
+    /// Start a new generation of the charts.
+    pub fn start_generation(&mut self) {
+        self.previous = std::mem::take(&mut self.entries);
+    }
+
+    /// Same as `values_to_polyline` for the line `idx` of the chart `id`, reusing the points of the values
+    /// it starts with in common with its previous generation.
+    #[tracing::instrument(level = "trace", skip(self, values))]
+    pub fn polyline<T: Debug>(
+        &mut self,
+        id: &str,
+        idx: usize,
+        values: &[ChartValue<T>],
+        value_range: (f64, f64),
+        range: &TimeRange,
+    ) -> Option<ChartPoints> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let key = (id.to_string(), idx);
+        let values = values
+            .iter()
+            .map(|(value, timestamp, _)| (*value, *timestamp))
+            .collect::<Vec<_>>();
+        let (mut points, reused) = match self
+            .previous
+            .remove(&key)
+            .filter(|cached| cached.same_scales(value_range, range))
+        {
+            Some(cached) => {
+                // NOTE: The last value may have changed (e.g. its bucket got a new snapshot), not only been appended
+                let reused = cached
+                    .values
+                    .iter()
+                    .zip(&values)
+                    .take_while(|(cached, current)| cached == current)
+                    .count();
+                let mut points = cached.points;
+                points.truncate(reused);
+                (points, reused)
+            }
+            None => (Vec::with_capacity(values.len()), 0),
+        };
+        if reused > 0 {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        trace!(reused, computed = values.len() - reused);
+
+        points.extend(values[reused..].iter().map(|(value, timestamp)| {
+            value_to_exact_point(*value, *timestamp, value_range, range)
+        }));
+        // NOTE: Unsimplified points are cached so the ones of the new values extend them exactly
+        let polyline = ChartPoints(points.clone()).compact();
+        self.entries.insert(
+            key,
+            CachedPolyline {
+                range: *range,
+                value_range,
+                values,
+                points,
+            },
+        );
+
+        Some(polyline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::svg::values_to_polyline;
+
+    const VALUE_RANGE: (f64, f64) = (0.0, 100.0);
+    const RANGE: TimeRange = TimeRange {
+        start: 0,
+        end: 3600,
+    };
+
+    /// Values a minute apart from the start of `RANGE`.
+    fn values(values: &[f64]) -> Vec<ChartValue<()>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| (*value, idx as i64 * 60, ()))
+            .collect()
+    }
+
+    /// Polyline of the next generation of the line `cpu` from the cache, checked against the one computed
+    /// from scratch.
+    fn generate(
+        cache: &mut PolylineCache,
+        values: &[ChartValue<()>],
+        value_range: (f64, f64),
+        range: &TimeRange,
+    ) -> Option<ChartPoints> {
+        cache.start_generation();
+        let polyline = cache.polyline("cpu", 0, values, value_range, range);
+        assert_eq!(polyline, values_to_polyline(values, value_range, range));
+        polyline
+    }
+
+    proptest! {
+        #[test]
+        fn appends_give_the_points_of_a_full_recomputation(
+            initial in prop::collection::vec(0.0..100.0, 1..30),
+            appends in prop::collection::vec(
+                (prop::collection::vec(0.0..100.0, 0..8), prop::option::of(0.0..100.0)),
+                1..8,
+            ),
+        ) {
+            let mut cache = PolylineCache::default();
+            let mut line = initial;
+            generate(&mut cache, &values(&line), VALUE_RANGE, &RANGE);
+
+            for (appended, last) in appends {
+                // NOTE: The last value changes when its bucket gets a new snapshot
+                if let Some(last) = last {
+                    *line.last_mut().unwrap() = last;
+                }
+                line.extend(appended);
+                generate(&mut cache, &values(&line), VALUE_RANGE, &RANGE);
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_lines_reuse_their_points() {
+        let mut cache = PolylineCache::default();
+        let line = values(&[10.0, 20.0, 30.0]);
+
+        generate(&mut cache, &line, VALUE_RANGE, &RANGE);
+        assert_eq!((cache.hits, cache.misses), (0, 1));
+        generate(&mut cache, &line, VALUE_RANGE, &RANGE);
+        generate(
+            &mut cache,
+            &values(&[10.0, 20.0, 30.0, 40.0]),
+            VALUE_RANGE,
+            &RANGE,
+        );
+        assert_eq!((cache.hits, cache.misses), (2, 1));
+    }
+
+    #[test]
+    fn changed_scales_recompute_every_point() {
+        let mut cache = PolylineCache::default();
+        let line = values(&[10.0, 20.0, 30.0]);
+        generate(&mut cache, &line, VALUE_RANGE, &RANGE);
+
+        generate(&mut cache, &line, (0.0, 200.0), &RANGE);
+        let moved = TimeRange {
+            start: 60,
+            end: 3660,
+        };
+        generate(&mut cache, &line, (0.0, 200.0), &moved);
+        assert_eq!((cache.hits, cache.misses), (0, 3));
+
+        // NOTE: A max moved by floating point noise only keeps the points
+        generate(&mut cache, &line, (0.0, 200.0 + 1e-12), &moved);
+        assert_eq!((cache.hits, cache.misses), (1, 3));
+    }
+
+    #[test]
+    fn changed_first_values_recompute_every_point() {
+        let mut cache = PolylineCache::default();
+        generate(
+            &mut cache,
+            &values(&[10.0, 20.0, 30.0]),
+            VALUE_RANGE,
+            &RANGE,
+        );
+
+        generate(
+            &mut cache,
+            &values(&[15.0, 20.0, 30.0]),
+            VALUE_RANGE,
+            &RANGE,
+        );
+        assert_eq!((cache.hits, cache.misses), (0, 2));
+    }
+
+    #[test]
+    fn lines_not_generated_again_are_dropped() {
+        let mut cache = PolylineCache::default();
+        let line = values(&[10.0, 20.0, 30.0]);
+        generate(&mut cache, &line, VALUE_RANGE, &RANGE);
+
+        cache.start_generation();
+        generate(&mut cache, &line, VALUE_RANGE, &RANGE);
+        assert_eq!((cache.hits, cache.misses), (0, 2));
+    }
+
+    #[test]
+    fn lines_are_cached_by_chart_and_index() {
+        let mut cache = PolylineCache::default();
+        let (first, second) = (values(&[10.0, 20.0]), values(&[80.0, 90.0]));
+        for _ in 0..2 {
+            cache.start_generation();
+            for (id, idx, line) in [("cpu", 0, &first), ("cpu", 1, &second), ("ram", 0, &second)] {
+                assert_eq!(
+                    cache.polyline(id, idx, line, VALUE_RANGE, &RANGE),
+                    values_to_polyline(line, VALUE_RANGE, &RANGE)
+                );
+            }
+        }
+
+        assert_eq!((cache.hits, cache.misses), (3, 3));
+        cache.start_generation();
+        assert_eq!(
+            cache.polyline::<()>("cpu", 0, &[], VALUE_RANGE, &RANGE),
+            None
+        );
+    }
+}
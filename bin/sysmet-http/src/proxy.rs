@@ -0,0 +1,508 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use axum::{
+    extract::{ConnectInfo, Extension, Request},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use log::{debug, tracing, tracing::Instrument};
+
+use crate::reload::SharedConfig;
+
+/// Keyword of `--trusted-proxies` trusting the connections of the unix socket, which have no address.
+const UNIX_SOCKET: &str = "unix";
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Range of addresses, e.g. `10.0.0.0/8` or `::1/128`, a lone address being its own range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // NOTE: IPv4 peers of a dual-stack socket are seen as IPv4-mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = value.split_once('/').unwrap_or((value, ""));
+        let addr = addr
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|err| format!("{value} is not an address or CIDR range: {err}"))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix.trim() {
+            "" => max_prefix,
+            prefix => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("{value} has an invalid prefix length"))?,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Peers whose `X-Forwarded-*` headers are believed, the headers of any other peer being ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    ranges: Vec<Cidr>,
+    /// Trust the connections of the unix socket.
+    unix_socket: bool,
+}
+
+impl TrustedProxies {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && !self.unix_socket
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        match peer {
+            Some(ip) => self.contains(ip),
+            None => self.unix_socket,
+        }
+    }
+}
+
+impl fmt::Display for TrustedProxies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self
+            .ranges
+            .iter()
+            .map(Cidr::to_string)
+            .chain(self.unix_socket.then(|| UNIX_SOCKET.to_string()))
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    /// Comma separated CIDR ranges and addresses, `unix` trusting the unix socket.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut proxies = Self::default();
+        for part in value
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            if part == UNIX_SOCKET {
+                proxies.unix_socket = true;
+            } else {
+                proxies.ranges.push(part.parse()?);
+            }
+        }
+
+        Ok(proxies)
+    }
+}
+
+/// Client of a request as seen by the proxies in front of the server, available to handlers as an extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// Address of the client, `None` for a direct connection to the unix socket.
+    pub ip: Option<IpAddr>,
+    /// `http` or `https`.
+    pub scheme: &'static str,
+    /// Host the client asked for, from `X-Forwarded-Host` then `Host`.
+    pub host: Option<String>,
+}
+
+impl ClientInfo {
+    /// Client of a request from `peer` (`None` for the unix socket) with the given headers.
+    pub fn resolve(proxies: &TrustedProxies, peer: Option<IpAddr>, headers: &HeaderMap) -> Self {
+        let direct = Self {
+            ip: peer,
+            scheme: "http",
+            host: first_value(headers, &axum::http::header::HOST).filter(|host| is_host(host)),
+        };
+        if !proxies.trusts(peer) {
+            return direct;
+        }
+
+        Self {
+            ip: forwarded_for(proxies, headers).or(direct.ip),
+            scheme: match first_value(headers, &X_FORWARDED_PROTO)
+                .map(|proto| proto.to_ascii_lowercase())
+                .as_deref()
+            {
+                Some("https") => "https",
+                _ => "http",
+            },
+            host: first_value(headers, &X_FORWARDED_HOST)
+                .filter(|host| is_host(host))
+                .or(direct.host),
+        }
+    }
+
+    /// Scheme and host the client reached the server with, e.g. `https://metrics.example.org`, `None` without
+    /// a host.
+    pub fn origin(&self) -> Option<String> {
+        self.host
+            .as_ref()
+            .map(|host| format!("{}://{host}", self.scheme))
+    }
+}
+
+/// First comma separated value of the first `name` header, `None` when missing, empty or not ASCII.
+fn first_value(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// Host with an optional port, rejecting anything that could smuggle a path or credentials into a URL.
+fn is_host(value: &str) -> bool {
+    value.len() <= 255
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+/// Address of a hop, with or without its port (e.g. `203.0.113.7:5123` or `[2001:db8::1]:443`).
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Rightmost hop of every `X-Forwarded-For` header that is not a trusted proxy, the leftmost hop when they
+/// all are.
+///
+/// Hops left of a malformed one were written by an unknown party, the last valid hop before it is used.
+fn forwarded_for(proxies: &TrustedProxies, headers: &HeaderMap) -> Option<IpAddr> {
+    let mut hops = headers
+        .get_all(&X_FORWARDED_FOR)
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    hops.reverse();
+
+    let mut client = None;
+    for hop in hops {
+        let Some(ip) = parse_hop(hop) else {
+            debug!("Malformed X-Forwarded-For hop {hop:?}");
+            break;
+        };
+        client = Some(ip);
+        if !proxies.contains(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Attach the `ClientInfo` of the request to it and run it in a span with the client fields.
+pub(crate) async fn forwarded(
+    Extension(shared_config): Extension<SharedConfig>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let client = ClientInfo::resolve(
+        &shared_config.load().trusted_proxies,
+        peer,
+        request.headers(),
+    );
+    let span = tracing::debug_span!(
+        "request",
+        client = ?client.ip,
+        scheme = client.scheme,
+        method = %request.method(),
+        path = request.uri().path(),
+    );
+    request.extensions_mut().insert(client);
+
+    async move {
+        let response = next.run(request).await;
+        debug!(status = response.status().as_u16(), "Answered");
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{HeaderValue, Request},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::reload::RuntimeConfig;
+
+    const PROXY: &str = "10.0.0.2";
+    const CLIENT: &str = "203.0.113.7";
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        "10.0.0.0/8, ::1, unix".parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_bytes(value.as_bytes()).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn resolve(peer: &str, pairs: &[(&str, &str)]) -> ClientInfo {
+        ClientInfo::resolve(&proxies(), Some(ip(peer)), &headers(pairs))
+    }
+
+    #[test]
+    fn trusted_proxies_are_believed() {
+        let client = resolve(
+            PROXY,
+            &[
+                ("host", "127.0.0.1:8080"),
+                ("x-forwarded-for", CLIENT),
+                ("x-forwarded-proto", "HTTPS"),
+                ("x-forwarded-host", "metrics.example.org"),
+            ],
+        );
+        assert_eq!(
+            client,
+            ClientInfo {
+                ip: Some(ip(CLIENT)),
+                scheme: "https",
+                host: Some("metrics.example.org".to_string()),
+            }
+        );
+        assert_eq!(
+            client.origin().as_deref(),
+            Some("https://metrics.example.org")
+        );
+    }
+
+    #[test]
+    fn untrusted_peers_are_not_believed() {
+        let spoofed = [
+            ("host", "metrics.example.org"),
+            ("x-forwarded-for", "10.0.0.9"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "evil.example.com"),
+        ];
+        assert_eq!(
+            resolve(CLIENT, &spoofed),
+            ClientInfo {
+                ip: Some(ip(CLIENT)),
+                scheme: "http",
+                host: Some("metrics.example.org".to_string()),
+            }
+        );
+        // NOTE: Without `unix` in the trusted proxies the connections of the unix socket are not believed either
+        let client = ClientInfo::resolve(&"10.0.0.0/8".parse().unwrap(), None, &headers(&spoofed));
+        assert_eq!(client.ip, None);
+        assert_eq!(client.scheme, "http");
+    }
+
+    #[test]
+    fn unix_socket_connections_can_be_trusted() {
+        let client = ClientInfo::resolve(
+            &proxies(),
+            None,
+            &headers(&[("x-forwarded-for", CLIENT), ("x-forwarded-proto", "https")]),
+        );
+        assert_eq!(client.ip, Some(ip(CLIENT)));
+        assert_eq!(client.scheme, "https");
+    }
+
+    #[test]
+    fn chains_stop_at_the_rightmost_untrusted_hop() {
+        // NOTE: The client can write any hop left of its own, only the proxies append reliably
+        let client = resolve(
+            PROXY,
+            &[("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.5")],
+        );
+        assert_eq!(client.ip, Some(ip(CLIENT)));
+
+        // NOTE: Repeated headers are one list, in their order
+        let client = resolve(
+            PROXY,
+            &[
+                ("x-forwarded-for", "198.51.100.1"),
+                ("x-forwarded-for", "203.0.113.7:5123, 10.0.0.5"),
+            ],
+        );
+        assert_eq!(client.ip, Some(ip(CLIENT)));
+
+        // NOTE: Only proxies, the leftmost one is the closest to the client
+        let client = resolve(PROXY, &[("x-forwarded-for", "10.1.1.1, ::1, 10.0.0.5")]);
+        assert_eq!(client.ip, Some(ip("10.1.1.1")));
+
+        let client = resolve(PROXY, &[("x-forwarded-for", "[2001:db8::1]:443")]);
+        assert_eq!(client.ip, Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn malformed_values_are_ignored() {
+        // NOTE: Hops left of a malformed one are not trusted
+        let client = resolve(
+            PROXY,
+            &[(
+                "x-forwarded-for",
+                "203.0.113.7, unknown, 198.51.100.1, 10.0.0.5",
+            )],
+        );
+        assert_eq!(client.ip, Some(ip("198.51.100.1")));
+
+        for forwarded_for in ["", "garbage", "300.1.1.1", " , "] {
+            let client = resolve(PROXY, &[("x-forwarded-for", forwarded_for)]);
+            assert_eq!(client.ip, Some(ip(PROXY)), "{forwarded_for:?}");
+        }
+        // NOTE: Not UTF-8, the peer is used
+        let client = resolve(PROXY, &[("x-forwarded-for", "203.0.113.7\u{e9}")]);
+        assert_eq!(client.ip, Some(ip(PROXY)));
+
+        let client = resolve(
+            PROXY,
+            &[
+                ("host", "metrics.example.org"),
+                ("x-forwarded-proto", "gopher"),
+                ("x-forwarded-host", "evil.example.com/path?@"),
+            ],
+        );
+        assert_eq!(client.scheme, "http");
+        assert_eq!(client.host.as_deref(), Some("metrics.example.org"));
+
+        let client = resolve(CLIENT, &[("host", "user@evil.example.com")]);
+        assert_eq!(client.host, None);
+        assert_eq!(client.origin(), None);
+    }
+
+    #[test]
+    fn ranges_contain_their_addresses() {
+        let range = "192.168.1.0/24".parse::<Cidr>().unwrap();
+        assert!(range.contains(ip("192.168.1.200")));
+        assert!(!range.contains(ip("192.168.2.1")));
+        // NOTE: IPv4 peers of a dual-stack socket
+        assert!(range.contains(ip("::ffff:192.168.1.3")));
+        assert!(!range.contains(ip("2001:db8::1")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip(CLIENT)));
+        assert!("2001:db8::/32"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(ip("2001:db8:ffff::1")));
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "localhost",
+            "10.0.0.0/x",
+        ] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn trusted_proxies_are_listed() {
+        let proxies = proxies();
+        assert_eq!(proxies.to_string(), "10.0.0.0/8,::1/128,unix");
+        assert_eq!(proxies.to_string().parse::<TrustedProxies>(), Ok(proxies));
+        assert!("".parse::<TrustedProxies>().unwrap().is_empty());
+        assert!("10.0.0.0/8,nope".parse::<TrustedProxies>().is_err());
+    }
+
+    #[tokio::test]
+    async fn handlers_get_the_client() {
+        let shared_config = SharedConfig::default();
+        shared_config.store(RuntimeConfig {
+            trusted_proxies: proxies(),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route(
+                "/origin",
+                get(|Extension(client): Extension<ClientInfo>| async move {
+                    format!("{:?} {}", client.ip, client.origin().unwrap_or_default())
+                }),
+            )
+            .layer(middleware::from_fn(forwarded))
+            .layer(Extension(shared_config));
+
+        let mut request = Request::get("/origin")
+            .header("host", "127.0.0.1:8080")
+            .header("x-forwarded-for", CLIENT)
+            .header("x-forwarded-proto", "https")
+            .header("x-forwarded-host", "metrics.example.org")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(ip(PROXY), 41000)));
+        let response = app.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            body,
+            format!("Some({CLIENT}) https://metrics.example.org").as_bytes()
+        );
+    }
+}
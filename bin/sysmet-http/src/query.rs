@@ -0,0 +1,487 @@
+//! Parsing of the query parameters shared by the pages and the API, each value that can't be used being
+//! replaced by a default and reported with a notice.
+use std::{fmt, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    api::parse_time,
+    generator::{CompareWith, CHART_GROUPS, DEFAULT_TIME_RANGE},
+    locale::{self, LocaleSpec},
+    parse_range, range_to_query,
+    svg::TimeRange,
+    units::{ChartUnits, UnitSystem},
+    Smoothing, Theme, MAX_RANGE_DAYS,
+};
+
+/// Chart groups left open by the lite view, the following ones are collapsed.
+const LITE_OPEN_GROUPS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The value was left out without changing what is shown, e.g. an unknown chart group.
+    Info,
+    /// Something else than asked is shown, e.g. the default range for an invalid `t`.
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// Query parameter that could not be used as given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Notice {
+    pub severity: Severity,
+    pub param: &'static str,
+    pub value: String,
+    pub reason: String,
+    /// What was used instead, e.g. `the default range of 3h`.
+    pub fallback: String,
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}: {}, {}.",
+            self.param, self.value, self.reason, self.fallback
+        )
+    }
+}
+
+/// Notices of a request, one per parameter that could not be used.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Notices(Vec<Notice>);
+
+impl Notices {
+    pub fn push(
+        &mut self,
+        severity: Severity,
+        param: &'static str,
+        value: &str,
+        reason: impl Into<String>,
+        fallback: impl Into<String>,
+    ) {
+        self.0.push(Notice {
+            severity,
+            param,
+            value: value.to_string(),
+            reason: reason.into(),
+            fallback: fallback.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Notice> {
+        self.0.iter()
+    }
+
+    /// Some parameter was replaced by a default, the API refusing such requests.
+    pub fn has_warnings(&self) -> bool {
+        self.iter()
+            .any(|notice| notice.severity == Severity::Warning)
+    }
+
+    /// Value of `param` read by `parse`, `None` with a warning when it can't be.
+    ///
+    /// A blank value is the same as a missing one, the empty fields of the forms being sent as such.
+    pub fn parse<T>(
+        &mut self,
+        param: &'static str,
+        value: Option<&str>,
+        parse: impl FnOnce(&str) -> Option<T>,
+        reason: &str,
+        fallback: &str,
+    ) -> Option<T> {
+        let value = value.filter(|value| !value.trim().is_empty())?;
+        let parsed = parse(value);
+        if parsed.is_none() {
+            self.push(Severity::Warning, param, value, reason, fallback);
+        }
+        parsed
+    }
+
+    /// `on` or `off` value of `param`, `None` with a warning for any other value.
+    pub fn switch(
+        &mut self,
+        param: &'static str,
+        value: Option<&str>,
+        fallback: &str,
+    ) -> Option<bool> {
+        self.parse(
+            param,
+            value,
+            |value| match value {
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => None,
+            },
+            "expected `on` or `off`",
+            fallback,
+        )
+    }
+}
+
+/// Theme of the `theme` parameter, the light one when it is missing or invalid.
+pub fn theme_param(notices: &mut Notices, value: Option<&str>) -> Theme {
+    notices
+        .parse(
+            "theme",
+            value,
+            Theme::from_query,
+            "expected `light` or `dark`",
+            "the light theme is used",
+        )
+        .unwrap_or_default()
+}
+
+/// `units` of the flags overridden by the `network_units` and `disk_units` parameters.
+pub fn units_params(
+    notices: &mut Notices,
+    network: Option<&str>,
+    disk: Option<&str>,
+    units: ChartUnits,
+) -> ChartUnits {
+    ChartUnits {
+        network: notices
+            .parse(
+                "network_units",
+                network,
+                UnitSystem::from_network_units,
+                "expected `bits` or `bytes`",
+                "the units of `--network-units` are used",
+            )
+            .unwrap_or(units.network),
+        disk: notices
+            .parse(
+                "disk_units",
+                disk,
+                UnitSystem::from_disk_units,
+                "expected `si` or `iec`",
+                "the units of `--disk-units` are used",
+            )
+            .unwrap_or(units.disk),
+    }
+}
+
+/// `locale` of `--locale` overridden by the `locale` parameter, e.g. `de` or `fr-CH`.
+pub fn locale_param(notices: &mut Notices, value: Option<&str>, locale: LocaleSpec) -> LocaleSpec {
+    notices
+        .parse(
+            "locale",
+            value,
+            LocaleSpec::from_tag,
+            &format!("expected one of {}", locale::tags()),
+            "the locale of `--locale` is used",
+        )
+        .unwrap_or(locale)
+}
+
+/// Range of the `from` and `to` parameters, or of the `t` duration ending at the newest data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeParams {
+    /// Range given by its dates, which takes precedence over `duration`.
+    pub explicit: Option<TimeRange>,
+    pub duration: Duration,
+}
+
+/// Range of the `t`, `from` and `to` parameters, an invalid date leaving both out and an invalid duration
+/// being replaced by `default`.
+pub fn range_params(
+    notices: &mut Notices,
+    t: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+    default: Duration,
+) -> RangeParams {
+    let explicit = explicit_range(
+        notices,
+        ("from", from),
+        ("to", to),
+        "the range of `t` is shown",
+    );
+    let duration = notices
+        .parse(
+            "t",
+            t.filter(|_| explicit.is_none()),
+            parse_range,
+            &format!("not a duration of at most {MAX_RANGE_DAYS} days, e.g. `1d` or `90m`"),
+            &format!("the default range of {} is shown", range_to_query(default)),
+        )
+        .unwrap_or(default);
+    if let (Some(t), Some(_)) = (t.filter(|t| !t.trim().is_empty()), explicit) {
+        notices.push(
+            Severity::Info,
+            "t",
+            t,
+            "`from` and `to` take precedence",
+            "the range of their dates is shown",
+        );
+    }
+
+    RangeParams { explicit, duration }
+}
+
+/// Range of a pair of date parameters, e.g. `from` and `to`, `None` with a warning when one is missing or
+/// can't be read or when they are not in order.
+pub fn explicit_range(
+    notices: &mut Notices,
+    (from_param, from): (&'static str, Option<&str>),
+    (to_param, to): (&'static str, Option<&str>),
+    fallback: &str,
+) -> Option<TimeRange> {
+    const NOT_A_TIME: &str = "not a RFC3339 date nor a unix timestamp";
+    let given = |value: &&str| !value.trim().is_empty();
+    match (from.filter(given), to.filter(given)) {
+        (None, None) => None,
+        (Some(value), None) => {
+            notices.push(
+                Severity::Warning,
+                from_param,
+                value,
+                format!("given without `{to_param}`"),
+                fallback,
+            );
+            None
+        }
+        (None, Some(value)) => {
+            notices.push(
+                Severity::Warning,
+                to_param,
+                value,
+                format!("given without `{from_param}`"),
+                fallback,
+            );
+            None
+        }
+        (Some(from), Some(to)) => {
+            let start = notices.parse(from_param, Some(from), parse_time, NOT_A_TIME, fallback);
+            let end = notices.parse(to_param, Some(to), parse_time, NOT_A_TIME, fallback);
+            let (start, end) = start.zip(end)?;
+            if start >= end {
+                notices.push(
+                    Severity::Warning,
+                    from_param,
+                    from,
+                    format!("not before `{to_param}`"),
+                    fallback,
+                );
+                return None;
+            }
+            Some(TimeRange {
+                start: start.timestamp(),
+                end: end.timestamp(),
+            })
+        }
+    }
+}
+
+/// State of the dashboard normalized from its query, the parameters that can't be used being replaced by
+/// their default.
+///
+/// The form, the links and the saved view are all written from it, so none of them drops a parameter the
+/// others keep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashboardState {
+    pub range: RangeParams,
+    pub refresh: bool,
+    pub compare: Option<CompareWith>,
+    /// Highlight the anomalous points, as asked: the lite view leaves them out, see `shows_anomalies`.
+    pub anomalies: bool,
+    /// Min/max bands forced on or off, `None` for the default of each chart, see `shown_bands`.
+    pub bands: Option<bool>,
+    /// Known group identifiers of `open`, every group is open without it, see `open_groups`.
+    pub open: Option<String>,
+    pub units: ChartUnits,
+    /// Numbers and dates of the page, the CSV keeping the machine format.
+    pub locale: LocaleSpec,
+    pub theme: Theme,
+    /// Slug of the chart scrolled to and highlighted.
+    pub focus: Option<String>,
+    pub smooth: Option<Smoothing>,
+    /// Y axis scaled to the max of the values, outliers included.
+    pub fullscale: bool,
+    /// Lite view with fewer points per line, for phones.
+    pub lite: bool,
+}
+
+impl Default for DashboardState {
+    fn default() -> Self {
+        Self {
+            range: RangeParams {
+                explicit: None,
+                duration: DEFAULT_TIME_RANGE,
+            },
+            refresh: false,
+            compare: None,
+            anomalies: false,
+            bands: None,
+            open: None,
+            units: ChartUnits::default(),
+            locale: LocaleSpec::default(),
+            theme: Theme::default(),
+            focus: None,
+            smooth: None,
+            fullscale: false,
+            lite: false,
+        }
+    }
+}
+
+impl DashboardState {
+    // NOTE: The anomalous points and the bands are most of the weight of a chart, the lite view drops them
+    pub fn shows_anomalies(&self) -> bool {
+        self.anomalies && !self.lite
+    }
+
+    pub fn shown_bands(&self) -> Option<bool> {
+        if self.lite {
+            Some(false)
+        } else {
+            self.bands
+        }
+    }
+
+    /// Open chart groups, the first `LITE_OPEN_GROUPS` ones in the lite view without `open`.
+    pub fn open_groups(&self) -> Option<String> {
+        self.open.clone().or_else(|| {
+            self.lite.then(|| {
+                CHART_GROUPS
+                    .iter()
+                    .take(LITE_OPEN_GROUPS)
+                    .map(|group| group.id)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+        })
+    }
+
+    /// Same state showing the `duration` ending at the newest data, e.g. for the range presets.
+    pub fn with_duration(&self, duration: Duration) -> Self {
+        Self {
+            range: RangeParams {
+                explicit: None,
+                duration,
+            },
+            ..self.clone()
+        }
+    }
+
+    /// Parameters of the state as (name, value), those at their default being left out except the range, the
+    /// units and the locale.
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        match self.range.explicit {
+            Some(range) => {
+                params.push(("from", range.start.to_string()));
+                params.push(("to", range.end.to_string()));
+            }
+            None => params.push(("t", range_to_query(self.range.duration))),
+        }
+        if self.refresh {
+            params.push(("refresh", "on".to_string()));
+        }
+        match self.compare {
+            Some(CompareWith::Offset(offset)) => params.push(("compare", range_to_query(offset))),
+            Some(CompareWith::Range(range)) => {
+                params.push(("compare_from", range.start.to_string()));
+                params.push(("compare_to", range.end.to_string()));
+            }
+            None => {}
+        }
+        if self.anomalies {
+            params.push(("anomalies", "on".to_string()));
+        }
+        match self.bands {
+            Some(true) => params.push(("bands", "on".to_string())),
+            Some(false) => params.push(("bands", "off".to_string())),
+            None => {}
+        }
+        if self.lite {
+            params.push(("lite", "on".to_string()));
+        }
+        if self.fullscale {
+            params.push(("fullscale", "on".to_string()));
+        }
+        if let Some(open) = &self.open {
+            params.push(("open", open.clone()));
+        }
+        if let Some(smooth) = self.smooth {
+            params.push(("smooth", smooth.to_query()));
+        }
+        params.push((
+            "network_units",
+            self.units.network.network_units().to_string(),
+        ));
+        params.push(("disk_units", self.units.disk.disk_units().to_string()));
+        params.push(("locale", self.locale.to_string()));
+        // NOTE: The light theme is the default one, it is left out of the links
+        if self.theme != Theme::Light {
+            params.push(("theme", self.theme.as_str().to_string()));
+        }
+        if let Some(focus) = &self.focus {
+            params.push(("focus", focus.clone()));
+        }
+        params
+    }
+
+    /// Query of the state, e.g. `t=1d&theme=dark`.
+    pub fn to_query(&self) -> String {
+        self.params()
+            .into_iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Query of the view saved by the "Save as default view" button, without explicit dates nor focused
+    /// chart: a default view showing a fixed past range would be surprising.
+    pub fn view_query(&self) -> String {
+        Self {
+            compare: self
+                .compare
+                .filter(|compare| matches!(compare, CompareWith::Offset(_))),
+            focus: None,
+            ..self.with_duration(self.range.duration)
+        }
+        .to_query()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_past_the_max_range_fall_back_to_the_default() {
+        for t in ["1000000000y", "3651d", "18446744073709551615s"] {
+            let mut notices = Notices::default();
+            let range = range_params(&mut notices, Some(t), None, None, DEFAULT_TIME_RANGE);
+
+            assert_eq!(range.duration, DEFAULT_TIME_RANGE, "{t}");
+            let notice = notices.iter().next().unwrap();
+            assert_eq!((notice.severity, notice.param), (Severity::Warning, "t"));
+            assert!(notice.reason.contains("3650 days"), "{notice}");
+        }
+    }
+
+    #[test]
+    fn the_max_range_is_accepted() {
+        let mut notices = Notices::default();
+        let range = range_params(&mut notices, Some("3650d"), None, None, DEFAULT_TIME_RANGE);
+
+        assert_eq!(range.duration, crate::MAX_RANGE);
+        assert!(notices.is_empty());
+    }
+}
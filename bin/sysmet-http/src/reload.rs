@@ -0,0 +1,217 @@
+//! Settings applied again on SIGHUP without dropping the listeners.
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use log::{debug, info, tracing, warn};
+use tokio::sync::Notify;
+
+use crate::{
+    locale::LocaleSpec,
+    proxy::TrustedProxies,
+    slowlog::SlowLog,
+    swap::SwapCell,
+    units::{AxisScale, ChartUnits},
+    Result,
+};
+
+/// Settings read by the requests and the actualization task each time they are used, so a reload applies
+/// to the next ones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuntimeConfig {
+    pub max_memory_points: usize,
+    pub units: ChartUnits,
+    /// Numbers and dates of the pages, `--locale`.
+    pub locale: LocaleSpec,
+    /// Scale of the y axis of the lines charts, `--outlier-factor`.
+    pub scale: AxisScale,
+    pub slow_op_threshold: Duration,
+    pub trusted_proxies: TrustedProxies,
+    /// State file of `sysmet-notify` shown in the alerting panel of the dashboard.
+    pub notify_state: Option<PathBuf>,
+}
+
+// NOTE: A request loads the config once, it never sees half of a reload
+pub(crate) type SharedConfig = Arc<SwapCell<RuntimeConfig>>;
+
+impl RuntimeConfig {
+    /// Each setting that differs in `new`, e.g. `network units: bytes -> bits`.
+    pub fn changes(&self, new: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        let mut compare = |name: &str, old: String, new: String| {
+            if old != new {
+                changes.push(format!("{name}: {old} -> {new}"));
+            }
+        };
+        compare(
+            "max memory points",
+            self.max_memory_points.to_string(),
+            new.max_memory_points.to_string(),
+        );
+        compare(
+            "network units",
+            self.units.network.network_units().to_string(),
+            new.units.network.network_units().to_string(),
+        );
+        compare(
+            "disk units",
+            self.units.disk.disk_units().to_string(),
+            new.units.disk.disk_units().to_string(),
+        );
+        compare("locale", self.locale.to_string(), new.locale.to_string());
+        compare(
+            "outlier factor",
+            self.scale.factor().to_string(),
+            new.scale.factor().to_string(),
+        );
+        compare(
+            "slow operation threshold",
+            humantime::format_duration(self.slow_op_threshold).to_string(),
+            humantime::format_duration(new.slow_op_threshold).to_string(),
+        );
+        compare(
+            "trusted proxies",
+            self.trusted_proxies.to_string(),
+            new.trusted_proxies.to_string(),
+        );
+        let notify_state = |config: &Self| {
+            config
+                .notify_state
+                .as_ref()
+                .map_or_else(|| "none".to_string(), |path| path.display().to_string())
+        };
+        compare("notify state", notify_state(self), notify_state(new));
+
+        changes
+    }
+}
+
+/// Settings read again from the config file and the environment.
+#[derive(Debug)]
+pub struct Reloaded {
+    pub config: RuntimeConfig,
+    /// Settings that changed but are only read at startup, e.g. `listen addresses`.
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Read the settings again, given by the binary as it knows where they come from.
+pub type Reloader = Box<dyn Fn() -> Result<Reloaded> + Send + Sync>;
+
+/// Swap the runtime config for the reloaded one and log what changed.
+#[tracing::instrument(skip(shared_config, slow_log))]
+pub(crate) fn apply(shared_config: &SharedConfig, slow_log: &SlowLog, reloaded: Reloaded) {
+    let changes = shared_config.load().changes(&reloaded.config);
+    slow_log.set_threshold(reloaded.config.slow_op_threshold);
+    shared_config.store(reloaded.config);
+
+    if changes.is_empty() {
+        info!("Reloaded the configuration, nothing changed");
+    } else {
+        info!("Reloaded the configuration: {}", changes.join(", "));
+    }
+    if !reloaded.restart_required.is_empty() {
+        warn!(
+            "Changes of {} require a restart, the previous values are kept",
+            reloaded.restart_required.join(", ")
+        );
+    }
+}
+
+/// Reload the settings and apply them, returns whether they were, the current ones are kept on an error.
+pub(crate) fn reload(
+    reloader: &Reloader,
+    shared_config: &SharedConfig,
+    slow_log: &SlowLog,
+) -> bool {
+    match reloader() {
+        Ok(reloaded) => {
+            apply(shared_config, slow_log, reloaded);
+            true
+        }
+        Err(err) => {
+            warn!("Failed to reload the configuration, keeping the current one: {err:#}");
+            false
+        }
+    }
+}
+
+/// Reload the settings on each SIGHUP and wake the actualization task up so the charts follow them.
+#[cfg(unix)]
+pub(crate) async fn reload_on_hangup(
+    reloader: Reloader,
+    shared_config: SharedConfig,
+    slow_log: Arc<SlowLog>,
+    actualize_now: Arc<Notify>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            warn!(
+                "Failed to install the SIGHUP handler, the configuration cannot be reloaded: {err}"
+            );
+            return;
+        }
+    };
+    debug!("Spawned SIGHUP handler task");
+
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the configuration");
+        if reload(&reloader, &shared_config, &slow_log) {
+            actualize_now.notify_one();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) async fn reload_on_hangup(
+    _reloader: Reloader,
+    _shared_config: SharedConfig,
+    _slow_log: Arc<SlowLog>,
+    _actualize_now: Arc<Notify>,
+) {
+    debug!("SIGHUP is only sent on unix, the configuration cannot be reloaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+
+    use super::*;
+
+    #[test]
+    fn reloads_swap_the_config_and_failed_ones_keep_it() {
+        let shared_config = SharedConfig::default();
+        let current = RuntimeConfig {
+            max_memory_points: 100,
+            slow_op_threshold: Duration::from_secs(1),
+            ..Default::default()
+        };
+        shared_config.store(current.clone());
+        let slow_log = SlowLog::new(current.slow_op_threshold);
+
+        let failing: Reloader = Box::new(|| Err(eyre!("expected `]`")));
+        assert!(!reload(&failing, &shared_config, &slow_log));
+        assert_eq!(*shared_config.load(), current);
+        assert_eq!(slow_log.threshold(), Duration::from_secs(1));
+
+        let new = RuntimeConfig {
+            max_memory_points: 200,
+            locale: "de".parse().unwrap(),
+            slow_op_threshold: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let reloaded = new.clone();
+        let reloader: Reloader = Box::new(move || {
+            Ok(Reloaded {
+                config: reloaded.clone(),
+                restart_required: vec!["listen addresses"],
+            })
+        });
+        assert!(reload(&reloader, &shared_config, &slow_log));
+        assert_eq!(*shared_config.load(), new);
+        assert_eq!(slow_log.threshold(), Duration::from_secs(2));
+
+        assert!(!reload(&failing, &shared_config, &slow_log));
+        assert_eq!(*shared_config.load(), new);
+    }
+}
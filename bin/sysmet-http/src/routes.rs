@@ -0,0 +1,633 @@
+//! Registry of the routes, both the router and `/api/routes` are built from it so they never drift apart.
+use axum::{
+    middleware,
+    routing::{get, put, MethodRouter},
+    Router,
+};
+use serde::Serialize;
+
+use crate::{admin, api, usagelog};
+
+/// Methods of the routes handled with `get`, which also answers `HEAD`.
+const GET: &[&str] = &["GET", "HEAD"];
+const ON_OFF: &[&str] = &["on", "off"];
+const THEMES: &[&str] = &["light", "dark"];
+const NETWORK_UNITS: &[&str] = &["bits", "bytes"];
+const DISK_UNITS: &[&str] = &["si", "iec"];
+const LOCALES: &[&str] = &["iso", "en", "de", "fr"];
+
+/// Middlewares wrapping a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    /// Shows the data: waits for the first load of the database and is written to the usage log.
+    Gated,
+    /// Reads the fleet databases on each request: written to the usage log without waiting for the local
+    /// database.
+    Fleet,
+    /// Always served, e.g. the health check or the styles of the warming up page.
+    Open,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamType {
+    String,
+    Integer,
+    Boolean,
+    /// Humantime duration, e.g. `1d` or `90m`.
+    Duration,
+    /// RFC3339 date or unix timestamp.
+    Time,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamLocation {
+    Path,
+    Query,
+}
+
+/// Parameter of a route as described on `/api/routes`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Param {
+    pub name: &'static str,
+    #[serde(rename = "in")]
+    pub location: ParamLocation,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    /// Value used when the parameter is missing, `None` when it depends on the flags or on other parameters.
+    pub default: Option<&'static str>,
+    /// Accepted values, any value of the type when empty.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub values: &'static [&'static str],
+    pub description: &'static str,
+}
+
+impl Param {
+    const fn new(
+        name: &'static str,
+        location: ParamLocation,
+        kind: ParamType,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            location,
+            kind,
+            default: None,
+            values: &[],
+            description,
+        }
+    }
+
+    const fn query(name: &'static str, kind: ParamType, description: &'static str) -> Self {
+        Self::new(name, ParamLocation::Query, kind, description)
+    }
+
+    const fn path(name: &'static str, description: &'static str) -> Self {
+        Self::new(name, ParamLocation::Path, ParamType::String, description)
+    }
+
+    const fn default(mut self, default: &'static str) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    const fn values(mut self, values: &'static [&'static str]) -> Self {
+        self.values = values;
+        self
+    }
+}
+
+/// Route of `sysmet-http` with the handler it is registered with.
+#[derive(Debug, Serialize)]
+pub struct Route {
+    /// Axum path template, e.g. `/download/:file`.
+    pub path: &'static str,
+    pub methods: &'static [&'static str],
+    pub group: RouteGroup,
+    /// Needs the `--admin-token` as `Authorization: Bearer <token>`, the other routes can be restricted by a
+    /// proxy doing the authentication in front of `sysmet-http`.
+    pub auth: bool,
+    pub params: &'static [Param],
+    pub description: &'static str,
+    #[serde(skip)]
+    handler: fn() -> MethodRouter,
+}
+
+const T: Param = Param::query(
+    "t",
+    ParamType::Duration,
+    "Length of the range ending at the newest snapshot.",
+)
+.default("3h");
+const FROM: Param = Param::query(
+    "from",
+    ParamType::Time,
+    "Start of an explicit range, `from` and `to` take precedence over `t`.",
+);
+const TO: Param = Param::query("to", ParamType::Time, "End of an explicit range.");
+const THEME: Param = Param::query("theme", ParamType::String, "Theme of the page.").values(THEMES);
+const NETWORK_UNITS_PARAM: Param = Param::query(
+    "network_units",
+    ParamType::String,
+    "Overrides `--network-units`.",
+)
+.values(NETWORK_UNITS);
+const DISK_UNITS_PARAM: Param =
+    Param::query("disk_units", ParamType::String, "Overrides `--disk-units`.").values(DISK_UNITS);
+const LOCALE: Param = Param::query(
+    "locale",
+    ParamType::String,
+    "Overrides `--locale` for the numbers and dates of the page.",
+)
+.values(LOCALES);
+const METRIC: Param = Param::query(
+    "metric",
+    ParamType::String,
+    "Metric combined across the hosts.",
+)
+.default("cpu");
+const HOSTS: Param = Param::query(
+    "hosts",
+    ParamType::String,
+    "`off` leaves the line of each host out, a comma separated list only reads those hosts.",
+)
+.default("on");
+const AGGREGATE_PARAMS: &[Param] = &[METRIC, T, FROM, TO, HOSTS];
+const AGGREGATE_PAGE_PARAMS: &[Param] = &[
+    METRIC,
+    T,
+    FROM,
+    TO,
+    HOSTS,
+    Param::query(
+        "overlay",
+        ParamType::String,
+        "`on` for a chart per metric of `metric`, all by default, with a line per host.",
+    )
+    .values(ON_OFF),
+    THEME,
+    LOCALE,
+    Param::query(
+        "form",
+        ParamType::String,
+        "Set by the form of the page, its unchecked checkboxes being missing from the query.",
+    ),
+];
+
+pub static ROUTES: &[Route] = &[
+    Route {
+        path: "/",
+        methods: GET,
+        group: RouteGroup::Gated,
+        auth: false,
+        params: &[
+            T,
+            FROM,
+            TO,
+            Param::query("refresh", ParamType::String, "`on` to reload the page.").values(ON_OFF),
+            Param::query(
+                "compare",
+                ParamType::Duration,
+                "Offset of the compared range, e.g. `7d`.",
+            ),
+            Param::query(
+                "compare_from",
+                ParamType::Time,
+                "Start of an explicit compared range.",
+            ),
+            Param::query(
+                "compare_to",
+                ParamType::Time,
+                "End of an explicit compared range.",
+            ),
+            Param::query(
+                "anomalies",
+                ParamType::String,
+                "Highlight the anomalous points when `on`.",
+            )
+            .values(ON_OFF),
+            Param::query(
+                "bands",
+                ParamType::String,
+                "Show or hide the min/max bands, each chart has its default.",
+            )
+            .values(ON_OFF),
+            Param::query(
+                "open",
+                ParamType::String,
+                "Comma separated identifiers of the open chart groups, e.g. `compute,network`.",
+            ),
+            NETWORK_UNITS_PARAM,
+            DISK_UNITS_PARAM,
+            LOCALE,
+            THEME,
+            Param::query(
+                "focus",
+                ParamType::String,
+                "Slug of the chart to scroll to and highlight, e.g. `load`.",
+            ),
+            Param::query(
+                "smooth",
+                ParamType::String,
+                "Moving average window, in points (e.g. `15`) or as a duration (e.g. `10m`).",
+            ),
+            Param::query(
+                "save_view",
+                ParamType::String,
+                "`on` to save the view in the cookie shown by the bare dashboard.",
+            )
+            .values(ON_OFF),
+            Param::query(
+                "reset_view",
+                ParamType::String,
+                "`on` to forget the saved view.",
+            )
+            .values(ON_OFF),
+            Param::query(
+                "format",
+                ParamType::String,
+                "`txt` for the plain text dashboard, also served for `Accept: text/plain`.",
+            )
+            .values(&["html", "txt"]),
+            Param::query(
+                "fullscale",
+                ParamType::String,
+                "`on` to scale the y axis of the charts to the max of their values.",
+            )
+            .values(ON_OFF),
+            Param::query(
+                "lite",
+                ParamType::String,
+                "`on` for the lite view, e.g. on a phone.",
+            )
+            .values(ON_OFF),
+        ],
+        description: "Dashboard of the charts.",
+        handler: || get(crate::home),
+    },
+    Route {
+        path: "/api/snapshot",
+        methods: GET,
+        group: RouteGroup::Gated,
+        auth: false,
+        params: &[
+            Param::query(
+                "at",
+                ParamType::Time,
+                "Time of the snapshot, the nearest one is returned.",
+            ),
+            Param::query(
+                "tolerance",
+                ParamType::Duration,
+                "Furthest the snapshot may be from `at`, a duration or a number of seconds.",
+            ),
+        ],
+        description: "Snapshot nearest to a time.",
+        handler: || get(api::snapshot),
+    },
+    Route {
+        path: "/api/snapshot/latest",
+        methods: GET,
+        group: RouteGroup::Gated,
+        auth: false,
+        params: &[],
+        description: "Newest snapshot.",
+        handler: || get(api::latest_snapshot),
+    },
+    Route {
+        path: "/download/:file",
+        methods: GET,
+        group: RouteGroup::Gated,
+        auth: false,
+        params: &[
+            Param::path(
+                "file",
+                "Slug of the chart followed by `.csv`, e.g. `load.csv`.",
+            ),
+            T,
+            FROM,
+            TO,
+            Param::query(
+                "raw",
+                ParamType::Boolean,
+                "Every snapshot instead of the points drawn on the chart.",
+            )
+            .default("false"),
+            NETWORK_UNITS_PARAM,
+            DISK_UNITS_PARAM,
+        ],
+        description: "CSV of a chart.",
+        handler: || get(api::download_csv),
+    },
+    Route {
+        path: "/now",
+        methods: GET,
+        group: RouteGroup::Gated,
+        auth: false,
+        params: &[Param::query(
+            "format",
+            ParamType::String,
+            "`json` for a JSON object, `key=value` lines otherwise.",
+        )
+        .values(&["json"])],
+        description: "Latest values.",
+        handler: || get(api::now),
+    },
+    Route {
+        path: "/board",
+        methods: GET,
+        group: RouteGroup::Gated,
+        auth: false,
+        params: &[
+            Param::query("cols", ParamType::Integer, "Number of columns of the grid.").default("4"),
+            Param::query(
+                "refresh",
+                ParamType::String,
+                "`off` to stop reloading the board every 30 seconds.",
+            )
+            .default("on")
+            .values(ON_OFF),
+            THEME,
+            LOCALE,
+        ],
+        description: "Status board of the current values and the sparklines of the last hour.",
+        handler: || get(crate::board),
+    },
+    Route {
+        path: "/aggregate",
+        methods: GET,
+        group: RouteGroup::Fleet,
+        auth: false,
+        params: AGGREGATE_PAGE_PARAMS,
+        description: "Chart of a metric combined across the fleet databases, or overlaid per host.",
+        handler: || get(crate::aggregate),
+    },
+    Route {
+        path: "/api/aggregate",
+        methods: GET,
+        group: RouteGroup::Fleet,
+        auth: false,
+        params: AGGREGATE_PARAMS,
+        description: "Series of a metric combined across the fleet databases.",
+        handler: || get(api::aggregate),
+    },
+    Route {
+        path: "/health",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: false,
+        params: &[],
+        description: "Health of the server and the freshness of the data.",
+        handler: || get(api::health),
+    },
+    Route {
+        path: "/stats",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: false,
+        params: &[],
+        description: "Statistics of the server, the database and the usage log.",
+        handler: || get(api::stats),
+    },
+    Route {
+        path: "/version",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: false,
+        params: &[],
+        description: "Build of the server.",
+        handler: || get(api::version),
+    },
+    Route {
+        path: "/api/schema",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: false,
+        params: &[],
+        description: "JSON Schema of each versioned payload.",
+        handler: || get(api::schema),
+    },
+    Route {
+        path: "/api/routes",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: false,
+        params: &[],
+        description: "These routes and their parameters.",
+        handler: || get(api::routes),
+    },
+    Route {
+        path: "/admin/db/download",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: true,
+        params: &[],
+        description: "Raw database file, answering 304 to an `If-None-Match` holding its `ETag`.",
+        handler: || get(admin::download),
+    },
+    Route {
+        path: "/admin/db/upload",
+        methods: &["PUT"],
+        group: RouteGroup::Open,
+        auth: true,
+        params: &[Param::query(
+            "force",
+            ParamType::Boolean,
+            "Replace the database even if the uploaded one ends before it.",
+        )
+        .default("false")],
+        description: "Replace the database with the raw file of the body, needs `--allow-upload`.",
+        handler: || put(admin::upload),
+    },
+    Route {
+        path: "/css/:path",
+        methods: GET,
+        group: RouteGroup::Open,
+        auth: false,
+        params: &[Param::path(
+            "path",
+            "Stylesheet, its name being suffixed with the hash of its contents.",
+        )],
+        description: "Stylesheets embedded in the binary.",
+        handler: || get(crate::css_assets),
+    },
+];
+
+/// Routes of `group` with their handlers.
+fn group_router(group: RouteGroup) -> Router {
+    ROUTES
+        .iter()
+        .filter(|route| route.group == group)
+        .fold(Router::new(), |router, route| {
+            router.route(route.path, (route.handler)())
+        })
+}
+
+/// Every route of `ROUTES` wrapped in the middlewares of its group.
+pub(crate) fn router() -> Router {
+    Router::new()
+        .merge(
+            group_router(RouteGroup::Gated)
+                .route_layer(middleware::from_fn(crate::readiness_gate))
+                .route_layer(middleware::from_fn(usagelog::record)),
+        )
+        .merge(group_router(RouteGroup::Fleet).route_layer(middleware::from_fn(usagelog::record)))
+        .merge(group_router(RouteGroup::Open))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+
+    use super::*;
+    use crate::{
+        testing::{Server, ADMIN_TOKEN},
+        CSS_HASHES,
+    };
+
+    /// Path of a route with its parameters filled with values it serves.
+    fn concrete_path(route: &Route) -> String {
+        let stylesheet = CSS_HASHES
+            .keys()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| "main.css".to_string());
+        route
+            .path
+            .split('/')
+            .map(|segment| match segment {
+                ":file" => "cpu.csv".to_string(),
+                ":path" => stylesheet.clone(),
+                segment => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Status and body of `method` on `path`, authenticated as the admin.
+    async fn request(server: &Server, method: &str, path: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = server.send(request).await;
+        (status, body)
+    }
+
+    /// The request reached a route rather than the fallback of the unknown pages.
+    fn is_routed(status: StatusCode, body: &str) -> bool {
+        !(status == StatusCode::NOT_FOUND && body.contains("Page not found"))
+    }
+
+    #[tokio::test]
+    async fn every_registered_route_is_served() {
+        let server = Server::new();
+        for route in ROUTES {
+            let path = concrete_path(route);
+            for method in route.methods {
+                let (status, body) = request(&server, method, &path).await;
+                assert!(is_routed(status, &body), "{method} {path}: {status}");
+                assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            }
+
+            // NOTE: The methods left out of the registry are not served either
+            let unregistered = ["GET", "PUT", "POST", "DELETE"]
+                .into_iter()
+                .filter(|method| !route.methods.contains(method));
+            for method in unregistered {
+                let (status, _) = request(&server, method, &path).await;
+                assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unregistered_paths_are_not_served() {
+        let server = Server::new();
+        let registered = ROUTES.iter().map(concrete_path).collect::<HashSet<_>>();
+        // NOTE: Prefixes and neighbours of the registered routes
+        let candidates = ROUTES
+            .iter()
+            .flat_map(|route| {
+                let path = concrete_path(route);
+                let parent = path.rsplit_once('/').map(|(parent, _)| parent.to_string());
+                [
+                    Some(format!("{path}/extra")),
+                    Some(format!("{path}x")),
+                    parent,
+                ]
+            })
+            .flatten()
+            .chain(["/api", "/admin", "/admin/db", "/index.html"].map(str::to_string))
+            .filter(|path| !path.is_empty() && !registered.contains(path));
+
+        for path in candidates {
+            let (status, body) = request(&server, "GET", &path).await;
+            // NOTE: Paths matching a parameter reach its route, which has its own answer to unknown values
+            let parameterized = ROUTES.iter().any(|route| {
+                route.path.contains("/:")
+                    && path.starts_with(route.path.split("/:").next().unwrap())
+                    && path.matches('/').count() == route.path.matches('/').count()
+            });
+            if !parameterized {
+                assert!(!is_routed(status, &body), "{path}: {status}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_are_described_once() {
+        let server = Server::new();
+        let (status, body) = request(&server, "GET", "/api/routes").await;
+        assert_eq!(status, StatusCode::OK);
+        let described = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        let paths = described
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|route| route["path"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            ROUTES.iter().map(|route| route.path).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            paths.iter().collect::<HashSet<_>>().len(),
+            paths.len(),
+            "a path is registered twice"
+        );
+
+        for route in ROUTES {
+            let mut names = route
+                .params
+                .iter()
+                .map(|param| param.name)
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            names.dedup();
+            assert_eq!(names.len(), route.params.len(), "{}", route.path);
+            for param in route
+                .params
+                .iter()
+                .filter(|param| matches!(param.location, ParamLocation::Path))
+            {
+                assert!(
+                    route.path.contains(&format!(":{}", param.name)),
+                    "{} has no :{} segment",
+                    route.path,
+                    param.name
+                );
+            }
+        }
+    }
+}
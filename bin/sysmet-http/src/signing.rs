@@ -0,0 +1,235 @@
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use color_eyre::eyre::{eyre, WrapErr};
+use log::info;
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+
+/// Block size of SHA-256, the HMAC keys are padded or hashed to it.
+const BLOCK_SIZE: usize = 64;
+/// Bytes of a generated secret.
+const GENERATED_SECRET_LEN: usize = 32;
+
+/// Key of the HMAC signing the cookies, either given with `--cookie-secret` or generated once and kept next
+/// to the database.
+#[derive(Clone)]
+pub struct CookieSecret(Vec<u8>);
+
+impl std::fmt::Debug for CookieSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CookieSecret(********)")
+    }
+}
+
+/// Generated secret of the database, next to it, e.g. `/var/lib/sysmet/db.cookie-secret` for
+/// `/var/lib/sysmet/db`.
+pub fn path_for(database: &str) -> PathBuf {
+    PathBuf::from(format!("{}.cookie-secret", database.trim_end_matches('/')))
+}
+
+impl CookieSecret {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self(secret.as_ref().to_vec())
+    }
+
+    /// Secret stored at `path`, generated and written there on the first start so the cookies outlive
+    /// restarts.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(secret) if !secret.is_empty() => return Ok(Self(secret)),
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).wrap_err_with(|| {
+                    format!("Failed to read the cookie secret {}", path.display())
+                })
+            }
+        }
+
+        let mut secret = vec![0; GENERATED_SECRET_LEN];
+        getrandom::getrandom(&mut secret)
+            .map_err(|err| eyre!("Failed to generate the cookie secret: {err}"))?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // NOTE: Anyone reading the secret can forge cookies
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(&secret))
+            .wrap_err_with(|| format!("Failed to write the cookie secret {}", path.display()))?;
+        info!(path = %path.display(), "Generated the cookie secret");
+
+        Ok(Self(secret))
+    }
+
+    /// HMAC-SHA256 of the message.
+    fn mac(&self, message: &[u8]) -> [u8; 32] {
+        let mut key = [0; BLOCK_SIZE];
+        if self.0.len() > BLOCK_SIZE {
+            key[..32].copy_from_slice(&Sha256::digest(&self.0));
+        } else {
+            key[..self.0.len()].copy_from_slice(&self.0);
+        }
+
+        let inner = Sha256::new()
+            .chain_update(key.map(|byte| byte ^ 0x36))
+            .chain_update(message)
+            .finalize();
+        Sha256::new()
+            .chain_update(key.map(|byte| byte ^ 0x5c))
+            .chain_update(inner)
+            .finalize()
+            .into()
+    }
+
+    /// Value and its signature joined by a dot, both base64 encoded so they fit in a cookie.
+    pub fn sign(&self, value: &str) -> String {
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(value),
+            URL_SAFE_NO_PAD.encode(self.mac(value.as_bytes()))
+        )
+    }
+
+    /// Value of a signed string, `None` when it was altered, signed with another secret or isn't one.
+    pub fn verify(&self, signed: &str) -> Option<String> {
+        let (value, mac) = signed.split_once('.')?;
+        let value = URL_SAFE_NO_PAD.decode(value).ok()?;
+        let mac = URL_SAFE_NO_PAD.decode(mac).ok()?;
+        // NOTE: Compared in constant time so the signature can't be guessed byte by byte
+        let expected = self.mac(&value);
+        let matches = mac.len() == expected.len()
+            && mac
+                .iter()
+                .zip(expected)
+                .fold(0, |diff, (byte, expected)| diff | (byte ^ expected))
+                == 0;
+
+        matches.then(|| String::from_utf8(value).ok()).flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn macs_match_the_rfc_4231_vectors() {
+        let long_key = [0xaa; 131];
+        let vectors: [(&[u8], &[u8], &str); 6] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &[
+                    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+                    0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19,
+                ],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // NOTE: Keys longer than a block are hashed first
+            (
+                &long_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &long_key,
+                b"This is a test using a larger than block-size key and a larger than block-size data. \
+                  The key needs to be hashed before being used by the HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+
+        for (key, message, expected) in vectors {
+            assert_eq!(hex(&CookieSecret::new(key).mac(message)), expected);
+        }
+    }
+
+    #[test]
+    fn signed_values_are_verified() {
+        let secret = CookieSecret::new("secret");
+        for value in ["", "t=1d&theme=dark", "locale=fr;é=.."] {
+            assert_eq!(secret.verify(&secret.sign(value)).as_deref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn tampered_values_are_rejected() {
+        let secret = CookieSecret::new("secret");
+        let signed = secret.sign("t=1d");
+        let (value, mac) = signed.split_once('.').unwrap();
+
+        let forged_value = URL_SAFE_NO_PAD.encode("t=30d");
+        assert_eq!(secret.verify(&format!("{forged_value}.{mac}")), None);
+        let mut flipped = URL_SAFE_NO_PAD.decode(mac).unwrap();
+        flipped[0] ^= 1;
+        let flipped = URL_SAFE_NO_PAD.encode(flipped);
+        assert_eq!(secret.verify(&format!("{value}.{flipped}")), None);
+        assert_eq!(secret.verify(&format!("{value}.{}", &mac[..10])), None);
+        assert_eq!(CookieSecret::new("other").verify(&signed), None);
+
+        for malformed in [
+            "",
+            ".",
+            "t=1d",
+            "dD0xZA",
+            "dD0xZA.",
+            "!!.!!",
+            &format!("{signed}."),
+        ] {
+            assert_eq!(secret.verify(malformed), None, "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn generated_secrets_are_kept() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = path_for(&dir.path().join("sysmet.db").to_string_lossy());
+        assert_eq!(path.file_name().unwrap(), "sysmet.db.cookie-secret");
+
+        let generated = CookieSecret::load_or_generate(&path).unwrap();
+        assert_eq!(generated.0.len(), GENERATED_SECRET_LEN);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let loaded = CookieSecret::load_or_generate(&path).unwrap();
+        assert_eq!(
+            loaded.verify(&generated.sign("t=1d")).as_deref(),
+            Some("t=1d")
+        );
+
+        // NOTE: An empty file is replaced
+        fs::write(&path, "").unwrap();
+        let regenerated = CookieSecret::load_or_generate(&path).unwrap();
+        assert_ne!(regenerated.0, generated.0);
+        assert_eq!(format!("{regenerated:?}"), "CookieSecret(********)");
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use log::{tracing, warn};
+use serde::Serialize;
+
+use crate::svg::TimeRange;
+
+/// Default duration past which an operation is kept in the slow log.
+pub const DEFAULT_SLOW_OP_THRESHOLD: Duration = Duration::from_millis(250);
+/// Slow operations kept, the oldest being dropped past it.
+const SLOW_LOG_CAPACITY: usize = 50;
+
+/// Operation that took longer than the threshold of the slow log.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowOperation {
+    /// e.g. `database load`, `chart cpu` or `render dashboard`.
+    pub name: Cow<'static, str>,
+    pub duration_ms: u64,
+    pub at: DateTime<Utc>,
+    /// Range of the charts being generated, `None` for operations that don't depend on it.
+    pub range: Option<TimeRange>,
+}
+
+/// Last operations slower than `threshold`, exposed on `/stats`.
+#[derive(Debug)]
+pub struct SlowLog {
+    /// Microseconds, changed by a reload of the configuration.
+    threshold_us: AtomicU64,
+    operations: Mutex<VecDeque<SlowOperation>>,
+}
+
+impl SlowLog {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold_us: AtomicU64::new(threshold.as_micros() as u64),
+            operations: Mutex::new(VecDeque::with_capacity(SLOW_LOG_CAPACITY)),
+        }
+    }
+
+    pub fn threshold(&self) -> Duration {
+        Duration::from_micros(self.threshold_us.load(Ordering::Relaxed))
+    }
+
+    /// Change the threshold of the next operations, the kept ones stay.
+    pub fn set_threshold(&self, threshold: Duration) {
+        self.threshold_us
+            .store(threshold.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Keep the operation when it took longer than the threshold.
+    pub fn record(
+        &self,
+        name: impl Into<Cow<'static, str>>,
+        duration: Duration,
+        range: Option<TimeRange>,
+    ) {
+        if duration <= self.threshold() {
+            return;
+        }
+
+        let operation = SlowOperation {
+            name: name.into(),
+            duration_ms: duration.as_millis() as u64,
+            at: Utc::now(),
+            range,
+        };
+        warn!("Slow {} took {}ms", operation.name, operation.duration_ms);
+        let mut operations = self.operations.lock().unwrap();
+        if operations.len() == SLOW_LOG_CAPACITY {
+            operations.pop_front();
+        }
+        operations.push_back(operation);
+    }
+
+    /// Slow operations from the oldest to the newest.
+    pub fn operations(&self) -> Vec<SlowOperation> {
+        self.operations.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_OP_THRESHOLD)
+    }
+}
+
+pub type SharedSlowLog = Arc<SlowLog>;
+
+/// Run `op` in a span named after it with how long it took, also kept in `slow_log` when slow.
+pub fn timed<T>(
+    slow_log: Option<&SlowLog>,
+    name: impl Into<Cow<'static, str>>,
+    range: Option<TimeRange>,
+    op: impl FnOnce() -> T,
+) -> T {
+    let name = name.into();
+    let span = tracing::debug_span!("timed", op = %name, duration_ms = tracing::field::Empty);
+    let _entered = span.enter();
+    let started = Instant::now();
+    let result = op();
+    let duration = started.elapsed();
+    span.record("duration_ms", duration.as_millis() as u64);
+    if let Some(slow_log) = slow_log {
+        slow_log.record(name, duration, range);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Operation taking at least `duration`, e.g. a slow read of the series.
+    fn slow_op(duration: Duration) -> impl FnOnce() -> u32 {
+        move || {
+            std::thread::sleep(duration);
+            42
+        }
+    }
+
+    #[test]
+    fn slow_operations_are_kept_with_their_name_and_range() {
+        let slow_log = SlowLog::new(Duration::from_millis(5));
+        let range = TimeRange::ending_at(1_700_000_000, Duration::from_secs(60 * 60));
+
+        let before = Utc::now();
+        let result = timed(
+            Some(&slow_log),
+            "series extraction",
+            Some(range),
+            slow_op(Duration::from_millis(20)),
+        );
+
+        assert_eq!(result, 42);
+        let operations = slow_log.operations();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].name, "series extraction");
+        assert!(operations[0].duration_ms >= 20);
+        assert!(before <= operations[0].at && operations[0].at <= Utc::now());
+        assert_eq!(operations[0].range, Some(range));
+    }
+
+    #[test]
+    fn fast_operations_are_not_kept() {
+        let slow_log = SlowLog::new(Duration::from_secs(60));
+
+        timed(Some(&slow_log), "chart cpu", None, || ());
+        slow_log.record("database load", Duration::from_secs(60), None);
+
+        assert!(slow_log.operations().is_empty());
+        assert_eq!(timed(None, "render board", None, || 42), 42);
+    }
+
+    #[test]
+    fn thresholds_apply_to_the_next_operations() {
+        let slow_log = SlowLog::new(Duration::from_millis(100));
+        slow_log.record("chart cpu", Duration::from_millis(200), None);
+
+        slow_log.set_threshold(Duration::from_millis(500));
+        slow_log.record("chart ram", Duration::from_millis(200), None);
+
+        assert_eq!(slow_log.threshold(), Duration::from_millis(500));
+        let names = slow_log
+            .operations()
+            .into_iter()
+            .map(|operation| operation.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["chart cpu"]);
+    }
+
+    #[test]
+    fn oldest_operations_are_dropped_past_the_capacity() {
+        let slow_log = SlowLog::new(Duration::ZERO);
+
+        for idx in 0..SLOW_LOG_CAPACITY + 5 {
+            slow_log.record(format!("chart {idx}"), Duration::from_millis(1), None);
+        }
+
+        let operations = slow_log.operations();
+        assert_eq!(operations.len(), SLOW_LOG_CAPACITY);
+        assert_eq!(operations[0].name, "chart 5");
+        assert_eq!(
+            operations.last().unwrap().name,
+            format!("chart {}", SLOW_LOG_CAPACITY + 4)
+        );
+    }
+}
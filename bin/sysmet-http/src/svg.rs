@@ -1,8 +1,10 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, ops::Range, time::Duration};
 
+use chrono::{DateTime, TimeZone, Utc};
 use log::{trace, tracing};
+use serde::{Deserialize, Serialize};
 
-use crate::ChartValue;
+use crate::{ChartPoints, ChartValue};
 
 pub(crate) const SVG_MIN_X: f64 = 0.0;
 pub(crate) const SVG_MAX_X: f64 = 1000.0;
@@ -21,59 +23,663 @@ pub(crate) const CHART_Y_RATIO: f64 = CHART_MAX_Y - CHART_MIN_Y;
 
 pub(crate) const LABELS_OFFSET: f64 = CHART_MIN_X - (ESTIMATED_ONE_CHAR_SIZE * 0.5);
 
+/// Max distance in SVG units of a dropped point to the segment drawn instead of it, a quarter of the line width.
+pub(crate) const POINT_TOLERANCE: f64 = 0.5;
+// NOTE: Past it the rounding to whole units is hidden by the neighbouring points
+const DENSE_POINTS_PER_UNIT: f64 = 2.0;
+
+/// Requested time range as unix timestamps, the x axis always spans the whole range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl TimeRange {
+    /// Range of `duration` up to `end`, starting at the oldest representable date at most.
+    pub fn ending_at(end: i64, duration: Duration) -> Self {
+        let oldest = DateTime::<Utc>::MIN_UTC.timestamp();
+        let start = i64::try_from(duration.as_secs())
+            .ok()
+            .and_then(|duration| end.checked_sub(duration))
+            .map_or(oldest, |start| start.max(oldest));
+        Self { start, end }
+    }
+
+    /// Same range moved back by `offset`, starting at the oldest representable date at most.
+    pub fn shifted_back(&self, offset: Duration) -> Self {
+        let start = Self::ending_at(self.start, offset).start;
+        Self {
+            start,
+            end: start.saturating_add(self.duration()),
+        }
+    }
+
+    pub fn duration(&self) -> i64 {
+        self.end - self.start
+    }
+
+    /// Dates of the range, clamped to the representable ones.
+    pub fn dates(&self) -> Range<DateTime<Utc>> {
+        timestamp_to_date(self.start)..timestamp_to_date(self.end)
+    }
+}
+
+/// Date of a unix timestamp, clamped to the representable ones.
+pub fn timestamp_to_date(timestamp: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .unwrap_or(if timestamp < 0 {
+            DateTime::<Utc>::MIN_UTC
+        } else {
+            DateTime::<Utc>::MAX_UTC
+        })
+}
+
+#[tracing::instrument(level = "trace")]
+pub fn timestamp_to_x(timestamp: i64, range: &TimeRange) -> f64 {
+    let result = timestamp_to_exact_x(timestamp, range).round();
+    trace!(result);
+    result
+}
+
+/// Same as `timestamp_to_x` without rounding.
+fn timestamp_to_exact_x(timestamp: i64, range: &TimeRange) -> f64 {
+    // NOTE: An empty range would divide by zero
+    let range_duration = range.duration().max(1) as f64;
+    (timestamp - range.start) as f64 / range_duration * CHART_X_RATIO + CHART_MIN_X
+}
+
 // Because the viewBox in SVG invert the values (top left corner is 0,0)
 #[tracing::instrument(level = "trace")]
 pub fn svg_value_invert(value: f64, max: f64, min: f64) -> f64 {
-    let result = ((value - min) + (max - min) * -1f64) * -1f64 + min;
+    let result = -((value - min) - (max - min)) + min;
     trace!(result);
     result
 }
 
+/// Coordinates of a value in the chart scaled to the value range, rounded to whole SVG units.
+pub fn value_to_point(
+    value: f64,
+    timestamp: i64,
+    value_range: (f64, f64),
+    range: &TimeRange,
+) -> (f64, f64) {
+    let (x, y) = value_to_exact_point(value, timestamp, value_range, range);
+    (x.round(), y.round())
+}
+
+/// Same as `value_to_point` without rounding, for the points of the lines rounded by `ChartPoints::compact`.
+pub fn value_to_exact_point(
+    value: f64,
+    timestamp: i64,
+    (min_value_range, max_value_range): (f64, f64),
+    range: &TimeRange,
+) -> (f64, f64) {
+    // NOTE: A flat series at the bottom would divide by zero
+    let value_ratio = match max_value_range - min_value_range {
+        ratio if ratio > 0.0 => ratio,
+        _ => 1.0,
+    };
+
+    (
+        timestamp_to_exact_x(timestamp, range),
+        svg_value_invert(
+            (value - min_value_range) / value_ratio * CHART_Y_RATIO + CHART_MIN_Y,
+            CHART_MAX_Y,
+            CHART_MIN_Y,
+        ),
+    )
+}
+
+/// Decimals kept in the coordinates of a line of `points` points, whole units when they are dense enough to
+/// hide the rounding, tenths otherwise so the curves of short ranges aren't quantized.
+pub fn coordinate_decimals(points: usize) -> usize {
+    if points as f64 / CHART_X_RATIO > DENSE_POINTS_PER_UNIT {
+        0
+    } else {
+        1
+    }
+}
+
+/// Points left after dropping those within `tolerance` of the segment joining the kept points around them
+/// (Ramer–Douglas–Peucker), the first and last points being always kept.
+#[tracing::instrument(level = "trace", skip(points))]
+pub fn simplify_points(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let distance = |(x, y): (f64, f64), (x1, y1): (f64, f64), (x2, y2): (f64, f64)| {
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let length = dx.hypot(dy);
+        if length == 0.0 {
+            (x - x1).hypot(y - y1)
+        } else {
+            ((x - x1) * dy - (y - y1) * dx).abs() / length
+        }
+    };
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    // NOTE: Walked with a stack rather than recursively so long lines can't overflow it
+    let mut segments = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = segments.pop() {
+        let farthest = (first + 1..last)
+            .map(|idx| (idx, distance(points[idx], points[first], points[last])))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((idx, _)) = farthest.filter(|(_, distance)| *distance > tolerance) {
+            keep[idx] = true;
+            segments.push((first, idx));
+            segments.push((idx, last));
+        }
+    }
+
+    let result = points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect::<Vec<_>>();
+    trace!(before = points.len(), after = result.len());
+    result
+}
+
 #[tracing::instrument(level = "trace", skip(raw_values))]
 pub fn values_to_polyline<T: Debug>(
     raw_values: &[ChartValue<T>],
     (min_value_range, max_value_range): (f64, f64),
-) -> Option<String> {
+    range: &TimeRange,
+) -> Option<ChartPoints> {
     if raw_values.is_empty() {
         return None;
     };
 
-    let value_ratio = max_value_range - min_value_range;
+    let points = raw_values
+        .iter()
+        .map(|(val, date, _)| {
+            value_to_exact_point(*val, *date, (min_value_range, max_value_range), range)
+        })
+        .collect::<Vec<_>>();
+    trace!(?points);
 
-    let first_date = raw_values.first().map(|(_, date, _)| date).unwrap();
-    let last_date = raw_values.last().map(|(_, date, _)| date).unwrap();
-    let date_ratio = (last_date - first_date) as f64;
-    trace!(
-        first_date_timestamp = first_date,
-        last_date_timestamp = last_date
-    );
+    Some(ChartPoints(points).compact())
+}
 
-    let values = raw_values
+/// Cumulative sums of the series in stacking order, all sampled on the union of their timestamps.
+///
+/// A series without a value at one of the timestamps contributes nothing to it.
+#[tracing::instrument(level = "trace", skip(series))]
+pub fn stack_values<T: Debug>(series: &[&[ChartValue<T>]]) -> Vec<Vec<(f64, i64)>> {
+    let mut timestamps = series
         .iter()
-        .map(|(val, date, _)| {
-            trace!(
-                date,
-                first_date,
-                top = date - first_date,
-                bottom = date_ratio * CHART_X_RATIO + CHART_MIN_X
-            );
-            format!(
-                "{},{}",
-                ((date - first_date) as f64 / date_ratio * CHART_X_RATIO + CHART_MIN_X).round(),
-                svg_value_invert(
-                    ((val - min_value_range) / value_ratio * CHART_Y_RATIO + CHART_MIN_Y).round(),
-                    CHART_MAX_Y,
-                    CHART_MIN_Y
-                ),
-            )
+        .flat_map(|values| values.iter().map(|(_, date, _)| *date))
+        .collect::<Vec<_>>();
+    timestamps.sort_unstable();
+    timestamps.dedup();
+
+    let mut baseline = vec![0f64; timestamps.len()];
+    series
+        .iter()
+        .map(|values| {
+            let mut values = values.iter().peekable();
+            timestamps
+                .iter()
+                .zip(baseline.iter_mut())
+                .map(|(timestamp, cumulated)| {
+                    // NOTE: Both are sorted so the series is walked only once
+                    while values.next_if(|(_, date, _)| date < timestamp).is_some() {}
+                    if let Some((value, _, _)) = values.next_if(|(_, date, _)| date == timestamp) {
+                        *cumulated += value;
+                    }
+                    (*cumulated, *timestamp)
+                })
+                .collect()
         })
-        .collect::<Vec<_>>()
-        .join(" ");
-    trace!(svg_values = values);
+        .collect()
+}
+
+/// Series sampled on the union of their timestamps, so lines of the same chart have points at the same x.
+///
+/// Values missing between the first and last ones of a series are linearly interpolated (without min/max
+/// spread), before and after them the series has no point and the line leaves a gap.
+#[tracing::instrument(level = "trace", skip(series))]
+pub fn align_values(series: &[&[ChartValue<(f64, f64)>]]) -> Vec<Vec<ChartValue<(f64, f64)>>> {
+    let mut timestamps = series
+        .iter()
+        .flat_map(|values| values.iter().map(|(_, date, _)| *date))
+        .collect::<Vec<_>>();
+    timestamps.sort_unstable();
+    timestamps.dedup();
+
+    series
+        .iter()
+        .map(|values| {
+            let (Some((_, first, _)), Some((_, last, _))) = (values.first(), values.last()) else {
+                return Vec::new();
+            };
+            let (first, last) = (*first, *last);
+            let mut result = Vec::with_capacity(timestamps.len());
+            let mut next = 0;
+            for timestamp in timestamps
+                .iter()
+                .filter(|date| (first..=last).contains(*date))
+            {
+                // NOTE: Both are sorted so the series is walked only once
+                while values[next].1 < *timestamp {
+                    next += 1;
+                }
+                let (value, date, extremes) = values[next];
+                if date == *timestamp {
+                    result.push((value, date, extremes));
+                } else {
+                    let (previous_value, previous_date, _) = values[next - 1];
+                    let progress =
+                        (timestamp - previous_date) as f64 / (date - previous_date) as f64;
+                    let interpolated = previous_value + (value - previous_value) * progress;
+                    result.push((interpolated, *timestamp, (interpolated, interpolated)));
+                }
+            }
+            result
+        })
+        .collect()
+}
+
+/// Outline of the area going along `top` then back along `baseline`, both being sampled on the same
+/// timestamps.
+#[tracing::instrument(level = "trace", skip(top, baseline))]
+pub fn values_to_area_points(
+    top: &[(f64, i64)],
+    baseline: Option<&[(f64, i64)]>,
+    (min_value_range, max_value_range): (f64, f64),
+    range: &TimeRange,
+) -> Option<ChartPoints> {
+    if top.is_empty() {
+        return None;
+    }
+
+    let to_point = |(value, date): &(f64, i64)| {
+        value_to_exact_point(*value, *date, (min_value_range, max_value_range), range)
+    };
+
+    let mut points = top.iter().map(to_point).collect::<Vec<_>>();
+    match baseline {
+        Some(baseline) => points.extend(baseline.iter().rev().map(to_point)),
+        // NOTE: The first series of the stack starts from the bottom of the chart
+        None => points.extend(
+            [top.last().unwrap(), top.first().unwrap()]
+                .iter()
+                .map(|(_, date)| to_point(&(min_value_range, *date))),
+        ),
+    }
+    trace!(?points);
+
+    Some(ChartPoints(points).compact())
+}
 
-    Some(values)
+/// Closed path going along `top` then back along `baseline`, both being sampled on the same timestamps.
+pub fn values_to_area_path(
+    top: &[(f64, i64)],
+    baseline: Option<&[(f64, i64)]>,
+    value_range: (f64, f64),
+    range: &TimeRange,
+) -> Option<String> {
+    values_to_area_points(top, baseline, value_range, range).map(|points| points.to_path())
+}
+
+/// Closed path going along the max of each value then back along its min, `None` when every min is its max.
+#[tracing::instrument(level = "trace", skip(values))]
+pub fn values_to_band_path(
+    values: &[ChartValue<(f64, f64)>],
+    (min_value_range, max_value_range): (f64, f64),
+    range: &TimeRange,
+) -> Option<String> {
+    // NOTE: A band without width would only be drawn over by its line
+    if values.iter().all(|(_, _, (min, max))| min >= max) {
+        return None;
+    }
+
+    let top = values
+        .iter()
+        .map(|(_, date, (_, max))| (*max, *date))
+        .collect::<Vec<_>>();
+    let bottom = values
+        .iter()
+        .map(|(_, date, (min, _))| (*min, *date))
+        .collect::<Vec<_>>();
+
+    values_to_area_path(
+        &top,
+        Some(&bottom),
+        (min_value_range, max_value_range),
+        range,
+    )
 }
 
 pub fn round_to_len(value: f64, len: usize) -> f64 {
     (value * 10f64.powi(len as i32)).round() / 10f64.powi(len as i32)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ending_at_stops_at_the_oldest_date() {
+        let oldest = DateTime::<Utc>::MIN_UTC.timestamp();
+        for duration in [Duration::MAX, Duration::from_secs(i64::MAX as u64 + 1)] {
+            let range = TimeRange::ending_at(1_700_000_000, duration);
+            assert_eq!(range.start, oldest);
+            assert_eq!(range.duration(), 1_700_000_000 - oldest);
+        }
+        let range = TimeRange::ending_at(oldest + 10, Duration::from_secs(60));
+        assert_eq!(range.start, oldest);
+    }
+
+    #[test]
+    fn shifted_back_keeps_the_duration() {
+        let range = TimeRange::ending_at(1_700_000_000, Duration::from_secs(3600));
+        let shifted = range.shifted_back(Duration::from_secs(7 * 24 * 3600));
+        assert_eq!(shifted.end, 1_700_000_000 - 7 * 24 * 3600);
+        assert_eq!(shifted.duration(), 3600);
+
+        let oldest = DateTime::<Utc>::MIN_UTC.timestamp();
+        let shifted = range.shifted_back(Duration::MAX);
+        assert_eq!((shifted.start, shifted.duration()), (oldest, 3600));
+    }
+
+    #[test]
+    fn timestamps_out_of_the_dates_are_clamped() {
+        assert_eq!(timestamp_to_date(i64::MIN), DateTime::<Utc>::MIN_UTC);
+        assert_eq!(timestamp_to_date(i64::MAX), DateTime::<Utc>::MAX_UTC);
+        assert_eq!(timestamp_to_date(0), DateTime::<Utc>::UNIX_EPOCH);
+
+        let range = TimeRange {
+            start: i64::MIN,
+            end: i64::MAX,
+        };
+        assert_eq!(
+            range.dates(),
+            DateTime::<Utc>::MIN_UTC..DateTime::<Utc>::MAX_UTC
+        );
+    }
+
+    #[test]
+    fn ending_at_spans_the_duration() {
+        let range = TimeRange::ending_at(1_700_000_000, Duration::from_secs(3600));
+        assert_eq!(range.start, 1_700_000_000 - 3600);
+        assert_eq!(range.duration(), 3600);
+    }
+
+    fn values(values: &[(f64, i64)]) -> Vec<ChartValue<()>> {
+        values
+            .iter()
+            .map(|(value, date)| (*value, *date, ()))
+            .collect()
+    }
+
+    #[test]
+    fn stacks_are_cumulative_sums() {
+        let first = values(&[(1.0, 0), (2.0, 10), (3.0, 20)]);
+        let second = values(&[(10.0, 0), (20.0, 10), (30.0, 20)]);
+
+        let stacked = stack_values(&[&first, &second]);
+
+        assert_eq!(
+            stacked,
+            vec![
+                vec![(1.0, 0), (2.0, 10), (3.0, 20)],
+                vec![(11.0, 0), (22.0, 10), (33.0, 20)],
+            ]
+        );
+    }
+
+    #[test]
+    fn stacks_are_sampled_on_every_timestamp() {
+        // NOTE: The first series misses a point in the middle, the second starts later and ends earlier
+        let first = values(&[(1.0, 0), (3.0, 20), (4.0, 30)]);
+        let second = values(&[(10.0, 10), (20.0, 20)]);
+        let empty = values(&[]);
+
+        let stacked = stack_values(&[&first, &empty, &second]);
+
+        assert_eq!(
+            stacked,
+            vec![
+                vec![(1.0, 0), (0.0, 10), (3.0, 20), (4.0, 30)],
+                vec![(1.0, 0), (0.0, 10), (3.0, 20), (4.0, 30)],
+                vec![(1.0, 0), (10.0, 10), (23.0, 20), (4.0, 30)],
+            ]
+        );
+        assert!(stacked.windows(2).all(|layers| layers[0]
+            .iter()
+            .zip(&layers[1])
+            .all(|(below, above)| below.0 <= above.0)));
+    }
+
+    #[test]
+    fn aligned_series_are_interpolated_between_their_ends() {
+        let first = [(0.0, 0, (0.0, 0.0)), (4.0, 40, (3.0, 5.0))];
+        let second = [(1.0, 10, (1.0, 1.0)), (3.0, 30, (3.0, 3.0))];
+
+        let aligned = align_values(&[&first, &second]);
+
+        assert_eq!(
+            aligned[0],
+            vec![
+                (0.0, 0, (0.0, 0.0)),
+                (1.0, 10, (1.0, 1.0)),
+                (3.0, 30, (3.0, 3.0)),
+                (4.0, 40, (3.0, 5.0)),
+            ]
+        );
+        assert_eq!(aligned[1], second);
+    }
+
+    #[test]
+    fn areas_go_back_along_their_baseline() {
+        let range = TimeRange { start: 0, end: 40 };
+        let point =
+            |(value, date): (f64, i64)| value_to_exact_point(value, date, (0.0, 100.0), &range);
+        let baseline = [(10.0, 0), (30.0, 20), (20.0, 40)];
+        let top = [(50.0, 0), (90.0, 20), (60.0, 40)];
+
+        let area = values_to_area_points(&top, Some(&baseline), (0.0, 100.0), &range).unwrap();
+
+        let expected = top
+            .iter()
+            .chain(baseline.iter().rev())
+            .map(|value| point(*value))
+            .collect();
+        assert_eq!(area, ChartPoints(expected).compact());
+    }
+
+    #[test]
+    fn bottom_areas_go_down_to_the_bottom_of_the_chart() {
+        let range = TimeRange { start: 0, end: 40 };
+        let point =
+            |(value, date): (f64, i64)| value_to_exact_point(value, date, (0.0, 100.0), &range);
+        let top = [(50.0, 0), (90.0, 20), (60.0, 40)];
+
+        let area = values_to_area_points(&top, None, (0.0, 100.0), &range).unwrap();
+
+        let expected = [(50.0, 0), (90.0, 20), (60.0, 40), (0.0, 40), (0.0, 0)]
+            .into_iter()
+            .map(point)
+            .collect();
+        assert_eq!(area, ChartPoints(expected).compact());
+        assert!(values_to_area_path(&top, None, (0.0, 100.0), &range)
+            .unwrap()
+            .ends_with(" Z"));
+        assert_eq!(values_to_area_path(&[], None, (0.0, 100.0), &range), None);
+    }
+
+    /// Whether every point of the path `path` is within the area of the chart.
+    fn within_the_chart(path: &str) -> bool {
+        ChartPoints::parse(path).unwrap().0.iter().all(|(x, y)| {
+            (CHART_MIN_X..=CHART_MAX_X).contains(x) && (CHART_MIN_Y..=CHART_MAX_Y).contains(y)
+        })
+    }
+
+    #[test]
+    fn bands_go_along_the_max_then_back_along_the_min() {
+        let range = TimeRange { start: 0, end: 40 };
+        let values = [
+            (20.0, 0, (10.0, 50.0)),
+            (60.0, 20, (30.0, 100.0)),
+            (20.0, 40, (0.0, 60.0)),
+        ];
+
+        let band = values_to_band_path(&values, (0.0, 100.0), &range).unwrap();
+
+        let top = [(50.0, 0), (100.0, 20), (60.0, 40)];
+        let bottom = [(10.0, 0), (30.0, 20), (0.0, 40)];
+        assert_eq!(
+            Some(band.clone()),
+            values_to_area_path(&top, Some(&bottom), (0.0, 100.0), &range)
+        );
+        assert!(within_the_chart(&band), "{band}");
+    }
+
+    #[test]
+    fn bands_stay_within_the_chart() {
+        let range = TimeRange {
+            start: 0,
+            end: 3600,
+        };
+        // NOTE: Spikes up to the max of the axis and dips down to 0 on every other point
+        let values = (0..=60)
+            .map(|idx| {
+                let value = 50.0 + (idx % 7) as f64;
+                let extremes = if idx % 2 == 0 {
+                    (0.0, 100.0)
+                } else {
+                    (value, value)
+                };
+                (value, idx * 60, extremes)
+            })
+            .collect::<Vec<_>>();
+
+        let band = values_to_band_path(&values, (0.0, 100.0), &range).unwrap();
+
+        assert!(within_the_chart(&band), "{band}");
+    }
+
+    #[test]
+    fn bands_without_width_are_not_drawn() {
+        let range = TimeRange { start: 0, end: 40 };
+        let flat = [(20.0, 0, (20.0, 20.0)), (30.0, 20, (30.0, 30.0))];
+
+        assert_eq!(values_to_band_path(&flat, (0.0, 100.0), &range), None);
+        assert_eq!(values_to_band_path(&[], (0.0, 100.0), &range), None);
+        // NOTE: Buckets without width are drawn as a point of the band
+        let single = [(20.0, 0, (20.0, 20.0)), (30.0, 20, (25.0, 35.0))];
+        let band = values_to_band_path(&single, (0.0, 100.0), &range).unwrap();
+        assert!(within_the_chart(&band), "{band}");
+        // NOTE: A flat series at the bottom of the chart
+        assert_eq!(values_to_band_path(&flat[..1], (0.0, 0.0), &range), None);
+    }
+
+    /// Distance from a point to the polyline drawn through `line`, what the eye sees of a dropped point.
+    fn distance_to_line((x, y): (f64, f64), line: &[(f64, f64)]) -> f64 {
+        line.windows(2)
+            .map(|segment| {
+                let ((x1, y1), (x2, y2)) = (segment[0], segment[1]);
+                let (dx, dy) = (x2 - x1, y2 - y1);
+                let along = (((x - x1) * dx + (y - y1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+                (x - (x1 + along * dx)).hypot(y - (y1 + along * dy))
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Points dense enough to be rounded to whole units, a slow wave with a deterministic noise on it.
+    fn noisy_line() -> Vec<(f64, f64)> {
+        let range = TimeRange {
+            start: 0,
+            end: 1999 * 60,
+        };
+        (0..2000)
+            .map(|idx| {
+                let noise = ((idx * 7919) % 13) as f64 - 6.0;
+                let value = 50.0 + 30.0 * (idx as f64 / 200.0).sin() + noise;
+                value_to_exact_point(value, idx * 60, (0.0, 100.0), &range)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simplified_lines_keep_their_ends_and_corners() {
+        let points = [
+            (0.0, 10.0),
+            (1.0, 10.0),
+            (2.0, 10.2),
+            (3.0, 10.0),
+            (4.0, 20.0),
+            (5.0, 20.0),
+        ];
+
+        let simplified = simplify_points(&points, POINT_TOLERANCE);
+
+        assert_eq!(
+            simplified,
+            vec![(0.0, 10.0), (3.0, 10.0), (4.0, 20.0), (5.0, 20.0)]
+        );
+        assert_eq!(simplify_points(&points[..2], POINT_TOLERANCE), points[..2]);
+        assert_eq!(simplify_points(&[], POINT_TOLERANCE), vec![]);
+    }
+
+    #[test]
+    fn simplified_lines_stay_within_the_tolerance() {
+        let points = noisy_line();
+
+        let simplified = simplify_points(&points, POINT_TOLERANCE);
+
+        assert!(simplified.len() < points.len());
+        for point in points {
+            let deviation = distance_to_line(point, &simplified);
+            assert!(
+                deviation <= POINT_TOLERANCE,
+                "{point:?} is {deviation} away"
+            );
+        }
+    }
+
+    #[test]
+    fn compact_lines_stay_within_the_tolerance_and_rounding() {
+        let points = noisy_line();
+
+        let compact = ChartPoints(points.clone()).compact();
+
+        // NOTE: Dense lines are rounded to whole units, moving each point at most half a unit on both axes
+        let rounding = 0.5f64.hypot(0.5);
+        assert!(compact
+            .0
+            .iter()
+            .all(|(x, y)| x.fract() == 0.0 && y.fract() == 0.0));
+        for point in points {
+            let deviation = distance_to_line(point, &compact.0);
+            assert!(
+                deviation <= POINT_TOLERANCE + rounding,
+                "{point:?} is {deviation} away"
+            );
+        }
+    }
+
+    #[test]
+    fn coordinates_are_whole_units_only_for_dense_lines() {
+        assert_eq!(coordinate_decimals(0), 1);
+        assert_eq!(coordinate_decimals(60), 1);
+        assert_eq!(coordinate_decimals(CHART_X_RATIO as usize * 2), 1);
+        assert_eq!(coordinate_decimals(CHART_X_RATIO as usize * 2 + 1), 0);
+        assert_eq!(coordinate_decimals(500), 1);
+        assert_eq!(coordinate_decimals(2000), 0);
+
+        let range = TimeRange { start: 0, end: 60 };
+        let sparse = values_to_polyline(&values(&[(33.3, 0), (66.6, 60)]), (0.0, 100.0), &range);
+        assert!(sparse
+            .unwrap()
+            .0
+            .iter()
+            .all(|(x, y)| (x * 10.0).fract() == 0.0 && (y * 10.0).fract() == 0.0));
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Cell holding an `Arc` that is replaced wholesale by writers.
+///
+/// Readers only clone the `Arc` while holding the lock, so they never wait on a writer building
+/// new data and always see either the old or the new value as a whole.
+#[derive(Debug, Default)]
+pub struct SwapCell<T> {
+    inner: RwLock<Arc<T>>,
+}
+
+impl<T> SwapCell<T> {
+    pub fn load(&self) -> Arc<T> {
+        // NOTE: The lock is never held while running user code so it cannot be poisoned in a torn state
+        self.inner
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn store(&self, value: T) {
+        let value = Arc::new(value);
+        *self.inner.write().unwrap_or_else(PoisonError::into_inner) = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    use super::*;
+
+    /// Data whose parts must all come from the same store, and which can't be cloned.
+    #[derive(Debug, Default)]
+    struct Generation {
+        number: usize,
+        lines: Vec<usize>,
+    }
+
+    impl Generation {
+        fn new(number: usize) -> Self {
+            Self {
+                number,
+                lines: vec![number; 1000],
+            }
+        }
+    }
+
+    #[test]
+    fn loads_share_the_stored_value() {
+        let cell = SwapCell::default();
+        cell.store(Generation::new(1));
+
+        let loads = (0..1000).map(|_| cell.load()).collect::<Vec<_>>();
+
+        // NOTE: `Generation` isn't `Clone`, every load is the one stored value
+        assert!(loads.iter().all(|loaded| Arc::ptr_eq(loaded, &loads[0])));
+        assert_eq!(Arc::strong_count(&loads[0]), 1001);
+    }
+
+    #[test]
+    fn readers_never_see_a_torn_value() {
+        let cell = Arc::new(SwapCell::default());
+        cell.store(Generation::new(0));
+        let writing = Arc::new(AtomicBool::new(true));
+
+        let readers = (0..8)
+            .map(|_| {
+                let (cell, writing) = (cell.clone(), writing.clone());
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while writing.load(Ordering::Relaxed) {
+                        let generation = cell.load();
+                        assert!(generation
+                            .lines
+                            .iter()
+                            .all(|line| *line == generation.number));
+                        // NOTE: Stores are never undone
+                        assert!(generation.number >= last);
+                        last = generation.number;
+                    }
+                    last
+                })
+            })
+            .collect::<Vec<_>>();
+        for number in 1..=500 {
+            cell.store(Generation::new(number));
+        }
+        writing.store(false, Ordering::Relaxed);
+
+        for reader in readers {
+            assert!(reader.join().unwrap() <= 500);
+        }
+        assert_eq!(cell.load().number, 500);
+    }
+
+    #[test]
+    fn loaded_values_outlive_the_stores() {
+        let cell = SwapCell::default();
+        cell.store(Generation::new(1));
+        let loaded = cell.load();
+
+        cell.store(Generation::new(2));
+
+        assert_eq!((loaded.number, loaded.lines.len()), (1, 1000));
+        assert_eq!(cell.load().number, 2);
+        assert_eq!(Arc::strong_count(&loaded), 1);
+    }
+}
@@ -0,0 +1,267 @@
+//! Databases and charts of the tests, the same on every run.
+// NOTE: Each helper is only used by the tests of some of the modules
+#![allow(dead_code)]
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{HeaderMap, Request, StatusCode},
+    Router,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use metrics::{
+    demo::{DemoOptions, DEFAULT_DEMO_SEED},
+    prelude::*,
+};
+use tempfile::TempDir;
+use tokio::sync::Notify;
+use tower::ServiceExt;
+
+use crate::{
+    admin::{AdminAccess, DatabaseAdmin, SharedAdmin},
+    generator::{SharedCoalescer, SharedData, SharedState, SharedStats},
+    reload::SharedConfig,
+    signing::CookieSecret,
+    slowlog::SharedSlowLog,
+    units::{AxisScale, ChartUnits},
+    ChartsData, SharedExtensions, TimeRange,
+};
+
+/// `--admin-token` of `Server`.
+pub(crate) const ADMIN_TOKEN: &str = "admin token of the tests";
+
+/// Set to write the golden files of the renderings instead of comparing with them.
+const UPDATE_ENV: &str = "SYSMET_UPDATE_GOLDEN";
+
+/// Time of the newest snapshot of the fixtures.
+pub(crate) fn end() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap()
+}
+
+/// Demo database of an hour with a snapshot every minute up to `end()`.
+pub(crate) fn database() -> Database {
+    Database::demo(&DemoOptions {
+        end: end(),
+        span: chrono::Duration::hours(1),
+        interval: chrono::Duration::minutes(1),
+        seed: DEFAULT_DEMO_SEED,
+    })
+    .unwrap()
+}
+
+/// Charts of the hour of `database()`.
+pub(crate) fn charts() -> ChartsData {
+    let mut charts = ChartsData::generate(
+        &database(),
+        TimeRange::ending_at(end().timestamp(), Duration::from_secs(60 * 60)),
+        None,
+        false,
+        None,
+        None,
+        ChartUnits::default(),
+        AxisScale::default(),
+    );
+    // NOTE: Detected against the clock of the host running the tests
+    charts.clock_mismatch = None;
+    charts
+}
+
+/// Stylesheets with a fixed hash, the embedded ones change with the styles.
+pub(crate) fn stylesheets() -> Vec<(String, String)> {
+    vec![("style.css".to_string(), "sha256-fixture".to_string())]
+}
+
+/// Critical styles with fixed contents, the embedded ones change with the styles.
+pub(crate) const CRITICAL_CSS: &str = ".container{margin:0 auto;max-width:80rem}";
+
+/// Checks `rendered` against the golden file `name`, writing it instead with `SYSMET_UPDATE_GOLDEN` set.
+pub(crate) fn assert_golden(name: &str, rendered: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("golden")
+        .join(name);
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, rendered).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "{}: {err}, run the tests with {UPDATE_ENV}=1 to write it",
+            path.display()
+        )
+    });
+    assert!(
+        golden == rendered,
+        "{name} differs from {}, run the tests with {UPDATE_ENV}=1 to update it if expected",
+        path.display()
+    );
+}
+
+/// Server with `database()` loaded and checked just now, its file being in `dir`, and the admin routes
+/// enabled with `ADMIN_TOKEN`.
+pub(crate) struct Server {
+    pub dir: TempDir,
+    pub extensions: SharedExtensions,
+    pub app: Router,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db").to_string_lossy().into_owned();
+        let mut database = database();
+        database.write_to_file(&path).unwrap();
+
+        let state = SharedState::default();
+        state.store(SharedData {
+            database: Arc::new(database),
+            charts: Arc::new(charts()),
+            ready: true,
+            ..Default::default()
+        });
+        let stats = SharedStats::default();
+        stats
+            .checked_at
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+        let extensions = SharedExtensions {
+            config: SharedConfig::default(),
+            state,
+            stats,
+            coalescer: SharedCoalescer::default(),
+            slow_log: SharedSlowLog::default(),
+            cookie_secret: Arc::new(CookieSecret::new("secret of the tests")),
+            fleet: Default::default(),
+            usage_log: Default::default(),
+            admin: SharedAdmin::new(DatabaseAdmin {
+                access: Some(AdminAccess {
+                    token: ADMIN_TOKEN.to_string(),
+                    allow_upload: true,
+                    backup: None,
+                }),
+                database: path,
+                key: None,
+                actualize_now: Arc::new(Notify::new()),
+                uploading: Default::default(),
+                downloads: Default::default(),
+            }),
+        };
+        let app = crate::app(extensions.clone());
+
+        Self {
+            dir,
+            extensions,
+            app,
+        }
+    }
+
+    /// Status, headers and body of the answer to `request`.
+    pub async fn send(&self, request: Request<Body>) -> (StatusCode, HeaderMap, String) {
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (
+            parts.status,
+            parts.headers,
+            String::from_utf8_lossy(&body).into_owned(),
+        )
+    }
+
+    /// Answer to a GET of `uri` with the given headers.
+    pub async fn get(
+        &self,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, HeaderMap, String) {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        self.send(request.body(Body::empty()).unwrap()).await
+    }
+}
+
+/// Rows of `csv`, its quoted fields unescaped.
+pub(crate) fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let (mut row, mut field) = (Vec::new(), String::new());
+    let (mut quoted, mut chars) = (false, csv.chars().peekable());
+    while let Some(char) = chars.next() {
+        match (quoted, char) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (_, char) => field.push(char),
+        }
+    }
+    assert!(!quoted && row.is_empty() && field.is_empty(), "{csv:?}");
+    rows
+}
+
+/// Value of the attribute `name` of an HTML tag, unescaped.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+    let value = &tag[start..start + tag[start..].find('"')?];
+    Some(value.replace("&quot;", "\"").replace("&amp;", "&"))
+}
+
+/// Query sent by submitting the dashboard form of `page` as rendered: its inputs, checked checkboxes and
+/// selected options, without its buttons.
+pub(crate) fn submitted_query(page: &str) -> String {
+    let form = page
+        .split("<form")
+        .skip(1)
+        .map(|form| &form[..form.find("</form>").unwrap()])
+        .find(|form| form.contains("name=\"compare\""))
+        .expect("no dashboard form");
+    let mut params = Vec::new();
+    for input in form.split("<input").skip(1) {
+        let tag = &input[..input.find('>').unwrap()];
+        let Some(name) = attribute(tag, "name") else {
+            continue;
+        };
+        match attribute(tag, "type").as_deref() {
+            Some("checkbox") if tag.contains(" checked") => {
+                params.push((
+                    name,
+                    attribute(tag, "value").unwrap_or_else(|| "on".to_string()),
+                ));
+            }
+            Some("checkbox" | "submit") => {}
+            _ => params.push((name, attribute(tag, "value").unwrap_or_default())),
+        }
+    }
+    for select in form.split("<select").skip(1) {
+        let select = &select[..select.find("</select>").unwrap()];
+        let name = attribute(&select[..select.find('>').unwrap()], "name").unwrap();
+        let options = select
+            .split("<option")
+            .skip(1)
+            .map(|option| &option[..option.find('>').unwrap()])
+            .collect::<Vec<_>>();
+        let selected = options
+            .iter()
+            .find(|option| option.contains(" selected"))
+            .unwrap_or(&options[0]);
+        params.push((name, attribute(selected, "value").unwrap_or_default()));
+    }
+    params
+        .into_iter()
+        .map(|(name, value)| format!("{name}={}", value.replace('+', "%2B")))
+        .collect::<Vec<_>>()
+        .join("&")
+}
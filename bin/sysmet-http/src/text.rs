@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use axum::http::{header, HeaderMap};
+
+use crate::{locale::LocaleSpec, query::Notices, range_summary, ChartsData, WEBSITE_TITLE};
+
+/// Blocks of a sparkline from the lowest to the highest value.
+const SPARKLINE_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Most blocks of the sparkline of a line, so a line stays readable in an 80 columns terminal.
+pub const SPARKLINE_WIDTH: usize = 60;
+
+/// Sparkline of at most `width` blocks, each one averaging consecutive values, scaled from the lowest to the
+/// highest of them. A constant series is a flat line of the lowest block.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+
+    let blocks = values.len().min(width);
+    // NOTE: With more values than blocks each block has at least one value
+    let averages = (0..blocks)
+        .map(|block| {
+            let values =
+                &values[block * values.len() / blocks..(block + 1) * values.len() / blocks];
+            values.iter().sum::<f64>() / values.len() as f64
+        })
+        .collect::<Vec<_>>();
+    let min = averages.iter().copied().fold(f64::MAX, f64::min);
+    let max = averages.iter().copied().fold(f64::MIN, f64::max);
+    let top = (SPARKLINE_BLOCKS.len() - 1) as f64;
+
+    averages
+        .iter()
+        .map(|value| {
+            let level = if max > min {
+                ((value - min) / (max - min) * top).round() as usize
+            } else {
+                0
+            };
+            SPARKLINE_BLOCKS[level.min(SPARKLINE_BLOCKS.len() - 1)]
+        })
+        .collect()
+}
+
+/// The `Accept` header prefers `text/plain` to HTML, e.g. `curl -H 'Accept: text/plain'`.
+///
+/// Wildcards are ignored as they accept both, so browsers and bare `curl` get the HTML.
+pub fn prefers_text(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+    else {
+        return false;
+    };
+    let quality = |media_type: &str| {
+        accept
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';').map(str::trim);
+                (params.next()? == media_type).then(|| {
+                    params
+                        .find_map(|param| param.strip_prefix("q="))
+                        .and_then(|quality| quality.parse::<f64>().ok())
+                        .unwrap_or(1.0)
+                })
+            })
+            .fold(0.0, f64::max)
+    };
+
+    let text = quality("text/plain");
+    text > 0.0 && text > quality("text/html")
+}
+
+/// Dashboard as plain text for terminals, one line per line of each chart with its sparkline and values.
+pub fn render_text(
+    data: &ChartsData,
+    hostname: Option<&str>,
+    notices: &Notices,
+    locale: &LocaleSpec,
+) -> String {
+    let time = Duration::from_secs(data.range.duration().max(0) as u64);
+    let mut text = match hostname {
+        Some(hostname) => format!("{WEBSITE_TITLE} - {hostname}\n"),
+        None => format!("{WEBSITE_TITLE}\n"),
+    };
+    text.push_str(&range_summary(
+        time,
+        data.range.end,
+        data.snapshots_count,
+        locale,
+    ));
+    text.push_str("\nAverages are weighted by the time each value covers\n");
+    for notice in notices.iter() {
+        text.push_str(&format!("{}: {notice}\n", notice.severity.as_str()));
+    }
+
+    for (_, chart) in &data.metrics {
+        let value = |value: f64| match chart.unit.as_str() {
+            "%" | "" => format!("{}{}", locale.number(value, 1), chart.unit),
+            unit => format!("{} {unit}", locale.number(value, 1)),
+        };
+        text.push_str(&format!("\n{}\n", chart.title));
+        let lines = chart
+            .data
+            .iter()
+            .filter_map(|line| Some((line, line.stats?)))
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            text.push_str("  no data\n");
+            continue;
+        }
+
+        let label_width = lines
+            .iter()
+            .map(|(line, _)| line.label.chars().count())
+            .max()
+            .unwrap_or_default();
+        for (line, stats) in lines {
+            let values = line
+                .values
+                .iter()
+                .map(|(value, _)| *value)
+                .collect::<Vec<_>>();
+            text.push_str(&format!(
+                "  {:<label_width$}  {:<SPARKLINE_WIDTH$}  min {}  avg {}  max {}  now {}\n",
+                line.label,
+                sparkline(&values, SPARKLINE_WIDTH),
+                value(stats.min),
+                value(stats.average),
+                value(stats.peak),
+                value(stats.current),
+            ));
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn empty_sparklines_have_no_blocks() {
+        assert_eq!(sparkline(&[], SPARKLINE_WIDTH), "");
+        assert_eq!(sparkline(&[1.0, 2.0], 0), "");
+    }
+
+    #[test]
+    fn constant_sparklines_are_flat_at_the_bottom() {
+        assert_eq!(sparkline(&[42.0], SPARKLINE_WIDTH), "▁");
+        assert_eq!(sparkline(&[5.0; 4], SPARKLINE_WIDTH), "▁▁▁▁");
+        assert_eq!(sparkline(&[0.0; 100], 3), "▁▁▁");
+    }
+
+    #[test]
+    fn sparklines_go_from_the_lowest_to_the_highest_value() {
+        assert_eq!(
+            sparkline(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0], SPARKLINE_WIDTH),
+            "▁▂▃▄▅▆▇█"
+        );
+        assert_eq!(sparkline(&[-10.0, 10.0, 0.0], SPARKLINE_WIDTH), "▁█▅");
+    }
+
+    #[test]
+    fn long_sparklines_average_their_values_per_block() {
+        let values = (0..600).map(|idx| (idx / 100) as f64).collect::<Vec<_>>();
+
+        let line = sparkline(&values, SPARKLINE_WIDTH);
+
+        assert_eq!(line.chars().count(), SPARKLINE_WIDTH);
+        assert!(line.starts_with("▁▁▁▁▁▁▁▁▁▁"));
+        assert!(line.ends_with("██████████"));
+        // NOTE: Each block averages 2 values
+        assert_eq!(sparkline(&[0.0, 0.0, 6.0, 8.0, 3.0, 4.0], 3), "▁█▅");
+    }
+
+    #[test]
+    fn text_is_preferred_only_when_asked_for() {
+        let prefers = |accept: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+            }
+            prefers_text(&headers)
+        };
+
+        assert!(prefers(Some("text/plain")));
+        assert!(prefers(Some("text/html;q=0.5, text/plain")));
+        assert!(prefers(Some("text/plain, */*")));
+        assert!(!prefers(None));
+        assert!(!prefers(Some("*/*")));
+        assert!(!prefers(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        )));
+        assert!(!prefers(Some("text/plain;q=0.5, text/html")));
+        assert!(!prefers(Some("text/plain;q=0")));
+    }
+
+    #[test]
+    fn text_dashboards_have_a_line_per_chart_line() {
+        let charts = testing::charts();
+
+        let text = render_text(
+            &charts,
+            Some("demo"),
+            &Notices::default(),
+            &LocaleSpec::default(),
+        );
+
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("Ferrous System Metrics - demo"));
+        for (_, chart) in &charts.metrics {
+            let section = text
+                .split(&format!("\n{}\n", chart.title))
+                .nth(1)
+                .unwrap_or_else(|| panic!("{} missing from {text}", chart.title));
+            let first = section.lines().next().unwrap();
+            if chart.data.iter().all(|line| line.stats.is_none()) {
+                assert_eq!(first, "  no data");
+            } else {
+                assert!(first.contains("  min "), "{first}");
+                assert!(first.contains("  now "), "{first}");
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fs::Permissions,
+    io::ErrorKind,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use axum::{extract::Request, Router};
+use color_eyre::eyre::{eyre, WrapErr};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+};
+use log::{debug, info, tracing, warn};
+use tokio::{net::UnixListener, sync::watch::Receiver};
+use tower::Service;
+
+use crate::Result;
+
+#[derive(Debug, Clone)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    pub mode: u32,
+}
+
+/// Parse an octal file mode, e.g. `0660` or `660`.
+pub fn parse_mode(value: &str) -> std::result::Result<u32, String> {
+    let digits = value.trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("{value} is not an octal file mode"))
+}
+
+/// Remove a socket file left by a previous run, a socket something still listens on is never removed.
+#[tracing::instrument]
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("Failed to check {}", path.display()))
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(eyre!(
+            "{} already exists and is not a socket",
+            path.display()
+        ));
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(eyre!("{} is already listened on", path.display())),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            warn!("Removing the stale socket {}", path.display());
+            std::fs::remove_file(path)
+                .wrap_err_with(|| format!("Failed to remove the stale socket {}", path.display()))
+        }
+        Err(err) => Err(err).wrap_err_with(|| format!("Failed to check {}", path.display())),
+    }
+}
+
+/// Bind the socket with its mode set, replacing a stale socket file.
+#[tracing::instrument]
+pub fn bind(socket: &UnixSocket) -> Result<UnixListener> {
+    remove_stale_socket(&socket.path)?;
+    let listener = UnixListener::bind(&socket.path)
+        .wrap_err_with(|| format!("Failed to listen on {}", socket.path.display()))?;
+    std::fs::set_permissions(&socket.path, Permissions::from_mode(socket.mode)).wrap_err_with(
+        || {
+            format!(
+                "Failed to set the mode of {} to {:o}",
+                socket.path.display(),
+                socket.mode
+            )
+        },
+    )?;
+
+    Ok(listener)
+}
+
+/// Serve the router on the listener until the stop signal, then remove the socket file.
+#[tracing::instrument(skip(listener, app, stop_rx))]
+pub async fn serve(
+    listener: UnixListener,
+    path: PathBuf,
+    app: Router,
+    mut stop_rx: Receiver<bool>,
+) -> Result<()> {
+    info!("Listening on {}", path.display());
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _addr)) => stream,
+                Err(err) => {
+                    warn!("Failed to accept a connection on {}: {err}", path.display());
+                    continue;
+                }
+            },
+            _ = stop_rx.changed() => break,
+        };
+
+        let app = app.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                app.clone().call(request)
+            });
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Connection on the unix socket failed: {err}");
+            }
+        });
+    }
+
+    debug!("Removing {}", path.display());
+    std::fs::remove_file(&path)
+        .wrap_err_with(|| format!("Failed to remove the socket {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixListener as StdUnixListener, sync::atomic::Ordering};
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        Extension,
+    };
+    use chrono::Utc;
+    use tempfile::TempDir;
+    use tokio::{net::UnixStream, sync::watch};
+
+    use super::*;
+    use crate::generator::{SharedData, SharedState, SharedStats};
+
+    fn socket(dir: &TempDir) -> UnixSocket {
+        UnixSocket {
+            path: dir.path().join("sysmet.sock"),
+            mode: 0o660,
+        }
+    }
+
+    /// Router of the server with a database checked just now.
+    fn app() -> Router {
+        let shared_state = SharedState::default();
+        shared_state.store(SharedData {
+            ready: true,
+            ..Default::default()
+        });
+        let stats = SharedStats::default();
+        stats
+            .checked_at
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+        crate::routes::router()
+            .layer(Extension(shared_state))
+            .layer(Extension(stats))
+    }
+
+    #[tokio::test]
+    async fn health_is_served_over_the_socket() {
+        let dir = TempDir::new().unwrap();
+        let socket = socket(&dir);
+        let listener = bind(&socket).unwrap();
+        let mode = std::fs::metadata(&socket.path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o660);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let server = tokio::spawn(serve(listener, socket.path.clone(), app(), stop_rx));
+
+        let stream = UnixStream::connect(&socket.path).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let response = sender
+            .send_request(
+                Request::get("/health")
+                    .header("host", "localhost")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+
+        stop_tx.send(true).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!socket.path.exists());
+    }
+
+    #[tokio::test]
+    async fn stale_sockets_are_replaced() {
+        let dir = TempDir::new().unwrap();
+        let socket = socket(&dir);
+        // NOTE: Closing the listener leaves its file behind, as a crash would
+        drop(StdUnixListener::bind(&socket.path).unwrap());
+        assert!(socket.path.exists());
+
+        let listener = bind(&socket).unwrap();
+        let (_stop_tx, stop_rx) = watch::channel(false);
+        tokio::spawn(serve(listener, socket.path.clone(), app(), stop_rx));
+        assert!(UnixStream::connect(&socket.path).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn listened_sockets_are_kept() {
+        let dir = TempDir::new().unwrap();
+        let socket = socket(&dir);
+        let _listener = StdUnixListener::bind(&socket.path).unwrap();
+
+        assert!(bind(&socket).is_err());
+        assert!(std::os::unix::net::UnixStream::connect(&socket.path).is_ok());
+    }
+
+    #[tokio::test]
+    async fn other_files_are_kept() {
+        let dir = TempDir::new().unwrap();
+        let socket = socket(&dir);
+        std::fs::write(&socket.path, "not a socket").unwrap();
+
+        assert!(bind(&socket).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&socket.path).unwrap(),
+            "not a socket"
+        );
+    }
+
+    #[test]
+    fn modes_are_octal() {
+        assert_eq!(parse_mode("0660"), Ok(0o660));
+        assert_eq!(parse_mode("600"), Ok(0o600));
+        assert_eq!(parse_mode("0o2770"), Ok(0o2770));
+        assert!(parse_mode("0980").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("rw").is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+
+const IEC_PREFIXES: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+const SI_PREFIXES: [&str; 6] = ["B", "kB", "MB", "GB", "TB", "PB"];
+const BIT_PREFIXES: [&str; 6] = ["bit", "Kbit", "Mbit", "Gbit", "Tbit", "Pbit"];
+
+/// How byte values are displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum UnitSystem {
+    /// Powers of 1024, e.g. MiB.
+    #[default]
+    IecBytes,
+    /// Powers of 1000, e.g. MB.
+    SiBytes,
+    /// Powers of 1000 of 8 bits, e.g. Mbit.
+    Bits,
+}
+
+impl UnitSystem {
+    /// Value of `--network-units`, `bits` or `bytes`.
+    pub fn from_network_units(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "bits" => Some(UnitSystem::Bits),
+            "bytes" => Some(UnitSystem::IecBytes),
+            _ => None,
+        }
+    }
+
+    /// Value of `--disk-units`, `si` or `iec`.
+    pub fn from_disk_units(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "si" => Some(UnitSystem::SiBytes),
+            "iec" => Some(UnitSystem::IecBytes),
+            _ => None,
+        }
+    }
+
+    pub fn network_units(self) -> &'static str {
+        match self {
+            UnitSystem::Bits => "bits",
+            UnitSystem::IecBytes | UnitSystem::SiBytes => "bytes",
+        }
+    }
+
+    pub fn disk_units(self) -> &'static str {
+        match self {
+            UnitSystem::SiBytes => "si",
+            UnitSystem::IecBytes | UnitSystem::Bits => "iec",
+        }
+    }
+
+    fn base(self) -> f64 {
+        match self {
+            UnitSystem::IecBytes => 1024.0,
+            UnitSystem::SiBytes | UnitSystem::Bits => 1000.0,
+        }
+    }
+
+    /// Units of one byte.
+    fn factor(self) -> f64 {
+        match self {
+            UnitSystem::Bits => 8.0,
+            UnitSystem::IecBytes | UnitSystem::SiBytes => 1.0,
+        }
+    }
+
+    fn prefixes(self) -> &'static [&'static str] {
+        match self {
+            UnitSystem::IecBytes => &IEC_PREFIXES,
+            UnitSystem::SiBytes => &SI_PREFIXES,
+            UnitSystem::Bits => &BIT_PREFIXES,
+        }
+    }
+
+    /// Largest prefix under which `max_bytes` is still at least 1, e.g. 1536 bytes are 1.5 KiB.
+    pub fn scale(self, max_bytes: f64) -> UnitScale {
+        let max = max_bytes * self.factor();
+        let prefixes = self.prefixes();
+        let mut power = 0;
+        while power + 1 < prefixes.len() && max >= self.base().powi(power as i32 + 1) {
+            power += 1;
+        }
+
+        UnitScale {
+            divisor: self.base().powi(power as i32) / self.factor(),
+            unit: prefixes[power],
+        }
+    }
+}
+
+/// Unit chosen for the values of a chart, every value of the chart is converted with it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitScale {
+    /// Bytes per unit.
+    pub divisor: f64,
+    pub unit: &'static str,
+}
+
+impl UnitScale {
+    pub fn convert(&self, bytes: f64) -> f64 {
+        bytes / self.divisor
+    }
+}
+
+/// Unit systems of the charts measured in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct ChartUnits {
+    pub network: UnitSystem,
+    /// Disks speed and memory usage.
+    pub disk: UnitSystem,
+}
+
+impl ChartUnits {
+    /// Units overridden by the `network_units` and `disk_units` query parameters, invalid values are ignored.
+    pub fn with_overrides(self, network: Option<&str>, disk: Option<&str>) -> Self {
+        Self {
+            network: network
+                .and_then(UnitSystem::from_network_units)
+                .unwrap_or(self.network),
+            disk: disk
+                .and_then(UnitSystem::from_disk_units)
+                .unwrap_or(self.disk),
+        }
+    }
+
+    /// Query parameters giving these units, e.g. `&network_units=bits&disk_units=iec`.
+    pub fn to_query(self) -> String {
+        format!(
+            "&network_units={}&disk_units={}",
+            self.network.network_units(),
+            self.disk.disk_units()
+        )
+    }
+}
+
+/// Default times the max of a chart must exceed the bulk of its values for its axis to end at the bulk.
+pub const DEFAULT_OUTLIER_FACTOR: u32 = 3;
+
+/// How the max of the y axis of the lines charts is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AxisScale {
+    /// Max of every value, a single bogus sample flattening the rest of the chart.
+    Full,
+    /// Bulk of the values (their 99.5th percentile) when their max is more than `factor` times above it, the
+    /// points above being clipped to the top of the chart and marked.
+    Robust { factor: u32 },
+}
+
+impl Default for AxisScale {
+    fn default() -> Self {
+        Self::Robust {
+            factor: DEFAULT_OUTLIER_FACTOR,
+        }
+    }
+}
+
+impl AxisScale {
+    /// Scale of `--outlier-factor`, 0 for the full scale.
+    pub fn from_factor(factor: u32) -> Self {
+        match factor {
+            0 => Self::Full,
+            factor => Self::Robust { factor },
+        }
+    }
+
+    /// Value of `--outlier-factor`, 0 for the full scale.
+    pub fn factor(self) -> u32 {
+        match self {
+            Self::Full => 0,
+            Self::Robust { factor } => factor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(system: UnitSystem, bytes: f64) -> &'static str {
+        system.scale(bytes).unit
+    }
+
+    #[test]
+    fn iec_prefixes_change_at_powers_of_1024() {
+        assert_eq!(unit(UnitSystem::IecBytes, 0.0), "B");
+        assert_eq!(unit(UnitSystem::IecBytes, 1000.0), "B");
+        assert_eq!(unit(UnitSystem::IecBytes, 1023.0), "B");
+        assert_eq!(unit(UnitSystem::IecBytes, 1024.0), "KiB");
+        assert_eq!(unit(UnitSystem::IecBytes, 1024.0 * 1024.0 - 1.0), "KiB");
+        assert_eq!(unit(UnitSystem::IecBytes, 1024.0 * 1024.0), "MiB");
+        assert_eq!(UnitSystem::IecBytes.scale(1536.0).convert(1536.0), 1.5);
+    }
+
+    #[test]
+    fn si_prefixes_change_at_powers_of_1000() {
+        assert_eq!(unit(UnitSystem::SiBytes, 999.0), "B");
+        assert_eq!(unit(UnitSystem::SiBytes, 1000.0), "kB");
+        assert_eq!(unit(UnitSystem::SiBytes, 1023.0), "kB");
+        assert_eq!(unit(UnitSystem::SiBytes, 999_999.0), "kB");
+        assert_eq!(unit(UnitSystem::SiBytes, 1_000_000.0), "MB");
+        assert_eq!(UnitSystem::SiBytes.scale(1500.0).convert(1500.0), 1.5);
+    }
+
+    #[test]
+    fn bits_are_eight_per_byte() {
+        // NOTE: 125 bytes are the first 1000 bits
+        assert_eq!(unit(UnitSystem::Bits, 124.0), "bit");
+        assert_eq!(unit(UnitSystem::Bits, 125.0), "Kbit");
+        assert_eq!(unit(UnitSystem::Bits, 124_999.0), "Kbit");
+        assert_eq!(unit(UnitSystem::Bits, 125_000.0), "Mbit");
+
+        let scale = UnitSystem::Bits.scale(1_000_000.0);
+        assert_eq!(scale.unit, "Mbit");
+        assert_eq!(scale.convert(1_000_000.0), 8.0);
+        assert_eq!(UnitSystem::Bits.scale(1.0).convert(1.0), 8.0);
+    }
+
+    #[test]
+    fn prefixes_stop_at_peta() {
+        let bytes = 1e21;
+        assert_eq!(unit(UnitSystem::IecBytes, bytes), "PiB");
+        assert_eq!(unit(UnitSystem::SiBytes, bytes), "PB");
+        assert_eq!(unit(UnitSystem::Bits, bytes), "Pbit");
+        assert_eq!(UnitSystem::SiBytes.scale(bytes).convert(bytes), 1e6);
+    }
+
+    #[test]
+    fn unit_flags_round_trip() {
+        for system in [UnitSystem::IecBytes, UnitSystem::Bits] {
+            assert_eq!(
+                UnitSystem::from_network_units(system.network_units()),
+                Some(system)
+            );
+        }
+        for system in [UnitSystem::IecBytes, UnitSystem::SiBytes] {
+            assert_eq!(
+                UnitSystem::from_disk_units(system.disk_units()),
+                Some(system)
+            );
+        }
+        assert_eq!(
+            UnitSystem::from_network_units(" BITS "),
+            Some(UnitSystem::Bits)
+        );
+        assert_eq!(UnitSystem::from_network_units("si"), None);
+        assert_eq!(UnitSystem::from_disk_units("bits"), None);
+    }
+
+    #[test]
+    fn invalid_overrides_keep_the_units() {
+        let units = ChartUnits {
+            network: UnitSystem::Bits,
+            disk: UnitSystem::SiBytes,
+        };
+
+        assert_eq!(units.with_overrides(None, None), units);
+        assert_eq!(units.with_overrides(Some("kbps"), Some("")), units);
+        assert_eq!(
+            units.with_overrides(Some("bytes"), Some("iec")),
+            ChartUnits::default()
+        );
+        assert_eq!(units.to_query(), "&network_units=bits&disk_units=si");
+    }
+
+    #[test]
+    fn outlier_factors_round_trip() {
+        assert_eq!(AxisScale::from_factor(0), AxisScale::Full);
+        assert_eq!(AxisScale::from_factor(0).factor(), 0);
+        assert_eq!(AxisScale::from_factor(5).factor(), 5);
+        assert_eq!(AxisScale::default().factor(), DEFAULT_OUTLIER_FACTOR);
+    }
+
+    #[test]
+    fn outlier_factors_of_0_are_the_full_scale() {
+        assert_eq!(AxisScale::from_factor(0), AxisScale::Full);
+        assert_eq!(AxisScale::from_factor(5), AxisScale::Robust { factor: 5 });
+        assert_eq!(AxisScale::default().factor(), DEFAULT_OUTLIER_FACTOR);
+        for factor in [0, 1, 3, 10] {
+            assert_eq!(AxisScale::from_factor(factor).factor(), factor);
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Opt-in log of the ranges and charts requested, one JSON line per dashboard or API request.
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::HttpBody,
+    extract::{Extension, Query, Request},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::WrapErr;
+use log::{debug, tracing, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::parse_time, generator::DEFAULT_TIME_RANGE, parse_range, proxy::ClientInfo, range_to_query,
+    Result,
+};
+
+/// Lines waiting for the writer before new ones are dropped, so a stalled disk never holds a response.
+const USAGE_LOG_CAPACITY: usize = 1024;
+/// Most bytes read from the end of the log to summarize it on `/stats`.
+const USAGE_SUMMARY_MAX_BYTES: u64 = 4 * 1024 * 1024;
+/// Ranges listed by the summary, the most requested first.
+const USAGE_SUMMARY_RANGES: usize = 5;
+
+/// Request of a dashboard or API route as written to the usage log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageEntry {
+    pub at: DateTime<Utc>,
+    /// Path of the route, e.g. `/` or `/api/snapshot`, without its query.
+    pub path: String,
+    /// Length of the requested range as a `t` value, e.g. `1d`, the default one when not given.
+    pub range: Option<String>,
+    /// How the values were combined, e.g. the `smooth` window, the fleet `mode` or `raw` for a CSV of
+    /// every snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<String>,
+    /// Charts or metrics asked for, e.g. the focused chart or the downloaded one.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub charts: Vec<String>,
+    pub status: u16,
+    pub duration_ms: u64,
+    /// Size of the response body, `None` when it is streamed.
+    pub bytes: Option<u64>,
+    /// Only with `--usage-log-clients`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// Only with `--usage-log-clients`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+/// Usage log appended by a dedicated thread, a line that can't be queued is counted and dropped.
+#[derive(Debug)]
+pub struct UsageLog {
+    path: PathBuf,
+    /// Also log the address and the user agent of the clients.
+    clients: bool,
+    sender: SyncSender<String>,
+    dropped: Arc<AtomicU64>,
+}
+
+pub(crate) type SharedUsageLog = Arc<Option<UsageLog>>;
+
+impl UsageLog {
+    /// Open `path` for appending, failing right away rather than on the first request.
+    #[tracing::instrument]
+    pub fn open(path: &Path, clients: bool) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("Failed to open the usage log {}", path.display()))?;
+        Self::with_writer(path, file, clients)
+    }
+
+    /// Usage log appending its lines to `writer`, the summary being read from `path`.
+    fn with_writer(
+        path: &Path,
+        writer: impl Write + Send + 'static,
+        clients: bool,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(USAGE_LOG_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        thread::Builder::new()
+            .name("usage-log".to_string())
+            .spawn({
+                let path = path.to_path_buf();
+                let dropped = dropped.clone();
+                move || write_lines(&path, writer, receiver, &dropped)
+            })
+            .wrap_err("Failed to start the usage log writer")?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            clients,
+            sender,
+            dropped,
+        })
+    }
+
+    /// Queue the line of the entry without waiting, dropping it when the writer is behind or gone.
+    pub fn record(&self, entry: &UsageEntry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Failed to encode a usage log line: {err}");
+                return;
+            }
+        };
+        if let Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) =
+            self.sender.try_send(line)
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Lines dropped since the start because the writer was behind or failed.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Summary of the end of the log, read again on each call.
+    #[tracing::instrument(level = "debug")]
+    pub fn summary(&self) -> io::Result<UsageSummary> {
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+        let start = len.saturating_sub(USAGE_SUMMARY_MAX_BYTES);
+        file.seek(SeekFrom::Start(start))?;
+        let mut lines = BufReader::new(file).lines();
+        // NOTE: Starting within the log cuts its first line
+        if start > 0 {
+            lines.next();
+        }
+
+        let mut ranges = HashMap::<String, u64>::new();
+        let mut durations = Vec::new();
+        for line in lines {
+            let Ok(entry) = serde_json::from_str::<UsageEntry>(&line?) else {
+                continue;
+            };
+            if let Some(range) = entry.range {
+                *ranges.entry(range).or_default() += 1;
+            }
+            durations.push(entry.duration_ms as f64);
+        }
+        let mut ranges = ranges.into_iter().collect::<Vec<_>>();
+        ranges.sort_by(|(range, count), (other_range, other_count)| {
+            other_count.cmp(count).then_with(|| range.cmp(other_range))
+        });
+        ranges.truncate(USAGE_SUMMARY_RANGES);
+
+        Ok(UsageSummary {
+            requests: durations.len(),
+            partial: start > 0,
+            top_ranges: ranges,
+            p95_duration_ms: metrics::stats::percentile(&durations, 95.0),
+            dropped_lines: self.dropped(),
+        })
+    }
+}
+
+/// Append each queued line, flushing once the queue is empty.
+fn write_lines(path: &Path, writer: impl Write, receiver: Receiver<String>, dropped: &AtomicU64) {
+    let mut writer = BufWriter::new(writer);
+    let write = |line: &str, writer: &mut BufWriter<_>| {
+        if let Err(err) = writeln!(writer, "{line}") {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            warn!("Failed to write to the usage log {}: {err}", path.display());
+        }
+    };
+    while let Ok(line) = receiver.recv() {
+        write(&line, &mut writer);
+        for line in receiver.try_iter() {
+            write(&line, &mut writer);
+        }
+        if let Err(err) = writer.flush() {
+            warn!("Failed to write to the usage log {}: {err}", path.display());
+        }
+    }
+    debug!("Usage log writer stopped");
+}
+
+/// Usage log summarized on `/stats`.
+#[derive(Debug, Serialize)]
+pub struct UsageSummary {
+    /// Requests of the summarized part of the log.
+    pub requests: usize,
+    /// Only the end of the log was read, it is larger than the cap.
+    pub partial: bool,
+    /// Most requested ranges as (range, requests).
+    pub top_ranges: Vec<(String, u64)>,
+    pub p95_duration_ms: Option<f64>,
+    /// Lines dropped since the start because the writer was behind or failed.
+    pub dropped_lines: u64,
+}
+
+/// Decoded query parameters of a request.
+type Params = HashMap<String, String>;
+
+/// Non-empty query parameter.
+fn param<'a>(query: &'a Params, name: &str) -> Option<&'a str> {
+    query
+        .get(name)
+        .map(String::as_str)
+        .filter(|value| !value.is_empty())
+}
+
+/// Length of the range of the query as a `t` value, the explicit dates first.
+fn range_of(path: &str, query: &Params) -> Option<String> {
+    let explicit = param(query, "from")
+        .and_then(parse_time)
+        .zip(param(query, "to").and_then(parse_time))
+        .and_then(|(from, to)| (to - from).to_std().ok());
+    let duration = explicit
+        .or_else(|| param(query, "t").and_then(parse_range))
+        .or_else(|| {
+            // NOTE: The snapshots and the latest values are not about a range
+            (path == "/" || path.starts_with("/download/") || path.ends_with("aggregate"))
+                .then_some(DEFAULT_TIME_RANGE)
+        })?;
+
+    Some(range_to_query(duration))
+}
+
+/// Entry of a request whose response took `duration`.
+fn entry_of(
+    path: &str,
+    query: &Params,
+    response: &Response,
+    duration: Duration,
+    client: Option<(Option<&ClientInfo>, Option<&str>)>,
+) -> UsageEntry {
+    let aggregation = param(query, "smooth")
+        .map(|smooth| format!("smooth={smooth}"))
+        .or_else(|| param(query, "mode").map(|mode| format!("mode={mode}")))
+        .or_else(|| (param(query, "raw") == Some("true")).then(|| "raw".to_string()));
+    let charts = path
+        .strip_prefix("/download/")
+        .map(|file| file.trim_end_matches(".csv").to_string())
+        .into_iter()
+        .chain(
+            ["focus", "metric"]
+                .into_iter()
+                .filter_map(|name| param(query, name).map(str::to_string)),
+        )
+        .chain(
+            param(query, "open")
+                .into_iter()
+                .flat_map(|open| open.split(','))
+                .map(|group| format!("group-{group}")),
+        )
+        .collect();
+
+    UsageEntry {
+        at: Utc::now(),
+        path: path.to_string(),
+        range: range_of(path, query),
+        aggregation,
+        charts,
+        status: response.status().as_u16(),
+        duration_ms: duration.as_millis() as u64,
+        bytes: response.body().size_hint().exact(),
+        client: client
+            .and_then(|(client, _)| client?.ip)
+            .map(|ip| ip.to_string()),
+        user_agent: client
+            .and_then(|(_, user_agent)| user_agent)
+            .map(str::to_string),
+    }
+}
+
+/// Middleware appending each request of the routes it wraps to the usage log, when there is one.
+pub(crate) async fn record(
+    Extension(usage_log): Extension<SharedUsageLog>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(usage_log) = usage_log.as_ref() else {
+        return next.run(request).await;
+    };
+
+    let path = request.uri().path().to_string();
+    let query = Query::<Params>::try_from_uri(request.uri())
+        .map(|Query(query)| query)
+        .unwrap_or_default();
+    let client = usage_log.clients.then(|| {
+        (
+            request.extensions().get::<ClientInfo>().cloned(),
+            request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|user_agent| user_agent.to_str().ok())
+                .map(str::to_string),
+        )
+    });
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    usage_log.record(&entry_of(
+        &path,
+        &query,
+        &response,
+        started.elapsed(),
+        client
+            .as_ref()
+            .map(|(client, user_agent)| (client.as_ref(), user_agent.as_deref())),
+    ));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{app, testing::Server};
+
+    /// Writer blocked on each write until its sender is dropped, as on a stalled disk.
+    struct Stalled(Receiver<()>);
+
+    impl Write for Stalled {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.0.recv();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Server logging its usage to `usage.log` in its directory.
+    fn server_with(usage_log: impl FnOnce(&Path) -> UsageLog) -> (Server, PathBuf) {
+        let mut server = Server::new();
+        let path = server.dir.path().join("usage.log");
+        server.extensions.usage_log = Arc::new(Some(usage_log(&path)));
+        server.app = app(server.extensions.clone());
+        (server, path)
+    }
+
+    /// The first `count` lines of the log at `path`, waiting for its writer.
+    fn logged(path: &Path, count: usize) -> Vec<Value> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let log = std::fs::read_to_string(path).unwrap();
+            if log.lines().count() >= count || Instant::now() > deadline {
+                return log
+                    .lines()
+                    .take(count)
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect();
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn keys(line: &Value) -> Vec<&str> {
+        line.as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_as_json_lines() {
+        let (server, path) = server_with(|path| UsageLog::open(path, false).unwrap());
+        let (status, _, body) = server.get("/?t=6h&smooth=5&focus=cpu", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        server.get("/api/snapshot/latest", &[]).await;
+
+        let lines = logged(&path, 2);
+        assert_eq!(lines.len(), 2);
+        let dashboard = &lines[0];
+        assert_eq!(
+            keys(dashboard),
+            [
+                "aggregation",
+                "at",
+                "bytes",
+                "charts",
+                "duration_ms",
+                "path",
+                "range",
+                "status"
+            ]
+        );
+        assert!(DateTime::parse_from_rfc3339(dashboard["at"].as_str().unwrap()).is_ok());
+        assert_eq!(dashboard["path"], "/");
+        assert_eq!(dashboard["range"], "6h");
+        assert_eq!(dashboard["aggregation"], "smooth=5");
+        assert_eq!(dashboard["charts"], serde_json::json!(["cpu"]));
+        assert_eq!(dashboard["status"], 200);
+        assert_eq!(dashboard["bytes"], body.len());
+        assert!(dashboard["duration_ms"].is_u64());
+
+        // NOTE: The latest snapshot is not about a range, nor combined
+        let now = &lines[1];
+        assert_eq!(now["path"], "/api/snapshot/latest");
+        assert_eq!(now["range"], Value::Null);
+        assert_eq!(
+            keys(now),
+            ["at", "bytes", "duration_ms", "path", "range", "status"]
+        );
+    }
+
+    #[tokio::test]
+    async fn clients_are_only_logged_when_enabled() {
+        let user_agent = [("user-agent", "Mozilla/5.0 (Android 14; Mobile)")];
+        let (server, path) = server_with(|path| UsageLog::open(path, false).unwrap());
+        server.get("/?t=1h", &user_agent).await;
+        let line = &logged(&path, 1)[0];
+        assert!(line.get("user_agent").is_none(), "{line}");
+        assert!(line.get("client").is_none(), "{line}");
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(!log.contains("Mozilla"), "{log}");
+
+        let (server, path) = server_with(|path| UsageLog::open(path, true).unwrap());
+        server.get("/?t=1h", &user_agent).await;
+        let line = &logged(&path, 1)[0];
+        assert_eq!(line["user_agent"], user_agent[0].1);
+    }
+
+    #[tokio::test]
+    async fn stalled_writes_never_hold_the_responses() {
+        let (stall, stalled) = mpsc::channel();
+        let (server, _) =
+            server_with(|path| UsageLog::with_writer(path, Stalled(stalled), false).unwrap());
+        let usage_log = server.extensions.usage_log.clone();
+        let usage_log = usage_log.as_ref().as_ref().unwrap();
+
+        for _ in 0..USAGE_LOG_CAPACITY + 100 {
+            let (status, _, _) = tokio::time::timeout(
+                Duration::from_secs(5),
+                server.get("/api/snapshot/latest", &[]),
+            )
+            .await
+            .expect("a response waited for the usage log");
+            assert_eq!(status, StatusCode::OK);
+        }
+        // NOTE: The writer holds the lines of its buffer, the queue the next ones
+        assert!(usage_log.dropped() > 0);
+        drop(stall);
+    }
+}
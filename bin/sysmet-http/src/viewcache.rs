@@ -0,0 +1,248 @@
+use std::{
+    borrow::Cow,
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, WrapErr};
+use log::{debug, tracing, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{
+    generator::ChartsData,
+    svg::TimeRange,
+    units::{AxisScale, ChartUnits},
+    ChartContext, Result,
+};
+
+/// First bytes of every view cache.
+const MAGIC: &[u8; 8] = b"SYSMETVC";
+/// Version of the layout of the cached charts, caches of another version are ignored then overwritten.
+const VERSION: u16 = 1;
+
+/// Charts of the default range as written to the view cache, without the snapshots they were generated from.
+#[derive(Debug, Serialize, Deserialize)]
+struct ViewCache<M> {
+    written_at: DateTime<Utc>,
+    generated_at: DateTime<Utc>,
+    range: TimeRange,
+    last_snapshot_time: Option<DateTime<Utc>>,
+    snapshots_count: usize,
+    units: ChartUnits,
+    // NOTE: Missing from the caches written before it, whose charts are replaced by the first actualization
+    #[serde(default)]
+    scale: AxisScale,
+    metrics: M,
+}
+
+/// View cache of the database, next to it, e.g. `/var/lib/sysmet/db.viewcache` for `/var/lib/sysmet/db`.
+pub fn path_for(database: &str) -> PathBuf {
+    // NOTE: The cache of a sharded database is next to its directory, not one of its shards
+    PathBuf::from(format!("{}.viewcache", database.trim_end_matches('/')))
+}
+
+/// Write the charts to the view cache, replacing it at once so a crash never leaves half of it.
+#[tracing::instrument(level = "debug", skip(charts))]
+pub fn save(path: &Path, charts: &ChartsData) -> Result<()> {
+    let age = chrono::Duration::from_std(charts.last_updated_time.elapsed())
+        .unwrap_or_else(|_| chrono::Duration::zero());
+    let now = Utc::now();
+    let cache = ViewCache {
+        written_at: now,
+        generated_at: now - age,
+        range: charts.range,
+        last_snapshot_time: charts.last_snapshot_time,
+        snapshots_count: charts.snapshots_count,
+        units: charts.units,
+        scale: charts.scale,
+        metrics: &charts.metrics,
+    };
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    ciborium::ser::into_writer(&cache, &mut bytes)
+        .map_err(|err| eyre!("Failed to encode the view cache: {err}"))?;
+
+    let partial = path.with_extension("viewcache.tmp");
+    let written = fs::File::create(&partial)
+        .and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&partial, path));
+    if written.is_err() {
+        fs::remove_file(&partial).ok();
+    }
+    written.wrap_err_with(|| format!("Failed to write the view cache {}", path.display()))?;
+    debug!(bytes = bytes.len(), "Wrote the view cache");
+
+    Ok(())
+}
+
+/// Charts of the view cache, `None` when there is none or it can't be used (e.g. written by another
+/// version or truncated).
+#[tracing::instrument(level = "debug")]
+pub fn load(path: &Path) -> Option<ChartsData> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            debug!("No view cache yet");
+            return None;
+        }
+        Err(err) => {
+            warn!("Failed to read the view cache {}: {err}", path.display());
+            return None;
+        }
+    };
+
+    let payload = match bytes
+        .strip_prefix(MAGIC.as_slice())
+        .and_then(|rest| Some((rest.get(..2)?, rest.get(2..)?)))
+    {
+        Some((version, payload)) if version == VERSION.to_le_bytes() => payload,
+        Some(_) => {
+            warn!(
+                "Ignoring the view cache {} written by another version",
+                path.display()
+            );
+            return None;
+        }
+        None => {
+            warn!("Ignoring {}, it is not a view cache", path.display());
+            return None;
+        }
+    };
+    let cache: ViewCache<Vec<(Cow<'static, str>, ChartContext)>> =
+        match ciborium::de::from_reader(payload) {
+            Ok(cache) => cache,
+            Err(err) => {
+                warn!("Ignoring the corrupt view cache {}: {err}", path.display());
+                return None;
+            }
+        };
+
+    // NOTE: Aged as when it was written so the first actualization refreshes it if needed
+    let age = (Utc::now() - cache.generated_at)
+        .to_std()
+        .unwrap_or_default();
+    Some(
+        ChartsData::builder()
+            .last_updated_time(Instant::now().checked_sub(age).unwrap_or_else(Instant::now))
+            .range(cache.range)
+            .last_snapshot_time(cache.last_snapshot_time)
+            .snapshots_count(cache.snapshots_count)
+            .compared_snapshots_count(None)
+            .units(cache.units)
+            .scale(cache.scale)
+            .metrics(cache.metrics)
+            .restored_from(Some(cache.written_at))
+            .build(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testing;
+
+    /// Path of the view cache of a database in a new directory, the directory being kept alive by the caller.
+    fn cache() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let path = path_for(&dir.path().join("sysmet.db").to_string_lossy());
+        (dir, path)
+    }
+
+    #[test]
+    fn caches_are_next_to_their_database() {
+        assert_eq!(
+            path_for("/var/lib/sysmet/db"),
+            PathBuf::from("/var/lib/sysmet/db.viewcache")
+        );
+        assert_eq!(
+            path_for("/var/lib/sysmet/shards/"),
+            PathBuf::from("/var/lib/sysmet/shards.viewcache")
+        );
+    }
+
+    #[test]
+    fn saved_charts_are_loaded_back_marked_as_restored() {
+        let (dir, path) = cache();
+        let charts = testing::charts();
+
+        let before = Utc::now();
+        save(&path, &charts).unwrap();
+        let restored = load(&path).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&restored.metrics).unwrap(),
+            serde_json::to_value(&charts.metrics).unwrap()
+        );
+        assert_eq!(restored.range, charts.range);
+        assert_eq!(restored.last_snapshot_time, charts.last_snapshot_time);
+        assert_eq!(restored.snapshots_count, charts.snapshots_count);
+        assert_eq!(restored.units, charts.units);
+        assert_eq!(restored.scale, charts.scale);
+        let written_at = restored.restored_from.unwrap();
+        assert!(before <= written_at && written_at <= Utc::now());
+        assert!(charts.restored_from.is_none());
+        // NOTE: Only the cache is left, the temporary file being renamed over it
+        let files = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec![path]);
+    }
+
+    #[test]
+    fn missing_caches_are_not_loaded() {
+        let (_dir, path) = cache();
+
+        assert!(load(&path).is_none());
+    }
+
+    #[test]
+    fn corrupt_caches_are_not_loaded() {
+        let (_dir, path) = cache();
+        save(&path, &testing::charts()).unwrap();
+        let saved = fs::read(&path).unwrap();
+
+        let mut other_version = saved.clone();
+        other_version[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let mut other_magic = saved.clone();
+        other_magic[0] = b'X';
+        for (name, bytes) in [
+            ("empty", Vec::new()),
+            ("magic only", MAGIC.to_vec()),
+            ("other version", other_version),
+            ("other magic", other_magic),
+            ("truncated", saved[..saved.len() / 2].to_vec()),
+            (
+                "garbage",
+                [
+                    MAGIC.as_slice(),
+                    &VERSION.to_le_bytes(),
+                    b"\xff\x00 not CBOR",
+                ]
+                .concat(),
+            ),
+        ] {
+            fs::write(&path, bytes).unwrap();
+            assert!(load(&path).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn corrupt_caches_are_replaced_on_save() {
+        let (_dir, path) = cache();
+        fs::write(&path, b"not a view cache").unwrap();
+
+        save(&path, &testing::charts()).unwrap();
+
+        assert!(load(&path).is_some());
+    }
+}
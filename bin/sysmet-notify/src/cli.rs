@@ -1,9 +1,15 @@
-use std::time::Duration;
+use std::{path::PathBuf, str::FromStr, time::Duration};
 
 use clap::Parser;
 use clap_verbosity_flag::Verbosity;
+use env::config::{
+    Config, DatabaseSection, NotifySection, RouteSection, RoutesSection, CONFIG_ENV,
+};
 use lettre::message::Mailbox;
 use log::{trace, tracing};
+use metrics::stats::Averaging;
+
+use crate::routes::Route;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -63,12 +69,53 @@ pub struct Cli {
         help = "Max Average Load before warning"
     )]
     pub avg_load_threshold: Option<u32>,
+    #[clap(
+        long,
+        env = "SWAP_ACTIVITY_THRESHOLD",
+        value_name = "RATE",
+        help = "Max pages swapped in and out per second, sustained over the window, before warning (database mode only)"
+    )]
+    pub swap_activity_threshold: Option<u32>,
+    #[clap(
+        long,
+        env = "CONNTRACK_THRESHOLD",
+        value_name = "PERCENTAGE",
+        value_parser = clap::value_parser!(u32).range(0..=100),
+        help = "Max conntrack entries in percent of nf_conntrack_max before warning (Linux only)"
+    )]
+    pub conntrack_threshold: Option<u32>,
+    #[clap(
+        long,
+        env = "NET_UTILIZATION_THRESHOLD",
+        value_name = "PERCENTAGE",
+        value_parser = clap::value_parser!(u32).range(0..=100),
+        help = "Max utilization of the busiest network link in percent of its speed, sustained over the window, before warning (database mode only)"
+    )]
+    pub net_utilization_threshold: Option<u32>,
+    #[clap(
+        long = "require-mount",
+        env = "NOTIFY_REQUIRE_MOUNTS",
+        value_delimiter = ',',
+        value_name = "MOUNTPOINT",
+        help = "Mountpoint whose absence is a critical alert, in database mode from the newest snapshot, can be repeated",
+        action = clap::ArgAction::Append
+    )]
+    pub require_mounts: Vec<String>,
+    #[clap(
+        long = "require-nic",
+        env = "NOTIFY_REQUIRE_NICS",
+        value_delimiter = ',',
+        value_name = "INTERFACE",
+        help = "Network interface whose absence is a critical alert, in database mode from the newest snapshot (Linux only), can be repeated",
+        action = clap::ArgAction::Append
+    )]
+    pub require_nics: Vec<String>,
     #[clap(
 		short,
 		long = "from",
 		env = "MAIL_FROM",
 		value_parser = mailbox_try_from_str,
-		required_unless_present("dry_run"),
+		required_unless_present_any(["dry_run", "print_config"]),
 		help = "Identity that will be used to send the mail"
 	)]
     pub from: Option<Mailbox>,
@@ -78,11 +125,20 @@ pub struct Cli {
 		env = "MAIL_CONTACTS",
 		value_delimiter = ',',
 		value_parser = mailbox_try_from_str,
-		required_unless_present("dry_run"),
+		required_unless_present_any(["dry_run", "print_config"]),
 		help = "Contacts that will receive the mail",
 		action = clap::ArgAction::Append
 	)]
     pub contacts: Vec<Mailbox>,
+    #[clap(
+        long = "route",
+        env = "NOTIFY_ROUTES",
+        value_delimiter = ';',
+        value_name = "metric=METRIC,severity=SEVERITY,contacts=CONTACTS",
+        value_parser = Route::from_str,
+        help = "Contacts of the thresholds of a metric and severity instead of --contacts, can be repeated"
+    )]
+    pub routes: Vec<Route>,
     #[clap(
         long = "cooldown",
         env = "MAIL_COOLDOWN",
@@ -94,21 +150,21 @@ pub struct Cli {
     #[clap(
         long = "smtp-user",
         env = "SMTP_USER",
-        required_unless_present("dry_run"),
+        required_unless_present_any(["dry_run", "print_config"]),
         help = "SMTP Username to authenticate with the Relay"
     )]
     pub smtp_user: Option<String>,
     #[clap(
         long = "smtp-pass",
         env = "SMTP_PASSWORD",
-        required_unless_present("dry_run"),
+        required_unless_present_any(["dry_run", "print_config"]),
         help = "SMTP Password to authenticate with the Relay"
     )]
     pub smtp_password: Option<String>,
     #[clap(
         long = "smtp-relay",
         env = "SMTP_RELAY",
-        required_unless_present("dry_run"),
+        required_unless_present_any(["dry_run", "print_config"]),
         help = "SMTP Relay that will be used to send the mail"
     )]
     pub smtp_relay: Option<String>,
@@ -127,6 +183,96 @@ pub struct Cli {
         help = "Timestamp of the last time a mail was sent"
     )]
     pub last_sent_instant: Option<String>,
+    #[clap(
+        long,
+        visible_alias = "db",
+        value_name = "FILE",
+        help = "Check the thresholds on the window of a database instead of the current system"
+    )]
+    pub database: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "NOTIFY_DATABASE_DIR",
+        value_name = "DIR",
+        conflicts_with = "database",
+        help = "Check every *.db database of the directory and send a single combined mail"
+    )]
+    pub database_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_DB_KEY",
+        hide_env_values = true,
+        value_name = "PASSPHRASE",
+        help = "Passphrase used to decrypt the databases, prefer the environment variable"
+    )]
+    pub db_key: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_DB_KEY_FILE",
+        value_name = "FILE",
+        help = "File containing the database passphrase"
+    )]
+    pub db_key_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "NOTIFY_WINDOW",
+        default_value = "15m",
+        value_parser = duration_try_from_str,
+        help = "Window of the database over which the usages are averaged"
+    )]
+    pub window: Duration,
+    #[clap(
+        long,
+        env = "NOTIFY_AVERAGING",
+        default_value_t = Averaging::TimeWeighted,
+        value_name = "AVERAGING",
+        help = "Average of the window, time-weighted so irregular snapshots don't skew it, or plain"
+    )]
+    pub averaging: Averaging,
+    #[clap(
+        long,
+        env = "NOTIFY_STALE_AFTER",
+        default_value = "30m",
+        value_parser = duration_try_from_str,
+        help = "Age of the last snapshot after which a database is reported as stale"
+    )]
+    pub stale_after: Duration,
+    #[clap(
+        long = "state-path",
+        env = "NOTIFY_STATE_PATH",
+        default_value = "/tmp/sysmet-notify-state.json",
+        help = "State of the checks: per host cooldowns, metric states and outcome of the last mail"
+    )]
+    pub state_path: PathBuf,
+    #[clap(
+        long,
+        env = "SYSMET_IGNORED_NETWORKS",
+        value_delimiter = ',',
+        value_name = "NETWORKS NAMES",
+        help = "Networks left out of the snapshot recorded in --database when a threshold is crossed"
+    )]
+    pub ignored_networks: Vec<String>,
+    #[clap(
+        long,
+        env = "NOTIFY_DASHBOARD_URL",
+        value_name = "URL",
+        help = "URL of sysmet-http, the mail links to the range around the incident"
+    )]
+    pub dashboard_url: Option<String>,
+    #[clap(
+        long,
+        env = "NOTIFY_INCLUDE_TOP",
+        value_name = "N",
+        help = "Add the N processes using the most CPU and the most memory to the alert mails"
+    )]
+    pub include_top: Option<usize>,
+    #[clap(
+        long,
+        env = "NOTIFY_INCLUDE_DMESG_LINES",
+        value_name = "N",
+        help = "Add the last N lines of the kernel log to the alert mails (Linux only)"
+    )]
+    pub include_dmesg_lines: Option<usize>,
     #[clap(
         long = "env",
         default_value = ".env",
@@ -134,12 +280,89 @@ pub struct Cli {
         required = false
     )]
     pub env_path: String,
+    #[clap(
+        long,
+        env = CONFIG_ENV,
+        value_name = "FILE",
+        help = "Path to the optional TOML config file"
+    )]
+    pub config: Option<PathBuf>,
+    #[clap(long, help = "Print the effective configuration and exit")]
+    pub print_config: bool,
     #[clap(long = "dry-run", help = "Simulate the run")]
     pub dry_run: bool,
     #[clap(flatten)]
     pub verbose: Verbosity,
 }
 
+impl Cli {
+    pub fn effective_config(&self) -> Config {
+        Config {
+            database: DatabaseSection {
+                key: self.db_key.clone(),
+                key_file: self.db_key_file.clone(),
+                ..Default::default()
+            },
+            notify: NotifySection {
+                cpu_threshold: self.cpu_threshold,
+                ram_threshold: self.ram_threshold,
+                swap_threshold: self.swap_threshold,
+                memory_threshold: self.memory_threshold,
+                disk_threshold: self.disk_threshold,
+                avg_load_threshold: self.avg_load_threshold,
+                swap_activity_threshold: self.swap_activity_threshold,
+                conntrack_threshold: self.conntrack_threshold,
+                net_utilization_threshold: self.net_utilization_threshold,
+                require_mounts: self.require_mounts.clone(),
+                require_nics: self.require_nics.clone(),
+                from: self.from.as_ref().map(|from| from.to_string()),
+                contacts: self
+                    .contacts
+                    .iter()
+                    .map(|contact| contact.to_string())
+                    .collect(),
+                cooldown: Some(humantime::format_duration(self.cooldown).to_string()),
+                smtp_user: self.smtp_user.clone(),
+                smtp_password: self.smtp_password.clone(),
+                smtp_relay: self.smtp_relay.clone(),
+                smtp_port: Some(self.smtp_port),
+                last_sent_path: self.last_sent_instant.clone(),
+                database_dir: self.database_dir.clone(),
+                window: Some(humantime::format_duration(self.window).to_string()),
+                averaging: Some(self.averaging.to_string()),
+                stale_after: Some(humantime::format_duration(self.stale_after).to_string()),
+                state_path: Some(self.state_path.clone()),
+                dashboard_url: self.dashboard_url.clone(),
+                include_top: self.include_top,
+                include_dmesg_lines: self.include_dmesg_lines,
+                ..Default::default()
+            },
+            routes: RoutesSection(
+                self.routes
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, route)| {
+                        (
+                            format!("route-{}", idx + 1),
+                            RouteSection {
+                                metric: route.metric.clone(),
+                                severity: route.severity.map(|severity| severity.to_string()),
+                                contacts: route
+                                    .contacts
+                                    .iter()
+                                    .map(|contact| contact.to_string())
+                                    .collect(),
+                                ..Default::default()
+                            },
+                        )
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+}
+
 #[tracing::instrument(level = "trace")]
 fn mailbox_try_from_str(value: &str) -> Result<Mailbox, lettre::address::AddressError> {
     let result = value.parse::<Mailbox>();
@@ -0,0 +1,239 @@
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use log::{debug, tracing};
+use metrics::processes::{format_process_table, TopProcesses, CPU_SAMPLE};
+
+use crate::cli::Cli;
+
+/// Time `dmesg` has to answer before the kernel log is reported as unavailable.
+#[cfg(target_os = "linux")]
+const DMESG_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sections describing the state of this host appended to the alert mails, `None` when neither
+/// `--include-top` nor `--include-dmesg-lines` asks for one.
+///
+/// A section that could not be collected says why instead of delaying or failing the alert.
+#[tracing::instrument(skip(app))]
+pub fn system_context(app: &Cli) -> Option<String> {
+    let mut sections = Vec::new();
+    if let Some(count) = app.include_top.filter(|count| *count > 0) {
+        match TopProcesses::current(count, CPU_SAMPLE) {
+            Ok(top) => {
+                sections.push(format!(
+                    "Top processes by CPU:\n{}",
+                    format_process_table(&top.by_cpu)
+                ));
+                sections.push(format!(
+                    "Top processes by memory:\n{}",
+                    format_process_table(&top.by_memory)
+                ));
+            }
+            Err(err) => {
+                debug!(%err, "Failed to list the processes");
+                sections.push(format!("Top processes by CPU: unavailable: {err}\n"));
+                sections.push(format!("Top processes by memory: unavailable: {err}\n"));
+            }
+        }
+    }
+    if let Some(lines) = app.include_dmesg_lines.filter(|lines| *lines > 0) {
+        sections.push(kernel_log_section(Path::new("dmesg"), lines));
+    }
+
+    (!sections.is_empty()).then(|| sections.join("\n"))
+}
+
+/// Section of the last `lines` lines of the kernel log read by the `dmesg` program, or why it is unavailable.
+fn kernel_log_section(dmesg: &Path, lines: usize) -> String {
+    match kernel_log_tail(dmesg, lines) {
+        Ok(tail) => format!("Kernel log:\n{tail}"),
+        Err(reason) => {
+            debug!(%reason, "Failed to read the kernel log");
+            format!("Kernel log: unavailable: {reason}\n")
+        }
+    }
+}
+
+/// Last `lines` lines of the kernel ring buffer, or why it could not be read (e.g. `dmesg_restrict` for
+/// unprivileged users).
+#[cfg(target_os = "linux")]
+fn kernel_log_tail(dmesg: &Path, lines: usize) -> Result<String, String> {
+    use std::{
+        io::Read,
+        process::{Command, Stdio},
+        thread,
+        time::Instant,
+    };
+
+    // NOTE: Read through dmesg as /dev/kmsg blocks once its records are drained
+    let mut child = Command::new(dmesg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to run dmesg: {err}"))?;
+    // NOTE: Read meanwhile so a large buffer never fills the pipe and blocks dmesg
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + DMESG_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "dmesg did not answer within {}",
+                    humantime::format_duration(DMESG_TIMEOUT)
+                ));
+            }
+            Err(err) => return Err(format!("failed to wait for dmesg: {err}")),
+        }
+    };
+    let output = reader
+        .join()
+        .ok()
+        .ok_or_else(|| "failed to read the output of dmesg".to_string())?
+        .map_err(|err| format!("failed to read the output of dmesg: {err}"))?;
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        return Err(match stderr.trim() {
+            "" => format!("dmesg failed with {status}"),
+            stderr => stderr.to_string(),
+        });
+    }
+
+    let output = output.lines().collect::<Vec<_>>();
+    let mut tail = output[output.len().saturating_sub(lines)..].join("\n");
+    tail.push('\n');
+    Ok(tail)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel_log_tail(_dmesg: &Path, _lines: usize) -> Result<String, String> {
+    Err("only available on Linux".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn no_section_is_collected_unless_asked() {
+        let fixture = Fixture::new();
+
+        assert_eq!(system_context(&fixture.cli(&[])), None);
+        assert_eq!(
+            system_context(&fixture.cli(&["--include-top", "0", "--include-dmesg-lines", "0"])),
+            None
+        );
+    }
+
+    #[test]
+    fn top_processes_are_listed_by_cpu_and_by_memory() {
+        let fixture = Fixture::new();
+
+        let context = system_context(&fixture.cli(&["--include-top", "3"])).unwrap();
+
+        let (by_cpu, by_memory) = context.split_once("\n\n").unwrap();
+        for (section, title) in [
+            (by_cpu, "Top processes by CPU:"),
+            (by_memory, "Top processes by memory:"),
+        ] {
+            let mut lines = section.lines();
+            assert_eq!(lines.next(), Some(title));
+            assert!(lines.next().unwrap().trim_start().starts_with("PID"));
+            assert!((1..=3).contains(&lines.count()), "{section}");
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    mod kernel_log {
+        use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+        use tempfile::TempDir;
+
+        use super::*;
+
+        /// Executable standing for dmesg, running the shell `script`.
+        fn fake_dmesg(dir: &TempDir, script: &str) -> PathBuf {
+            let path = dir.path().join("dmesg");
+            fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        }
+
+        #[test]
+        fn only_the_last_lines_are_kept() {
+            let dir = TempDir::new().unwrap();
+            let dmesg = fake_dmesg(
+                &dir,
+                "for line in 1 2 3 4 5; do echo \"[    $line.0] line $line\"; done",
+            );
+
+            assert_eq!(
+                kernel_log_section(&dmesg, 2),
+                "Kernel log:\n[    4.0] line 4\n[    5.0] line 5\n"
+            );
+            assert_eq!(kernel_log_section(&dmesg, 10).lines().count(), 6);
+        }
+
+        #[test]
+        fn denied_reads_say_why() {
+            let dir = TempDir::new().unwrap();
+            let dmesg = fake_dmesg(
+                &dir,
+                "echo 'dmesg: read kernel buffer failed: Operation not permitted' >&2\nexit 1",
+            );
+
+            assert_eq!(
+                kernel_log_section(&dmesg, 10),
+                "Kernel log: unavailable: dmesg: read kernel buffer failed: Operation not permitted\n"
+            );
+        }
+
+        #[test]
+        fn silent_failures_give_their_status() {
+            let dir = TempDir::new().unwrap();
+            let dmesg = fake_dmesg(&dir, "exit 3");
+
+            assert_eq!(
+                kernel_log_section(&dmesg, 10),
+                "Kernel log: unavailable: dmesg failed with exit status: 3\n"
+            );
+        }
+
+        #[test]
+        fn missing_dmesg_is_reported() {
+            let dir = TempDir::new().unwrap();
+
+            let section = kernel_log_section(&dir.path().join("dmesg"), 10);
+
+            assert!(
+                section.starts_with("Kernel log: unavailable: failed to run dmesg: "),
+                "{section}"
+            );
+        }
+
+        #[test]
+        fn hanging_dmesg_is_given_up_on() {
+            let dir = TempDir::new().unwrap();
+            let dmesg = fake_dmesg(&dir, "exec sleep 10");
+
+            assert_eq!(
+                kernel_log_section(&dmesg, 10),
+                "Kernel log: unavailable: dmesg did not answer within 2s\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,800 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use log::{debug, info, trace, tracing, warn};
+use metrics::{
+    prelude::*,
+    stats::Averaging,
+    thresholds::state::{NotifyState, SendResult},
+};
+
+use crate::{
+    alert_lines, alert_text, alerts_header, checked_metrics,
+    cli::Cli,
+    context::system_context,
+    crossed,
+    mail::{dashboard_link, format_snapshot, send_mail, MailTransport},
+    routes::{split_by_recipients, Router},
+    state, writer, Alert, LastSeen, MissingItem, Outcome, PercentSnapshot, Result,
+};
+
+const DATABASE_EXTENSION: &str = "db";
+
+#[derive(Debug)]
+struct HostReport {
+    hostname: String,
+    alerts: Vec<Alert>,
+    snapshot: PercentSnapshot,
+}
+
+/// Part of the mails routed on its own.
+#[derive(Debug)]
+enum MailItem<'a> {
+    /// Threshold crossed or required item missing on the host of the report.
+    Alert(&'a HostReport, Alert),
+    /// Problem of a host, e.g. an unreadable database.
+    Problem(&'a (String, String)),
+}
+
+impl MailItem<'_> {
+    fn hostname(&self) -> &str {
+        match self {
+            MailItem::Alert(report, _) => &report.hostname,
+            MailItem::Problem((hostname, _)) => hostname,
+        }
+    }
+}
+
+/// Check the thresholds of every database and send a single mail for all of them.
+#[tracing::instrument(skip(app, transport))]
+pub fn check(app: &Cli, transport: &impl MailTransport, now: DateTime<Utc>) -> Result<Outcome> {
+    let paths = match (&app.database, &app.database_dir) {
+        (Some(path), _) => vec![path.clone()],
+        (None, Some(dir)) => list_databases(dir)?,
+        (None, None) => return Err(eyre!("No database to check")),
+    };
+    info!("Checking {} databases", paths.len());
+
+    let key = DatabaseKey::resolve(app.db_key.as_deref(), app.db_key_file.as_deref())?;
+    // NOTE: A dry run leaves the state as it was, as it sends nothing
+    let mut state = state::load(&app.state_path);
+    state.checked_at = Some(now);
+    state.cooldown_seconds = app.cooldown.as_secs();
+    let is_in_cooldown = |state: &NotifyState, hostname: &str| {
+        let result = state.next_eligible(hostname, now).is_some();
+        if result {
+            info!("Skipping {hostname}, we are before the end of its cooldown");
+        }
+        result
+    };
+
+    let mut reports = Vec::new();
+    let mut problems = Vec::new();
+    let mut incident = None;
+    for path in paths {
+        let database = match Database::from_file(&path.to_string_lossy(), key.as_ref()) {
+            Ok(database) => database,
+            Err(err) => {
+                warn!("Failed to load {path:?}: {err}");
+                let hostname = hostname_from_path(&path);
+                let problem = format!("unreadable database ({err})");
+                state::record_problem(&mut state, &hostname, &problem);
+                if !is_in_cooldown(&state, &hostname) {
+                    problems.push((hostname, problem));
+                }
+                continue;
+            }
+        };
+        let hostname = database.hostname().map_or_else(
+            || hostname_from_path(&path),
+            |hostname| hostname.to_string(),
+        );
+
+        if is_in_cooldown(&state, &hostname) {
+            continue;
+        }
+
+        match database.snapshots().last().map(|snap| snap.time) {
+            None => {
+                state::record_problem(&mut state, &hostname, "empty database");
+                problems.push((hostname, "empty database".to_string()));
+                continue;
+            }
+            Some(last_snapshot_time)
+                if now - last_snapshot_time > chrono::Duration::from_std(app.stale_after)? =>
+            {
+                let problem = format!(
+                    "stale database, no snapshot since {}",
+                    last_snapshot_time.format("%d/%m/%Y %H:%M")
+                );
+                state::record_problem(&mut state, &hostname, &problem);
+                problems.push((hostname, problem));
+                continue;
+            }
+            Some(_) => (),
+        }
+
+        let Some(snapshot) = window_snapshot(&database, now, app.window, app.averaging)? else {
+            continue;
+        };
+        trace!(snapshot =? snapshot, "Window snapshot of {hostname}");
+        let alerts = crossed(app, &snapshot)
+            .into_iter()
+            .map(Alert::Threshold)
+            .chain(
+                missing_items(app, &database)
+                    .into_iter()
+                    .map(Alert::Missing),
+            )
+            .collect::<Vec<_>>();
+        state::record_check(
+            &mut state,
+            &hostname,
+            &checked_metrics(app, &snapshot),
+            &alerts,
+            now,
+        );
+        if !alerts.is_empty() {
+            info!("At least one alert on {hostname}!");
+            // NOTE: Only a single database is the one of this host, the ones of a directory come from others
+            if app.database.is_some() && !app.dry_run {
+                incident = take_incident_snapshot(app).map(|incident_snapshot| {
+                    (path.clone(), incident_snapshot, alert_text(&alerts))
+                });
+            }
+            reports.push(HostReport {
+                hostname,
+                alerts,
+                snapshot,
+            });
+        }
+    }
+
+    if reports.is_empty() && problems.is_empty() {
+        info!("Finishing early because no threshold have been crossed");
+        if !app.dry_run {
+            state::save(&app.state_path, &state)?;
+        }
+        return Ok(Outcome::NoThresholdCrossed);
+    }
+
+    // NOTE: Each set of recipients only gets the thresholds routed to it, problems go to the default contacts
+    let router = Router::new(&app.routes, &app.contacts);
+    // NOTE: The processes and kernel log are the ones of this host, only a single database is its own
+    let context = (app.database.is_some() && !reports.is_empty())
+        .then(|| system_context(app))
+        .flatten();
+    let items = reports
+        .iter()
+        .flat_map(|report| {
+            report.alerts.iter().map(|alert| {
+                (
+                    router.recipients(alert.metric(), alert.severity()),
+                    MailItem::Alert(report, alert.clone()),
+                )
+            })
+        })
+        .chain(
+            problems
+                .iter()
+                .map(|problem| (router.default_recipients(), MailItem::Problem(problem))),
+        );
+    let mut mails = Vec::new();
+    for (recipients, items) in split_by_recipients(items) {
+        let mut hostnames = items
+            .iter()
+            .map(|item| item.hostname().to_string())
+            .collect::<Vec<_>>();
+        hostnames.dedup();
+        let mail = format_mail(app, now, items, context.as_deref())?;
+        debug!(
+            body = mail.1.as_str(),
+            ?recipients,
+            "Body that will be sent"
+        );
+        mails.push((recipients, hostnames, mail));
+    }
+
+    if app.dry_run {
+        info!(
+            "Finishing early because there is no need to send a mail, the app is in dry-run mode"
+        );
+        return Ok(Outcome::DryRun);
+    }
+
+    // NOTE: A failed mail leaves the cooldowns as is, the next check sends every mail again
+    let mut results = HashMap::<String, SendResult>::new();
+    for (recipients, hostnames, (server_ident, body)) in mails {
+        let result = send_mail(app, transport, &recipients, &server_ident, &body)?;
+        // NOTE: A host is recorded with the first failure of its mails
+        for hostname in hostnames {
+            let host_result = results.entry(hostname).or_insert(SendResult::Sent);
+            if host_result.is_sent() {
+                *host_result = result.clone();
+            }
+        }
+    }
+    let is_sent = results.values().all(SendResult::is_sent);
+    for (hostname, result) in results {
+        state::record_attempt(&mut state, &hostname, now, result);
+    }
+    // NOTE: Recorded after the mails so a slow or locked database never delays them
+    if let Some((path, snapshot, text)) = incident {
+        record_incident(&path, key.as_ref(), snapshot, text);
+    }
+    if !is_sent {
+        state::save(&app.state_path, &state)?;
+        return Ok(Outcome::SendFailed);
+    }
+    for hostname in reports
+        .iter()
+        .map(|report| &report.hostname)
+        .chain(problems.iter().map(|(hostname, _)| hostname))
+    {
+        state::record_sent(&mut state, hostname, now);
+    }
+    state::save(&app.state_path, &state)?;
+
+    Ok(Outcome::Sent)
+}
+
+/// Full snapshot of this host at the time thresholds crossed, `None` when it could not be taken.
+fn take_incident_snapshot(app: &Cli) -> Option<SnapShot> {
+    let networks_to_ignore = app
+        .ignored_networks
+        .iter()
+        .map(|n| n.as_ref())
+        .collect::<Vec<&str>>();
+    SnapShot::new(&networks_to_ignore)
+        .map_err(|err| warn!("Failed to take the incident snapshot: {err}"))
+        .ok()
+}
+
+/// Append the incident snapshot to the database with an alert annotation, failures are only logged.
+#[tracing::instrument(skip(key, snapshot))]
+fn record_incident(path: &Path, key: Option<&DatabaseKey>, snapshot: SnapShot, text: String) {
+    if let Err(err) = try_record_incident(path, key, snapshot, text) {
+        warn!("Failed to record the incident in {path:?}: {err}");
+    }
+}
+
+fn try_record_incident(
+    path: &Path,
+    key: Option<&DatabaseKey>,
+    snapshot: SnapShot,
+    text: String,
+) -> Result<()> {
+    let (mut database, file, lock_path) =
+        Database::from_file_with_write(&path.to_string_lossy(), key)?;
+    let hostname = get_hostname();
+    if let Some(database_hostname) = database
+        .hostname()
+        .filter(|database_hostname| *database_hostname != hostname)
+        .map(str::to_string)
+    {
+        database.close_file(&lock_path)?;
+        return Err(eyre!(
+            "it is the database of {database_hostname}, not of {hostname}"
+        ));
+    }
+
+    database.set_writer(writer());
+    database.push_snapshot(snapshot);
+    // NOTE: The snapshot time may have been clamped after the last one
+    let time = database
+        .snapshots()
+        .last()
+        .map_or_else(Utc::now, |snap| snap.time);
+    database.annotations.push(Annotation {
+        time,
+        kind: AnnotationKind::Alert,
+        text,
+    });
+    if let Err(err) = database.write_and_close_file(file, &lock_path) {
+        database.close_file(&lock_path)?;
+        return Err(err.into());
+    }
+    info!("Incident recorded in {path:?}");
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug")]
+fn list_databases(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        // NOTE: Dangling links are kept, the database that went missing is reported as unreadable
+        .filter(|path| {
+            !path.is_dir()
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension == DATABASE_EXTENSION)
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    debug!(databases =? paths);
+
+    Ok(paths)
+}
+
+fn hostname_from_path(path: &Path) -> String {
+    path.file_stem().map_or_else(
+        || path.to_string_lossy().to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    )
+}
+
+/// Mountpoints and network interfaces of `--require-mount` and `--require-nic` missing from the newest
+/// snapshot, with the last snapshot holding them.
+#[tracing::instrument(level = "debug", skip(app, database))]
+fn missing_items(app: &Cli, database: &Database) -> Vec<MissingItem> {
+    let Some(newest) = database.snapshots().last() else {
+        return Vec::new();
+    };
+    let last_seen = |time: Option<DateTime<Utc>>| Some(time.map_or(LastSeen::Never, LastSeen::At));
+    let mounts = app
+        .require_mounts
+        .iter()
+        .filter(|mount| {
+            !newest.disks_memory.contains_key(*mount) && !newest.disks_usage.contains_key(*mount)
+        })
+        .map(|mount| MissingItem {
+            metric: "mount",
+            name: mount.clone(),
+            last_seen: last_seen(database.mount_last_seen(mount)),
+        });
+    let nics = app
+        .require_nics
+        .iter()
+        .filter(|nic| !newest.nic_info.contains_key(*nic))
+        .map(|nic| MissingItem {
+            metric: "nic",
+            name: nic.clone(),
+            last_seen: last_seen(database.nic_last_seen(nic)),
+        });
+    let result = mounts.chain(nics).collect::<Vec<_>>();
+    debug!(missing = ?result);
+
+    result
+}
+
+/// Average usages over the `window` preceding `now`, `None` when there is no snapshot in it.
+#[tracing::instrument(level = "debug", skip(database))]
+fn window_snapshot(
+    database: &Database,
+    now: DateTime<Utc>,
+    window: Duration,
+    averaging: Averaging,
+) -> Result<Option<PercentSnapshot>> {
+    let range = now - chrono::Duration::from_std(window)?..now;
+    let Some(last_snapshot) = database
+        .snapshots()
+        .iter()
+        .rev()
+        .find(|snapshot| range.contains(&snapshot.time))
+    else {
+        return Ok(None);
+    };
+
+    let values = |metric| {
+        database
+            .series(metric, range.clone(), None)
+            .map(|(time, value)| (value, time))
+            .collect::<Vec<_>>()
+    };
+    let average = |values: Vec<(f64, DateTime<Utc>)>| {
+        averaging
+            .mean(&values, Some(range.clone()))
+            .unwrap_or_default() as f32
+    };
+    // NOTE: The median ignores short bursts so only sustained paging is reported
+    let swap_activity = {
+        let swapped_out_rates = database
+            .series(MetricKind::SwapOutRate, range.clone(), None)
+            .collect::<HashMap<_, _>>();
+        let mut rates = database
+            .series(MetricKind::SwapInRate, range.clone(), None)
+            .filter_map(|(time, swapped_in)| Some(swapped_in + swapped_out_rates.get(&time)?))
+            .collect::<Vec<_>>();
+        rates.sort_by(f64::total_cmp);
+        rates.get(rates.len() / 2).map(|rate| *rate as f32)
+    };
+    let conntrack = Some(values(MetricKind::KernelGaugePercent {
+        gauge: "conntrack".to_string(),
+    }))
+    .filter(|values| !values.is_empty())
+    .map(average);
+    // NOTE: Links are averaged apart, a saturated uplink isn't hidden by an idle second interface
+    let net_utilization = last_snapshot
+        .nic_info
+        .iter()
+        .filter(|(_, link)| link.speed_mbps.is_some())
+        .filter_map(|(nic, _)| {
+            Some(values(MetricKind::NetUtilizationPercent {
+                nic: Some(nic.clone()),
+            }))
+            .filter(|values| !values.is_empty())
+            .map(average)
+        })
+        .reduce(f32::max);
+    let ram = average(values(MetricKind::RamPercent));
+    let swap = average(values(MetricKind::SwapPercent));
+
+    Ok(Some(PercentSnapshot {
+        cpu: average(values(MetricKind::CpuPercent)),
+        ram,
+        swap,
+        memory: (ram + swap) / 2.0,
+        // NOTE: The fullest disk is the one that matters
+        disk: last_snapshot
+            .get_disks_size_usage()
+            .into_iter()
+            .fold(0f64, |max, (_, usage)| max.max(usage)) as f32,
+        avg_load: average(values(MetricKind::Load15)),
+        swap_activity,
+        conntrack,
+        net_utilization,
+    }))
+}
+
+/// Server identity and body of the mail of `items`, the thresholds being grouped by host.
+fn format_mail(
+    app: &Cli,
+    now: DateTime<Utc>,
+    items: Vec<MailItem<'_>>,
+    context: Option<&str>,
+) -> Result<(String, String)> {
+    let mut reports: Vec<(&HostReport, Vec<Alert>)> = Vec::new();
+    let mut problems = Vec::new();
+    for item in items {
+        match item {
+            MailItem::Alert(report, alert) => {
+                match reports
+                    .iter_mut()
+                    .find(|(other, _)| std::ptr::eq(*other, report))
+                {
+                    Some((_, host_alerts)) => host_alerts.push(alert),
+                    None => reports.push((report, vec![alert])),
+                }
+            }
+            MailItem::Problem(problem) => problems.push(problem),
+        }
+    }
+
+    let mut body = format_body(&reports, &problems)?;
+    if let Some(link) = dashboard_link(app, now).filter(|_| !reports.is_empty()) {
+        body.push_str("\n\n");
+        body.push_str(&link);
+    }
+    if let Some(context) = context.filter(|_| !reports.is_empty()) {
+        body.push_str("\n\n");
+        body.push_str(context);
+    }
+    let server_ident = match (reports.len() + problems.len(), reports.first()) {
+        (1, Some((report, _))) => report.hostname.clone(),
+        (1, None) => problems[0].0.clone(),
+        (hosts, _) => format!("{hosts} hosts"),
+    };
+
+    Ok((server_ident, body))
+}
+
+fn format_body(
+    reports: &[(&HostReport, Vec<Alert>)],
+    problems: &[&(String, String)],
+) -> Result<String> {
+    let mut body = String::new();
+    if !reports.is_empty() {
+        let alerts = reports
+            .iter()
+            .flat_map(|(_, alerts)| alerts.iter().cloned())
+            .collect::<Vec<_>>();
+        body.push_str(alerts_header(&alerts));
+        for (report, alerts) in reports {
+            body.push_str(&format!("\n## {}\n", report.hostname));
+            body.push_str(&alert_lines(alerts)?);
+            body.push('\n');
+            body.push_str(&format_snapshot(&report.snapshot)?);
+        }
+    }
+    if !problems.is_empty() {
+        if !body.is_empty() {
+            body.push_str("\n\n");
+        }
+        body.push_str("Problems:\n");
+        for (hostname, problem) in problems {
+            body.push_str(&format!("- {hostname}: {problem}\n"));
+        }
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run,
+        testing::{CapturingTransport, Fixture},
+    };
+
+    #[test]
+    fn crossings_record_an_incident_snapshot() {
+        let fixture = Fixture::new();
+        let before = Database::from_file(&fixture.database().to_string_lossy(), None).unwrap();
+        let transport = CapturingTransport::default();
+
+        let cli = fixture.cli(&["--dashboard-url", "http://sysmet.local/"]);
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+
+        let after = Database::from_file(&fixture.database().to_string_lossy(), None).unwrap();
+        assert_eq!(after.len(), before.len() + 1);
+        let incident = after.snapshots().last().unwrap().time;
+        let alert = after
+            .annotations
+            .iter()
+            .find(|annotation| annotation.kind == AnnotationKind::Alert)
+            .unwrap();
+        assert_eq!(alert.time, incident);
+        assert!(alert.text.starts_with("alert: cpu "), "{}", alert.text);
+        let link = format!(
+            "Dashboard: http://sysmet.local/?from={}&to={}",
+            (fixture.now - chrono::Duration::hours(1)).timestamp(),
+            (fixture.now + chrono::Duration::hours(1)).timestamp()
+        );
+        assert!(transport.mails()[0].contains(&link));
+    }
+
+    #[test]
+    fn mails_are_sent_with_an_unwritable_database() {
+        let fixture = Fixture::new();
+        let database = fixture.database();
+        let transport = CapturingTransport {
+            // NOTE: The database becomes a directory once read, writing the incident to it fails
+            on_send: Some(Box::new(move || {
+                fs::remove_file(&database).unwrap();
+                fs::create_dir(&database).unwrap();
+            })),
+            ..CapturingTransport::default()
+        };
+
+        assert_eq!(
+            run(&fixture.cli(&[]), &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+        assert_eq!(transport.mails().len(), 1);
+        assert!(fixture.database().is_dir());
+        assert!(!fixture.dir.path().join("sysmet.db.lock").exists());
+        assert_eq!(
+            fixture.state().hosts[&get_hostname()].last_sent,
+            Some(fixture.now)
+        );
+    }
+
+    #[test]
+    fn dry_runs_record_no_incident() {
+        let fixture = Fixture::new();
+        let before = fs::read(fixture.database()).unwrap();
+
+        let outcome = run(
+            &fixture.cli(&["--dry-run"]),
+            &CapturingTransport::default(),
+            &fixture.clock(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::DryRun);
+        assert_eq!(fs::read(fixture.database()).unwrap(), before);
+    }
+
+    #[test]
+    fn conntrack_is_averaged_over_the_window_once_known() {
+        use metrics::psutil::{KernelGauge, KernelGauges};
+
+        let fixture = Fixture::new();
+        let mut database =
+            Database::from_file(&fixture.database().to_string_lossy(), None).unwrap();
+        let window = Duration::from_secs(10 * 60);
+        let snapshot = |database: &Database| {
+            window_snapshot(database, fixture.now, window, Averaging::Plain)
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(snapshot(&database).conntrack, None);
+
+        let mut newest = database.snapshots().last().unwrap().clone();
+        newest.time = fixture.now - chrono::Duration::seconds(30);
+        newest.kernel = Some(KernelGauges(
+            [(
+                "conntrack".to_string(),
+                KernelGauge {
+                    value: 95.0,
+                    max: Some(100.0),
+                },
+            )]
+            .into(),
+        ));
+        database.push_snapshot(newest);
+
+        assert_eq!(snapshot(&database).conntrack, Some(95.0));
+    }
+
+    #[test]
+    fn net_utilization_is_only_known_for_links_with_a_speed() {
+        let fixture = Fixture::new();
+        let database = Database::from_file(&fixture.database().to_string_lossy(), None).unwrap();
+        let utilization = |database: &Database| {
+            window_snapshot(
+                database,
+                fixture.now,
+                Duration::from_secs(10 * 60),
+                Averaging::Plain,
+            )
+            .unwrap()
+            .unwrap()
+            .net_utilization
+        };
+
+        let percent = utilization(&database).unwrap();
+        assert!((0.0..=100.0).contains(&percent), "{percent}");
+
+        let mut virtual_only = Database::default();
+        for snapshot in database.snapshots() {
+            let mut snapshot = snapshot.clone();
+            for link in snapshot.nic_info.values_mut() {
+                link.speed_mbps = None;
+            }
+            virtual_only.push_snapshot(snapshot);
+        }
+        assert_eq!(utilization(&virtual_only), None);
+    }
+
+    #[test]
+    fn windows_are_time_weighted_unless_asked_otherwise() {
+        use clap::Parser;
+
+        let fixture = Fixture::new();
+
+        assert_eq!(fixture.cli(&[]).averaging, Averaging::TimeWeighted);
+        assert_eq!(
+            fixture.cli(&["--averaging", "plain"]).averaging,
+            Averaging::Plain
+        );
+        assert!(Cli::try_parse_from(["sysmet-notify", "--averaging", "median"]).is_err());
+    }
+
+    #[test]
+    fn required_items_missing_from_the_newest_snapshot_are_alerted() {
+        let fixture = Fixture::new();
+        let database = Database::from_file(&fixture.database().to_string_lossy(), None).unwrap();
+        let snapshots = database.snapshots();
+        let gone = snapshots[snapshots.len() - 5].time;
+        // NOTE: /home unmounted and eth0 removed for the last 5 snapshots
+        let mut rewritten = Database::default();
+        for snapshot in snapshots {
+            let mut snapshot = snapshot.clone();
+            if snapshot.time >= gone {
+                snapshot.disks_memory.remove("/home");
+                snapshot.disks_usage.remove("/home");
+                snapshot.nic_info.remove("eth0");
+            }
+            rewritten.push_snapshot(snapshot);
+        }
+        rewritten
+            .write_to_file(&fixture.database().to_string_lossy())
+            .unwrap();
+        let cli = fixture.cli(&[
+            "--require-mount",
+            "/",
+            "--require-mount",
+            "/home",
+            "--require-nic",
+            "eth0,wlan9",
+        ]);
+        let report = |database: &Database| missing_items(&cli, database);
+
+        let missing = report(&rewritten);
+        assert_eq!(
+            missing
+                .iter()
+                .map(|missing| (missing.metric, missing.name.as_str(), missing.last_seen))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "mount",
+                    "/home",
+                    Some(LastSeen::At(gone - chrono::Duration::minutes(1)))
+                ),
+                (
+                    "nic",
+                    "eth0",
+                    Some(LastSeen::At(gone - chrono::Duration::minutes(1)))
+                ),
+                ("nic", "wlan9", Some(LastSeen::Never)),
+            ]
+        );
+        assert_eq!(report(&database).len(), 1);
+
+        let transport = CapturingTransport::default();
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+        let mail = &transport.mails()[0];
+        let last_seen = (gone - chrono::Duration::minutes(1)).format("%d/%m/%Y %H:%M");
+        for line in [
+            "Thresholds crossed:".to_string(),
+            "- CPU threshold crossed (0%): observed".to_string(),
+            format!("- Mountpoint /home missing, last seen {last_seen}, critical"),
+            format!("- Network interface eth0 missing, last seen {last_seen}, critical"),
+            "- Network interface wlan9 missing, never seen, critical".to_string(),
+        ] {
+            assert!(mail.contains(&line), "{line:?} missing from {mail}");
+        }
+        assert!(!mail.contains("Mountpoint / missing"), "{mail}");
+    }
+
+    #[test]
+    fn batches_report_unreadable_databases_as_problems() {
+        let fixture = Fixture::new();
+        let batch = fixture.dir.path().join("batch");
+        fs::create_dir(&batch).unwrap();
+        fs::copy(fixture.database(), batch.join("web.db")).unwrap();
+        std::os::unix::fs::symlink(batch.join("gone.db"), batch.join("missing.db")).unwrap();
+        fs::write(batch.join("corrupt.db"), b"not a database").unwrap();
+        let mut vault = Database::from_file(&fixture.database().to_string_lossy(), None).unwrap();
+        vault.set_key(Some(DatabaseKey::new("secret").unwrap()));
+        vault
+            .write_to_file(&batch.join("vault.db").to_string_lossy())
+            .unwrap();
+        fs::write(batch.join("notes.txt"), b"not checked").unwrap();
+        let mut cli = fixture.cli(&[]);
+        cli.database = None;
+        cli.database_dir = Some(batch.clone());
+        let transport = CapturingTransport::default();
+
+        // NOTE: The batch isn't aborted, `main` exits with 0
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+        let mails = transport.mails();
+        assert_eq!(mails.len(), 1);
+        let mail = &mails[0];
+        assert!(mail.contains("4 hosts"), "{mail}");
+        assert!(mail.contains(&format!("## {}", get_hostname())), "{mail}");
+        assert!(mail.contains("Problems:"), "{mail}");
+        for hostname in ["corrupt", "missing", "vault"] {
+            let problem = format!("- {hostname}: unreadable database (");
+            assert!(mail.contains(&problem), "{problem:?} missing from {mail}");
+        }
+        assert!(!mail.contains("notes"), "{mail}");
+        let state = fixture.state();
+        for hostname in [
+            get_hostname(),
+            "corrupt".into(),
+            "missing".into(),
+            "vault".into(),
+        ] {
+            assert_eq!(
+                state.hosts[&hostname].last_sent,
+                Some(fixture.now),
+                "{hostname}"
+            );
+        }
+
+        // NOTE: Without a directory to check there is no batch, `main` exits with 1
+        cli.database_dir = Some(batch.join("gone"));
+        assert!(run(&cli, &transport, &fixture.clock()).is_err());
+        assert_eq!(transport.mails().len(), 1);
+    }
+}
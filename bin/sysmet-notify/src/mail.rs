@@ -1,27 +1,122 @@
-use std::{
-    fmt::{Debug, Display},
-    str::FromStr,
-};
+use std::{str::FromStr, time::Duration};
 
-use lettre::{message::Mailbox, Message};
-use log::tracing;
+use chrono::{DateTime, Utc};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Message, SmtpTransport,
+    Transport,
+};
+use log::{error, info, tracing};
+use metrics::thresholds::state::SendResult;
 use rust_decimal::prelude::Decimal;
 
-use crate::{PercentSnapshot, Result};
+use crate::{cli::Cli, CrossedThreshold, LastSeen, MissingItem, PercentSnapshot, Result};
+
+/// Time shown on each side of the incident by the dashboard link.
+const INCIDENT_CONTEXT: Duration = Duration::from_secs(60 * 60);
+
+/// Way the generated mails leave the binary.
+pub trait MailTransport {
+    fn send(&self, email: &Message) -> Result<()>;
+}
+
+/// Relay given on the command line, connected to for each mail.
+#[derive(Debug, Clone)]
+pub struct SmtpMailer {
+    relay: Option<String>,
+    port: u16,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+impl SmtpMailer {
+    pub fn from_cli(app: &Cli) -> Self {
+        Self {
+            relay: app.smtp_relay.clone(),
+            port: app.smtp_port,
+            user: app.smtp_user.clone(),
+            password: app.smtp_password.clone(),
+        }
+    }
+}
+
+impl MailTransport for SmtpMailer {
+    fn send(&self, email: &Message) -> Result<()> {
+        // NOTE: Clap requires the SMTP options unless in dry-run, where nothing is sent
+        let mailer = SmtpTransport::relay(self.relay.as_deref().unwrap())?
+            .port(self.port)
+            .credentials(Credentials::new(
+                self.user.clone().unwrap(),
+                self.password.clone().unwrap(),
+            ))
+            .build();
+        MailTransport::send(&mailer, email)
+    }
+}
+
+impl MailTransport for SmtpTransport {
+    fn send(&self, email: &Message) -> Result<()> {
+        Transport::send(self, email)?;
+
+        Ok(())
+    }
+}
+
+/// Send the mail to the recipients, returns whether it was sent or why it wasn't.
+#[tracing::instrument(skip(app, transport, body))]
+pub fn send_mail(
+    app: &Cli,
+    transport: &impl MailTransport,
+    recipients: &[Mailbox],
+    server_ident: &str,
+    body: &str,
+) -> Result<SendResult> {
+    let email = generate_mail(
+        server_ident,
+        app.from.clone().unwrap_or("user@example.org".parse()?),
+        recipients.to_vec(),
+        body,
+    )?;
+
+    match transport.send(&email) {
+        Ok(()) => {
+            info!("Mail sent successfully!");
+            Ok(SendResult::Sent)
+        }
+        Err(e) => {
+            error!(error =? e, "Failed to send mail because an error happened");
+            Ok(SendResult::Failed {
+                error: e.to_string(),
+            })
+        }
+    }
+}
 
 #[tracing::instrument(level = "trace")]
-pub fn format_threshold_crossed_msg<T: Debug + Display, O: Debug + Display>(
-    name: &str,
-    threshold: T,
-    observed_value: O,
-) -> Result<String> {
+pub fn format_threshold_crossed_msg(crossed: &CrossedThreshold) -> Result<String> {
+    let unit = crossed.unit;
     Ok(format!(
-        "- {name} threshold crossed ({}%): observed {}%\n",
-        Decimal::from_str(&threshold.to_string())?.round_dp(3),
-        Decimal::from_str(&observed_value.to_string())?.round_dp(3)
+        "- {} threshold crossed ({}{unit}): observed {}{unit}, {}\n",
+        crossed.name,
+        Decimal::from_str(&crossed.threshold.to_string())?.round_dp(3),
+        Decimal::from_str(&crossed.observed_value.to_string())?.round_dp(3),
+        crossed.severity
     ))
 }
 
+pub fn format_missing_msg(missing: &MissingItem) -> String {
+    let last_seen = match missing.last_seen {
+        Some(LastSeen::At(time)) => format!(", last seen {}", time.format("%d/%m/%Y %H:%M")),
+        Some(LastSeen::Never) => ", never seen".to_string(),
+        None => String::new(),
+    };
+    format!(
+        "- {} {} missing{last_seen}, {}\n",
+        missing.kind(),
+        missing.name,
+        MissingItem::SEVERITY
+    )
+}
+
 #[tracing::instrument(level = "debug", skip(snap))]
 pub fn format_snapshot(snap: &PercentSnapshot) -> Result<String> {
     let mut body = "System state:\n".to_string();
@@ -45,10 +140,39 @@ pub fn format_snapshot(snap: &PercentSnapshot) -> Result<String> {
         "- Average Load (on 15min) {}%\n",
         Decimal::from_str(&snap.avg_load.to_string())?.round_dp(3)
     ));
+    if let Some(swap_activity) = snap.swap_activity {
+        body.push_str(&format!(
+            "- Swap activity {} pages/s\n",
+            Decimal::from_str(&swap_activity.to_string())?.round_dp(3)
+        ));
+    }
+    if let Some(conntrack) = snap.conntrack {
+        body.push_str(&format!(
+            "- Conntrack {}%\n",
+            Decimal::from_str(&conntrack.to_string())?.round_dp(3)
+        ));
+    }
+    if let Some(net_utilization) = snap.net_utilization {
+        body.push_str(&format!(
+            "- Network utilization {}%\n",
+            Decimal::from_str(&net_utilization.to_string())?.round_dp(3)
+        ));
+    }
 
     Ok(body)
 }
 
+/// Link to the dashboard on the range centered on `incident`, `None` without `--dashboard-url`.
+pub fn dashboard_link(app: &Cli, incident: DateTime<Utc>) -> Option<String> {
+    let url = app.dashboard_url.as_deref()?.trim_end_matches('/');
+    let context = chrono::Duration::from_std(INCIDENT_CONTEXT).ok()?;
+    Some(format!(
+        "Dashboard: {url}/?from={}&to={}",
+        (incident - context).timestamp(),
+        (incident + context).timestamp()
+    ))
+}
+
 #[tracing::instrument]
 pub fn generate_mail(
     server_ident: &str,
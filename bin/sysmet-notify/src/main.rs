@@ -8,16 +8,34 @@ use std::{
     path::Path,
 };
 
+use chrono::{DateTime, Utc};
 use clap::Parser;
 pub use color_eyre::Result;
-use lettre::{transport::smtp::authentication::Credentials, SmtpTransport, Transport};
-use log::{debug, error, info, trace};
-use metrics::prelude::*;
+use env::{
+    config::{Config, EnvSection},
+    version::{is_verbose_version, BuildInfo},
+};
+use log::{debug, info, trace, tracing, warn};
+use metrics::{prelude::*, schema::CrossedThresholdV1, thresholds::state::SendResult};
 
-use crate::mail::{format_snapshot, format_threshold_crossed_msg, generate_mail};
+use crate::{
+    mail::{
+        dashboard_link, format_missing_msg, format_snapshot, format_threshold_crossed_msg,
+        send_mail, MailTransport, SmtpMailer,
+    },
+    routes::{split_by_recipients, Router, Severity},
+};
 
 mod cli;
+mod context;
+mod databases;
 mod mail;
+mod routes;
+mod state;
+#[cfg(test)]
+mod testing;
+
+const BUILD_INFO: BuildInfo = BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
 #[derive(Debug)]
 pub struct PercentSnapshot {
@@ -27,6 +45,89 @@ pub struct PercentSnapshot {
     pub memory: f32,
     pub disk: f32,
     pub avg_load: f32,
+    /// Pages swapped in and out per second, only known in database mode.
+    pub swap_activity: Option<f32>,
+    /// Conntrack entries in percent of the max, only known on Linux with the conntrack module loaded.
+    pub conntrack: Option<f32>,
+    /// Busiest network link in percent of its speed, only known in database mode for links reporting a speed.
+    pub net_utilization: Option<f32>,
+}
+
+/// Threshold crossed by a snapshot.
+#[derive(Debug, Clone)]
+pub struct CrossedThreshold {
+    /// Identifier matched by the routes, e.g. `cpu`.
+    pub metric: &'static str,
+    pub name: &'static str,
+    pub threshold: u32,
+    pub observed_value: f32,
+    pub unit: &'static str,
+    pub severity: Severity,
+}
+
+impl From<&CrossedThreshold> for CrossedThresholdV1 {
+    fn from(crossed: &CrossedThreshold) -> Self {
+        Self {
+            metric: crossed.metric.to_string(),
+            name: crossed.name.to_string(),
+            threshold: crossed.threshold,
+            observed_value: crossed.observed_value as f64,
+            unit: crossed.unit.to_string(),
+            severity: crossed.severity.to_string(),
+        }
+    }
+}
+
+/// When a missing item was last seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LastSeen {
+    Never,
+    At(DateTime<Utc>),
+}
+
+/// Mountpoint or network interface required by `--require-mount` or `--require-nic` and missing.
+#[derive(Debug, Clone)]
+pub struct MissingItem {
+    /// Identifier matched by the routes, `mount` or `nic`.
+    pub metric: &'static str,
+    pub name: String,
+    /// `None` in live mode, which has no history to look into.
+    pub last_seen: Option<LastSeen>,
+}
+
+impl MissingItem {
+    /// A missing disk or link breaks what depends on it, there is no milder level.
+    pub const SEVERITY: Severity = Severity::Critical;
+
+    pub fn kind(&self) -> &'static str {
+        match self.metric {
+            "mount" => "Mountpoint",
+            _ => "Network interface",
+        }
+    }
+}
+
+/// Part of an alert routed on its own.
+#[derive(Debug, Clone)]
+pub enum Alert {
+    Threshold(CrossedThreshold),
+    Missing(MissingItem),
+}
+
+impl Alert {
+    pub fn metric(&self) -> &'static str {
+        match self {
+            Alert::Threshold(crossed) => crossed.metric,
+            Alert::Missing(missing) => missing.metric,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Alert::Threshold(crossed) => crossed.severity,
+            Alert::Missing(_) => MissingItem::SEVERITY,
+        }
+    }
 }
 
 fn is_threshold_crossed(debug_msg: &str, threshold: Option<u32>, observered_value: f32) -> bool {
@@ -51,7 +152,7 @@ fn main() -> Result<()> {
 
     let mut is_setup_with_specific_path = false;
     for win in args_os().collect::<Vec<OsString>>().windows(2) {
-        if let Some(prev) = win.get(0) {
+        if let Some(prev) = win.first() {
             if prev == "--env" {
                 if let Some(next) = win.get(1) {
                     is_setup_with_specific_path = true;
@@ -64,8 +165,20 @@ fn main() -> Result<()> {
         // Setup env to .env
         env::setup_env();
     }
+    // NOTE: Loaded after the env file so its values keep the precedence over the config file
+    let config = Config::from_args()?;
+    if let Some(config) = &config {
+        config.database.apply();
+        config.notify.apply();
+        config.routes.apply();
+    }
 
-    let app = cli::Cli::parse();
+    let args = args_os().collect::<Vec<_>>();
+    if is_verbose_version(&args) {
+        println!("{}", BUILD_INFO.verbose());
+        return Ok(());
+    }
+    let app = cli::Cli::parse_from(args);
     if app.verbose.is_silent() {
         set_var("LOG_LEVEL", "SILENT");
     } else if let Some(level) = app.verbose.log_level() {
@@ -73,19 +186,88 @@ fn main() -> Result<()> {
     }
 
     log::setup_simple_logger();
+
+    if let Some(unknown_keys) = config
+        .map(|config| config.unknown_keys())
+        .filter(|keys| !keys.is_empty())
+    {
+        warn!(
+            "Unknown keys in the config file: {}",
+            unknown_keys.join(", ")
+        );
+    }
+    if app.print_config {
+        println!("{}", app.effective_config().masked().to_toml()?);
+        return Ok(());
+    }
+
+    let outcome = run(&app, &SmtpMailer::from_cli(&app), &SystemClock)?;
+    debug!(?outcome);
+
+    Ok(())
+}
+
+/// Identity of this build, stamped on the databases it writes.
+pub(crate) fn writer() -> Writer {
+    Writer {
+        name: BUILD_INFO.name.to_string(),
+        version: BUILD_INFO.version.to_string(),
+        commit: BUILD_INFO.commit.to_string(),
+    }
+}
+
+/// How a check ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Before the end of the cooldown, usages were not checked.
+    InCooldown,
+    NoThresholdCrossed,
+    /// A mail would have been sent.
+    DryRun,
+    Sent,
+    /// The mail could not be sent, the cooldown was not updated.
+    SendFailed,
+}
+
+/// Source of the current time, checks and cooldowns are computed from.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Check the thresholds of the current device or of the databases, sending a mail through `transport`.
+#[tracing::instrument(skip_all)]
+pub fn run(app: &cli::Cli, transport: &impl MailTransport, clock: &impl Clock) -> Result<Outcome> {
+    let now = clock.now();
+    if app.database.is_some() || app.database_dir.is_some() {
+        return databases::check(app, transport, now);
+    }
+
     let hostname = get_hostname();
     info!("Check started on device {hostname}");
     trace!(args =? app, "Cli called with args on device {hostname}");
 
-    let now = chrono::Utc::now();
+    // NOTE: A dry run leaves the state as it was, as it sends nothing
+    let mut notify_state = state::load(&app.state_path);
+    notify_state.checked_at = Some(now);
+    notify_state.cooldown_seconds = app.cooldown.as_secs();
+
     if !app.dry_run {
         if let Some(path) = &app.last_sent_instant {
             let content = fs::read_to_string(path).unwrap_or_default();
             let after_cooldown = if !content.is_empty() {
-                match content.parse::<chrono::DateTime<chrono::Utc>>() {
+                match content.parse::<DateTime<Utc>>() {
                     Ok(date) => date
                         .checked_add_signed(chrono::Duration::from_std(app.cooldown)?)
-                        .map_or(true, |i| i < now),
+                        .is_none_or(|i| i < now),
                     Err(_) => true,
                 }
             } else {
@@ -94,7 +276,8 @@ fn main() -> Result<()> {
 
             if !after_cooldown {
                 info!("No need to take check usages, we are before the end of the cooldown");
-                return Ok(());
+                state::save(&app.state_path, &notify_state)?;
+                return Ok(Outcome::InCooldown);
             }
         }
     }
@@ -109,126 +292,587 @@ fn main() -> Result<()> {
         memory: (ram + swap) / 2.0, // REVIEW: Might need a more precise way of calculating average memory load
         disk: disk_usage_percent()?,
         avg_load: load_avg_percent()?.2,
+        swap_activity: None,
+        conntrack: metrics::psutil::KernelGauges::current()
+            .and_then(|kernel| kernel.percent("conntrack"))
+            .map(|percent| percent as f32),
+        net_utilization: None,
     };
 
     trace!(snapshot =? snapshot, "System snapshot taken at {pretty_formated_now}");
 
-    let cpu_threshold_crossed =
-        is_threshold_crossed("CPU threshold crossed", app.cpu_threshold, snapshot.cpu);
-    let ram_threshold_crossed =
-        is_threshold_crossed("RAM threshold crossed", app.ram_threshold, snapshot.ram);
-    let swap_threshold_crossed =
-        is_threshold_crossed("Swap threshold crossed", app.swap_threshold, snapshot.swap);
-    let memory_threshold_crossed = is_threshold_crossed(
-        "Memory threshold crossed",
-        app.memory_threshold,
-        snapshot.memory,
-    );
-    let disk_threshold_crossed =
-        is_threshold_crossed("Disk threshold crossed", app.disk_threshold, snapshot.disk);
-    let avg_load_threshold_crossed = is_threshold_crossed(
-        "Average Load threshold crossed",
-        app.avg_load_threshold,
-        snapshot.avg_load,
+    let alerts = crossed(app, &snapshot)
+        .into_iter()
+        .map(Alert::Threshold)
+        .chain(missing_items(app)?.into_iter().map(Alert::Missing))
+        .collect::<Vec<_>>();
+    state::record_check(
+        &mut notify_state,
+        &hostname,
+        &checked_metrics(app, &snapshot),
+        &alerts,
+        now,
     );
-    let mut body = "Thresholds crossed:\n".to_string();
+    if alerts.is_empty() {
+        info!("Finishing early because no threshold have been crossed");
+        if !app.dry_run {
+            state::save(&app.state_path, &notify_state)?;
+        }
+        return Ok(Outcome::NoThresholdCrossed);
+    } else {
+        info!("At least one threshold crossed!");
+    }
+
+    // NOTE: Each set of recipients only gets the thresholds routed to it
+    let router = Router::new(&app.routes, &app.contacts);
+    // NOTE: Collected once, every mail describes the same moment
+    let context = context::system_context(app);
+    let mut mails = Vec::new();
+    for (recipients, alerts) in split_by_recipients(
+        alerts
+            .into_iter()
+            .map(|alert| (router.recipients(alert.metric(), alert.severity()), alert)),
+    ) {
+        let mut body = alerts_header(&alerts).to_string();
+        body.push_str(&alert_lines(&alerts)?);
+        body.push_str("\n\n");
+        body.push_str(&format_snapshot(&snapshot)?);
+        if let Some(link) = dashboard_link(app, now) {
+            body.push_str("\n\n");
+            body.push_str(&link);
+        }
+        if let Some(context) = &context {
+            body.push_str("\n\n");
+            body.push_str(context);
+        }
+        debug!(body, ?recipients, "Body that will be sent");
+        mails.push((recipients, body));
+    }
 
-    let mut at_least_one_threshold_crossed = false;
-    if cpu_threshold_crossed {
-        at_least_one_threshold_crossed = true;
-        body.push_str(
-            &(format_threshold_crossed_msg("CPU", app.cpu_threshold.unwrap(), snapshot.cpu)?),
+    if app.dry_run {
+        info!(
+            "Finishing early because there is no need to send a mail, the app is in dry-run mode"
         );
+        return Ok(Outcome::DryRun);
+    }
+
+    // NOTE: A failed mail leaves the cooldown as is, the next check sends every mail again
+    let mut result = SendResult::Sent;
+    for (recipients, body) in mails {
+        let sent = send_mail(app, transport, &recipients, &hostname, &body)?;
+        // NOTE: The first failure is the one recorded
+        if result.is_sent() {
+            result = sent;
+        }
+    }
+    let is_sent = result.is_sent();
+    state::record_attempt(&mut notify_state, &hostname, now, result);
+    if !is_sent {
+        state::save(&app.state_path, &notify_state)?;
+        return Ok(Outcome::SendFailed);
     }
-    if ram_threshold_crossed {
-        at_least_one_threshold_crossed = true;
-        body.push_str(
-            &(format_threshold_crossed_msg("RAM", app.ram_threshold.unwrap(), snapshot.ram)?),
+    state::record_sent(&mut notify_state, &hostname, now);
+    state::save(&app.state_path, &notify_state)?;
+    if let Some(path) = &app.last_sent_instant {
+        let mut last_mail_instant = File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        last_mail_instant.seek(SeekFrom::Start(0))?;
+        last_mail_instant.write_all(now.to_rfc3339().as_bytes())?;
+    }
+
+    Ok(Outcome::Sent)
+}
+
+/// Threshold as (metric, name, threshold, observed value, unit), the value being `None` when it can't be
+/// observed.
+type Threshold = (
+    &'static str,
+    &'static str,
+    Option<u32>,
+    Option<f32>,
+    &'static str,
+);
+
+fn thresholds(app: &cli::Cli, snapshot: &PercentSnapshot) -> [Threshold; 9] {
+    [
+        ("cpu", "CPU", app.cpu_threshold, Some(snapshot.cpu), "%"),
+        ("ram", "RAM", app.ram_threshold, Some(snapshot.ram), "%"),
+        ("swap", "Swap", app.swap_threshold, Some(snapshot.swap), "%"),
+        (
+            "memory",
+            "RAM & Swap",
+            app.memory_threshold,
+            Some(snapshot.memory),
+            "%",
+        ),
+        ("disk", "Disk", app.disk_threshold, Some(snapshot.disk), "%"),
+        (
+            "load",
+            "Average Load",
+            app.avg_load_threshold,
+            Some(snapshot.avg_load),
+            "%",
+        ),
+        (
+            "swap-activity",
+            "Swap activity",
+            app.swap_activity_threshold,
+            snapshot.swap_activity,
+            " pages/s",
+        ),
+        (
+            "conntrack",
+            "Conntrack",
+            app.conntrack_threshold,
+            snapshot.conntrack,
+            "%",
+        ),
+        (
+            "net-utilization",
+            "Network utilization",
+            app.net_utilization_threshold,
+            snapshot.net_utilization,
+            "%",
+        ),
+    ]
+}
+
+/// Metrics with a threshold and a value, and the required mountpoints and network interfaces.
+pub(crate) fn checked_metrics(app: &cli::Cli, snapshot: &PercentSnapshot) -> Vec<&'static str> {
+    thresholds(app, snapshot)
+        .into_iter()
+        .filter(|(_, _, threshold, observed_value, _)| {
+            threshold.is_some() && observed_value.is_some()
+        })
+        .map(|(metric, ..)| metric)
+        .chain((!app.require_mounts.is_empty()).then_some("mount"))
+        .chain((!app.require_nics.is_empty()).then_some("nic"))
+        .collect()
+}
+
+/// Thresholds crossed by the snapshot.
+pub(crate) fn crossed(app: &cli::Cli, snapshot: &PercentSnapshot) -> Vec<CrossedThreshold> {
+    // NOTE: Values that can't be observed (e.g. swap activity outside of database mode) are skipped
+    thresholds(app, snapshot)
+        .into_iter()
+        .filter_map(|(metric, name, threshold, observed_value, unit)| {
+            let observed_value = observed_value?;
+            is_threshold_crossed(
+                &format!("{name} threshold crossed"),
+                threshold,
+                observed_value,
+            )
+            .then(|| {
+                let threshold = threshold.unwrap();
+                CrossedThreshold {
+                    metric,
+                    name,
+                    threshold,
+                    observed_value,
+                    unit,
+                    severity: Severity::of(threshold, observed_value, unit == "%"),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Mountpoints and network interfaces of `--require-mount` and `--require-nic` missing from the current
+/// system.
+#[tracing::instrument(skip(app))]
+pub(crate) fn missing_items(app: &cli::Cli) -> Result<Vec<MissingItem>> {
+    let mut missing = Vec::new();
+    if !app.require_mounts.is_empty() {
+        let mountpoints = mountpoints()?;
+        missing.extend(
+            app.require_mounts
+                .iter()
+                .filter(|mount| !mountpoints.contains(mount))
+                .map(|mount| MissingItem {
+                    metric: "mount",
+                    name: mount.clone(),
+                    last_seen: None,
+                }),
         );
     }
-    if swap_threshold_crossed {
-        at_least_one_threshold_crossed = true;
-        body.push_str(
-            &(format_threshold_crossed_msg("Swap", app.swap_threshold.unwrap(), snapshot.swap)?),
+    if !app.require_nics.is_empty() {
+        let nics = network_interfaces()?;
+        missing.extend(
+            app.require_nics
+                .iter()
+                .filter(|nic| !nics.contains(nic))
+                .map(|nic| MissingItem {
+                    metric: "nic",
+                    name: nic.clone(),
+                    last_seen: None,
+                }),
         );
     }
-    if memory_threshold_crossed {
-        at_least_one_threshold_crossed = true;
-        body.push_str(
-            &(format_threshold_crossed_msg(
-                "RAM & Swap",
-                app.memory_threshold.unwrap(),
-                snapshot.memory,
-            )?),
+    debug!(?missing);
+
+    Ok(missing)
+}
+
+/// First line of the alerts, the usual one as long as a threshold is crossed.
+pub(crate) fn alerts_header(alerts: &[Alert]) -> &'static str {
+    if alerts
+        .iter()
+        .any(|alert| matches!(alert, Alert::Threshold(_)))
+    {
+        "Thresholds crossed:\n"
+    } else {
+        "Required items missing:\n"
+    }
+}
+
+/// Lines describing every alert, the crossed thresholds first.
+pub(crate) fn alert_lines(alerts: &[Alert]) -> Result<String> {
+    let mut result = String::new();
+    for alert in alerts {
+        if let Alert::Threshold(crossed) = alert {
+            result.push_str(&format_threshold_crossed_msg(crossed)?);
+        }
+    }
+    for alert in alerts {
+        if let Alert::Missing(missing) = alert {
+            result.push_str(&format_missing_msg(missing));
+        }
+    }
+    Ok(result)
+}
+
+/// Short text of the alerts, e.g. "alert: cpu 97%, ram 92%, mount /data missing".
+pub(crate) fn alert_text(alerts: &[Alert]) -> String {
+    let alerts = alerts
+        .iter()
+        .map(|alert| match alert {
+            Alert::Threshold(crossed) => format!(
+                "{} {:.0}{}",
+                crossed.name.to_lowercase(),
+                crossed.observed_value,
+                crossed.unit
+            ),
+            Alert::Missing(missing) => format!("{} {} missing", missing.metric, missing.name),
+        })
+        .collect::<Vec<_>>();
+    format!("alert: {}", alerts.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics::thresholds::state::{AlertStatus, MetricState, SendAttempt};
+
+    use super::*;
+    use crate::testing::{CapturingTransport, Fixture, SmtpSink, CONTACT};
+
+    #[test]
+    fn crossed_thresholds_send_one_mail() {
+        let fixture = Fixture::new();
+        let transport = CapturingTransport::default();
+
+        let outcome = run(&fixture.cli(&[]), &transport, &fixture.clock()).unwrap();
+
+        assert_eq!(outcome, Outcome::Sent);
+        let mails = transport.mails();
+        assert_eq!(mails.len(), 1, "{mails:#?}");
+        let hostname = get_hostname();
+        for line in [
+            format!("Subject: Warning threshold reached on {hostname}"),
+            "Thresholds crossed:".to_string(),
+            format!("## {hostname}"),
+            "- CPU threshold crossed (0%): observed".to_string(),
+            "System state:".to_string(),
+        ] {
+            assert!(
+                mails[0].contains(&line),
+                "{line:?} missing from {}",
+                mails[0]
+            );
+        }
+        assert_eq!(
+            transport.sent.borrow()[0].envelope().to(),
+            [CONTACT.parse().unwrap()]
+        );
+        assert_eq!(
+            fixture.state().hosts[&hostname].last_sent,
+            Some(fixture.now)
         );
     }
-    if disk_threshold_crossed {
-        at_least_one_threshold_crossed = true;
-        body.push_str(
-            &(format_threshold_crossed_msg("Disk", app.disk_threshold.unwrap(), snapshot.disk)?),
+
+    #[test]
+    fn routed_thresholds_go_to_their_contacts() {
+        let fixture = Fixture::new();
+        let transport = CapturingTransport::default();
+        let cli = fixture.cli(&[
+            "--route",
+            "metric=cpu,contacts=app@corp.com,oncall@corp.com",
+            "--route",
+            "metric=disk,contacts=storage@corp.com",
+        ]);
+
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+
+        let sent = transport.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].envelope().to(),
+            [
+                "app@corp.com".parse().unwrap(),
+                "oncall@corp.com".parse().unwrap()
+            ]
         );
     }
-    if avg_load_threshold_crossed {
-        at_least_one_threshold_crossed = true;
-        body.push_str(
-            &(format_threshold_crossed_msg(
-                "Average Load",
-                app.avg_load_threshold.unwrap(),
-                snapshot.avg_load,
-            )?),
+
+    #[test]
+    fn unrouted_thresholds_go_to_the_default_contacts() {
+        let fixture = Fixture::new();
+        let transport = CapturingTransport::default();
+        let cli = fixture.cli(&["--route", "metric=disk,contacts=storage@corp.com"]);
+
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+
+        assert_eq!(
+            transport.sent.borrow()[0].envelope().to(),
+            [CONTACT.parse().unwrap()]
         );
     }
 
-    if !at_least_one_threshold_crossed {
-        info!("Finishing early because no threshold have been crossed");
-        return Ok(()); // Exit SUCCESS;
-    } else {
-        info!("At least one threshold crossed!");
+    #[test]
+    fn conntrack_is_only_checked_when_known() {
+        let fixture = Fixture::new();
+        let cli = fixture.cli(&["--conntrack-threshold", "90"]);
+        let snapshot = |conntrack| PercentSnapshot {
+            cpu: 10.0,
+            ram: 10.0,
+            swap: 0.0,
+            memory: 5.0,
+            disk: 10.0,
+            avg_load: 10.0,
+            swap_activity: None,
+            conntrack,
+            net_utilization: None,
+        };
+
+        // NOTE: The CPU threshold of the fixture is always crossed
+        let crossed_conntrack = |conntrack| {
+            crossed(&cli, &snapshot(conntrack))
+                .into_iter()
+                .filter(|crossed| crossed.metric != "cpu")
+                .map(|crossed| (crossed.metric, crossed.observed_value))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(crossed_conntrack(Some(95.0)), [("conntrack", 95.0)]);
+        assert!(crossed_conntrack(Some(50.0)).is_empty());
+        // NOTE: Without the conntrack module loaded
+        assert!(crossed_conntrack(None).is_empty());
     }
 
-    body.push_str("\n\n");
-    body.push_str(&format_snapshot(&snapshot)?);
+    #[test]
+    fn net_utilization_is_only_checked_when_known() {
+        let fixture = Fixture::new();
+        let cli = fixture.cli(&["--net-utilization-threshold", "80"]);
+        let snapshot = |net_utilization| PercentSnapshot {
+            cpu: 10.0,
+            ram: 10.0,
+            swap: 0.0,
+            memory: 5.0,
+            disk: 10.0,
+            avg_load: 10.0,
+            swap_activity: None,
+            conntrack: None,
+            net_utilization,
+        };
 
-    debug!(body, "Body that will be sent");
+        // NOTE: The CPU threshold of the fixture is always crossed
+        let crossed_utilization = |net_utilization| {
+            crossed(&cli, &snapshot(net_utilization))
+                .into_iter()
+                .filter(|crossed| crossed.metric != "cpu")
+                .map(|crossed| (crossed.name, crossed.observed_value, crossed.unit))
+                .collect::<Vec<_>>()
+        };
 
-    if app.dry_run {
-        info!(
-            "Finishing early because there is no need to send a mail, the app is in dry-run mode"
+        let crossed = crossed_utilization(Some(95.0));
+        assert_eq!(crossed.len(), 1);
+        assert_eq!((crossed[0].1, crossed[0].2), (95.0, "%"));
+        assert!(crossed_utilization(Some(50.0)).is_empty());
+        // NOTE: Outside of database mode or without a link speed
+        assert!(crossed_utilization(None).is_empty());
+    }
+
+    #[test]
+    fn fresh_cooldowns_send_nothing() {
+        let fixture = Fixture::new();
+        let cli = fixture.cli(&[]);
+        let transport = CapturingTransport::default();
+
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
         );
-        return Ok(()); // Exit SUCCESS;
+        let clock = testing::FixedClock(fixture.now + chrono::Duration::minutes(1));
+        assert_eq!(
+            run(&cli, &transport, &clock).unwrap(),
+            Outcome::NoThresholdCrossed
+        );
+        assert_eq!(transport.mails().len(), 1);
     }
 
-    let smtp_relay = app.smtp_relay.unwrap();
-    let smtp_user = app.smtp_user.unwrap();
-    let smtp_password = app.smtp_password.unwrap();
-    let last_sent_instant = app.last_sent_instant.unwrap();
+    #[test]
+    fn dry_runs_send_nothing() {
+        let fixture = Fixture::new();
+        let transport = CapturingTransport::default();
+        let before = fs::read(fixture.database()).unwrap();
 
-    let email = generate_mail(
-        &hostname,
-        app.from.unwrap_or("user@example.org".parse()?),
-        app.contacts,
-        &body,
-    )?;
-
-    let mailer = SmtpTransport::relay(&smtp_relay)?
-        .port(app.smtp_port)
-        .credentials(Credentials::new(smtp_user, smtp_password))
-        .build();
-
-    match mailer.send(&email) {
-        Ok(_) => {
-            info!("Mail sent successfully!");
-
-            let mut last_mail_instant = File::options()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(last_sent_instant)?;
-            last_mail_instant.seek(SeekFrom::Start(0))?;
-            last_mail_instant.write_all(now.to_rfc3339().as_bytes())?;
-        }
-        Err(e) => error!(error =? e, "Failed to send mail because an error happened"),
+        let outcome = run(&fixture.cli(&["--dry-run"]), &transport, &fixture.clock()).unwrap();
+
+        assert_eq!(outcome, Outcome::DryRun);
+        assert!(transport.mails().is_empty());
+        assert!(!fixture.state_path().exists());
+        assert_eq!(fs::read(fixture.database()).unwrap(), before);
     }
 
-    Ok(())
+    #[test]
+    fn failed_mails_leave_the_cooldown() {
+        let fixture = Fixture::new();
+        let cli = fixture.cli(&[]);
+
+        let outcome = run(&cli, &CapturingTransport::failing(), &fixture.clock()).unwrap();
+
+        assert_eq!(outcome, Outcome::SendFailed);
+        let state = fixture.state();
+        let host = &state.hosts[&get_hostname()];
+        assert_eq!(host.last_sent, None);
+        assert!(!host.last_attempt.as_ref().unwrap().result.is_sent());
+        // The next check sends the mail again
+        let transport = CapturingTransport::default();
+        assert_eq!(
+            run(&cli, &transport, &fixture.clock()).unwrap(),
+            Outcome::Sent
+        );
+        assert_eq!(transport.mails().len(), 1);
+    }
+
+    #[test]
+    fn checks_are_recorded_in_the_state_file() {
+        let fixture = Fixture::new();
+        let cli = fixture.cli(&["--cooldown", "2h"]);
+
+        run(&cli, &CapturingTransport::default(), &fixture.clock()).unwrap();
+
+        let state = fixture.state();
+        assert_eq!(state.checked_at, Some(fixture.now));
+        assert_eq!(state.cooldown_seconds, 2 * 60 * 60);
+        let host = &state.hosts[&get_hostname()];
+        assert_eq!(
+            host.metrics["cpu"],
+            MetricState {
+                status: AlertStatus::Alert,
+                last_alert: Some(fixture.now),
+            }
+        );
+        assert_eq!(
+            host.last_attempt,
+            Some(SendAttempt {
+                at: fixture.now,
+                result: SendResult::Sent,
+            })
+        );
+        assert_eq!(
+            state.next_eligible(&get_hostname(), fixture.now),
+            Some(fixture.now + chrono::Duration::hours(2))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mails_go_through_smtp() {
+        let sink = SmtpSink::start(false).await;
+        let fixture = Fixture::new();
+        let (cli, transport, clock) = (fixture.cli(&[]), sink.transport(), fixture.clock());
+
+        let outcome = tokio::task::spawn_blocking(move || run(&cli, &transport, &clock))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Sent);
+        let mails = sink.mails();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("- CPU threshold crossed (0%): observed"));
+        // NOTE: The recipients are only in the envelope, lettre leaves the Bcc header out
+        assert!(!mails[0].contains(CONTACT));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refused_smtp_mails_leave_the_cooldown() {
+        let sink = SmtpSink::start(true).await;
+        let fixture = Fixture::new();
+        let (cli, transport, clock) = (fixture.cli(&[]), sink.transport(), fixture.clock());
+
+        let outcome = tokio::task::spawn_blocking(move || run(&cli, &transport, &clock))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::SendFailed);
+        assert!(sink.mails().is_empty());
+        assert_eq!(fixture.state().hosts[&get_hostname()].last_sent, None);
+    }
+
+    #[test]
+    fn alert_mails_end_with_the_top_processes_when_asked() {
+        let (fixture, other) = (Fixture::new(), Fixture::new());
+        let transport = CapturingTransport::default();
+
+        run(
+            &fixture.cli(&["--include-top", "2"]),
+            &transport,
+            &fixture.clock(),
+        )
+        .unwrap();
+        run(&other.cli(&[]), &transport, &other.clock()).unwrap();
+
+        let mails = transport.mails();
+        assert_eq!(mails.len(), 2, "{mails:#?}");
+        let top = mails[0].find("Top processes by CPU:").unwrap();
+        assert!(mails[0].find("System state:").unwrap() < top);
+        assert!(mails[0][top..].contains("Top processes by memory:"));
+        assert!(!mails[1].contains("Top processes"), "{}", mails[1]);
+    }
+
+    #[test]
+    fn required_items_are_looked_up_on_the_running_host() {
+        let fixture = Fixture::new();
+        let cli = fixture.cli(&["--require-mount", "/,/sysmet-nowhere"]);
+
+        let missing = missing_items(&cli).unwrap();
+
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].name, "/sysmet-nowhere");
+        assert_eq!(missing[0].kind(), "Mountpoint");
+        // NOTE: The running host has no history to look into
+        assert_eq!(missing[0].last_seen, None);
+        assert_eq!(
+            format_missing_msg(&missing[0]),
+            "- Mountpoint /sysmet-nowhere missing, critical\n"
+        );
+        assert_eq!(
+            alert_text(&[Alert::Missing(missing[0].clone())]),
+            "alert: mount /sysmet-nowhere missing"
+        );
+        assert_eq!(
+            alerts_header(&[Alert::Missing(missing[0].clone())]),
+            "Required items missing:\n"
+        );
+    }
 }
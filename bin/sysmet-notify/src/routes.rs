@@ -0,0 +1,405 @@
+use std::{fmt, str::FromStr};
+
+use lettre::message::Mailbox;
+use log::{trace, tracing};
+
+/// Identifiers of the thresholds a route can match.
+pub const METRICS: [&str; 11] = [
+    "cpu",
+    "ram",
+    "swap",
+    "memory",
+    "disk",
+    "load",
+    "swap-activity",
+    "conntrack",
+    "net-utilization",
+    "mount",
+    "nic",
+];
+const WILDCARD: &str = "*";
+
+/// How far a crossed threshold is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    /// `Critical` once the value is past the middle between the threshold and 100%, or twice the
+    /// threshold for values without a max (e.g. pages swapped per second).
+    pub fn of(threshold: u32, observed_value: f32, is_percent: bool) -> Self {
+        let threshold = threshold as f32;
+        let critical_from = if is_percent {
+            threshold + (100.0 - threshold) / 2.0
+        } else {
+            threshold * 2.0
+        };
+
+        if observed_value >= critical_from {
+            Severity::Critical
+        } else {
+            Severity::Warning
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!(
+                "{value} is not a severity, expected warning or critical"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// Contacts of the thresholds of a metric and severity, given as
+/// `metric=cpu,severity=critical,contacts=app@corp.com,oncall@corp.com`.
+///
+/// A missing or `*` metric or severity matches any of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub metric: Option<String>,
+    pub severity: Option<Severity>,
+    pub contacts: Vec<Mailbox>,
+}
+
+impl Route {
+    pub fn matches(&self, metric: &str, severity: Severity) -> bool {
+        self.metric.as_deref().is_none_or(|route| route == metric)
+            && self.severity.is_none_or(|route| route == severity)
+    }
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut route = Route {
+            metric: None,
+            severity: None,
+            contacts: Vec::new(),
+        };
+        let mut last_key = None;
+        for part in value
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            // NOTE: Values without a key are the next contacts of a list, e.g. `contacts=a@x.org,b@x.org`
+            let (key, value) = match part.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None if last_key == Some("contacts") => ("contacts", part),
+                None => return Err(format!("{part} is not a key=value pair in {value}")),
+            };
+            match key {
+                "metric" if value == WILDCARD => route.metric = None,
+                "metric" if METRICS.contains(&value) => route.metric = Some(value.to_string()),
+                "metric" => {
+                    return Err(format!(
+                        "{value} is not a metric, expected one of {} or {WILDCARD}",
+                        METRICS.join(", ")
+                    ))
+                }
+                "severity" if value == WILDCARD => route.severity = None,
+                "severity" => route.severity = Some(value.parse()?),
+                "contacts" => route.contacts.push(
+                    value
+                        .parse()
+                        .map_err(|err| format!("{value} is not a contact: {err}"))?,
+                ),
+                _ => {
+                    return Err(format!(
+                        "{key} is not a route key, expected metric, severity or contacts"
+                    ))
+                }
+            }
+            last_key = Some(key);
+        }
+
+        if route.contacts.is_empty() {
+            return Err(format!("The route {value} has no contacts"));
+        }
+        Ok(route)
+    }
+}
+
+/// Routes evaluated for each crossed threshold, the default contacts receiving the ones no route matches.
+#[derive(Debug, Clone)]
+pub struct Router<'a> {
+    routes: &'a [Route],
+    default_contacts: &'a [Mailbox],
+}
+
+impl<'a> Router<'a> {
+    pub fn new(routes: &'a [Route], default_contacts: &'a [Mailbox]) -> Self {
+        Self {
+            routes,
+            default_contacts,
+        }
+    }
+
+    /// Contacts of every matching route without duplicates, the default ones when none matches.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn recipients(&self, metric: &str, severity: Severity) -> Vec<Mailbox> {
+        let mut recipients = Vec::new();
+        for route in self
+            .routes
+            .iter()
+            .filter(|route| route.matches(metric, severity))
+        {
+            push_unique(&mut recipients, &route.contacts);
+        }
+        if recipients.is_empty() {
+            push_unique(&mut recipients, self.default_contacts);
+        }
+        trace!(?recipients);
+
+        recipients
+    }
+
+    /// The default contacts without duplicates, for what is not a threshold (e.g. unreadable databases).
+    pub fn default_recipients(&self) -> Vec<Mailbox> {
+        let mut recipients = Vec::new();
+        push_unique(&mut recipients, self.default_contacts);
+        recipients
+    }
+}
+
+/// Append the contacts not already in `recipients`, the same address with another name being a duplicate.
+fn push_unique(recipients: &mut Vec<Mailbox>, contacts: &[Mailbox]) {
+    for contact in contacts {
+        if !recipients
+            .iter()
+            .any(|recipient| recipient.email == contact.email)
+        {
+            recipients.push(contact.clone());
+        }
+    }
+}
+
+/// Items grouped by recipients, each distinct set of recipients (in any order) getting a single group.
+///
+/// Groups are in the order their first item was given.
+pub fn split_by_recipients<T>(
+    items: impl IntoIterator<Item = (Vec<Mailbox>, T)>,
+) -> Vec<(Vec<Mailbox>, Vec<T>)> {
+    let mut groups: Vec<(Vec<Mailbox>, Vec<T>)> = Vec::new();
+    for (recipients, item) in items {
+        let same_recipients = |group: &Vec<Mailbox>| {
+            group.len() == recipients.len()
+                && recipients
+                    .iter()
+                    .all(|recipient| group.iter().any(|other| other.email == recipient.email))
+        };
+        match groups.iter_mut().find(|(group, _)| same_recipients(group)) {
+            Some((_, group_items)) => group_items.push(item),
+            None => groups.push((recipients, vec![item])),
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contacts(addresses: &[&str]) -> Vec<Mailbox> {
+        addresses
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect()
+    }
+
+    fn routes(routes: &[&str]) -> Vec<Route> {
+        routes.iter().map(|route| route.parse().unwrap()).collect()
+    }
+
+    fn emails(recipients: &[Mailbox]) -> Vec<String> {
+        recipients
+            .iter()
+            .map(|recipient| recipient.email.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn routes_are_parsed() {
+        assert_eq!(
+            "metric=cpu, severity=critical, contacts=app@corp.com,oncall@corp.com"
+                .parse::<Route>()
+                .unwrap(),
+            Route {
+                metric: Some("cpu".to_string()),
+                severity: Some(Severity::Critical),
+                contacts: contacts(&["app@corp.com", "oncall@corp.com"]),
+            }
+        );
+        // NOTE: Missing and `*` keys match everything
+        for any in [
+            "contacts=ops@corp.com",
+            "metric=*,severity=*,contacts=ops@corp.com",
+        ] {
+            assert_eq!(
+                any.parse::<Route>().unwrap(),
+                Route {
+                    metric: None,
+                    severity: None,
+                    contacts: contacts(&["ops@corp.com"]),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_routes_are_refused() {
+        for (route, error) in [
+            ("metric=gpu,contacts=a@corp.com", "gpu is not a metric"),
+            (
+                "severity=fatal,contacts=a@corp.com",
+                "fatal is not a severity",
+            ),
+            ("metric=cpu,contacts=not an address", "is not a contact"),
+            (
+                "metric=cpu,team=app,contacts=a@corp.com",
+                "team is not a route key",
+            ),
+            ("metric=cpu", "has no contacts"),
+            (
+                "metric=cpu,a@corp.com",
+                "a@corp.com is not a key=value pair",
+            ),
+        ] {
+            let err = route.parse::<Route>().unwrap_err();
+            assert!(err.contains(error), "{route}: {err}");
+        }
+    }
+
+    #[test]
+    fn severity_wildcards_match_both_severities() {
+        let [any, critical] = <[Route; 2]>::try_from(routes(&[
+            "metric=disk,contacts=storage@corp.com",
+            "metric=disk,severity=critical,contacts=oncall@corp.com",
+        ]))
+        .unwrap();
+
+        assert!(any.matches("disk", Severity::Warning));
+        assert!(any.matches("disk", Severity::Critical));
+        assert!(!any.matches("cpu", Severity::Critical));
+        assert!(critical.matches("disk", Severity::Critical));
+        assert!(!critical.matches("disk", Severity::Warning));
+    }
+
+    #[test]
+    fn every_matching_route_receives_the_threshold() {
+        let routes = routes(&[
+            "metric=disk,contacts=storage@corp.com",
+            "severity=critical,contacts=oncall@corp.com",
+            "metric=cpu,contacts=app@corp.com",
+        ]);
+        let defaults = contacts(&["admin@corp.com"]);
+        let router = Router::new(&routes, &defaults);
+
+        assert_eq!(
+            emails(&router.recipients("disk", Severity::Critical)),
+            ["storage@corp.com", "oncall@corp.com"]
+        );
+        assert_eq!(
+            emails(&router.recipients("disk", Severity::Warning)),
+            ["storage@corp.com"]
+        );
+        assert_eq!(
+            emails(&router.recipients("ram", Severity::Critical)),
+            ["oncall@corp.com"]
+        );
+    }
+
+    #[test]
+    fn unrouted_thresholds_fall_back_to_the_default_contacts() {
+        let routes = routes(&["metric=disk,contacts=storage@corp.com"]);
+        let defaults = contacts(&["admin@corp.com", "Admin <admin@corp.com>"]);
+        let router = Router::new(&routes, &defaults);
+
+        assert_eq!(
+            emails(&router.recipients("cpu", Severity::Warning)),
+            ["admin@corp.com"]
+        );
+        assert_eq!(emails(&router.default_recipients()), ["admin@corp.com"]);
+        assert_eq!(
+            emails(&Router::new(&[], &defaults).recipients("disk", Severity::Critical)),
+            ["admin@corp.com"]
+        );
+    }
+
+    #[test]
+    fn recipients_are_listed_once_across_routes() {
+        let routes = routes(&[
+            "metric=cpu,contacts=app@corp.com,oncall@corp.com",
+            "severity=warning,contacts=Oncall <oncall@corp.com>,app@corp.com",
+            "contacts=oncall@corp.com",
+        ]);
+        let router = Router::new(&routes, &[]);
+
+        let recipients = router.recipients("cpu", Severity::Warning);
+
+        assert_eq!(emails(&recipients), ["app@corp.com", "oncall@corp.com"]);
+        // NOTE: The first route naming a contact gives its name
+        assert_eq!(recipients[1].name, None);
+    }
+
+    #[test]
+    fn thresholds_are_grouped_by_recipient_set() {
+        let groups = split_by_recipients([
+            (contacts(&["app@corp.com", "oncall@corp.com"]), "cpu"),
+            (contacts(&["storage@corp.com"]), "disk"),
+            (
+                contacts(&["Oncall <oncall@corp.com>", "app@corp.com"]),
+                "load",
+            ),
+            (contacts(&["app@corp.com"]), "ram"),
+        ]);
+
+        let groups = groups
+            .iter()
+            .map(|(recipients, items)| (emails(recipients), items.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [
+                (
+                    vec!["app@corp.com".to_string(), "oncall@corp.com".to_string()],
+                    vec!["cpu", "load"]
+                ),
+                (vec!["storage@corp.com".to_string()], vec!["disk"]),
+                (vec!["app@corp.com".to_string()], vec!["ram"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn severities_are_critical_past_the_middle_to_the_max() {
+        assert_eq!(Severity::of(80, 89.0, true), Severity::Warning);
+        assert_eq!(Severity::of(80, 90.0, true), Severity::Critical);
+        // NOTE: Values without a max are critical at twice their threshold
+        assert_eq!(Severity::of(100, 199.0, false), Severity::Warning);
+        assert_eq!(Severity::of(100, 200.0, false), Severity::Critical);
+        for severity in [Severity::Warning, Severity::Critical] {
+            assert_eq!(severity.to_string().parse(), Ok(severity));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! State file of `--state-path`, written by both modes and shown by `sysmet-http --notify-state`.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use chrono::{DateTime, Utc};
+use log::{tracing, warn};
+use metrics::thresholds::state::{AlertStatus, MetricState, NotifyState, SendAttempt, SendResult};
+
+use crate::{Alert, Result};
+
+/// Read the state file, the per host timestamps of the older files being converted.
+#[tracing::instrument(level = "debug")]
+pub(crate) fn load(path: &Path) -> NotifyState {
+    let Ok(content) = fs::read_to_string(path) else {
+        return NotifyState::default();
+    };
+    serde_json::from_str(&content)
+        .or_else(|err| {
+            serde_json::from_str::<BTreeMap<String, DateTime<Utc>>>(&content)
+                .map(NotifyState::from_cooldowns)
+                .ok()
+                .ok_or(err)
+        })
+        .unwrap_or_else(|err| {
+            warn!("Ignoring invalid state file {path:?}: {err}");
+            NotifyState::default()
+        })
+}
+
+#[tracing::instrument(level = "debug", skip(state))]
+pub(crate) fn save(path: &Path, state: &NotifyState) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+/// Record a check of `hostname`: the `checked` metrics are ok unless one of the `alerts` is about them.
+pub(crate) fn record_check(
+    state: &mut NotifyState,
+    hostname: &str,
+    checked: &[&'static str],
+    alerts: &[Alert],
+    now: DateTime<Utc>,
+) {
+    let host = state.hosts.entry(hostname.to_string()).or_default();
+    host.problem = None;
+    let previous = std::mem::take(&mut host.metrics);
+    for metric in checked
+        .iter()
+        .copied()
+        .chain(alerts.iter().map(Alert::metric))
+    {
+        let alerted = alerts.iter().any(|alert| alert.metric() == metric);
+        let last_alert = previous.get(metric).and_then(|state| state.last_alert);
+        host.metrics.insert(
+            metric.to_string(),
+            MetricState {
+                status: if alerted {
+                    AlertStatus::Alert
+                } else {
+                    AlertStatus::Ok
+                },
+                last_alert: if alerted { Some(now) } else { last_alert },
+            },
+        );
+    }
+}
+
+/// Record why `hostname` could not be checked, its metrics keep their last state.
+pub(crate) fn record_problem(state: &mut NotifyState, hostname: &str, problem: &str) {
+    state.hosts.entry(hostname.to_string()).or_default().problem = Some(problem.to_string());
+}
+
+/// Record the outcome of the mails about `hostname`.
+pub(crate) fn record_attempt(
+    state: &mut NotifyState,
+    hostname: &str,
+    now: DateTime<Utc>,
+    result: SendResult,
+) {
+    state
+        .hosts
+        .entry(hostname.to_string())
+        .or_default()
+        .last_attempt = Some(SendAttempt { at: now, result });
+}
+
+/// Start the cooldown of `hostname`.
+pub(crate) fn record_sent(state: &mut NotifyState, hostname: &str, now: DateTime<Utc>) {
+    state
+        .hosts
+        .entry(hostname.to_string())
+        .or_default()
+        .last_sent = Some(now);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::MissingItem;
+
+    fn time(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn missing_mount() -> Alert {
+        Alert::Missing(MissingItem {
+            metric: "mount",
+            name: "/home".to_string(),
+            last_seen: None,
+        })
+    }
+
+    #[test]
+    fn states_are_read_back_and_older_files_converted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+        assert_eq!(load(&path), NotifyState::default());
+
+        fs::write(&path, r#"{"web": "2024-05-03T12:00:00Z"}"#).unwrap();
+        let legacy = load(&path);
+        assert_eq!(legacy.hosts["web"].last_sent, Some(time(0)));
+        assert_eq!(legacy.checked_at, None);
+
+        let mut state = legacy;
+        state.checked_at = Some(time(1));
+        record_attempt(
+            &mut state,
+            "web",
+            time(1),
+            SendResult::Failed {
+                error: "connection refused".to_string(),
+            },
+        );
+        save(&path, &state).unwrap();
+        assert_eq!(load(&path), state);
+
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(load(&path), NotifyState::default());
+    }
+
+    #[test]
+    fn metrics_keep_their_last_alert_once_ok_again() {
+        let mut state = NotifyState::default();
+        record_problem(&mut state, "web", "database older than 10 minutes");
+        record_check(&mut state, "web", &["cpu"], &[missing_mount()], time(0));
+
+        let host = &state.hosts["web"];
+        assert_eq!(host.problem, None);
+        assert_eq!(
+            host.metrics["cpu"],
+            MetricState {
+                status: AlertStatus::Ok,
+                last_alert: None,
+            }
+        );
+        assert_eq!(
+            host.metrics["mount"],
+            MetricState {
+                status: AlertStatus::Alert,
+                last_alert: Some(time(0)),
+            }
+        );
+
+        record_check(&mut state, "web", &["cpu", "mount"], &[], time(5));
+        assert_eq!(
+            state.hosts["web"].metrics["mount"],
+            MetricState {
+                status: AlertStatus::Ok,
+                last_alert: Some(time(0)),
+            }
+        );
+
+        record_problem(&mut state, "web", "database older than 10 minutes");
+        let host = &state.hosts["web"];
+        assert_eq!(
+            host.problem.as_deref(),
+            Some("database older than 10 minutes")
+        );
+        assert_eq!(host.metrics.len(), 2);
+    }
+
+    #[test]
+    fn only_sent_mails_start_the_cooldown() {
+        let mut state = NotifyState {
+            cooldown_seconds: 3600,
+            ..NotifyState::default()
+        };
+        record_attempt(
+            &mut state,
+            "web",
+            time(0),
+            SendResult::Failed {
+                error: "connection refused".to_string(),
+            },
+        );
+        assert_eq!(state.next_eligible("web", time(0)), None);
+
+        record_attempt(&mut state, "web", time(1), SendResult::Sent);
+        record_sent(&mut state, "web", time(1));
+        assert_eq!(state.next_eligible("web", time(1)), Some(time(61)));
+        assert_eq!(
+            state.hosts["web"].last_attempt,
+            Some(SendAttempt {
+                at: time(1),
+                result: SendResult::Sent,
+            })
+        );
+    }
+}
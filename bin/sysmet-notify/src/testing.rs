@@ -0,0 +1,238 @@
+//! Transports, databases and command lines of the tests.
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use clap::Parser;
+use lettre::{Message, SmtpTransport};
+use metrics::{
+    demo::{DemoOptions, DEFAULT_DEMO_SEED},
+    prelude::*,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{cli::Cli, mail::MailTransport, state, Clock, Result};
+
+/// Contact of the fixture, every alert is routed to it.
+pub(crate) const CONTACT: &str = "admin@example.org";
+
+/// Transport keeping the mails instead of sending them, each send failing when `failing` is set.
+#[derive(Default)]
+pub(crate) struct CapturingTransport {
+    pub sent: RefCell<Vec<Message>>,
+    pub failing: bool,
+    /// Called on each send before the mail is kept, e.g. to break the database in the meantime.
+    pub on_send: Option<Box<dyn Fn()>>,
+}
+
+impl CapturingTransport {
+    pub fn failing() -> Self {
+        Self {
+            failing: true,
+            ..Self::default()
+        }
+    }
+
+    /// Headers and body of the mails sent.
+    pub fn mails(&self) -> Vec<String> {
+        self.sent
+            .borrow()
+            .iter()
+            .map(|message| String::from_utf8_lossy(&message.formatted()).into_owned())
+            .collect()
+    }
+}
+
+impl MailTransport for CapturingTransport {
+    fn send(&self, email: &Message) -> Result<()> {
+        if let Some(on_send) = &self.on_send {
+            on_send();
+        }
+        if self.failing {
+            return Err(color_eyre::eyre::eyre!("connection refused"));
+        }
+        self.sent.borrow_mut().push(email.clone());
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Database of this host with a snapshot every minute of the last half hour, checked by a command line
+/// whose CPU threshold is always crossed and the other ones almost never.
+pub(crate) struct Fixture {
+    pub dir: TempDir,
+    pub now: DateTime<Utc>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let now = Utc::now().duration_trunc(Duration::minutes(1)).unwrap();
+        let demo = Database::demo(&DemoOptions {
+            end: now - Duration::minutes(1),
+            span: Duration::minutes(30),
+            interval: Duration::minutes(1),
+            seed: DEFAULT_DEMO_SEED,
+        })
+        .unwrap();
+        // NOTE: Pushed again so the database is the one of this host, incidents are only recorded in those
+        let mut database = Database::default();
+        for snapshot in demo.snapshots() {
+            database.push_snapshot(snapshot.clone());
+        }
+        let fixture = Self { dir, now };
+        database
+            .write_to_file(&fixture.database().to_string_lossy())
+            .unwrap();
+
+        fixture
+    }
+
+    pub fn database(&self) -> PathBuf {
+        self.dir.path().join("sysmet.db")
+    }
+
+    pub fn state_path(&self) -> PathBuf {
+        self.dir.path().join("state.json")
+    }
+
+    pub fn state(&self) -> metrics::thresholds::state::NotifyState {
+        state::load(&self.state_path())
+    }
+
+    pub fn clock(&self) -> FixedClock {
+        FixedClock(self.now)
+    }
+
+    /// Command line checking the database, followed by `args`.
+    pub fn cli(&self, args: &[&str]) -> Cli {
+        let database = self.database().to_string_lossy().into_owned();
+        let state_path = self.state_path().to_string_lossy().into_owned();
+        let last_sent = self
+            .dir
+            .path()
+            .join("last-sent.txt")
+            .to_string_lossy()
+            .into_owned();
+        let mut cli = vec![
+            "sysmet-notify",
+            "--database",
+            &database,
+            "--state-path",
+            &state_path,
+            "--last-sent-path",
+            &last_sent,
+            "--from",
+            "sysmet@example.org",
+            "--contacts",
+            CONTACT,
+            "--smtp-user",
+            "sysmet",
+            "--smtp-pass",
+            "secret",
+            "--smtp-relay",
+            "127.0.0.1",
+            "--cpu-threshold",
+            "0",
+        ];
+        for threshold in [
+            "--ram-threshold",
+            "--swap-threshold",
+            "--disk-threshold",
+            "--avg-load-threshold",
+        ] {
+            cli.extend([threshold, "100"]);
+        }
+        cli.extend(args);
+        Cli::try_parse_from(cli).unwrap()
+    }
+}
+
+/// SMTP server on the loopback speaking just enough of the protocol to receive mails, refusing them with
+/// `reject`.
+pub(crate) struct SmtpSink {
+    pub port: u16,
+    /// Data of each mail received.
+    pub mails: Arc<Mutex<Vec<String>>>,
+}
+
+impl SmtpSink {
+    pub async fn start(reject: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mails = Arc::new(Mutex::new(Vec::new()));
+        {
+            let mails = mails.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(session(stream, reject, mails.clone()));
+                }
+            });
+        }
+
+        Self { port, mails }
+    }
+
+    /// Real SMTP transport of lettre, without TLS nor authentication.
+    pub fn transport(&self) -> SmtpTransport {
+        SmtpTransport::builder_dangerous("127.0.0.1")
+            .port(self.port)
+            .build()
+    }
+
+    pub fn mails(&self) -> Vec<String> {
+        self.mails.lock().unwrap().clone()
+    }
+}
+
+async fn session(
+    stream: TcpStream,
+    reject: bool,
+    mails: Arc<Mutex<Vec<String>>>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"220 sink ESMTP\r\n").await?;
+    while let Some(line) = lines.next_line().await? {
+        let command = line.to_ascii_uppercase();
+        let reply: &[u8] = if command.starts_with("MAIL FROM") && reject {
+            b"550 rejected\r\n"
+        } else if command.starts_with("DATA") {
+            writer.write_all(b"354 end with <CRLF>.<CRLF>\r\n").await?;
+            let mut data = String::new();
+            while let Some(line) = lines.next_line().await? {
+                if line == "." {
+                    break;
+                }
+                data.push_str(&line);
+                data.push('\n');
+            }
+            mails.lock().unwrap().push(data);
+            b"250 queued\r\n"
+        } else if command.starts_with("QUIT") {
+            writer.write_all(b"221 bye\r\n").await?;
+            return Ok(());
+        } else {
+            b"250 ok\r\n"
+        };
+        writer.write_all(reply).await?;
+    }
+
+    Ok(())
+}
@@ -1,36 +1,598 @@
 #![forbid(unsafe_code)]
 
-use std::env::set_var;
+#[cfg(feature = "export")]
+use std::io::Write;
+#[cfg(any(feature = "import", feature = "export", feature = "demo"))]
+use std::path::Path;
+use std::{
+    env::{args_os, set_var},
+    fs::{self, File},
+    path::PathBuf,
+    str::FromStr,
+};
 
-use clap::{ArgAction, Parser};
+use chrono::{DateTime, Utc};
+use clap::{ArgAction, Parser, Subcommand};
+use color_eyre::eyre::eyre;
+#[cfg(feature = "export")]
+use color_eyre::eyre::WrapErr;
 pub(crate) use color_eyre::Result;
-use metrics::prelude::*;
+use env::{
+    config::{Config, DatabaseSection, EnvSection, UpdateSection, CONFIG_ENV},
+    legacy::{replace_legacy_flags, strict_flags, LegacyFlag},
+    version::{is_verbose_version, BuildInfo},
+};
+use log::{debug, error, info, warn};
+#[cfg(feature = "demo")]
+use metrics::demo::{DemoOptions, DEFAULT_DEMO_SEED};
+#[cfg(feature = "diff")]
+use metrics::diff::{FieldChange, SnapshotDiff};
+#[cfg(feature = "import")]
+use metrics::import::{gosysmet, sar};
+#[cfg(feature = "export")]
+use metrics::schema::{self, ExportedSnapshotV1};
+use metrics::{
+    backup::{self, create_backup},
+    export::statsd::{self, StatsdEmitter},
+    prelude::*,
+};
+#[cfg(feature = "export")]
+use serde::{Deserialize, Serialize};
+
+const BUILD_INFO: BuildInfo = BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+/// Exit code of a write whose read back didn't match, the database being left as it was before.
+const EXIT_WRITE_VERIFICATION_FAILED: i32 = 3;
+
+/// Flags of the original sysmet still accepted with a deprecation warning.
+const LEGACY_FLAGS: &[LegacyFlag] = &[
+    ("-db", "--database"),
+    ("-gc", "--cleanup-older"),
+    ("-in", "--ignored-networks"),
+    ("-gin", "--glob-ignored-networks"),
+];
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
-    #[clap(long, visible_alias = "db", value_name = "FILE")]
-    database: String,
-    #[clap(long, visible_alias = "gc", value_parser, value_name = "DAYS")]
+    #[clap(long, env = CONFIG_ENV, value_name = "FILE", help = "Path to the optional TOML config file")]
+    config: Option<PathBuf>,
+    #[clap(long, help = "Print the effective configuration and exit")]
+    print_config: bool,
+    // NOTE: Global so it can also be given after the subcommand, clap forbids global args to be required
+    #[clap(
+        long,
+        visible_alias = "db",
+        env = "SYSMET_DATABASE",
+        value_name = "FILE",
+        global = true
+    )]
+    database: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_DATABASE_DIR",
+        value_name = "DIR",
+        global = true,
+        conflicts_with = "database",
+        help = "Directory of a database sharded by time period, used instead of --database"
+    )]
+    database_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_SHARD_PERIOD",
+        default_value_t = ShardPeriod::Monthly,
+        value_name = "PERIOD",
+        help = "Time covered by each shard, monthly or weekly"
+    )]
+    shard_period: ShardPeriod,
+    #[clap(
+        long,
+        env = "SYSMET_DB_KEY",
+        hide_env_values = true,
+        value_name = "PASSPHRASE",
+        help = "Passphrase used to encrypt the database, prefer the environment variable"
+    )]
+    db_key: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_DB_KEY_FILE",
+        value_name = "FILE",
+        help = "File containing the database passphrase"
+    )]
+    db_key_file: Option<PathBuf>,
+    #[clap(
+        long,
+        conflicts_with = "decrypt",
+        help = "Rewrite the database encrypted with the provided key"
+    )]
+    encrypt: bool,
+    #[clap(long, help = "Rewrite the database unencrypted")]
+    decrypt: bool,
+    #[clap(
+        long,
+        env = "SYSMET_CHECKSUMS",
+        help = "Write the database with a checksum per snapshot to detect corruption"
+    )]
+    checksums: bool,
+    #[clap(
+        long,
+        env = "SYSMET_VERIFY_WRITE",
+        help = "Read the database back after writing it, its previous content is restored when it doesn't match"
+    )]
+    verify_write: bool,
+    #[clap(
+        long,
+        visible_alias = "gc",
+        env = "SYSMET_CLEANUP_OLDER",
+        value_parser,
+        value_name = "DAYS"
+    )]
     cleanup_older: Option<i64>,
-    #[clap(long, visible_alias = "in", value_name = "NETWORKS NAMES")]
+    #[clap(
+        long,
+        env = "SYSMET_KEEP_ANNOTATIONS",
+        help = "Keep the annotations older than --cleanup-older, they are tiny"
+    )]
+    keep_annotations: bool,
+    #[clap(
+        long,
+        env = "SYSMET_VACUUM",
+        help = "Also remove the annotations older than the oldest snapshot left"
+    )]
+    vacuum: bool,
+    #[clap(
+        long,
+        env = "SYSMET_SPILL_ON_LOCK_TIMEOUT",
+        help = "Keep the snapshot in <database>.pending when the database stays locked, merged by the next run"
+    )]
+    spill_on_lock_timeout: bool,
+    #[clap(
+        long,
+        visible_alias = "in",
+        env = "SYSMET_IGNORED_NETWORKS",
+        value_delimiter = ',',
+        value_name = "NETWORKS NAMES"
+    )]
     ignored_networks: Vec<String>,
-    #[clap(long, visible_alias = "gin", value_name = "GLOB")]
+    #[clap(
+        long,
+        visible_alias = "gin",
+        env = "SYSMET_GLOB_IGNORED_NETWORKS",
+        value_delimiter = ',',
+        value_name = "GLOB"
+    )]
     glob_ignored_networks: Vec<String>, // TODO: Glob ignore
+    #[clap(
+        long,
+        env = "SYSMET_NO_CGROUP_LIMITS",
+        help = "In a container, compute the memory and CPU usages against the host instead of the cgroup limits"
+    )]
+    no_cgroup_limits: bool,
+    #[clap(
+        long = "custom-metric",
+        env = "SYSMET_CUSTOM_METRICS",
+        value_delimiter = ',',
+        value_name = "NAME=PATH",
+        value_parser = FileCollector::from_str,
+        help = "Number read from a file on each snapshot and charted under its name, can be repeated"
+    )]
+    custom_metrics: Vec<FileCollector>,
+    #[clap(
+        long,
+        env = "SYSMET_BACKUP_DIR",
+        value_name = "DIR",
+        help = "Directory of the backups taken before destructive operations, defaults to the database directory"
+    )]
+    backup_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "SYSMET_BACKUP_KEEP",
+        default_value = "3",
+        value_parser = backup::parse_keep,
+        value_name = "NUMBER OF BACKUPS",
+        help = "Number of backups to keep, at least 1"
+    )]
+    backup_keep: usize,
+    #[clap(
+        long,
+        help = "Do not back up the database before destructive operations"
+    )]
+    no_backup: bool,
+    #[clap(
+        long,
+        env = "SYSMET_STATSD_ADDR",
+        value_name = "HOST:PORT",
+        help = "Also send the snapshot as statsd metrics over UDP"
+    )]
+    statsd_addr: Option<String>,
+    #[clap(
+        long,
+        env = "SYSMET_STATSD_PREFIX",
+        default_value = statsd::DEFAULT_PREFIX,
+        value_name = "PREFIX",
+        help = "Prefix of the statsd metrics names"
+    )]
+    statsd_prefix: String,
+    #[clap(
+        long,
+        env = "SYSMET_SLOW_COLLECTION_WARN",
+        value_name = "MS",
+        help = "Warn when collecting the snapshot takes longer, with the slowest part of the collection"
+    )]
+    slow_collection_warn: Option<u64>,
     #[clap(short, long = "verbose", action = ArgAction::Count)]
     verbosity: u8,
     #[clap(long = "dry-run", action, default_value = "false")]
     dry_run: bool,
+    #[clap(
+        long,
+        value_name = "RFC3339",
+        value_parser = parse_rfc3339,
+        requires = "allow_backdate",
+        conflicts_with = "times",
+        help = "Stamp the snapshot with this past date rather than now, inserted in time order"
+    )]
+    at: Option<DateTime<Utc>>,
+    #[clap(
+        long,
+        help = "Allow --at, the rates around the backdated snapshot being ignored"
+    )]
+    allow_backdate: bool,
     // NOTE: This is only used for benchmarking and testing purposes and should not be used in normally.
     #[clap(long, value_name = "NUMBER OF SNAPSHOTS", hide(true))]
     times: Option<u32>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replace the database with a backup after checking it can be loaded
+    Restore {
+        #[clap(long, value_name = "BACKUP")]
+        from: PathBuf,
+    },
+    /// Check the integrity of the database, every corrupt snapshot is reported
+    Verify,
+    /// Print the header of the database, its snapshots and the build which last wrote it
+    Inspect {
+        #[cfg(feature = "diff")]
+        #[clap(
+            long,
+            num_args = 2,
+            value_names = ["TIME1", "TIME2"],
+            value_parser = parse_export_time,
+            help = "Compare the snapshots nearest both dates (or durations ago) field by field instead"
+        )]
+        diff: Option<Vec<DateTime<Utc>>>,
+        #[cfg(feature = "diff")]
+        #[clap(
+            long,
+            default_value = "table",
+            requires = "diff",
+            help = "Format of the diff, table or json"
+        )]
+        format: DiffFormat,
+    },
+    /// Split a database into one file per period of --shard-period, existing shards are never overwritten
+    Shard {
+        #[clap(long, value_name = "DATABASE")]
+        from: PathBuf,
+        #[clap(long, value_name = "DIR")]
+        to: PathBuf,
+    },
+    /// Write a database of plausible synthetic snapshots to --database, the same seed and end giving the same file
+    #[cfg(feature = "demo")]
+    Demo {
+        #[clap(
+            long,
+            default_value = "7",
+            help = "Days between the oldest and the newest snapshots"
+        )]
+        days: u32,
+        #[clap(
+            long,
+            default_value = "1m",
+            value_parser = humantime::parse_duration,
+            help = "Time between two snapshots"
+        )]
+        interval: std::time::Duration,
+        #[clap(long, default_value_t = DEFAULT_DEMO_SEED)]
+        seed: u64,
+        #[clap(
+            long,
+            value_name = "RFC3339|DURATION",
+            value_parser = parse_export_time,
+            help = "Time of the newest snapshot, or this long ago, now without it"
+        )]
+        end: Option<DateTime<Utc>>,
+    },
+    /// Print every snapshot as one JSON object per line, in the versioned shape of `metrics::schema`
+    #[cfg(feature = "export")]
+    Export {
+        #[clap(long, help = "Print the JSON Schema of each versioned payload instead")]
+        print_schema: bool,
+        #[clap(
+            long,
+            help = "Print the scalar fields as CSV, the disks, temperatures and custom metrics are left out"
+        )]
+        csv: bool,
+        #[clap(
+            long,
+            help = "Leave the CSV header out, to concatenate the output onto a previous export"
+        )]
+        append: bool,
+        #[clap(
+            long,
+            value_name = "RFC3339|DURATION",
+            value_parser = parse_export_time,
+            conflicts_with = "since_last",
+            help = "Only export the snapshots taken from this date, or this long ago (e.g. 7d)"
+        )]
+        since: Option<DateTime<Utc>>,
+        #[clap(
+            long,
+            value_name = "RFC3339|DURATION",
+            value_parser = parse_export_time,
+            help = "Only export the snapshots taken before this date, or this long ago"
+        )]
+        until: Option<DateTime<Utc>>,
+        #[clap(
+            long,
+            requires = "state_file",
+            help = "Continue after the last snapshot recorded in --state-file, from the start without it"
+        )]
+        since_last: bool,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "Record the last exported snapshot, for the next export with --since-last"
+        )]
+        state_file: Option<PathBuf>,
+    },
+    /// Import the history of sysstat, from sa files read with sadf or from `sadf -j` JSON exports
+    #[cfg(feature = "import")]
+    Backfill {
+        #[clap(long, value_name = "FILE|DIR")]
+        from_sar: PathBuf,
+    },
+    /// Import the history of the original Go sysmet from its database file
+    #[cfg(feature = "import")]
+    ImportGo {
+        #[clap(long, value_name = "FILE")]
+        from: PathBuf,
+    },
+}
+
+/// The command merges the snapshots of another history into the database.
+#[cfg(feature = "import")]
+fn imports(command: Option<&Command>) -> bool {
+    matches!(
+        command,
+        Some(Command::Backfill { .. } | Command::ImportGo { .. })
+    )
+}
+
+#[cfg(not(feature = "import"))]
+fn imports(_command: Option<&Command>) -> bool {
+    false
+}
+
+/// Output of `inspect --diff`.
+#[cfg(feature = "diff")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffFormat {
+    Table,
+    Json,
+}
+
+#[cfg(feature = "diff")]
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Invalid diff format {value}, expected table or json"
+            )),
+        }
+    }
+}
+
+/// RFC3339 date of `--at`, which only backdates.
+fn parse_rfc3339(value: &str) -> std::result::Result<DateTime<Utc>, String> {
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|err| format!("`{value}` is not a RFC3339 date: {err}"))?
+        .with_timezone(&Utc);
+    if time > Utc::now() {
+        return Err(format!("`{value}` is in the future"));
+    }
+    Ok(time)
+}
+
+/// RFC3339 date, or a duration before now (e.g. `7d`).
+#[cfg(any(feature = "export", feature = "diff", feature = "demo"))]
+fn parse_export_time(value: &str) -> std::result::Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let ago = humantime::parse_duration(value)
+        .map_err(|err| format!("`{value}` is neither a RFC3339 date nor a duration: {err}"))?;
+    chrono::Duration::from_std(ago)
+        .map(|ago| Utc::now() - ago)
+        .map_err(|err| err.to_string())
+}
+
+/// Newest exported snapshot, the next export with `--since-last` continues right after it.
+#[cfg(feature = "export")]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct ExportState {
+    time: DateTime<Utc>,
+    /// Snapshots at `time` already exported, several snapshots may share a time and they are exported in
+    /// the order of the database.
+    exported_at_time: usize,
+}
+
+#[cfg(feature = "export")]
+impl ExportState {
+    /// Read the state file, `None` before the first export.
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content).wrap_err_with(
+                || {
+                    format!(
+                        "Invalid export state file {path:?}, remove it to export everything again"
+                    )
+                },
+            )?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// State once `time` is exported after the snapshots of `self`.
+    fn after(state: Option<Self>, time: DateTime<Utc>) -> Self {
+        let exported_at_time = match state {
+            Some(state) if state.time == time => state.exported_at_time + 1,
+            _ => 1,
+        };
+        Self {
+            time,
+            exported_at_time,
+        }
+    }
+}
+
+/// Flags of the `export` subcommand.
+#[cfg(feature = "export")]
+#[derive(Default)]
+struct ExportOptions<'a> {
+    csv: bool,
+    append: bool,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    since_last: bool,
+    state_file: Option<&'a Path>,
+}
+
+/// Write the snapshots of the databases at `paths` to `out` then record the last one in the state file,
+/// returns the number of snapshots exported.
+#[cfg(feature = "export")]
+fn export(
+    paths: &[String],
+    key: Option<&DatabaseKey>,
+    options: &ExportOptions<'_>,
+    out: &mut impl Write,
+) -> Result<usize> {
+    let previous = match options.state_file {
+        Some(state_file) if options.since_last => ExportState::load(state_file)?,
+        _ => None,
+    };
+    let range = previous
+        .map(|previous| previous.time)
+        .or(options.since)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+        ..options.until.unwrap_or(DateTime::<Utc>::MAX_UTC);
+    // NOTE: The snapshots at the time of the state which were already exported are the first ones
+    let mut skipped_ties = previous.map_or(0, |previous| previous.exported_at_time);
+    let mut state = previous;
+    let mut exported = 0_usize;
+
+    if options.csv && !options.append {
+        writeln!(out, "{}", ExportedSnapshotV1::CSV_HEADER)?;
+    }
+    // NOTE: Shards are sorted by period so the snapshots stay sorted by time across them
+    for path in paths {
+        let database = Database::from_file(path, key)?;
+        for snapshot in database.snapshots_within(range.clone()) {
+            if skipped_ties > 0 && previous.is_some_and(|previous| previous.time == snapshot.time) {
+                skipped_ties -= 1;
+                continue;
+            }
+            let exported_snapshot = ExportedSnapshotV1::from(snapshot);
+            if options.csv {
+                writeln!(out, "{}", exported_snapshot.csv_record())?;
+            } else {
+                serde_json::to_writer(&mut *out, &exported_snapshot)?;
+                writeln!(out)?;
+            }
+            state = Some(ExportState::after(state, snapshot.time));
+            exported += 1;
+        }
+    }
+    out.flush()?;
+
+    // NOTE: Only written once the output is complete, a failed export is done again by the next one
+    if let Some((state_file, state)) = options.state_file.zip(state) {
+        fs::write(state_file, serde_json::to_string_pretty(&state)?)
+            .wrap_err_with(|| format!("Failed to write the export state file {state_file:?}"))?;
+    }
+    Ok(exported)
+}
+
+impl Cli {
+    fn effective_config(&self) -> Config {
+        Config {
+            database: DatabaseSection {
+                path: self.database.clone(),
+                key: self.db_key.clone(),
+                key_file: self.db_key_file.clone(),
+                ..Default::default()
+            },
+            update: UpdateSection {
+                cleanup_older: self.cleanup_older,
+                keep_annotations: Some(self.keep_annotations),
+                vacuum: Some(self.vacuum),
+                spill_on_lock_timeout: Some(self.spill_on_lock_timeout),
+                ignored_networks: self.ignored_networks.clone(),
+                glob_ignored_networks: self.glob_ignored_networks.clone(),
+                custom_metrics: self
+                    .custom_metrics
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                backup_dir: self.backup_dir.clone(),
+                backup_keep: Some(self.backup_keep),
+                checksums: Some(self.checksums),
+                verify_write: Some(self.verify_write),
+                no_cgroup_limits: Some(self.no_cgroup_limits),
+                statsd_addr: self.statsd_addr.clone(),
+                statsd_prefix: Some(self.statsd_prefix.clone()),
+                slow_collection_warn: self.slow_collection_warn,
+                database_dir: self.database_dir.clone(),
+                shard_period: Some(self.shard_period.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn backup_policy(&self) -> Option<BackupPolicy> {
+        (!self.no_backup).then(|| BackupPolicy {
+            dir: self.backup_dir.clone(),
+            keep: self.backup_keep,
+        })
+    }
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let app = Cli::parse();
+    // NOTE: The env and the config file must be loaded before parsing as clap reads them as environment variables
     env::setup_env();
+    let config = Config::from_args()?;
+    if let Some(config) = &config {
+        config.database.apply();
+        config.update.apply();
+    }
+
+    let legacy_args = replace_legacy_flags(args_os(), LEGACY_FLAGS, strict_flags())?;
+    if is_verbose_version(&legacy_args.args) {
+        println!("{}", BUILD_INFO.verbose());
+        return Ok(());
+    }
+    let app = Cli::parse_from(legacy_args.args);
 
     if app.verbosity > 2 {
         set_var("LOG_LEVEL", "trace");
@@ -41,36 +603,1031 @@ fn main() -> Result<()> {
     }
     log::setup_hierarchical_logger();
 
-    let (mut database, file, path) = Database::from_file_with_write(&app.database)?;
-    if let Some(times) = app.times {
-        for _ in 0..times {
-            database.take_snapshot(
-                app.ignored_networks
-                    .iter()
-                    .map(|n| n.as_ref())
-                    .collect::<Vec<&str>>()
-                    .as_ref(),
+    for (legacy, current) in legacy_args.used {
+        warn!("{legacy} is deprecated, use {current} instead");
+    }
+
+    if let Some(unknown_keys) = config
+        .map(|config| config.unknown_keys())
+        .filter(|keys| !keys.is_empty())
+    {
+        warn!(
+            "Unknown keys in the config file: {}",
+            unknown_keys.join(", ")
+        );
+    }
+    if app.print_config {
+        println!("{}", app.effective_config().masked().to_toml()?);
+        return Ok(());
+    }
+
+    #[cfg(feature = "export")]
+    if let Some(Command::Export {
+        print_schema: true, ..
+    }) = &app.command
+    {
+        println!("{}", serde_json::to_string_pretty(&schema::json_schemas())?);
+        return Ok(());
+    }
+
+    let key = DatabaseKey::resolve(app.db_key.as_deref(), app.db_key_file.as_deref())?;
+    if app.encrypt && key.is_none() {
+        return Err(eyre!(
+            "--encrypt requires a key, set SYSMET_DB_KEY or --db-key-file"
+        ));
+    }
+
+    if let Some(Command::Shard { from, to }) = &app.command {
+        let mut database = Database::from_file(&from.to_string_lossy(), key.as_ref())?;
+        database.set_writer(writer());
+        if app.dry_run {
+            info!(
+                "{} snapshots of {from:?} would be split into {to:?}",
+                database.len()
+            );
+        } else {
+            let sharded = ShardedDatabase::split(&database, to, app.shard_period)?;
+            info!(
+                "Split {from:?} into {} shards in {to:?}",
+                sharded.shards().len()
+            );
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "demo")]
+    if let Some(Command::Demo {
+        days,
+        interval,
+        seed,
+        end,
+    }) = &app.command
+    {
+        let Some(path) = app.database.as_deref() else {
+            return Err(eyre!("demo requires --database, set it or SYSMET_DATABASE"));
+        };
+        // NOTE: Never replace a real history with synthetic snapshots
+        if Path::new(path).exists() {
+            return Err(eyre!(
+                "{path} already exists, demo only writes new databases"
+            ));
+        }
+        let options = DemoOptions {
+            end: end.unwrap_or_else(Utc::now),
+            span: chrono::Duration::days(i64::from(*days)),
+            interval: chrono::Duration::from_std(*interval)?,
+            seed: *seed,
+        };
+        let mut database = Database::demo(&options)?;
+        database.set_key(if app.encrypt { key } else { None });
+        database.set_writer(writer());
+        if app.dry_run {
+            info!(
+                "{} demo snapshots would be written to {path}",
+                database.len()
+            );
+        } else {
+            database.write_to_file(path)?;
+            info!("Wrote {} demo snapshots to {path}", database.len());
+        }
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let mut sharded = app
+        .database_dir
+        .as_deref()
+        .map(Database::open_sharded)
+        .transpose()?;
+    let database_path = match (&sharded, app.database.as_deref()) {
+        (Some(sharded), _) => {
+            fs::create_dir_all(sharded.dir())?;
+            sharded
+                .shard_path(app.shard_period, app.at.unwrap_or(now))
+                .to_string_lossy()
+                .to_string()
+        }
+        (None, Some(path)) => path.to_string(),
+        (None, None) => {
+            return Err(eyre!(
+                "--database or --database-dir is required, set it or SYSMET_DATABASE"
+            ))
+        }
+    };
+    let database_path = database_path.as_str();
+
+    let paths = match &sharded {
+        Some(sharded) => sharded
+            .shards()
+            .iter()
+            .map(|shard| shard.path.to_string_lossy().to_string())
+            .collect(),
+        None => vec![database_path.to_string()],
+    };
+
+    #[cfg(feature = "diff")]
+    if let Some(Command::Inspect {
+        diff: Some(times),
+        format,
+    }) = &app.command
+    {
+        let databases = paths
+            .iter()
+            .map(|path| Database::from_file(path, key.as_ref()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let nearest = |at: DateTime<Utc>| {
+            nearest_snapshot(&databases, at)
+                .ok_or_else(|| eyre!("No snapshot to compare in {paths:?}"))
+        };
+        let diff = SnapshotDiff::between(nearest(times[0])?, nearest(times[1])?)?;
+        match format {
+            DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+            DiffFormat::Table => print_diff(&diff),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Inspect { .. }) = &app.command {
+        for path in &paths {
+            let database = Database::from_file(path, key.as_ref())?;
+            println!("{path}");
+            println!("  version: {}", database.version());
+            println!("  hostname: {}", database.hostname().unwrap_or("unknown"));
+            match database.time_range() {
+                Some((first, last)) => {
+                    println!("  snapshots: {} from {first} to {last}", database.len());
+                }
+                None => println!("  snapshots: 0"),
+            }
+            let counts = database.collection_counts();
+            println!(
+                "  annotations: {} ({} alerts)",
+                counts.annotations, counts.alerts
+            );
+            println!("  custom metrics: {}", counts.custom_keys);
+            println!(
+                "  checksums: {}",
+                if database.has_checksums() {
+                    "yes"
+                } else {
+                    "no"
+                }
+            );
+            match database.last_write() {
+                Some(last_write) => println!("  last written by {last_write}"),
+                None => println!("  last written by an unknown build"),
+            }
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "export")]
+    if let Some(Command::Export {
+        csv,
+        append,
+        since,
+        until,
+        since_last,
+        state_file,
+        ..
+    }) = &app.command
+    {
+        let options = ExportOptions {
+            csv: *csv,
+            append: *append,
+            since: *since,
+            until: *until,
+            since_last: *since_last,
+            state_file: state_file.as_deref(),
+        };
+        let exported = export(
+            &paths,
+            key.as_ref(),
+            &options,
+            &mut std::io::stdout().lock(),
+        )?;
+        info!("Exported {exported} snapshots");
+        return Ok(());
+    }
+
+    if let Some(Command::Verify) = &app.command {
+        let mut corrupt_databases = Vec::new();
+        for path in &paths {
+            let report = Database::verify_file(path, key.as_ref())?;
+            if !report.checksummed {
+                println!("{path} has no checksums, only its decoding was checked");
+            }
+            for corrupt_record in &report.corrupt_records {
+                println!("Corrupt {corrupt_record}");
+            }
+            println!(
+                "{}{} valid snapshots, {} corrupt",
+                if sharded.is_some() {
+                    format!("{path}: ")
+                } else {
+                    String::new()
+                },
+                report.snapshots,
+                report.corrupt_records.len()
+            );
+            if !report.is_ok() {
+                corrupt_databases.push(format!(
+                    "{path} has {} corrupt snapshots",
+                    report.corrupt_records.len()
+                ));
+            }
+        }
+        if !corrupt_databases.is_empty() {
+            return Err(eyre!(corrupt_databases.join(", ")));
+        }
+        return Ok(());
+    }
+
+    // NOTE: Shards are independent files, these are run on each of them with --database
+    let rewrites_database = app.encrypt
+        || app.decrypt
+        || matches!(app.command, Some(Command::Restore { .. }))
+        || imports(app.command.as_ref());
+    if sharded.is_some() && rewrites_database {
+        return Err(eyre!(
+            "--encrypt, --decrypt, restore, backfill and import-go are not supported with --database-dir, give each shard with --database"
+        ));
+    }
+
+    if let Some(Command::Restore { from }) = &app.command {
+        let snapshots = Database::validate_backup(from, key.as_ref())?;
+        info!("Backup {from:?} is valid with {snapshots} snapshots");
+        if !app.dry_run {
+            Database::restore_backup(
+                from,
+                database_path,
+                key.as_ref(),
+                app.backup_policy().as_ref(),
             )?;
         }
+        return Ok(());
+    }
+
+    let networks_to_ignore = app
+        .ignored_networks
+        .iter()
+        .map(|n| n.as_ref())
+        .collect::<Vec<&str>>();
+    let mut collectors = CollectorRegistry::default();
+    for custom_metric in &app.custom_metrics {
+        collectors.register(custom_metric.clone());
+    }
+
+    let (mut database, file, path) =
+        match Database::from_file_with_write(database_path, key.as_ref()) {
+            // NOTE: Only the runs taking a snapshot spill it, the other commands keep failing
+            Err(Error::LockFileTimeout(locked))
+                if app.spill_on_lock_timeout && app.command.is_none() =>
+            {
+                warn!("{locked:?} is still locked, spilling the snapshot to its pending directory");
+                for _ in 0..app.times.unwrap_or(1) {
+                    let snapshot = SnapShot::new_with_collectors_at(
+                        app.at.unwrap_or_else(Utc::now),
+                        &networks_to_ignore,
+                        &collectors,
+                        !app.no_cgroup_limits,
+                    )?;
+                    if app.dry_run {
+                        info!("The snapshot of {} would be spilled", snapshot.time);
+                    } else {
+                        Database::spill_snapshot(database_path, &snapshot, key.as_ref())?;
+                    }
+                }
+                return Ok(());
+            }
+            result => result?,
+        };
+    database.set_writer(writer());
+    if app.checksums {
+        database.set_checksums(true);
+    }
+    // NOTE: A new shard starts from the last one so host changes between both are still noticed
+    if let Some(previous) = sharded
+        .as_ref()
+        .filter(|_| database.is_empty())
+        .and_then(|sharded| sharded.shard_before(now))
+    {
+        match Database::from_file(&previous.path.to_string_lossy(), key.as_ref()) {
+            Ok(previous) => database.continue_from(&previous),
+            Err(err) => warn!(
+                "Failed to read the previous shard {:?}: {err}",
+                previous.path
+            ),
+        }
+    }
+
+    // NOTE: Taken while holding the lock, before anything rewrites or removes snapshots,
+    // shards are backed up when removed
+    let is_destructive = app.encrypt
+        || app.decrypt
+        || imports(app.command.as_ref())
+        || app.vacuum
+        || (app.cleanup_older.is_some() && sharded.is_none());
+    if is_destructive && !app.dry_run {
+        if let Some(policy) = app.backup_policy() {
+            if let Err(err) = create_backup(&path, &policy) {
+                database.close_file(&path)?;
+                return Err(err.into());
+            }
+        }
+    }
+
+    if app.encrypt || app.decrypt {
+        // NOTE: Migrations only rewrite the database in place, no snapshot is taken
+        database.set_key(if app.encrypt { key } else { None });
+        if app.dry_run {
+            database.close_file(&path)?;
+        } else {
+            write_database(&app, &mut database, file, &path)?;
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "import")]
+    if let Some(Command::Backfill { from_sar }) = &app.command {
+        let report = match backfill(&app, &mut database, from_sar) {
+            Ok(report) => report,
+            Err(err) => {
+                database.close_file(&path)?;
+                return Err(err);
+            }
+        };
+        println!(
+            "{} snapshots imported, {} skipped as duplicates, {} rejected",
+            report.imported, report.duplicates, report.rejected
+        );
+        if app.dry_run || report.imported == 0 {
+            database.close_file(&path)?;
+        } else {
+            write_database(&app, &mut database, file, &path)?;
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "import")]
+    if let Some(Command::ImportGo { from }) = &app.command {
+        let report = match import_go(&app, &mut database, from) {
+            Ok(report) => report,
+            Err(err) => {
+                database.close_file(&path)?;
+                return Err(err);
+            }
+        };
+        println!(
+            "{} snapshots imported, {} skipped as duplicates, {} failed",
+            report.imported, report.duplicates, report.rejected
+        );
+        if app.dry_run || report.imported == 0 {
+            database.close_file(&path)?;
+        } else {
+            write_database(&app, &mut database, file, &path)?;
+        }
+        return Ok(());
+    }
+
+    // NOTE: Spilled by the previous runs which found the database locked, before the new snapshot
+    let pending = match database.merge_pending(&path, key.as_ref()) {
+        Ok(pending) => pending,
+        Err(err) => {
+            database.close_file(&path)?;
+            return Err(err.into());
+        }
+    };
+
+    if let Some(at) = app.at {
+        if let Err(err) =
+            database.take_snapshot_at(at, &networks_to_ignore, &collectors, !app.no_cgroup_limits)
+        {
+            database.close_file(&path)?;
+            return Err(err.into());
+        }
+    } else if let Some(times) = app.times {
+        for _ in 0..times {
+            database.take_snapshot(&networks_to_ignore, &collectors, !app.no_cgroup_limits)?;
+        }
     } else {
-        database.take_snapshot(
-            app.ignored_networks
-                .iter()
-                .map(|n| n.as_ref())
-                .collect::<Vec<&str>>()
-                .as_ref(),
-        )?;
+        database.take_snapshot(&networks_to_ignore, &collectors, !app.no_cgroup_limits)?;
     }
 
-    if let Some(days_number) = app.cleanup_older {
-        database.remove_older(days_number)?;
+    if let Some(threshold) = app.slow_collection_warn {
+        warn_slow_collection(&database, threshold);
+    }
+
+    if let (Some(days_number), None) = (app.cleanup_older, &sharded) {
+        let pruned = database.remove_older(days_number, app.keep_annotations)?;
+        info!("Cleanup: {pruned}");
+    }
+    if app.vacuum {
+        let vacuumed = database.vacuum();
+        info!("Vacuum: {vacuumed}");
     }
 
     if app.dry_run {
         database.close_file(&path)?;
     } else {
-        database.write_and_close_file(file, &path)?;
+        write_database(&app, &mut database, file, &path)?;
+        pending.remove_files()?;
+    }
+
+    // NOTE: Retention of a sharded database only removes whole shards
+    if let (Some(days_number), Some(sharded)) = (app.cleanup_older, &mut sharded) {
+        let oldest_date = now
+            .checked_sub_signed(chrono::Duration::days(days_number))
+            .ok_or_else(|| eyre!("--cleanup-older {days_number} is too big"))?;
+        if app.dry_run {
+            for shard in sharded
+                .shards()
+                .iter()
+                .filter(|shard| shard.end <= oldest_date)
+            {
+                info!("Shard {:?} would be removed", shard.path);
+            }
+        } else {
+            sharded.remove_older(
+                oldest_date,
+                app.backup_policy().as_ref(),
+                app.keep_annotations,
+                key.as_ref(),
+            )?;
+        }
+    }
+
+    if let Some(addr) = &app.statsd_addr {
+        emit_statsd(&app, addr, &database);
     }
 
     Ok(())
 }
+
+/// Snapshot taken the closest to `at` across the shards.
+#[cfg(feature = "diff")]
+fn nearest_snapshot(databases: &[Database], at: DateTime<Utc>) -> Option<&SnapShot> {
+    databases
+        .iter()
+        .filter_map(|database| database.nearest_snapshot(at, chrono::Duration::max_value()))
+        .min_by_key(|snapshot| (snapshot.time - at).abs())
+}
+
+/// Section of `print_diff` listing `change`.
+#[cfg(feature = "diff")]
+fn diff_section(change: &FieldChange) -> &'static str {
+    match change {
+        FieldChange::Added { .. } => "Added",
+        FieldChange::Removed { .. } => "Removed",
+        FieldChange::Numeric { .. } | FieldChange::Changed { .. } => "Changed",
+    }
+}
+
+/// Print the changes of `diff` grouped by kind, the numbers as a table.
+#[cfg(feature = "diff")]
+fn print_diff(diff: &SnapshotDiff) {
+    println!("from {} ({} bytes)", diff.from_time, diff.from_size);
+    println!(
+        "to   {} ({} bytes, {:+})",
+        diff.to_time,
+        diff.to_size,
+        diff.to_size as i64 - diff.from_size as i64
+    );
+
+    let width = diff
+        .changes
+        .iter()
+        .map(|change| change.path.len())
+        .max()
+        .unwrap_or_default();
+    for section in ["Added", "Removed", "Changed"] {
+        let mut changes = diff
+            .changes
+            .iter()
+            .filter(|field| diff_section(&field.change) == section)
+            .peekable();
+        if changes.peek().is_none() {
+            continue;
+        }
+        println!("\n{section}");
+        for field in changes {
+            let path = &field.path;
+            match &field.change {
+                FieldChange::Added { value } | FieldChange::Removed { value } => {
+                    println!("  {path:width$}  {value}");
+                }
+                FieldChange::Numeric {
+                    from,
+                    to,
+                    delta,
+                    rate,
+                } => println!(
+                    "  {path:width$}  {from} -> {to} ({delta:+}{})",
+                    rate.map(|rate| format!(", {rate:.2}/s"))
+                        .unwrap_or_default()
+                ),
+                FieldChange::Changed { from, to } => println!("  {path:width$}  {from} -> {to}"),
+            }
+        }
+    }
+    if diff.changes.is_empty() {
+        println!("\nNo change");
+    }
+}
+
+/// Write the database and release its lock, reading it back first with `--verify-write`.
+///
+/// Exits with `EXIT_WRITE_VERIFICATION_FAILED` when the read back doesn't match so monitoring can tell it
+/// apart from the other failures.
+#[allow(clippy::exit)]
+fn write_database(app: &Cli, database: &mut Database, file: File, path: &PathBuf) -> Result<()> {
+    let result = if app.verify_write {
+        database.write_verified(file, path)
+    } else {
+        database.write_and_close_file(file, path)
+    };
+
+    match result {
+        Err(err) => match write_exit_code(&err) {
+            Some(code) => {
+                error!("{err}");
+                std::process::exit(code);
+            }
+            None => Err(err.into()),
+        },
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Dedicated exit code of a failed write, `None` when it exits like any other error.
+fn write_exit_code(err: &Error) -> Option<i32> {
+    matches!(err, Error::WriteVerificationFailed(_)).then_some(EXIT_WRITE_VERIFICATION_FAILED)
+}
+
+/// Identity of this build, stamped on the databases it writes.
+fn writer() -> Writer {
+    Writer {
+        name: BUILD_INFO.name.to_string(),
+        version: BUILD_INFO.version.to_string(),
+        commit: BUILD_INFO.commit.to_string(),
+    }
+}
+
+/// Merge every sar file of `from` into the database, a file failing to parse is counted as rejected.
+#[cfg(feature = "import")]
+fn backfill(app: &Cli, database: &mut Database, from: &Path) -> Result<ImportReport> {
+    let networks_to_ignore = app
+        .ignored_networks
+        .iter()
+        .map(|n| n.as_ref())
+        .collect::<Vec<&str>>();
+    let mut report = ImportReport::default();
+    let mut imported = Vec::new();
+    for file in sar::sar_files(from)? {
+        match sar::read_sar_file(&file)
+            .and_then(|json| sar::parse_sadf_json(&json, &networks_to_ignore))
+        {
+            Ok(import) => {
+                debug!(
+                    "{} snapshots read from {file:?}, {} rejected",
+                    import.snapshots.len(),
+                    import.rejected
+                );
+                report.rejected += import.rejected;
+                imported.extend(import.snapshots);
+            }
+            Err(err) => {
+                warn!("Failed to import {file:?}: {err}");
+                report.rejected += 1;
+            }
+        }
+    }
+    report.add(database.merge_imported(imported));
+
+    Ok(report)
+}
+
+/// Merge the snapshots of the Go sysmet database `from`, its unreadable records being counted as rejected.
+#[cfg(feature = "import")]
+fn import_go(app: &Cli, database: &mut Database, from: &Path) -> Result<ImportReport> {
+    let networks_to_ignore = app
+        .ignored_networks
+        .iter()
+        .map(|n| n.as_ref())
+        .collect::<Vec<&str>>();
+    let import = gosysmet::import_go_database(from, &networks_to_ignore)?;
+    debug!(
+        "{} snapshots read from {from:?}, {} rejected",
+        import.snapshots.len(),
+        import.rejected
+    );
+    let mut report = ImportReport {
+        rejected: import.rejected,
+        ..Default::default()
+    };
+    report.add(database.merge_imported(import.snapshots));
+
+    Ok(report)
+}
+
+/// Warn when collecting the last snapshot took more than `threshold` milliseconds.
+fn warn_slow_collection(database: &Database, threshold: u64) {
+    if let Some(warning) = slow_collection_warning(database, threshold) {
+        warn!("{warning}");
+    }
+}
+
+/// Warning about the collection of the last snapshot taking more than `threshold` milliseconds, naming its
+/// slowest part.
+fn slow_collection_warning(database: &Database, threshold: u64) -> Option<String> {
+    let snapshot = database.snapshots().last()?;
+    let duration = snapshot
+        .collection_duration_ms
+        .filter(|duration| *duration > threshold)?;
+
+    Some(match snapshot.slowest_collector() {
+        Some((collector, collector_duration)) => format!(
+            "Collecting the snapshot took {duration}ms (more than {threshold}ms), {collector} took {collector_duration}ms"
+        ),
+        None => format!("Collecting the snapshot took {duration}ms (more than {threshold}ms)"),
+    })
+}
+
+/// Send the last snapshot to statsd, failures are only logged as the database is already written.
+fn emit_statsd(app: &Cli, addr: &str, database: &Database) {
+    let Some((snapshot, previous)) = database
+        .snapshots()
+        .split_last()
+        .map(|(snapshot, previous)| (snapshot, previous.last()))
+    else {
+        return;
+    };
+
+    if app.dry_run {
+        for datagram in statsd::format_datagrams(
+            &statsd::snapshot_metrics(snapshot, previous),
+            &app.statsd_prefix,
+        ) {
+            debug!(datagram, "Statsd datagram that would be sent to {addr}");
+        }
+        return;
+    }
+
+    if let Err(err) = StatsdEmitter::new(addr, &app.statsd_prefix)
+        .and_then(|emitter| emitter.emit(snapshot, previous))
+    {
+        warn!("Failed to send the statsd metrics to {addr}: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_least_one_backup_is_kept() {
+        let keep = |value: &str| {
+            Cli::try_parse_from([env!("CARGO_PKG_NAME"), "--backup-keep", value])
+                .map(|app| app.backup_keep)
+        };
+        assert!(keep("0").is_err());
+        assert_eq!(keep("1").unwrap(), 1);
+    }
+
+    #[test]
+    fn failed_verifications_restore_the_database_and_exit_with_their_code() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db").to_string_lossy().to_string();
+        Database::demo(&metrics::demo::DemoOptions {
+            end: chrono::Utc::now(),
+            span: chrono::Duration::minutes(9),
+            interval: chrono::Duration::minutes(1),
+            seed: metrics::demo::DEFAULT_DEMO_SEED,
+        })
+        .unwrap()
+        .write_to_file(&path)
+        .unwrap();
+        let before = std::fs::read(&path).unwrap();
+        let (mut database, file, locked) = Database::from_file_with_write(&path, None).unwrap();
+        drop(file);
+        let snapshot = database.snapshots().last().unwrap().clone();
+        database.push_snapshot(snapshot);
+
+        // NOTE: A handle the database can't be written with, as a failing disk would
+        let read_only = File::open(&locked).unwrap();
+        let err = database.write_verified(read_only, &locked).unwrap_err();
+
+        assert_eq!(write_exit_code(&err), Some(EXIT_WRITE_VERIFICATION_FAILED));
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert!(!dir.path().join("sysmet.db.restoring").exists());
+        assert!(!dir.path().join("sysmet.db.lock").exists());
+        assert_eq!(
+            write_exit_code(&Error::LockFileTimeout(locked.clone())),
+            None
+        );
+    }
+
+    #[test]
+    fn backdated_snapshots_need_to_be_allowed() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from([env!("CARGO_PKG_NAME")].iter().chain(args)).map(|app| app.at)
+        };
+
+        let err = parse(&["--at", "2024-05-03T12:00:00Z"]).unwrap_err();
+        assert!(err.to_string().contains("--allow-backdate"), "{err}");
+        assert_eq!(
+            parse(&["--at", "2024-05-03T14:00:00+02:00", "--allow-backdate"]).unwrap(),
+            Some(
+                chrono::DateTime::parse_from_rfc3339("2024-05-03T12:00:00Z")
+                    .unwrap()
+                    .with_timezone(&chrono::Utc)
+            )
+        );
+        assert!(parse(&["--at", "yesterday", "--allow-backdate"]).is_err());
+        assert!(parse(&["--at", "2999-01-01T00:00:00Z", "--allow-backdate"]).is_err());
+        assert!(parse(&[
+            "--at",
+            "2024-05-03T12:00:00Z",
+            "--allow-backdate",
+            "--times",
+            "2"
+        ])
+        .is_err());
+        assert_eq!(parse(&["--allow-backdate"]).unwrap(), None);
+    }
+
+    #[test]
+    fn slow_collections_name_their_slowest_collector() {
+        struct Sleeping(u64);
+
+        impl Collector for Sleeping {
+            fn name(&self) -> &str {
+                "disk wakeup"
+            }
+
+            fn collect(&self) -> std::result::Result<Vec<(String, f64)>, Error> {
+                std::thread::sleep(std::time::Duration::from_millis(self.0));
+                Ok(Vec::new())
+            }
+        }
+
+        let mut registry = CollectorRegistry::default();
+        registry.register(Sleeping(400));
+        let mut database = Database::default();
+        assert_eq!(slow_collection_warning(&database, 0), None);
+        database.push_snapshot(
+            SnapShot::new_with_collectors_at(Utc::now(), &[], &registry, false).unwrap(),
+        );
+        let snapshot = database.snapshots().last().unwrap();
+        let duration = snapshot.collection_duration_ms.unwrap();
+        let slowest = snapshot.collection_breakdown_ms["custom disk wakeup"];
+        assert!(
+            slowest >= 400 && duration >= slowest,
+            "{duration} {slowest}"
+        );
+
+        assert_eq!(
+            slow_collection_warning(&database, 200).as_deref(),
+            Some(
+                format!(
+                    "Collecting the snapshot took {duration}ms (more than 200ms), custom disk wakeup took {slowest}ms"
+                )
+                .as_str()
+            )
+        );
+        assert_eq!(slow_collection_warning(&database, duration), None);
+
+        // NOTE: Snapshots of older versions have no breakdown
+        let mut without_breakdown = snapshot.clone();
+        without_breakdown.collection_breakdown_ms.clear();
+        let mut database = Database::default();
+        database.push_snapshot(without_breakdown);
+        assert_eq!(
+            slow_collection_warning(&database, 200).as_deref(),
+            Some(format!("Collecting the snapshot took {duration}ms (more than 200ms)").as_str())
+        );
+    }
+
+    #[test]
+    fn custom_metrics_are_repeated_or_comma_separated() {
+        let app = Cli::try_parse_from([
+            env!("CARGO_PKG_NAME"),
+            "--custom-metric",
+            "gpu=/run/gpu,queue=/run/queue",
+            "--custom-metric=temp=/run/temp",
+        ])
+        .unwrap();
+
+        let names = app
+            .custom_metrics
+            .iter()
+            .map(|collector| collector.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["gpu", "queue", "temp"]);
+        assert_eq!(
+            app.effective_config().update.custom_metrics,
+            ["gpu=/run/gpu", "queue=/run/queue", "temp=/run/temp"]
+        );
+        assert!(
+            Cli::try_parse_from([env!("CARGO_PKG_NAME"), "--custom-metric", "/run/gpu"]).is_err()
+        );
+    }
+
+    /// Effective config of the command line once its legacy flags are replaced, and the legacy flags used.
+    fn parsed(args: &[&str]) -> (String, Vec<LegacyFlag>) {
+        let legacy = replace_legacy_flags(
+            std::iter::once(env!("CARGO_PKG_NAME"))
+                .chain(args.iter().copied())
+                .map(std::ffi::OsString::from),
+            LEGACY_FLAGS,
+            false,
+        )
+        .unwrap();
+        let app = Cli::try_parse_from(legacy.args).unwrap();
+        (app.effective_config().to_toml().unwrap(), legacy.used)
+    }
+
+    #[test]
+    fn legacy_flags_give_the_same_config() {
+        let (legacy, used) = parsed(&[
+            "-db",
+            "sysmet.db",
+            "-gc=30",
+            "-in",
+            "lo",
+            "-in=docker0",
+            "-gin",
+            "veth*",
+        ]);
+        let (current, none) = parsed(&[
+            "--database",
+            "sysmet.db",
+            "--cleanup-older=30",
+            "--ignored-networks",
+            "lo",
+            "--ignored-networks=docker0",
+            "--glob-ignored-networks",
+            "veth*",
+        ]);
+
+        assert_eq!(legacy, current);
+        assert!(legacy.contains("cleanup_older = 30"), "{legacy}");
+        assert!(none.is_empty());
+        // NOTE: Warned about once each, even when given several times
+        assert_eq!(used, LEGACY_FLAGS);
+    }
+
+    /// Shard of `snapshots` written at `path`, returns its path.
+    #[cfg(feature = "export")]
+    fn write_shard(path: &Path, snapshots: &[SnapShot]) -> String {
+        let mut database = Database::default();
+        for snapshot in snapshots {
+            database.push_snapshot(snapshot.clone());
+        }
+        let path = path.to_string_lossy().to_string();
+        database.write_to_file(&path).unwrap();
+        path
+    }
+
+    #[cfg(feature = "export")]
+    fn exported(paths: &[String], options: &ExportOptions<'_>) -> String {
+        let mut out = Vec::new();
+        export(paths, None, options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Ten snapshots a minute apart, the last one at `end`.
+    #[cfg(feature = "export")]
+    fn demo_snapshots(end: DateTime<Utc>) -> Vec<SnapShot> {
+        Database::demo(&metrics::demo::DemoOptions {
+            end,
+            span: chrono::Duration::minutes(9),
+            interval: chrono::Duration::minutes(1),
+            seed: metrics::demo::DEFAULT_DEMO_SEED,
+        })
+        .unwrap()
+        .snapshots()
+        .to_vec()
+    }
+
+    #[cfg(any(feature = "export", feature = "diff", feature = "demo"))]
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn incremental_exports_add_up_to_the_full_export() {
+        let snapshots = demo_snapshots(time("2024-05-03T12:00:00Z"));
+        for csv in [false, true] {
+            let dir = tempfile::TempDir::new().unwrap();
+            let (first, second) = (dir.path().join("2024-04.db"), dir.path().join("2024-05.db"));
+            let state_file = dir.path().join("export.json");
+            let incremental = |append| ExportOptions {
+                csv,
+                append,
+                since_last: true,
+                state_file: Some(&state_file),
+                ..ExportOptions::default()
+            };
+
+            let mut concatenated =
+                exported(&[write_shard(&first, &snapshots[..4])], &incremental(false));
+            // NOTE: The first shard grew and the second one starts at the time of its last snapshot
+            let paths = [
+                write_shard(&first, &snapshots[..6]),
+                write_shard(&second, &snapshots[5..]),
+            ];
+            concatenated += &exported(&paths, &incremental(true));
+            assert_eq!(exported(&paths, &incremental(true)), "");
+
+            let full = exported(
+                &paths,
+                &ExportOptions {
+                    csv,
+                    ..ExportOptions::default()
+                },
+            );
+            assert_eq!(full.lines().count(), 11 + usize::from(csv));
+            assert_eq!(concatenated, full);
+            assert_eq!(
+                ExportState::load(&state_file)
+                    .unwrap()
+                    .map(|state| (state.time, state.exported_at_time)),
+                Some((snapshots[9].time, 1))
+            );
+        }
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn exports_are_limited_to_since_and_until() {
+        let snapshots = demo_snapshots(time("2024-05-03T12:00:00Z"));
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths = [write_shard(&dir.path().join("sysmet.db"), &snapshots)];
+
+        let lines = exported(
+            &paths,
+            &ExportOptions {
+                since: Some(time("2024-05-03T11:53:00Z")),
+                until: Some(time("2024-05-03T11:56:00Z")),
+                ..ExportOptions::default()
+            },
+        )
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<ExportedSnapshotV1>(line)
+                .unwrap()
+                .time
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                time("2024-05-03T11:53:00Z"),
+                time("2024-05-03T11:54:00Z"),
+                time("2024-05-03T11:55:00Z")
+            ]
+        );
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn invalid_state_files_are_refused() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state_file = dir.path().join("export.json");
+        assert!(ExportState::load(&state_file).unwrap().is_none());
+
+        fs::write(&state_file, "{").unwrap();
+        let err = ExportState::load(&state_file).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("remove it to export everything again"));
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn export_states_count_the_snapshots_sharing_a_time() {
+        let (first, second) = (time("2024-05-03T12:00:00Z"), time("2024-05-03T12:01:00Z"));
+        let state = ExportState::after(None, first);
+        assert_eq!(state.exported_at_time, 1);
+        let state = ExportState::after(Some(state), first);
+        assert_eq!((state.time, state.exported_at_time), (first, 2));
+        let state = ExportState::after(Some(state), second);
+        assert_eq!((state.time, state.exported_at_time), (second, 1));
+    }
+
+    #[cfg(any(feature = "export", feature = "diff", feature = "demo"))]
+    #[test]
+    fn export_times_are_dates_or_durations_ago() {
+        assert_eq!(
+            parse_export_time("2024-05-03T12:00:00+02:00").unwrap(),
+            time("2024-05-03T10:00:00Z")
+        );
+        let ago = Utc::now() - parse_export_time("7d").unwrap();
+        assert!((ago - chrono::Duration::days(7)).num_seconds().abs() < 60);
+        assert!(parse_export_time("yesterday").is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use std::{env, process::Command};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string()).filter(|output| !output.is_empty())
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SYSMET_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // NOTE: Only rebuilt when the checked out commit changes, the timestamp is the one of that build
+    if let Some(git_dir) = command_output("git", &["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/refs/heads");
+    }
+
+    // NOTE: Source tarballs have no git repository, packagers may give the commit instead
+    let commit = env::var("SYSMET_GIT_COMMIT")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    // SOURCE: https://reproducible-builds.org/docs/source-date-epoch/
+    let built_at = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .and_then(|epoch| DateTime::<Utc>::from_timestamp(epoch, 0))
+        .unwrap_or_else(Utc::now);
+
+    println!("cargo:rustc-env=SYSMET_GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=SYSMET_RUSTC_VERSION={rustc_version}");
+    println!(
+        "cargo:rustc-env=SYSMET_BUILD_TIMESTAMP={}",
+        built_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+}
@@ -0,0 +1,750 @@
+use std::{
+    collections::BTreeMap,
+    env::{args_os, set_var, var_os},
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+use crate::Error;
+
+pub const CONFIG_ENV: &str = "SYSMET_CONFIG";
+const CONFIG_FLAG: &str = "--config";
+const MASKED_SECRET: &str = "********";
+
+/// Content of a config file shared by every binary, each one only applying the sections it needs.
+///
+/// Values are applied as environment variables that are not already set, so the precedence is
+/// CLI flag > environment variable > config file > default.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub database: DatabaseSection,
+    #[serde(default)]
+    pub update: UpdateSection,
+    #[serde(default)]
+    pub http: HttpSection,
+    #[serde(default)]
+    pub notify: NotifySection,
+    #[serde(default, skip_serializing_if = "RoutesSection::is_empty")]
+    pub routes: RoutesSection,
+    /// Sections not known by any binary.
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DatabaseSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+    /// Keys not known by this section.
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UpdateSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cleanup_older: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_annotations: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vacuum: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spill_on_lock_timeout: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignored_networks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub glob_ignored_networks: Vec<String>,
+    /// `name=/path` of the files read on each snapshot.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_metrics: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_keep: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statsd_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statsd_prefix: Option<String>,
+    /// Milliseconds of collection after which a warning is logged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_collection_warn: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_write: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_cgroup_limits: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_dir: Option<PathBuf>,
+    /// `monthly` or `weekly`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shard_period: Option<String>,
+    /// Keys not known by this section.
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HttpSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
+    /// Octal file mode of the unix socket, e.g. "0660".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix_socket_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_memory_points: Option<usize>,
+    /// `bits` or `bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_units: Option<String>,
+    /// `si` or `iec`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_units: Option<String>,
+    /// `iso`, `en`, `de` or `fr`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Times the max of a chart must exceed the bulk of its values to be clipped, 0 to never clip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outlier_factor: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view_cache: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_readiness: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookie_secret: Option<String>,
+    /// Directory of one database per host, combined on `/aggregate`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fleet_dir: Option<PathBuf>,
+    /// File the dashboard and API requests are appended to, one JSON line each.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_log: Option<PathBuf>,
+    /// Also log the address and the user agent of the clients.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_log_clients: Option<bool>,
+    /// State file of sysmet-notify, shown on the dashboard.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_state: Option<PathBuf>,
+    /// Bearer token of the admin routes serving the raw database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    /// Let the admin routes replace the database with an upload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_upload: Option<bool>,
+    /// Humantime duration, e.g. "250ms".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_op_threshold: Option<String>,
+    /// Comma separated CIDR ranges, e.g. "127.0.0.1,10.0.0.0/8".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted_proxies: Option<String>,
+    /// Keys not known by this section.
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct NotifySection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ram_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_load_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_activity_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conntrack_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_utilization_threshold: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub require_mounts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub require_nics: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_relay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sent_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub averaging: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dashboard_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_top: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_dmesg_lines: Option<usize>,
+    /// Keys not known by this section.
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+/// Named routes of `sysmet-notify`, e.g. `[routes.storage]`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RoutesSection(pub BTreeMap<String, RouteSection>);
+
+impl RoutesSection {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RouteSection {
+    /// `cpu`, `ram`, `swap`, `memory`, `disk`, `load`, `swap-activity`, `mount` or `nic`, any of them when
+    /// unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<String>,
+    /// `warning` or `critical`, any of them when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<String>,
+    /// Keys not known by this section.
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+/// A config section whose values are read by clap through environment variables.
+pub trait EnvSection {
+    /// Environment variables names with their value from the config file, if set.
+    fn env_vars(&self) -> Vec<(&'static str, Option<String>)>;
+
+    /// Set the environment variables that are not already set, returning the names of the ones set.
+    fn apply(&self) -> Vec<&'static str> {
+        let mut applied = Vec::new();
+        for (name, value) in self.env_vars() {
+            let Some(value) = value else { continue };
+            if var_os(name).is_some() {
+                trace!("{name} already set, ignoring the config file value");
+                continue;
+            }
+            debug!("Setting {name} from the config file");
+            set_var(name, value);
+            applied.push(name);
+        }
+
+        applied
+    }
+}
+
+impl EnvSection for DatabaseSection {
+    fn env_vars(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("SYSMET_DATABASE", self.path.clone()),
+            ("SYSMET_DB_KEY", self.key.clone()),
+            ("SYSMET_DB_KEY_FILE", path_to_string(&self.key_file)),
+        ]
+    }
+}
+
+impl EnvSection for UpdateSection {
+    fn env_vars(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            (
+                "SYSMET_CLEANUP_OLDER",
+                self.cleanup_older.map(|days| days.to_string()),
+            ),
+            (
+                "SYSMET_KEEP_ANNOTATIONS",
+                self.keep_annotations.map(|keep| keep.to_string()),
+            ),
+            (
+                "SYSMET_VACUUM",
+                self.vacuum.map(|vacuum| vacuum.to_string()),
+            ),
+            (
+                "SYSMET_SPILL_ON_LOCK_TIMEOUT",
+                self.spill_on_lock_timeout.map(|spill| spill.to_string()),
+            ),
+            ("SYSMET_IGNORED_NETWORKS", join(&self.ignored_networks)),
+            (
+                "SYSMET_GLOB_IGNORED_NETWORKS",
+                join(&self.glob_ignored_networks),
+            ),
+            ("SYSMET_CUSTOM_METRICS", join(&self.custom_metrics)),
+            ("SYSMET_BACKUP_DIR", path_to_string(&self.backup_dir)),
+            (
+                "SYSMET_BACKUP_KEEP",
+                self.backup_keep.map(|keep| keep.to_string()),
+            ),
+            ("SYSMET_STATSD_ADDR", self.statsd_addr.clone()),
+            ("SYSMET_STATSD_PREFIX", self.statsd_prefix.clone()),
+            (
+                "SYSMET_SLOW_COLLECTION_WARN",
+                self.slow_collection_warn.map(|ms| ms.to_string()),
+            ),
+            (
+                "SYSMET_CHECKSUMS",
+                self.checksums.map(|checksums| checksums.to_string()),
+            ),
+            (
+                "SYSMET_VERIFY_WRITE",
+                self.verify_write
+                    .map(|verify_write| verify_write.to_string()),
+            ),
+            (
+                "SYSMET_NO_CGROUP_LIMITS",
+                self.no_cgroup_limits
+                    .map(|no_cgroup_limits| no_cgroup_limits.to_string()),
+            ),
+            ("SYSMET_DATABASE_DIR", path_to_string(&self.database_dir)),
+            ("SYSMET_SHARD_PERIOD", self.shard_period.clone()),
+        ]
+    }
+}
+
+impl EnvSection for HttpSection {
+    fn env_vars(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("SYSMET_HTTP_ADDRESS", self.address.clone()),
+            ("SYSMET_HTTP_UDS", path_to_string(&self.unix_socket)),
+            ("SYSMET_HTTP_UDS_MODE", self.unix_socket_mode.clone()),
+            (
+                "SYSMET_HTTP_MAX_MEMORY_POINTS",
+                self.max_memory_points.map(|points| points.to_string()),
+            ),
+            ("SYSMET_HTTP_NETWORK_UNITS", self.network_units.clone()),
+            ("SYSMET_HTTP_DISK_UNITS", self.disk_units.clone()),
+            ("SYSMET_HTTP_LOCALE", self.locale.clone()),
+            (
+                "SYSMET_HTTP_OUTLIER_FACTOR",
+                self.outlier_factor.map(|factor| factor.to_string()),
+            ),
+            (
+                "SYSMET_HTTP_VIEW_CACHE",
+                self.view_cache.map(|view_cache| view_cache.to_string()),
+            ),
+            (
+                "SYSMET_HTTP_STRICT_READINESS",
+                self.strict_readiness
+                    .map(|strict_readiness| strict_readiness.to_string()),
+            ),
+            ("SYSMET_HTTP_COOKIE_SECRET", self.cookie_secret.clone()),
+            ("SYSMET_HTTP_FLEET_DIR", path_to_string(&self.fleet_dir)),
+            ("SYSMET_HTTP_USAGE_LOG", path_to_string(&self.usage_log)),
+            (
+                "SYSMET_HTTP_USAGE_LOG_CLIENTS",
+                self.usage_log_clients.map(|clients| clients.to_string()),
+            ),
+            (
+                "SYSMET_HTTP_NOTIFY_STATE",
+                path_to_string(&self.notify_state),
+            ),
+            ("SYSMET_HTTP_ADMIN_TOKEN", self.admin_token.clone()),
+            (
+                "SYSMET_HTTP_ALLOW_UPLOAD",
+                self.allow_upload.map(|allow| allow.to_string()),
+            ),
+            (
+                "SYSMET_HTTP_SLOW_OP_THRESHOLD",
+                self.slow_op_threshold.clone(),
+            ),
+            ("SYSMET_HTTP_TRUSTED_PROXIES", self.trusted_proxies.clone()),
+        ]
+    }
+}
+
+impl EnvSection for NotifySection {
+    fn env_vars(&self) -> Vec<(&'static str, Option<String>)> {
+        let to_string = |value: Option<u32>| value.map(|value| value.to_string());
+        vec![
+            ("CPU_THRESHOLD", to_string(self.cpu_threshold)),
+            ("RAM_THRESHOLD", to_string(self.ram_threshold)),
+            ("SWAP_THRESHOLD", to_string(self.swap_threshold)),
+            ("MEMORY_THRESHOLD", to_string(self.memory_threshold)),
+            ("DISK_THRESHOLD", to_string(self.disk_threshold)),
+            ("AVG_LOAD_THRESHOLD", to_string(self.avg_load_threshold)),
+            (
+                "SWAP_ACTIVITY_THRESHOLD",
+                to_string(self.swap_activity_threshold),
+            ),
+            ("CONNTRACK_THRESHOLD", to_string(self.conntrack_threshold)),
+            (
+                "NET_UTILIZATION_THRESHOLD",
+                to_string(self.net_utilization_threshold),
+            ),
+            ("NOTIFY_REQUIRE_MOUNTS", join(&self.require_mounts)),
+            ("NOTIFY_REQUIRE_NICS", join(&self.require_nics)),
+            ("MAIL_FROM", self.from.clone()),
+            ("MAIL_CONTACTS", join(&self.contacts)),
+            ("MAIL_COOLDOWN", self.cooldown.clone()),
+            ("SMTP_USER", self.smtp_user.clone()),
+            ("SMTP_PASSWORD", self.smtp_password.clone()),
+            ("SMTP_RELAY", self.smtp_relay.clone()),
+            ("SMTP_PORT", self.smtp_port.map(|port| port.to_string())),
+            ("LAST_SENT_PATH", self.last_sent_path.clone()),
+            ("NOTIFY_DATABASE_DIR", path_to_string(&self.database_dir)),
+            ("NOTIFY_WINDOW", self.window.clone()),
+            ("NOTIFY_AVERAGING", self.averaging.clone()),
+            ("NOTIFY_STALE_AFTER", self.stale_after.clone()),
+            ("NOTIFY_STATE_PATH", path_to_string(&self.state_path)),
+            ("NOTIFY_DASHBOARD_URL", self.dashboard_url.clone()),
+            (
+                "NOTIFY_INCLUDE_TOP",
+                self.include_top.map(|count| count.to_string()),
+            ),
+            (
+                "NOTIFY_INCLUDE_DMESG_LINES",
+                self.include_dmesg_lines.map(|lines| lines.to_string()),
+            ),
+        ]
+    }
+}
+
+impl EnvSection for RoutesSection {
+    fn env_vars(&self) -> Vec<(&'static str, Option<String>)> {
+        // NOTE: Routes are separated by `;` as each one is a comma separated list
+        let routes = self
+            .0
+            .values()
+            .map(|route| {
+                route
+                    .metric
+                    .iter()
+                    .map(|metric| format!("metric={metric}"))
+                    .chain(
+                        route
+                            .severity
+                            .iter()
+                            .map(|severity| format!("severity={severity}")),
+                    )
+                    .chain(
+                        route
+                            .contacts
+                            .iter()
+                            .map(|contact| format!("contacts={contact}")),
+                    )
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>();
+        vec![(
+            "NOTIFY_ROUTES",
+            (!routes.is_empty()).then(|| routes.join(";")),
+        )]
+    }
+}
+
+fn path_to_string(path: &Option<PathBuf>) -> Option<String> {
+    path.as_ref().map(|path| path.to_string_lossy().to_string())
+}
+
+fn join(values: &[String]) -> Option<String> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(","))
+    }
+}
+
+impl Config {
+    #[tracing::instrument]
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(Error::ReadConfig)?;
+        let config = toml::from_str::<Self>(&content)?;
+        trace!(config =? config, "Loaded config file");
+
+        Ok(config)
+    }
+
+    /// Load the config file given with `--config` or `SYSMET_CONFIG`, before the CLI is parsed.
+    pub fn from_args() -> Result<Option<Self>, Error> {
+        Self::from_arg_list(args_os().collect())
+    }
+
+    /// Same as [`Config::from_args`] with the arguments `args` instead of the ones of the process.
+    pub fn from_arg_list(args: Vec<OsString>) -> Result<Option<Self>, Error> {
+        path_from_args(args)
+            .map(|path| Self::from_file(&path))
+            .transpose()
+    }
+
+    /// Keys of the file that are not known by any section, as `section.key`.
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let prefixed = |section: &str, extra: &BTreeMap<String, toml::Value>| {
+            extra
+                .keys()
+                .map(|key| format!("{section}.{key}"))
+                .collect::<Vec<_>>()
+        };
+
+        self.extra
+            .keys()
+            .cloned()
+            .chain(prefixed("database", &self.database.extra))
+            .chain(prefixed("update", &self.update.extra))
+            .chain(prefixed("http", &self.http.extra))
+            .chain(prefixed("notify", &self.notify.extra))
+            .chain(
+                self.routes
+                    .0
+                    .iter()
+                    .flat_map(|(name, route)| prefixed(&format!("routes.{name}"), &route.extra)),
+            )
+            .collect()
+    }
+
+    /// Copy of the config with the secrets replaced, meant to be printed.
+    pub fn masked(mut self) -> Self {
+        let mask = |secret: &mut Option<String>| {
+            if secret.is_some() {
+                *secret = Some(MASKED_SECRET.to_string());
+            }
+        };
+        mask(&mut self.database.key);
+        mask(&mut self.notify.smtp_password);
+        mask(&mut self.http.cookie_secret);
+        mask(&mut self.http.admin_token);
+
+        self
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        let mut table = toml::Table::try_from(self)?;
+        // NOTE: Sections not used by the binary are left empty
+        table.retain(
+            |_, section| !matches!(section, toml::Value::Table(section) if section.is_empty()),
+        );
+
+        Ok(toml::to_string_pretty(&table)?)
+    }
+}
+
+fn path_from_args(args: Vec<OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == CONFIG_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix(CONFIG_FLAG))
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    var_os(CONFIG_ENV).map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::var;
+
+    use super::*;
+
+    fn parse(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn variables_already_set_beat_the_file() {
+        // NOTE: Only variables no other test applies, the tests share the environment
+        set_var("SYSMET_DATABASE", "/from/env.db");
+        let database = parse(
+            r#"
+            [database]
+            path = "/from/file.db"
+            key = "file-key"
+            "#,
+        )
+        .database;
+
+        let applied = database.apply();
+
+        assert_eq!(applied, ["SYSMET_DB_KEY"]);
+        assert_eq!(var("SYSMET_DATABASE").unwrap(), "/from/env.db");
+        assert_eq!(var("SYSMET_DB_KEY").unwrap(), "file-key");
+        // NOTE: Applying again keeps what the first file set
+        let other = DatabaseSection {
+            key: Some("other-key".to_string()),
+            ..DatabaseSection::default()
+        };
+        assert!(other.apply().is_empty());
+        assert_eq!(var("SYSMET_DB_KEY").unwrap(), "file-key");
+    }
+
+    #[test]
+    fn partial_files_leave_the_rest_unset() {
+        let config = parse(
+            r#"
+            [http]
+            locale = "de"
+            "#,
+        );
+
+        assert_eq!(config.http.locale.as_deref(), Some("de"));
+        let set = |vars: Vec<(&'static str, Option<String>)>| {
+            vars.into_iter()
+                .filter_map(|(name, value)| value.map(|value| (name, value)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            set(config.http.env_vars()),
+            [("SYSMET_HTTP_LOCALE", "de".to_string())]
+        );
+        assert!(set(config.database.env_vars()).is_empty());
+        assert!(set(config.update.env_vars()).is_empty());
+        assert!(set(config.notify.env_vars()).is_empty());
+        assert!(set(config.routes.env_vars()).is_empty());
+        assert!(config.unknown_keys().is_empty());
+        assert_eq!(config.to_toml().unwrap(), "[http]\nlocale = \"de\"\n");
+
+        let empty = parse("");
+        assert!(empty.http.locale.is_none());
+        assert_eq!(empty.to_toml().unwrap(), "");
+    }
+
+    #[test]
+    fn unknown_keys_are_listed_by_section() {
+        let config = parse(
+            r#"
+            colour = "blue"
+
+            [http]
+            local = "de"
+
+            [notify]
+            cpu_threshold = 90
+
+            [routes.storage]
+            metric = "disk"
+            severty = "critical"
+            "#,
+        );
+
+        assert_eq!(config.notify.cpu_threshold, Some(90));
+        assert!(config.extra.contains_key("colour"));
+        assert!(config.http.extra.contains_key("local"));
+        assert_eq!(
+            config.unknown_keys(),
+            ["colour", "http.local", "routes.storage.severty"]
+        );
+    }
+
+    #[test]
+    fn secrets_are_masked() {
+        let config = parse(
+            r#"
+            [database]
+            path = "/var/lib/sysmet/db"
+            key = "database-key"
+
+            [http]
+            cookie_secret = "cookie"
+
+            [notify]
+            smtp_user = "sysmet"
+            smtp_password = "password"
+            "#,
+        );
+
+        let masked = config.masked();
+
+        assert_eq!(masked.database.key.as_deref(), Some(MASKED_SECRET));
+        assert_eq!(masked.http.cookie_secret.as_deref(), Some(MASKED_SECRET));
+        assert_eq!(masked.notify.smtp_password.as_deref(), Some(MASKED_SECRET));
+        // NOTE: Unset secrets stay unset, the other values are kept
+        assert_eq!(masked.http.admin_token, None);
+        assert_eq!(masked.database.path.as_deref(), Some("/var/lib/sysmet/db"));
+        assert_eq!(masked.notify.smtp_user.as_deref(), Some("sysmet"));
+        let printed = masked.to_toml().unwrap();
+        assert!(!printed.contains("database-key"), "{printed}");
+        assert!(!printed.contains("password\""), "{printed}");
+    }
+
+    #[test]
+    fn printed_configs_are_read_back_the_same() {
+        let config = parse(
+            r#"
+            unknown = 1
+
+            [database]
+            path = "/var/lib/sysmet/db"
+
+            [update]
+            cleanup_older = 30
+            ignored_networks = ["lo", "docker0"]
+
+            [http]
+            address = "0.0.0.0:8080"
+            view_cache = true
+
+            [notify]
+            contacts = ["ops@example.org"]
+            cooldown = "1h"
+
+            [routes.storage]
+            metric = "disk"
+            contacts = ["storage@example.org"]
+            "#,
+        );
+
+        let printed = config.to_toml().unwrap();
+        let read_back = parse(&printed);
+
+        assert_eq!(read_back.to_toml().unwrap(), printed);
+        assert_eq!(read_back.update.ignored_networks, ["lo", "docker0"]);
+        assert_eq!(
+            read_back.routes.0["storage"].contacts,
+            ["storage@example.org"]
+        );
+        // NOTE: Unknown keys are warned about, not printed
+        assert!(!printed.contains("unknown"), "{printed}");
+        assert!(read_back.unknown_keys().is_empty());
+    }
+
+    #[test]
+    fn config_paths_are_taken_from_the_flag() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            path_from_args(args(&["sysmet-http", "--config", "/etc/sysmet.toml"])),
+            Some(PathBuf::from("/etc/sysmet.toml"))
+        );
+        assert_eq!(
+            path_from_args(args(&["sysmet-http", "-v", "--config=/etc/sysmet.toml"])),
+            Some(PathBuf::from("/etc/sysmet.toml"))
+        );
+        assert!(matches!(
+            Config::from_file(Path::new("/nonexistent/sysmet.toml")),
+            Err(Error::ReadConfig(_))
+        ));
+    }
+}
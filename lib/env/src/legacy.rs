@@ -0,0 +1,147 @@
+use std::ffi::OsString;
+
+use crate::Error;
+
+/// Set to `1` or `true` to refuse the legacy flags instead of warning about them.
+pub const STRICT_FLAGS_ENV: &str = "SYSMET_STRICT_FLAGS";
+
+/// Flag of the original sysmet as (legacy spelling, current spelling), e.g. `("-db", "--database")`.
+pub type LegacyFlag = (&'static str, &'static str);
+
+#[derive(Debug)]
+pub struct LegacyArgs {
+    /// Arguments with every legacy flag replaced by its current spelling.
+    pub args: Vec<OsString>,
+    /// Legacy flags found in the arguments, once each in order of appearance.
+    pub used: Vec<LegacyFlag>,
+}
+
+pub fn strict_flags() -> bool {
+    std::env::var(STRICT_FLAGS_ENV).is_ok_and(|value| value == "1" || value == "true")
+}
+
+/// Replace the legacy flags, given alone (`-db path`) or with their value (`-db=path`), by their current spelling.
+///
+/// Arguments after `--` are left untouched. With `strict` the first legacy flag is an error.
+#[tracing::instrument(skip(args))]
+pub fn replace_legacy_flags(
+    args: impl IntoIterator<Item = OsString>,
+    flags: &[LegacyFlag],
+    strict: bool,
+) -> Result<LegacyArgs, Error> {
+    let mut result = LegacyArgs {
+        args: Vec::new(),
+        used: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            result.args.push(arg);
+            result.args.extend(args.by_ref());
+            break;
+        }
+
+        let replaced = arg.to_str().and_then(|arg| {
+            flags.iter().find_map(|flag @ (legacy, current)| {
+                if arg == *legacy {
+                    Some((*flag, (*current).to_string()))
+                } else {
+                    arg.strip_prefix(legacy)
+                        .and_then(|rest| rest.strip_prefix('='))
+                        .map(|value| (*flag, format!("{current}={value}")))
+                }
+            })
+        });
+        match replaced {
+            Some(((legacy, current), _)) if strict => {
+                return Err(Error::LegacyFlag {
+                    legacy: legacy.to_string(),
+                    current: current.to_string(),
+                })
+            }
+            Some((flag, replacement)) => {
+                if !result.used.contains(&flag) {
+                    result.used.push(flag);
+                }
+                result.args.push(replacement.into());
+            }
+            None => result.args.push(arg),
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: &[LegacyFlag] = &[("-db", "--database"), ("-gc", "--cleanup-older")];
+
+    fn replaced(args: &[&str], strict: bool) -> Result<LegacyArgs, Error> {
+        replace_legacy_flags(args.iter().map(OsString::from), FLAGS, strict)
+    }
+
+    #[test]
+    fn legacy_flags_are_replaced_alone_or_with_their_value() {
+        let legacy = replaced(&["sysmet", "-db", "sysmet.db", "-gc=30", "-v"], false).unwrap();
+
+        assert_eq!(
+            legacy.args,
+            [
+                "sysmet",
+                "--database",
+                "sysmet.db",
+                "--cleanup-older=30",
+                "-v"
+            ]
+        );
+        assert_eq!(legacy.used, FLAGS);
+    }
+
+    #[test]
+    fn flags_used_several_times_are_listed_once() {
+        let legacy = replaced(&["sysmet", "-db", "a.db", "-db=b.db", "-gc", "7"], false).unwrap();
+
+        assert_eq!(legacy.used, FLAGS);
+    }
+
+    #[test]
+    fn other_arguments_are_left_untouched() {
+        let args = ["sysmet", "--database", "-dbx", "-db-path", "--", "-db"];
+
+        let legacy = replaced(&args, false).unwrap();
+
+        assert_eq!(legacy.args, args);
+        assert!(legacy.used.is_empty());
+        // NOTE: Even in strict mode
+        assert!(replaced(&args, true).is_ok());
+    }
+
+    #[test]
+    fn strict_mode_refuses_the_legacy_flags() {
+        let err = replaced(&["sysmet", "--database", "a.db", "-gc=30"], true).unwrap_err();
+
+        assert!(matches!(
+            &err,
+            Error::LegacyFlag { legacy, current } if legacy == "-gc" && current == "--cleanup-older"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "legacy flag `-gc` is refused as SYSMET_STRICT_FLAGS is set, use `--cleanup-older`"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_arguments_are_kept() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let invalid = OsString::from_vec(vec![b'-', b'd', b'b', 0xff]);
+        let legacy =
+            replace_legacy_flags([OsString::from("sysmet"), invalid.clone()], FLAGS, false)
+                .unwrap();
+
+        assert_eq!(legacy.args, [OsString::from("sysmet"), invalid]);
+    }
+}
@@ -3,6 +3,10 @@ use std::{env::VarError, ffi::OsStr, path::Path};
 use dotenvy::{dotenv, from_path};
 use thiserror::Error;
 
+pub mod config;
+pub mod legacy;
+pub mod version;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("environment variable `{0}` is empty")]
@@ -11,6 +15,14 @@ pub enum Error {
     VarNotSet(#[from] VarError),
     #[error("failed to load .env file {0}")]
     DotEnv(#[from] dotenvy::Error),
+    #[error("failed to read config file {0}")]
+    ReadConfig(std::io::Error),
+    #[error("failed to parse config file {0}")]
+    ParseConfig(#[from] toml::de::Error),
+    #[error("failed to serialize config {0}")]
+    SerializeConfig(#[from] toml::ser::Error),
+    #[error("legacy flag `{legacy}` is refused as SYSMET_STRICT_FLAGS is set, use `{current}`")]
+    LegacyFlag { legacy: String, current: String },
 }
 
 pub fn setup_env() {
@@ -0,0 +1,109 @@
+use std::{
+    ffi::OsString,
+    fmt::{self, Display},
+};
+
+use serde::Serialize;
+
+/// Build of a binary, stamped on the databases it writes and printed by `--version --verbose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Short hash of the commit the binary was built from, `unknown` outside of a git repository.
+    pub commit: &'static str,
+    pub rustc_version: &'static str,
+    /// RFC 3339 time of the build, `SOURCE_DATE_EPOCH` when set.
+    pub build_timestamp: &'static str,
+}
+
+impl BuildInfo {
+    /// Build info of the binary with the name and version of its own package.
+    ///
+    /// ```ignore
+    /// BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+    /// ```
+    pub const fn new(name: &'static str, version: &'static str) -> Self {
+        Self {
+            name,
+            version,
+            commit: env!("SYSMET_GIT_COMMIT"),
+            rustc_version: env!("SYSMET_RUSTC_VERSION"),
+            build_timestamp: env!("SYSMET_BUILD_TIMESTAMP"),
+        }
+    }
+
+    /// Multi-line version printed by `--version --verbose`.
+    pub fn verbose(&self) -> String {
+        format!(
+            "{self}\ncommit: {}\nrustc: {}\nbuilt: {}",
+            self.commit, self.rustc_version, self.build_timestamp
+        )
+    }
+}
+
+impl Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", self.name, self.version, self.commit)
+    }
+}
+
+/// Whether the arguments ask for the version with `--verbose`, clap only knows about the short version.
+pub fn is_verbose_version(args: &[OsString]) -> bool {
+    let has = |flags: &[&str]| args.iter().any(|arg| flags.iter().any(|flag| arg == flag));
+    has(&["--version", "-V"]) && has(&["--verbose", "-v"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: BuildInfo = BuildInfo {
+        name: "sysmet-update",
+        version: "0.3.1",
+        commit: "abc1234",
+        rustc_version: "rustc 1.80.0",
+        build_timestamp: "2024-05-03T12:00:00Z",
+    };
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn versions_name_the_build() {
+        assert_eq!(INFO.to_string(), "sysmet-update 0.3.1 (abc1234)");
+        assert_eq!(
+            INFO.verbose(),
+            "sysmet-update 0.3.1 (abc1234)\ncommit: abc1234\nrustc: rustc 1.80.0\nbuilt: 2024-05-03T12:00:00Z"
+        );
+    }
+
+    #[test]
+    fn builds_are_stamped_at_compile_time() {
+        let info = BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+        assert_eq!(info.to_string(), format!("env 0.1.0 ({})", info.commit));
+        for value in [info.commit, info.rustc_version, info.build_timestamp] {
+            assert!(!value.is_empty());
+        }
+    }
+
+    #[test]
+    fn verbose_versions_need_both_flags() {
+        for verbose in [
+            &["sysmet", "--version", "--verbose"][..],
+            &["sysmet", "-v", "-V"],
+            &["sysmet", "--database", "sysmet.db", "-V", "--verbose"],
+        ] {
+            assert!(is_verbose_version(&args(verbose)), "{verbose:?}");
+        }
+        for plain in [
+            &["sysmet", "--version"][..],
+            &["sysmet", "-v"],
+            &["sysmet", "--versions", "--verbose"],
+        ] {
+            assert!(!is_verbose_version(&args(plain)), "{plain:?}");
+        }
+    }
+}
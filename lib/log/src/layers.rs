@@ -1,9 +1,15 @@
-use std::env;
+use std::{env, fmt::Display, str::FromStr, thread, time::Duration};
 
-use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::non_blocking::{ErrorCounter, NonBlockingBuilder, WorkerGuard};
 use tracing_subscriber::{fmt, EnvFilter, Layer};
+#[cfg(feature = "hierarchical")]
 use tracing_tree::HierarchicalLayer;
 
+use crate::{
+    rolling::{RollingFile, Rotation},
+    throttle::RateLimit,
+};
+
 pub fn with_pretty<S>() -> Box<dyn Layer<S> + Send + Sync + 'static>
 where
     S: tracing::Subscriber,
@@ -25,6 +31,7 @@ where
     EnvFilter::from_env("LOG_LEVEL").boxed()
 }
 
+#[cfg(feature = "hierarchical")]
 pub fn with_hierarchical<S>() -> Box<dyn Layer<S> + Send + Sync + 'static>
 where
     S: tracing::Subscriber,
@@ -38,6 +45,7 @@ where
         .boxed()
 }
 
+#[cfg(feature = "honeycomb")]
 pub fn with_honeycomb<S>(
     service_name: &'static str,
     dataset: &str,
@@ -65,6 +73,72 @@ where
     }
 }
 
+/// Lines buffered for the log files writer before dropping new ones, same as tracing-appender's default.
+const DEFAULT_BUFFER_LINES: usize = 128_000;
+const MONITOR_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Value of the env variable, the default when unset or invalid.
+fn env_or<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => value.parse().unwrap_or_else(|err| {
+            // NOTE: The logger is not set up yet
+            eprintln!("Ignoring invalid {name}={value}: {err}");
+            default
+        }),
+        _ => default,
+    }
+}
+
+/// Reporter of the lines dropped because the buffer was full and of the suppressed events.
+struct Monitor {
+    error_counter: ErrorCounter,
+    rate_limit: RateLimit,
+    reported_dropped: usize,
+}
+
+impl Monitor {
+    fn new(error_counter: ErrorCounter, rate_limit: RateLimit) -> Self {
+        Self {
+            error_counter,
+            rate_limit,
+            reported_dropped: 0,
+        }
+    }
+
+    /// Log what was dropped or suppressed since the previous report.
+    fn report(&mut self) {
+        let dropped = self.error_counter.dropped_lines();
+        if dropped > self.reported_dropped {
+            tracing::warn!(
+                "Dropped {} log lines, the log files writer could not keep up",
+                dropped - self.reported_dropped
+            );
+            self.reported_dropped = dropped;
+        }
+        for (location, suppressed) in self.rate_limit.take_suppressed() {
+            tracing::warn!("Suppressed {suppressed} similar messages from {location}");
+        }
+    }
+
+    /// Report once a minute.
+    fn spawn(mut self) {
+        let spawned = thread::Builder::new()
+            .name("logfiles-monitor".to_string())
+            .spawn(move || loop {
+                thread::sleep(MONITOR_INTERVAL);
+                self.report();
+            });
+        if let Err(err) = spawned {
+            eprintln!("Failed to spawn the log files monitor: {err}");
+        }
+    }
+}
+
+/// Log files layer configured by `LOG_DIRECTORY`, `LOG_ROTATION` (hourly, daily or size:<BYTES>),
+/// `LOG_MAX_FILES`, `LOG_BUFFER_LINES` and `LOG_RATE_LIMIT` (events per second per callsite).
 pub fn with_logfiles<S>(
     logfile_prefix: &str,
 ) -> Option<(Box<dyn Layer<S> + Send + Sync + 'static>, WorkerGuard)>
@@ -72,23 +146,188 @@ where
     S: tracing::Subscriber,
     for<'a> S: tracing_subscriber::registry::LookupSpan<'a>,
 {
-    if let Ok(directory) = env::var("LOG_DIRECTORY") {
-        if !directory.is_empty() {
-            let file_appender =
-                tracing_appender::rolling::hourly(directory, format!("{logfile_prefix}.log"));
-            let (log_writer, guard) = tracing_appender::non_blocking(file_appender);
-            Some((
-                fmt::layer()
-                    .with_writer(log_writer)
-                    .with_ansi(false)
-                    .compact()
-                    .boxed(),
-                guard,
-            ))
-        } else {
-            None
+    let directory = env::var("LOG_DIRECTORY")
+        .ok()
+        .filter(|directory| !directory.is_empty())?;
+
+    let rotation = env_or("LOG_ROTATION", Rotation::Hourly);
+    let max_files = match env_or("LOG_MAX_FILES", 0usize) {
+        0 => None,
+        max_files => Some(max_files),
+    };
+    let file_appender = match RollingFile::new(
+        &directory,
+        format!("{logfile_prefix}.log"),
+        rotation,
+        max_files,
+    ) {
+        Ok(file_appender) => file_appender,
+        Err(err) => {
+            eprintln!("Failed to open the log files in {directory}: {err}");
+            return None;
         }
-    } else {
-        None
+    };
+
+    let (log_writer, guard) = NonBlockingBuilder::default()
+        .buffered_lines_limit(env_or("LOG_BUFFER_LINES", DEFAULT_BUFFER_LINES))
+        .lossy(true)
+        .finish(file_appender);
+    let rate_limit = RateLimit::new(env_or("LOG_RATE_LIMIT", 0u32));
+    Monitor::new(log_writer.error_counter(), rate_limit.clone()).spawn();
+
+    Some((
+        fmt::layer()
+            .with_writer(log_writer)
+            .with_ansi(false)
+            .compact()
+            .with_filter(rate_limit)
+            .boxed(),
+        guard,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use tempfile::TempDir;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::*;
+    use crate::testing::Captured;
+
+    /// Writer blocked until the test releases the mutex.
+    struct Blocked(Arc<Mutex<()>>);
+
+    impl Write for Blocked {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            drop(self.0.lock().unwrap());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Lines logged by a report of `monitor`.
+    fn report(monitor: &mut Monitor) -> Vec<String> {
+        let captured = Captured::default();
+        let subscriber =
+            Registry::default().with(fmt::layer().with_writer(captured.clone()).with_ansi(false));
+        tracing::subscriber::with_default(subscriber, || monitor.report());
+        captured.lines()
+    }
+
+    #[test]
+    fn log_files_are_written_rotated_and_pruned() {
+        let dir = TempDir::new().unwrap();
+        // NOTE: The only test setting these variables
+        env::set_var("LOG_DIRECTORY", dir.path());
+        env::set_var("LOG_ROTATION", "size:1K");
+        env::set_var("LOG_MAX_FILES", "2");
+
+        let (layer, guard) = with_logfiles::<Registry>("sysmet-test").unwrap();
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            for idx in 0..40 {
+                tracing::info!("line {idx:>2} {}", "x".repeat(40));
+                // NOTE: Files are named after the millisecond of their creation
+                thread::sleep(Duration::from_millis(2));
+            }
+        });
+        // NOTE: Flushes the lines still buffered
+        drop(guard);
+
+        let mut names = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with("sysmet-test.log.")));
+        let last = fs::read_to_string(dir.path().join(&names[1])).unwrap();
+        assert!(
+            last.trim_end()
+                .ends_with(&format!("line 39 {}", "x".repeat(40))),
+            "{last}"
+        );
+        assert!(last.len() <= 1024);
+    }
+
+    #[test]
+    fn lines_dropped_by_full_buffers_are_reported_once() {
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let (writer, guard) = NonBlockingBuilder::default()
+            .buffered_lines_limit(1)
+            .lossy(true)
+            .finish(Blocked(gate.clone()));
+        let mut monitor = Monitor::new(writer.error_counter(), RateLimit::new(0));
+        assert_eq!(report(&mut monitor), Vec::<String>::new());
+
+        let subscriber = Registry::default().with(fmt::layer().with_writer(writer));
+        tracing::subscriber::with_default(subscriber, || {
+            for idx in 0..20 {
+                tracing::info!("line {idx}");
+            }
+        });
+
+        // NOTE: The writer holds a line and the buffer another one at most
+        let dropped = monitor.error_counter.dropped_lines();
+        assert!(dropped >= 18, "{dropped} lines dropped");
+        let lines = report(&mut monitor);
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert!(
+            lines[0].ends_with(&format!(
+                "Dropped {dropped} log lines, the log files writer could not keep up"
+            )),
+            "{lines:?}"
+        );
+        assert_eq!(report(&mut monitor), Vec::<String>::new());
+
+        drop(blocked);
+        drop(guard);
+    }
+
+    #[test]
+    fn suppressed_events_are_reported_once() {
+        let rate_limit = RateLimit::new(1);
+        let (writer, _guard) = NonBlockingBuilder::default().finish(io::sink());
+        let mut monitor = Monitor::new(writer.error_counter(), rate_limit.clone());
+        let subscriber =
+            Registry::default().with(fmt::layer().with_writer(writer).with_filter(rate_limit));
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..4 {
+                tracing::info!("repeated");
+            }
+        });
+
+        let lines = report(&mut monitor);
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert!(
+            lines[0].contains("Suppressed 3 similar messages from log::layers::tests"),
+            "{lines:?}"
+        );
+        assert_eq!(report(&mut monitor), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_env_values_fall_back_to_the_default() {
+        assert_eq!(env_or("SYSMET_TEST_UNSET", 3usize), 3);
+        env::set_var("SYSMET_TEST_INVALID", "many");
+        assert_eq!(env_or("SYSMET_TEST_INVALID", 3usize), 3);
+        env::set_var("SYSMET_TEST_VALID", "7");
+        assert_eq!(env_or("SYSMET_TEST_VALID", 3usize), 7);
+        env::set_var("SYSMET_TEST_ROTATION", "weekly");
+        assert_eq!(
+            env_or("SYSMET_TEST_ROTATION", Rotation::Daily),
+            Rotation::Daily
+        );
     }
 }
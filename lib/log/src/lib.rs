@@ -1,7 +1,7 @@
 #![forbid(unsafe_code)]
 use either::{for_both, Either};
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 /// Re-export tracing for convenience.
 pub use tracing;
@@ -10,6 +10,10 @@ pub use tracing;
 pub use tracing::{debug, error, info, trace, warn};
 
 pub mod layers;
+pub mod rolling;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod throttle;
 
 pub fn setup_simple_logger() {
     // This will print tracing events to standard output for humans to read
@@ -19,11 +23,32 @@ pub fn setup_simple_logger() {
         .init();
 }
 
+/// Indented output of the nested spans.
+#[cfg(feature = "hierarchical")]
+fn with_console<S>() -> Box<dyn Layer<S> + Send + Sync + 'static>
+where
+    S: tracing::Subscriber,
+    for<'a> S: tracing_subscriber::registry::LookupSpan<'a>,
+{
+    layers::with_hierarchical()
+}
+
+/// Pretty output, the indented one needing the `hierarchical` feature.
+#[cfg(not(feature = "hierarchical"))]
+fn with_console<S>() -> Box<dyn Layer<S> + Send + Sync + 'static>
+where
+    S: tracing::Subscriber,
+    for<'a> S: tracing_subscriber::registry::LookupSpan<'a>,
+{
+    layers::with_pretty()
+}
+
+/// Same as `setup_simple_logger` without the `hierarchical` feature.
 pub fn setup_hierarchical_logger() {
     // This will print tracing events to standard output for humans to read
     tracing_subscriber::Registry::default()
         .with(layers::with_env())
-        .with(layers::with_hierarchical())
+        .with(with_console())
         .init();
 }
 
@@ -31,7 +56,7 @@ pub fn setup_logger_with_logfiles(logfile_prefix: &str) -> Option<WorkerGuard> {
     // This will print tracing events to standard output for humans to read
     let logger = tracing_subscriber::Registry::default()
         .with(layers::with_env())
-        .with(layers::with_hierarchical());
+        .with(with_console());
     // When this variable goes out of scope (at the end of the function where this function is called), it will flush the log file writer
     let mut file_logger_guard = Option::None;
 
@@ -0,0 +1,322 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Hourly,
+    Daily,
+    /// New file once the current one would exceed this number of bytes.
+    Size(u64),
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// Parse `hourly`, `daily` or `size:<BYTES>` with an optional `K`, `M` or `G` suffix, e.g. `size:50M`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "hourly" => Ok(Rotation::Hourly),
+            "daily" => Ok(Rotation::Daily),
+            value => value
+                .strip_prefix("size:")
+                .and_then(parse_size)
+                .filter(|size| *size > 0)
+                .map(Rotation::Size)
+                .ok_or_else(|| {
+                    format!("{value} is not a rotation, expected hourly, daily or size:<BYTES>")
+                }),
+        }
+    }
+}
+
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.chars().last()? {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Log file writer rotating on time or size, keeping at most `max_files` files of its prefix.
+///
+/// Files are named `<prefix>.<date>` so their names sort by creation time.
+#[derive(Debug)]
+pub struct RollingFile {
+    directory: PathBuf,
+    prefix: String,
+    rotation: Rotation,
+    max_files: Option<usize>,
+    file: Option<File>,
+    file_name: String,
+    written: u64,
+}
+
+impl RollingFile {
+    pub fn new(
+        directory: impl AsRef<Path>,
+        prefix: impl Into<String>,
+        rotation: Rotation,
+        max_files: Option<usize>,
+    ) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        let mut result = Self {
+            directory,
+            prefix: prefix.into(),
+            rotation,
+            max_files,
+            file: None,
+            file_name: String::new(),
+            written: 0,
+        };
+
+        // NOTE: Short lived binaries (e.g. run by cron) keep appending to the last file instead of creating one per run
+        let reusable = match rotation {
+            Rotation::Size(max_size) => result.files()?.pop().filter(|name| {
+                fs::metadata(result.directory.join(name))
+                    .is_ok_and(|metadata| metadata.len() < max_size)
+            }),
+            Rotation::Hourly | Rotation::Daily => None,
+        };
+        let file_name = reusable.unwrap_or_else(|| result.file_name_at(Utc::now()));
+        result.open(file_name)?;
+
+        Ok(result)
+    }
+
+    fn file_name_at(&self, now: DateTime<Utc>) -> String {
+        let date = match self.rotation {
+            Rotation::Hourly => now.format("%Y-%m-%d-%H"),
+            Rotation::Daily => now.format("%Y-%m-%d"),
+            Rotation::Size(_) => now.format("%Y-%m-%d-%H-%M-%S%.3f"),
+        };
+        format!("{}.{date}", self.prefix)
+    }
+
+    /// Names of the log files of the prefix, oldest first.
+    fn files(&self) -> io::Result<Vec<String>> {
+        let prefix = format!("{}.", self.prefix);
+        let mut files = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// Remove the oldest files beyond `max_files`, the current one is never removed.
+    fn prune(&self) -> io::Result<()> {
+        let Some(max_files) = self.max_files else {
+            return Ok(());
+        };
+
+        let files = self.files()?;
+        let excess = files.len().saturating_sub(max_files.max(1));
+        for name in files
+            .iter()
+            .filter(|name| **name != self.file_name)
+            .take(excess)
+        {
+            fs::remove_file(self.directory.join(name))?;
+        }
+
+        Ok(())
+    }
+
+    fn open(&mut self, file_name: String) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(&file_name))?;
+        self.written = file.metadata()?.len();
+        self.file = Some(file);
+        self.file_name = file_name;
+
+        self.prune()
+    }
+
+    fn rotate_if_needed(&mut self, next_write: usize) -> io::Result<()> {
+        let file_name = match self.rotation {
+            Rotation::Hourly | Rotation::Daily => self.file_name_at(Utc::now()),
+            Rotation::Size(max_size) => {
+                // NOTE: A line longer than the max size still goes in its own file
+                if self.written > 0 && self.written + next_write as u64 > max_size {
+                    self.file_name_at(Utc::now())
+                } else {
+                    return Ok(());
+                }
+            }
+        };
+
+        if file_name != self.file_name {
+            if let Some(mut file) = self.file.take() {
+                file.flush()?;
+            }
+            self.open(file_name)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rotate_if_needed(buf.len())?;
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No log file opened"))?;
+        let written = file.write(buf)?;
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const PREFIX: &str = "sysmet-http.log";
+
+    fn names(dir: &TempDir) -> Vec<String> {
+        let mut names = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Line of 100 bytes.
+    fn line(idx: usize) -> String {
+        format!("{idx:>4} {}\n", "x".repeat(94))
+    }
+
+    #[test]
+    fn rotations_are_parsed() {
+        assert_eq!("hourly".parse(), Ok(Rotation::Hourly));
+        assert_eq!(" Daily ".parse(), Ok(Rotation::Daily));
+        assert_eq!("size:4096".parse(), Ok(Rotation::Size(4096)));
+        assert_eq!("size:50M".parse(), Ok(Rotation::Size(50 << 20)));
+        assert_eq!("size:1g".parse(), Ok(Rotation::Size(1 << 30)));
+        for invalid in ["weekly", "size:", "size:0", "size:12T", "size:-1"] {
+            assert!(invalid.parse::<Rotation>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn files_past_the_max_size_are_rotated_and_pruned() {
+        let dir = TempDir::new().unwrap();
+        let mut file = RollingFile::new(dir.path(), PREFIX, Rotation::Size(1000), Some(3)).unwrap();
+        for idx in 0..50 {
+            file.write_all(line(idx).as_bytes()).unwrap();
+            // NOTE: Files are named after the millisecond of their creation
+            thread::sleep(Duration::from_millis(2));
+        }
+        file.flush().unwrap();
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 3, "{names:?}");
+        let contents = names
+            .iter()
+            .map(|name| fs::read_to_string(dir.path().join(name)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(contents.iter().all(|content| content.len() <= 1000));
+        // NOTE: The oldest files were removed, the kept ones follow each other up to the last line
+        let kept = contents.concat();
+        assert!(kept.ends_with(&line(49)));
+        assert!(!kept.contains(&line(0)));
+        assert_eq!(kept.lines().count(), 10 + 10 + 10);
+    }
+
+    #[test]
+    fn lines_longer_than_the_max_size_get_their_own_file() {
+        let dir = TempDir::new().unwrap();
+        let mut file = RollingFile::new(dir.path(), PREFIX, Rotation::Size(50), None).unwrap();
+        for idx in 0..3 {
+            file.write_all(line(idx).as_bytes()).unwrap();
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        assert_eq!(names(&dir).len(), 3);
+    }
+
+    #[test]
+    fn size_rotations_append_to_the_last_file_below_the_max_size() {
+        let dir = TempDir::new().unwrap();
+        for idx in 0..3 {
+            let mut file =
+                RollingFile::new(dir.path(), PREFIX, Rotation::Size(1000), None).unwrap();
+            file.write_all(line(idx).as_bytes()).unwrap();
+        }
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 1, "{names:?}");
+        assert_eq!(
+            fs::read_to_string(dir.path().join(&names[0])).unwrap(),
+            (0..3).map(line).collect::<String>()
+        );
+    }
+
+    #[test]
+    fn older_files_of_the_prefix_are_pruned_when_opened() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "sysmet-http.log.2023-01-01-00",
+            "sysmet-http.log.2023-01-01-01",
+            "sysmet-http.log.2023-01-01-02",
+            "sysmet-notify.log.2023-01-01-00",
+        ] {
+            fs::write(dir.path().join(name), "old\n").unwrap();
+        }
+
+        let mut file = RollingFile::new(dir.path(), PREFIX, Rotation::Hourly, Some(2)).unwrap();
+        file.write_all(b"new\n").unwrap();
+
+        let names = names(&dir);
+        assert_eq!(names.len(), 3, "{names:?}");
+        assert_eq!(names[0], "sysmet-http.log.2023-01-01-02");
+        assert!(names[1].starts_with("sysmet-http.log.20") && names[1] > names[0]);
+        // NOTE: Files of other prefixes are left alone
+        assert_eq!(names[2], "sysmet-notify.log.2023-01-01-00");
+    }
+
+    #[test]
+    fn files_are_kept_without_a_max() {
+        let dir = TempDir::new().unwrap();
+        for hour in 0..5 {
+            fs::write(
+                dir.path().join(format!("{PREFIX}.2023-01-01-0{hour}")),
+                "old\n",
+            )
+            .unwrap();
+        }
+
+        RollingFile::new(dir.path(), PREFIX, Rotation::Daily, None).unwrap();
+
+        assert_eq!(names(&dir).len(), 6);
+    }
+}
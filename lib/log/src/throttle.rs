@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use tracing::{callsite::Identifier, Event, Metadata};
+use tracing_subscriber::layer::{Context, Filter};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct CallsiteWindow {
+    started_at: Instant,
+    count: u32,
+    /// Events dropped since the last report.
+    suppressed: u64,
+    location: String,
+}
+
+/// Per-layer filter letting at most `max_per_second` events of each callsite through.
+///
+/// The number of suppressed events is kept until taken with `take_suppressed`, it cannot be
+/// logged from the filter itself as it runs while an event is being dispatched.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// `0` lets every event through.
+    max_per_second: u32,
+    callsites: Arc<Mutex<HashMap<Identifier, CallsiteWindow>>>,
+}
+
+impl RateLimit {
+    pub fn new(max_per_second: u32) -> Self {
+        Self {
+            max_per_second,
+            callsites: Arc::default(),
+        }
+    }
+
+    /// Number of events suppressed per callsite location since the last call.
+    pub fn take_suppressed(&self) -> Vec<(String, u64)> {
+        let mut callsites = self
+            .callsites
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        callsites
+            .values_mut()
+            .filter(|window| window.suppressed > 0)
+            .map(|window| {
+                (
+                    window.location.clone(),
+                    std::mem::take(&mut window.suppressed),
+                )
+            })
+            .collect()
+    }
+}
+
+fn location(metadata: &Metadata<'_>) -> String {
+    match (metadata.file(), metadata.line()) {
+        (Some(file), Some(line)) => format!("{} ({file}:{line})", metadata.target()),
+        _ => metadata.target().to_string(),
+    }
+}
+
+impl<S> Filter<S> for RateLimit {
+    fn enabled(&self, _metadata: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        true
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, _cx: &Context<'_, S>) -> bool {
+        if self.max_per_second == 0 {
+            return true;
+        }
+
+        let metadata = event.metadata();
+        let now = Instant::now();
+        let mut callsites = self
+            .callsites
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let window = callsites
+            .entry(metadata.callsite())
+            .or_insert_with(|| CallsiteWindow {
+                started_at: now,
+                count: 0,
+                suppressed: 0,
+                location: location(metadata),
+            });
+        if now.duration_since(window.started_at) >= WINDOW {
+            window.started_at = now;
+            window.count = 0;
+        }
+
+        window.count += 1;
+        if window.count > self.max_per_second {
+            window.suppressed += 1;
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::{fmt, layer::SubscriberExt, Layer, Registry};
+
+    use super::*;
+    use crate::testing::Captured;
+
+    /// Lines written through a layer limited to `max_per_second` while running `log`.
+    fn limited(max_per_second: u32, log: impl FnOnce()) -> (Vec<String>, RateLimit) {
+        let captured = Captured::default();
+        let rate_limit = RateLimit::new(max_per_second);
+        let subscriber = Registry::default().with(
+            fmt::layer()
+                .with_writer(captured.clone())
+                .with_ansi(false)
+                .with_filter(rate_limit.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, log);
+        (captured.lines(), rate_limit)
+    }
+
+    #[test]
+    fn events_beyond_the_limit_are_suppressed() {
+        let (lines, rate_limit) = limited(2, || {
+            for idx in 0..5 {
+                tracing::info!("repeated {idx}");
+            }
+        });
+
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert!(lines[1].ends_with("repeated 1"), "{lines:?}");
+        let suppressed = rate_limit.take_suppressed();
+        assert_eq!(suppressed.len(), 1);
+        assert!(
+            suppressed[0].0.starts_with("log::throttle::tests"),
+            "{suppressed:?}"
+        );
+        assert_eq!(suppressed[0].1, 3);
+        assert_eq!(rate_limit.take_suppressed(), vec![]);
+    }
+
+    #[test]
+    fn callsites_are_limited_separately() {
+        let (lines, rate_limit) = limited(1, || {
+            for _ in 0..3 {
+                tracing::info!("first");
+                tracing::warn!("second");
+            }
+        });
+
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        let mut suppressed = rate_limit.take_suppressed();
+        suppressed.sort();
+        assert_eq!(
+            suppressed
+                .iter()
+                .map(|(_, count)| *count)
+                .collect::<Vec<_>>(),
+            vec![2, 2]
+        );
+    }
+
+    #[test]
+    fn windows_start_again_every_second() {
+        let (lines, rate_limit) = limited(1, || {
+            for idx in 0..2 {
+                if idx > 0 {
+                    std::thread::sleep(WINDOW);
+                }
+                tracing::info!("slow");
+            }
+        });
+
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert_eq!(rate_limit.take_suppressed(), vec![]);
+    }
+
+    #[test]
+    fn no_limit_lets_every_event_through() {
+        let (lines, rate_limit) = limited(0, || {
+            for _ in 0..100 {
+                tracing::info!("unlimited");
+            }
+        });
+
+        assert_eq!(lines.len(), 100);
+        assert_eq!(rate_limit.take_suppressed(), vec![]);
+    }
+}
@@ -0,0 +1,255 @@
+//! Series of several hosts combined into one, e.g. the network egress of a whole fleet.
+use std::{collections::BTreeMap, ops::Range};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, tracing};
+
+use crate::{prelude::*, series::bucket_average};
+
+/// Names of the metrics that can be aggregated.
+pub const AGGREGATE_METRICS: [&str; 10] = [
+    "cpu",
+    "ram",
+    "swap",
+    "load1",
+    "load5",
+    "load15",
+    "net-rx",
+    "net-tx",
+    "disk-read",
+    "disk-write",
+];
+
+/// Metric of a name of `AGGREGATE_METRICS`, `None` for any other name.
+pub fn aggregate_metric(name: &str) -> Option<MetricKind> {
+    match name {
+        "cpu" => Some(MetricKind::CpuPercent),
+        "ram" => Some(MetricKind::RamPercent),
+        "swap" => Some(MetricKind::SwapPercent),
+        "load1" => Some(MetricKind::Load1),
+        "load5" => Some(MetricKind::Load5),
+        "load15" => Some(MetricKind::Load15),
+        "net-rx" => Some(MetricKind::NetRxRate),
+        "net-tx" => Some(MetricKind::NetTxRate),
+        "disk-read" => Some(MetricKind::DiskReadRate),
+        "disk-write" => Some(MetricKind::DiskWriteRate),
+        _ => None,
+    }
+}
+
+/// How the values of the hosts are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    Sum,
+    Average,
+}
+
+impl Combine {
+    /// Sum for the rates in bytes as the fleet moves the bytes of all its hosts, average for the percents.
+    pub fn of(metric: &MetricKind) -> Self {
+        match metric {
+            MetricKind::NetRxRate
+            | MetricKind::NetTxRate
+            | MetricKind::DiskReadRate
+            | MetricKind::DiskWriteRate
+            | MetricKind::SwapInRate
+            | MetricKind::SwapOutRate => Combine::Sum,
+            _ => Combine::Average,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Combine::Sum => "sum",
+            Combine::Average => "average",
+        }
+    }
+}
+
+/// Series of several hosts combined bucket by bucket.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aggregate {
+    /// Combined value of each bucket, timestamped with its start.
+    pub points: Vec<Point>,
+    /// Bucketed series of each host with points in the range, in the given order.
+    pub hosts: Vec<(String, Vec<Point>)>,
+    /// Hosts without points in the range.
+    pub excluded: Vec<String>,
+}
+
+/// Series of each host averaged per `step` long bucket from the start of `range`, then combined with `mode`.
+///
+/// Bucketing aligns hosts collecting at different times. A bucket only combines the hosts with points in it,
+/// so a host missing a bucket neither counts as 0 in a sum nor lowers an average.
+#[tracing::instrument(level = "debug", skip(per_host))]
+pub fn combine(
+    per_host: Vec<(String, Vec<Point>)>,
+    range: Range<DateTime<Utc>>,
+    step: Duration,
+    mode: Combine,
+) -> Aggregate {
+    let mut aggregate = Aggregate::default();
+    let mut buckets = BTreeMap::<DateTime<Utc>, (f64, usize)>::new();
+    for (host, points) in per_host {
+        let points = points
+            .into_iter()
+            .filter(|(time, _)| range.contains(time))
+            .collect::<Vec<_>>();
+        if points.is_empty() {
+            aggregate.excluded.push(host);
+            continue;
+        }
+
+        let bucketed = bucket_average(&points, range.clone(), step);
+        for (time, value) in &bucketed {
+            let (sum, count) = buckets.entry(*time).or_default();
+            *sum += value;
+            *count += 1;
+        }
+        aggregate.hosts.push((host, bucketed));
+    }
+
+    aggregate.points = buckets
+        .into_iter()
+        .map(|(time, (sum, count))| match mode {
+            Combine::Sum => (time, sum),
+            Combine::Average => (time, sum / count as f64),
+        })
+        .collect();
+    debug!(
+        hosts = aggregate.hosts.len(),
+        excluded = aggregate.excluded.len(),
+        points = aggregate.points.len()
+    );
+    aggregate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::time;
+
+    /// Points of a host at `(seconds, value)`.
+    fn points(samples: &[(i64, f64)]) -> Vec<Point> {
+        samples
+            .iter()
+            .map(|(seconds, value)| (time(*seconds), *value))
+            .collect()
+    }
+
+    /// Hosts collecting every minute, `beta` 20s after `alpha`.
+    fn misaligned() -> Vec<(String, Vec<Point>)> {
+        vec![
+            ("alpha".into(), points(&[(0, 1.0), (60, 2.0), (120, 3.0)])),
+            (
+                "beta".into(),
+                points(&[(20, 10.0), (80, 20.0), (140, 30.0)]),
+            ),
+        ]
+    }
+
+    #[test]
+    fn misaligned_hosts_are_combined_per_bucket() {
+        let aggregate = combine(
+            misaligned(),
+            time(0)..time(180),
+            Duration::seconds(60),
+            Combine::Sum,
+        );
+
+        assert_eq!(
+            aggregate.points,
+            points(&[(0, 11.0), (60, 22.0), (120, 33.0)])
+        );
+        assert_eq!(
+            aggregate.hosts,
+            vec![
+                ("alpha".into(), points(&[(0, 1.0), (60, 2.0), (120, 3.0)])),
+                ("beta".into(), points(&[(0, 10.0), (60, 20.0), (120, 30.0)])),
+            ]
+        );
+        assert!(aggregate.excluded.is_empty());
+    }
+
+    #[test]
+    fn gauges_are_averaged_and_rates_summed() {
+        let combined = |mode| {
+            combine(
+                misaligned(),
+                time(0)..time(180),
+                Duration::seconds(60),
+                mode,
+            )
+            .points
+        };
+
+        assert_eq!(
+            combined(Combine::Average),
+            points(&[(0, 5.5), (60, 11.0), (120, 16.5)])
+        );
+        assert_eq!(
+            combined(Combine::Sum),
+            points(&[(0, 11.0), (60, 22.0), (120, 33.0)])
+        );
+        assert_eq!(Combine::of(&MetricKind::NetTxRate), Combine::Sum);
+        assert_eq!(Combine::of(&MetricKind::DiskReadRate), Combine::Sum);
+        assert_eq!(Combine::of(&MetricKind::CpuPercent), Combine::Average);
+        assert_eq!(Combine::of(&MetricKind::RamPercent), Combine::Average);
+    }
+
+    #[test]
+    fn hosts_without_points_in_the_range_are_excluded() {
+        let mut per_host = misaligned();
+        per_host.push(("gamma".into(), points(&[(600, 100.0)])));
+        per_host.push(("delta".into(), Vec::new()));
+
+        let aggregate = combine(
+            per_host,
+            time(0)..time(180),
+            Duration::seconds(60),
+            Combine::Average,
+        );
+
+        assert_eq!(aggregate.excluded, ["gamma", "delta"]);
+        assert_eq!(aggregate.hosts.len(), 2);
+        assert_eq!(
+            aggregate.points,
+            points(&[(0, 5.5), (60, 11.0), (120, 16.5)])
+        );
+    }
+
+    #[test]
+    fn buckets_only_combine_the_hosts_with_points_in_them() {
+        let per_host = vec![
+            ("alpha".into(), points(&[(0, 1.0), (60, 2.0), (120, 3.0)])),
+            ("beta".into(), points(&[(20, 10.0)])),
+        ];
+        let combined = |mode| {
+            combine(
+                per_host.clone(),
+                time(0)..time(180),
+                Duration::seconds(60),
+                mode,
+            )
+            .points
+        };
+
+        // NOTE: Neither a 0 for beta in the sum nor a lower average past its last point
+        assert_eq!(
+            combined(Combine::Sum),
+            points(&[(0, 11.0), (60, 2.0), (120, 3.0)])
+        );
+        assert_eq!(
+            combined(Combine::Average),
+            points(&[(0, 5.5), (60, 2.0), (120, 3.0)])
+        );
+    }
+
+    #[test]
+    fn aggregated_metrics_have_a_kind() {
+        for name in AGGREGATE_METRICS {
+            assert!(aggregate_metric(name).is_some(), "{name}");
+        }
+        assert_eq!(aggregate_metric("temperature"), None);
+    }
+}
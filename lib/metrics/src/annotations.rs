@@ -0,0 +1,326 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, tracing};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{psutil::HostFacts, rates::cpu_count_changed, snapshot::SnapShot};
+
+// NOTE: The boot time is derived from the uptime so clock adjustments move it slightly
+const BOOT_TIME_TOLERANCE_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnnotationKind {
+    Reboot,
+    Kernel,
+    Os,
+    ClockJump,
+    /// Thresholds crossed, noticed by sysmet-notify.
+    Alert,
+    /// The number of CPUs changed, e.g. a resized VM.
+    CpuCount,
+    /// The host was suspended, annotated when it resumed.
+    Suspend,
+}
+
+/// Event shown on the charts at the time it was noticed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Annotation {
+    pub time: DateTime<Utc>,
+    pub kind: AnnotationKind,
+    /// Short description, e.g. "kernel 6.1.0 → 6.6.8".
+    pub text: String,
+}
+
+/// Changes between the facts of two consecutive snapshots as (kind, description).
+pub fn host_changes(previous: &HostFacts, current: &HostFacts) -> Vec<(AnnotationKind, String)> {
+    let mut changes = Vec::new();
+
+    // NOTE: Facts without boot identifier fall back to a move of the boot time
+    let rebooted = match previous.same_boot(current) {
+        Some(same_boot) => !same_boot,
+        None => previous
+            .boot_time
+            .zip(current.boot_time)
+            .is_some_and(|(previous, current)| {
+                (current - previous).abs() > Duration::seconds(BOOT_TIME_TOLERANCE_SECS)
+            }),
+    };
+    if rebooted {
+        changes.push((AnnotationKind::Reboot, "rebooted".to_string()));
+    }
+    if previous.kernel != current.kernel {
+        changes.push((
+            AnnotationKind::Kernel,
+            format!("kernel {} \u{2192} {}", previous.kernel, current.kernel),
+        ));
+    }
+    // NOTE: A missing os-release on either side is not a change of OS
+    if let (Some(previous_os), Some(current_os)) = (&previous.os, &current.os) {
+        if previous_os != current_os {
+            changes.push((
+                AnnotationKind::Os,
+                format!("OS {previous_os} \u{2192} {current_os}"),
+            ));
+        }
+    }
+
+    changes
+}
+
+/// Step of the clock between two snapshots, negative when it went back.
+///
+/// The boot time is derived from the clock so it moves along with it while a real reboot
+/// happens after the previous snapshot, or changes the boot identifier when both snapshots have one.
+/// Without boot times only backward steps are noticed, each snapshot taken before the clock caught up being one.
+pub fn clock_jump(previous: &SnapShot, current: &SnapShot) -> Option<Duration> {
+    let same_boot = previous
+        .host_facts
+        .as_ref()
+        .zip(current.host_facts.as_ref())
+        .and_then(|(previous, current)| previous.same_boot(current));
+    let boot_times = previous
+        .host_facts
+        .as_ref()
+        .and_then(|facts| facts.boot_time)
+        .zip(
+            current
+                .host_facts
+                .as_ref()
+                .and_then(|facts| facts.boot_time),
+        );
+    match boot_times {
+        Some((previous_boot, current_boot))
+            if (current_boot - previous_boot).abs()
+                > Duration::seconds(BOOT_TIME_TOLERANCE_SECS)
+                && same_boot.unwrap_or(current_boot < previous.time) =>
+        {
+            Some(current_boot - previous_boot)
+        }
+        // NOTE: The clock did not move since the previous snapshot, it is only ahead for having been clamped after
+        // an earlier jump
+        Some((previous_boot, current_boot))
+            if (current_boot - previous_boot).abs()
+                <= Duration::seconds(BOOT_TIME_TOLERANCE_SECS)
+                && same_boot != Some(false) =>
+        {
+            None
+        }
+        _ if current.time <= previous.time => Some(current.time - previous.time),
+        _ => None,
+    }
+}
+
+/// Description of a clock step, e.g. "clock went back by 40 min".
+pub fn clock_jump_text(jump: Duration) -> String {
+    let direction = if jump < Duration::zero() {
+        "back"
+    } else {
+        "forward"
+    };
+    let jump = jump.abs();
+    if jump < Duration::minutes(2) {
+        format!("clock went {direction} by {}s", jump.num_seconds())
+    } else {
+        format!("clock went {direction} by {} min", jump.num_minutes())
+    }
+}
+
+/// Annotation at the time of `current` when its number of CPUs differs from the one of `previous`, e.g.
+/// "CPU count changed 4 → 8".
+pub fn cpu_count_annotation(previous: &SnapShot, current: &SnapShot) -> Option<Annotation> {
+    cpu_count_changed(previous, current).then(|| Annotation {
+        time: current.time,
+        kind: AnnotationKind::CpuCount,
+        text: format!(
+            "CPU count changed {} \u{2192} {}",
+            previous.get_cpu_count(),
+            current.get_cpu_count()
+        ),
+    })
+}
+
+/// Annotations at `time` for the changes between the facts of two consecutive snapshots.
+#[tracing::instrument(level = "debug")]
+pub fn host_annotations(
+    previous: &HostFacts,
+    current: &HostFacts,
+    time: DateTime<Utc>,
+) -> Vec<Annotation> {
+    let annotations = host_changes(previous, current)
+        .into_iter()
+        .map(|(kind, text)| Annotation { time, kind, text })
+        .collect::<Vec<_>>();
+    debug!(annotations = ?annotations);
+    annotations
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn facts(kernel: &str, os: Option<&str>, boot_minute: u32, boot_id: Option<&str>) -> HostFacts {
+        HostFacts {
+            kernel: kernel.to_string(),
+            os: os.map(str::to_string),
+            boot_time: Some(Utc.with_ymd_and_hms(2024, 5, 3, 8, boot_minute, 0).unwrap()),
+            boot_id: boot_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn unchanged_facts_have_no_changes() {
+        let current = facts(
+            "6.1.0",
+            Some("Debian GNU/Linux 12 (bookworm)"),
+            0,
+            Some("a"),
+        );
+        assert_eq!(host_changes(&current, &current.clone()), vec![]);
+    }
+
+    #[test]
+    fn kernel_and_os_upgrades_are_described() {
+        let previous = facts(
+            "6.1.0",
+            Some("Debian GNU/Linux 11 (bullseye)"),
+            0,
+            Some("a"),
+        );
+        let current = facts(
+            "6.6.8",
+            Some("Debian GNU/Linux 12 (bookworm)"),
+            0,
+            Some("a"),
+        );
+        assert_eq!(
+            host_changes(&previous, &current),
+            vec![
+                (AnnotationKind::Kernel, "kernel 6.1.0 → 6.6.8".to_string()),
+                (
+                    AnnotationKind::Os,
+                    "OS Debian GNU/Linux 11 (bullseye) → Debian GNU/Linux 12 (bookworm)"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_os_releases_are_not_changes() {
+        let previous = facts("6.1.0", None, 0, None);
+        let current = facts("6.1.0", Some("Arch Linux"), 0, None);
+        assert_eq!(host_changes(&previous, &current), vec![]);
+        assert_eq!(host_changes(&current, &previous), vec![]);
+    }
+
+    #[test]
+    fn reboots_are_known_from_the_boot_identifiers() {
+        // NOTE: The boot time moved along with the clock, the identifier did not change
+        let previous = facts("6.1.0", None, 0, Some("a"));
+        assert_eq!(
+            host_changes(&previous, &facts("6.1.0", None, 30, Some("a"))),
+            vec![]
+        );
+        assert_eq!(
+            host_changes(&previous, &facts("6.1.0", None, 0, Some("b"))),
+            vec![(AnnotationKind::Reboot, "rebooted".to_string())]
+        );
+    }
+
+    #[test]
+    fn reboots_without_identifiers_move_the_boot_time() {
+        let previous = facts("6.1.0", None, 0, None);
+        // NOTE: Below the tolerance of a boot time derived from the uptime
+        let adjusted = HostFacts {
+            boot_time: previous.boot_time.map(|time| time + Duration::seconds(30)),
+            ..previous.clone()
+        };
+        assert_eq!(host_changes(&previous, &adjusted), vec![]);
+        assert_eq!(
+            host_changes(&previous, &facts("6.6.8", None, 5, Some("b"))),
+            vec![
+                (AnnotationKind::Reboot, "rebooted".to_string()),
+                (AnnotationKind::Kernel, "kernel 6.1.0 → 6.6.8".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn annotations_are_at_the_time_of_the_change() {
+        let time = Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap();
+        let annotations = host_annotations(
+            &facts("6.1.0", None, 0, Some("a")),
+            &facts("6.6.8", None, 0, Some("a")),
+            time,
+        );
+        assert_eq!(
+            annotations,
+            vec![Annotation {
+                time,
+                kind: AnnotationKind::Kernel,
+                text: "kernel 6.1.0 → 6.6.8".to_string(),
+            }]
+        );
+    }
+
+    #[cfg(feature = "database")]
+    mod databases {
+        use super::*;
+        use crate::{
+            database::Database,
+            testing::{snapshots, time},
+        };
+
+        #[test]
+        fn host_changes_are_annotated_when_pushed() {
+            let mut database = Database::default();
+            let facts = [
+                // NOTE: Snapshots taken before the facts were recorded are skipped over
+                None,
+                Some(facts("6.1.0", None, 0, Some("a"))),
+                None,
+                Some(facts("6.1.0", None, 0, Some("a"))),
+                Some(facts("6.6.8", None, 0, Some("b"))),
+            ];
+            for (snapshot, facts) in snapshots(facts.len(), 60).into_iter().zip(facts) {
+                database.push_snapshot(SnapShot {
+                    host_facts: facts,
+                    ..snapshot
+                });
+            }
+
+            assert_eq!(
+                database.annotations,
+                vec![
+                    Annotation {
+                        time: time(240),
+                        kind: AnnotationKind::Reboot,
+                        text: "rebooted".to_string(),
+                    },
+                    Annotation {
+                        time: time(240),
+                        kind: AnnotationKind::Kernel,
+                        text: "kernel 6.1.0 → 6.6.8".to_string(),
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn older_snapshots_are_read_without_facts() {
+            let mut snapshot = serde_json::to_value(&snapshots(1, 60)[0]).unwrap();
+            snapshot.as_object_mut().unwrap().remove("host_facts");
+            let snapshot = serde_json::from_value::<SnapShot>(snapshot).unwrap();
+            assert_eq!(snapshot.host_facts, None);
+
+            let mut facts = serde_json::to_value(facts("6.1.0", None, 0, Some("a"))).unwrap();
+            facts.as_object_mut().unwrap().remove("boot_id");
+            let facts = serde_json::from_value::<HostFacts>(facts).unwrap();
+            assert_eq!(facts.boot_id, None);
+        }
+    }
+}
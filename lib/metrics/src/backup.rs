@@ -0,0 +1,249 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use log::{debug, info, tracing};
+
+use crate::{prelude::*, Result};
+
+const BACKUP_INFIX: &str = ".bak.";
+// NOTE: Lexicographic order is the chronological order
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Where to store the backups and how many of them to keep.
+#[derive(Debug, Clone)]
+pub struct BackupPolicy {
+    /// Defaults to the database directory.
+    pub dir: Option<PathBuf>,
+    pub keep: usize,
+}
+
+/// Number of backups to keep of a command line flag, refusing 0 which would remove the backup just made.
+pub fn parse_keep(value: &str) -> std::result::Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("at least 1 backup has to be kept".to_string()),
+        Ok(keep) => Ok(keep),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn backup_prefix(path: &Path) -> Result<String> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::InvalidDatabasePath(path.to_path_buf()))?;
+    Ok(format!("{}{BACKUP_INFIX}", name.to_string_lossy()))
+}
+
+/// Copy the database file to `<name>.bak.<timestamp>` and only keep the newest backups.
+///
+/// Should be called while holding the database lock, returns `None` when there is nothing to back up.
+#[tracing::instrument]
+pub fn create_backup(path: &Path, policy: &BackupPolicy) -> Result<Option<PathBuf>> {
+    if fs::metadata(path).map_or(true, |metadata| metadata.len() == 0) {
+        debug!("Nothing to back up in {path:?}");
+        return Ok(None);
+    }
+
+    let backup_dir = match &policy.dir {
+        Some(dir) => dir.clone(),
+        None => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    fs::create_dir_all(&backup_dir).map_err(Error::FailedToBackup)?;
+
+    let backup = backup_dir.join(format!(
+        "{}{}",
+        backup_prefix(path)?,
+        Utc::now().format(BACKUP_TIMESTAMP_FORMAT)
+    ));
+    fs::copy(path, &backup).map_err(Error::FailedToBackup)?;
+    info!("Backed up {path:?} to {backup:?}");
+
+    // NOTE: The backup just made is never pruned, whatever the policy
+    prune_backups(path, &backup_dir, policy.keep.max(1))?;
+
+    Ok(Some(backup))
+}
+
+/// Remove the oldest backups of the database to only keep the `keep` newest.
+#[tracing::instrument]
+pub fn prune_backups(path: &Path, backup_dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    let prefix = backup_prefix(path)?;
+    let mut backups = fs::read_dir(backup_dir)
+        .map_err(Error::FailedToBackup)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|backup| {
+            backup
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect::<Vec<_>>();
+    backups.sort();
+
+    let to_remove = backups.len().saturating_sub(keep);
+    let removed = backups.drain(..to_remove).collect::<Vec<_>>();
+    for backup in &removed {
+        fs::remove_file(backup).map_err(Error::FailedToRemoveFile)?;
+        debug!("Removed old backup {backup:?}");
+    }
+
+    Ok(removed)
+}
+
+impl Database {
+    /// Load a backup to make sure it can be restored, returns its number of snapshots.
+    #[tracing::instrument]
+    pub fn validate_backup(backup: &Path, key: Option<&DatabaseKey>) -> Result<usize> {
+        let file = File::open(backup).map_err(Error::FailedToOpenFile)?;
+        let database = Self::load_database(&file, key)?;
+        debug!(
+            "Backup {backup:?} is valid with {} snapshots",
+            database.snapshots.len()
+        );
+
+        Ok(database.snapshots.len())
+    }
+
+    /// Copy the database file to `to` while holding its lock, e.g. to serve it without a write tearing it,
+    /// returns the metadata of the database when it was copied.
+    #[tracing::instrument]
+    pub fn copy_locked(ipath: &str, to: &Path) -> Result<fs::Metadata> {
+        let path = Self::str_to_pathbuf(ipath)?;
+        let mut options = OpenOptions::new();
+        options.read(true);
+        let file = Self::lock(options, &path)?;
+
+        let result = file
+            .metadata()
+            .map_err(Error::FailedToGetFileMetadata)
+            .and_then(|metadata| {
+                fs::copy(&path, to)
+                    .map(|_| metadata)
+                    .map_err(Error::FailedToBackup)
+            });
+        drop(file);
+        Self::unlock(&path)?;
+
+        result
+    }
+
+    /// Validate the backup then atomically replace the database with it,
+    /// the current database is backed up first when a policy is given.
+    #[tracing::instrument]
+    pub fn restore_backup(
+        backup: &Path,
+        ipath: &str,
+        key: Option<&DatabaseKey>,
+        policy: Option<&BackupPolicy>,
+    ) -> Result<()> {
+        Self::validate_backup(backup, key)?;
+
+        // NOTE: Copy next to the database first so the rename cannot cross filesystems,
+        // staging it before backing up the current database also protects it from being pruned
+        let path = Self::str_to_pathbuf(ipath)?;
+        let restoring = PathBuf::from(format!("{}.restoring", path.to_string_lossy()));
+        if let Err(err) = fs::copy(backup, &restoring) {
+            fs::remove_file(&restoring).ok();
+            return Err(Error::FailedToRestoreBackup(err));
+        }
+        Self::replace_with_staged(&restoring, ipath, policy)?;
+        info!("Restored {path:?} from {backup:?}");
+
+        Ok(())
+    }
+
+    /// Atomically replace the database with `staged` while holding its lock, the current database is backed
+    /// up first when a policy is given.
+    ///
+    /// `staged` must be on the filesystem of the database, e.g. next to it, it is removed when the
+    /// replacement fails.
+    #[tracing::instrument]
+    pub fn replace_with_staged(
+        staged: &Path,
+        ipath: &str,
+        policy: Option<&BackupPolicy>,
+    ) -> Result<()> {
+        let path = Self::str_to_pathbuf(ipath)?;
+        let mut options = OpenOptions::new();
+        options.read(true);
+        options.write(true);
+        options.create(true);
+        let _file = match Self::lock(options, &path) {
+            Ok(file) => file,
+            Err(err) => {
+                fs::remove_file(staged).ok();
+                return Err(err);
+            }
+        };
+
+        let result = match policy {
+            Some(policy) => create_backup(&path, policy).map(|_| ()),
+            None => Ok(()),
+        }
+        .and_then(|_| fs::rename(staged, &path).map_err(Error::FailedToRestoreBackup));
+        match &result {
+            Ok(()) => debug!("Replaced {path:?} with {staged:?}"),
+            Err(_) => {
+                fs::remove_file(staged).ok();
+            }
+        }
+        Self::unlock(&path)?;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_backups_are_refused() {
+        assert!(parse_keep("0").is_err());
+        assert!(parse_keep("-1").is_err());
+        assert_eq!(parse_keep("1"), Ok(1));
+        assert_eq!(parse_keep("3"), Ok(3));
+    }
+
+    #[test]
+    fn the_backup_just_made_is_never_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sysmet.db");
+        fs::write(&path, b"database").unwrap();
+        let policy = BackupPolicy { dir: None, keep: 0 };
+
+        let backup = create_backup(&path, &policy).unwrap().unwrap();
+        assert!(backup.exists());
+        let backup = create_backup(&path, &policy).unwrap().unwrap();
+        assert!(backup.exists());
+        assert_eq!(
+            prune_backups(&path, dir.path(), 1).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    fn only_the_newest_backups_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sysmet.db");
+        fs::write(&path, b"database").unwrap();
+        let policy = BackupPolicy {
+            dir: Some(dir.path().join("backups")),
+            keep: 2,
+        };
+
+        let backups = (0..4)
+            .map(|_| {
+                // NOTE: The names are only unique to the millisecond
+                std::thread::sleep(std::time::Duration::from_millis(2));
+                create_backup(&path, &policy).unwrap().unwrap()
+            })
+            .collect::<Vec<_>>();
+        let left = backups
+            .iter()
+            .filter(|backup| backup.exists())
+            .collect::<Vec<_>>();
+        assert_eq!(left, [&backups[2], &backups[3]]);
+    }
+}
@@ -0,0 +1,156 @@
+//! Heuristics noticing clocks that disagree, between two snapshots or between the database and a host reading it.
+use chrono::{DateTime, Duration, Utc};
+
+/// Past this many median intervals between two snapshots the interval is reported as a forward jump.
+pub const MAX_INTERVAL_RATIO: i32 = 10;
+/// Snapshots up to this far in the future are within the drift of two synchronized clocks.
+const FUTURE_TOLERANCE_SECS: i64 = 2 * 60;
+/// Distance to a whole number of hours under which a gap looks like a timezone offset.
+const WHOLE_HOURS_TOLERANCE_SECS: i64 = 5 * 60;
+/// Number of last snapshots the median interval is taken from.
+pub const RECENT_SNAPSHOTS: usize = 20;
+
+/// Unexpected interval between the last snapshot and a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntervalAnomaly {
+    /// The new snapshot is not after the last one, by this much.
+    Backward(Duration),
+    /// The new snapshot is `gap` after the last one while they are usually `median` apart.
+    ForwardJump { gap: Duration, median: Duration },
+}
+
+/// Mismatch between the time of the newest snapshot and the time of the host reading it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSkew {
+    /// The newest snapshot is this far in the future.
+    Future(Duration),
+    /// The newest snapshot is about this many hours old while snapshots are usually much closer.
+    WholeHours(i64),
+}
+
+/// Median interval between consecutive times, `None` with less than two of them.
+pub fn median_interval(times: &[DateTime<Utc>]) -> Option<Duration> {
+    let mut intervals = times
+        .windows(2)
+        .map(|window| window[1] - window[0])
+        .collect::<Vec<_>>();
+    intervals.sort();
+    intervals.get(intervals.len() / 2).copied()
+}
+
+/// Anomaly of a new snapshot at `new` following the snapshots taken at `previous`, sorted by time.
+pub fn interval_anomaly(previous: &[DateTime<Utc>], new: DateTime<Utc>) -> Option<IntervalAnomaly> {
+    let last = *previous.last()?;
+    if new <= last {
+        return Some(IntervalAnomaly::Backward(last - new));
+    }
+
+    let recent = &previous[previous.len().saturating_sub(RECENT_SNAPSHOTS)..];
+    let median = median_interval(recent).filter(|median| *median > Duration::zero())?;
+    let gap = new - last;
+    (gap > median * MAX_INTERVAL_RATIO).then_some(IntervalAnomaly::ForwardJump { gap, median })
+}
+
+/// Skew between the snapshots at `times`, sorted by time, and `now` on the host reading them.
+///
+/// A gap close to a whole number of hours is only suspicious when the snapshots are dense,
+/// a collector stopped for hours gives the same gap.
+pub fn clock_skew(times: &[DateTime<Utc>], now: DateTime<Utc>) -> Option<ClockSkew> {
+    let newest = *times.last()?;
+    let ahead = newest - now;
+    if ahead > Duration::seconds(FUTURE_TOLERANCE_SECS) {
+        return Some(ClockSkew::Future(ahead));
+    }
+
+    let gap = now - newest;
+    let hours = (gap.num_seconds() as f64 / 3600.0).round() as i64;
+    let off_whole_hours = (gap - Duration::hours(hours)).abs();
+    let recent = &times[times.len().saturating_sub(RECENT_SNAPSHOTS)..];
+    let dense = median_interval(recent)
+        .is_some_and(|median| gap > median * MAX_INTERVAL_RATIO && median < Duration::minutes(30));
+    (hours >= 1 && off_whole_hours <= Duration::seconds(WHOLE_HOURS_TOLERANCE_SECS) && dense)
+        .then_some(ClockSkew::WholeHours(hours))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// Times `seconds` after the first one of the tests.
+    fn times(seconds: &[i64]) -> Vec<DateTime<Utc>> {
+        seconds
+            .iter()
+            .map(|seconds| {
+                Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap() + Duration::seconds(*seconds)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn medians_are_taken_from_the_intervals() {
+        assert_eq!(median_interval(&times(&[0])), None);
+        assert_eq!(
+            median_interval(&times(&[0, 60, 120, 3600, 3660])),
+            Some(Duration::seconds(60))
+        );
+    }
+
+    #[test]
+    fn backward_steps_are_anomalies() {
+        let previous = times(&[0, 60, 120]);
+
+        assert_eq!(
+            interval_anomaly(&previous, times(&[-2280])[0]),
+            Some(IntervalAnomaly::Backward(Duration::minutes(40)))
+        );
+        assert_eq!(
+            interval_anomaly(&previous, previous[2]),
+            Some(IntervalAnomaly::Backward(Duration::zero()))
+        );
+    }
+
+    #[test]
+    fn forward_jumps_are_relative_to_the_median_interval() {
+        let previous = times(&[0, 60, 120, 180]);
+
+        assert_eq!(interval_anomaly(&previous, times(&[180 + 600])[0]), None);
+        assert_eq!(
+            interval_anomaly(&previous, times(&[180 + 601])[0]),
+            Some(IntervalAnomaly::ForwardJump {
+                gap: Duration::seconds(601),
+                median: Duration::seconds(60),
+            })
+        );
+        // NOTE: A single snapshot has no usual interval
+        assert_eq!(interval_anomaly(&times(&[0]), times(&[86400])[0]), None);
+    }
+
+    #[test]
+    fn snapshots_from_the_future_are_skewed() {
+        let snapshots = times(&[0, 60, 120]);
+
+        assert_eq!(
+            clock_skew(&snapshots, times(&[-60])[0]),
+            Some(ClockSkew::Future(Duration::minutes(3)))
+        );
+        // NOTE: Within the drift of two synchronized clocks
+        assert_eq!(clock_skew(&snapshots, times(&[0])[0]), None);
+        assert_eq!(clock_skew(&[], times(&[0])[0]), None);
+    }
+
+    #[test]
+    fn whole_hour_gaps_of_dense_snapshots_look_like_timezones() {
+        let dense = times(&[0, 60, 120]);
+        assert_eq!(
+            clock_skew(&dense, times(&[120 + 2 * 3600 + 30])[0]),
+            Some(ClockSkew::WholeHours(2))
+        );
+        assert_eq!(clock_skew(&dense, times(&[120 + 2 * 3600 + 1800])[0]), None);
+
+        // NOTE: A collector stopped for hours gives the same gap
+        let sparse = times(&[0, 3600, 7200]);
+        assert_eq!(clock_skew(&sparse, times(&[7200 + 2 * 3600])[0]), None);
+    }
+}
@@ -0,0 +1,393 @@
+use std::{collections::HashMap, fmt, fs, path::PathBuf, str::FromStr, time::Instant};
+
+use log::{debug, tracing, warn};
+
+use crate::{errors::Error, Result};
+
+/// Source of site-specific metrics stored in `SnapShot::custom`, e.g. a GPU utilization or a queue depth.
+pub trait Collector {
+    /// Name used in the logs when the collector fails.
+    fn name(&self) -> &str;
+    /// Metrics as (key, value), keys should be unique across the collectors of a registry.
+    fn collect(&self) -> Result<Vec<(String, f64)>>;
+}
+
+/// Collectors run after the built-in collection of a snapshot, in registration order.
+#[derive(Default)]
+pub struct CollectorRegistry {
+    collectors: Vec<Box<dyn Collector>>,
+}
+
+impl fmt::Debug for CollectorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.collectors.iter().map(|collector| collector.name()))
+            .finish()
+    }
+}
+
+impl CollectorRegistry {
+    pub fn register(&mut self, collector: impl Collector + 'static) {
+        self.collectors.push(Box::new(collector));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collectors.is_empty()
+    }
+
+    /// Values of every collector, a failing collector is logged and skipped so the others still run.
+    pub fn collect(&self) -> HashMap<String, f64> {
+        self.collect_timed(&mut HashMap::new())
+    }
+
+    /// Same as `collect`, the milliseconds taken by each collector being added to `durations` as
+    /// `custom <name>`.
+    #[tracing::instrument(level = "debug", skip(durations))]
+    pub fn collect_timed(&self, durations: &mut HashMap<String, u64>) -> HashMap<String, f64> {
+        let mut result = HashMap::new();
+        for collector in &self.collectors {
+            let started = Instant::now();
+            let values = collector.collect();
+            durations.insert(
+                format!("custom {}", collector.name()),
+                started.elapsed().as_millis() as u64,
+            );
+            match values {
+                Ok(values) => {
+                    debug!(
+                        "Collector {} returned {} values",
+                        collector.name(),
+                        values.len()
+                    );
+                    result.extend(values);
+                }
+                Err(err) => warn!("Collector {} failed: {err}", collector.name()),
+            }
+        }
+
+        result
+    }
+}
+
+/// Number read from a file, e.g. written by a cron job, given as `name=/path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCollector {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for FileCollector {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                Ok(FileCollector {
+                    name: name.trim().to_string(),
+                    path: PathBuf::from(path.trim()),
+                })
+            }
+            _ => Err(Error::InvalidCustomMetric(value.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for FileCollector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.path.display())
+    }
+}
+
+impl Collector for FileCollector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn collect(&self) -> Result<Vec<(String, f64)>> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|err| Error::FailedToReadCustomMetric(self.path.clone(), err))?;
+        let value = content
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| {
+                Error::InvalidCustomMetricValue(self.path.clone(), content.trim().to_string())
+            })?;
+
+        Ok(vec![(self.name.clone(), value)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use chrono::Utc;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::snapshot::SnapShot;
+
+    /// Collector returning `values`, counting its runs.
+    struct Fixed {
+        name: &'static str,
+        values: Vec<(&'static str, f64)>,
+        runs: Rc<Cell<usize>>,
+    }
+
+    impl Fixed {
+        fn new(name: &'static str, values: &[(&'static str, f64)]) -> Self {
+            Self {
+                name,
+                values: values.to_vec(),
+                runs: Default::default(),
+            }
+        }
+    }
+
+    impl Collector for Fixed {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn collect(&self) -> Result<Vec<(String, f64)>> {
+            self.runs.set(self.runs.get() + 1);
+            Ok(self
+                .values
+                .iter()
+                .map(|(key, value)| (key.to_string(), *value))
+                .collect())
+        }
+    }
+
+    struct Failing;
+
+    impl Collector for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn collect(&self) -> Result<Vec<(String, f64)>> {
+            Err(Error::InvalidCustomMetric("failing".to_string()))
+        }
+    }
+
+    /// Collector taking `delay` to return a single value.
+    struct Sleeping {
+        name: &'static str,
+        delay: std::time::Duration,
+    }
+
+    impl Collector for Sleeping {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn collect(&self) -> Result<Vec<(String, f64)>> {
+            std::thread::sleep(self.delay);
+            Ok(vec![(self.name.to_string(), 1.0)])
+        }
+    }
+
+    fn write(dir: &TempDir, name: &str, content: &str) -> FileCollector {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        FileCollector {
+            name: name.to_string(),
+            path,
+        }
+    }
+
+    #[test]
+    fn values_of_every_collector_are_gathered() {
+        let mut registry = CollectorRegistry::default();
+        assert!(registry.is_empty());
+        registry.register(Fixed::new("gpu", &[("gpu", 42.0), ("gpu memory", 3.5)]));
+        registry.register(Fixed::new("queue", &[("queue", 7.0)]));
+
+        assert!(!registry.is_empty());
+        assert_eq!(format!("{registry:?}"), r#"["gpu", "queue"]"#);
+        assert_eq!(
+            registry.collect(),
+            HashMap::from([
+                ("gpu".to_string(), 42.0),
+                ("gpu memory".to_string(), 3.5),
+                ("queue".to_string(), 7.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn failing_collectors_do_not_stop_the_others() {
+        let after = Fixed::new("queue", &[("queue", 7.0)]);
+        let runs = after.runs.clone();
+        let mut registry = CollectorRegistry::default();
+        registry.register(Failing);
+        registry.register(after);
+
+        let mut durations = HashMap::new();
+        let values = registry.collect_timed(&mut durations);
+
+        assert_eq!(values, HashMap::from([("queue".to_string(), 7.0)]));
+        assert_eq!(runs.get(), 1);
+        let mut timed = durations.keys().collect::<Vec<_>>();
+        timed.sort();
+        assert_eq!(timed, ["custom failing", "custom queue"]);
+    }
+
+    #[test]
+    fn later_collectors_win_on_the_same_key() {
+        let mut registry = CollectorRegistry::default();
+        registry.register(Fixed::new("first", &[("depth", 1.0)]));
+        registry.register(Fixed::new("second", &[("depth", 2.0)]));
+
+        assert_eq!(registry.collect()["depth"], 2.0);
+    }
+
+    #[test]
+    fn file_collectors_are_parsed_from_name_and_path() {
+        let collector = " queue = /run/queue-depth "
+            .parse::<FileCollector>()
+            .unwrap();
+        assert_eq!(
+            collector,
+            FileCollector {
+                name: "queue".to_string(),
+                path: PathBuf::from("/run/queue-depth"),
+            }
+        );
+        assert_eq!(collector.to_string(), "queue=/run/queue-depth");
+        assert_eq!(
+            collector.to_string().parse::<FileCollector>().unwrap(),
+            collector
+        );
+
+        for invalid in ["queue", "=/run/queue-depth", "queue=", " = "] {
+            assert!(
+                matches!(
+                    invalid.parse::<FileCollector>(),
+                    Err(Error::InvalidCustomMetric(value)) if value == invalid
+                ),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn file_collectors_read_a_number() {
+        let dir = TempDir::new().unwrap();
+
+        assert_eq!(
+            write(&dir, "queue", " 12.5\n").collect().unwrap(),
+            [("queue".to_string(), 12.5)]
+        );
+        for content in ["", "twelve", "NaN", "inf"] {
+            assert!(
+                matches!(
+                    write(&dir, "queue", content).collect(),
+                    Err(Error::InvalidCustomMetricValue(_, value)) if value == content
+                ),
+                "{content:?}"
+            );
+        }
+        let missing = FileCollector {
+            name: "missing".to_string(),
+            path: dir.path().join("missing"),
+        };
+        assert!(matches!(
+            missing.collect(),
+            Err(Error::FailedToReadCustomMetric(..))
+        ));
+    }
+
+    #[test]
+    fn snapshots_store_the_values_of_the_collectors() {
+        let dir = TempDir::new().unwrap();
+        let mut registry = CollectorRegistry::default();
+        registry.register(write(&dir, "queue", "3"));
+        registry.register(Failing);
+        registry.register(Fixed::new("gpu", &[("gpu", 42.0)]));
+
+        let snapshot = SnapShot::new_with_collectors_at(Utc::now(), &[], &registry, false).unwrap();
+
+        assert_eq!(
+            snapshot.custom,
+            HashMap::from([("queue".to_string(), 3.0), ("gpu".to_string(), 42.0)])
+        );
+        assert!(snapshot
+            .collection_breakdown_ms
+            .contains_key("custom failing"));
+    }
+
+    #[test]
+    fn each_collector_is_timed_in_the_breakdown() {
+        let mut registry = CollectorRegistry::default();
+        registry.register(Fixed::new("gpu", &[("gpu", 42.0)]));
+        registry.register(Sleeping {
+            name: "queue",
+            delay: std::time::Duration::from_millis(300),
+        });
+        registry.register(Sleeping {
+            name: "disk wakeup",
+            delay: std::time::Duration::from_millis(600),
+        });
+
+        let snapshot = SnapShot::new_with_collectors_at(Utc::now(), &[], &registry, false).unwrap();
+
+        let breakdown = &snapshot.collection_breakdown_ms;
+        for part in ["container", "disks_memory", "custom gpu"] {
+            assert!(
+                breakdown.contains_key(part),
+                "{part} missing from {breakdown:?}"
+            );
+        }
+        assert!(breakdown["custom queue"] >= 300, "{breakdown:?}");
+        assert!(breakdown["custom disk wakeup"] >= 600, "{breakdown:?}");
+        assert!(breakdown["custom gpu"] < 300, "{breakdown:?}");
+        assert_eq!(
+            snapshot.slowest_collector(),
+            Some(("custom disk wakeup", breakdown["custom disk wakeup"]))
+        );
+        // NOTE: The whole collection includes every part of it
+        assert!(snapshot.collection_duration_ms.unwrap() >= breakdown.values().sum::<u64>());
+    }
+
+    #[cfg(feature = "database")]
+    mod databases {
+        use super::*;
+        use crate::{
+            database::Database,
+            testing::{time, SnapshotBuilder},
+        };
+
+        #[test]
+        fn custom_values_are_written_and_read_back() {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sysmet.db").to_string_lossy().into_owned();
+            let mut database = Database::default();
+            database.push_snapshot(SnapshotBuilder::at(time(0)).build());
+            database.push_snapshot(
+                SnapshotBuilder::at(time(60))
+                    .custom("gpu", 42.0)
+                    .custom("queue", 3.0)
+                    .build(),
+            );
+            database.write_to_file(&path).unwrap();
+
+            let loaded = Database::from_file(&path, None).unwrap();
+            let custom = loaded
+                .snapshots()
+                .iter()
+                .map(|snapshot| snapshot.custom.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                custom,
+                [
+                    HashMap::new(),
+                    HashMap::from([("gpu".to_string(), 42.0), ("queue".to_string(), 3.0)])
+                ]
+            );
+        }
+    }
+}
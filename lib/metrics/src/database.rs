@@ -1,6 +1,9 @@
 use std::{
-    fs::{remove_file, File, OpenOptions},
-    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display},
+    fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions},
+    io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::{Path, PathBuf},
     str::FromStr,
     thread::sleep,
@@ -8,40 +11,190 @@ use std::{
 };
 
 use chrono::{DateTime, Utc};
-use log::{debug, trace, tracing, warn};
+use log::{debug, info, trace, tracing, warn};
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
-use crate::{prelude::*, Result};
+use crate::{
+    annotations::{clock_jump, clock_jump_text, cpu_count_annotation, host_annotations},
+    clock::{interval_anomaly, IntervalAnomaly, RECENT_SNAPSHOTS},
+    encryption,
+    integrity::{self, RecordsHeader},
+    prelude::*,
+    psutil::{HostFacts, KERNEL_GAUGES},
+    rates,
+    suspend::{interval_gap, suspension_text, GapKind},
+    Result,
+};
 
 const SLEEP_DURATION_BEFORE_RETRY_LOCK: Duration = Duration::from_millis(100);
 const LOCKFILE_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Extension of the snapshots spilled while the database was locked, their temporary files having none.
+const PENDING_EXTENSION: &str = "snapshot";
+/// Age after which a temporary file of the pending directory is considered left by a crashed run.
+const PENDING_STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
 const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Most snapshots returned by a page of `snapshots_page`.
+pub const MAX_SNAPSHOTS_PAGE: usize = 1000;
+
+/// Values of a chart line as (value, time).
+pub type Values = Vec<(f64, DateTime<Utc>)>;
+
+/// Build of the binary writing the database, e.g. `sysmet-update 0.1.0 (abc1234)`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Writer {
+    pub name: String,
+    pub version: String,
+    pub commit: String,
+}
+
+/// Snapshots of the pending directory merged into a database.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PendingMerge {
+    pub merged: usize,
+    /// Already in the database, e.g. merged by a run which failed before removing their files.
+    pub duplicates: usize,
+    pub files: Vec<PathBuf>,
+}
+
+impl PendingMerge {
+    /// Remove the merged files, once the database holding their snapshots is written.
+    pub fn remove_files(&self) -> Result<()> {
+        for file in &self.files {
+            remove_file(file).map_err(Error::FailedToRemoveFile)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Directory of the snapshots spilled while the database at `path` was locked, e.g. `metrics.db.pending`.
+pub fn pending_dir(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.pending", path.to_string_lossy()))
+}
+
+/// Build which last wrote the database and when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastWrite {
+    pub writer: Writer,
+    pub time: DateTime<Utc>,
+}
+
+impl Display for LastWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}) at {}",
+            self.writer.name, self.writer.version, self.writer.commit, self.time
+        )
+    }
+}
+
+/// Number of items of each collection of a database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CollectionCounts {
+    pub snapshots: usize,
+    pub annotations: usize,
+    /// Annotations of the thresholds crossed, noticed by sysmet-notify, also counted in `annotations`.
+    pub alerts: usize,
+    /// Distinct keys of the custom metrics recorded in the snapshots.
+    pub custom_keys: usize,
+}
+
+impl Display for CollectionCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} snapshots, {} annotations ({} alerts), {} custom metrics",
+            self.snapshots, self.annotations, self.alerts, self.custom_keys
+        )
+    }
+}
+
+/// What the retention removed from a database.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Pruned {
+    pub snapshots: usize,
+    /// Annotations, the alerts included.
+    pub annotations: usize,
+    /// Keys of the custom metrics no retained snapshot records anymore.
+    pub custom_keys: Vec<String>,
+}
+
+impl Display for Pruned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} snapshots and {} annotations removed",
+            self.snapshots, self.annotations
+        )?;
+        if !self.custom_keys.is_empty() {
+            write!(
+                f,
+                ", custom metrics no longer recorded: {}",
+                self.custom_keys.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Database {
-    version: String,
-    pub snapshots: Vec<SnapShot>,
+    pub(crate) version: String,
+    // NOTE: Missing from databases written before it was introduced
+    #[serde(default)]
+    pub(crate) hostname: Option<String>,
+    // NOTE: Sorted by time, only appended through `push_snapshot` so the order holds
+    pub(crate) snapshots: Vec<SnapShot>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    // NOTE: Never written to the file, used to encrypt the database when writing it back
+    #[serde(skip)]
+    pub(crate) key: Option<DatabaseKey>,
+    // NOTE: Format of the file, not part of the serialized database
+    #[serde(skip)]
+    pub(crate) checksums: bool,
+    #[serde(skip)]
+    pub(crate) skipped_records: usize,
+    // NOTE: Facts of the last snapshot of the previous shard, compared with the first snapshot of this one
+    #[serde(skip)]
+    pub(crate) previous_facts: Option<HostFacts>,
+    // NOTE: Missing from databases written before it was introduced
+    #[serde(default)]
+    pub(crate) last_write: Option<LastWrite>,
+    // NOTE: Stamped as the last write on every write, never written itself
+    #[serde(skip)]
+    pub(crate) writer: Option<Writer>,
 }
 
 impl Default for Database {
     fn default() -> Self {
         Self {
             version: CRATE_VERSION.to_string(),
+            hostname: None,
             snapshots: Vec::new(),
+            annotations: Vec::new(),
+            key: None,
+            checksums: false,
+            skipped_records: 0,
+            previous_facts: None,
+            last_write: None,
+            writer: None,
         }
     }
 }
 
 impl Database {
-    fn str_to_pathbuf(path: &str) -> Result<PathBuf> {
+    pub(crate) fn str_to_pathbuf(path: &str) -> Result<PathBuf> {
         let path = PathBuf::from_str(path).map_err(Error::InvalidPath)?;
         Ok(path)
     }
 
     #[tracing::instrument(level = "trace")]
-    fn lock(options: OpenOptions, path: &PathBuf) -> Result<File> {
+    pub(crate) fn lock(options: OpenOptions, path: &PathBuf) -> Result<File> {
         let lockfile = PathBuf::from_str(&format!("{}.lock", path.to_str().unwrap()))
             .map_err(Error::InvalidPath)?;
         let instant = Instant::now();
@@ -57,7 +210,11 @@ impl Database {
             File::create(&lockfile).map_err(Error::FailedToOpenFile)?;
         }
         debug!("Created lockfile {:?}", &lockfile);
-        let file = options.open(path).map_err(Error::FailedToOpenFile)?;
+        // NOTE: A lockfile left behind would make every later access wait for the timeout
+        let file = options.open(path).map_err(|err| {
+            remove_file(&lockfile).ok();
+            Error::FailedToOpenFile(err)
+        })?;
         let file_size = file
             .metadata()
             .map_err(Error::FailedToGetFileMetadata)?
@@ -71,7 +228,7 @@ impl Database {
     }
 
     #[tracing::instrument(level = "trace")]
-    fn unlock(path: &Path) -> Result<()> {
+    pub(crate) fn unlock(path: &Path) -> Result<()> {
         if path.exists() {
             let lockfile = PathBuf::from_str(&format!("{}.lock", path.to_str().unwrap()))
                 .map_err(Error::InvalidPath)?;
@@ -81,31 +238,150 @@ impl Database {
         Ok(())
     }
 
-    #[tracing::instrument(level = "debug")]
-    fn load_database(file: &File) -> Result<Self> {
-        let file_size = file
-            .metadata()
-            .map_err(Error::FailedToGetFileMetadata)?
-            .len();
+    /// Content of the reader, decrypted when needed, `None` when it is empty.
+    #[tracing::instrument(level = "trace", skip(reader))]
+    fn read_plaintext(reader: impl Read, key: Option<&DatabaseKey>) -> Result<Option<Vec<u8>>> {
+        Self::decrypt(Self::read_all(reader)?, key)
+    }
+
+    /// Whole content of the reader.
+    fn read_all(mut reader: impl Read) -> Result<Vec<u8>> {
+        // NOTE: Reading a `File` to its end already reserves its size, a `BufReader` would only copy it twice
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(Error::FailedToReadFile)?;
+        Ok(bytes)
+    }
 
-        let mut result = if file_size == 0 {
-            Database::default()
+    /// Content of a database file, decrypted when needed, `None` when it is empty.
+    fn decrypt(bytes: Vec<u8>, key: Option<&DatabaseKey>) -> Result<Option<Vec<u8>>> {
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+
+        if encryption::is_encrypted(&bytes) {
+            let key = key.ok_or(Error::MissingKey)?;
+            Ok(Some(encryption::decrypt(key, &bytes)?))
         } else {
-            let mut reader = BufReader::new(file);
-            let database = ciborium::de::from_reader::<Database, _>(&mut reader)?;
-            tracing::debug!(
-                "Deserialized database with {} snapshots",
-                database.snapshots.len()
-            );
-            database
+            Ok(Some(bytes))
+        }
+    }
+
+    /// Deserialize the database of `len` bytes read from `reader` and the records skipped because they are
+    /// corrupt.
+    #[tracing::instrument(level = "trace", skip(reader))]
+    fn decode(
+        mut reader: impl BufRead,
+        len: usize,
+    ) -> Result<(Self, Vec<integrity::CorruptRecord>)> {
+        if integrity::is_checksummed(reader.fill_buf().map_err(Error::FailedToReadFile)?) {
+            let decoded = integrity::decode(reader, len)?;
+            let database = Database {
+                version: decoded.header.version,
+                hostname: decoded.header.hostname,
+                snapshots: decoded.snapshots,
+                annotations: decoded.header.annotations,
+                key: None,
+                checksums: true,
+                skipped_records: decoded.corrupt_records.len(),
+                previous_facts: None,
+                last_write: decoded.header.last_write,
+                writer: None,
+            };
+            Ok((database, decoded.corrupt_records))
+        } else {
+            let database = ciborium::de::from_reader::<Database, _>(reader)
+                .map_err(|err| Error::CorruptDatabase(format!("undecodable database ({err})")))?;
+            Ok((database, Vec::new()))
+        }
+    }
+
+    /// Load a database from its file or any other reader, e.g. an in-memory cursor.
+    ///
+    /// The bytes are untrusted: a truncated or corrupt content gives an error, never a panic.
+    #[tracing::instrument(level = "debug", skip(reader))]
+    pub fn load_database(reader: impl Read, key: Option<&DatabaseKey>) -> Result<Self> {
+        Self::from_plaintext(Self::read_plaintext(reader, key)?.as_deref(), key, false)
+    }
+
+    /// Load a database about to replace the current one, e.g. an upload, refusing a database written by a
+    /// newer version, with corrupt records or in plaintext while `key` is given instead of loading it.
+    #[tracing::instrument(level = "debug", skip(reader))]
+    pub fn load_replacement(reader: impl Read, key: Option<&DatabaseKey>) -> Result<Self> {
+        let bytes = Self::read_all(reader)?;
+        // NOTE: Replacing an encrypted database with a plaintext one would silently drop its encryption
+        if key.is_some() && !bytes.is_empty() && !encryption::is_encrypted(&bytes) {
+            return Err(Error::UnencryptedReplacement);
+        }
+        let database = Self::from_plaintext(Self::decrypt(bytes, key)?.as_deref(), key, true)?;
+        // NOTE: The skipped records would be dropped for good on the next write
+        if database.skipped_records > 0 {
+            return Err(Error::CorruptRecords(database.skipped_records));
+        }
+
+        Ok(database)
+    }
+
+    /// Database of the decrypted content of its file, `None` for an empty file.
+    #[tracing::instrument(level = "debug", skip(bytes))]
+    fn from_plaintext(
+        bytes: Option<&[u8]>,
+        key: Option<&DatabaseKey>,
+        reject_newer: bool,
+    ) -> Result<Self> {
+        let decoded = bytes
+            .map(|bytes| Self::decode(bytes, bytes.len()))
+            .transpose()?;
+        Self::from_decoded(decoded, key, reject_newer)
+    }
+
+    /// Database of its decoded file and its corrupt records, `None` for an empty file.
+    #[tracing::instrument(level = "debug", skip(decoded))]
+    fn from_decoded(
+        decoded: Option<(Self, Vec<integrity::CorruptRecord>)>,
+        key: Option<&DatabaseKey>,
+        reject_newer: bool,
+    ) -> Result<Self> {
+        let mut result = match decoded {
+            None => Database::default(),
+            Some((database, corrupt_records)) => {
+                if !corrupt_records.is_empty() {
+                    warn!(
+                        "Skipped {} corrupt snapshots, they will be dropped on the next write",
+                        corrupt_records.len()
+                    );
+                }
+                tracing::debug!(
+                    "Deserialized database with {} snapshots",
+                    database.snapshots.len()
+                );
+                database
+            }
         };
+        result.key = key.cloned();
+        let clamped = result.normalize_times();
+        if clamped > 0 {
+            warn!("Clamped the time of {clamped} snapshots taken after the clock went back");
+        }
+        let suspensions = result.mark_suspensions();
+        if suspensions > 0 {
+            info!("Noticed {suspensions} suspensions of the host in snapshots written before they were");
+        }
 
         debug!("Loaded database with version {}", result.version);
         trace!("Loaded database from file \n{:#?}", result);
 
-        if VersionReq::from_str(&format!(">{}", env!("CARGO_PKG_VERSION")))?
-            .matches(&Version::from_str(&result.version)?)
-        {
+        let version = Version::from_str(&result.version).map_err(|err| {
+            Error::CorruptDatabase(format!("invalid version {:?} ({err})", result.version))
+        })?;
+        if VersionReq::from_str(&format!(">{}", env!("CARGO_PKG_VERSION")))?.matches(&version) {
+            if reject_newer {
+                return Err(Error::NewerDatabaseVersion(
+                    result.version,
+                    CRATE_VERSION.to_string(),
+                ));
+            }
             warn!(
                 "Database version mismatch, current version is {}, database version is {}",
                 CRATE_VERSION, result.version
@@ -116,6 +392,31 @@ impl Database {
         Ok(result)
     }
 
+    /// Content of the file of the database, encrypted when it has a key.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        let plaintext = if self.checksums {
+            integrity::encode(
+                &RecordsHeader {
+                    version: self.version.clone(),
+                    hostname: self.hostname.clone(),
+                    records: self.snapshots.len() as u64,
+                    annotations: self.annotations.clone(),
+                    last_write: self.last_write.clone(),
+                },
+                &self.snapshots,
+            )?
+        } else {
+            let mut plaintext = Vec::new();
+            ciborium::ser::into_writer(&self, &mut plaintext)?;
+            plaintext
+        };
+        match &self.key {
+            Some(key) => encryption::encrypt(key, &plaintext),
+            None => Ok(plaintext),
+        }
+    }
+
     #[tracing::instrument(level = "debug")]
     fn write_self_to_file(&self, file: &File) -> Result<()> {
         let mut writer = BufWriter::new(file);
@@ -125,8 +426,16 @@ impl Database {
                 .map_err(Error::FailedToGetFileMetadata)?
                 .len()
         );
-        ciborium::ser::into_writer(&self, &mut writer)?;
+        let content = self.to_bytes()?;
+        writer
+            .write_all(&content)
+            .map_err(Error::FailedToWriteFile)?;
         writer.flush().map_err(Error::FailedToWriteFile)?;
+        // NOTE: Drop leftovers of a previous longer content when the file was not truncated on open
+        let written = writer
+            .stream_position()
+            .map_err(Error::FailedToSetFileCursor)?;
+        file.set_len(written).map_err(Error::FailedToWriteFile)?;
         debug!(
             "File size after write is {}",
             file.metadata()
@@ -137,21 +446,99 @@ impl Database {
     }
 
     #[tracing::instrument]
-    pub fn from_file(ipath: &str) -> Result<Self> {
+    pub fn from_file(ipath: &str, key: Option<&DatabaseKey>) -> Result<Self> {
         let path = Self::str_to_pathbuf(ipath)?;
 
         let mut options = OpenOptions::new();
         options.read(true);
 
         let file = Self::lock(options, &path)?;
-        let result = Self::load_database(&file)?;
+        let result = Self::load_database(&file, key);
         Self::unlock(&path)?;
 
-        Ok(result)
+        result
+    }
+
+    /// Load a database without copying its file to the heap first, for the readers of large databases.
+    ///
+    /// With the `streaming` feature the snapshots are decoded from the file one record at a time while its
+    /// lock is held, only an encrypted file is still read whole to be decrypted. Without it this is
+    /// `from_file`.
+    #[tracing::instrument]
+    pub fn open_readonly_streamed(ipath: &str, key: Option<&DatabaseKey>) -> Result<Self> {
+        let path = Self::str_to_pathbuf(ipath)?;
+
+        let mut options = OpenOptions::new();
+        options.read(true);
+
+        let file = Self::lock(options, &path)?;
+        let result = Self::load_streamed(&file, key);
+        Self::unlock(&path)?;
+
+        result
+    }
+
+    #[cfg(feature = "streaming")]
+    #[tracing::instrument(level = "debug")]
+    fn load_streamed(file: &File, key: Option<&DatabaseKey>) -> Result<Self> {
+        let file_size = file
+            .metadata()
+            .map_err(Error::FailedToGetFileMetadata)?
+            .len();
+        let mut reader = std::io::BufReader::new(file);
+        let start = reader.fill_buf().map_err(Error::FailedToReadFile)?;
+        if start.is_empty() {
+            return Self::from_decoded(None, key, false);
+        }
+        // NOTE: The whole ciphertext is needed to authenticate it before anything is decoded
+        if encryption::is_encrypted(start) {
+            return Self::load_database(reader, key);
+        }
+
+        let decoded = Self::decode(reader, usize::try_from(file_size).unwrap_or(usize::MAX))?;
+        Self::from_decoded(Some(decoded), key, false)
+    }
+
+    #[cfg(not(feature = "streaming"))]
+    fn load_streamed(file: &File, key: Option<&DatabaseKey>) -> Result<Self> {
+        Self::load_database(file, key)
+    }
+
+    /// Check every record of the database file without failing on corrupt snapshots.
+    #[tracing::instrument]
+    pub fn verify_file(ipath: &str, key: Option<&DatabaseKey>) -> Result<IntegrityReport> {
+        let path = Self::str_to_pathbuf(ipath)?;
+
+        let mut options = OpenOptions::new();
+        options.read(true);
+
+        let file = Self::lock(options, &path)?;
+        let result = Self::read_plaintext(&file, key).and_then(|bytes| match bytes {
+            None => Ok(IntegrityReport {
+                checksummed: false,
+                snapshots: 0,
+                corrupt_records: Vec::new(),
+            }),
+            Some(bytes) => {
+                Self::decode(&bytes[..], bytes.len()).map(|(database, corrupt_records)| {
+                    IntegrityReport {
+                        checksummed: database.checksums,
+                        snapshots: database.snapshots.len(),
+                        corrupt_records,
+                    }
+                })
+            }
+        });
+        Self::unlock(&path)?;
+
+        result
     }
 
     #[tracing::instrument]
-    pub fn from_file_with_write(ipath: &str) -> Result<(Self, File, PathBuf)> {
+    pub fn from_file_with_write(
+        ipath: &str,
+        key: Option<&DatabaseKey>,
+    ) -> Result<(Self, File, PathBuf)> {
         let path = Self::str_to_pathbuf(ipath)?;
 
         let mut options = OpenOptions::new();
@@ -160,7 +547,13 @@ impl Database {
         options.create(true);
 
         let mut file = Self::lock(options, &path)?;
-        let result = Self::load_database(&file)?;
+        let result = match Self::load_database(&file, key) {
+            Ok(result) => result,
+            Err(err) => {
+                Self::unlock(&path)?;
+                return Err(err);
+            }
+        };
 
         // NOTE: We need to reset the file pointer to the beginning of the file to overwrite
         // SOURCE: https://doc.rust-lang.org/std/fs/struct.OpenOptions.html#method.append
@@ -170,8 +563,61 @@ impl Database {
         Ok((result, file, path))
     }
 
+    /// Version of the format the database was written with.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Hostname of the device the snapshots were taken on.
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn has_checksums(&self) -> bool {
+        self.checksums
+    }
+
+    /// Write the database back with a checksum per snapshot to detect corruption.
+    pub fn set_checksums(&mut self, checksums: bool) {
+        self.checksums = checksums;
+    }
+
+    /// Number of corrupt snapshots skipped when loading the database.
+    pub fn skipped_records(&self) -> usize {
+        self.skipped_records
+    }
+
+    /// Build and time of the last write, `None` for databases written before it was recorded.
+    pub fn last_write(&self) -> Option<&LastWrite> {
+        self.last_write.as_ref()
+    }
+
+    /// Record `writer` as the last writer on each following write.
+    pub fn set_writer(&mut self, writer: Writer) {
+        self.writer = Some(writer);
+    }
+
+    /// Stamp the writer, if any, with the current time before writing.
+    fn stamp_write(&mut self) {
+        if let Some(writer) = &self.writer {
+            self.last_write = Some(LastWrite {
+                writer: writer.clone(),
+                time: Utc::now(),
+            });
+        }
+    }
+
+    /// Change the key used when writing the database back, `None` writes it unencrypted.
+    pub fn set_key(&mut self, key: Option<DatabaseKey>) {
+        self.key = key;
+    }
+
     #[tracing::instrument(skip(self))]
-    pub fn write_to_file(&self, path: &str) -> Result<()> {
+    pub fn write_to_file(&mut self, path: &str) -> Result<()> {
         debug!(
             "Number of snapshot that will be written {}",
             self.snapshots.len()
@@ -188,6 +634,7 @@ impl Database {
             "Number of snapshot that will be written {}",
             self.snapshots.len()
         );
+        self.stamp_write();
         self.write_self_to_file(&file)?;
         Self::unlock(&path)?;
 
@@ -195,154 +642,2121 @@ impl Database {
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn write_and_close_file(&self, file: File, path: &PathBuf) -> Result<()> {
+    pub fn write_and_close_file(&mut self, file: File, path: &PathBuf) -> Result<()> {
         debug!(
             "Number of snapshot that will be written {}",
             self.snapshots.len()
         );
+        self.stamp_write();
         self.write_self_to_file(&file)?;
         Self::unlock(path)?;
 
         Ok(())
     }
 
+    /// Same as `write_and_close_file`, the file being read back before unlocking it to check it holds the
+    /// written snapshots.
+    ///
+    /// On a failed write or a mismatch the content the file had before is put back and
+    /// `Error::WriteVerificationFailed` is returned.
     #[tracing::instrument(skip(self))]
-    pub fn close_file(&self, path: &PathBuf) -> Result<()> {
+    pub fn write_verified(&mut self, file: File, path: &PathBuf) -> Result<()> {
+        self.write_verified_with(file, path, Self::write_self_to_file)
+    }
+
+    /// Same as `write_verified`, the database being written to the file by `write`.
+    fn write_verified_with(
+        &mut self,
+        file: File,
+        path: &PathBuf,
+        write: impl FnOnce(&Self, &File) -> Result<()>,
+    ) -> Result<()> {
         debug!(
-            "Number of snapshot that would have been written {}",
+            "Number of snapshot that will be written {}",
             self.snapshots.len()
         );
+        // NOTE: Kept in memory rather than relying on a backup, which is only taken before destructive operations
+        let previous = std::fs::read(path).map_err(Error::FailedToReadFile)?;
+        self.stamp_write();
+        let result = write(self, &file).and_then(|()| self.verify_written(path));
+        drop(file);
+
+        if let Err(err) = result {
+            warn!(
+                "Database read back after writing is wrong, restoring its previous content: {err}"
+            );
+            // NOTE: Written next to the database then renamed so a failure here can't leave it half restored
+            let restoring = PathBuf::from(format!("{}.restoring", path.to_string_lossy()));
+            let restored = std::fs::write(&restoring, &previous)
+                .and_then(|()| std::fs::rename(&restoring, path))
+                .map_err(Error::FailedToRestoreBackup);
+            Self::unlock(path)?;
+            restored?;
+            return Err(Error::WriteVerificationFailed(err.to_string()));
+        }
         Self::unlock(path)?;
 
         Ok(())
     }
 
+    /// Compare the number of snapshots and the newest one of the file to the database.
+    ///
+    /// The file is opened without waiting for the lock, which is held by the caller.
+    #[tracing::instrument(level = "debug", skip(self))]
+    fn verify_written(&self, path: &Path) -> Result<()> {
+        let file = File::open(path).map_err(Error::FailedToOpenFile)?;
+        let written = Self::load_database(&file, self.key.as_ref())?;
+        let newest = |database: &Database| database.snapshots.last().map(|snapshot| snapshot.time);
+        if written.snapshots.len() != self.snapshots.len() || newest(&written) != newest(self) {
+            return Err(Error::CorruptDatabase(format!(
+                "read back {} snapshots up to {:?}, expected {} up to {:?}",
+                written.snapshots.len(),
+                newest(&written),
+                self.snapshots.len(),
+                newest(self)
+            )));
+        }
+        debug!("Verified the {} written snapshots", written.snapshots.len());
+
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self))]
-    pub fn take_snapshot(&mut self, networks_to_ignore: &[&str]) -> Result<()> {
-        self.snapshots.push(SnapShot::new(networks_to_ignore)?);
+    pub fn close_file(&self, path: &PathBuf) -> Result<()> {
         debug!(
-            "Number of snapshots after appending {}",
+            "Number of snapshot that would have been written {}",
             self.snapshots.len()
         );
+        Self::unlock(path)?;
 
         Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    pub fn remove_older(&mut self, older_than_days: i64) -> Result<()> {
-        let oldest_date = Utc::now()
-            .checked_sub_signed(chrono::Duration::days(older_than_days))
-            .ok_or(Error::OldestDateOverflow)?;
-        self.snapshots.retain(|snap| snap.time > oldest_date);
+    /// Write `snapshot` to a new file of the pending directory of the database at `ipath`, encrypted with
+    /// `key` like the database, for the next write holding its lock to merge it with `merge_pending`.
+    #[tracing::instrument(skip(snapshot, key))]
+    pub fn spill_snapshot(
+        ipath: &str,
+        snapshot: &SnapShot,
+        key: Option<&DatabaseKey>,
+    ) -> Result<PathBuf> {
+        let dir = pending_dir(&Self::str_to_pathbuf(ipath)?);
+        create_dir_all(&dir).map_err(Error::FailedToWritePending)?;
+        let mut plaintext = Vec::new();
+        ciborium::ser::into_writer(snapshot, &mut plaintext)?;
+        let content = match key {
+            Some(key) => encryption::encrypt(key, &plaintext)?,
+            None => plaintext,
+        };
 
-        Ok(())
+        let name = format!(
+            "{}-{}",
+            snapshot.time.format("%Y%m%dT%H%M%S%.9fZ"),
+            std::process::id()
+        );
+        let temporary = dir.join(&name);
+        let spilled = dir.join(format!("{name}.{PENDING_EXTENSION}"));
+        // NOTE: Written then renamed so a merge never reads a half written snapshot
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temporary)
+            .map_err(Error::FailedToWritePending)?;
+        file.write_all(&content)
+            .and_then(|()| file.sync_all())
+            .map_err(Error::FailedToWritePending)?;
+        rename(&temporary, &spilled).map_err(Error::FailedToWritePending)?;
+        #[cfg(unix)]
+        File::open(&dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(Error::FailedToWritePending)?;
+        info!("Spilled the snapshot of {} to {spilled:?}", snapshot.time);
+
+        Ok(spilled)
     }
 
-    #[tracing::instrument(skip(self))]
-    pub fn get_cpu_usage(&self) -> Vec<(f64, DateTime<Utc>)> {
-        let mut result: Vec<(f64, DateTime<Utc>)> = Vec::with_capacity(self.snapshots.len());
-        let cpus_times = self
-            .snapshots
-            .iter()
-            .map(|s| (s.get_cpu_time(), s.time))
-            .collect::<Vec<_>>();
+    /// Insert the snapshots spilled to the pending directory of the database at `path` in time order, those
+    /// whose time is already in the database being skipped.
+    ///
+    /// Corrupt entries, and half written ones left for a while, are removed with a warning. The merged files
+    /// are only removed by `PendingMerge::remove_files`, once the database holding their snapshots is written.
+    /// Encrypted entries are kept without `key`.
+    #[tracing::instrument(skip(self, key))]
+    pub fn merge_pending(
+        &mut self,
+        path: &Path,
+        key: Option<&DatabaseKey>,
+    ) -> Result<PendingMerge> {
+        let mut merge = PendingMerge::default();
+        let entries = match read_dir(pending_dir(path)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(merge),
+            Err(err) => return Err(Error::FailedToReadPending(err)),
+        };
 
-        for (idx, ((active, total), time)) in cpus_times.iter().enumerate() {
-            let usage = active / total * 100.0;
+        let mut pending = Vec::new();
+        for entry in entries {
+            let file = entry.map_err(Error::FailedToReadPending)?.path();
+            if file.extension() != Some(PENDING_EXTENSION.as_ref()) {
+                // NOTE: Another run may still be writing it
+                let stale = file
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| {
+                        modified.elapsed().unwrap_or_default() > PENDING_STALE_AFTER
+                    });
+                if stale {
+                    warn!("Removing the half written pending snapshot {file:?}");
+                    remove_file(&file).map_err(Error::FailedToRemoveFile)?;
+                }
+                continue;
+            }
+            match Self::read_pending(&file, key) {
+                Ok(snapshot) => pending.push((snapshot, file)),
+                Err(Error::MissingKey) => {
+                    warn!("Keeping the encrypted pending snapshot {file:?} until a key is given");
+                }
+                Err(err) => {
+                    warn!("Removing the corrupt pending snapshot {file:?}: {err}");
+                    remove_file(&file).map_err(Error::FailedToRemoveFile)?;
+                }
+            }
+        }
 
-            let idx = cpus_times.len() - idx - 1;
-            debug!(idx, cpu_usage=?usage, time=?time);
-            result.push((usage, *time));
+        pending.sort_by_key(|(snapshot, _)| snapshot.time);
+        for (snapshot, file) in pending {
+            // NOTE: Taken before a snapshot written meanwhile, inserted as is rather than clamped after it
+            match self.insert_snapshot(snapshot) {
+                Ok(_) => merge.merged += 1,
+                Err(Error::SnapshotTimeTaken(_)) => merge.duplicates += 1,
+                Err(err) => return Err(err),
+            }
+            merge.files.push(file);
+        }
+        if !merge.files.is_empty() {
+            info!(
+                "Merged {} pending snapshots, {} were already in the database",
+                merge.merged, merge.duplicates
+            );
         }
 
-        debug!(cpu_usage_percentages = ?result);
-        result
+        Ok(merge)
     }
 
-    #[tracing::instrument(skip(self))]
-    pub fn get_ram_usage(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
-        let result = self
-            .snapshots
-            .iter()
-            .map(|s| (s.get_ram_usage(), s.time))
-            .collect::<Vec<_>>();
+    fn read_pending(file: &Path, key: Option<&DatabaseKey>) -> Result<SnapShot> {
+        let content = std::fs::read(file).map_err(Error::FailedToReadPending)?;
+        let plaintext = if encryption::is_encrypted(&content) {
+            encryption::decrypt(key.ok_or(Error::MissingKey)?, &content)?
+        } else {
+            content
+        };
+        Ok(ciborium::de::from_reader(plaintext.as_slice())?)
+    }
 
-        debug!(ram_usage_percentages = ?result);
-        result
+    /// Snapshots from the oldest to the newest.
+    pub fn snapshots(&self) -> &[SnapShot] {
+        &self.snapshots
     }
 
-    #[tracing::instrument(skip(self))]
-    pub fn get_load(&self) -> Vec<((f64, f64, f64), DateTime<Utc>)> {
-        let result = self
+    /// Snapshots taken within `range`, from the oldest to the newest.
+    pub fn snapshots_within(&self, range: Range<DateTime<Utc>>) -> &[SnapShot] {
+        &self.snapshots[self.indices_within(&range)]
+    }
+
+    /// Indices of the snapshots taken within `range`, empty when the range is.
+    pub(crate) fn indices_within(&self, range: &Range<DateTime<Utc>>) -> Range<usize> {
+        // NOTE: Snapshots are sorted by time
+        let start = self
             .snapshots
-            .iter()
-            .map(|s| {
-                let (one, five, fifteen) = s.get_load();
-                let cpu_count = s.get_cpu_count() as f64;
-                let to_percentage = |load| load / cpu_count * 100.0;
-                (
-                    (
-                        to_percentage(one),
-                        to_percentage(five),
-                        to_percentage(fifteen),
-                    ),
-                    s.time,
-                )
-            })
-            .collect::<Vec<_>>();
+            .partition_point(|snapshot| snapshot.time < range.start);
+        let end = self
+            .snapshots
+            .partition_point(|snapshot| snapshot.time < range.end);
+        start..end.max(start)
+    }
 
-        debug!(load_avg = ?result);
-        result
+    /// Up to `limit` snapshots from the `offset`th oldest one, at most `MAX_SNAPSHOTS_PAGE`.
+    pub fn snapshots_page(&self, offset: usize, limit: usize) -> &[SnapShot] {
+        let start = offset.min(self.snapshots.len());
+        let end = start
+            .saturating_add(limit.min(MAX_SNAPSHOTS_PAGE))
+            .min(self.snapshots.len());
+        &self.snapshots[start..end]
     }
 
-    #[tracing::instrument(skip(self))]
-    pub fn get_network(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
-        let result = self
-            .snapshots
-            .iter()
-            .map(|s| {
-                let (recv, sent) = s.get_network_usage();
-                let to_mib = |bytes| bytes / 1024.0 / 1024.0;
-                ((to_mib(recv), to_mib(sent)), s.time)
-            })
-            .collect::<Vec<_>>();
+    /// Mutable snapshots, kept for a release while the callers move to the methods keeping them sorted.
+    #[deprecated(
+        note = "use `push_snapshot`, `retain_snapshots` or `drop_oldest`, which keep the snapshots sorted"
+    )]
+    pub fn snapshots_mut(&mut self) -> &mut Vec<SnapShot> {
+        &mut self.snapshots
+    }
 
-        debug!(network_usage = ?result);
-        result
+    /// Number of snapshots.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Time of the oldest and the newest snapshots, `None` without snapshots.
+    pub fn time_range(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        Some((self.snapshots.first()?.time, self.snapshots.last()?.time))
+    }
+
+    /// Only keep the snapshots for which `keep` is true, in their order.
+    pub fn retain_snapshots(&mut self, keep: impl FnMut(&SnapShot) -> bool) {
+        self.snapshots.retain(keep);
+    }
+
+    /// Remove the `count` oldest snapshots, and the annotations older than the snapshots left.
+    pub fn drop_oldest(&mut self, count: usize) {
+        self.snapshots.drain(..count.min(self.snapshots.len()));
+        let oldest = self.snapshots.first().map(|snap| snap.time);
+        self.annotations
+            .retain(|annotation| oldest.is_some_and(|oldest| annotation.time >= oldest));
+    }
+
+    /// Number of items of each collection.
+    pub fn collection_counts(&self) -> CollectionCounts {
+        CollectionCounts {
+            snapshots: self.snapshots.len(),
+            annotations: self.annotations.len(),
+            alerts: self
+                .annotations
+                .iter()
+                .filter(|annotation| annotation.kind == AnnotationKind::Alert)
+                .count(),
+            custom_keys: self.custom_keys().len(),
+        }
+    }
+
+    /// Keys of the custom metrics recorded in any snapshot.
+    pub fn custom_keys(&self) -> BTreeSet<String> {
+        self.snapshots
+            .iter()
+            .flat_map(|snap| snap.custom.keys())
+            .cloned()
+            .collect()
     }
 
+    /// Take a snapshot of this host, a container being measured against its cgroup limits with
+    /// `cgroup_limits`.
     #[tracing::instrument(skip(self))]
-    pub fn get_disks_speed_usage(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
-        let result = self
+    pub fn take_snapshot(
+        &mut self,
+        networks_to_ignore: &[&str],
+        collectors: &CollectorRegistry,
+        cgroup_limits: bool,
+    ) -> Result<()> {
+        let snapshot =
+            SnapShot::new_with_collectors(networks_to_ignore, collectors, cgroup_limits)?;
+        self.push_snapshot(snapshot);
+
+        Ok(())
+    }
+
+    /// Take a snapshot stamped with `time` rather than now, inserted in time order.
+    ///
+    /// Its counters being read now, the rates of the intervals before and after it are ignored.
+    #[tracing::instrument(skip(self, collectors))]
+    pub fn take_snapshot_at(
+        &mut self,
+        time: DateTime<Utc>,
+        networks_to_ignore: &[&str],
+        collectors: &CollectorRegistry,
+        cgroup_limits: bool,
+    ) -> Result<()> {
+        let mut snapshot =
+            SnapShot::new_with_collectors_at(time, networks_to_ignore, collectors, cgroup_limits)?;
+        snapshot.clock_jump = true;
+        let index = self.insert_snapshot(snapshot)?;
+        if let Some(next) = self.snapshots.get_mut(index + 1) {
+            next.clock_jump = true;
+        }
+
+        Ok(())
+    }
+
+    /// Insert a snapshot in time order, e.g. a backdated or a spilled one, returns its index.
+    ///
+    /// A snapshot after the last one goes through `push_snapshot`, the others are inserted as they are rather
+    /// than clamped after the last one. `Error::SnapshotTimeTaken` is returned when a snapshot has its time.
+    #[tracing::instrument(skip_all)]
+    pub fn insert_snapshot(&mut self, snapshot: SnapShot) -> Result<usize> {
+        let index = self
             .snapshots
-            .iter()
-            .map(|s| {
-                let (read, written) = s.get_disk_speed_usage();
-                let to_kib = |bytes: u64| (bytes / 1024) as f64;
-                ((to_kib(read), to_kib(written)), s.time)
-            })
-            .collect::<Vec<_>>();
+            .partition_point(|existing| existing.time < snapshot.time);
+        if self
+            .snapshots
+            .get(index)
+            .is_some_and(|existing| existing.time == snapshot.time)
+        {
+            return Err(Error::SnapshotTimeTaken(snapshot.time));
+        }
+        if index == self.snapshots.len() {
+            self.push_snapshot(snapshot);
+        } else {
+            debug!(
+                "Inserted the snapshot of {} before {}",
+                snapshot.time, self.snapshots[index].time
+            );
+            self.snapshots.insert(index, snapshot);
+        }
 
-        debug!(disks_speed_usage = ?result);
-        result
+        Ok(index)
     }
 
-    #[tracing::instrument(skip(self))]
-    pub fn get_disk_memory_usage(&self) -> Vec<(f64, DateTime<Utc>)> {
-        let result = self
+    /// Append a snapshot taken earlier, its time is clamped after the last one and host changes are annotated,
+    /// see `insert_snapshot` to keep its time.
+    #[tracing::instrument(skip_all)]
+    pub fn push_snapshot(&mut self, mut snapshot: SnapShot) {
+        let jump = self
+            .snapshots
+            .last()
+            .and_then(|previous| clock_jump(previous, &snapshot));
+        if let Some(jump) = jump {
+            snapshot.clock_jump = true;
+            warn!(
+                "The {}, the rates of this interval are ignored",
+                clock_jump_text(jump)
+            );
+            self.annotations.push(Annotation {
+                time: snapshot.time,
+                kind: AnnotationKind::ClockJump,
+                text: clock_jump_text(jump),
+            });
+        }
+        // NOTE: Backward steps are already warned about when clamped below
+        let recent_times = self
             .snapshots
             .iter()
-            .map(|s| {
-                let usage = s
-                    .get_disks_size_usage()
-                    .into_iter()
-                    .fold(0.0, |sum, (_label, usage)| sum + usage);
-                let to_mib = |bytes| bytes / 1024.0 / 1024.0;
-                (to_mib(usage), s.time)
+            .rev()
+            .take(RECENT_SNAPSHOTS)
+            .rev()
+            .map(|snap| snap.time)
+            .collect::<Vec<_>>();
+        if let Some(IntervalAnomaly::ForwardJump { gap, median }) =
+            interval_anomaly(&recent_times, snapshot.time)
+        {
+            match self
+                .snapshots
+                .last()
+                .map(|previous| interval_gap(&recent_times, previous, &snapshot))
+            {
+                Some(GapKind::Suspended(duration)) if jump.is_none() => {
+                    snapshot.suspended = true;
+                    info!(
+                        "The host was {}, the rates of this interval are ignored",
+                        suspension_text(duration)
+                    );
+                    self.annotations.push(Annotation {
+                        time: snapshot.time,
+                        kind: AnnotationKind::Suspend,
+                        text: suspension_text(duration),
+                    });
+                }
+                // NOTE: Reboots are annotated with the host changes below
+                Some(GapKind::Reboot) => {}
+                _ => warn!(
+                    "Snapshot taken {} min after the last one while they are usually {}s apart, was the clock changed?",
+                    gap.num_minutes(),
+                    median.num_seconds()
+                ),
+            }
+        }
+        // NOTE: Snapshots stay sorted so charts never go backward
+        if let Some(last) = self
+            .snapshots
+            .last()
+            .filter(|last| snapshot.time <= last.time)
+        {
+            warn!(
+                "Snapshot time {} is not after the last one, clamped to {}",
+                snapshot.time,
+                last.time + chrono::Duration::seconds(1)
+            );
+            snapshot.time = last.time + chrono::Duration::seconds(1);
+            // NOTE: Its counters are read much later than its time says
+            snapshot.clock_jump = true;
+        }
+
+        // NOTE: Older snapshots have no facts, the last ones known are compared
+        let previous_facts = self
+            .snapshots
+            .iter()
+            .rev()
+            .find_map(|snap| snap.host_facts.as_ref())
+            .or(self.previous_facts.as_ref());
+        if let Some((previous, current)) = previous_facts.zip(snapshot.host_facts.as_ref()) {
+            let mut annotations = host_annotations(previous, current, snapshot.time);
+            // NOTE: The boot time moves along with the clock, a jump is only a reboot when the boot identifiers
+            // say so
+            if jump.is_some() && previous.same_boot(current).is_none() {
+                annotations.retain(|annotation| annotation.kind != AnnotationKind::Reboot);
+            }
+            for annotation in &annotations {
+                info!("Host change noticed: {}", annotation.text);
+            }
+            self.annotations.extend(annotations);
+        }
+        if let Some(annotation) = self
+            .snapshots
+            .last()
+            .and_then(|previous| cpu_count_annotation(previous, &snapshot))
+        {
+            warn!(
+                "{}, the CPU usage of this interval is ignored",
+                annotation.text
+            );
+            self.annotations.push(annotation);
+        }
+        self.snapshots.push(snapshot);
+        self.hostname = Some(get_hostname());
+        debug!(
+            "Number of snapshots after appending {}",
+            self.snapshots.len()
+        );
+    }
+
+    /// Clamp the time of each snapshot not taken after its predecessor, written before clock jumps were handled,
+    /// returns the number of clamped snapshots.
+    #[tracing::instrument(skip(self))]
+    pub fn normalize_times(&mut self) -> usize {
+        let mut clamped = 0;
+        for idx in 1..self.snapshots.len() {
+            let previous_time = self.snapshots[idx - 1].time;
+            let snapshot = &mut self.snapshots[idx];
+            if snapshot.time <= previous_time {
+                // NOTE: A corrupt time may be the last one chrono can represent
+                snapshot.time = previous_time
+                    .checked_add_signed(chrono::Duration::seconds(1))
+                    .unwrap_or(previous_time);
+                snapshot.clock_jump = true;
+                clamped += 1;
+            }
+        }
+        debug!(clamped);
+        clamped
+    }
+
+    /// Flag and annotate the suspensions of the host between snapshots written before they were noticed,
+    /// returns the number of suspensions found.
+    #[tracing::instrument(skip(self))]
+    pub fn mark_suspensions(&mut self) -> usize {
+        let times = self
+            .snapshots
+            .iter()
+            .map(|snap| snap.time)
+            .collect::<Vec<_>>();
+        let mut found = 0;
+        for idx in 1..self.snapshots.len() {
+            let (previous, current) = (&self.snapshots[idx - 1], &self.snapshots[idx]);
+            if current.interval_untrusted() {
+                continue;
+            }
+            let recent = &times[idx.saturating_sub(RECENT_SNAPSHOTS)..idx];
+            if let GapKind::Suspended(duration) = interval_gap(recent, previous, current) {
+                self.snapshots[idx].suspended = true;
+                self.annotations.push(Annotation {
+                    time: times[idx],
+                    kind: AnnotationKind::Suspend,
+                    text: suspension_text(duration),
+                });
+                found += 1;
+            }
+        }
+        if found > 0 {
+            self.annotations.sort_by_key(|annotation| annotation.time);
+        }
+        debug!(found);
+        found
+    }
+
+    /// Remove the snapshots older than `older_than_days` days, and the annotations too unless
+    /// `keep_annotations`.
+    #[tracing::instrument(skip(self))]
+    pub fn remove_older(&mut self, older_than_days: i64, keep_annotations: bool) -> Result<Pruned> {
+        let oldest_date = Utc::now()
+            .checked_sub_signed(chrono::Duration::days(older_than_days))
+            .ok_or(Error::OldestDateOverflow)?;
+        let (snapshots, annotations) = (self.snapshots.len(), self.annotations.len());
+        let custom_keys = self.custom_keys();
+        self.snapshots.retain(|snap| snap.time > oldest_date);
+        if !keep_annotations {
+            self.annotations
+                .retain(|annotation| annotation.time > oldest_date);
+        }
+
+        let retained_keys = self.custom_keys();
+        let result = Pruned {
+            snapshots: snapshots - self.snapshots.len(),
+            annotations: annotations - self.annotations.len(),
+            custom_keys: custom_keys.difference(&retained_keys).cloned().collect(),
+        };
+        debug!(pruned = ?result);
+        Ok(result)
+    }
+
+    /// Remove the annotations older than the oldest snapshot, e.g. kept by `remove_older`, which no chart
+    /// shows anymore.
+    ///
+    /// The custom metrics are recorded in the snapshots, their keys go away with the last snapshot recording
+    /// them and are reported by `remove_older`.
+    #[tracing::instrument(skip(self))]
+    pub fn vacuum(&mut self) -> Pruned {
+        let annotations = self.annotations.len();
+        let oldest = self.snapshots.first().map(|snap| snap.time);
+        self.annotations
+            .retain(|annotation| oldest.is_some_and(|oldest| annotation.time >= oldest));
+
+        let result = Pruned {
+            annotations: annotations - self.annotations.len(),
+            ..Default::default()
+        };
+        debug!(vacuumed = ?result);
+        result
+    }
+
+    /// Median time between two consecutive snapshots, `None` with less than two snapshots.
+    #[tracing::instrument(skip(self))]
+    pub fn median_interval(&self) -> Option<chrono::Duration> {
+        let mut intervals = self
+            .snapshots
+            .windows(2)
+            .map(|window| window[1].time - window[0].time)
+            .collect::<Vec<_>>();
+        intervals.sort();
+        let result = intervals.get(intervals.len() / 2).copied();
+        debug!(median_interval = ?result);
+        result
+    }
+
+    /// Snapshot taken the closest to `at`, `None` when none is within `tolerance`.
+    #[tracing::instrument(skip(self))]
+    pub fn nearest_snapshot(
+        &self,
+        at: DateTime<Utc>,
+        tolerance: chrono::Duration,
+    ) -> Option<&SnapShot> {
+        // NOTE: Snapshots are appended so they are sorted by time
+        let idx = self.snapshots.partition_point(|snap| snap.time < at);
+        let result = [idx.checked_sub(1), Some(idx)]
+            .into_iter()
+            .flatten()
+            .filter_map(|idx| self.snapshots.get(idx))
+            .min_by_key(|snap| (snap.time - at).abs())
+            .filter(|snap| (snap.time - at).abs() <= tolerance);
+        debug!(nearest_snapshot_time = ?result.map(|snap| snap.time));
+        result
+    }
+
+    /// Time of the newest snapshot `holds` is true for, `None` when it is false for every snapshot.
+    pub fn last_seen(&self, holds: impl Fn(&SnapShot) -> bool) -> Option<DateTime<Utc>> {
+        // NOTE: Walked from the end as a missing item usually disappeared recently
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|snap| holds(snap))
+            .map(|snap| snap.time)
+    }
+
+    /// Time of the newest snapshot with the filesystem mounted on `mountpoint`.
+    #[tracing::instrument(skip(self))]
+    pub fn mount_last_seen(&self, mountpoint: &str) -> Option<DateTime<Utc>> {
+        let result = self.last_seen(|snap| {
+            snap.disks_memory.contains_key(mountpoint) || snap.disks_usage.contains_key(mountpoint)
+        });
+        debug!(last_seen = ?result);
+        result
+    }
+
+    /// Time of the newest snapshot with the network interface `nic`, only recorded on Linux.
+    #[tracing::instrument(skip(self))]
+    pub fn nic_last_seen(&self, nic: &str) -> Option<DateTime<Utc>> {
+        let result = self.last_seen(|snap| snap.nic_info.contains_key(nic));
+        debug!(last_seen = ?result);
+        result
+    }
+
+    /// Copy of the database only keeping the snapshots taken between `from` and `to` (both included).
+    #[tracing::instrument(skip(self))]
+    pub fn filter_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        let snapshots = self
+            .snapshots
+            .iter()
+            .filter(|snap| (from..=to).contains(&snap.time))
+            .cloned()
+            .collect::<Vec<_>>();
+        let annotations = self
+            .annotations
+            .iter()
+            .filter(|annotation| (from..=to).contains(&annotation.time))
+            .cloned()
+            .collect::<Vec<_>>();
+        debug!("Kept {} snapshots between {from} and {to}", snapshots.len());
+
+        Self {
+            version: self.version.clone(),
+            hostname: self.hostname.clone(),
+            snapshots,
+            annotations,
+            key: None,
+            checksums: self.checksums,
+            skipped_records: self.skipped_records,
+            previous_facts: None,
+            last_write: self.last_write.clone(),
+            writer: None,
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn get_cpu_usage(&self) -> Vec<(f64, DateTime<Utc>)> {
+        let result = self
+            .whole_series(MetricKind::CpuPercent)
+            .map(|(time, usage)| (usage, time))
+            .collect::<Vec<_>>();
+
+        debug!(cpu_usage_percentages = ?result);
+        result
+    }
+
+    /// Per interval percentages of (busy, iowait, steal, idle) CPU time.
+    #[tracing::instrument(skip(self))]
+    #[allow(clippy::type_complexity)]
+    pub fn get_cpu_breakdown(&self) -> Vec<((f64, Option<f64>, Option<f64>, f64), DateTime<Utc>)> {
+        let cpus_times = rates::cpu_samples(&self.snapshots, SnapShot::get_cpu_time_breakdown);
+
+        let result = rates::per_interval(
+            &cpus_times,
+            |(previous_busy, previous_iowait, previous_steal, previous_idle),
+             (busy, iowait, steal, idle),
+             _elapsed| {
+                let optional_delta =
+                    |previous: &Option<f64>, current: &Option<f64>| match (previous, current) {
+                        (Some(previous), Some(current)) => {
+                            rates::counter_delta(*previous, *current).map(Some)
+                        }
+                        _ => Some(None),
+                    };
+                let busy = rates::counter_delta(*previous_busy, *busy)?;
+                let iowait = optional_delta(previous_iowait, iowait)?;
+                let steal = optional_delta(previous_steal, steal)?;
+                let idle = rates::counter_delta(*previous_idle, *idle)?;
+
+                let total = busy + iowait.unwrap_or_default() + steal.unwrap_or_default() + idle;
+                if total <= 0.0 {
+                    return None;
+                }
+                let to_percentage = |time: f64| time / total * 100.0;
+
+                Some((
+                    to_percentage(busy),
+                    iowait.map(to_percentage),
+                    steal.map(to_percentage),
+                    to_percentage(idle),
+                ))
+            },
+        );
+
+        debug!(cpu_breakdown_percentages = ?result);
+        result
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn get_ram_usage(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
+        // NOTE: Both are read from every snapshot so the series are aligned
+        let result = self
+            .whole_series(MetricKind::RamPercent)
+            .zip(self.whole_series(MetricKind::SwapPercent))
+            .map(|((time, ram), (_, swap))| ((ram, swap), time))
+            .collect::<Vec<_>>();
+
+        debug!(ram_usage_percentages = ?result);
+        result
+    }
+
+    /// Current CPU frequency in percent of the max one, a dip while the CPU is busy meaning throttling.
+    ///
+    /// Empty when the platform doesn't report the CPU frequency.
+    #[tracing::instrument(skip(self))]
+    pub fn get_cpu_frequency(&self) -> Vec<(f64, DateTime<Utc>)> {
+        let result = self
+            .whole_series(MetricKind::CpuFrequencyPercent)
+            .map(|(time, frequency)| (frequency, time))
+            .collect::<Vec<_>>();
+
+        debug!(cpu_frequency_percentages = ?result);
+        result
+    }
+
+    /// Milliseconds taken to collect each snapshot, snapshots taken before it was recorded are skipped.
+    #[tracing::instrument(skip(self))]
+    pub fn get_collection_durations(&self) -> Vec<(f64, DateTime<Utc>)> {
+        let result = self
+            .whole_series(MetricKind::CollectionDurationMs)
+            .map(|(time, duration)| (duration, time))
+            .collect::<Vec<_>>();
+
+        debug!(collection_durations = ?result);
+        result
+    }
+
+    /// Each kernel gauge with a max in percent of it as (name, values), in the order of `KERNEL_GAUGES`.
+    ///
+    /// Gauges missing from every snapshot are left out, the others only have values for the snapshots
+    /// having them (e.g. conntrack since its module was loaded).
+    #[tracing::instrument(skip(self))]
+    pub fn get_kernel_gauges(&self) -> Vec<(&'static str, Values)> {
+        let result = KERNEL_GAUGES
+            .iter()
+            .map(|gauge| {
+                let values = self
+                    .whole_series(MetricKind::KernelGaugePercent {
+                        gauge: gauge.to_string(),
+                    })
+                    .map(|(time, percent)| (percent, time))
+                    .collect::<Vec<_>>();
+                (*gauge, values)
+            })
+            .filter(|(_, values)| !values.is_empty())
+            .collect::<Vec<_>>();
+
+        debug!(kernel_gauges = ?result);
+        result
+    }
+
+    /// Busiest direction of the network interfaces in percent of their link speed as (interface, values), the
+    /// interfaces with a known speed summed together under `None` unless `per_nic`.
+    ///
+    /// Virtual interfaces and snapshots taken before the link speeds were recorded have no speed, they are
+    /// only part of the raw rates of `get_network`.
+    #[tracing::instrument(skip(self))]
+    pub fn get_network_utilization_percent(&self, per_nic: bool) -> Vec<(Option<String>, Values)> {
+        let nics = if per_nic {
+            self.snapshots
+                .iter()
+                .flat_map(|snapshot| {
+                    snapshot
+                        .nic_info
+                        .iter()
+                        .filter(|(_, link)| link.speed_mbps.is_some())
+                        .map(|(name, _)| name)
+                })
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|name| Some(name.clone()))
+                .collect::<Vec<_>>()
+        } else {
+            vec![None]
+        };
+        let result = nics
+            .into_iter()
+            .map(|nic| {
+                let values = self
+                    .whole_series(MetricKind::NetUtilizationPercent { nic: nic.clone() })
+                    .map(|(time, percent)| (percent, time))
+                    .collect::<Vec<_>>();
+                (nic, values)
+            })
+            .filter(|(_, values)| !values.is_empty())
+            .collect::<Vec<_>>();
+
+        debug!(network_utilization = ?result);
+        result
+    }
+
+    /// Per interval rates of pages swapped (in, out) per second.
+    ///
+    /// Empty when the platform doesn't report swap activity.
+    #[tracing::instrument(skip(self))]
+    pub fn get_swap_activity_rate(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
+        // NOTE: An interval is dropped when either counter went backward
+        let swapped_out_rates = self
+            .whole_series(MetricKind::SwapOutRate)
+            .collect::<BTreeMap<_, _>>();
+        let result = self
+            .whole_series(MetricKind::SwapInRate)
+            .filter_map(|(time, swapped_in)| {
+                let swapped_out = swapped_out_rates.get(&time)?;
+                Some(((swapped_in, *swapped_out), time))
+            })
+            .collect::<Vec<_>>();
+
+        debug!(swap_activity_rate = ?result);
+        result
+    }
+
+    /// Load averages over (1, 5, 15) minutes in percent of the number of CPUs.
+    #[tracing::instrument(skip(self))]
+    pub fn get_load(&self) -> Vec<((f64, f64, f64), DateTime<Utc>)> {
+        let result = self
+            .whole_series(MetricKind::Load1)
+            .zip(self.whole_series(MetricKind::Load5))
+            .zip(self.whole_series(MetricKind::Load15))
+            .map(|(((time, one), (_, five)), (_, fifteen))| ((one, five, fifteen), time))
+            .collect::<Vec<_>>();
+
+        debug!(load_avg = ?result);
+        result
+    }
+
+    /// Bytes received and sent by the network interfaces.
+    #[tracing::instrument(skip(self))]
+    pub fn get_network(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
+        let result = self
+            .snapshots
+            .iter()
+            .map(|s| {
+                let (recv, sent) = s.get_network_usage();
+                ((recv, sent), s.time)
             })
             .collect::<Vec<_>>();
 
         debug!(network_usage = ?result);
         result
     }
+
+    /// Bytes read from and written to the disks.
+    #[tracing::instrument(skip(self))]
+    pub fn get_disks_speed_usage(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
+        let result = self
+            .snapshots
+            .iter()
+            .map(|s| {
+                let (read, written) = s.get_disk_speed_usage();
+                ((read as f64, written as f64), s.time)
+            })
+            .collect::<Vec<_>>();
+
+        debug!(disks_speed_usage = ?result);
+        result
+    }
+
+    /// Used and free bytes of every filesystem, snapshots taken before the bytes were recorded are skipped.
+    #[tracing::instrument(skip(self))]
+    pub fn get_disk_memory_usage(&self) -> Vec<((f64, f64), DateTime<Utc>)> {
+        let result = self
+            .snapshots
+            .iter()
+            .filter(|s| !s.disks_usage.is_empty())
+            .map(|s| {
+                let (used, free) = s.disks_usage.values().fold((0, 0), |(used, free), usage| {
+                    (used + usage.used, free + usage.free)
+                });
+                ((used as f64, free as f64), s.time)
+            })
+            .collect::<Vec<_>>();
+
+        debug!(disk_memory_usage = ?result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::mpsc};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::testing::{self, SnapshotBuilder};
+
+    /// Load of `bytes` on another thread, failing the test when it panics or hangs.
+    fn load(bytes: Vec<u8>) -> Result<Database> {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            sender.send(Database::load_database(&bytes[..], None)).ok();
+        });
+        receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the load panicked or did not end")
+    }
+
+    /// Whether the load of a damaged database noticed it.
+    fn noticed(loaded: &Result<Database>) -> bool {
+        loaded
+            .as_ref()
+            .map_or(true, |database| database.skipped_records() > 0)
+    }
+
+    /// Snapshots as compared by the tests, `SnapShot` having no `PartialEq`.
+    fn content(database: &Database) -> serde_json::Value {
+        serde_json::json!({
+            "version": database.version,
+            "hostname": database.hostname,
+            "snapshots": database.snapshots,
+            "annotations": database.annotations,
+            "last_write": database.last_write,
+        })
+    }
+
+    fn fixture(checksums: bool) -> Vec<u8> {
+        Database {
+            hostname: Some("fixture".to_string()),
+            snapshots: testing::snapshots(20, 10),
+            checksums,
+            ..Database::default()
+        }
+        .to_bytes()
+        .unwrap()
+    }
+
+    /// Counters of a snapshot added to the previous ones, as (seconds since the previous snapshot, busy, idle,
+    /// memory used, bytes received, bytes read, percent of the disk used, load, custom value).
+    type Sample = (i64, f64, f64, u64, u64, u64, f32, f64, Option<f64>);
+
+    fn sample() -> impl Strategy<Value = Sample> {
+        (
+            1i64..3600,
+            0.0..1e3,
+            0.0..1e3,
+            0..16 * 1024 * 1024 * 1024u64,
+            0..1024 * 1024 * 1024u64,
+            0..1024 * 1024 * 1024u64,
+            0.0f32..100.0,
+            0.0..64.0,
+            prop::option::of(-1e9..1e9),
+        )
+    }
+
+    prop_compose! {
+        fn database()(
+            samples in prop::collection::vec(sample(), 0..16),
+            hostname in prop::option::of("[a-z0-9-]{1,16}"),
+            checksums in any::<bool>(),
+        ) -> Database {
+            let (mut seconds, mut busy, mut idle, mut received, mut read) = (0, 0.0, 0.0, 0, 0);
+            let snapshots = samples
+                .into_iter()
+                .map(|(gap, busy_delta, idle_delta, used, received_delta, read_delta, disk, load, custom)| {
+                    seconds += gap;
+                    busy += busy_delta;
+                    idle += idle_delta;
+                    received += received_delta;
+                    read += read_delta;
+                    let builder = SnapshotBuilder::at(testing::time(seconds))
+                        .cpu(busy, idle)
+                        .memory(used, 16 * 1024 * 1024 * 1024)
+                        .network(received, received / 2)
+                        .disk(read, read / 2)
+                        .disk_usage(disk)
+                        .load(load);
+                    match custom {
+                        Some(value) => builder.custom("queue", value),
+                        None => builder,
+                    }
+                    .build()
+                })
+                .collect();
+            let mut database = Database {
+                hostname,
+                snapshots,
+                checksums,
+                ..Database::default()
+            };
+            // NOTE: Loading flags the suspensions, the generated database already holds them
+            database.mark_suspensions();
+            database
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn databases_round_trip(database in database()) {
+            let loaded = load(database.to_bytes().unwrap()).unwrap();
+            prop_assert_eq!(content(&loaded), content(&database));
+            prop_assert_eq!(loaded.checksums, database.checksums);
+            prop_assert_eq!(loaded.skipped_records(), 0);
+        }
+
+        #[test]
+        fn flipped_bits_of_checksummed_databases_are_noticed(
+            index in any::<prop::sample::Index>(),
+            bit in 0..8u8,
+        ) {
+            let mut bytes = fixture(true);
+            let position = index.index(bytes.len());
+            bytes[position] ^= 1 << bit;
+            prop_assert!(noticed(&load(bytes)), "flip of bit {} at byte {} went unnoticed", bit, position);
+        }
+
+        #[test]
+        fn flipped_bytes_never_panic(
+            flips in prop::collection::vec((any::<prop::sample::Index>(), 1..=u8::MAX), 1..8),
+            checksums in any::<bool>(),
+        ) {
+            let mut bytes = fixture(checksums);
+            for (index, mask) in flips {
+                let position = index.index(bytes.len());
+                bytes[position] ^= mask;
+            }
+            let loaded = load(bytes);
+            if checksums {
+                prop_assert!(noticed(&loaded));
+            }
+        }
+
+        #[test]
+        fn raw_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..1024)) {
+            load(bytes).ok();
+        }
+
+        #[test]
+        fn raw_records_never_panic(records in prop::collection::vec(any::<u8>(), 0..1024)) {
+            let mut bytes = integrity::MAGIC.to_vec();
+            bytes.push(1);
+            bytes.extend(records);
+            load(bytes).ok();
+        }
+    }
+
+    #[test]
+    fn truncated_databases_are_noticed() {
+        for checksums in [false, true] {
+            let bytes = fixture(checksums);
+            // NOTE: An empty file is a new database, it is not truncated
+            for len in (1..bytes.len()).step_by(3) {
+                let loaded = load(bytes[..len].to_vec());
+                assert!(
+                    noticed(&loaded),
+                    "database cut at {len} of {} bytes went unnoticed",
+                    bytes.len()
+                );
+                if !checksums {
+                    assert!(loaded.is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn databases_cut_between_records_are_noticed() {
+        let database = Database {
+            snapshots: testing::snapshots(3, 10),
+            checksums: true,
+            ..Database::default()
+        };
+        let bytes = database.to_bytes().unwrap();
+        let last_record = {
+            let mut two = database.clone();
+            two.snapshots.pop();
+            two.to_bytes().unwrap().len()
+        };
+
+        let loaded = load(bytes[..last_record].to_vec()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.skipped_records(), 1);
+    }
+
+    #[test]
+    fn garbage_versions_are_errors() {
+        for version in ["", "not a version", "1.2", "99999999999999999999.0.0"] {
+            let bytes = Database {
+                version: version.to_string(),
+                snapshots: testing::snapshots(2, 10),
+                ..Database::default()
+            }
+            .to_bytes()
+            .unwrap();
+            assert!(
+                matches!(load(bytes), Err(Error::CorruptDatabase(_))),
+                "{version:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cpu_breakdowns_with_steal_add_up_to_the_whole() {
+        // NOTE: An oversold VPS, a fifth of the time of its two CPUs being stolen
+        let snapshots = (0..10)
+            .map(|idx| {
+                let seconds = f64::from(idx) * 10.0;
+                SnapshotBuilder::at(testing::time(idx.into()))
+                    .cpus(&[(seconds * 0.4, seconds * 0.3); 2])
+                    .waits(seconds * 0.1, seconds * 0.2)
+                    .build()
+            })
+            .collect();
+        let database = Database {
+            snapshots,
+            ..Database::default()
+        };
+
+        let breakdown = database.get_cpu_breakdown();
+
+        assert_eq!(breakdown.len(), 9);
+        for ((busy, iowait, steal, idle), _) in breakdown {
+            let (iowait, steal) = (iowait.unwrap_or_default(), steal.unwrap_or_default());
+            assert!(busy + iowait + steal + idle <= 100.0 + 1e-9);
+            assert!((busy - 40.0).abs() < 1e-6, "{busy}");
+            assert!((idle - 30.0).abs() < 1e-6, "{idle}");
+            if cfg!(target_os = "linux") {
+                assert!((iowait - 10.0).abs() < 1e-6, "{iowait}");
+                assert!((steal - 20.0).abs() < 1e-6, "{steal}");
+            }
+        }
+    }
+
+    #[test]
+    fn kernel_gauges_start_where_they_appear() {
+        // NOTE: The conntrack module loaded halfway, threads without a max on this kernel
+        let snapshots = (0..6)
+            .map(|idx| {
+                let snapshot = SnapshotBuilder::at(testing::time(idx * 60))
+                    .kernel("entropy", 256.0, Some(4096.0))
+                    .kernel("threads", 500.0, None);
+                if idx >= 3 {
+                    snapshot.kernel("conntrack", 50.0 * idx as f64, Some(1000.0))
+                } else {
+                    snapshot
+                }
+                .build()
+            })
+            .collect();
+        let database = Database {
+            snapshots,
+            ..Database::default()
+        };
+
+        let gauges = database.get_kernel_gauges();
+
+        assert_eq!(
+            gauges,
+            [
+                (
+                    "entropy",
+                    (0..6)
+                        .map(|idx| (6.25, testing::time(idx * 60)))
+                        .collect::<Vec<_>>()
+                ),
+                (
+                    "conntrack",
+                    vec![
+                        (15.0, testing::time(180)),
+                        (20.0, testing::time(240)),
+                        (25.0, testing::time(300)),
+                    ]
+                ),
+            ]
+        );
+        let without = Database {
+            snapshots: testing::snapshots(3, 60),
+            ..Database::default()
+        };
+        assert!(without.get_kernel_gauges().is_empty());
+    }
+
+    #[test]
+    fn network_utilizations_are_per_link_or_summed() {
+        use crate::psutil::NicInfo;
+
+        // NOTE: eth0 of 8 Mbit/s receiving 100kB/s, eth1 of 80 Mbit/s sending 300kB/s and a virtual bridge
+        let snapshots = (0..3u64)
+            .map(|idx| {
+                let mut snapshot = SnapshotBuilder::at(testing::time(idx as i64 * 10)).build();
+                let link = |speed_mbps, bytes_recv, bytes_sent| NicInfo {
+                    speed_mbps,
+                    up: true,
+                    bytes_recv,
+                    bytes_sent,
+                };
+                snapshot.nic_info = HashMap::from([
+                    ("eth0".to_string(), link(Some(8), 1_000_000 * idx, 0)),
+                    ("eth1".to_string(), link(Some(80), 0, 3_000_000 * idx)),
+                    ("br0".to_string(), link(None, 0, 50_000_000 * idx)),
+                ]);
+                snapshot
+            })
+            .collect();
+        let database = Database {
+            snapshots,
+            ..Database::default()
+        };
+        let percents = |per_nic| {
+            database
+                .get_network_utilization_percent(per_nic)
+                .into_iter()
+                .map(|(nic, values)| {
+                    let percents = values
+                        .into_iter()
+                        .map(|(percent, _)| (percent * 1000.0).round() / 1000.0)
+                        .collect::<Vec<_>>();
+                    (nic, percents)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            percents(true),
+            [
+                (Some("eth0".to_string()), vec![10.0, 10.0]),
+                (Some("eth1".to_string()), vec![3.0, 3.0]),
+            ]
+        );
+        // NOTE: 400kB/s out of 11MB/s
+        assert_eq!(percents(false), [(None, vec![3.636, 3.636])]);
+    }
+
+    /// Snapshot `seconds` after `time(0)` on a host whose counters grew at a steady 1000 bytes per second for
+    /// `elapsed` seconds, its boot time read against the same clock.
+    fn stamped(seconds: i64, elapsed: u64) -> SnapShot {
+        SnapShot {
+            host_facts: Some(HostFacts {
+                kernel: "6.6.8".to_string(),
+                os: None,
+                // NOTE: Derived from the uptime, it moves along with the clock
+                boot_time: Some(testing::time(seconds - elapsed as i64 - 600)),
+                boot_id: Some("boot".to_string()),
+            }),
+            ..SnapshotBuilder::at(testing::time(seconds))
+                .network(1000 * elapsed, 1000 * elapsed)
+                .disk(1000 * elapsed, 0)
+                .build()
+        }
+    }
+
+    fn times(database: &Database) -> Vec<DateTime<Utc>> {
+        database
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.time)
+            .collect()
+    }
+
+    /// Rates of the database are the steady 1000 bytes per second, whatever the clock did.
+    fn assert_steady_rates(database: &Database) {
+        for kind in [
+            MetricKind::NetRxRate,
+            MetricKind::NetTxRate,
+            MetricKind::DiskReadRate,
+        ] {
+            let rates = database.whole_series(kind.clone()).collect::<Vec<_>>();
+            assert!(!rates.is_empty(), "{kind:?}");
+            for (time, rate) in rates {
+                assert!((rate - 1000.0).abs() < 1e-6, "{kind:?} of {rate} at {time}");
+            }
+        }
+    }
+
+    #[test]
+    fn backward_jumps_are_clamped_and_flagged() {
+        let mut database = Database::default();
+        // NOTE: NTP steps the clock back 40 minutes after the third snapshot
+        for (seconds, elapsed) in [
+            (0, 0),
+            (60, 60),
+            (120, 120),
+            (-2160, 180),
+            (-2100, 240),
+            (-2040, 300),
+        ] {
+            database.push_snapshot(stamped(seconds, elapsed));
+        }
+
+        let times = times(&database);
+        assert!(times.windows(2).all(|pair| pair[0] < pair[1]), "{times:?}");
+        assert_eq!(times[3], testing::time(121));
+        assert_eq!(
+            database
+                .snapshots
+                .iter()
+                .map(|snapshot| snapshot.clock_jump)
+                .collect::<Vec<_>>(),
+            [false, false, false, true, true, true]
+        );
+        assert_eq!(
+            database
+                .annotations
+                .iter()
+                .map(|annotation| (annotation.kind, annotation.text.as_str()))
+                .collect::<Vec<_>>(),
+            [(AnnotationKind::ClockJump, "clock went back by 39 min")]
+        );
+        assert_steady_rates(&database);
+    }
+
+    #[test]
+    fn forward_jumps_are_flagged() {
+        let mut database = Database::default();
+        // NOTE: The clock is set 3 hours ahead after the third snapshot
+        for (seconds, elapsed) in [(0, 0), (60, 60), (120, 120), (10980, 180), (11040, 240)] {
+            database.push_snapshot(stamped(seconds, elapsed));
+        }
+
+        assert_eq!(
+            times(&database),
+            [0, 60, 120, 10980, 11040].map(testing::time)
+        );
+        assert_eq!(
+            database
+                .snapshots
+                .iter()
+                .map(|snapshot| snapshot.clock_jump)
+                .collect::<Vec<_>>(),
+            [false, false, false, true, false]
+        );
+        assert_eq!(
+            database
+                .annotations
+                .iter()
+                .map(|annotation| (annotation.kind, annotation.text.as_str()))
+                .collect::<Vec<_>>(),
+            [(AnnotationKind::ClockJump, "clock went forward by 180 min")]
+        );
+        assert_steady_rates(&database);
+    }
+
+    #[test]
+    fn snapshots_out_of_order_are_normalized_on_load() {
+        // NOTE: Written before the clock jumps were handled
+        let database = Database {
+            snapshots: [
+                (0, 0),
+                (60, 60),
+                (120, 120),
+                (-2160, 180),
+                (-2100, 240),
+                (300, 300),
+            ]
+            .into_iter()
+            .map(|(seconds, elapsed)| stamped(seconds, elapsed))
+            .collect(),
+            ..Database::default()
+        };
+
+        let loaded = load(database.to_bytes().unwrap()).unwrap();
+
+        let times = times(&loaded);
+        assert_eq!(
+            times,
+            [0, 60, 120, 121, 122, 300].map(testing::time),
+            "{times:?}"
+        );
+        assert_eq!(
+            loaded
+                .snapshots
+                .iter()
+                .map(|snapshot| snapshot.clock_jump)
+                .collect::<Vec<_>>(),
+            [false, false, false, true, true, false]
+        );
+        let mut normalized = loaded;
+        assert_eq!(normalized.normalize_times(), 0);
+    }
+
+    fn writer() -> Writer {
+        Writer {
+            name: "sysmet-update".to_string(),
+            version: "0.3.1".to_string(),
+            commit: "abc1234".to_string(),
+        }
+    }
+
+    #[test]
+    fn last_writes_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for checksums in [false, true] {
+            let path = dir.path().join(format!("{checksums}.db"));
+            let path = path.to_str().unwrap();
+            let mut database = Database {
+                snapshots: testing::snapshots(3, 60),
+                checksums,
+                ..Database::default()
+            };
+            database.set_writer(writer());
+
+            let before = Utc::now();
+            database.write_to_file(path).unwrap();
+            let loaded = Database::from_file(path, None).unwrap();
+
+            let last_write = loaded.last_write().unwrap();
+            assert_eq!(last_write.writer, writer());
+            assert!(last_write.time >= before && last_write.time <= Utc::now());
+            assert_eq!(
+                last_write.to_string(),
+                format!("sysmet-update 0.3.1 (abc1234) at {}", last_write.time)
+            );
+        }
+    }
+
+    #[test]
+    fn writes_without_a_writer_keep_the_last_write() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db");
+        let path = path.to_str().unwrap();
+        let mut database = Database {
+            snapshots: testing::snapshots(3, 60),
+            ..Database::default()
+        };
+        database.set_writer(writer());
+        database.write_to_file(path).unwrap();
+        let written = database.last_write().cloned();
+
+        // NOTE: e.g. a database written back by sysmet-http
+        let mut loaded = Database::from_file(path, None).unwrap();
+        loaded.write_to_file(path).unwrap();
+
+        assert_eq!(
+            Database::from_file(path, None).unwrap().last_write(),
+            written.as_ref()
+        );
+    }
+
+    /// Database of `count` snapshots written to `dir`, opened for writing as (database, file, path, content).
+    fn opened_for_write(
+        dir: &tempfile::TempDir,
+        count: usize,
+    ) -> (Database, File, PathBuf, Vec<u8>) {
+        let path = dir.path().join("sysmet.db");
+        let path = path.to_str().unwrap();
+        Database {
+            snapshots: testing::snapshots(count, 60),
+            ..Database::default()
+        }
+        .write_to_file(path)
+        .unwrap();
+        let content = std::fs::read(path).unwrap();
+        let (database, file, path) = Database::from_file_with_write(path, None).unwrap();
+        (database, file, path, content)
+    }
+
+    fn restoring(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.restoring", path.display()))
+    }
+
+    #[test]
+    fn verified_writes_keep_what_was_read_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (mut database, file, path, _) = opened_for_write(&dir, 3);
+        database.push_snapshot(SnapshotBuilder::at(testing::time(180)).build());
+
+        database.write_verified(file, &path).unwrap();
+
+        let written = Database::from_file(path.to_str().unwrap(), None).unwrap();
+        assert_eq!(written.snapshots.len(), 4);
+        assert!(!restoring(&path).exists());
+        // NOTE: The lock is released
+        assert!(Database::from_file_with_write(path.to_str().unwrap(), None).is_ok());
+    }
+
+    #[test]
+    fn short_writes_are_rolled_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (mut database, file, path, before) = opened_for_write(&dir, 10);
+        database.push_snapshot(SnapshotBuilder::at(testing::time(600)).build());
+
+        // NOTE: Half of the content, e.g. a full disk
+        let err = database
+            .write_verified_with(file, &path, |database, mut file| {
+                let bytes = database.to_bytes()?;
+                file.write_all(&bytes[..bytes.len() / 2])
+                    .map_err(Error::FailedToWriteFile)?;
+                file.set_len(bytes.len() as u64 / 2)
+                    .map_err(Error::FailedToWriteFile)
+            })
+            .unwrap_err();
+
+        assert!(matches!(err, Error::WriteVerificationFailed(_)), "{err}");
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert!(!restoring(&path).exists());
+        assert_eq!(
+            Database::from_file_with_write(path.to_str().unwrap(), None)
+                .unwrap()
+                .0
+                .snapshots
+                .len(),
+            10
+        );
+    }
+
+    #[test]
+    fn failed_writes_are_rolled_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (mut database, file, path, before) = opened_for_write(&dir, 10);
+
+        // NOTE: Garbage written before the writer gave up
+        let err = database
+            .write_verified_with(file, &path, |_, mut file| {
+                file.write_all(b"garbage").unwrap();
+                Err(Error::FailedToWriteFile(std::io::Error::other(
+                    "No space left on device",
+                )))
+            })
+            .unwrap_err();
+
+        assert!(err.to_string().contains("No space left on device"), "{err}");
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert!(!restoring(&path).exists());
+        assert!(!PathBuf::from(format!("{}.lock", path.display())).exists());
+    }
+
+    #[test]
+    fn snapshots_spilled_while_locked_are_merged_in_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db");
+        let path = path.to_str().unwrap();
+        Database {
+            snapshots: testing::snapshots(5, 60),
+            ..Database::default()
+        }
+        .write_to_file(path)
+        .unwrap();
+
+        // NOTE: Held by another process, e.g. a long read of sysmet-http
+        let lockfile = PathBuf::from(format!("{path}.lock"));
+        File::create(&lockfile).unwrap();
+        assert!(matches!(
+            Database::from_file_with_write(path, None),
+            Err(Error::LockFileTimeout(_))
+        ));
+        // NOTE: Spilled out of order, the last one already being in the database
+        for seconds in [420, 300, 360, 240] {
+            let snapshot = SnapshotBuilder::at(testing::time(seconds))
+                .cpu(seconds as f64 / 2.0, seconds as f64 / 2.0)
+                .build();
+            Database::spill_snapshot(path, &snapshot, None).unwrap();
+        }
+        let pending = pending_dir(Path::new(path));
+        std::fs::write(pending.join("corrupt.snapshot"), b"garbage").unwrap();
+        std::fs::write(pending.join("being-written"), b"").unwrap();
+        std::fs::remove_file(&lockfile).unwrap();
+
+        let (mut database, file, path) = Database::from_file_with_write(path, None).unwrap();
+        let merge = database.merge_pending(&path, None).unwrap();
+        database.write_and_close_file(file, &path).unwrap();
+        merge.remove_files().unwrap();
+
+        assert_eq!(
+            (merge.merged, merge.duplicates, merge.files.len()),
+            (3, 1, 4)
+        );
+        let written = Database::from_file(path.to_str().unwrap(), None).unwrap();
+        let times = written
+            .snapshots()
+            .iter()
+            .map(|snapshot| snapshot.time)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            (0..8)
+                .map(|idx| testing::time(idx * 60))
+                .collect::<Vec<_>>()
+        );
+        // NOTE: The corrupt entry is removed, the one another run may still write is left alone
+        let left = read_dir(&pending)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(left, ["being-written"]);
+    }
+
+    #[test]
+    fn encrypted_spills_wait_for_their_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db");
+        let key = DatabaseKey::new("passphrase").unwrap();
+        let snapshot = SnapshotBuilder::at(testing::time(0)).build();
+        Database::spill_snapshot(path.to_str().unwrap(), &snapshot, Some(&key)).unwrap();
+
+        let mut database = Database::default();
+        let without_key = database.merge_pending(&path, None).unwrap();
+        assert_eq!(without_key, PendingMerge::default());
+        assert_eq!(read_dir(pending_dir(&path)).unwrap().count(), 1);
+
+        let with_key = database.merge_pending(&path, Some(&key)).unwrap();
+        assert_eq!(with_key.merged, 1);
+        assert_eq!(database.snapshots()[0].time, testing::time(0));
+    }
+
+    #[test]
+    fn backdated_snapshots_are_inserted_in_time_order() {
+        let mut database = Database::default();
+        for seconds in [0, 60, 180, 240] {
+            database.push_snapshot(
+                SnapshotBuilder::at(testing::time(seconds))
+                    .cpu(seconds as f64 / 2.0, seconds as f64 / 2.0)
+                    .network(seconds as u64 * 100, 0)
+                    .build(),
+            );
+        }
+        let collectors = CollectorRegistry::default();
+
+        database
+            .take_snapshot_at(testing::time(120), &[], &collectors, false)
+            .unwrap();
+
+        let times = database
+            .snapshots()
+            .iter()
+            .map(|snapshot| snapshot.time)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            (0..5)
+                .map(|idx| testing::time(idx * 60))
+                .collect::<Vec<_>>()
+        );
+        // NOTE: Its counters are read now, the intervals on both sides are not trusted
+        let jumps = database
+            .snapshots()
+            .iter()
+            .map(|snapshot| snapshot.clock_jump)
+            .collect::<Vec<_>>();
+        assert_eq!(jumps, [false, false, true, true, false]);
+        for metric in [MetricKind::RamPercent, MetricKind::NetRxRate] {
+            let points = database
+                .series(metric.clone(), testing::time(0)..testing::time(300), None)
+                .map(|(time, _)| time)
+                .collect::<Vec<_>>();
+            assert!(
+                points.windows(2).all(|pair| pair[0] < pair[1]),
+                "{metric:?}: {points:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn snapshots_are_not_inserted_over_another_one() {
+        let mut database = Database::default();
+        for snapshot in testing::snapshots(3, 60) {
+            database.push_snapshot(snapshot);
+        }
+
+        let taken = database.insert_snapshot(SnapshotBuilder::at(testing::time(60)).build());
+
+        assert!(matches!(taken, Err(Error::SnapshotTimeTaken(time)) if time == testing::time(60)));
+        assert_eq!(database.snapshots().len(), 3);
+        // NOTE: After the last one it is appended as any new snapshot
+        let index = database
+            .insert_snapshot(SnapshotBuilder::at(testing::time(180)).build())
+            .unwrap();
+        assert_eq!(index, 3);
+        let index = database
+            .insert_snapshot(SnapshotBuilder::at(testing::time(-60)).build())
+            .unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(database.snapshots()[4].time, testing::time(180));
+    }
+
+    /// Snapshots 10, 5 and 1 days ago, the custom metric `old` only recorded by the first one, with an alert 9
+    /// days ago and a reboot 4 days ago.
+    fn spanning_the_cutoff() -> Database {
+        let now = Utc::now();
+        let day = |days: i64| now - chrono::Duration::days(days);
+        Database {
+            snapshots: [10, 5, 1]
+                .into_iter()
+                .map(|days| {
+                    let builder = SnapshotBuilder::at(day(days)).custom("queue", 1.0);
+                    if days == 10 {
+                        builder.custom("old", 2.0)
+                    } else {
+                        builder
+                    }
+                    .build()
+                })
+                .collect(),
+            annotations: vec![
+                Annotation {
+                    time: day(9),
+                    kind: AnnotationKind::Alert,
+                    text: "cpu above 90%".to_string(),
+                },
+                Annotation {
+                    time: day(4),
+                    kind: AnnotationKind::Reboot,
+                    text: "rebooted".to_string(),
+                },
+            ],
+            ..Database::default()
+        }
+    }
+
+    #[test]
+    fn retention_prunes_every_collection() {
+        let mut database = spanning_the_cutoff();
+        assert_eq!(
+            database.collection_counts(),
+            CollectionCounts {
+                snapshots: 3,
+                annotations: 2,
+                alerts: 1,
+                custom_keys: 2,
+            }
+        );
+
+        let pruned = database.remove_older(7, false).unwrap();
+
+        assert_eq!(
+            pruned,
+            Pruned {
+                snapshots: 1,
+                annotations: 1,
+                custom_keys: vec!["old".to_string()],
+            }
+        );
+        assert_eq!(
+            database.collection_counts(),
+            CollectionCounts {
+                snapshots: 2,
+                annotations: 1,
+                alerts: 0,
+                custom_keys: 1,
+            }
+        );
+        assert_eq!(database.annotations[0].text, "rebooted");
+        assert_eq!(
+            pruned.to_string(),
+            "1 snapshots and 1 annotations removed, custom metrics no longer recorded: old"
+        );
+    }
+
+    #[test]
+    fn kept_annotations_are_only_removed_by_a_vacuum() {
+        let mut database = spanning_the_cutoff();
+
+        let pruned = database.remove_older(7, true).unwrap();
+
+        assert_eq!((pruned.snapshots, pruned.annotations), (1, 0));
+        assert_eq!(pruned.custom_keys, ["old"]);
+        assert_eq!(database.collection_counts().alerts, 1);
+
+        let vacuumed = database.vacuum();
+
+        assert_eq!(vacuumed.annotations, 1);
+        assert_eq!(
+            database
+                .annotations
+                .iter()
+                .map(|annotation| annotation.kind)
+                .collect::<Vec<_>>(),
+            [AnnotationKind::Reboot]
+        );
+        // NOTE: Nothing older than the oldest snapshot is left
+        assert_eq!(database.vacuum(), Pruned::default());
+    }
+
+    #[test]
+    fn dropping_the_oldest_snapshots_drops_their_annotations() {
+        let mut database = spanning_the_cutoff();
+
+        database.drop_oldest(2);
+
+        assert_eq!(database.snapshots.len(), 1);
+        assert!(database.annotations.is_empty());
+        database.drop_oldest(5);
+        assert_eq!(database.collection_counts(), CollectionCounts::default());
+    }
+
+    #[test]
+    fn snapshots_without_bytes_keep_their_percent_among_newer_ones() {
+        let database = Database {
+            snapshots: [40.0, 41.0, 42.0, 43.0]
+                .into_iter()
+                .zip(0..)
+                .map(|(percent, idx)| {
+                    SnapshotBuilder::at(testing::time(idx * 60))
+                        .disk_usage(percent)
+                        .build()
+                })
+                .collect(),
+            ..Database::default()
+        };
+        // NOTE: The first two as written before the bytes were recorded
+        let mut value = ciborium::value::Value::serialized(&database).unwrap();
+        let ciborium::value::Value::Map(fields) = &mut value else {
+            panic!("{value:?}");
+        };
+        let (_, ciborium::value::Value::Array(snapshots)) = fields
+            .iter_mut()
+            .find(|(key, _)| key.as_text() == Some("snapshots"))
+            .unwrap()
+        else {
+            panic!("{value:?}");
+        };
+        for snapshot in &mut snapshots[..2] {
+            let ciborium::value::Value::Map(snapshot_fields) = snapshot else {
+                panic!("{snapshot:?}");
+            };
+            let len = snapshot_fields.len();
+            snapshot_fields.retain(|(key, _)| key.as_text() != Some("disks_usage"));
+            assert_eq!(snapshot_fields.len(), len - 1);
+        }
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&value, &mut bytes).unwrap();
+
+        let loaded = load(bytes).unwrap();
+
+        assert_eq!(loaded.len(), 4);
+        for (snapshot, percent) in loaded.snapshots().iter().zip([40.0, 41.0, 42.0, 43.0]) {
+            assert_eq!(
+                snapshot.get_disks_size_usage(),
+                [(testing::MOUNTPOINT.to_string(), percent)]
+            );
+        }
+        assert_eq!(loaded.snapshots()[1].get_disks_bytes_usage(), []);
+        let newer = &database.snapshots()[2..];
+        assert_eq!(
+            loaded.snapshots()[2..]
+                .iter()
+                .map(SnapShot::get_disks_bytes_usage)
+                .collect::<Vec<_>>(),
+            newer
+                .iter()
+                .map(SnapShot::get_disks_bytes_usage)
+                .collect::<Vec<_>>()
+        );
+        // NOTE: Only the newer snapshots are charted in bytes, the older ones having none
+        assert_eq!(
+            loaded.get_disk_memory_usage(),
+            newer
+                .iter()
+                .map(|snapshot| {
+                    let usage = snapshot.disks_usage[testing::MOUNTPOINT];
+                    ((usage.used as f64, usage.free as f64), snapshot.time)
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn databases_without_a_last_write_load_unchanged() {
+        let database = Database {
+            snapshots: testing::snapshots(3, 60),
+            ..Database::default()
+        };
+        // NOTE: As written before the last write was recorded
+        let mut value = ciborium::value::Value::serialized(&database).unwrap();
+        let ciborium::value::Value::Map(fields) = &mut value else {
+            panic!("{value:?}");
+        };
+        fields.retain(|(key, _)| key.as_text() != Some("last_write"));
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&value, &mut bytes).unwrap();
+
+        let loaded = load(bytes).unwrap();
+
+        assert_eq!(loaded.last_write(), None);
+        assert_eq!(content(&loaded), content(&database));
+    }
+
+    #[test]
+    fn pushed_snapshots_stay_sorted_whatever_their_times() {
+        let mut database = Database::default();
+        assert_eq!(database.time_range(), None);
+
+        // NOTE: A snapshot at the time of the last one, then one older than every other
+        for seconds in [0, 60, 60, -600, 120] {
+            database.push_snapshot(SnapshotBuilder::at(testing::time(seconds)).build());
+        }
+
+        assert_eq!(database.len(), 5);
+        assert_eq!(times(&database), [0, 60, 61, 62, 120].map(testing::time));
+        assert_eq!(
+            database.time_range(),
+            Some((testing::time(0), testing::time(120)))
+        );
+        assert_eq!(
+            database
+                .snapshots()
+                .iter()
+                .map(|snapshot| snapshot.clock_jump)
+                .collect::<Vec<_>>(),
+            [false, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn snapshots_pages_stop_at_the_newest_and_the_max() {
+        let database = Database {
+            snapshots: testing::snapshots(MAX_SNAPSHOTS_PAGE + 10, 10),
+            ..Database::default()
+        };
+        let page = |offset, limit| {
+            database
+                .snapshots_page(offset, limit)
+                .iter()
+                .map(|snapshot| snapshot.time)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(page(2, 3), [20, 30, 40].map(testing::time));
+        assert_eq!(page(MAX_SNAPSHOTS_PAGE + 8, 10).len(), 2);
+        assert!(page(MAX_SNAPSHOTS_PAGE + 10, 10).is_empty());
+        assert!(page(usize::MAX, usize::MAX).is_empty());
+        assert_eq!(page(0, usize::MAX).len(), MAX_SNAPSHOTS_PAGE);
+        assert!(page(0, 0).is_empty());
+    }
+
+    #[test]
+    fn snapshots_keep_their_serialized_name() {
+        let database = Database {
+            hostname: Some("fixture".to_string()),
+            snapshots: testing::snapshots(3, 10),
+            ..Database::default()
+        };
+
+        let serialized = serde_json::to_value(&database).unwrap();
+
+        assert_eq!(serialized["snapshots"].as_array().unwrap().len(), 3);
+        let deserialized: Database = serde_json::from_value(serialized).unwrap();
+        assert_eq!(content(&deserialized), content(&database));
+        let loaded = load(database.to_bytes().unwrap()).unwrap();
+        assert_eq!(content(&loaded), content(&database));
+    }
+
+    /// Snapshots every minute with `/data` mounted until `time(60)` and `eth1` up until `time(120)`.
+    fn disappearing() -> Database {
+        let mut database = Database::default();
+        for idx in 0..3 {
+            let mut snapshot = SnapshotBuilder::at(testing::time(idx * 60)).build();
+            if idx < 2 {
+                let root = snapshot.disks_usage[testing::MOUNTPOINT];
+                snapshot.disks_memory.insert("/data".to_string(), 10.0);
+                snapshot.disks_usage.insert("/data".to_string(), root);
+            }
+            snapshot.nic_info = HashMap::from([(
+                "eth1".to_string(),
+                crate::psutil::NicInfo {
+                    speed_mbps: None,
+                    up: true,
+                    bytes_recv: 0,
+                    bytes_sent: 0,
+                },
+            )]);
+            database.push_snapshot(snapshot);
+        }
+        database
+    }
+
+    #[test]
+    fn items_never_seen_have_no_last_time() {
+        let database = disappearing();
+
+        assert_eq!(database.mount_last_seen("/backup"), None);
+        assert_eq!(database.nic_last_seen("wlan0"), None);
+        assert_eq!(Database::default().mount_last_seen("/"), None);
+    }
+
+    #[test]
+    fn items_that_disappeared_were_last_seen_in_their_newest_snapshot() {
+        let database = disappearing();
+
+        assert_eq!(database.mount_last_seen("/data"), Some(testing::time(60)));
+        assert_eq!(
+            database.last_seen(|snap| snap.time < testing::time(30)),
+            Some(testing::time(0))
+        );
+    }
+
+    #[test]
+    fn items_still_present_are_seen_in_the_newest_snapshot() {
+        let database = disappearing();
+
+        assert_eq!(
+            database.mount_last_seen(testing::MOUNTPOINT),
+            Some(testing::time(120))
+        );
+        assert_eq!(database.nic_last_seen("eth1"), Some(testing::time(120)));
+    }
+
+    /// Snapshots every 10s of CPUs busy half of the time, 4 of them then 8 from `time(50)`, the CPUs added
+    /// having been busy far longer since boot.
+    fn resized() -> Database {
+        let mut database = Database::default();
+        for idx in 0..10 {
+            let seconds = 5.0 * idx as f64;
+            let mut cpus = vec![(seconds, seconds); 4];
+            if idx >= 5 {
+                cpus.extend([(900.0 + seconds, 100.0 + seconds); 4]);
+            }
+            database.push_snapshot(
+                SnapshotBuilder::at(testing::time(idx * 10))
+                    .cpus(&cpus)
+                    .build(),
+            );
+        }
+        database
+    }
+
+    #[test]
+    fn cpu_count_changes_are_annotated() {
+        let database = resized();
+
+        assert_eq!(
+            database.annotations,
+            vec![Annotation {
+                time: testing::time(50),
+                kind: AnnotationKind::CpuCount,
+                text: "CPU count changed 4 \u{2192} 8".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn cpu_count_changes_leave_no_spike() {
+        let database = resized();
+
+        let breakdown = database.get_cpu_breakdown();
+        let usages = database
+            .series(
+                MetricKind::CpuPercent,
+                testing::time(0)..testing::time(100),
+                None,
+            )
+            .collect::<Vec<_>>();
+
+        // NOTE: The interval ending at the resize is dropped, the others are half busy
+        assert_eq!(breakdown.len(), 8);
+        assert!(
+            breakdown.iter().all(|((busy, ..), time)| {
+                *time != testing::time(50) && (busy - 50.0).abs() < 1e-9
+            }),
+            "{breakdown:?}"
+        );
+        assert_eq!(usages.len(), 8);
+        assert!(
+            usages
+                .iter()
+                .all(|(time, usage)| { *time != testing::time(50) && (usage - 50.0).abs() < 1e-9 }),
+            "{usages:?}"
+        );
+    }
+
+    #[test]
+    fn snapshots_within_include_the_start_but_not_the_end() {
+        let database = Database {
+            snapshots: testing::snapshots(5, 10),
+            ..Database::default()
+        };
+        let times = |range: Range<DateTime<Utc>>| {
+            database
+                .snapshots_within(range)
+                .iter()
+                .map(|snapshot| snapshot.time)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            times(testing::time(10)..testing::time(30)),
+            [testing::time(10), testing::time(20)]
+        );
+        assert_eq!(
+            times(testing::time(5)..testing::time(11)),
+            [testing::time(10)]
+        );
+        assert_eq!(
+            times(DateTime::<Utc>::MIN_UTC..DateTime::<Utc>::MAX_UTC).len(),
+            5
+        );
+        assert!(times(testing::time(30)..testing::time(10)).is_empty());
+        assert!(times(testing::time(100)..testing::time(200)).is_empty());
+        assert_eq!(
+            database.indices_within(&(testing::time(30)..testing::time(10))),
+            3..3
+        );
+    }
 }
@@ -0,0 +1,482 @@
+//! Synthetic databases for the screenshots and the development, generated from seeded pseudo-random
+//! functions so the same options always give the same snapshots.
+use std::{collections::HashMap, f64::consts::TAU};
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use log::{debug, tracing};
+use serde_json::{json, Value};
+
+use crate::{
+    errors::Error,
+    prelude::*,
+    psutil::{CpuFreq, HostFacts, LoadAvg, NicInfo},
+    snapshot::DiskUsageSample,
+    Result,
+};
+
+pub const DEMO_HOSTNAME: &str = "demo";
+/// Seed of the demo databases when none is given, the screenshots of the README being taken with it.
+pub const DEFAULT_DEMO_SEED: u64 = 42;
+const CPUS: usize = 4;
+/// Share of the usage of the host taken by each CPU, the first ones being busier.
+const CPU_SKEW: [f64; CPUS] = [1.3, 1.1, 0.9, 0.7];
+const MEMORY_TOTAL: f64 = 16.0 * GIB;
+const SWAP_TOTAL: f64 = 4.0 * GIB;
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+/// Filesystems as (mountpoint, size in GiB, used fraction at the start, growth of the fraction per day).
+const FILESYSTEMS: [(&str, f64, f64, f64); 2] =
+    [("/", 256.0, 0.41, 0.004), ("/home", 1024.0, 0.63, 0.0015)];
+const NIC: &str = "eth0";
+const DISK: &str = "nvme0n1";
+/// Chance of a CPU spike on each snapshot.
+const SPIKE_CHANCE: f64 = 0.004;
+/// Chance of a network burst starting on each snapshot.
+const BURST_CHANCE: f64 = 0.01;
+/// Length of the simulated incident, starting two thirds into the range.
+const INCIDENT_MINUTES: i64 = 45;
+
+/// Range and seed of a demo database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DemoOptions {
+    /// Time of the newest snapshot.
+    pub end: DateTime<Utc>,
+    /// Time between the oldest and the newest snapshots.
+    pub span: Duration,
+    pub interval: Duration,
+    pub seed: u64,
+}
+
+/// SplitMix64, small and good enough for plausible looking noise.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = self.0;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+
+    /// Uniform between 0 (included) and 1 (excluded).
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Uniform between -1 and 1.
+    fn signed(&mut self) -> f64 {
+        self.unit() * 2.0 - 1.0
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+}
+
+/// Activity of the host at `time`, from 0 at 4am to 1 at 4pm.
+fn diurnal(time: DateTime<Utc>) -> f64 {
+    let hours = time.hour() as f64 + time.minute() as f64 / 60.0;
+    0.5 - 0.5 * (TAU * (hours - 4.0) / 24.0).cos()
+}
+
+/// Seconds spent by a CPU in each state since boot.
+#[derive(Debug, Default, Clone, Copy)]
+struct CpuCounters {
+    user: f64,
+    nice: f64,
+    system: f64,
+    iowait: f64,
+    irq: f64,
+    softirq: f64,
+    steal: f64,
+    idle: f64,
+}
+
+impl CpuCounters {
+    /// Add `seconds` of which `usage` (from 0 to 1) were busy and `iowait` waited for the disks.
+    fn add(&mut self, seconds: f64, usage: f64, iowait: f64) {
+        let busy = seconds * usage;
+        let iowait = (seconds * iowait).min(seconds - busy);
+        self.user += busy * 0.68;
+        self.system += busy * 0.24;
+        self.nice += busy * 0.02;
+        self.irq += busy * 0.01;
+        self.softirq += busy * 0.03;
+        self.steal += busy * 0.02;
+        self.iowait += iowait;
+        self.idle += seconds - busy - iowait;
+    }
+
+    fn to_value(self) -> Value {
+        json!({
+            "user": duration(self.user),
+            "system": duration(self.system),
+            "idle": duration(self.idle),
+            "nice": duration(self.nice),
+            "iowait": duration(self.iowait),
+            "irq": duration(self.irq),
+            "softirq": duration(self.softirq),
+            "steal": duration(self.steal),
+            "guest": null,
+            "guest_nice": null,
+        })
+    }
+}
+
+fn duration(seconds: f64) -> Value {
+    let duration = std::time::Duration::from_secs_f64(seconds.max(0.0));
+    json!({ "secs": duration.as_secs(), "nanos": duration.subsec_nanos() })
+}
+
+fn from_value<T: serde::de::DeserializeOwned>(value: Value) -> Result<T> {
+    serde_json::from_value(value).map_err(Error::DemoSnapshot)
+}
+
+/// Counters and smoothed values carried from a snapshot to the next.
+#[derive(Debug, Default)]
+struct DemoState {
+    cpus: [CpuCounters; CPUS],
+    cpu_noise: f64,
+    load: (f64, f64, f64),
+    swap_used: f64,
+    swapped: (f64, f64),
+    network: (f64, f64),
+    disk: (f64, f64),
+    /// Snapshots left in the current network burst and its factor.
+    burst: (usize, f64),
+}
+
+impl Database {
+    /// Database of a plausible host following `options`: diurnal CPU usage and load, slowly filling disks,
+    /// bursty network, occasional CPU spikes and an incident two thirds into the range, annotated as an alert.
+    #[tracing::instrument]
+    pub fn demo(options: &DemoOptions) -> Result<Self> {
+        let interval = options.interval.max(Duration::seconds(1));
+        let count = (options.span.num_seconds() / interval.num_seconds()).max(1) as usize + 1;
+        let start = options.end - interval * (count as i32 - 1);
+        let incident = count * 2 / 3
+            ..count * 2 / 3 + (INCIDENT_MINUTES * 60 / interval.num_seconds()).max(1) as usize;
+        let seconds = interval.num_seconds() as f64;
+
+        let mut rng = Rng(options.seed);
+        let mut state = DemoState::default();
+        let mut snapshots = Vec::with_capacity(count);
+        for idx in 0..count {
+            let time = start + interval * idx as i32;
+            let in_incident = incident.contains(&idx);
+            let activity = diurnal(time);
+
+            // NOTE: The noise is a random walk so consecutive values stay close
+            state.cpu_noise = state.cpu_noise * 0.8 + rng.signed() * 3.0;
+            let mut usage = 6.0 + 48.0 * activity + state.cpu_noise;
+            if rng.chance(SPIKE_CHANCE) {
+                usage += 35.0 + 20.0 * rng.unit();
+            }
+            if in_incident {
+                usage = 94.0 + 4.0 * rng.unit();
+            }
+            let usage = usage.clamp(1.0, 100.0) / 100.0;
+            let iowait = if in_incident { 0.06 } else { 0.01 };
+            for (counters, skew) in state.cpus.iter_mut().zip(CPU_SKEW) {
+                counters.add(seconds, (usage * skew).clamp(0.0, 1.0), iowait);
+            }
+
+            let runnable = CPUS as f64
+                * usage
+                * (1.0 + 0.1 * rng.signed())
+                * if in_incident { 1.6 } else { 1.0 };
+            let smooth = |average: f64, period: f64| {
+                average + (runnable - average) * (1.0 - (-seconds / period).exp())
+            };
+            state.load = (
+                smooth(state.load.0, 60.0),
+                smooth(state.load.1, 300.0),
+                smooth(state.load.2, 900.0),
+            );
+
+            let used = (0.32
+                + 0.18 * activity
+                + 0.02 * rng.signed()
+                + if in_incident { 0.35 } else { 0.0 })
+            .clamp(0.05, 0.97)
+                * MEMORY_TOTAL;
+            let buffers = 0.02 * MEMORY_TOTAL;
+            let cached = (MEMORY_TOTAL - used - buffers) * 0.5;
+            let free = MEMORY_TOTAL - used - buffers - cached;
+
+            // NOTE: Pages swapped out during the incident are only slowly swapped back in
+            let (swapping_in, swapping_out) = if in_incident {
+                (150.0 + 50.0 * rng.unit(), 300.0 + 100.0 * rng.unit())
+            } else {
+                (rng.unit() * 2.0, rng.unit())
+            };
+            state.swapped.0 += swapping_in * seconds;
+            state.swapped.1 += swapping_out * seconds;
+            state.swap_used = (state.swap_used + (swapping_out - swapping_in) * 4096.0 * seconds)
+                .clamp(0.02 * SWAP_TOTAL, 0.9 * SWAP_TOTAL);
+
+            if state.burst.0 == 0 && rng.chance(BURST_CHANCE) {
+                state.burst = (3 + (rng.unit() * 12.0) as usize, 10.0 + 30.0 * rng.unit());
+            }
+            let burst = if state.burst.0 > 0 {
+                state.burst.0 -= 1;
+                state.burst.1
+            } else {
+                1.0
+            };
+            let received = 40_000.0 * (0.2 + activity) * (1.0 + 0.3 * rng.signed()) * burst;
+            let sent = received * (0.35 + 0.1 * rng.signed());
+            state.network.0 += received * seconds;
+            state.network.1 += sent * seconds;
+
+            let read = 200_000.0 * (0.3 + activity) * (1.0 + 0.4 * rng.signed());
+            let written = 600_000.0
+                * (0.3 + activity)
+                * (1.0 + 0.4 * rng.signed())
+                * if in_incident { 5.0 } else { 1.0 };
+            state.disk.0 += read * seconds;
+            state.disk.1 += written * seconds;
+
+            let days = (time - start).num_seconds() as f64 / 86_400.0;
+            let disks_usage = FILESYSTEMS
+                .iter()
+                .map(|(mountpoint, size, used, growth)| {
+                    let total = size * GIB;
+                    let percent =
+                        ((used + growth * days + 0.0005 * rng.signed()) * 100.0).clamp(0.0, 98.0);
+                    let used = total * percent / 100.0;
+                    (
+                        mountpoint.to_string(),
+                        DiskUsageSample {
+                            total: total as u64,
+                            used: used as u64,
+                            free: (total - used) as u64,
+                            percent: percent as f32,
+                        },
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+
+            snapshots.push(SnapShot {
+                cpus: state
+                    .cpus
+                    .iter()
+                    .map(|counters| from_value(counters.to_value()))
+                    .collect::<Result<Vec<_>>>()?,
+                memory: from_value(json!({
+                    "total": MEMORY_TOTAL as u64,
+                    "available": (free + cached + buffers) as u64,
+                    "used": used as u64,
+                    "free": free as u64,
+                    "percent": used / MEMORY_TOTAL * 100.0,
+                    "active": (used * 0.6) as u64,
+                    "inactive": (cached * 0.5) as u64,
+                    "buffers": buffers as u64,
+                    "cached": cached as u64,
+                    "shared": 0,
+                    "slab": 0,
+                }))?,
+                swap: from_value(json!({
+                    "total": SWAP_TOTAL as u64,
+                    "used": state.swap_used as u64,
+                    "free": (SWAP_TOTAL - state.swap_used) as u64,
+                    "percent": state.swap_used / SWAP_TOTAL * 100.0,
+                    "swapped_in": state.swapped.0 as u64,
+                    "swapped_out": state.swapped.1 as u64,
+                }))?,
+                networks: vec![from_value(json!({
+                    "bytes_sent": state.network.1 as u64,
+                    "bytes_recv": state.network.0 as u64,
+                    "packets_sent": (state.network.1 / 800.0) as u64,
+                    "packets_recv": (state.network.0 / 800.0) as u64,
+                    "err_in": 0,
+                    "err_out": 0,
+                    "drop_in": 0,
+                    "drop_out": 0,
+                }))?],
+                disks_io: HashMap::from([(
+                    DISK.to_string(),
+                    from_value(json!({
+                        "read_count": (state.disk.0 / 4096.0) as u64,
+                        "write_count": (state.disk.1 / 4096.0) as u64,
+                        "read_bytes": state.disk.0 as u64,
+                        "write_bytes": state.disk.1 as u64,
+                        "read_time": duration(0.0),
+                        "write_time": duration(0.0),
+                        "busy_time": duration(0.0),
+                        "read_merged_count": 0,
+                        "write_merged_count": 0,
+                    }))?,
+                )]),
+                disks_memory: disks_usage
+                    .iter()
+                    .map(|(mountpoint, usage)| (mountpoint.clone(), usage.percent))
+                    .collect(),
+                disks_usage,
+                temps: Vec::new(),
+                load_avgs: LoadAvg {
+                    one: state.load.0,
+                    five: state.load.1,
+                    fifteen: state.load.2,
+                },
+                time,
+                host_facts: Some(HostFacts {
+                    kernel: "6.6.8-demo".to_string(),
+                    os: Some("Demo Linux 12".to_string()),
+                    boot_time: Some(start - Duration::days(2)),
+                    boot_id: Some(format!("demo-{:016x}", options.seed)),
+                }),
+                clock_jump: false,
+                suspended: false,
+                custom: HashMap::new(),
+                cpu_freq: Some(CpuFreq {
+                    current_mhz: 1400.0 + 2000.0 * usage,
+                    max_mhz: 3600.0,
+                }),
+                collection_duration_ms: Some(35 + (rng.unit() * 20.0) as u64),
+                collection_breakdown_ms: HashMap::new(),
+                kernel: None,
+                nic_info: HashMap::from([(
+                    NIC.to_string(),
+                    NicInfo {
+                        speed_mbps: Some(1000),
+                        up: true,
+                        bytes_recv: state.network.0 as u64,
+                        bytes_sent: state.network.1 as u64,
+                    },
+                )]),
+                container: false,
+                cgroup: None,
+            });
+        }
+
+        let annotations = snapshots
+            .get(incident.start)
+            .map(|snapshot| Annotation {
+                time: snapshot.time,
+                kind: AnnotationKind::Alert,
+                text: "CPU usage above 90%".to_string(),
+            })
+            .into_iter()
+            .collect();
+        debug!("Generated {} demo snapshots", snapshots.len());
+
+        Ok(Self {
+            hostname: Some(DEMO_HOSTNAME.to_string()),
+            snapshots,
+            annotations,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn options(seed: u64) -> DemoOptions {
+        DemoOptions {
+            end: Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap(),
+            span: Duration::days(2),
+            interval: Duration::minutes(5),
+            seed,
+        }
+    }
+
+    /// Checksum of the snapshots and annotations, their maps being sorted as the ones of a `SnapShot` are
+    /// encoded in the order of their hashes.
+    fn hash(database: &Database) -> u32 {
+        let canonical =
+            serde_json::to_value((database.snapshots(), &database.annotations)).unwrap();
+        crc32fast::hash(&serde_json::to_vec(&canonical).unwrap())
+    }
+
+    #[test]
+    fn seeds_give_the_same_database() {
+        let database = Database::demo(&options(DEFAULT_DEMO_SEED)).unwrap();
+
+        assert_eq!(
+            hash(&Database::demo(&options(DEFAULT_DEMO_SEED)).unwrap()),
+            hash(&database)
+        );
+        // NOTE: Changed along with the generators, the screenshots of the README having to be taken again
+        assert_eq!(hash(&database), 1_005_408_357);
+        assert_ne!(
+            hash(&Database::demo(&options(DEFAULT_DEMO_SEED + 1)).unwrap()),
+            hash(&database)
+        );
+    }
+
+    #[test]
+    fn values_stay_within_their_range() {
+        let options = options(DEFAULT_DEMO_SEED);
+        let database = Database::demo(&options).unwrap();
+        let snapshots = database.snapshots();
+
+        assert_eq!(snapshots.len(), 2 * 24 * 12 + 1);
+        assert_eq!(snapshots.last().unwrap().time, options.end);
+        assert_eq!(snapshots[0].time, options.end - options.span);
+        assert_eq!(database.hostname(), Some(DEMO_HOSTNAME));
+        let [incident] = &database.annotations[..] else {
+            panic!("a single incident expected");
+        };
+        assert_eq!(incident.kind, AnnotationKind::Alert);
+        assert!((snapshots[0].time..=options.end).contains(&incident.time));
+
+        let range = snapshots[0].time..options.end + Duration::seconds(1);
+        for metric in [
+            MetricKind::CpuPercent,
+            MetricKind::RamPercent,
+            MetricKind::SwapPercent,
+            MetricKind::CpuFrequencyPercent,
+        ] {
+            let values = database
+                .series(metric.clone(), range.clone(), None)
+                .collect::<Vec<_>>();
+            assert!(values.len() >= snapshots.len() - 1, "{metric:?}");
+            for (time, value) in values {
+                assert!(
+                    (0.0..=100.0).contains(&value),
+                    "{metric:?} at {time}: {value}"
+                );
+            }
+        }
+        for rate in [
+            MetricKind::NetRxRate,
+            MetricKind::NetTxRate,
+            MetricKind::DiskReadRate,
+            MetricKind::DiskWriteRate,
+            MetricKind::SwapInRate,
+            MetricKind::SwapOutRate,
+        ] {
+            for (time, value) in database.series(rate.clone(), range.clone(), None) {
+                assert!(
+                    value.is_finite() && value >= 0.0,
+                    "{rate:?} at {time}: {value}"
+                );
+            }
+        }
+        for (previous, snapshot) in snapshots.iter().zip(&snapshots[1..]) {
+            assert_eq!(snapshot.time - previous.time, options.interval);
+            assert!(!snapshot.clock_jump && !snapshot.suspended);
+            let load = &snapshot.load_avgs;
+            for average in [load.one, load.five, load.fifteen] {
+                assert!((0.0..=2.0 * CPUS as f64).contains(&average), "{load:?}");
+            }
+            for (mountpoint, usage) in &snapshot.disks_usage {
+                assert!((0.0..=98.0).contains(&usage.percent), "{mountpoint}");
+                assert!(usage.used <= usage.total, "{mountpoint}");
+                // NOTE: Filesystems only fill up, apart from the noise
+                assert!(
+                    usage.percent + 0.2 >= previous.disks_usage[mountpoint].percent,
+                    "{mountpoint} at {}",
+                    snapshot.time
+                );
+            }
+            assert!(snapshot.swap.used() <= snapshot.swap.total());
+            assert!(snapshot.memory.used() <= snapshot.memory.total());
+        }
+    }
+}
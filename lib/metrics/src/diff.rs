@@ -0,0 +1,405 @@
+//! Field by field comparison of two snapshots, done on their serde representation so the fields added to
+//! `SnapShot` are compared without being listed here.
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{errors::Error, rates::counter_delta, snapshot::SnapShot, Result};
+
+/// Top level fields holding monotonic counters, their deltas also being given as a rate per second.
+const COUNTER_FIELDS: &[&str] = &["cpus", "networks", "disks_io"];
+/// Fields naming the items of a list, e.g. the `unit` and `label` of a temperature sensor, the items without
+/// any being compared by position.
+const ITEM_NAME_FIELDS: &[&str] = &["name", "unit", "label", "mountpoint", "interface"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FieldChange {
+    /// Only in the second snapshot, e.g. a new mountpoint.
+    Added { value: Value },
+    /// Only in the first snapshot, e.g. a NIC which went away.
+    Removed { value: Value },
+    Numeric {
+        from: f64,
+        to: f64,
+        delta: f64,
+        /// Delta per second of a counter, `None` for a gauge or a counter which went backward.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rate: Option<f64>,
+    },
+    /// Any other value which changed, e.g. a string or a number becoming `null`.
+    Changed { from: Value, to: Value },
+}
+
+/// Change of the value at `path`, e.g. `disks_usage./home.used` or `temps[coretemp/Core 0].current`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldDiff {
+    pub path: String,
+    #[serde(flatten)]
+    pub change: FieldChange,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub from_time: DateTime<Utc>,
+    pub to_time: DateTime<Utc>,
+    /// Size in bytes of each snapshot as encoded in the database, before compression and encryption.
+    pub from_size: usize,
+    pub to_size: usize,
+    /// Only the values which differ, in the order of the fields.
+    pub changes: Vec<FieldDiff>,
+}
+
+impl SnapshotDiff {
+    pub fn between(from: &SnapShot, to: &SnapShot) -> Result<Self> {
+        let elapsed = (to.time - from.time).num_milliseconds() as f64 / 1000.0;
+        Ok(Self {
+            from_time: from.time,
+            to_time: to.time,
+            from_size: encoded_size(from)?,
+            to_size: encoded_size(to)?,
+            changes: diff_values(&to_value(from)?, &to_value(to)?, elapsed),
+        })
+    }
+}
+
+fn to_value(snapshot: &SnapShot) -> Result<Value> {
+    serde_json::to_value(snapshot).map_err(Error::SnapshotToValue)
+}
+
+fn encoded_size(snapshot: &SnapShot) -> Result<usize> {
+    let mut encoded = Vec::new();
+    ciborium::ser::into_writer(snapshot, &mut encoded)?;
+    Ok(encoded.len())
+}
+
+/// Changes from `from` to `to`, the counters under `COUNTER_FIELDS` also getting their rate over `elapsed`
+/// seconds.
+pub fn diff_values(from: &Value, to: &Value, elapsed: f64) -> Vec<FieldDiff> {
+    let mut changes = Vec::new();
+    walk("", from, to, (elapsed, false), &mut changes);
+    changes
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// Fields of `from` missing from `to`.
+fn removed<'a>(
+    from: &'a Map<String, Value>,
+    to: &'a Map<String, Value>,
+    path: &'a str,
+) -> impl Iterator<Item = FieldDiff> + 'a {
+    from.iter()
+        .filter(|(key, _)| !to.contains_key(*key))
+        .map(|(key, value)| FieldDiff {
+            path: join(path, key),
+            change: FieldChange::Removed {
+                value: value.clone(),
+            },
+        })
+}
+
+/// Name of each item of a list, from its `ITEM_NAME_FIELDS` or its position, a number being appended to the
+/// names given to several items.
+fn item_names(items: &[Value]) -> Vec<String> {
+    let mut taken = HashMap::<String, usize>::new();
+    items
+        .iter()
+        .enumerate()
+        .map(|(position, item)| {
+            let name = item
+                .as_object()
+                .map(|item| {
+                    ITEM_NAME_FIELDS
+                        .iter()
+                        .filter_map(|field| item.get(*field)?.as_str())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                })
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| position.to_string());
+            let count = taken.entry(name.clone()).or_default();
+            *count += 1;
+            if *count == 1 {
+                name
+            } else {
+                format!("{name}#{count}")
+            }
+        })
+        .collect()
+}
+
+/// Changes of the value at `path`, with the seconds between both snapshots and whether it is under a counter.
+fn walk(
+    path: &str,
+    from: &Value,
+    to: &Value,
+    (elapsed, counter): (f64, bool),
+    changes: &mut Vec<FieldDiff>,
+) {
+    match (from, to) {
+        (Value::Object(from_map), Value::Object(to_map)) => {
+            for (key, to_value) in to_map {
+                let counter =
+                    counter || (path.is_empty() && COUNTER_FIELDS.contains(&key.as_str()));
+                match from_map.get(key) {
+                    Some(from_value) => walk(
+                        &join(path, key),
+                        from_value,
+                        to_value,
+                        (elapsed, counter),
+                        changes,
+                    ),
+                    None => changes.push(FieldDiff {
+                        path: join(path, key),
+                        change: FieldChange::Added {
+                            value: to_value.clone(),
+                        },
+                    }),
+                }
+            }
+            changes.extend(removed(from_map, to_map, path));
+        }
+        (Value::Array(from_items), Value::Array(to_items)) => {
+            let from_names = item_names(from_items);
+            let to_names = item_names(to_items);
+            for (name, to_item) in to_names.iter().zip(to_items) {
+                let item_path = format!("{path}[{name}]");
+                match from_names.iter().position(|from_name| from_name == name) {
+                    Some(position) => walk(
+                        &item_path,
+                        &from_items[position],
+                        to_item,
+                        (elapsed, counter),
+                        changes,
+                    ),
+                    None => changes.push(FieldDiff {
+                        path: item_path,
+                        change: FieldChange::Added {
+                            value: to_item.clone(),
+                        },
+                    }),
+                }
+            }
+            for (name, from_item) in from_names.iter().zip(from_items) {
+                if !to_names.contains(name) {
+                    changes.push(FieldDiff {
+                        path: format!("{path}[{name}]"),
+                        change: FieldChange::Removed {
+                            value: from_item.clone(),
+                        },
+                    });
+                }
+            }
+        }
+        (Value::Number(from_number), Value::Number(to_number)) => {
+            let (Some(from_number), Some(to_number)) = (from_number.as_f64(), to_number.as_f64())
+            else {
+                return;
+            };
+            if from_number == to_number {
+                return;
+            }
+            changes.push(FieldDiff {
+                path: path.to_string(),
+                change: FieldChange::Numeric {
+                    from: from_number,
+                    to: to_number,
+                    delta: to_number - from_number,
+                    rate: (counter && elapsed > 0.0)
+                        .then(|| counter_delta(from_number, to_number))
+                        .flatten()
+                        .map(|delta| delta / elapsed),
+                },
+            });
+        }
+        _ if from != to => changes.push(FieldDiff {
+            path: path.to_string(),
+            change: FieldChange::Changed {
+                from: from.clone(),
+                to: to.clone(),
+            },
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        prelude::Database,
+        psutil::NicInfo,
+        testing::{time, SnapshotBuilder, MOUNTPOINT},
+    };
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn nic() -> NicInfo {
+        NicInfo {
+            speed_mbps: Some(1000),
+            up: true,
+            bytes_recv: 0,
+            bytes_sent: 0,
+        }
+    }
+
+    /// Database of two snapshots a minute apart, `/home` being mounted and `wlan0` removed in the meantime.
+    fn database() -> Database {
+        let mut from = SnapshotBuilder::at(time(0))
+            .network(1_000, 500)
+            .memory(4 * GIB, 16 * GIB)
+            .load(1.0)
+            .build();
+        from.nic_info = HashMap::from([("eth0".to_string(), nic()), ("wlan0".to_string(), nic())]);
+        let mut to = SnapshotBuilder::at(time(60))
+            .network(61_000, 30_500)
+            .memory(6 * GIB, 16 * GIB)
+            .load(1.0)
+            .build();
+        to.nic_info = HashMap::from([("eth0".to_string(), nic())]);
+        let home = to.disks_usage[MOUNTPOINT];
+        to.disks_usage.insert("/home".to_string(), home);
+        to.disks_memory.insert("/home".to_string(), 0.0);
+
+        let mut database = Database::default();
+        database.push_snapshot(from);
+        database.push_snapshot(to);
+        database
+    }
+
+    #[test]
+    fn changes_are_categorized_by_path() {
+        let database = database();
+        let [from, to] = database.snapshots() else {
+            panic!("two snapshots expected");
+        };
+
+        let diff = SnapshotDiff::between(from, to).unwrap();
+
+        assert_eq!((diff.from_time, diff.to_time), (time(0), time(60)));
+        assert_eq!(diff.from_size, encoded_size(from).unwrap());
+        assert_eq!(diff.to_size, encoded_size(to).unwrap());
+        let paths = |is_kind: fn(&FieldChange) -> bool| {
+            diff.changes
+                .iter()
+                .filter(|change| is_kind(&change.change))
+                .map(|change| change.path.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(|change| matches!(change, FieldChange::Added { .. })),
+            ["disks_memory./home", "disks_usage./home"]
+        );
+        assert_eq!(
+            paths(|change| matches!(change, FieldChange::Removed { .. })),
+            ["nic_info.wlan0"]
+        );
+        assert_eq!(
+            paths(|change| matches!(change, FieldChange::Changed { .. })),
+            ["time"]
+        );
+        assert_eq!(
+            paths(|change| matches!(change, FieldChange::Numeric { .. })),
+            [
+                "memory.available",
+                "memory.free",
+                "memory.percent",
+                "memory.used",
+                "networks[0].bytes_recv",
+                "networks[0].bytes_sent",
+            ]
+        );
+        let change = |path: &str| {
+            &diff
+                .changes
+                .iter()
+                .find(|change| change.path == path)
+                .unwrap()
+                .change
+        };
+        // NOTE: Counters get a rate per second, gauges only their delta
+        assert_eq!(
+            change("networks[0].bytes_recv"),
+            &FieldChange::Numeric {
+                from: 1_000.0,
+                to: 61_000.0,
+                delta: 60_000.0,
+                rate: Some(1_000.0),
+            }
+        );
+        assert_eq!(
+            change("networks[0].bytes_sent"),
+            &FieldChange::Numeric {
+                from: 500.0,
+                to: 30_500.0,
+                delta: 30_000.0,
+                rate: Some(500.0),
+            }
+        );
+        assert_eq!(
+            change("memory.used"),
+            &FieldChange::Numeric {
+                from: (4 * GIB) as f64,
+                to: (6 * GIB) as f64,
+                delta: (2 * GIB) as f64,
+                rate: None,
+            }
+        );
+    }
+
+    #[test]
+    fn identical_snapshots_have_no_change() {
+        let database = database();
+        let to = &database.snapshots()[1];
+
+        let diff = SnapshotDiff::between(to, to).unwrap();
+
+        assert_eq!(diff.changes, []);
+        assert_eq!(diff.from_size, diff.to_size);
+    }
+
+    #[test]
+    fn counters_going_backward_have_no_rate() {
+        let changes = diff_values(
+            &serde_json::json!({ "networks": [{ "bytes_recv": 500 }], "load": 2 }),
+            &serde_json::json!({ "networks": [{ "bytes_recv": 100 }], "load": 1 }),
+            60.0,
+        );
+
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (change.path.as_str(), &change.change))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "load",
+                    &FieldChange::Numeric {
+                        from: 2.0,
+                        to: 1.0,
+                        delta: -1.0,
+                        rate: None,
+                    }
+                ),
+                (
+                    "networks[0].bytes_recv",
+                    &FieldChange::Numeric {
+                        from: 500.0,
+                        to: 100.0,
+                        delta: -400.0,
+                        rate: None,
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::{fmt, fs, path::Path};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{self, rand_core::RngCore, Aead, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use log::{debug, tracing};
+
+use crate::{prelude::*, Result};
+
+// NOTE: Header layout is MAGIC | FORMAT_VERSION | salt | nonce, followed by the ciphertext
+pub(crate) const MAGIC: &[u8; 8] = b"SYSMETEN";
+const FORMAT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+// NOTE: Poly1305 authentication tag appended by the AEAD
+const TAG_LEN: usize = 16;
+
+/// Passphrase used to encrypt and decrypt a database file.
+#[derive(Clone)]
+pub struct DatabaseKey {
+    passphrase: String,
+}
+
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseKey")
+            .field("passphrase", &"********")
+            .finish()
+    }
+}
+
+impl DatabaseKey {
+    pub fn new(passphrase: &str) -> Result<Self> {
+        if passphrase.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+
+        Ok(Self {
+            passphrase: passphrase.to_string(),
+        })
+    }
+
+    #[tracing::instrument]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(Error::FailedToReadKeyFile)?;
+        // NOTE: Key files are usually written with a trailing newline
+        Self::new(content.trim_end_matches(['\n', '\r']))
+    }
+
+    /// Resolve the key from a passphrase (usually `SYSMET_DB_KEY`) or a key file, the passphrase winning.
+    pub fn resolve(passphrase: Option<&str>, key_file: Option<&Path>) -> Result<Option<Self>> {
+        match (passphrase, key_file) {
+            (Some(passphrase), _) => Ok(Some(Self::new(passphrase)?)),
+            (None, Some(path)) => Ok(Some(Self::from_file(path)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn derive(&self, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+
+        Ok(XChaCha20Poly1305::new(&key.into()))
+    }
+}
+
+pub(crate) fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+#[tracing::instrument(level = "trace", skip(plaintext))]
+pub(crate) fn encrypt(key: &DatabaseKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = key
+        .derive(&salt)?
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|aead::Error| Error::EncryptionFailed)?;
+
+    let mut result = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    result.extend_from_slice(MAGIC);
+    result.push(FORMAT_VERSION);
+    result.extend_from_slice(&salt);
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&ciphertext);
+    debug!(
+        plaintext_size = plaintext.len(),
+        encrypted_size = result.len(),
+        "Encrypted database"
+    );
+
+    Ok(result)
+}
+
+#[tracing::instrument(level = "trace", skip(data))]
+pub(crate) fn decrypt(key: &DatabaseKey, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < HEADER_LEN + TAG_LEN {
+        return Err(Error::TruncatedCiphertext);
+    }
+
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+    let version = header[MAGIC.len()];
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedEncryptionVersion(version));
+    }
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[MAGIC.len() + 1 + SALT_LEN..];
+
+    // NOTE: The AEAD cannot tell a wrong key from a tampered or truncated ciphertext
+    let plaintext = key
+        .derive(salt)?
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|aead::Error| Error::WrongKey)?;
+    debug!(
+        encrypted_size = data.len(),
+        plaintext_size = plaintext.len(),
+        "Decrypted database"
+    );
+
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testing;
+
+    fn key(passphrase: &str) -> DatabaseKey {
+        DatabaseKey::new(passphrase).unwrap()
+    }
+
+    #[test]
+    fn encrypted_bytes_round_trip() {
+        let key = key("correct horse");
+        let plaintext = b"snapshots of the database".repeat(100);
+        let encrypted = encrypt(&key, &plaintext).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(encrypted.len(), HEADER_LEN + plaintext.len() + TAG_LEN);
+        assert!(!encrypted
+            .windows(b"snapshots".len())
+            .any(|window| window == b"snapshots"));
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), plaintext);
+
+        // NOTE: A fresh salt and nonce each time
+        assert_ne!(encrypt(&key, &plaintext).unwrap(), encrypted);
+        assert_eq!(
+            decrypt(&key, &encrypt(&key, &[]).unwrap()).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn wrong_keys_are_refused() {
+        let encrypted = encrypt(&key("correct horse"), b"plaintext").unwrap();
+        assert!(matches!(
+            decrypt(&key("battery staple"), &encrypted),
+            Err(Error::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn damaged_ciphertexts_are_refused() {
+        let key = key("correct horse");
+        let encrypted = encrypt(&key, &[42; 64]).unwrap();
+
+        for len in [0, MAGIC.len(), HEADER_LEN, HEADER_LEN + TAG_LEN - 1] {
+            assert!(
+                matches!(
+                    decrypt(&key, &encrypted[..len]),
+                    Err(Error::TruncatedCiphertext)
+                ),
+                "{len}"
+            );
+        }
+        // NOTE: Long enough for a tag, the AEAD refuses it like a wrong key
+        assert!(matches!(
+            decrypt(&key, &encrypted[..encrypted.len() - 1]),
+            Err(Error::WrongKey)
+        ));
+        for index in [
+            MAGIC.len() + 1,
+            HEADER_LEN - 1,
+            HEADER_LEN,
+            encrypted.len() - 1,
+        ] {
+            let mut tampered = encrypted.clone();
+            tampered[index] ^= 1;
+            assert!(
+                matches!(decrypt(&key, &tampered), Err(Error::WrongKey)),
+                "{index}"
+            );
+        }
+
+        let mut newer = encrypted.clone();
+        newer[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            decrypt(&key, &newer),
+            Err(Error::UnsupportedEncryptionVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn keys_are_resolved() {
+        assert!(matches!(DatabaseKey::new(""), Err(Error::EmptyKey)));
+        assert!(!format!("{:?}", key("correct horse")).contains("horse"));
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("key");
+        fs::write(&path, "correct horse\r\n").unwrap();
+        assert_eq!(
+            DatabaseKey::from_file(&path).unwrap().passphrase,
+            "correct horse"
+        );
+        fs::write(&path, "\n").unwrap();
+        assert!(matches!(
+            DatabaseKey::from_file(&path),
+            Err(Error::EmptyKey)
+        ));
+        assert!(matches!(
+            DatabaseKey::from_file(&dir.path().join("missing")),
+            Err(Error::FailedToReadKeyFile(_))
+        ));
+
+        // NOTE: The passphrase wins over the key file
+        let resolved = DatabaseKey::resolve(Some("from env"), Some(&path)).unwrap();
+        assert_eq!(resolved.unwrap().passphrase, "from env");
+        assert!(DatabaseKey::resolve(None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn database_files_are_encrypted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db").to_string_lossy().into_owned();
+        let key = key("correct horse");
+        let mut database = Database::default();
+        for snapshot in testing::snapshots(5, 10) {
+            database.push_snapshot(snapshot);
+        }
+        database.set_key(Some(key.clone()));
+        database.write_to_file(&path).unwrap();
+        assert!(is_encrypted(&fs::read(&path).unwrap()));
+
+        let loaded = Database::from_file(&path, Some(&key)).unwrap();
+        assert!(loaded.is_encrypted());
+        assert_eq!(loaded.snapshots().len(), 5);
+        assert!(matches!(
+            Database::from_file(&path, None),
+            Err(Error::MissingKey)
+        ));
+        assert!(matches!(
+            Database::from_file(&path, Some(&self::key("battery staple"))),
+            Err(Error::WrongKey)
+        ));
+
+        // NOTE: Truncated by a full disk
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(
+            Database::from_file(&path, Some(&key)),
+            Err(Error::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn plaintext_files_are_read_with_a_key() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db").to_string_lossy().into_owned();
+        let mut database = Database::default();
+        for snapshot in testing::snapshots(5, 10) {
+            database.push_snapshot(snapshot);
+        }
+        database.write_to_file(&path).unwrap();
+
+        // NOTE: Databases written before the key was set, encrypted on their next write
+        let key = key("correct horse");
+        let mut loaded = Database::from_file(&path, Some(&key)).unwrap();
+        assert_eq!(loaded.snapshots().len(), 5);
+        loaded.write_to_file(&path).unwrap();
+        assert!(is_encrypted(&fs::read(&path).unwrap()));
+        assert_eq!(
+            Database::from_file(&path, Some(&key))
+                .unwrap()
+                .snapshots()
+                .len(),
+            5
+        );
+    }
+}
@@ -39,7 +39,126 @@ pub enum Error {
     #[cfg(feature = "database")]
     #[error("Timeout while trying to lock {0:?}")]
     LockFileTimeout(std::path::PathBuf),
+    #[cfg(feature = "database")]
+    #[error("Failed to read file: {0}")]
+    FailedToReadFile(std::io::Error),
+    #[cfg(feature = "database")]
+    #[error("Invalid database path {0:?}")]
+    InvalidDatabasePath(std::path::PathBuf),
+    #[cfg(feature = "database")]
+    #[error("Invalid shard period {0}, expected monthly or weekly")]
+    InvalidShardPeriod(String),
+    #[error("Invalid averaging {0}, expected time-weighted or plain")]
+    InvalidAveraging(String),
+    #[cfg(feature = "database")]
+    #[error("A snapshot was already taken at {0}")]
+    SnapshotTimeTaken(chrono::DateTime<chrono::Utc>),
+    #[cfg(feature = "database")]
+    #[error("Shard {0:?} already exists")]
+    ShardAlreadyExists(std::path::PathBuf),
+    // Pending snapshots
+    #[cfg(feature = "database")]
+    #[error("Failed to spill the snapshot to the pending directory: {0}")]
+    FailedToWritePending(std::io::Error),
+    #[cfg(feature = "database")]
+    #[error("Failed to read the pending directory: {0}")]
+    FailedToReadPending(std::io::Error),
+    // Backups
+    #[cfg(feature = "database")]
+    #[error("Failed to back up the database: {0}")]
+    FailedToBackup(std::io::Error),
+    #[cfg(feature = "database")]
+    #[error("Failed to restore the backup: {0}")]
+    FailedToRestoreBackup(std::io::Error),
+    #[cfg(feature = "database")]
+    #[error("Database version {0} is newer than this build ({1})")]
+    NewerDatabaseVersion(String, String),
+    #[cfg(feature = "database")]
+    #[error("Database read back after writing is wrong, its previous content was restored: {0}")]
+    WriteVerificationFailed(String),
+    // Encryption
+    #[cfg(feature = "database")]
+    #[error("Database key is empty")]
+    EmptyKey,
+    #[cfg(feature = "database")]
+    #[error("Failed to read database key file: {0}")]
+    FailedToReadKeyFile(std::io::Error),
+    #[cfg(feature = "database")]
+    #[error("Failed to derive the encryption key: {0}")]
+    KeyDerivation(String),
+    #[cfg(feature = "database")]
+    #[error("Failed to encrypt the database")]
+    EncryptionFailed,
+    #[cfg(feature = "database")]
+    #[error("Wrong database key or corrupted database")]
+    WrongKey,
+    #[cfg(feature = "database")]
+    #[error("Database is encrypted but no key was provided (set SYSMET_DB_KEY or --db-key-file)")]
+    MissingKey,
+    #[cfg(feature = "database")]
+    #[error("Encrypted database is truncated")]
+    TruncatedCiphertext,
+    #[cfg(feature = "database")]
+    #[error("Unsupported encrypted database format version {0}")]
+    UnsupportedEncryptionVersion(u8),
+    #[cfg(feature = "database")]
+    #[error("Database is not encrypted while the one it replaces is")]
+    UnencryptedReplacement,
+    // Integrity
+    #[cfg(feature = "database")]
+    #[error("Checksum mismatch in the {portion}: expected {expected:08x}, got {actual:08x}")]
+    ChecksumMismatch {
+        portion: String,
+        expected: u32,
+        actual: u32,
+    },
+    #[cfg(feature = "database")]
+    #[error("Corrupt database: {0}")]
+    CorruptDatabase(String),
+    #[cfg(feature = "database")]
+    #[error("Unsupported checksummed database format version {0}")]
+    UnsupportedChecksumVersion(u8),
+    #[cfg(feature = "database")]
+    #[error("{0} records of the database are corrupt")]
+    CorruptRecords(usize),
+    // Export
+    #[error("Failed to send metrics: {0}")]
+    FailedToSendMetrics(std::io::Error),
     // Chrono
     #[error("Oldest date is too big to big calculated")]
     OldestDateOverflow,
+    // Collectors
+    #[error("Invalid custom metric {0}, expected name=/path")]
+    InvalidCustomMetric(String),
+    #[error("Failed to read the custom metric file {0:?}: {1}")]
+    FailedToReadCustomMetric(std::path::PathBuf, std::io::Error),
+    #[error("Custom metric file {0:?} does not hold a number: {1:?}")]
+    InvalidCustomMetricValue(std::path::PathBuf, String),
+    // Demo
+    #[cfg(feature = "demo")]
+    #[error("Failed to build a demo snapshot: {0}")]
+    DemoSnapshot(serde_json::Error),
+    // Diff
+    #[cfg(feature = "diff")]
+    #[error("Failed to convert the snapshot to a JSON value: {0}")]
+    SnapshotToValue(serde_json::Error),
+    // Import
+    #[cfg(feature = "import")]
+    #[error("Failed to parse the sadf JSON: {0}")]
+    InvalidSadfJson(#[from] serde_json::Error),
+    #[cfg(feature = "import")]
+    #[error("Failed to read the sar file {0:?}: {1}")]
+    FailedToReadSarFile(std::path::PathBuf, std::io::Error),
+    #[cfg(feature = "import")]
+    #[error("Failed to run sadf, is sysstat installed? {0}")]
+    FailedToRunSadf(std::io::Error),
+    #[cfg(feature = "import")]
+    #[error("sadf failed on {0:?}: {1}")]
+    SadfFailed(std::path::PathBuf, String),
+    #[cfg(feature = "import")]
+    #[error("Failed to read the Go sysmet database {0:?}: {1}")]
+    FailedToReadGoDatabase(std::path::PathBuf, std::io::Error),
+    #[cfg(feature = "import")]
+    #[error("Not a Go sysmet database, no valid bbolt meta page")]
+    InvalidGoDatabase,
 }
@@ -0,0 +1,439 @@
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use log::{debug, trace, tracing};
+
+use crate::{
+    prelude::*,
+    rates::{counter_delta, cpu_count_changed, rebooted},
+    Result,
+};
+
+pub const DEFAULT_PREFIX: &str = "sysmet.";
+/// Largest payload that fits in a single ethernet frame without fragmentation.
+pub const MAX_DATAGRAM_SIZE: usize = 1432;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Gauge,
+    Counter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    pub kind: MetricKind,
+}
+
+impl Metric {
+    fn gauge(name: impl Into<String>, value: f64) -> Self {
+        Self {
+            name: name.into(),
+            value,
+            kind: MetricKind::Gauge,
+        }
+    }
+
+    fn counter(name: impl Into<String>, value: f64) -> Self {
+        Self {
+            name: name.into(),
+            value,
+            kind: MetricKind::Counter,
+        }
+    }
+
+    /// Line in the plain statsd text format, e.g. `sysmet.cpu.percent:12.5|g`.
+    pub fn format(&self, prefix: &str) -> String {
+        // NOTE: Counters only accept integers on most statsd servers
+        match self.kind {
+            MetricKind::Gauge => format!("{prefix}{}:{}|g", self.name, round(self.value)),
+            MetricKind::Counter => format!("{prefix}{}:{}|c", self.name, self.value as u64),
+        }
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+/// Make a name usable as a single statsd path segment, e.g. `/var/lib` becomes `var_lib` and `/` becomes `root`.
+pub fn sanitize_name(name: &str) -> String {
+    let sanitized = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let sanitized = sanitized.trim_matches('_');
+
+    if sanitized.is_empty() {
+        "root".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// Metrics of the `snapshot`, usages and counters are computed since the `previous` snapshot when given.
+#[tracing::instrument(skip_all)]
+pub fn snapshot_metrics(snapshot: &SnapShot, previous: Option<&SnapShot>) -> Vec<Metric> {
+    let mut metrics = Vec::new();
+    // NOTE: The counters restarted on a reboot, even when they are already past their previous values
+    let previous = previous.filter(|previous| !rebooted(previous, snapshot));
+
+    // NOTE: Falls back to the usage since boot without a previous snapshot, after a reboot or a change of the
+    // number of CPUs
+    let (active, total) = snapshot.get_cpu_time();
+    let cpu_percent = previous
+        .filter(|previous| !cpu_count_changed(previous, snapshot))
+        .and_then(|previous| {
+            let (previous_active, previous_total) = previous.get_cpu_time();
+            let active = counter_delta(previous_active, active)?;
+            let total = counter_delta(previous_total, total)?;
+            (total > 0.0).then(|| active / total * 100.0)
+        })
+        .or_else(|| (total > 0.0).then(|| active / total * 100.0));
+    if let Some(cpu_percent) = cpu_percent {
+        metrics.push(Metric::gauge("cpu.percent", cpu_percent));
+    }
+
+    let (ram, swap) = snapshot.get_ram_usage();
+    metrics.push(Metric::gauge("memory.ram.percent", ram));
+    metrics.push(Metric::gauge("memory.swap.percent", swap));
+
+    let (one, five, fifteen) = snapshot.get_load();
+    metrics.push(Metric::gauge("load.1", one));
+    metrics.push(Metric::gauge("load.5", five));
+    metrics.push(Metric::gauge("load.15", fifteen));
+
+    let mut disks = snapshot.get_disks_size_usage();
+    disks.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (mountpoint, usage) in disks {
+        metrics.push(Metric::gauge(
+            format!("disk.usage.{}.percent", sanitize_name(&mountpoint)),
+            usage,
+        ));
+    }
+
+    if let Some(previous) = previous {
+        let (received, sent) = snapshot.get_network_usage();
+        let (previous_received, previous_sent) = previous.get_network_usage();
+        let (read, written) = snapshot.get_disk_speed_usage();
+        let (previous_read, previous_written) = previous.get_disk_speed_usage();
+        let counters = [
+            ("network.received_bytes", previous_received, received),
+            ("network.sent_bytes", previous_sent, sent),
+            ("disk.io.read_bytes", previous_read as f64, read as f64),
+            (
+                "disk.io.written_bytes",
+                previous_written as f64,
+                written as f64,
+            ),
+        ];
+        for (name, previous, current) in counters {
+            if let Some(delta) = counter_delta(previous, current) {
+                metrics.push(Metric::counter(name, delta));
+            }
+        }
+    }
+
+    trace!(metrics = ?metrics);
+    metrics
+}
+
+/// Join the lines in datagrams of at most `max_size` bytes, a longer line is sent alone.
+pub fn batch_datagrams(lines: &[String], max_size: usize) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > max_size {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+
+    datagrams
+}
+
+/// Datagrams to send for the metrics, with every name prefixed by `prefix`.
+pub fn format_datagrams(metrics: &[Metric], prefix: &str) -> Vec<String> {
+    let lines = metrics
+        .iter()
+        .map(|metric| metric.format(prefix))
+        .collect::<Vec<_>>();
+    batch_datagrams(&lines, MAX_DATAGRAM_SIZE)
+}
+
+#[derive(Debug)]
+pub struct StatsdEmitter {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdEmitter {
+    #[tracing::instrument]
+    pub fn new(addr: &str, prefix: &str) -> Result<Self> {
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(Error::FailedToSendMetrics)?
+            .next()
+            .ok_or_else(|| {
+                Error::FailedToSendMetrics(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{addr} did not resolve to any address"),
+                ))
+            })?;
+        let bind_addr = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).map_err(Error::FailedToSendMetrics)?;
+        socket.connect(addr).map_err(Error::FailedToSendMetrics)?;
+
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Send the metrics of the snapshot, stops at the first failure and returns the number of datagrams sent.
+    #[tracing::instrument(skip_all)]
+    pub fn emit(&self, snapshot: &SnapShot, previous: Option<&SnapShot>) -> Result<usize> {
+        let datagrams = format_datagrams(&snapshot_metrics(snapshot, previous), &self.prefix);
+        for datagram in &datagrams {
+            self.socket
+                .send(datagram.as_bytes())
+                .map_err(Error::FailedToSendMetrics)?;
+        }
+        debug!("Sent {} statsd datagrams", datagrams.len());
+
+        Ok(datagrams.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines_of(datagrams: &[String]) -> Vec<&str> {
+        datagrams
+            .iter()
+            .flat_map(|datagram| datagram.lines())
+            .collect()
+    }
+
+    #[test]
+    fn mountpoints_are_single_segments() {
+        assert_eq!(sanitize_name("/"), "root");
+        assert_eq!(sanitize_name("/var/lib/docker"), "var_lib_docker");
+        assert_eq!(sanitize_name("/mnt/usb drive:1"), "mnt_usb_drive_1");
+        assert_eq!(sanitize_name("/srv/data-2_b/"), "srv_data-2_b");
+        assert_eq!(sanitize_name("C:\\"), "C");
+        assert_eq!(sanitize_name("/média"), "m_dia");
+    }
+
+    #[test]
+    fn metrics_use_the_plain_text_format() {
+        assert_eq!(
+            Metric::gauge("cpu.percent", 12.345_67).format(DEFAULT_PREFIX),
+            "sysmet.cpu.percent:12.346|g"
+        );
+        assert_eq!(
+            Metric::counter("network.sent_bytes", 1024.9).format(""),
+            "network.sent_bytes:1024|c"
+        );
+    }
+
+    #[test]
+    fn datagrams_stay_below_the_max_size() {
+        let lines = (0..200)
+            .map(|idx| format!("sysmet.disk.usage.mount{idx}.percent:{idx}|g"))
+            .collect::<Vec<_>>();
+
+        let datagrams = batch_datagrams(&lines, MAX_DATAGRAM_SIZE);
+
+        assert!(datagrams.len() > 1);
+        assert!(datagrams
+            .iter()
+            .all(|datagram| datagram.len() <= MAX_DATAGRAM_SIZE));
+        // NOTE: Each datagram is filled before starting the next one
+        for pair in datagrams.windows(2) {
+            let next = pair[1].lines().next().unwrap();
+            assert!(pair[0].len() + 1 + next.len() > MAX_DATAGRAM_SIZE);
+        }
+        assert_eq!(lines_of(&datagrams), lines);
+    }
+
+    #[test]
+    fn long_lines_are_sent_alone() {
+        let lines = vec!["a:1|g".to_string(), "b".repeat(20), "c:1|g".to_string()];
+
+        let datagrams = batch_datagrams(&lines, 10);
+
+        assert_eq!(
+            datagrams,
+            vec!["a:1|g".to_string(), "b".repeat(20), "c:1|g".to_string()]
+        );
+        assert_eq!(batch_datagrams(&lines, 100), vec![lines.join("\n")]);
+        assert!(batch_datagrams(&[], 100).is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    mod snapshots {
+        use std::{net::UdpSocket, time::Duration};
+
+        use super::super::*;
+        use crate::testing::{time, SnapshotBuilder, MOUNTPOINT};
+
+        const GIB: u64 = 1024 * 1024 * 1024;
+
+        /// Snapshot with a second mountpoint, `/var/lib/docker`.
+        fn snapshot(builder: SnapshotBuilder) -> SnapShot {
+            let mut snapshot = builder
+                .memory(4 * GIB, 16 * GIB)
+                .disk_usage(40.0)
+                .load(1.5)
+                .build();
+            let mut docker = snapshot.disks_usage[MOUNTPOINT];
+            docker.percent = 70.0;
+            snapshot
+                .disks_usage
+                .insert("/var/lib/docker".to_string(), docker);
+            snapshot
+        }
+
+        fn snapshots() -> (SnapShot, SnapShot) {
+            (
+                snapshot(
+                    SnapshotBuilder::at(time(0))
+                        .cpu(10.0, 30.0)
+                        .network(1000, 500)
+                        .disk(4096, 2048),
+                ),
+                snapshot(
+                    SnapshotBuilder::at(time(10))
+                        .cpu(15.0, 35.0)
+                        .network(3000, 800)
+                        .disk(8192, 2048),
+                ),
+            )
+        }
+
+        #[test]
+        fn datagrams_hold_the_usages_and_counters_since_the_previous_snapshot() {
+            let (previous, snapshot) = snapshots();
+
+            let datagrams =
+                format_datagrams(&snapshot_metrics(&snapshot, Some(&previous)), "sysmet.");
+
+            assert_eq!(
+                datagrams,
+                vec![[
+                    "sysmet.cpu.percent:50|g",
+                    "sysmet.memory.ram.percent:25|g",
+                    "sysmet.memory.swap.percent:0|g",
+                    "sysmet.load.1:1.5|g",
+                    "sysmet.load.5:1.5|g",
+                    "sysmet.load.15:1.5|g",
+                    "sysmet.disk.usage.root.percent:40|g",
+                    "sysmet.disk.usage.var_lib_docker.percent:70|g",
+                    "sysmet.network.received_bytes:2000|c",
+                    "sysmet.network.sent_bytes:300|c",
+                    "sysmet.disk.io.read_bytes:4096|c",
+                    "sysmet.disk.io.written_bytes:0|c",
+                ]
+                .join("\n")]
+            );
+        }
+
+        #[test]
+        fn first_snapshots_have_no_counters() {
+            let (snapshot, _) = snapshots();
+
+            let metrics = snapshot_metrics(&snapshot, None);
+
+            assert!(metrics
+                .iter()
+                .all(|metric| metric.kind == MetricKind::Gauge));
+            // NOTE: The usage since boot
+            assert_eq!(metrics[0], Metric::gauge("cpu.percent", 25.0));
+        }
+
+        #[test]
+        fn cpu_count_changes_fall_back_to_the_usage_since_boot() {
+            let (previous, _) = snapshots();
+            let snapshot = snapshot(
+                SnapshotBuilder::at(time(10))
+                    .cpus(&[(15.0, 35.0), (35.0, 15.0)])
+                    .network(3000, 800)
+                    .disk(8192, 2048),
+            );
+
+            let metrics = snapshot_metrics(&snapshot, Some(&previous));
+
+            // NOTE: Not the 66.7% of the sums of both CPUs against the one before
+            assert_eq!(metrics[0], Metric::gauge("cpu.percent", 50.0));
+        }
+
+        #[test]
+        fn counters_going_backward_are_left_out() {
+            let (previous, _) = snapshots();
+            let snapshot = snapshot(
+                SnapshotBuilder::at(time(10))
+                    .cpu(1.0, 3.0)
+                    .network(10, 5)
+                    .disk(8192, 4096),
+            );
+
+            let metrics = snapshot_metrics(&snapshot, Some(&previous));
+
+            let counters = metrics
+                .iter()
+                .filter(|metric| metric.kind == MetricKind::Counter)
+                .map(|metric| metric.name.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(counters, ["disk.io.read_bytes", "disk.io.written_bytes"]);
+            assert_eq!(metrics[0], Metric::gauge("cpu.percent", 25.0));
+        }
+
+        #[test]
+        fn datagrams_are_sent_over_udp() {
+            let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+            server
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let (previous, snapshot) = snapshots();
+            let emitter =
+                StatsdEmitter::new(&server.local_addr().unwrap().to_string(), "host.").unwrap();
+
+            assert_eq!(emitter.emit(&snapshot, Some(&previous)).unwrap(), 1);
+
+            let mut buffer = [0; MAX_DATAGRAM_SIZE];
+            let len = server.recv(&mut buffer).unwrap();
+            let datagram = std::str::from_utf8(&buffer[..len]).unwrap();
+            assert!(
+                datagram.starts_with("host.cpu.percent:50|g\n"),
+                "{datagram}"
+            );
+            assert_eq!(datagram.lines().count(), 12);
+        }
+
+        #[test]
+        fn unresolved_addresses_are_errors() {
+            assert!(matches!(
+                StatsdEmitter::new("not an address", DEFAULT_PREFIX),
+                Err(Error::FailedToSendMetrics(_))
+            ));
+        }
+    }
+}
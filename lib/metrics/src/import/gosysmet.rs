@@ -0,0 +1,542 @@
+//! Historical snapshots from the database of the original Go sysmet.
+//!
+//! It is a bbolt file whose buckets hold one MessagePack encoded snapshot per key. The snapshots are made of
+//! the gopsutil structs psutil mirrors, so the counters are copied as is, only the names of the fields
+//! differ (`BytesRecv` for `bytes_recv`).
+use std::{collections::HashMap, fs, path::Path};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::{debug, tracing};
+use rmpv::Value;
+use serde_json::json;
+
+use super::ImportedSnapshot;
+use crate::{errors::Error, prelude::*, psutil::LoadAvg, Result};
+
+const BOLT_MAGIC: u32 = 0xED0C_DAED;
+const PAGE_HEADER_SIZE: usize = 16;
+/// Size of the elements of the branch and leaf pages, their keys and values following them.
+const ELEMENT_SIZE: usize = 16;
+/// Size of the header of a bucket, an inline bucket having its page right after it.
+const BUCKET_HEADER_SIZE: usize = 16;
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const BUCKET_LEAF: u32 = 0x01;
+/// Nesting of pages and buckets past which a corrupt file is assumed to loop.
+const MAX_DEPTH: usize = 64;
+/// Extension type of the MessagePack timestamps.
+const TIMESTAMP_EXTENSION: i8 = -1;
+
+/// Snapshots parsed from a Go sysmet database.
+#[derive(Debug, Default)]
+pub struct GoImport {
+    pub snapshots: Vec<ImportedSnapshot>,
+    /// Records that could not be decoded into a snapshot and unreadable pages.
+    pub rejected: usize,
+}
+
+/// Pages of a bbolt file, read from the most recent valid meta page.
+struct BoltFile<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    root: u64,
+}
+
+/// Key and value of the records of every bucket, with the number of pages and elements that could not be read.
+#[derive(Debug, Default)]
+struct Records<'a> {
+    records: Vec<(&'a [u8], &'a [u8])>,
+    corrupt: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// FNV-1a hash bbolt checks its meta pages with.
+fn fnv64a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl<'a> BoltFile<'a> {
+    fn open(data: &'a [u8]) -> Result<Self> {
+        // NOTE: The page size is read from the first meta page, the second one being at that offset
+        let meta = |offset: usize| -> Option<(u32, u64, u64)> {
+            let meta = data.get(offset + PAGE_HEADER_SIZE..offset + PAGE_HEADER_SIZE + 64)?;
+            let valid =
+                read_u32(meta, 0)? == BOLT_MAGIC && read_u64(meta, 56)? == fnv64a(&meta[..56]);
+            // NOTE: (page size, root bucket page, transaction)
+            valid.then_some((read_u32(meta, 8)?, read_u64(meta, 16)?, read_u64(meta, 48)?))
+        };
+        let first = meta(0);
+        let page_size = first.map_or(4096, |(page_size, _, _)| page_size as usize);
+        let (page_size, root, txid) = [first, meta(page_size)]
+            .into_iter()
+            .flatten()
+            .max_by_key(|(_, _, txid)| *txid)
+            .ok_or(Error::InvalidGoDatabase)?;
+        debug!(page_size, root, txid, "Opened bbolt file");
+
+        Ok(Self {
+            data,
+            page_size: page_size as usize,
+            root,
+        })
+    }
+
+    /// Bytes of the page `id` and of its overflow pages, cut at the end of a truncated file.
+    fn page(&self, id: u64) -> Option<&'a [u8]> {
+        let start = usize::try_from(id).ok()?.checked_mul(self.page_size)?;
+        let overflow = read_u32(self.data, start + 12)? as usize;
+        let end = start
+            .saturating_add((overflow + 1).saturating_mul(self.page_size))
+            .min(self.data.len());
+        self.data.get(start..end)
+    }
+
+    fn records(&self) -> Records<'a> {
+        let mut records = Records::default();
+        match self.page(self.root) {
+            Some(page) => self.walk(page, 0, &mut records),
+            None => records.corrupt += 1,
+        }
+        records
+    }
+
+    /// Records of the branch or leaf `page`, going down the sub-buckets.
+    fn walk(&self, page: &'a [u8], depth: usize, records: &mut Records<'a>) {
+        let (Some(flags), Some(count)) = (read_u16(page, 8), read_u16(page, 10)) else {
+            records.corrupt += 1;
+            return;
+        };
+        if depth > MAX_DEPTH {
+            records.corrupt += 1;
+            return;
+        }
+
+        for index in 0..count as usize {
+            let offset = PAGE_HEADER_SIZE + index * ELEMENT_SIZE;
+            let walked = match flags {
+                BRANCH_PAGE => read_u64(page, offset + 8)
+                    .and_then(|child| self.page(child))
+                    .map(|child| self.walk(child, depth + 1, records)),
+                LEAF_PAGE => Self::leaf_element(page, offset).map(|(flags, key, value)| {
+                    if flags & BUCKET_LEAF == 0 {
+                        records.records.push((key, value));
+                        return;
+                    }
+                    match read_u64(value, 0) {
+                        // NOTE: Small buckets are inlined in the value of their parent
+                        Some(0) => match value.get(BUCKET_HEADER_SIZE..) {
+                            Some(inline) => self.walk(inline, depth + 1, records),
+                            None => records.corrupt += 1,
+                        },
+                        Some(root) => match self.page(root) {
+                            Some(child) => self.walk(child, depth + 1, records),
+                            None => records.corrupt += 1,
+                        },
+                        None => records.corrupt += 1,
+                    }
+                }),
+                _ => None,
+            };
+            if walked.is_none() {
+                records.corrupt += 1;
+            }
+        }
+    }
+
+    /// Flags, key and value of the leaf element at `offset`, its key and value being relative to it.
+    fn leaf_element(page: &'a [u8], offset: usize) -> Option<(u32, &'a [u8], &'a [u8])> {
+        let flags = read_u32(page, offset)?;
+        let position = offset.checked_add(read_u32(page, offset + 4)? as usize)?;
+        let key_size = read_u32(page, offset + 8)? as usize;
+        let value_size = read_u32(page, offset + 12)? as usize;
+        let key = page.get(position..position.checked_add(key_size)?)?;
+        let value_start = position + key_size;
+        let value = page.get(value_start..value_start.checked_add(value_size)?)?;
+
+        Some((flags, key, value))
+    }
+}
+
+/// Read the Go sysmet database at `path`, see `parse_go_database`.
+#[tracing::instrument]
+pub fn import_go_database(path: &Path, networks_to_ignore: &[&str]) -> Result<GoImport> {
+    let data =
+        fs::read(path).map_err(|err| Error::FailedToReadGoDatabase(path.to_path_buf(), err))?;
+    parse_go_database(&data, networks_to_ignore)
+}
+
+/// Snapshots of every record of a Go sysmet database, the fields sysmet doesn't track are ignored and the
+/// ones Go sysmet lacks are left empty.
+///
+/// Only a file without any valid meta page is an error, corrupt pages and records are counted as rejected.
+#[tracing::instrument(skip(data))]
+pub fn parse_go_database(data: &[u8], networks_to_ignore: &[&str]) -> Result<GoImport> {
+    let file = BoltFile::open(data)?;
+    let Records { records, corrupt } = file.records();
+    let mut result = GoImport {
+        rejected: corrupt,
+        ..Default::default()
+    };
+
+    for (key, value) in records {
+        if let Some(snapshot) = snapshot_from_record(key, value, networks_to_ignore) {
+            result.snapshots.push(ImportedSnapshot {
+                snapshot,
+                // NOTE: Go sysmet has no interval, only a snapshot at the same second is a duplicate
+                interval: Duration::seconds(1),
+            });
+        } else {
+            debug!(key = ?String::from_utf8_lossy(key), "Rejected Go sysmet record");
+            result.rejected += 1;
+        }
+    }
+    debug!(
+        snapshots = result.snapshots.len(),
+        rejected = result.rejected,
+        "Parsed Go sysmet database"
+    );
+
+    Ok(result)
+}
+
+/// Lowercase name without underscores, so `BytesRecv`, `bytesRecv` and `bytes_recv` are the same field.
+fn normalized(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// First field of the map `value` named one of `names`, given normalized.
+fn field<'v>(value: &'v Value, names: &[&str]) -> Option<&'v Value> {
+    value.as_map()?.iter().find_map(|(key, value)| {
+        let key = normalized(key.as_str()?);
+        names.contains(&key.as_str()).then_some(value)
+    })
+}
+
+fn to_f64(value: &Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_i64().map(|value| value as f64))
+        .or_else(|| value.as_u64().map(|value| value as f64))
+}
+
+fn number(value: &Value, names: &[&str]) -> f64 {
+    field(value, names).and_then(to_f64).unwrap_or_default()
+}
+
+fn counter(value: &Value, names: &[&str]) -> u64 {
+    number(value, names).max(0.0) as u64
+}
+
+/// Time of a unix timestamp in seconds, milliseconds, microseconds or nanoseconds, told apart by magnitude.
+fn time_from_number(value: i64) -> Option<DateTime<Utc>> {
+    match value.unsigned_abs() {
+        0..=99_999_999_999 => Utc.timestamp_opt(value, 0).single(),
+        100_000_000_000..=99_999_999_999_999 => Utc.timestamp_millis_opt(value).single(),
+        100_000_000_000_000..=99_999_999_999_999_999 => Utc.timestamp_micros(value).single(),
+        _ => Some(Utc.timestamp_nanos(value)),
+    }
+}
+
+/// Time of a MessagePack timestamp, the way Go encodes `time.Time`.
+fn time_from_extension(bytes: &[u8]) -> Option<DateTime<Utc>> {
+    let (seconds, nanos) = match bytes.len() {
+        4 => (u32::from_be_bytes(bytes.try_into().ok()?) as i64, 0),
+        8 => {
+            let value = u64::from_be_bytes(bytes.try_into().ok()?);
+            ((value & 0x3_ffff_ffff) as i64, (value >> 34) as u32)
+        }
+        12 => (
+            i64::from_be_bytes(bytes[4..].try_into().ok()?),
+            u32::from_be_bytes(bytes[..4].try_into().ok()?),
+        ),
+        _ => return None,
+    };
+    Utc.timestamp_opt(seconds, nanos).single()
+}
+
+/// Time of a record from its `Time` field, or else from its key, either a big endian unix timestamp or a
+/// RFC3339 date.
+fn record_time(key: &[u8], value: &Value) -> Option<DateTime<Utc>> {
+    let from_value = field(value, &["time", "timestamp"]).and_then(|time| match time {
+        Value::Ext(TIMESTAMP_EXTENSION, bytes) => time_from_extension(bytes),
+        Value::String(text) => DateTime::parse_from_rfc3339(text.as_str()?)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        time => time.as_i64().and_then(time_from_number),
+    });
+    from_value.or_else(|| match key.len() {
+        8 => time_from_number(i64::from_be_bytes(key.try_into().ok()?)),
+        _ => DateTime::parse_from_rfc3339(std::str::from_utf8(key).ok()?)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+    })
+}
+
+fn duration(seconds: f64) -> serde_json::Value {
+    let duration = std::time::Duration::from_secs_f64(seconds.max(0.0));
+    json!({ "secs": duration.as_secs(), "nanos": duration.subsec_nanos() })
+}
+
+/// Snapshot of a record, `None` when it isn't MessagePack or has no time or CPU times.
+fn snapshot_from_record(key: &[u8], value: &[u8], networks_to_ignore: &[&str]) -> Option<SnapShot> {
+    let value = rmpv::decode::read_value(&mut &value[..]).ok()?;
+    let time = record_time(key, &value)?;
+    let cpus = field(&value, &["cpus", "cpu", "cputimes"])?
+        .as_array()?
+        .iter()
+        .map(|cpu| {
+            // NOTE: gopsutil reports the CPU times in seconds
+            serde_json::from_value(json!({
+                "user": duration(number(cpu, &["user"])),
+                "system": duration(number(cpu, &["system"])),
+                "idle": duration(number(cpu, &["idle"])),
+                "nice": duration(number(cpu, &["nice"])),
+                "iowait": duration(number(cpu, &["iowait"])),
+                "irq": duration(number(cpu, &["irq"])),
+                "softirq": duration(number(cpu, &["softirq"])),
+                "steal": duration(number(cpu, &["steal"])),
+                "guest": duration(number(cpu, &["guest"])),
+                "guest_nice": duration(number(cpu, &["guestnice"])),
+            }))
+            .ok()
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if cpus.is_empty() {
+        return None;
+    }
+
+    let empty = Value::Nil;
+    let memory = field(&value, &["memory", "mem", "virtualmemory"]).unwrap_or(&empty);
+    let swap = field(&value, &["swap", "swapmemory"]).unwrap_or(&empty);
+    let load = field(&value, &["load", "loadavg", "loadaverage"]).unwrap_or(&empty);
+    let networks = field(&value, &["network", "networks", "net", "netio"])
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|net| {
+            field(net, &["name"])
+                .and_then(Value::as_str)
+                .is_none_or(|name| !networks_to_ignore.contains(&name))
+        })
+        .map(|net| {
+            serde_json::from_value(json!({
+                "bytes_sent": counter(net, &["bytessent"]),
+                "bytes_recv": counter(net, &["bytesrecv"]),
+                "packets_sent": counter(net, &["packetssent"]),
+                "packets_recv": counter(net, &["packetsrecv"]),
+                "err_in": counter(net, &["errin"]),
+                "err_out": counter(net, &["errout"]),
+                "drop_in": counter(net, &["dropin"]),
+                "drop_out": counter(net, &["dropout"]),
+            }))
+            .ok()
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // NOTE: Go sysmet keeps the usage of the filesystems, without their IO counters
+    let disks_usage = field(&value, &["disks", "disk", "diskusage", "usage"])
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|disk| {
+            let path = field(disk, &["path", "mountpoint"])?.as_str()?;
+            Some((
+                path.to_string(),
+                DiskUsageSample {
+                    total: counter(disk, &["total"]),
+                    used: counter(disk, &["used"]),
+                    free: counter(disk, &["free"]),
+                    percent: number(disk, &["usedpercent", "percent"]) as f32,
+                },
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+
+    Some(SnapShot {
+        cpus,
+        memory: serde_json::from_value(json!({
+            "total": counter(memory, &["total"]),
+            "available": counter(memory, &["available"]),
+            "used": counter(memory, &["used"]),
+            "free": counter(memory, &["free"]),
+            "percent": number(memory, &["usedpercent", "percent"]) as f32,
+            "active": counter(memory, &["active"]),
+            "inactive": counter(memory, &["inactive"]),
+            "buffers": counter(memory, &["buffers"]),
+            "cached": counter(memory, &["cached"]),
+            "shared": counter(memory, &["shared"]),
+            "slab": counter(memory, &["slab"]),
+        }))
+        .ok()?,
+        swap: serde_json::from_value(json!({
+            "total": counter(swap, &["total"]),
+            "used": counter(swap, &["used"]),
+            "free": counter(swap, &["free"]),
+            "percent": number(swap, &["usedpercent", "percent"]) as f32,
+            "swapped_in": counter(swap, &["sin", "swappedin"]),
+            "swapped_out": counter(swap, &["sout", "swappedout"]),
+        }))
+        .ok()?,
+        networks,
+        disks_io: HashMap::new(),
+        disks_memory: disks_usage
+            .iter()
+            .map(|(path, usage)| (path.clone(), usage.percent))
+            .collect(),
+        disks_usage,
+        temps: Vec::new(),
+        load_avgs: LoadAvg {
+            one: number(load, &["load1", "one"]),
+            five: number(load, &["load5", "five"]),
+            fifteen: number(load, &["load15", "fifteen"]),
+        },
+        time,
+        host_facts: None,
+        clock_jump: false,
+        suspended: false,
+        custom: HashMap::new(),
+        cpu_freq: None,
+        collection_duration_ms: None,
+        collection_breakdown_ms: HashMap::new(),
+        kernel: None,
+        nic_info: HashMap::new(),
+        container: false,
+        cgroup: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::import::ImportReport;
+
+    /// Database written by `.scripts/go-sysmet-fixtures.py`.
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/gosysmet")
+            .join(name)
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 1, 2, 3, 4, 0).unwrap()
+    }
+
+    #[test]
+    fn fixture_fields_are_mapped() {
+        let import = import_go_database(&fixture("sysmet.db"), &["lo"]).unwrap();
+        assert_eq!(import.rejected, 0);
+        let times = import
+            .snapshots
+            .iter()
+            .map(|imported| imported.snapshot.time)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            (0..6)
+                .map(|minutes| start() + Duration::minutes(minutes))
+                .collect::<Vec<_>>()
+        );
+
+        let snapshot = &import.snapshots[1].snapshot;
+        assert_eq!(snapshot.cpus.len(), 1);
+        assert_eq!(snapshot.cpus[0].user().as_secs_f64(), 130.0);
+        assert_eq!(snapshot.cpus[0].idle().as_secs_f64(), 1024.0);
+        assert_eq!(snapshot.memory.total(), 8 << 30);
+        assert_eq!(snapshot.memory.used(), (2 << 30) + (1 << 20));
+        assert_eq!(snapshot.swap.total(), 1 << 30);
+        // NOTE: `lo` is ignored
+        assert_eq!(snapshot.networks.len(), 1);
+        assert_eq!(snapshot.networks[0].bytes_recv(), 4000);
+        assert_eq!(snapshot.networks[0].bytes_sent(), 1000);
+        assert_eq!(snapshot.disks_usage["/"].percent, 40.0);
+        assert_eq!(snapshot.disks_memory["/"], 40.0);
+        assert!(snapshot.disks_io.is_empty());
+        assert_eq!(snapshot.load_avgs.one, 0.6);
+        assert_eq!(snapshot.load_avgs.fifteen, 0.3);
+    }
+
+    #[test]
+    fn partially_corrupt_files_keep_the_readable_records() {
+        let import = import_go_database(&fixture("partially-corrupt.db"), &[]).unwrap();
+        // NOTE: The value that isn't MessagePack, the snapshot without CPUs and the page past the end
+        assert_eq!(import.rejected, 3);
+        assert_eq!(import.snapshots.len(), 6);
+        assert_eq!(import.snapshots[0].snapshot.networks.len(), 2);
+    }
+
+    #[test]
+    fn merges_count_the_imported_and_skipped_records() {
+        let import = import_go_database(&fixture("partially-corrupt.db"), &[]).unwrap();
+        let mut database = Database::default();
+        database.push_snapshot(import.snapshots[2].snapshot.clone());
+
+        let mut report = ImportReport {
+            rejected: import.rejected,
+            ..Default::default()
+        };
+        report.add(database.merge_imported(import.snapshots));
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 5,
+                duplicates: 1,
+                rejected: 3,
+            }
+        );
+        assert_eq!(database.snapshots().len(), 6);
+        assert!(database
+            .snapshots()
+            .windows(2)
+            .all(|pair| pair[0].time < pair[1].time));
+    }
+
+    #[test]
+    fn truncated_files_never_panic() {
+        let data = fs::read(fixture("sysmet.db")).unwrap();
+        for len in (0..data.len()).step_by(97) {
+            if let Ok(import) = parse_go_database(&data[..len], &[]) {
+                assert!(import.snapshots.len() <= 6);
+            }
+        }
+        // NOTE: Cut before the end of the first meta page, the second one being missing
+        assert!(matches!(
+            parse_go_database(&data[..64], &[]),
+            Err(Error::InvalidGoDatabase)
+        ));
+    }
+
+    #[test]
+    fn flipped_bytes_never_panic() {
+        let data = fs::read(fixture("partially-corrupt.db")).unwrap();
+        for index in (0..data.len()).step_by(13) {
+            let mut corrupt = data.clone();
+            corrupt[index] ^= 0xff;
+            let _ = parse_go_database(&corrupt, &[]);
+        }
+    }
+}
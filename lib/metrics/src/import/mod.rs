@@ -0,0 +1,86 @@
+pub mod gosysmet;
+pub mod sar;
+
+use chrono::Duration;
+use log::{debug, tracing};
+
+use crate::prelude::*;
+
+/// Snapshot rebuilt from the records of another tool.
+#[derive(Debug, Clone)]
+pub struct ImportedSnapshot {
+    pub snapshot: SnapShot,
+    /// Period covered by the record, ending at the snapshot time.
+    pub interval: Duration,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Records whose interval already holds a snapshot.
+    pub duplicates: usize,
+    /// Records that could not be turned into a snapshot.
+    pub rejected: usize,
+}
+
+impl ImportReport {
+    pub fn add(&mut self, other: ImportReport) {
+        self.imported += other.imported;
+        self.duplicates += other.duplicates;
+        self.rejected += other.rejected;
+    }
+}
+
+impl Database {
+    /// Insert the imported snapshots in time order, a record whose interval already holds a snapshot is skipped.
+    ///
+    /// The counters of imported snapshots are not comparable with the collected ones, so the snapshot
+    /// following a switch between both is marked as a clock jump and its interval is dropped from the rates.
+    #[tracing::instrument(skip_all)]
+    pub fn merge_imported(&mut self, mut imported: Vec<ImportedSnapshot>) -> ImportReport {
+        let mut report = ImportReport::default();
+        imported.sort_by_key(|record| record.snapshot.time);
+        self.snapshots.sort_by_key(|snapshot| snapshot.time);
+
+        let mut accepted: Vec<SnapShot> = Vec::new();
+        for ImportedSnapshot { snapshot, interval } in imported {
+            let start = snapshot.time - interval;
+            let index = self
+                .snapshots
+                .partition_point(|existing| existing.time <= start);
+            let taken = self
+                .snapshots
+                .get(index)
+                .is_some_and(|existing| existing.time <= snapshot.time)
+                || accepted.last().is_some_and(|last| last.time > start);
+            if taken {
+                report.duplicates += 1;
+            } else {
+                accepted.push(snapshot);
+            }
+        }
+        report.imported = accepted.len();
+        if accepted.is_empty() {
+            return report;
+        }
+
+        let mut merged = self
+            .snapshots
+            .drain(..)
+            .map(|snapshot| (snapshot, false))
+            .chain(accepted.into_iter().map(|snapshot| (snapshot, true)))
+            .collect::<Vec<_>>();
+        merged.sort_by_key(|(snapshot, _)| snapshot.time);
+        let mut previous_imported = None;
+        for (snapshot, imported) in merged.iter_mut() {
+            if previous_imported.is_some_and(|previous| previous != *imported) {
+                snapshot.clock_jump = true;
+            }
+            previous_imported = Some(*imported);
+        }
+        self.snapshots = merged.into_iter().map(|(snapshot, _)| snapshot).collect();
+        debug!(?report, "Merged imported snapshots");
+
+        report
+    }
+}
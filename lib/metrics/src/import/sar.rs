@@ -0,0 +1,685 @@
+//! Historical snapshots from the `sadf -j` JSON export of sysstat.
+//!
+//! sar records rates averaged over an interval while sysmet stores cumulative counters, so the
+//! counters are rebuilt from zero by adding `rate * interval` for every record.
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone, Utc};
+use log::{debug, tracing, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::ImportedSnapshot;
+use crate::{errors::Error, prelude::*, psutil::LoadAvg, Result};
+
+/// A record further than this many intervals from the previous one starts a new run.
+const MAX_MISSED_INTERVALS: i32 = 2;
+const SECTOR_SIZE: f64 = 512.0;
+
+#[derive(Debug, Deserialize)]
+struct SadfDocument {
+    sysstat: SadfSysstat,
+}
+
+#[derive(Debug, Deserialize)]
+struct SadfSysstat {
+    #[serde(default)]
+    hosts: Vec<SadfHost>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SadfHost {
+    #[serde(rename = "number-of-cpus", default)]
+    number_of_cpus: Option<usize>,
+    #[serde(default)]
+    statistics: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SadfRecord {
+    timestamp: SadfTimestamp,
+    #[serde(rename = "cpu-load", alias = "cpu-load-all", default)]
+    cpu_load: Vec<SadfCpu>,
+    #[serde(default)]
+    memory: Option<SadfMemory>,
+    #[serde(rename = "swap-pages", default)]
+    swap_pages: Option<SadfSwapPages>,
+    #[serde(default)]
+    queue: Option<SadfQueue>,
+    #[serde(default)]
+    network: Option<SadfNetwork>,
+    #[serde(default)]
+    disk: Vec<SadfDisk>,
+    #[serde(default)]
+    filesystems: Vec<SadfFilesystem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SadfTimestamp {
+    date: String,
+    time: String,
+    #[serde(default)]
+    utc: u8,
+    /// Seconds.
+    interval: u64,
+}
+
+/// Percentages of the CPU time.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfCpu {
+    cpu: String,
+    #[serde(alias = "usr")]
+    user: f64,
+    nice: f64,
+    #[serde(alias = "sys")]
+    system: f64,
+    iowait: f64,
+    steal: f64,
+    irq: f64,
+    soft: f64,
+    idle: f64,
+}
+
+/// Sizes in kB.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfMemory {
+    memfree: u64,
+    avail: u64,
+    memused: u64,
+    #[serde(rename = "memused-percent")]
+    memused_percent: f32,
+    buffers: u64,
+    cached: u64,
+    active: u64,
+    inactive: u64,
+    swpfree: u64,
+    swpused: u64,
+    #[serde(rename = "swpused-percent")]
+    swpused_percent: f32,
+}
+
+/// Pages per second.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfSwapPages {
+    pswpin: f64,
+    pswpout: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfQueue {
+    #[serde(rename = "ldavg-1")]
+    one: f64,
+    #[serde(rename = "ldavg-5")]
+    five: f64,
+    #[serde(rename = "ldavg-15")]
+    fifteen: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfNetwork {
+    #[serde(rename = "net-dev")]
+    net_dev: Vec<SadfNetDev>,
+}
+
+/// Rates per second.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfNetDev {
+    iface: String,
+    #[serde(rename = "rxpck")]
+    rx_packets: f64,
+    #[serde(rename = "txpck")]
+    tx_packets: f64,
+    #[serde(rename = "rxkB")]
+    rx_kb: f64,
+    #[serde(rename = "txkB")]
+    tx_kb: f64,
+}
+
+/// Rates per second, older sysstat versions report sectors instead of kB.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfDisk {
+    #[serde(rename = "disk-device")]
+    device: String,
+    #[serde(rename = "rkB")]
+    read_kb: Option<f64>,
+    #[serde(rename = "wkB")]
+    write_kb: Option<f64>,
+    #[serde(rename = "rd_sec")]
+    read_sectors: Option<f64>,
+    #[serde(rename = "wr_sec")]
+    write_sectors: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SadfFilesystem {
+    #[serde(rename = "filesystem", alias = "mountpoint")]
+    name: String,
+    #[serde(rename = "fsused-percent")]
+    used_percent: f32,
+    // NOTE: Missing from the reports of older sysstat versions
+    #[serde(rename = "MBfsfree")]
+    free_mb: Option<f64>,
+    #[serde(rename = "MBfsused")]
+    used_mb: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct CpuCounters {
+    user: f64,
+    nice: f64,
+    system: f64,
+    iowait: f64,
+    steal: f64,
+    irq: f64,
+    soft: f64,
+    idle: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct NetCounters {
+    bytes_sent: f64,
+    bytes_recv: f64,
+    packets_sent: f64,
+    packets_recv: f64,
+}
+
+/// Cumulative counters of one sar file, in seconds of one CPU, bytes or pages.
+#[derive(Debug, Default)]
+struct Counters {
+    cpu: CpuCounters,
+    swapped_in: f64,
+    swapped_out: f64,
+    networks: HashMap<String, NetCounters>,
+    disks: HashMap<String, (f64, f64)>,
+}
+
+/// Snapshots parsed from a `sadf -j` export.
+#[derive(Debug, Default)]
+pub struct SarImport {
+    pub snapshots: Vec<ImportedSnapshot>,
+    /// Records without a valid timestamp or CPU load.
+    pub rejected: usize,
+}
+
+/// Parse the output of `sadf -j`, the fields sysmet doesn't track are ignored.
+#[tracing::instrument(skip(json))]
+pub fn parse_sadf_json(json: &str, networks_to_ignore: &[&str]) -> Result<SarImport> {
+    let document: SadfDocument = serde_json::from_str(json)?;
+    let mut result = SarImport::default();
+    for host in document.sysstat.hosts {
+        let number_of_cpus = host.number_of_cpus.unwrap_or(1).max(1);
+        let mut counters = Counters::default();
+        let mut previous_time: Option<DateTime<Utc>> = None;
+        for statistic in host.statistics {
+            let record = match serde_json::from_value::<SadfRecord>(statistic) {
+                Ok(record) => record,
+                Err(err) => {
+                    debug!("Rejected sadf record: {err}");
+                    result.rejected += 1;
+                    continue;
+                }
+            };
+            let Some((time, interval)) = record_time(&record.timestamp) else {
+                debug!(timestamp = ?record.timestamp, "Rejected sadf record with an invalid timestamp");
+                result.rejected += 1;
+                continue;
+            };
+            let Some(cpu) = record.cpu_load.iter().find(|cpu| cpu.cpu == "all") else {
+                debug!(%time, "Rejected sadf record without CPU load");
+                result.rejected += 1;
+                continue;
+            };
+
+            let starts_run = previous_time.is_none_or(|previous| {
+                time <= previous || time - previous > interval * MAX_MISSED_INTERVALS
+            });
+            previous_time = Some(time);
+            match snapshot_from_record(
+                &record,
+                cpu,
+                time,
+                interval,
+                number_of_cpus,
+                &mut counters,
+                networks_to_ignore,
+            ) {
+                Ok(mut snapshot) => {
+                    snapshot.clock_jump = starts_run;
+                    result
+                        .snapshots
+                        .push(ImportedSnapshot { snapshot, interval });
+                }
+                Err(err) => {
+                    debug!(%time, "Rejected sadf record: {err}");
+                    result.rejected += 1;
+                }
+            }
+        }
+    }
+    debug!(
+        snapshots = result.snapshots.len(),
+        rejected = result.rejected,
+        "Parsed sadf JSON"
+    );
+
+    Ok(result)
+}
+
+/// JSON of a sar file, files holding JSON are read as is while binary sa files go through `sadf -j`.
+#[tracing::instrument]
+pub fn read_sar_file(path: &Path) -> Result<String> {
+    let content =
+        fs::read(path).map_err(|err| Error::FailedToReadSarFile(path.to_path_buf(), err))?;
+    if content.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{') {
+        return Ok(String::from_utf8_lossy(&content).into_owned());
+    }
+
+    debug!("Converting {path:?} with sadf");
+    let output = Command::new("sadf")
+        .arg("-j")
+        .arg(path)
+        .args(["--", "-A"])
+        .output()
+        .map_err(Error::FailedToRunSadf)?;
+    if !output.status.success() {
+        return Err(Error::SadfFailed(
+            path.to_path_buf(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The file itself, or the `sa*` and `*.json` files of a directory such as `/var/log/sa` in name order.
+#[tracing::instrument]
+pub fn sar_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = fs::read_dir(path)
+        .map_err(|err| Error::FailedToReadSarFile(path.to_path_buf(), err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            let name = file
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            // NOTE: sar* files are the text reports of sa1/sa2, only the binary sa<DD> files hold the data
+            let is_data = name
+                .strip_prefix("sa")
+                .is_some_and(|rest| rest.chars().next().is_some_and(|c| c.is_ascii_digit()));
+            file.is_file() && (is_data || name.ends_with(".json"))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    if files.is_empty() {
+        warn!("No sa or json file found in {path:?}");
+    }
+
+    Ok(files)
+}
+
+fn record_time(timestamp: &SadfTimestamp) -> Option<(DateTime<Utc>, Duration)> {
+    if timestamp.interval == 0 {
+        return None;
+    }
+    let naive = NaiveDateTime::parse_from_str(
+        &format!("{} {}", timestamp.date, timestamp.time),
+        "%Y-%m-%d %H:%M:%S",
+    )
+    .ok()?;
+    let time = if timestamp.utc == 1 {
+        Utc.from_utc_datetime(&naive)
+    } else {
+        Local
+            .from_local_datetime(&naive)
+            .earliest()?
+            .with_timezone(&Utc)
+    };
+
+    Some((time, Duration::seconds(timestamp.interval as i64)))
+}
+
+fn snapshot_from_record(
+    record: &SadfRecord,
+    cpu: &SadfCpu,
+    time: DateTime<Utc>,
+    interval: Duration,
+    number_of_cpus: usize,
+    counters: &mut Counters,
+    networks_to_ignore: &[&str],
+) -> Result<SnapShot> {
+    let seconds = interval.num_seconds() as f64;
+    let cpu_seconds = |percent: f64| percent.max(0.0) / 100.0 * seconds;
+    let cpu_counters = &mut counters.cpu;
+    cpu_counters.user += cpu_seconds(cpu.user);
+    cpu_counters.nice += cpu_seconds(cpu.nice);
+    cpu_counters.system += cpu_seconds(cpu.system);
+    cpu_counters.iowait += cpu_seconds(cpu.iowait);
+    cpu_counters.steal += cpu_seconds(cpu.steal);
+    cpu_counters.irq += cpu_seconds(cpu.irq);
+    cpu_counters.soft += cpu_seconds(cpu.soft);
+    cpu_counters.idle += cpu_seconds(cpu.idle);
+    // NOTE: sar only averages the CPUs with `-u`, so every CPU gets the average load
+    let cpu_times = serde_json::from_value(json!({
+        "user": duration(cpu_counters.user),
+        "system": duration(cpu_counters.system),
+        "idle": duration(cpu_counters.idle),
+        "nice": duration(cpu_counters.nice),
+        "iowait": duration(cpu_counters.iowait),
+        "irq": duration(cpu_counters.irq),
+        "softirq": duration(cpu_counters.soft),
+        "steal": duration(cpu_counters.steal),
+        "guest": null,
+        "guest_nice": null,
+    }))?;
+
+    let memory = record.memory.as_ref();
+    let default_memory = SadfMemory::default();
+    let memory = memory.unwrap_or(&default_memory);
+    if let Some(pages) = &record.swap_pages {
+        counters.swapped_in += pages.pswpin.max(0.0) * seconds;
+        counters.swapped_out += pages.pswpout.max(0.0) * seconds;
+    }
+    let memory_total = memory.memfree + memory.memused + memory.buffers + memory.cached;
+
+    let mut networks = Vec::new();
+    for net in record
+        .network
+        .as_ref()
+        .map(|network| network.net_dev.as_slice())
+        .unwrap_or_default()
+    {
+        if networks_to_ignore.contains(&net.iface.as_str()) {
+            continue;
+        }
+        let net_counters = counters.networks.entry(net.iface.clone()).or_default();
+        net_counters.bytes_recv += net.rx_kb.max(0.0) * 1024.0 * seconds;
+        net_counters.bytes_sent += net.tx_kb.max(0.0) * 1024.0 * seconds;
+        net_counters.packets_recv += net.rx_packets.max(0.0) * seconds;
+        net_counters.packets_sent += net.tx_packets.max(0.0) * seconds;
+        networks.push(serde_json::from_value(json!({
+            "bytes_sent": net_counters.bytes_sent as u64,
+            "bytes_recv": net_counters.bytes_recv as u64,
+            "packets_sent": net_counters.packets_sent as u64,
+            "packets_recv": net_counters.packets_recv as u64,
+            "err_in": 0,
+            "err_out": 0,
+            "drop_in": 0,
+            "drop_out": 0,
+        }))?);
+    }
+
+    let mut disks_io = HashMap::new();
+    for disk in &record.disk {
+        let read = disk
+            .read_kb
+            .map(|kb| kb * 1024.0)
+            .or(disk.read_sectors.map(|sectors| sectors * SECTOR_SIZE))
+            .unwrap_or_default();
+        let write = disk
+            .write_kb
+            .map(|kb| kb * 1024.0)
+            .or(disk.write_sectors.map(|sectors| sectors * SECTOR_SIZE))
+            .unwrap_or_default();
+        let (read_bytes, write_bytes) = counters.disks.entry(disk.device.clone()).or_default();
+        *read_bytes += read.max(0.0) * seconds;
+        *write_bytes += write.max(0.0) * seconds;
+        disks_io.insert(
+            disk.device.clone(),
+            serde_json::from_value(json!({
+                "read_count": 0,
+                "write_count": 0,
+                "read_bytes": *read_bytes as u64,
+                "write_bytes": *write_bytes as u64,
+                "read_time": duration(0.0),
+                "write_time": duration(0.0),
+                "busy_time": duration(0.0),
+                "read_merged_count": 0,
+                "write_merged_count": 0,
+            }))?,
+        );
+    }
+
+    Ok(SnapShot {
+        cpus: vec![cpu_times; number_of_cpus],
+        memory: serde_json::from_value(json!({
+            "total": memory_total * 1024,
+            "available": memory.avail * 1024,
+            "used": memory.memused * 1024,
+            "free": memory.memfree * 1024,
+            "percent": memory.memused_percent,
+            "active": memory.active * 1024,
+            "inactive": memory.inactive * 1024,
+            "buffers": memory.buffers * 1024,
+            "cached": memory.cached * 1024,
+            "shared": 0,
+            "slab": 0,
+        }))?,
+        swap: serde_json::from_value(json!({
+            "total": (memory.swpfree + memory.swpused) * 1024,
+            "used": memory.swpused * 1024,
+            "free": memory.swpfree * 1024,
+            "percent": memory.swpused_percent,
+            "swapped_in": counters.swapped_in as u64,
+            "swapped_out": counters.swapped_out as u64,
+        }))?,
+        networks,
+        disks_io,
+        disks_memory: record
+            .filesystems
+            .iter()
+            .map(|filesystem| (filesystem.name.clone(), filesystem.used_percent))
+            .collect(),
+        disks_usage: record
+            .filesystems
+            .iter()
+            .filter_map(|filesystem| {
+                let to_bytes = |mb: f64| (mb * 1024.0 * 1024.0) as u64;
+                let (free, used) = (filesystem.free_mb?, filesystem.used_mb?);
+                Some((
+                    filesystem.name.clone(),
+                    DiskUsageSample {
+                        total: to_bytes(free + used),
+                        used: to_bytes(used),
+                        free: to_bytes(free),
+                        percent: filesystem.used_percent,
+                    },
+                ))
+            })
+            .collect(),
+        temps: Vec::new(),
+        load_avgs: record.queue.as_ref().map_or(
+            LoadAvg {
+                one: 0.0,
+                five: 0.0,
+                fifteen: 0.0,
+            },
+            |queue| LoadAvg {
+                one: queue.one,
+                five: queue.five,
+                fifteen: queue.fifteen,
+            },
+        ),
+        time,
+        host_facts: None,
+        clock_jump: false,
+        suspended: false,
+        custom: HashMap::new(),
+        cpu_freq: None,
+        collection_duration_ms: None,
+        collection_breakdown_ms: HashMap::new(),
+        kernel: None,
+        nic_info: HashMap::new(),
+        container: false,
+        cgroup: None,
+    })
+}
+
+fn duration(seconds: f64) -> Value {
+    let duration = std::time::Duration::from_secs_f64(seconds.max(0.0));
+    json!({ "secs": duration.as_secs(), "nanos": duration.subsec_nanos() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::ImportReport;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/sar")
+            .join(name)
+    }
+
+    fn import(name: &str, networks_to_ignore: &[&str]) -> SarImport {
+        parse_sadf_json(&read_sar_file(&fixture(name)).unwrap(), networks_to_ignore).unwrap()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn counters_are_rebuilt_from_the_rates() {
+        let import = import("sysstat-12.json", &["lo"]);
+        assert_eq!(import.rejected, 0);
+        assert_eq!(import.snapshots.len(), 4);
+        let first = &import.snapshots[0];
+        assert_eq!(first.interval, Duration::minutes(10));
+        assert_eq!(first.snapshot.time, at("2024-05-03T10:10:01Z"));
+
+        let second = &import.snapshots[1].snapshot;
+        // NOTE: Every CPU gets the load of `all`, 12.5% then 13.5% of 600s
+        assert_eq!(second.cpus.len(), 2);
+        assert_eq!(second.cpus[1].user().as_secs_f64(), 156.0);
+        assert_eq!(second.cpus[0].system().as_secs_f64(), 30.0);
+        assert_eq!(
+            second.memory.total(),
+            (4194304 + 3145728 + 262144 + 786432) * 1024
+        );
+        assert_eq!(second.memory.used(), 3145728 * 1024);
+        assert_eq!(second.swap.total(), 1048576 * 1024);
+        assert_eq!(second.swap.swapped_in(), 600);
+        assert_eq!(second.networks.len(), 1);
+        assert_eq!(second.networks[0].bytes_recv(), 2 * 2 * 1024 * 600);
+        assert_eq!(second.networks[0].packets_sent(), 2 * 5 * 600);
+        assert_eq!(second.disks_io["sda"].read_bytes(), 2 * 4 * 1024 * 600);
+        assert_eq!(second.disks_io["sda"].write_bytes(), 2 * 8 * 1024 * 600);
+        assert_eq!(second.disks_usage["/dev/sda1"].total, 100 << 30);
+        assert_eq!(second.disks_memory["/dev/sda1"], 40.0);
+        assert_eq!(second.load_avgs.one, 0.6);
+    }
+
+    #[test]
+    fn gaps_start_new_runs() {
+        let import = import("sysstat-12.json", &[]);
+        let clock_jumps = import
+            .snapshots
+            .iter()
+            .map(|imported| imported.snapshot.clock_jump)
+            .collect::<Vec<_>>();
+        assert_eq!(clock_jumps, [true, false, false, true]);
+        assert_eq!(import.snapshots[0].snapshot.networks.len(), 2);
+    }
+
+    #[test]
+    fn older_sysstat_versions_are_read() {
+        let import = import("sysstat-10.json", &[]);
+        assert_eq!(import.rejected, 0);
+        assert_eq!(import.snapshots.len(), 2);
+        let snapshot = &import.snapshots[1].snapshot;
+        // NOTE: Without `utc` the times are local to the host
+        let local = Local
+            .with_ymd_and_hms(2019, 11, 20, 8, 10, 1)
+            .earliest()
+            .unwrap();
+        assert_eq!(snapshot.time, local.with_timezone(&Utc));
+        assert_eq!(snapshot.cpus.len(), 1);
+        assert_eq!(snapshot.cpus[0].user().as_secs_f64(), 2.0 * 30.0 * 3.0);
+        assert_eq!(snapshot.cpus[0].system().as_secs_f64(), 2.0 * 10.0 * 3.0);
+        // NOTE: Sectors of 512 bytes
+        assert_eq!(snapshot.disks_io["dev8-0"].read_bytes(), 2 * 8 * 512 * 300);
+        assert_eq!(snapshot.disks_memory["/dev/sda1"], 55.0);
+        assert!(snapshot.disks_usage.is_empty());
+        assert!(snapshot.networks.is_empty());
+        assert_eq!(snapshot.memory.total(), 0);
+    }
+
+    #[test]
+    fn invalid_records_are_rejected() {
+        let import = import("rejected.json", &[]);
+        // NOTE: An invalid time, a null interval, no CPU load, no timestamp and a memory that isn't an object
+        assert_eq!(import.rejected, 5);
+        let times = import
+            .snapshots
+            .iter()
+            .map(|imported| imported.snapshot.time)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            [at("2024-05-03T10:10:01Z"), at("2024-05-03T11:00:01Z")]
+        );
+    }
+
+    #[test]
+    fn merges_skip_the_intervals_already_held() {
+        let import = import("sysstat-12.json", &[]);
+        let mut database = Database::default();
+        // NOTE: Within the interval of the second record
+        let mut existing = import.snapshots[0].snapshot.clone();
+        existing.time = at("2024-05-03T10:15:00Z");
+        database.push_snapshot(existing);
+
+        assert_eq!(
+            database.merge_imported(import.snapshots),
+            ImportReport {
+                imported: 3,
+                duplicates: 1,
+                rejected: 0,
+            }
+        );
+        assert_eq!(database.snapshots().len(), 4);
+    }
+
+    #[test]
+    fn directories_list_their_sa_and_json_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["sa03", "sar03", "sa01", "export.json", "notes.txt"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let names = sar_files(dir.path())
+            .unwrap()
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["export.json", "sa01", "sa03"]);
+        assert_eq!(
+            sar_files(&fixture("sysstat-12.json")).unwrap(),
+            [fixture("sysstat-12.json")]
+        );
+    }
+
+    #[test]
+    fn invalid_documents_are_errors() {
+        assert!(parse_sadf_json("{\"sysstat\": ", &[]).is_err());
+        assert!(parse_sadf_json("[]", &[]).is_err());
+    }
+}
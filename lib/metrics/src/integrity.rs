@@ -0,0 +1,473 @@
+use std::{fmt, io::Read};
+
+use log::{debug, tracing, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{prelude::*, Result};
+
+// NOTE: Layout is MAGIC | FORMAT_VERSION, followed by the header record then one record per snapshot,
+// each record being its payload length (u32 LE) | CRC32 of the payload (u32 LE) | CBOR payload
+pub(crate) const MAGIC: &[u8; 8] = b"SYSMETCK";
+const FORMAT_VERSION: u8 = 1;
+const PREAMBLE_LEN: usize = MAGIC.len() + 1;
+const RECORD_HEADER_LEN: usize = 8;
+
+/// First record of a checksummed database.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RecordsHeader {
+    pub version: String,
+    pub hostname: Option<String>,
+    /// Number of snapshot records following the header.
+    pub records: u64,
+    // NOTE: Missing from headers written before annotations were introduced
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    // NOTE: Missing from headers written before the writer was recorded
+    #[serde(default)]
+    pub last_write: Option<LastWrite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordProblem {
+    ChecksumMismatch { expected: u32, actual: u32 },
+    Truncated,
+    Undecodable(String),
+}
+
+/// Snapshot record that was skipped while loading a checksummed database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptRecord {
+    pub index: usize,
+    /// Position of the record in the (decrypted) database bytes.
+    pub offset: usize,
+    pub problem: RecordProblem,
+}
+
+impl fmt::Display for CorruptRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "snapshot #{} at byte {}: ", self.index, self.offset)?;
+        match &self.problem {
+            RecordProblem::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {expected:08x}, got {actual:08x})"
+                )
+            }
+            RecordProblem::Truncated => write!(f, "truncated"),
+            RecordProblem::Undecodable(err) => write!(f, "undecodable ({err})"),
+        }
+    }
+}
+
+/// Result of checking the integrity of a database file.
+#[derive(Debug, Clone)]
+pub struct IntegrityReport {
+    /// Whether the database carries checksums, older databases can only be checked for decoding errors.
+    pub checksummed: bool,
+    pub snapshots: usize,
+    pub corrupt_records: Vec<CorruptRecord>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_records.is_empty()
+    }
+}
+
+pub(crate) struct Decoded {
+    pub header: RecordsHeader,
+    pub snapshots: Vec<SnapShot>,
+    pub corrupt_records: Vec<CorruptRecord>,
+}
+
+pub(crate) fn is_checksummed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn push_record<T: Serialize>(bytes: &mut Vec<u8>, value: &T) -> Result<()> {
+    let mut payload = Vec::new();
+    ciborium::ser::into_writer(value, &mut payload)?;
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "trace", skip(snapshots))]
+pub(crate) fn encode(header: &RecordsHeader, snapshots: &[SnapShot]) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(PREAMBLE_LEN);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    push_record(&mut bytes, header)?;
+    for snapshot in snapshots {
+        push_record(&mut bytes, snapshot)?;
+    }
+    debug!("Encoded {} checksummed records", snapshots.len() + 1);
+
+    Ok(bytes)
+}
+
+/// Read the record at the position of `reader` into `payload`, with the number of bytes it took.
+///
+/// `None` when the reader is at its end, the payload is only filled when the record is not truncated.
+fn read_record(
+    reader: &mut impl Read,
+    payload: &mut Vec<u8>,
+) -> Result<Option<(usize, std::result::Result<(), RecordProblem>)>> {
+    let mut record_header = Vec::with_capacity(RECORD_HEADER_LEN);
+    reader
+        .by_ref()
+        .take(RECORD_HEADER_LEN as u64)
+        .read_to_end(&mut record_header)
+        .map_err(Error::FailedToReadFile)?;
+    match record_header.len() {
+        0 => return Ok(None),
+        RECORD_HEADER_LEN => {}
+        read => return Ok(Some((read, Err(RecordProblem::Truncated)))),
+    }
+    let len = u32::from_le_bytes(record_header[..4].try_into().unwrap()) as usize;
+    let expected = u32::from_le_bytes(record_header[4..].try_into().unwrap());
+
+    // NOTE: The length is not trusted to size the allocation, the payload grows with what is actually read
+    payload.clear();
+    reader
+        .by_ref()
+        .take(len as u64)
+        .read_to_end(payload)
+        .map_err(Error::FailedToReadFile)?;
+    if payload.len() < len {
+        return Ok(Some((
+            RECORD_HEADER_LEN + payload.len(),
+            Err(RecordProblem::Truncated),
+        )));
+    }
+
+    let actual = crc32fast::hash(payload);
+    if actual != expected {
+        return Ok(Some((
+            RECORD_HEADER_LEN + len,
+            Err(RecordProblem::ChecksumMismatch { expected, actual }),
+        )));
+    }
+
+    Ok(Some((RECORD_HEADER_LEN + len, Ok(()))))
+}
+
+fn decode_payload<T: DeserializeOwned>(payload: &[u8]) -> std::result::Result<T, RecordProblem> {
+    ciborium::de::from_reader(payload).map_err(|err| RecordProblem::Undecodable(err.to_string()))
+}
+
+/// Decode a checksummed database of `len` bytes read from `reader`, corrupt snapshot records are
+/// skipped and reported.
+///
+/// Only one record is held at a time besides the decoded snapshots, so the database can be streamed from
+/// its file. A corrupt header fails the whole decoding as nothing after it can be trusted.
+#[tracing::instrument(level = "trace", skip(reader))]
+pub(crate) fn decode(mut reader: impl Read, len: usize) -> Result<Decoded> {
+    let mut preamble = Vec::with_capacity(PREAMBLE_LEN);
+    reader
+        .by_ref()
+        .take(PREAMBLE_LEN as u64)
+        .read_to_end(&mut preamble)
+        .map_err(Error::FailedToReadFile)?;
+    let version = *preamble
+        .get(MAGIC.len())
+        .ok_or_else(|| Error::CorruptDatabase("truncated preamble".to_string()))?;
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedChecksumVersion(version));
+    }
+
+    let mut payload = Vec::new();
+    let mut offset = PREAMBLE_LEN;
+    let header = match read_record(&mut reader, &mut payload)? {
+        Some((read, record)) => {
+            offset += read;
+            record.and_then(|()| decode_payload::<RecordsHeader>(&payload))
+        }
+        None => Err(RecordProblem::Truncated),
+    };
+    let header = match header {
+        Ok(header) => header,
+        Err(RecordProblem::ChecksumMismatch { expected, actual }) => {
+            return Err(Error::ChecksumMismatch {
+                portion: "header".to_string(),
+                expected,
+                actual,
+            })
+        }
+        Err(RecordProblem::Truncated) => {
+            return Err(Error::CorruptDatabase("truncated header".to_string()))
+        }
+        Err(RecordProblem::Undecodable(err)) => {
+            return Err(Error::CorruptDatabase(format!(
+                "undecodable header ({err})"
+            )))
+        }
+    };
+
+    // NOTE: The announced number of records is not trusted to size the allocation, each takes at least its header
+    let mut snapshots = Vec::with_capacity((header.records as usize).min(len / RECORD_HEADER_LEN));
+    let mut corrupt_records = Vec::new();
+    let mut index = 0;
+    while let Some((read, record)) = read_record(&mut reader, &mut payload)? {
+        match record.and_then(|()| decode_payload::<SnapShot>(&payload)) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(problem) => {
+                let is_truncated = problem == RecordProblem::Truncated;
+                let corrupt_record = CorruptRecord {
+                    index,
+                    offset,
+                    problem,
+                };
+                warn!("Skipping corrupt {corrupt_record}");
+                corrupt_records.push(corrupt_record);
+                // NOTE: The length of a record whose checksum failed may be the corrupt part,
+                // following records are still tried as a bad length shows up as checksum failures
+                if is_truncated {
+                    break;
+                }
+            }
+        }
+        offset += read;
+        index += 1;
+    }
+
+    // NOTE: A file cut right after a record is only noticed by the records missing after it
+    let cut = corrupt_records
+        .last()
+        .is_none_or(|record| record.problem != RecordProblem::Truncated);
+    if (index as u64) < header.records && cut {
+        corrupt_records.push(CorruptRecord {
+            index,
+            offset,
+            problem: RecordProblem::Truncated,
+        });
+    }
+    if index as u64 != header.records {
+        warn!(
+            "Database header announces {} snapshots but {index} records were found",
+            header.records
+        );
+    }
+    debug!(
+        "Decoded {} snapshots, {} corrupt records skipped",
+        snapshots.len(),
+        corrupt_records.len()
+    );
+
+    Ok(Decoded {
+        header,
+        snapshots,
+        corrupt_records,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testing;
+
+    const SNAPSHOTS: usize = 4;
+
+    fn header() -> RecordsHeader {
+        RecordsHeader {
+            version: "1.0.0".to_string(),
+            hostname: Some("fixture".to_string()),
+            records: SNAPSHOTS as u64,
+            annotations: Vec::new(),
+            last_write: None,
+        }
+    }
+
+    /// Checksummed database of `SNAPSHOTS` snapshots with the position of each of its snapshot records.
+    fn fixture() -> (Vec<u8>, Vec<usize>) {
+        let snapshots = testing::snapshots(SNAPSHOTS, 10);
+        let bytes = encode(&header(), &snapshots).unwrap();
+        let mut offsets = Vec::new();
+        let mut offset = PREAMBLE_LEN;
+        while offset < bytes.len() {
+            offsets.push(offset);
+            let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            offset += RECORD_HEADER_LEN + len;
+        }
+        // NOTE: The first record is the header
+        offsets.remove(0);
+        (bytes, offsets)
+    }
+
+    fn decode_bytes(bytes: &[u8]) -> Result<Decoded> {
+        decode(bytes, bytes.len())
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let (bytes, offsets) = fixture();
+
+        let decoded = decode_bytes(&bytes).unwrap();
+
+        assert!(is_checksummed(&bytes));
+        assert_eq!(offsets.len(), SNAPSHOTS);
+        assert_eq!(decoded.header.hostname.as_deref(), Some("fixture"));
+        assert!(decoded.corrupt_records.is_empty());
+        let times = decoded.snapshots.iter().map(|snapshot| snapshot.time);
+        assert!(times.eq(testing::snapshots(SNAPSHOTS, 10)
+            .iter()
+            .map(|snapshot| snapshot.time)));
+    }
+
+    #[test]
+    fn every_flipped_byte_is_detected() {
+        let (bytes, offsets) = fixture();
+        let original = testing::snapshots(SNAPSHOTS, 10)
+            .iter()
+            .map(|snapshot| (snapshot.time, serde_json::to_value(snapshot).unwrap()))
+            .collect::<Vec<_>>();
+
+        // NOTE: A flipped magic makes an unchecksummed database, which is not decoded here
+        for offset in MAGIC.len()..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[offset] ^= 0x5a;
+
+            let decoded = match decode_bytes(&flipped) {
+                Ok(decoded) => decoded,
+                // NOTE: Nothing can be trusted after a corrupt preamble or header
+                Err(err) => {
+                    assert!(offset < offsets[0], "byte {offset}: {err}");
+                    continue;
+                }
+            };
+            assert!(offset >= offsets[0], "byte {offset} of the header");
+            assert!(!decoded.corrupt_records.is_empty(), "byte {offset}");
+            // NOTE: The snapshots kept are never the corrupt ones
+            for snapshot in &decoded.snapshots {
+                let (_, expected) = original
+                    .iter()
+                    .find(|(time, _)| *time == snapshot.time)
+                    .unwrap();
+                assert_eq!(
+                    &serde_json::to_value(snapshot).unwrap(),
+                    expected,
+                    "byte {offset}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_payloads_only_skip_their_record() {
+        let (bytes, offsets) = fixture();
+
+        for (index, offset) in offsets.iter().enumerate() {
+            let mut flipped = bytes.clone();
+            flipped[offset + RECORD_HEADER_LEN + 3] ^= 0x01;
+
+            let decoded = decode_bytes(&flipped).unwrap();
+
+            assert_eq!(decoded.snapshots.len(), SNAPSHOTS - 1);
+            assert_eq!(decoded.corrupt_records.len(), 1);
+            let corrupt = &decoded.corrupt_records[0];
+            assert_eq!((corrupt.index, corrupt.offset), (index, *offset));
+            assert!(matches!(
+                corrupt.problem,
+                RecordProblem::ChecksumMismatch { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn cut_files_report_the_missing_records() {
+        let (bytes, offsets) = fixture();
+
+        // NOTE: Cut inside the fourth record, then right after the third one
+        for (len, problem) in [
+            (offsets[3] + 5, RecordProblem::Truncated),
+            (offsets[3], RecordProblem::Truncated),
+        ] {
+            let decoded = decode_bytes(&bytes[..len]).unwrap();
+
+            assert_eq!(decoded.snapshots.len(), 3);
+            assert_eq!(
+                decoded.corrupt_records,
+                vec![CorruptRecord {
+                    index: 3,
+                    offset: offsets[3],
+                    problem,
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_headers_and_versions_are_errors() {
+        let (bytes, offsets) = fixture();
+
+        let mut flipped = bytes.clone();
+        flipped[offsets[0] - 1] ^= 0x01;
+        assert!(matches!(
+            decode_bytes(&flipped),
+            Err(Error::ChecksumMismatch { portion, .. }) if portion == "header"
+        ));
+
+        let mut flipped = bytes.clone();
+        flipped[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            decode_bytes(&flipped),
+            Err(Error::UnsupportedChecksumVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+
+        assert!(matches!(
+            decode_bytes(&bytes[..PREAMBLE_LEN + 3]),
+            Err(Error::CorruptDatabase(_))
+        ));
+    }
+
+    #[test]
+    fn verified_files_report_their_corrupt_records() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db");
+        let path = path.to_str().unwrap();
+        let mut database = Database::default();
+        for snapshot in testing::snapshots(SNAPSHOTS, 10) {
+            database.push_snapshot(snapshot);
+        }
+        database.set_checksums(true);
+        database.write_to_file(path).unwrap();
+        let report = Database::verify_file(path, None).unwrap();
+        assert!(report.checksummed && report.is_ok());
+        assert_eq!(report.snapshots, SNAPSHOTS);
+
+        let mut bytes = std::fs::read(path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(path, &bytes).unwrap();
+        let report = Database::verify_file(path, None).unwrap();
+
+        assert!(!report.is_ok());
+        assert_eq!(report.snapshots, SNAPSHOTS - 1);
+        assert_eq!(report.corrupt_records[0].index, SNAPSHOTS - 1);
+        // NOTE: The corrupt snapshot is skipped by the loads too
+        let loaded = Database::from_file(path, None).unwrap();
+        assert_eq!(loaded.skipped_records(), 1);
+    }
+
+    #[test]
+    fn unchecksummed_files_are_verified_by_decoding_them() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sysmet.db");
+        let path = path.to_str().unwrap();
+        let mut database = Database::default();
+        for snapshot in testing::snapshots(SNAPSHOTS, 10) {
+            database.push_snapshot(snapshot);
+        }
+        database.set_checksums(false);
+        database.write_to_file(path).unwrap();
+
+        let report = Database::verify_file(path, None).unwrap();
+
+        assert!(!report.checksummed && report.is_ok());
+        assert_eq!(report.snapshots, SNAPSHOTS);
+    }
+}
@@ -1,20 +1,70 @@
 #[cfg(feature = "database")]
+pub mod aggregate;
+#[cfg(feature = "database")]
+pub mod backup;
+#[cfg(feature = "database")]
 pub mod database;
+#[cfg(feature = "demo")]
+pub mod demo;
+#[cfg(feature = "diff")]
+pub mod diff;
+#[cfg(feature = "database")]
+pub mod encryption;
+#[cfg(feature = "import")]
+pub mod import;
+#[cfg(feature = "database")]
+pub mod integrity;
+#[cfg(feature = "database")]
+pub mod retry;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "database")]
+pub mod series;
+#[cfg(feature = "database")]
+pub mod shards;
 #[cfg(feature = "thresholds")]
 pub mod thresholds;
 
+pub mod annotations;
+pub mod clock;
+pub mod collectors;
 pub mod errors;
+pub mod export;
+pub mod processes;
 pub mod psutil;
+pub mod rates;
 pub mod snapshot;
+pub mod stats;
+pub mod suspend;
+#[cfg(all(test, feature = "serde"))]
+pub(crate) mod testing;
 
 pub mod prelude {
     #[cfg(feature = "database")]
-    pub use super::database::Database;
+    pub use super::backup::BackupPolicy;
+    #[cfg(feature = "database")]
+    pub use super::database::{
+        pending_dir, CollectionCounts, Database, LastWrite, PendingMerge, Pruned, Writer,
+    };
+    #[cfg(feature = "database")]
+    pub use super::encryption::DatabaseKey;
+    #[cfg(feature = "import")]
+    pub use super::import::{ImportReport, ImportedSnapshot};
+    #[cfg(feature = "database")]
+    pub use super::integrity::IntegrityReport;
+    #[cfg(feature = "database")]
+    pub use super::retry::{with_retry, RetryPolicy};
+    #[cfg(feature = "database")]
+    pub use super::series::{MetricKind, Point};
+    #[cfg(feature = "database")]
+    pub use super::shards::{ShardPeriod, ShardedDatabase};
     #[cfg(feature = "thresholds")]
     pub use super::thresholds::*;
 
+    pub use super::annotations::{Annotation, AnnotationKind};
+    pub use super::collectors::{Collector, CollectorRegistry, FileCollector};
     pub use super::errors::Error;
-    pub use super::snapshot::SnapShot;
+    pub use super::snapshot::{DiskUsageSample, SnapShot};
 
     pub fn get_hostname() -> String {
         ::psutil::host::info().hostname().to_string()
@@ -0,0 +1,141 @@
+//! Processes using the most resources, captured when an alert fires.
+use std::{thread, time::Duration};
+
+use crate::Result;
+
+/// Time the CPU usage of the processes is measured over.
+pub const CPU_SAMPLE: Duration = Duration::from_millis(500);
+
+/// Usage of a running process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    pub name: String,
+    /// Percent of one core, above 100 for processes busy on several cores.
+    pub cpu_percent: f32,
+    /// Resident memory.
+    pub memory_bytes: u64,
+    pub memory_percent: f32,
+}
+
+/// Processes using the most CPU and the most memory, e.g. to explain an alert.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TopProcesses {
+    pub by_cpu: Vec<ProcessUsage>,
+    pub by_memory: Vec<ProcessUsage>,
+}
+
+impl TopProcesses {
+    /// The `count` processes using the most CPU over `sample` and the most memory, processes exiting or
+    /// unreadable (e.g. of other users) meanwhile being left out.
+    pub fn current(count: usize, sample: Duration) -> Result<Self> {
+        let mut running = psutil::process::processes()?
+            .into_iter()
+            .filter_map(|process| process.ok())
+            .collect::<Vec<_>>();
+        // NOTE: The CPU usage is the one since the previous call, this one starts the sample of every process
+        for process in running.iter_mut() {
+            let _ = process.cpu_percent();
+        }
+        thread::sleep(sample);
+
+        let usages = running
+            .iter_mut()
+            .filter_map(|process| {
+                Some(ProcessUsage {
+                    pid: process.pid(),
+                    name: process.name().ok()?,
+                    cpu_percent: process.cpu_percent().ok()?,
+                    memory_bytes: process.memory_info().ok()?.rss(),
+                    memory_percent: process.memory_percent().ok()?,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let top = |key: fn(&ProcessUsage) -> f32| {
+            let mut usages = usages.clone();
+            usages.sort_by(|a, b| key(b).total_cmp(&key(a)));
+            usages.truncate(count);
+            usages
+        };
+        Ok(Self {
+            by_cpu: top(|usage| usage.cpu_percent),
+            by_memory: top(|usage| usage.memory_percent),
+        })
+    }
+}
+
+/// Aligned table of the processes with a header line, e.g. `  1234   97.0%    2.1%   340.5 MiB  postgres`.
+pub fn format_process_table(processes: &[ProcessUsage]) -> String {
+    let mut table = format!(
+        "{:>7} {:>7} {:>7} {:>11}  {}\n",
+        "PID", "CPU", "MEM", "RSS", "NAME"
+    );
+    for process in processes {
+        table.push_str(&format!(
+            "{:>7} {:>6.1}% {:>6.1}% {:>7.1} MiB  {}\n",
+            process.pid,
+            process.cpu_percent,
+            process.memory_percent,
+            process.memory_bytes as f64 / (1024.0 * 1024.0),
+            process.name
+        ));
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_tables_are_aligned_under_their_header() {
+        let processes = [
+            ProcessUsage {
+                pid: 1234,
+                name: "postgres".to_string(),
+                cpu_percent: 97.04,
+                memory_bytes: 340 * 1024 * 1024 + 512 * 1024,
+                memory_percent: 2.1,
+            },
+            ProcessUsage {
+                pid: 1,
+                name: "init".to_string(),
+                cpu_percent: 250.0,
+                memory_bytes: 0,
+                memory_percent: 0.0,
+            },
+        ];
+
+        assert_eq!(
+            format_process_table(&processes),
+            "    PID     CPU     MEM         RSS  NAME\n\
+            \x20  1234   97.0%    2.1%   340.5 MiB  postgres\n\
+            \x20     1  250.0%    0.0%     0.0 MiB  init\n"
+        );
+        assert_eq!(
+            format_process_table(&[]),
+            "    PID     CPU     MEM         RSS  NAME\n"
+        );
+    }
+
+    #[test]
+    fn top_processes_are_the_busiest_first() {
+        let top = TopProcesses::current(3, Duration::from_millis(10)).unwrap();
+
+        for (processes, key) in [
+            (
+                &top.by_cpu,
+                (|usage| usage.cpu_percent) as fn(&ProcessUsage) -> f32,
+            ),
+            (&top.by_memory, |usage| usage.memory_percent),
+        ] {
+            // NOTE: At least the process of the tests is readable
+            assert!((1..=3).contains(&processes.len()), "{processes:?}");
+            assert!(processes
+                .windows(2)
+                .all(|pair| key(&pair[0]) >= key(&pair[1])));
+        }
+    }
+}
@@ -1,3 +1,9 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -24,3 +30,723 @@ impl LoadAvg {
         })
     }
 }
+
+/// Frequency of the CPUs averaged over the cores reporting it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CpuFreq {
+    pub current_mhz: f64,
+    /// Max frequency without throttling, 0 when unknown.
+    pub max_mhz: f64,
+}
+
+impl CpuFreq {
+    /// Frequency of the running host, `None` without the cpufreq sysfs files (e.g. outside of Linux or in
+    /// containers).
+    pub fn current() -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Self::from_sysfs(Path::new("/sys/devices/system/cpu"))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Average of the `cpu<N>/cpufreq` files under `cpu_dir`, cores missing one of them being skipped.
+    pub fn from_sysfs(cpu_dir: &Path) -> Option<Self> {
+        // NOTE: Frequencies are in kHz
+        let read_khz = |path: &Path| -> Option<f64> {
+            std::fs::read_to_string(path)
+                .ok()?
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .map(|khz| khz as f64)
+        };
+
+        let cores = std::fs::read_dir(cpu_dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_prefix("cpu"))
+                    .is_some_and(|index| {
+                        !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())
+                    })
+            })
+            .filter_map(|entry| {
+                let cpufreq = entry.path().join("cpufreq");
+                Some((
+                    read_khz(&cpufreq.join("scaling_cur_freq"))?,
+                    read_khz(&cpufreq.join("cpuinfo_max_freq"))?,
+                ))
+            })
+            .collect::<Vec<_>>();
+        if cores.is_empty() {
+            return None;
+        }
+
+        let count = cores.len() as f64;
+        let (current, max) = cores
+            .iter()
+            .fold((0.0, 0.0), |(current, max), (core_current, core_max)| {
+                (current + core_current, max + core_max)
+            });
+        Some(Self {
+            current_mhz: current / count / 1000.0,
+            max_mhz: max / count / 1000.0,
+        })
+    }
+
+    /// Current frequency in percent of the max one, `None` when the max is unknown.
+    pub fn percent_of_max(&self) -> Option<f64> {
+        (self.max_mhz > 0.0).then(|| self.current_mhz / self.max_mhz * 100.0)
+    }
+}
+
+/// Names of the kernel gauges, in display order.
+pub const KERNEL_GAUGES: [&str; 4] = ["entropy", "file-handles", "conntrack", "threads"];
+
+/// Current value of a kernel table and its size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KernelGauge {
+    pub value: f64,
+    /// `None` for gauges without a max.
+    pub max: Option<f64>,
+}
+
+impl KernelGauge {
+    /// Value in percent of the max, `None` without a max.
+    pub fn percent(&self) -> Option<f64> {
+        self.max
+            .filter(|max| *max > 0.0)
+            .map(|max| self.value / max * 100.0)
+    }
+}
+
+/// Small kernel tables whose exhaustion causes odd outages (e.g. refused connections when the conntrack
+/// table is full), by name of `KERNEL_GAUGES`.
+///
+/// Gauges the kernel doesn't have (e.g. conntrack without the module loaded) are missing from the map.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KernelGauges(pub BTreeMap<String, KernelGauge>);
+
+impl KernelGauges {
+    /// Gauges of the running host, `None` outside of Linux.
+    pub fn current() -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Self::from_procfs(Path::new("/proc"))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Gauges read from the files under `proc_dir`, each one being left out when one of its files can't be
+    /// read, `None` when none could.
+    pub fn from_procfs(proc_dir: &Path) -> Option<Self> {
+        let read_fields = |path: &str| -> Option<Vec<String>> {
+            Some(
+                std::fs::read_to_string(proc_dir.join(path))
+                    .ok()?
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+            )
+        };
+        let read_number = |path: &str| -> Option<f64> { read_fields(path)?.first()?.parse().ok() };
+
+        let mut gauges = BTreeMap::new();
+        let mut insert = |name: &str, gauge: Option<KernelGauge>| {
+            if let Some(gauge) = gauge {
+                gauges.insert(name.to_string(), gauge);
+            }
+        };
+        insert(
+            "entropy",
+            read_number("sys/kernel/random/entropy_avail").map(|value| KernelGauge {
+                value,
+                max: read_number("sys/kernel/random/poolsize"),
+            }),
+        );
+        // NOTE: Allocated handles, free ones among them and max
+        insert(
+            "file-handles",
+            read_fields("sys/fs/file-nr").and_then(|fields| {
+                let number = |idx: usize| fields.get(idx)?.parse::<f64>().ok();
+                Some(KernelGauge {
+                    value: number(0)? - number(1)?,
+                    max: number(2),
+                })
+            }),
+        );
+        insert(
+            "conntrack",
+            read_number("sys/net/netfilter/nf_conntrack_count").map(|value| KernelGauge {
+                value,
+                max: read_number("sys/net/netfilter/nf_conntrack_max"),
+            }),
+        );
+        // NOTE: The 4th field of loadavg is running/existing scheduling entities, i.e. threads
+        insert(
+            "threads",
+            read_fields("loadavg").and_then(|fields| {
+                Some(KernelGauge {
+                    value: fields.get(3)?.split_once('/')?.1.parse().ok()?,
+                    max: read_number("sys/kernel/threads-max"),
+                })
+            }),
+        );
+
+        (!gauges.is_empty()).then_some(Self(gauges))
+    }
+
+    /// Gauge in percent of its max, `None` when it is missing or has no max.
+    pub fn percent(&self, gauge: &str) -> Option<f64> {
+        self.0.get(gauge)?.percent()
+    }
+}
+
+/// Link of a network interface and its byte counters, read together so the rates and the speed they are
+/// compared to come from the same instant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NicInfo {
+    /// Negotiated link speed in Mbit/s, `None` for virtual interfaces (e.g. `lo`, bridges, tunnels) and links
+    /// that are down, which report `-1` or nothing.
+    pub speed_mbps: Option<u64>,
+    /// `operstate` is `up`.
+    pub up: bool,
+    pub bytes_recv: u64,
+    pub bytes_sent: u64,
+}
+
+impl NicInfo {
+    /// Interfaces of the running host by name, empty outside of Linux.
+    pub fn current() -> HashMap<String, Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Self::from_sysfs(Path::new("/sys/class/net"))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            HashMap::new()
+        }
+    }
+
+    /// Interfaces under `net_dir`, one directory per interface, those without byte counters being skipped.
+    pub fn from_sysfs(net_dir: &Path) -> HashMap<String, Self> {
+        let read = |path: PathBuf| -> Option<String> {
+            Some(std::fs::read_to_string(path).ok()?.trim().to_string())
+        };
+
+        let Ok(entries) = std::fs::read_dir(net_dir) else {
+            return HashMap::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.to_string();
+                let nic_dir = entry.path();
+                let statistics = nic_dir.join("statistics");
+                let info = Self {
+                    // NOTE: Reading the speed of a link that is down fails with EINVAL
+                    speed_mbps: read(nic_dir.join("speed"))
+                        .and_then(|speed| speed.parse::<i64>().ok())
+                        .filter(|speed| *speed > 0)
+                        .map(|speed| speed as u64),
+                    up: read(nic_dir.join("operstate")).as_deref() == Some("up"),
+                    bytes_recv: read(statistics.join("rx_bytes"))?.parse().ok()?,
+                    bytes_sent: read(statistics.join("tx_bytes"))?.parse().ok()?,
+                };
+                Some((name, info))
+            })
+            .collect()
+    }
+
+    /// Link capacity in bytes per second in each direction, `None` without a known speed.
+    pub fn capacity_bytes_per_sec(&self) -> Option<f64> {
+        self.speed_mbps
+            .map(|speed| speed as f64 * 1_000_000.0 / 8.0)
+    }
+}
+
+/// Limits and usage of the cgroup v2 of the process, read from its `memory.*` and `cpu.*` files.
+///
+/// Limits set to `max` are unlimited and stored as `None`, the usages of the host apply to them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CgroupLimits {
+    pub memory_max: Option<u64>,
+    pub memory_current: Option<u64>,
+    /// Cores the CPU quota allows, e.g. `1.5` for `150000 100000`.
+    pub cpu_max_cores: Option<f64>,
+    /// CPU time used by the cgroup since its creation, in microseconds.
+    pub cpu_usage_usec: Option<u64>,
+}
+
+impl CgroupLimits {
+    /// Limits of the cgroup of the running process, `None` outside of Linux or without cgroup v2.
+    pub fn current() -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Self::from_cgroup_dir(Path::new("/sys/fs/cgroup"))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Limits read from the files of `cgroup_dir`, `None` when it isn't a cgroup v2 directory.
+    pub fn from_cgroup_dir(cgroup_dir: &Path) -> Option<Self> {
+        // NOTE: Only cgroup v2 has this file, v1 splits the controllers in separate hierarchies
+        if !cgroup_dir.join("cgroup.controllers").exists() {
+            return None;
+        }
+        let read = |name: &str| -> Option<String> {
+            Some(
+                std::fs::read_to_string(cgroup_dir.join(name))
+                    .ok()?
+                    .trim()
+                    .to_string(),
+            )
+        };
+        let limit = |value: &str| -> Option<u64> {
+            match value {
+                "max" => None,
+                value => value.parse().ok(),
+            }
+        };
+
+        Some(Self {
+            memory_max: read("memory.max").as_deref().and_then(limit),
+            memory_current: read("memory.current").and_then(|value| value.parse().ok()),
+            // NOTE: `<quota> <period>` in microseconds, the quota being `max` without limit
+            cpu_max_cores: read("cpu.max").and_then(|value| {
+                let (quota, period) = value.split_once(' ')?;
+                let quota = limit(quota)? as f64;
+                let period = period.parse::<f64>().ok()?;
+                (period > 0.0).then(|| quota / period)
+            }),
+            cpu_usage_usec: read("cpu.stat").and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))?
+                    .trim()
+                    .parse()
+                    .ok()
+            }),
+        })
+    }
+
+    /// Memory used in percent of the limit, `None` without a limit.
+    pub fn memory_percent(&self) -> Option<f64> {
+        let (current, max) = self.memory_current.zip(self.memory_max)?;
+        (max > 0).then(|| current as f64 / max as f64 * 100.0)
+    }
+
+    /// CPU time in seconds and cores allowed by the quota, `None` without a quota.
+    pub fn cpu_quota(&self) -> Option<(f64, f64)> {
+        Some((
+            self.cpu_usage_usec? as f64 / 1_000_000.0,
+            self.cpu_max_cores?,
+        ))
+    }
+}
+
+/// Whether the process runs in a container (Docker, Podman, LXC, Kubernetes), from the files under `root`.
+pub fn detect_container(root: &Path) -> bool {
+    const RUNTIMES: [&str; 5] = ["docker", "lxc", "kubepods", "containerd", "libpod"];
+
+    if root.join(".dockerenv").exists() || root.join("run/.containerenv").exists() {
+        return true;
+    }
+    // NOTE: The cgroup of init names its runtime, unless hidden by a cgroup namespace
+    let init_cgroup = std::fs::read_to_string(root.join("proc/1/cgroup")).unwrap_or_default();
+    if RUNTIMES.iter().any(|runtime| init_cgroup.contains(runtime)) {
+        return true;
+    }
+    // NOTE: Set by LXC and systemd-nspawn, only readable by root
+    std::fs::read(root.join("proc/1/environ"))
+        .unwrap_or_default()
+        .split(|byte| *byte == 0)
+        .any(|variable| variable.starts_with(b"container="))
+}
+
+/// Whether the running process is in a container, always `false` outside of Linux.
+pub fn in_container() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        detect_container(Path::new("/"))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        false
+    }
+}
+
+/// Facts about the host that only change on upgrades and reboots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HostFacts {
+    /// Kernel release, e.g. `6.6.8-arch1-1`.
+    pub kernel: String,
+    /// Distribution name and version, e.g. `Debian GNU/Linux 12 (bookworm)`.
+    pub os: Option<String>,
+    pub boot_time: Option<DateTime<Utc>>,
+    /// Identifier of the boot, changing on each reboot whatever the clock does, `None` outside of Linux and
+    /// for snapshots taken before it was recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub boot_id: Option<String>,
+}
+
+impl HostFacts {
+    /// Facts of the running host.
+    pub fn current() -> Self {
+        let info = psutil::host::info();
+        Self {
+            kernel: info.release().to_string(),
+            os: os_release(),
+            boot_time: boot_time(),
+            boot_id: boot_id(),
+        }
+    }
+
+    /// Both facts were taken during the same boot, `None` when either has no boot identifier.
+    pub fn same_boot(&self, other: &Self) -> Option<bool> {
+        self.boot_id
+            .as_ref()
+            .zip(other.boot_id.as_ref())
+            .map(|(boot_id, other)| boot_id == other)
+    }
+}
+
+/// `PRETTY_NAME` of the os-release file.
+fn os_release() -> Option<String> {
+    ["/etc/os-release", "/usr/lib/os-release"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())?
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|value| value.trim_matches('"').to_string())
+}
+
+// NOTE: The boot time can't identify a boot, it moves along with the clock
+fn boot_id() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+            .ok()
+            .map(|boot_id| boot_id.trim().to_string())
+            .filter(|boot_id| !boot_id.is_empty())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+fn boot_time() -> Option<DateTime<Utc>> {
+    #[cfg(target_os = "linux")]
+    {
+        psutil::host::boot_time().ok().map(DateTime::<Utc>::from)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Writes `content` to `path` under `dir`, creating its parents.
+    fn write(dir: &TempDir, path: &str, content: &str) {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn cpu_frequencies_are_averaged_over_the_cores() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "cpu0/cpufreq/scaling_cur_freq", "2000000\n");
+        write(&dir, "cpu0/cpufreq/cpuinfo_max_freq", "4000000\n");
+        write(&dir, "cpu1/cpufreq/scaling_cur_freq", "1000000\n");
+        write(&dir, "cpu1/cpufreq/cpuinfo_max_freq", "4000000\n");
+        // NOTE: Neither cores nor cores with both files
+        write(&dir, "cpu2/cpufreq/scaling_cur_freq", "800000\n");
+        write(&dir, "cpu3/cpufreq/scaling_cur_freq", "fast\n");
+        write(&dir, "cpu3/cpufreq/cpuinfo_max_freq", "4000000\n");
+        write(&dir, "cpufreq/policy0/scaling_cur_freq", "100\n");
+        write(&dir, "cpuidle/current_driver", "intel_idle\n");
+
+        assert_eq!(
+            CpuFreq::from_sysfs(dir.path()),
+            Some(CpuFreq {
+                current_mhz: 1500.0,
+                max_mhz: 4000.0,
+            })
+        );
+    }
+
+    #[test]
+    fn cpu_frequencies_need_the_cpufreq_files() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(CpuFreq::from_sysfs(dir.path()), None);
+        assert_eq!(CpuFreq::from_sysfs(&dir.path().join("missing")), None);
+
+        // NOTE: Containers often have the cores without their cpufreq directory
+        write(&dir, "cpu0/topology/core_id", "0\n");
+        assert_eq!(CpuFreq::from_sysfs(dir.path()), None);
+    }
+
+    #[test]
+    fn cpu_frequencies_are_in_percent_of_the_max() {
+        let frequency = |current_mhz, max_mhz| CpuFreq {
+            current_mhz,
+            max_mhz,
+        };
+
+        assert_eq!(frequency(1500.0, 3000.0).percent_of_max(), Some(50.0));
+        // NOTE: Turbo boost goes past the max without throttling
+        assert_eq!(frequency(4500.0, 3000.0).percent_of_max(), Some(150.0));
+        assert_eq!(frequency(1500.0, 0.0).percent_of_max(), None);
+    }
+
+    /// Procfs of a Linux host with every kernel gauge.
+    fn procfs() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "sys/kernel/random/entropy_avail", "256\n");
+        write(&dir, "sys/kernel/random/poolsize", "4096\n");
+        write(&dir, "sys/fs/file-nr", "3000\t1000\t100000\n");
+        write(&dir, "sys/net/netfilter/nf_conntrack_count", "180\n");
+        write(&dir, "sys/net/netfilter/nf_conntrack_max", "200\n");
+        write(&dir, "loadavg", "0.52 0.58 0.59 2/512 12345\n");
+        write(&dir, "sys/kernel/threads-max", "2048\n");
+        dir
+    }
+
+    #[test]
+    fn kernel_gauges_are_read_from_procfs() {
+        let dir = procfs();
+
+        let gauges = KernelGauges::from_procfs(dir.path()).unwrap();
+
+        let gauge = |value, max| KernelGauge {
+            value,
+            max: Some(max),
+        };
+        assert_eq!(
+            gauges.0.into_iter().collect::<Vec<_>>(),
+            [
+                ("conntrack".to_string(), gauge(180.0, 200.0)),
+                ("entropy".to_string(), gauge(256.0, 4096.0)),
+                // NOTE: The free handles are not in use
+                ("file-handles".to_string(), gauge(2000.0, 100_000.0)),
+                ("threads".to_string(), gauge(512.0, 2048.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn kernel_gauges_are_in_percent_of_their_max() {
+        let gauges = KernelGauges::from_procfs(procfs().path()).unwrap();
+
+        assert_eq!(gauges.percent("conntrack"), Some(90.0));
+        assert_eq!(gauges.percent("entropy"), Some(6.25));
+        assert_eq!(gauges.percent("file-handles"), Some(2.0));
+        assert_eq!(gauges.percent("threads"), Some(25.0));
+        assert_eq!(gauges.percent("unknown"), None);
+        let without_max = KernelGauge {
+            value: 10.0,
+            max: None,
+        };
+        assert_eq!(without_max.percent(), None);
+        let zero_max = KernelGauge {
+            value: 10.0,
+            max: Some(0.0),
+        };
+        assert_eq!(zero_max.percent(), None);
+    }
+
+    #[test]
+    fn kernel_gauges_missing_from_the_kernel_are_left_out() {
+        let dir = procfs();
+        // NOTE: No conntrack module loaded, an unreadable file-nr and a kernel without threads-max
+        std::fs::remove_dir_all(dir.path().join("sys/net/netfilter")).unwrap();
+        write(&dir, "sys/fs/file-nr", "3000\n");
+        std::fs::remove_file(dir.path().join("sys/kernel/threads-max")).unwrap();
+
+        let gauges = KernelGauges::from_procfs(dir.path()).unwrap();
+
+        assert_eq!(
+            gauges.0.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["entropy", "threads"]
+        );
+        assert_eq!(gauges.0["threads"].max, None);
+        assert_eq!(gauges.percent("threads"), None);
+        assert_eq!(gauges.percent("conntrack"), None);
+    }
+
+    #[test]
+    fn kernel_gauges_need_procfs() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(KernelGauges::from_procfs(dir.path()), None);
+
+        write(&dir, "loadavg", "not a load average\n");
+        assert_eq!(KernelGauges::from_procfs(dir.path()), None);
+    }
+
+    /// Interface `name` under `dir` with its counters, and its speed and operstate when given.
+    fn nic(dir: &TempDir, name: &str, speed: Option<&str>, operstate: &str) {
+        write(dir, &format!("{name}/statistics/rx_bytes"), "4000\n");
+        write(dir, &format!("{name}/statistics/tx_bytes"), "1000\n");
+        write(dir, &format!("{name}/operstate"), &format!("{operstate}\n"));
+        if let Some(speed) = speed {
+            write(dir, &format!("{name}/speed"), &format!("{speed}\n"));
+        }
+    }
+
+    #[test]
+    fn links_are_read_from_sysfs() {
+        let dir = TempDir::new().unwrap();
+        nic(&dir, "eth0", Some("1000"), "up");
+        // NOTE: Virtual interfaces report -1 or nothing, links that are down fail to read their speed
+        nic(&dir, "lo", None, "unknown");
+        nic(&dir, "docker0", Some("-1"), "up");
+        nic(&dir, "eth1", None, "down");
+        // NOTE: Without byte counters the interface is skipped
+        write(&dir, "bond0/speed", "2000\n");
+
+        let links = NicInfo::from_sysfs(dir.path());
+
+        let link = |speed_mbps, up| NicInfo {
+            speed_mbps,
+            up,
+            bytes_recv: 4000,
+            bytes_sent: 1000,
+        };
+        assert_eq!(
+            links.into_iter().collect::<BTreeMap<_, _>>(),
+            BTreeMap::from([
+                ("docker0".to_string(), link(None, true)),
+                ("eth0".to_string(), link(Some(1000), true)),
+                ("eth1".to_string(), link(None, false)),
+                ("lo".to_string(), link(None, false)),
+            ])
+        );
+        assert!(NicInfo::from_sysfs(&dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn link_capacities_are_in_bytes_per_second() {
+        let link = |speed_mbps| NicInfo {
+            speed_mbps,
+            up: true,
+            bytes_recv: 0,
+            bytes_sent: 0,
+        };
+
+        assert_eq!(link(Some(8)).capacity_bytes_per_sec(), Some(1_000_000.0));
+        assert_eq!(
+            link(Some(10_000)).capacity_bytes_per_sec(),
+            Some(1_250_000_000.0)
+        );
+        assert_eq!(link(None).capacity_bytes_per_sec(), None);
+    }
+
+    /// Files of a cgroup v2 directory limited to `memory_max` bytes and `cpu_max` (`<quota> <period>`).
+    fn cgroup(memory_max: &str, cpu_max: &str) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "cgroup.controllers", "cpuset cpu io memory pids\n");
+        write(&dir, "memory.max", &format!("{memory_max}\n"));
+        write(&dir, "memory.current", "268435456\n");
+        write(&dir, "cpu.max", &format!("{cpu_max}\n"));
+        write(
+            &dir,
+            "cpu.stat",
+            "usage_usec 2500000\nuser_usec 2000000\nsystem_usec 500000\n",
+        );
+        dir
+    }
+
+    #[test]
+    fn cgroup_limits_are_read_from_the_cgroup_files() {
+        let dir = cgroup("1073741824", "150000 100000");
+
+        let limits = CgroupLimits::from_cgroup_dir(dir.path()).unwrap();
+
+        assert_eq!(
+            limits,
+            CgroupLimits {
+                memory_max: Some(1024 * 1024 * 1024),
+                memory_current: Some(256 * 1024 * 1024),
+                cpu_max_cores: Some(1.5),
+                cpu_usage_usec: Some(2_500_000),
+            }
+        );
+        assert_eq!(limits.memory_percent(), Some(25.0));
+        assert_eq!(limits.cpu_quota(), Some((2.5, 1.5)));
+    }
+
+    #[test]
+    fn max_cgroup_limits_are_unlimited() {
+        let dir = cgroup("max", "max 100000");
+
+        let limits = CgroupLimits::from_cgroup_dir(dir.path()).unwrap();
+
+        assert_eq!((limits.memory_max, limits.cpu_max_cores), (None, None));
+        assert_eq!(limits.memory_current, Some(256 * 1024 * 1024));
+        // NOTE: The usages of the host apply instead
+        assert_eq!(limits.memory_percent(), None);
+        assert_eq!(limits.cpu_quota(), None);
+    }
+
+    #[test]
+    fn cgroup_limits_need_cgroup_v2() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "memory/memory.limit_in_bytes", "1073741824\n");
+
+        assert_eq!(CgroupLimits::from_cgroup_dir(dir.path()), None);
+        assert_eq!(
+            CgroupLimits::from_cgroup_dir(&dir.path().join("missing")),
+            None
+        );
+    }
+
+    #[test]
+    fn containers_are_detected_from_their_marks() {
+        let detected = |path: &str, content: &[u8]| {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+            detect_container(dir.path())
+        };
+
+        assert!(detected(".dockerenv", b""));
+        assert!(detected("run/.containerenv", b""));
+        assert!(detected(
+            "proc/1/cgroup",
+            b"0::/system.slice/docker-0123abcd.scope\n"
+        ));
+        assert!(detected("proc/1/environ", b"PATH=/bin\0container=lxc\0"));
+        assert!(!detected("proc/1/cgroup", b"0::/init.scope\n"));
+        assert!(!detected("proc/1/environ", b"PATH=/bin\0HOME=/root\0"));
+        assert!(!detect_container(
+            &TempDir::new().unwrap().path().join("missing")
+        ));
+    }
+}
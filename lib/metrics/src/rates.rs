@@ -0,0 +1,257 @@
+use chrono::{DateTime, Utc};
+use log::{trace, tracing};
+
+use crate::snapshot::SnapShot;
+
+/// Difference between two readings of a monotonic counter, `None` when it went backward (counter reset or reboot).
+pub fn counter_delta(previous: f64, current: f64) -> Option<f64> {
+    if current >= previous {
+        Some(current - previous)
+    } else {
+        trace!(previous, current, "Counter went backward");
+        None
+    }
+}
+
+/// Error of a rate over `elapsed` seconds computed from a counter counting in steps of `resolution`.
+///
+/// Each reading is truncated to a whole step, so the delta of two of them is off by up to one step.
+pub fn rate_error(resolution: f64, elapsed: f64) -> f64 {
+    if elapsed > 0.0 {
+        resolution / elapsed
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// Lowest rate over `elapsed` seconds whose error is at most `max_relative_error` of it, the rates below it
+/// can't be told apart from the steps of the counter.
+pub fn noise_floor(resolution: f64, elapsed: f64, max_relative_error: f64) -> f64 {
+    rate_error(resolution, elapsed) / max_relative_error
+}
+
+/// The number of CPUs changed between two snapshots (CPU hotplug or a resized VM), the CPU times summed over
+/// them jumping like after a counter reset.
+pub fn cpu_count_changed(previous: &SnapShot, current: &SnapShot) -> bool {
+    previous.get_cpu_count() != current.get_cpu_count()
+}
+
+/// The host rebooted between two snapshots, known from their boot identifiers.
+///
+/// The counters restart from zero on a reboot, which `counter_delta` only notices when they end up below
+/// their previous values: after a long uptime followed by a short one they don't. Snapshots without a boot
+/// identifier are never considered rebooted, their counters going backward being left to `counter_delta`.
+pub fn rebooted(previous: &SnapShot, current: &SnapShot) -> bool {
+    previous
+        .host_facts
+        .as_ref()
+        .zip(current.host_facts.as_ref())
+        .and_then(|(previous, current)| previous.same_boot(current))
+        == Some(false)
+}
+
+/// Samples of the snapshots for `per_interval`, taken by `sample` and leaving out the snapshots without one.
+/// The intervals across a reboot are dropped like those ending at a clock jump or a suspension.
+pub fn samples<'a, T>(
+    snapshots: &'a [SnapShot],
+    sample: impl Fn(&'a SnapShot) -> Option<T>,
+) -> Vec<(T, DateTime<Utc>, bool)> {
+    let mut previous: Option<&SnapShot> = None;
+    snapshots
+        .iter()
+        .filter_map(|snapshot| {
+            let value = sample(snapshot)?;
+            let across_reboot = previous.is_some_and(|previous| rebooted(previous, snapshot));
+            previous = Some(snapshot);
+            Some((
+                value,
+                snapshot.time,
+                snapshot.interval_untrusted() || across_reboot,
+            ))
+        })
+        .collect()
+}
+
+/// Samples of the CPU times of the snapshots for `per_interval`, taken by `times`. The intervals during which
+/// the number of CPUs changed are dropped like those across a reboot or ending at a clock jump or a
+/// suspension.
+pub fn cpu_samples<T>(
+    snapshots: &[SnapShot],
+    times: impl Fn(&SnapShot) -> T,
+) -> Vec<(T, DateTime<Utc>, bool)> {
+    snapshots
+        .iter()
+        .enumerate()
+        .map(|(idx, snapshot)| {
+            let reset = idx.checked_sub(1).is_some_and(|previous| {
+                cpu_count_changed(&snapshots[previous], snapshot)
+                    || rebooted(&snapshots[previous], snapshot)
+            });
+            (
+                times(snapshot),
+                snapshot.time,
+                snapshot.interval_untrusted() || reset,
+            )
+        })
+        .collect()
+}
+
+/// Map every pair of consecutive samples to a value for the interval between them.
+///
+/// Samples are (value, time, whether the interval since the previous sample is untrusted, see
+/// `SnapShot::interval_untrusted`). The closure receives the previous sample, the current one and
+/// the interval length in seconds, the result is timestamped with the current sample time.
+/// Intervals with a non positive length, untrusted or for which the closure returns `None` are
+/// dropped.
+#[tracing::instrument(level = "trace", skip_all)]
+pub fn per_interval<T, R>(
+    samples: &[(T, DateTime<Utc>, bool)],
+    f: impl Fn(&T, &T, f64) -> Option<R>,
+) -> Vec<(R, DateTime<Utc>)> {
+    samples
+        .windows(2)
+        .filter_map(|window| {
+            let (previous, previous_time, _) = &window[0];
+            let (current, time, clock_jump) = &window[1];
+            let elapsed = (*time - *previous_time).num_milliseconds() as f64 / 1000.0;
+            if elapsed <= 0.0 || *clock_jump {
+                trace!(elapsed, clock_jump, "Interval dropped");
+                return None;
+            }
+
+            f(previous, current, elapsed).map(|value| (value, *time))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_going_backward_have_no_delta() {
+        assert_eq!(counter_delta(10.0, 25.0), Some(15.0));
+        assert_eq!(counter_delta(10.0, 10.0), Some(0.0));
+        assert_eq!(counter_delta(10.0, 9.0), None);
+        assert_eq!(counter_delta(1e12, 0.0), None);
+    }
+
+    #[test]
+    fn rate_errors_shrink_with_the_interval() {
+        assert_eq!(rate_error(1.0, 10.0), 0.1);
+        assert_eq!(rate_error(1.0, 0.0), f64::INFINITY);
+        assert_eq!(noise_floor(1.0, 10.0, 0.05), 2.0);
+    }
+
+    #[test]
+    fn noise_floors_scale_with_the_interval() {
+        // NOTE: A page over a second is only told apart from none at 2 pages/s, with half of it as error
+        let every_second = noise_floor(1.0, 1.0, 0.5);
+        let every_10_minutes = noise_floor(1.0, 600.0, 0.5);
+
+        assert_eq!(every_second, 2.0);
+        assert!((every_10_minutes - 1.0 / 300.0).abs() < 1e-12);
+        assert!((every_second / every_10_minutes - 600.0).abs() < 1e-9);
+        // NOTE: Counters in coarser steps raise the floor as much
+        assert_eq!(noise_floor(4096.0, 1.0, 0.5), 4096.0 * every_second);
+    }
+
+    #[test]
+    fn intervals_are_dropped_when_untrusted_or_not_positive() {
+        let at = |seconds| chrono::TimeZone::timestamp_opt(&Utc, seconds, 0).unwrap();
+        let samples = [
+            (0.0, at(0), false),
+            (10.0, at(10), false),
+            // NOTE: The clock went back
+            (20.0, at(5), false),
+            (30.0, at(20), true),
+            (40.0, at(30), false),
+            (35.0, at(40), false),
+        ];
+
+        let rates = per_interval(&samples, |previous, current, elapsed| {
+            counter_delta(*previous, *current).map(|delta| delta / elapsed)
+        });
+
+        assert_eq!(rates, vec![(1.0, at(10)), (1.0, at(30))]);
+    }
+
+    #[cfg(feature = "serde")]
+    mod snapshots {
+        use super::super::*;
+        use crate::{
+            psutil::HostFacts,
+            testing::{time, SnapshotBuilder},
+        };
+
+        fn booted(snapshot: SnapShot, boot_id: &str) -> SnapShot {
+            SnapShot {
+                host_facts: Some(HostFacts {
+                    kernel: "6.6.8".to_string(),
+                    os: None,
+                    boot_time: None,
+                    boot_id: Some(boot_id.to_string()),
+                }),
+                ..snapshot
+            }
+        }
+
+        #[test]
+        fn reboots_are_known_from_the_boot_identifiers() {
+            let first = booted(SnapshotBuilder::at(time(0)).build(), "a");
+            let same = booted(SnapshotBuilder::at(time(10)).build(), "a");
+            let other = booted(SnapshotBuilder::at(time(20)).build(), "b");
+            let unknown = SnapshotBuilder::at(time(30)).build();
+
+            assert!(!rebooted(&first, &same));
+            assert!(rebooted(&same, &other));
+            assert!(!rebooted(&other, &unknown));
+        }
+
+        #[test]
+        fn cpu_intervals_across_resets_are_untrusted() {
+            let snapshots = [
+                SnapshotBuilder::at(time(0)).cpu(10.0, 10.0).build(),
+                SnapshotBuilder::at(time(10)).cpu(15.0, 15.0).build(),
+                SnapshotBuilder::at(time(20))
+                    .cpus(&[(20.0, 20.0), (1.0, 1.0)])
+                    .build(),
+                SnapshotBuilder::at(time(30))
+                    .cpus(&[(25.0, 25.0), (6.0, 6.0)])
+                    .build(),
+                booted(
+                    SnapshotBuilder::at(time(40))
+                        .cpus(&[(30.0, 30.0), (11.0, 11.0)])
+                        .build(),
+                    "a",
+                ),
+                booted(
+                    SnapshotBuilder::at(time(50))
+                        .cpus(&[(1.0, 1.0), (1.0, 1.0)])
+                        .build(),
+                    "b",
+                ),
+            ];
+
+            let untrusted = cpu_samples(&snapshots, SnapShot::get_cpu_time)
+                .into_iter()
+                .map(|(_, _, untrusted)| untrusted)
+                .collect::<Vec<_>>();
+
+            assert_eq!(untrusted, [false, false, true, false, false, true]);
+        }
+
+        #[test]
+        fn samples_skip_the_snapshots_without_one() {
+            let snapshots = [
+                SnapshotBuilder::at(time(0)).custom("queue", 1.0).build(),
+                SnapshotBuilder::at(time(10)).build(),
+                SnapshotBuilder::at(time(20)).custom("queue", 3.0).build(),
+            ];
+
+            let samples = samples(&snapshots, |snapshot| snapshot.custom.get("queue").copied());
+
+            assert_eq!(samples, vec![(1.0, time(0), false), (3.0, time(20), false)]);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Retries of database reads failing for reasons that go away by themselves, e.g. `sysmet-update` holding
+//! the lock while it writes.
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    thread::sleep,
+    time::Duration,
+};
+
+use log::{debug, tracing, warn};
+
+use crate::{prelude::*, Result};
+
+/// How many times a read is attempted and how long to wait between two attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts including the first one, `1` never retries.
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled after each failed attempt.
+    pub initial_delay: Duration,
+    /// Fraction of each wait added or removed at random (e.g. `0.2` for ±20%), so readers failing together
+    /// don't retry together.
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Policy of the reads a user is waiting for, giving up quickly so the error is shown instead.
+    pub const INTERACTIVE: Self = Self {
+        max_attempts: 3,
+        initial_delay: Duration::from_millis(200),
+        jitter: 0.2,
+    };
+    /// Policy of the reads in the background, where waiting is cheaper than skipping the read.
+    pub const BACKGROUND: Self = Self {
+        max_attempts: 5,
+        initial_delay: Duration::from_millis(500),
+        jitter: 0.2,
+    };
+
+    /// Wait after the failed `attempt` (starting at 1), before jitter.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+
+    /// `delay` moved by a random fraction of at most `jitter` of it.
+    fn jittered_delay(&self, attempt: u32) -> Duration {
+        // NOTE: Randomly seeded hasher, good enough to spread retries without a RNG dependency
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let factor = 1.0 + self.jitter.clamp(0.0, 1.0) * (random * 2.0 - 1.0);
+        self.delay(attempt).mul_f64(factor)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::INTERACTIVE
+    }
+}
+
+impl Error {
+    /// Whether the same read may succeed later, never the case for data that can't be decoded.
+    pub fn is_transient(&self) -> bool {
+        let transient_io = |err: &std::io::Error| {
+            matches!(
+                err.kind(),
+                ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+            )
+        };
+        match self {
+            Error::LockFileTimeout(_) => true,
+            Error::FailedToOpenFile(err)
+            | Error::FailedToGetFileMetadata(err)
+            | Error::FailedToReadFile(err) => transient_io(err),
+            _ => false,
+        }
+    }
+}
+
+/// Result of `read`, attempted again as long as it fails with a transient error and `policy` allows it.
+#[tracing::instrument(level = "debug", skip(read), fields(attempts = tracing::field::Empty))]
+pub fn with_retry<T>(policy: &RetryPolicy, mut read: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        let result = read();
+        match result {
+            Err(err) if err.is_transient() && attempt < policy.max_attempts => {
+                let delay = policy.jittered_delay(attempt);
+                debug!("Attempt {attempt} failed ({err}), retrying in {delay:?}");
+                sleep(delay);
+                attempt += 1;
+            }
+            result => {
+                tracing::Span::current().record("attempts", attempt);
+                if let Err(err) = &result {
+                    if err.is_transient() {
+                        warn!("Giving up after {attempt} attempts: {err}");
+                    }
+                }
+                return result;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, path::PathBuf, time::Instant};
+
+    use super::*;
+
+    /// Policy of the tests, short enough to keep them fast but long enough to measure.
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        initial_delay: Duration::from_millis(10),
+        jitter: 0.5,
+    };
+
+    fn lock_timeout() -> Error {
+        Error::LockFileTimeout(PathBuf::from("sysmet.db.lock"))
+    }
+
+    /// Read failing with `err` the first `failures` times then succeeding, with the number of calls.
+    fn flaky(failures: u32, err: fn() -> Error) -> (Cell<u32>, impl Fn(&Cell<u32>) -> Result<u32>) {
+        let read = move |calls: &Cell<u32>| {
+            calls.set(calls.get() + 1);
+            if calls.get() <= failures {
+                Err(err())
+            } else {
+                Ok(calls.get())
+            }
+        };
+        (Cell::new(0), read)
+    }
+
+    #[test]
+    fn transient_errors_are_attempted_again() {
+        let (calls, read) = flaky(2, lock_timeout);
+
+        assert_eq!(with_retry(&POLICY, || read(&calls)).unwrap(), 3);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn attempts_stop_at_the_max() {
+        let (calls, read) = flaky(10, lock_timeout);
+
+        let err = with_retry(&POLICY, || read(&calls)).unwrap_err();
+
+        assert!(matches!(err, Error::LockFileTimeout(_)), "{err:?}");
+        assert_eq!(calls.get(), POLICY.max_attempts);
+
+        let (calls, read) = flaky(10, lock_timeout);
+        let once = RetryPolicy {
+            max_attempts: 1,
+            ..POLICY
+        };
+        assert!(with_retry(&once, || read(&calls)).is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn errors_that_are_not_transient_are_returned_at_once() {
+        let decoding = || {
+            Error::CborDeserialize(ciborium::de::Error::Semantic(
+                None,
+                "not a database".to_string(),
+            ))
+        };
+        let not_found = || Error::FailedToOpenFile(std::io::Error::from(ErrorKind::NotFound));
+        for err in [decoding, not_found] {
+            let (calls, read) = flaky(10, err);
+
+            assert!(with_retry(&POLICY, || read(&calls)).is_err());
+            assert_eq!(calls.get(), 1);
+        }
+    }
+
+    #[test]
+    fn transient_errors_are_lock_timeouts_and_interrupted_io() {
+        let io = |kind| std::io::Error::from(kind);
+
+        assert!(lock_timeout().is_transient());
+        assert!(Error::FailedToReadFile(io(ErrorKind::Interrupted)).is_transient());
+        assert!(Error::FailedToOpenFile(io(ErrorKind::WouldBlock)).is_transient());
+        assert!(Error::FailedToGetFileMetadata(io(ErrorKind::TimedOut)).is_transient());
+        assert!(!Error::FailedToReadFile(io(ErrorKind::UnexpectedEof)).is_transient());
+        assert!(!Error::FailedToOpenFile(io(ErrorKind::PermissionDenied)).is_transient());
+        assert!(!Error::FailedToWriteFile(io(ErrorKind::Interrupted)).is_transient());
+    }
+
+    #[test]
+    fn delays_double_after_each_attempt_within_the_jitter() {
+        assert_eq!(POLICY.delay(1), Duration::from_millis(10));
+        assert_eq!(POLICY.delay(2), Duration::from_millis(20));
+        assert_eq!(POLICY.delay(3), Duration::from_millis(40));
+        for attempt in 1..=3 {
+            let delay = POLICY.delay(attempt);
+            for _ in 0..100 {
+                let jittered = POLICY.jittered_delay(attempt);
+                assert!(
+                    delay.mul_f64(0.5) <= jittered && jittered <= delay.mul_f64(1.5),
+                    "{jittered:?}"
+                );
+            }
+        }
+        // NOTE: Large attempts saturate instead of overflowing
+        assert_eq!(
+            RetryPolicy::BACKGROUND.delay(u32::MAX),
+            Duration::from_millis(500).saturating_mul(u32::MAX)
+        );
+    }
+
+    #[test]
+    fn total_waits_follow_the_policy() {
+        let (calls, read) = flaky(10, lock_timeout);
+        let started = Instant::now();
+
+        assert!(with_retry(&POLICY, || read(&calls)).is_err());
+
+        // NOTE: Waits of 10, 20 and 40ms, each at least halved and at most half longer by the jitter
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(35), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(105 + 500), "{elapsed:?}");
+    }
+
+    #[test]
+    fn background_reads_are_more_patient_than_interactive_ones() {
+        let (interactive, background) = (RetryPolicy::INTERACTIVE, RetryPolicy::BACKGROUND);
+
+        assert!(background.max_attempts > interactive.max_attempts);
+        assert!(background.initial_delay > interactive.initial_delay);
+        assert_eq!(RetryPolicy::default(), interactive);
+    }
+}
@@ -0,0 +1,648 @@
+//! Versioned shapes of the JSON written by the binaries, for the tools reading it.
+//!
+//! The internal types follow the database format while these only change with their `schema_version`:
+//! adding a field keeps the version, renaming, retyping or removing one bumps it.
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{prelude::*, stats};
+
+pub const EXPORTED_SNAPSHOT_VERSION: u32 = 1;
+pub const SERIES_VERSION: u32 = 1;
+pub const SUMMARY_STATS_VERSION: u32 = 1;
+pub const THRESHOLD_REPORT_VERSION: u32 = 1;
+pub const HEALTH_VERSION: u32 = 1;
+
+/// Space of a filesystem, the bytes are `None` for snapshots taken before they were recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedDiskV1 {
+    pub used_percent: f64,
+    pub total_bytes: Option<u64>,
+    pub used_bytes: Option<u64>,
+    pub free_bytes: Option<u64>,
+}
+
+/// Snapshot with its gauges and the counters the rates are computed from.
+///
+/// Counters are totals since boot, a rate is the difference between two consecutive snapshots divided by
+/// the seconds between them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedSnapshotV1 {
+    pub schema_version: u32,
+    pub time: DateTime<Utc>,
+    pub cpu_count: usize,
+    pub cpu_busy_seconds: f64,
+    pub cpu_total_seconds: f64,
+    pub ram_percent: f64,
+    pub swap_percent: f64,
+    pub load1: f64,
+    pub load5: f64,
+    pub load15: f64,
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+    pub disk_read_bytes: u64,
+    pub disk_write_bytes: u64,
+    /// By mountpoint.
+    pub disks: BTreeMap<String, ExportedDiskV1>,
+    /// Degrees Celsius by sensor.
+    pub temperatures: BTreeMap<String, f64>,
+    /// Values of the custom collectors by key.
+    pub custom: BTreeMap<String, f64>,
+    pub collection_duration_ms: Option<u64>,
+}
+
+impl From<&SnapShot> for ExportedSnapshotV1 {
+    fn from(snapshot: &SnapShot) -> Self {
+        let (cpu_busy_seconds, cpu_total_seconds) = snapshot.get_cpu_time();
+        let (ram_percent, swap_percent) = snapshot.get_ram_usage();
+        let (load1, load5, load15) = snapshot.get_load();
+        let (net_rx_bytes, net_tx_bytes) = snapshot.get_network_usage();
+        let (disk_read_bytes, disk_write_bytes) = snapshot.get_disk_speed_usage();
+        let bytes = snapshot
+            .get_disks_bytes_usage()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+        Self {
+            schema_version: EXPORTED_SNAPSHOT_VERSION,
+            time: snapshot.time,
+            cpu_count: snapshot.get_cpu_count(),
+            cpu_busy_seconds,
+            cpu_total_seconds,
+            ram_percent,
+            swap_percent,
+            load1,
+            load5,
+            load15,
+            net_rx_bytes: net_rx_bytes as u64,
+            net_tx_bytes: net_tx_bytes as u64,
+            disk_read_bytes,
+            disk_write_bytes,
+            disks: snapshot
+                .get_disks_size_usage()
+                .into_iter()
+                .map(|(mount, used_percent)| {
+                    let usage = bytes.get(&mount);
+                    let disk = ExportedDiskV1 {
+                        used_percent,
+                        total_bytes: usage.map(|usage| usage.total),
+                        used_bytes: usage.map(|usage| usage.used),
+                        free_bytes: usage.map(|usage| usage.free),
+                    };
+                    (mount, disk)
+                })
+                .collect(),
+            temperatures: snapshot.get_temperatures().into_iter().collect(),
+            custom: snapshot
+                .custom
+                .iter()
+                .map(|(key, value)| (key.clone(), *value))
+                .collect(),
+            collection_duration_ms: snapshot.collection_duration_ms,
+        }
+    }
+}
+
+impl ExportedSnapshotV1 {
+    /// Columns of `csv_record`, the disks, temperatures and custom metrics are only in the JSON.
+    pub const CSV_HEADER: &'static str =
+        "schema_version,time,cpu_count,cpu_busy_seconds,cpu_total_seconds,\
+        ram_percent,swap_percent,load1,load5,load15,net_rx_bytes,net_tx_bytes,disk_read_bytes,\
+        disk_write_bytes,collection_duration_ms";
+
+    /// Line of the scalar fields in the order of `CSV_HEADER`, without its line break.
+    pub fn csv_record(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.schema_version,
+            self.time.to_rfc3339(),
+            self.cpu_count,
+            self.cpu_busy_seconds,
+            self.cpu_total_seconds,
+            self.ram_percent,
+            self.swap_percent,
+            self.load1,
+            self.load5,
+            self.load15,
+            self.net_rx_bytes,
+            self.net_tx_bytes,
+            self.disk_read_bytes,
+            self.disk_write_bytes,
+            self.collection_duration_ms
+                .map_or_else(String::new, |duration| duration.to_string()),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SeriesPointV1 {
+    pub time: DateTime<Utc>,
+    pub value: f64,
+}
+
+impl From<Point> for SeriesPointV1 {
+    fn from((time, value): Point) -> Self {
+        Self { time, value }
+    }
+}
+
+/// Points of a metric sorted by time, the version covers the shape of its points.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeriesV1 {
+    pub schema_version: u32,
+    /// Name of the metric, e.g. `cpu` or `disk:/var`.
+    pub metric: String,
+    pub points: Vec<SeriesPointV1>,
+}
+
+impl SeriesV1 {
+    pub fn new(metric: impl Into<String>, points: impl IntoIterator<Item = Point>) -> Self {
+        Self {
+            schema_version: SERIES_VERSION,
+            metric: metric.into(),
+            points: points.into_iter().map(SeriesPointV1::from).collect(),
+        }
+    }
+}
+
+/// Statistics of the points of a metric over a range.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SummaryStatsV1 {
+    pub schema_version: u32,
+    pub metric: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    /// Weighted by the time each point covers, see `stats::time_weighted_mean`.
+    pub average: f64,
+    /// Value of the last point.
+    pub current: f64,
+}
+
+impl SummaryStatsV1 {
+    /// Statistics of `points` sorted by time, `None` without points.
+    pub fn of(metric: impl Into<String>, points: &[Point]) -> Option<Self> {
+        let (first, last) = (points.first()?, points.last()?);
+        let values = points.iter().map(|(_, value)| *value);
+        let samples = points
+            .iter()
+            .map(|(time, value)| (*value, *time))
+            .collect::<Vec<_>>();
+
+        Some(Self {
+            schema_version: SUMMARY_STATS_VERSION,
+            metric: metric.into(),
+            from: first.0,
+            to: last.0,
+            count: points.len(),
+            min: values.clone().fold(f64::MAX, f64::min),
+            max: values.fold(f64::MIN, f64::max),
+            average: stats::time_weighted_mean(&samples, None)?,
+            current: last.1,
+        })
+    }
+}
+
+/// Threshold crossed by a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrossedThresholdV1 {
+    /// Identifier of the metric, e.g. `cpu`.
+    pub metric: String,
+    pub name: String,
+    pub threshold: u32,
+    pub observed_value: f64,
+    pub unit: String,
+    /// `warning` or `critical`.
+    pub severity: String,
+}
+
+/// Thresholds crossed by the snapshot taken at `time`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdReportV1 {
+    pub schema_version: u32,
+    pub hostname: Option<String>,
+    pub time: DateTime<Utc>,
+    pub crossed: Vec<CrossedThresholdV1>,
+}
+
+impl ThresholdReportV1 {
+    pub fn new(
+        hostname: Option<String>,
+        time: DateTime<Utc>,
+        crossed: impl IntoIterator<Item = CrossedThresholdV1>,
+    ) -> Self {
+        Self {
+            schema_version: THRESHOLD_REPORT_VERSION,
+            hostname,
+            time,
+            crossed: crossed.into_iter().collect(),
+        }
+    }
+}
+
+/// Health of a server showing the charts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthV1 {
+    pub schema_version: u32,
+    /// `ok` when the database file was checked recently, `unavailable` otherwise, `starting` until the first
+    /// load with `--strict-readiness`, `degraded` when the last check took longer than the interval between
+    /// two of them.
+    pub status: String,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub last_snapshot_time: Option<DateTime<Utc>>,
+    /// Time of the last snapshot when the end of the default range has no data.
+    pub stale_since: Option<DateTime<Utc>>,
+    /// Rough size of the cached charts of the default range.
+    pub estimated_bytes: usize,
+    /// The charts were restored from the view cache at startup and not generated from the database yet.
+    pub possibly_stale: bool,
+    /// When the restored charts were written to the view cache.
+    pub restored_from: Option<DateTime<Utc>>,
+    /// Duration of the last cycle of the actualization task.
+    pub last_cycle_ms: u64,
+}
+
+fn object(title: &str, version: u32, mut properties: Value) -> Value {
+    properties["schema_version"] = json!({ "const": version });
+    let required = properties
+        .as_object()
+        .map(|properties| properties.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": title,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": [kind, "null"] })
+}
+
+fn time() -> Value {
+    json!({ "type": "string", "format": "date-time" })
+}
+
+fn nullable_time() -> Value {
+    json!({ "type": ["string", "null"], "format": "date-time" })
+}
+
+/// JSON Schema of each versioned payload by name, e.g. `exported-snapshot`.
+pub fn json_schemas() -> BTreeMap<&'static str, Value> {
+    let series_point = json!({
+        "type": "object",
+        "properties": { "time": time(), "value": number() },
+        "required": ["time", "value"],
+    });
+    let crossed_threshold = json!({
+        "type": "object",
+        "properties": {
+            "metric": string(),
+            "name": string(),
+            "threshold": integer(),
+            "observed_value": number(),
+            "unit": string(),
+            "severity": { "enum": ["warning", "critical"] },
+        },
+        "required": ["metric", "name", "threshold", "observed_value", "unit", "severity"],
+    });
+    let disk = json!({
+        "type": "object",
+        "properties": {
+            "used_percent": number(),
+            "total_bytes": nullable("integer"),
+            "used_bytes": nullable("integer"),
+            "free_bytes": nullable("integer"),
+        },
+        "required": ["used_percent", "total_bytes", "used_bytes", "free_bytes"],
+    });
+
+    BTreeMap::from([
+        (
+            "exported-snapshot",
+            object(
+                "ExportedSnapshotV1",
+                EXPORTED_SNAPSHOT_VERSION,
+                json!({
+                    "time": time(),
+                    "cpu_count": integer(),
+                    "cpu_busy_seconds": number(),
+                    "cpu_total_seconds": number(),
+                    "ram_percent": number(),
+                    "swap_percent": number(),
+                    "load1": number(),
+                    "load5": number(),
+                    "load15": number(),
+                    "net_rx_bytes": integer(),
+                    "net_tx_bytes": integer(),
+                    "disk_read_bytes": integer(),
+                    "disk_write_bytes": integer(),
+                    "disks": { "type": "object", "additionalProperties": disk },
+                    "temperatures": { "type": "object", "additionalProperties": number() },
+                    "custom": { "type": "object", "additionalProperties": number() },
+                    "collection_duration_ms": nullable("integer"),
+                }),
+            ),
+        ),
+        (
+            "series",
+            object(
+                "SeriesV1",
+                SERIES_VERSION,
+                json!({
+                    "metric": string(),
+                    "points": { "type": "array", "items": series_point },
+                }),
+            ),
+        ),
+        (
+            "summary-stats",
+            object(
+                "SummaryStatsV1",
+                SUMMARY_STATS_VERSION,
+                json!({
+                    "metric": string(),
+                    "from": time(),
+                    "to": time(),
+                    "count": integer(),
+                    "min": number(),
+                    "max": number(),
+                    "average": number(),
+                    "current": number(),
+                }),
+            ),
+        ),
+        (
+            "threshold-report",
+            object(
+                "ThresholdReportV1",
+                THRESHOLD_REPORT_VERSION,
+                json!({
+                    "hostname": nullable("string"),
+                    "time": time(),
+                    "crossed": { "type": "array", "items": crossed_threshold },
+                }),
+            ),
+        ),
+        (
+            "health",
+            object(
+                "HealthV1",
+                HEALTH_VERSION,
+                json!({
+                    "status": { "enum": ["ok", "degraded", "unavailable", "starting"] },
+                    "checked_at": nullable_time(),
+                    "last_snapshot_time": nullable_time(),
+                    "stale_since": nullable_time(),
+                    "estimated_bytes": integer(),
+                    "possibly_stale": { "type": "boolean" },
+                    "restored_from": nullable_time(),
+                    "last_cycle_ms": integer(),
+                }),
+            ),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::*;
+    use crate::testing::{self, SnapshotBuilder};
+
+    /// Set to write the golden files of the schemas instead of comparing with them, once a version is bumped.
+    const UPDATE_ENV: &str = "SYSMET_UPDATE_SCHEMAS";
+
+    fn golden_path(name: &str, schema: &Value) -> PathBuf {
+        let version = &schema["properties"]["schema_version"]["const"];
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("schemas")
+            .join(format!("{name}.v{version}.json"))
+    }
+
+    /// Problems of `value` against the subset of JSON Schema used by `json_schemas`, extra fields included.
+    fn problems(path: &str, value: &Value, schema: &Value) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                problems.push(format!("{path}: {value} is not {expected}"));
+            }
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                problems.push(format!("{path}: {value} is not one of {allowed:?}"));
+            }
+        }
+        if let Some(kind) = schema.get("type") {
+            let kinds = kind
+                .as_array()
+                .cloned()
+                .unwrap_or_else(|| vec![kind.clone()]);
+            let matches = kinds.iter().any(|kind| match kind.as_str() {
+                Some("object") => value.is_object(),
+                Some("array") => value.is_array(),
+                Some("string") => value.is_string(),
+                Some("number") => value.is_number(),
+                Some("integer") => value.is_u64(),
+                Some("boolean") => value.is_boolean(),
+                Some("null") => value.is_null(),
+                _ => false,
+            });
+            if !matches {
+                problems.push(format!("{path}: {value} is not of type {kind}"));
+            }
+        }
+        if let (Some(object), Some(properties)) = (
+            value.as_object(),
+            schema.get("properties").and_then(Value::as_object),
+        ) {
+            for key in object.keys().filter(|key| !properties.contains_key(*key)) {
+                problems.push(format!("{path}.{key}: missing from the schema"));
+            }
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let key = required.as_str().unwrap();
+                if !object.contains_key(key) {
+                    problems.push(format!("{path}.{key}: required but missing"));
+                }
+            }
+            for (key, property) in properties {
+                if let Some(field) = object.get(key) {
+                    problems.extend(self::problems(&format!("{path}.{key}"), field, property));
+                }
+            }
+        }
+        if let (Some(object), Some(additional)) =
+            (value.as_object(), schema.get("additionalProperties"))
+        {
+            for (key, field) in object {
+                problems.extend(self::problems(&format!("{path}.{key}"), field, additional));
+            }
+        }
+        if let (Some(items), Some(item)) = (value.as_array(), schema.get("items")) {
+            for (index, value) in items.iter().enumerate() {
+                problems.extend(self::problems(&format!("{path}[{index}]"), value, item));
+            }
+        }
+
+        problems
+    }
+
+    /// One payload of each schema, with every optional field set.
+    fn payloads() -> BTreeMap<&'static str, Value> {
+        let snapshot = SnapshotBuilder::at(testing::time(0))
+            .cpu(10.0, 30.0)
+            .memory(4 << 30, 16 << 30)
+            .network(2048, 1024)
+            .disk(4096, 512)
+            .disk_usage(42.0)
+            .load(1.5)
+            .custom("queue", 3.0)
+            .build();
+        let mut exported = ExportedSnapshotV1::from(&snapshot);
+        exported.temperatures.insert("cpu".to_string(), 55.0);
+        exported.collection_duration_ms = Some(12);
+        let points = [(testing::time(0), 1.0), (testing::time(10), 3.0)];
+
+        BTreeMap::from([
+            ("exported-snapshot", serde_json::to_value(exported).unwrap()),
+            (
+                "series",
+                serde_json::to_value(SeriesV1::new("cpu", points)).unwrap(),
+            ),
+            (
+                "summary-stats",
+                serde_json::to_value(SummaryStatsV1::of("cpu", &points).unwrap()).unwrap(),
+            ),
+            (
+                "threshold-report",
+                serde_json::to_value(ThresholdReportV1::new(
+                    Some("host".to_string()),
+                    testing::time(0),
+                    [CrossedThresholdV1 {
+                        metric: "cpu".to_string(),
+                        name: "CPU".to_string(),
+                        threshold: 90,
+                        observed_value: 97.5,
+                        unit: "%".to_string(),
+                        severity: "critical".to_string(),
+                    }],
+                ))
+                .unwrap(),
+            ),
+            (
+                "health",
+                serde_json::to_value(HealthV1 {
+                    schema_version: HEALTH_VERSION,
+                    status: "ok".to_string(),
+                    checked_at: Some(testing::time(0)),
+                    last_snapshot_time: Some(testing::time(0)),
+                    stale_since: Some(testing::time(0)),
+                    estimated_bytes: 1024,
+                    possibly_stale: false,
+                    restored_from: Some(testing::time(0)),
+                    last_cycle_ms: 30,
+                })
+                .unwrap(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn schemas_match_their_golden_files() {
+        let update = env::var_os(UPDATE_ENV).is_some();
+        for (name, schema) in json_schemas() {
+            let path = golden_path(name, &schema);
+            if update {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, format!("{:#}\n", schema)).unwrap();
+                continue;
+            }
+
+            let golden = fs::read_to_string(&path).unwrap_or_else(|err| {
+                panic!("{path:?} unreadable ({err}), write it with {UPDATE_ENV}=1 once its version is bumped")
+            });
+            assert_eq!(
+                serde_json::from_str::<Value>(&golden).unwrap(),
+                schema,
+                "the schema of {name} changed without bumping its version, bump it then write {path:?} with \
+                 {UPDATE_ENV}=1"
+            );
+        }
+    }
+
+    #[test]
+    fn payloads_match_their_schemas() {
+        let schemas = json_schemas();
+        let payloads = payloads();
+        assert_eq!(
+            schemas.keys().collect::<Vec<_>>(),
+            payloads.keys().collect::<Vec<_>>()
+        );
+        for (name, payload) in payloads {
+            let problems = problems(name, &payload, &schemas[name]);
+            assert!(problems.is_empty(), "{problems:#?}");
+        }
+    }
+
+    #[test]
+    fn changed_payloads_are_caught() {
+        let schemas = json_schemas();
+        let mut payload = payloads()["series"].clone();
+        payload["points"][0]["value"] = json!("1.0");
+        payload["unit"] = json!("%");
+        payload["schema_version"] = json!(SERIES_VERSION + 1);
+
+        assert_eq!(problems("series", &payload, &schemas["series"]).len(), 3);
+    }
+
+    #[test]
+    fn csv_records_have_the_columns_of_the_header() {
+        let mut exported =
+            ExportedSnapshotV1::from(&SnapshotBuilder::at(testing::time(0)).load(1.5).build());
+        exported.collection_duration_ms = Some(12);
+        let header = ExportedSnapshotV1::CSV_HEADER
+            .split(',')
+            .collect::<Vec<_>>();
+        let record = exported.csv_record();
+        let columns = header
+            .iter()
+            .zip(record.split(','))
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(record.split(',').count(), header.len());
+        assert_eq!(
+            columns[&"schema_version"],
+            EXPORTED_SNAPSHOT_VERSION.to_string()
+        );
+        assert_eq!(columns[&"time"], "2024-05-03T12:00:00+00:00");
+        assert_eq!(columns[&"load1"], "1.5");
+        assert_eq!(columns[&"collection_duration_ms"], "12");
+
+        let exported = ExportedSnapshotV1 {
+            collection_duration_ms: None,
+            ..exported
+        };
+        assert!(exported.csv_record().ends_with(','));
+    }
+}
@@ -0,0 +1,787 @@
+use std::{collections::HashMap, ops::Range};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, tracing};
+
+use crate::{database::Values, prelude::*, psutil::NicInfo, rates, stats};
+
+/// Time and value of a metric.
+pub type Point = (DateTime<Utc>, f64);
+
+/// Metric a series is computed for, rates are per second over the interval ending at each point.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MetricKind {
+    /// Busy CPU time in percent of the total CPU time.
+    CpuPercent,
+    RamPercent,
+    SwapPercent,
+    /// Load average over 1 minute in percent of the number of CPUs.
+    Load1,
+    /// Load average over 5 minutes in percent of the number of CPUs.
+    Load5,
+    /// Load average over 15 minutes in percent of the number of CPUs.
+    Load15,
+    /// Pages swapped in per second, empty when the platform doesn't report swap activity.
+    SwapInRate,
+    /// Pages swapped out per second, empty when the platform doesn't report swap activity.
+    SwapOutRate,
+    /// Bytes received per second by the network interfaces.
+    NetRxRate,
+    /// Bytes sent per second by the network interfaces.
+    NetTxRate,
+    /// Bytes read per second from the disks.
+    DiskReadRate,
+    /// Bytes written per second to the disks.
+    DiskWriteRate,
+    /// Used space in percent of the filesystem mounted at `mount`.
+    DiskUsage {
+        mount: String,
+    },
+    /// Degrees Celsius of the sensor named `sensor`, see `SnapShot::get_temperatures`.
+    Temperature {
+        sensor: String,
+    },
+    /// Current CPU frequency in percent of the max one, empty when the platform doesn't report it.
+    CpuFrequencyPercent,
+    /// Milliseconds taken to collect the snapshot, empty for snapshots taken before it was recorded.
+    CollectionDurationMs,
+    /// Kernel gauge named `gauge` in percent of its max, see `KernelGauges`.
+    KernelGaugePercent {
+        gauge: String,
+    },
+    /// Busiest direction of the interface named `nic` in percent of its link speed, or of every interface
+    /// with a known speed summed together for `None`, see `NicInfo`.
+    NetUtilizationPercent {
+        nic: Option<String>,
+    },
+}
+
+impl MetricKind {
+    /// The value is computed from the counters of two consecutive snapshots.
+    fn is_rate(&self) -> bool {
+        matches!(
+            self,
+            Self::CpuPercent
+                | Self::SwapInRate
+                | Self::SwapOutRate
+                | Self::NetRxRate
+                | Self::NetTxRate
+                | Self::DiskReadRate
+                | Self::DiskWriteRate
+                | Self::NetUtilizationPercent { .. }
+        )
+    }
+
+    /// Step of the counter of a rate in the unit of its readings, `None` when the value isn't a counter
+    /// divided by the elapsed time.
+    pub fn counter_resolution(&self) -> Option<f64> {
+        match self {
+            // NOTE: Whole pages and whole bytes
+            Self::SwapInRate
+            | Self::SwapOutRate
+            | Self::NetRxRate
+            | Self::NetTxRate
+            | Self::DiskReadRate
+            | Self::DiskWriteRate => Some(1.0),
+            _ => None,
+        }
+    }
+
+    /// Value of a gauge, or of the counter of a rate, `None` when the snapshot doesn't have it.
+    fn sample(&self, snapshot: &SnapShot) -> Option<f64> {
+        match self {
+            Self::CpuPercent | Self::NetUtilizationPercent { .. } => None,
+            Self::RamPercent => Some(snapshot.get_ram_usage().0),
+            Self::SwapPercent => Some(snapshot.get_ram_usage().1),
+            Self::Load1 | Self::Load5 | Self::Load15 => {
+                let (one, five, fifteen) = snapshot.get_load();
+                let load = match self {
+                    Self::Load1 => one,
+                    Self::Load5 => five,
+                    _ => fifteen,
+                };
+                Some(load / snapshot.get_cpu_count() as f64 * 100.0)
+            }
+            Self::SwapInRate => snapshot
+                .get_swap_activity()
+                .map(|(swapped_in, _)| swapped_in),
+            Self::SwapOutRate => snapshot
+                .get_swap_activity()
+                .map(|(_, swapped_out)| swapped_out),
+            Self::NetRxRate => Some(snapshot.get_network_usage().0),
+            Self::NetTxRate => Some(snapshot.get_network_usage().1),
+            Self::DiskReadRate => Some(snapshot.get_disk_speed_usage().0 as f64),
+            Self::DiskWriteRate => Some(snapshot.get_disk_speed_usage().1 as f64),
+            Self::DiskUsage { mount } => {
+                snapshot.disks_memory.get(mount).map(|usage| *usage as f64)
+            }
+            Self::Temperature { sensor } => snapshot
+                .get_temperatures()
+                .into_iter()
+                .find_map(|(name, celsius)| (name == *sensor).then_some(celsius)),
+            Self::CpuFrequencyPercent => snapshot
+                .cpu_freq
+                .as_ref()
+                .and_then(|cpu_freq| cpu_freq.percent_of_max()),
+            Self::CollectionDurationMs => snapshot
+                .collection_duration_ms
+                .map(|duration| duration as f64),
+            Self::KernelGaugePercent { gauge } => snapshot
+                .kernel
+                .as_ref()
+                .and_then(|kernel| kernel.percent(gauge)),
+        }
+    }
+}
+
+impl Database {
+    /// Points of `metric` taken in `range`, averaged per `step` long bucket when given.
+    ///
+    /// Rates of the first snapshot of the range use the snapshot preceding it. A bucket is
+    /// timestamped with its start, its points are weighted by the time they cover and buckets without
+    /// points are skipped.
+    #[tracing::instrument(skip(self))]
+    pub fn series(
+        &self,
+        metric: MetricKind,
+        range: Range<DateTime<Utc>>,
+        step: Option<Duration>,
+    ) -> impl Iterator<Item = Point> {
+        let Range { start, end } = self.indices_within(&range);
+        let points = if start >= end {
+            Vec::new()
+        } else if metric.is_rate() {
+            let snapshots = &self.snapshots[start.saturating_sub(1)..end];
+            rate_points(&metric, snapshots)
+                .into_iter()
+                .filter(|(time, _)| range.contains(time))
+                .collect()
+        } else {
+            self.snapshots[start..end]
+                .iter()
+                .filter_map(|snapshot| Some((snapshot.time, metric.sample(snapshot)?)))
+                .collect()
+        };
+
+        let result = match step.filter(|step| *step > Duration::zero()) {
+            Some(step) => bucket_average(&points, range, step),
+            None => points,
+        };
+        debug!(points = result.len());
+        result.into_iter()
+    }
+
+    /// Every point of `metric` in the database.
+    pub(crate) fn whole_series(&self, metric: MetricKind) -> impl Iterator<Item = Point> {
+        self.series(
+            metric,
+            DateTime::<Utc>::MIN_UTC..DateTime::<Utc>::MAX_UTC,
+            None,
+        )
+    }
+
+    /// Error of every point of the rate `metric` in the database, see `rates::rate_error`, timestamped like
+    /// its points. Empty when `metric` isn't a counter divided by the elapsed time.
+    #[tracing::instrument(skip(self))]
+    pub fn rate_errors(&self, metric: MetricKind) -> Vec<Point> {
+        let Some(resolution) = metric.counter_resolution() else {
+            return Vec::new();
+        };
+        let counters = rates::samples(&self.snapshots, |s| metric.sample(s));
+        // NOTE: The intervals dropped from the rates are dropped here too so both stay aligned
+        let result = rates::per_interval(&counters, |previous, current, elapsed| {
+            rates::counter_delta(*previous, *current)?;
+            Some(rates::rate_error(resolution, elapsed))
+        })
+        .into_iter()
+        .map(|(error, time)| (time, error))
+        .collect::<Vec<_>>();
+        debug!(points = result.len());
+        result
+    }
+}
+
+fn rate_points(metric: &MetricKind, snapshots: &[SnapShot]) -> Vec<Point> {
+    let result = if *metric == MetricKind::CpuPercent {
+        let cpus_times = rates::cpu_samples(snapshots, |s| {
+            (
+                s.get_cpu_time(),
+                s.cgroup.and_then(|cgroup| cgroup.cpu_quota()),
+            )
+        });
+        // NOTE: CPU times are counters since boot, the usage is computed on each interval
+        rates::per_interval(
+            &cpus_times,
+            |((previous_active, previous_total), previous_quota),
+             ((active, total), quota),
+             elapsed| {
+                // NOTE: A container with a CPU quota is compared to the cores it may use, not the host ones
+                if let Some(((previous_usage, _), (usage, cores))) = previous_quota.zip(*quota) {
+                    let usage = rates::counter_delta(previous_usage, usage)?;
+                    return (cores > 0.0).then(|| usage / (elapsed * cores) * 100.0);
+                }
+                let active = rates::counter_delta(*previous_active, *active)?;
+                let total = rates::counter_delta(*previous_total, *total)?;
+                (total > 0.0).then(|| active / total * 100.0)
+            },
+        )
+    } else if let MetricKind::NetUtilizationPercent { nic } = metric {
+        let links = rates::samples(snapshots, |s| Some(&s.nic_info));
+        rates::per_interval(&links, |previous, current, elapsed| {
+            net_utilization(previous, current, elapsed, nic.as_deref())
+        })
+    } else {
+        let counters = rates::samples(snapshots, |s| metric.sample(s));
+        rates::per_interval(&counters, |previous, current, elapsed| {
+            Some(rates::counter_delta(*previous, *current)? / elapsed)
+        })
+    };
+
+    result
+        .into_iter()
+        .map(|(value, time)| (time, value))
+        .collect()
+}
+
+/// Utilization in percent of the interfaces named `nic` (every one for `None`) over an interval of `elapsed`
+/// seconds, `None` when none of them has a known speed at both ends of it.
+///
+/// Interfaces down at either end of the interval flapped during it and are left out, as are those whose
+/// counters went backward. A link renegotiated during the interval is compared to its slowest speed.
+fn net_utilization(
+    previous: &HashMap<String, NicInfo>,
+    current: &HashMap<String, NicInfo>,
+    elapsed: f64,
+    nic: Option<&str>,
+) -> Option<f64> {
+    let (busy, capacity) = current
+        .iter()
+        .filter(|(name, _)| nic.is_none_or(|nic| nic == name.as_str()))
+        .filter_map(|(name, link)| {
+            let previous = previous
+                .get(name)
+                .filter(|previous| previous.up && link.up)?;
+            let capacity = previous
+                .capacity_bytes_per_sec()?
+                .min(link.capacity_bytes_per_sec()?);
+            // NOTE: Links are full-duplex, each direction has the whole capacity
+            let received =
+                rates::counter_delta(previous.bytes_recv as f64, link.bytes_recv as f64)?;
+            let sent = rates::counter_delta(previous.bytes_sent as f64, link.bytes_sent as f64)?;
+            Some((received.max(sent) / elapsed, capacity))
+        })
+        .fold(
+            (0.0, 0.0),
+            |(busy, capacity), (link_busy, link_capacity)| {
+                (busy + link_busy, capacity + link_capacity)
+            },
+        );
+
+    (capacity > 0.0).then(|| busy / capacity * 100.0)
+}
+
+/// Time-weighted mean of the points of each `step` long bucket starting from the start of `range`.
+pub(crate) fn bucket_average(
+    points: &[Point],
+    range: Range<DateTime<Utc>>,
+    step: Duration,
+) -> Vec<Point> {
+    let step_ms = step.num_milliseconds().max(1);
+    let mut buckets: Vec<(i64, Values)> = Vec::new();
+    for (time, value) in points {
+        let bucket = (*time - range.start).num_milliseconds() / step_ms;
+        match buckets.last_mut() {
+            Some((last, samples)) if *last == bucket => samples.push((*value, *time)),
+            _ => buckets.push((bucket, vec![(*value, *time)])),
+        }
+    }
+
+    buckets
+        .into_iter()
+        .filter_map(|(bucket, samples)| {
+            let start = range.start + Duration::milliseconds(bucket * step_ms);
+            // NOTE: The last bucket stops at the end of the range, not in the future
+            let end = (start + step).min(range.end);
+            Some((
+                start,
+                stats::time_weighted_mean(&samples, Some(start..end))?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        psutil::{CgroupLimits, CpuFreq, KernelGauge, KernelGauges},
+        testing::{time, SnapshotBuilder, MOUNTPOINT},
+    };
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+    const SENSOR: &str = "coretemp Package";
+
+    /// Snapshots every minute from `time(0)` to `time(240)`, every gauge being constant but the temperature
+    /// and the collection duration, and every rate constant.
+    fn fixture() -> Database {
+        let mut database = Database::default();
+        for idx in 0..5u64 {
+            let seconds = idx as f64 * 60.0;
+            let mut snapshot = SnapshotBuilder::at(time(idx as i64 * 60))
+                .cpus(&[(seconds / 2.0, seconds / 2.0); 2])
+                .memory(4 * GIB, 16 * GIB)
+                .network(6000 * idx, 60 * idx)
+                .disk(12_000 * idx, 0)
+                .disk_usage(40.0)
+                .load(1.0)
+                .build();
+            snapshot.temps = vec![serde_json::from_value(json!({
+                "unit": "coretemp",
+                "label": "Package",
+                "current": { "celsius": 50.0 + idx as f64 },
+                "max": null,
+                "crit": null,
+            }))
+            .unwrap()];
+            snapshot.cpu_freq = Some(CpuFreq {
+                current_mhz: 1500.0,
+                max_mhz: 3000.0,
+            });
+            snapshot.collection_duration_ms = Some(10 * idx);
+            snapshot.kernel = Some(KernelGauges(BTreeMap::from([(
+                "threads".to_string(),
+                KernelGauge {
+                    value: 100.0,
+                    max: Some(400.0),
+                },
+            )])));
+            // NOTE: 8 Mbit/s are 1MB/s, of which 100kB/s are received
+            snapshot.nic_info = HashMap::from([(
+                "eth0".to_string(),
+                NicInfo {
+                    speed_mbps: Some(8),
+                    up: true,
+                    bytes_recv: 6_000_000 * idx,
+                    bytes_sent: 60 * idx,
+                },
+            )]);
+            database.push_snapshot(snapshot);
+        }
+        database
+    }
+
+    fn whole() -> Range<DateTime<Utc>> {
+        time(0)..time(300)
+    }
+
+    fn values(points: impl Iterator<Item = Point>) -> Vec<(i64, f64)> {
+        points
+            .map(|(point_time, value)| ((point_time - time(0)).num_seconds(), value))
+            .collect()
+    }
+
+    #[test]
+    fn gauges_have_a_point_per_snapshot() {
+        let database = fixture();
+        let constant = |value| (0..5).map(|idx| (idx * 60, value)).collect::<Vec<_>>();
+
+        for (metric, value) in [
+            (MetricKind::RamPercent, 25.0),
+            (MetricKind::SwapPercent, 0.0),
+            // NOTE: A load of 1 over 2 CPUs
+            (MetricKind::Load1, 50.0),
+            (MetricKind::Load5, 50.0),
+            (MetricKind::Load15, 50.0),
+            (
+                MetricKind::DiskUsage {
+                    mount: MOUNTPOINT.to_string(),
+                },
+                40.0,
+            ),
+            (MetricKind::CpuFrequencyPercent, 50.0),
+            (
+                MetricKind::KernelGaugePercent {
+                    gauge: "threads".to_string(),
+                },
+                25.0,
+            ),
+        ] {
+            assert_eq!(
+                values(database.series(metric.clone(), whole(), None)),
+                constant(value),
+                "{metric:?}"
+            );
+        }
+
+        assert_eq!(
+            values(database.series(
+                MetricKind::Temperature {
+                    sensor: SENSOR.to_string()
+                },
+                whole(),
+                None
+            )),
+            [(0, 50.0), (60, 51.0), (120, 52.0), (180, 53.0), (240, 54.0)]
+        );
+        assert_eq!(
+            values(database.series(MetricKind::CollectionDurationMs, whole(), None)),
+            [(0, 0.0), (60, 10.0), (120, 20.0), (180, 30.0), (240, 40.0)]
+        );
+    }
+
+    #[test]
+    fn rates_have_a_point_per_interval() {
+        let database = fixture();
+        let constant = |value| (1..5).map(|idx| (idx * 60, value)).collect::<Vec<_>>();
+
+        for (metric, value) in [
+            (MetricKind::CpuPercent, 50.0),
+            (MetricKind::NetRxRate, 100.0),
+            (MetricKind::NetTxRate, 1.0),
+            (MetricKind::DiskReadRate, 200.0),
+            (MetricKind::DiskWriteRate, 0.0),
+            (
+                MetricKind::NetUtilizationPercent {
+                    nic: Some("eth0".to_string()),
+                },
+                10.0,
+            ),
+            (MetricKind::NetUtilizationPercent { nic: None }, 10.0),
+        ] {
+            assert_eq!(
+                values(database.series(metric.clone(), whole(), None)),
+                constant(value),
+                "{metric:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn swap_activity_is_only_known_on_linux() {
+        let database = fixture();
+
+        for metric in [MetricKind::SwapInRate, MetricKind::SwapOutRate] {
+            let points = values(database.series(metric.clone(), whole(), None));
+            if cfg!(target_os = "linux") {
+                assert_eq!(points, [(60, 0.0), (120, 0.0), (180, 0.0), (240, 0.0)]);
+            } else {
+                assert!(points.is_empty(), "{metric:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_mounts_sensors_gauges_and_interfaces_are_empty() {
+        let database = fixture();
+
+        for metric in [
+            MetricKind::DiskUsage {
+                mount: "/home".to_string(),
+            },
+            MetricKind::Temperature {
+                sensor: "nvme Composite".to_string(),
+            },
+            MetricKind::KernelGaugePercent {
+                gauge: "conntrack".to_string(),
+            },
+            MetricKind::NetUtilizationPercent {
+                nic: Some("wlan0".to_string()),
+            },
+        ] {
+            assert_eq!(
+                database.series(metric.clone(), whole(), None).count(),
+                0,
+                "{metric:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_start_inclusive_and_end_exclusive() {
+        let database = fixture();
+
+        assert_eq!(
+            values(database.series(MetricKind::RamPercent, time(60)..time(180), None)),
+            [(60, 25.0), (120, 25.0)]
+        );
+        // NOTE: The rate at the start of the range uses the snapshot before it
+        assert_eq!(
+            values(database.series(MetricKind::NetRxRate, time(60)..time(121), None)),
+            [(60, 100.0), (120, 100.0)]
+        );
+    }
+
+    #[test]
+    fn empty_ranges_have_no_points() {
+        let database = fixture();
+
+        for range in [
+            time(60)..time(60),
+            time(180)..time(60),
+            time(1000)..time(2000),
+            time(-600)..time(0),
+        ] {
+            for step in [None, Some(Duration::seconds(60))] {
+                for metric in [MetricKind::RamPercent, MetricKind::CpuPercent] {
+                    assert_eq!(
+                        database.series(metric.clone(), range.clone(), step).count(),
+                        0,
+                        "{metric:?} over {range:?} by {step:?}"
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            Database::default()
+                .series(MetricKind::RamPercent, whole(), None)
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn steps_average_the_points_of_each_bucket() {
+        let database = fixture();
+        let temperature = MetricKind::Temperature {
+            sensor: SENSOR.to_string(),
+        };
+
+        // NOTE: The last sample of a bucket holds until its end
+        assert_eq!(
+            values(database.series(temperature.clone(), whole(), Some(Duration::seconds(120)))),
+            [(0, 50.75), (120, 52.75), (240, 54.0)]
+        );
+        assert_eq!(
+            values(database.series(
+                MetricKind::CpuPercent,
+                whole(),
+                Some(Duration::seconds(120))
+            )),
+            [(0, 50.0), (120, 50.0), (240, 50.0)]
+        );
+        // NOTE: Buckets start from the start of the range, not from the first point
+        assert_eq!(
+            values(database.series(
+                MetricKind::RamPercent,
+                time(-30)..time(300),
+                Some(Duration::seconds(120))
+            )),
+            [(-30, 25.0), (90, 25.0), (210, 25.0)]
+        );
+    }
+
+    #[test]
+    fn steps_longer_than_the_range_give_a_single_point() {
+        let database = fixture();
+        let temperature = MetricKind::Temperature {
+            sensor: SENSOR.to_string(),
+        };
+
+        assert_eq!(
+            values(database.series(temperature, whole(), Some(Duration::days(1)))),
+            [(0, 52.4)]
+        );
+        assert_eq!(
+            values(database.series(MetricKind::NetRxRate, whole(), Some(Duration::days(1)))),
+            [(0, 100.0)]
+        );
+    }
+
+    #[test]
+    fn steps_that_are_not_positive_are_ignored() {
+        let database = fixture();
+        let raw = values(database.series(MetricKind::CpuPercent, whole(), None));
+
+        for step in [Duration::zero(), Duration::seconds(-60)] {
+            assert_eq!(
+                values(database.series(MetricKind::CpuPercent, whole(), Some(step))),
+                raw
+            );
+        }
+    }
+
+    /// Link of 8 Mbit/s (1MB/s) with the given counters.
+    fn link(up: bool, speed_mbps: Option<u64>, bytes_recv: u64, bytes_sent: u64) -> NicInfo {
+        NicInfo {
+            speed_mbps,
+            up,
+            bytes_recv,
+            bytes_sent,
+        }
+    }
+
+    #[test]
+    fn utilizations_are_the_busiest_direction_of_the_links() {
+        let previous = HashMap::from([
+            ("eth0".to_string(), link(true, Some(8), 0, 0)),
+            ("eth1".to_string(), link(true, Some(8), 0, 0)),
+            ("lo".to_string(), link(true, None, 0, 0)),
+        ]);
+        // NOTE: 100kB/s received and 300kB/s sent on eth0, 100kB/s on eth1 and a busy loopback over 10s
+        let current = HashMap::from([
+            (
+                "eth0".to_string(),
+                link(true, Some(8), 1_000_000, 3_000_000),
+            ),
+            ("eth1".to_string(), link(true, Some(8), 1_000_000, 0)),
+            ("lo".to_string(), link(true, None, 90_000_000, 90_000_000)),
+        ]);
+
+        let utilization = |nic| net_utilization(&previous, &current, 10.0, nic).unwrap();
+
+        assert!((utilization(Some("eth0")) - 30.0).abs() < 1e-9);
+        assert!((utilization(Some("eth1")) - 10.0).abs() < 1e-9);
+        // NOTE: Summed over the links with a speed, the loopback being left out
+        assert!((utilization(None) - 20.0).abs() < 1e-9);
+        assert_eq!(net_utilization(&previous, &current, 10.0, Some("lo")), None);
+    }
+
+    #[test]
+    fn flapping_links_are_left_out_of_the_utilization() {
+        let previous = HashMap::from([
+            ("eth0".to_string(), link(true, Some(8), 0, 0)),
+            ("eth1".to_string(), link(false, None, 0, 0)),
+            ("eth2".to_string(), link(true, Some(8), 5_000_000, 0)),
+        ]);
+        // NOTE: eth0 went down, eth1 came back up and eth2 reset its counters while flapping
+        let current = HashMap::from([
+            ("eth0".to_string(), link(false, None, 1_000_000, 0)),
+            ("eth1".to_string(), link(true, Some(8), 1_000_000, 0)),
+            ("eth2".to_string(), link(true, Some(8), 1_000_000, 0)),
+        ]);
+
+        for nic in [Some("eth0"), Some("eth1"), Some("eth2"), None] {
+            assert_eq!(
+                net_utilization(&previous, &current, 10.0, nic),
+                None,
+                "{nic:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn renegotiated_links_are_compared_to_their_slowest_speed() {
+        let previous = HashMap::from([("eth0".to_string(), link(true, Some(80), 0, 0))]);
+        let current = HashMap::from([("eth0".to_string(), link(true, Some(8), 1_000_000, 0))]);
+
+        let utilization = net_utilization(&previous, &current, 10.0, None).unwrap();
+
+        assert!((utilization - 10.0).abs() < 1e-9, "{utilization}");
+    }
+
+    /// Container using a core over the first minute then half a core, with the host half busy.
+    fn container(memory_max: Option<u64>, cpu_max_cores: Option<f64>) -> Database {
+        let mut database = Database::default();
+        for (idx, usage_usec) in [0, 60_000_000, 90_000_000].into_iter().enumerate() {
+            let seconds = idx as f64 * 60.0;
+            database.push_snapshot(
+                SnapshotBuilder::at(time(idx as i64 * 60))
+                    .cpus(&[(seconds / 2.0, seconds / 2.0); 4])
+                    .memory(4 * GIB, 16 * GIB)
+                    .cgroup(CgroupLimits {
+                        memory_max,
+                        memory_current: Some(GIB),
+                        cpu_max_cores,
+                        cpu_usage_usec: Some(usage_usec),
+                    })
+                    .build(),
+            );
+        }
+        database
+    }
+
+    #[test]
+    fn containers_are_measured_against_their_cgroup_limits() {
+        let database = container(Some(2 * GIB), Some(2.0));
+
+        assert_eq!(
+            values(database.series(MetricKind::RamPercent, whole(), None)),
+            [(0, 50.0), (60, 50.0), (120, 50.0)]
+        );
+        assert_eq!(
+            values(database.series(MetricKind::CpuPercent, whole(), None)),
+            [(60, 50.0), (120, 25.0)]
+        );
+    }
+
+    #[test]
+    fn unlimited_containers_are_measured_against_the_host() {
+        let database = container(None, None);
+
+        assert_eq!(
+            values(database.series(MetricKind::RamPercent, whole(), None)),
+            [(0, 25.0), (60, 25.0), (120, 25.0)]
+        );
+        assert_eq!(
+            values(database.series(MetricKind::CpuPercent, whole(), None)),
+            [(60, 50.0), (120, 50.0)]
+        );
+    }
+
+    #[test]
+    fn buckets_weight_their_points_by_the_time_they_cover() {
+        let mut database = Database::default();
+        // NOTE: The memory is full for the first 10 seconds of the minute and empty from the 50th
+        for (seconds, used) in [(0, 16), (5, 16), (10, 16), (50, 0), (60, 0), (120, 0)] {
+            database.push_snapshot(
+                SnapshotBuilder::at(time(seconds))
+                    .memory(used * GIB, 16 * GIB)
+                    .build(),
+            );
+        }
+
+        let buckets = values(database.series(
+            MetricKind::RamPercent,
+            time(0)..time(120),
+            Some(Duration::seconds(60)),
+        ));
+
+        // NOTE: Plainly averaged the first bucket would be at 75%
+        assert_eq!(buckets, [(0, 50.0), (60, 0.0)]);
+    }
+
+    // NOTE: Swap activity is only counted in pages on Linux
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rate_errors_are_one_step_over_each_interval() {
+        let database = |interval: i64| {
+            let mut database = Database::default();
+            for (idx, swapped) in [0, 5, 9, 20].into_iter().enumerate() {
+                database.push_snapshot(
+                    SnapshotBuilder::at(time(idx as i64 * interval))
+                        .swapped(swapped, 0)
+                        .build(),
+                );
+            }
+            database
+        };
+
+        for interval in [1, 600] {
+            let database = database(interval);
+
+            let errors = database.rate_errors(MetricKind::SwapInRate);
+
+            let error = 1.0 / interval as f64;
+            assert_eq!(
+                errors,
+                (1..4)
+                    .map(|idx| (time(idx * interval), error))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                errors.iter().map(|(time, _)| *time).collect::<Vec<_>>(),
+                database
+                    .series(MetricKind::SwapInRate, time(0)..time(4 * interval), None)
+                    .map(|(time, _)| time)
+                    .collect::<Vec<_>>()
+            );
+            assert!(database.rate_errors(MetricKind::CpuPercent).is_empty());
+        }
+        assert_eq!(MetricKind::NetRxRate.counter_resolution(), Some(1.0));
+        assert_eq!(MetricKind::RamPercent.counter_resolution(), None);
+    }
+}
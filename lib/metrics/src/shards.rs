@@ -0,0 +1,412 @@
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, TimeZone, Utc};
+use log::{debug, info, tracing, warn};
+
+use crate::{
+    backup::{create_backup, BackupPolicy},
+    prelude::*,
+    Result,
+};
+
+const SHARD_EXTENSION: &str = "db";
+
+/// Time covered by each file of a sharded database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardPeriod {
+    #[default]
+    Monthly,
+    Weekly,
+}
+
+impl FromStr for ShardPeriod {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "monthly" => Ok(ShardPeriod::Monthly),
+            "weekly" => Ok(ShardPeriod::Weekly),
+            _ => Err(Error::InvalidShardPeriod(value.to_string())),
+        }
+    }
+}
+
+impl Display for ShardPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardPeriod::Monthly => write!(f, "monthly"),
+            ShardPeriod::Weekly => write!(f, "weekly"),
+        }
+    }
+}
+
+impl ShardPeriod {
+    /// Name of the shard `time` belongs to, `2024-05` when monthly and the ISO week `2024-W19` when weekly.
+    pub fn shard_name(self, time: DateTime<Utc>) -> String {
+        match self {
+            ShardPeriod::Monthly => time.format("%Y-%m").to_string(),
+            ShardPeriod::Weekly => time.format("%G-W%V").to_string(),
+        }
+    }
+}
+
+/// Time span of a shard from its name whatever its period, the end being excluded.
+fn shard_span(name: &str) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let (start, end) =
+        if let Ok(start) = NaiveDate::parse_from_str(&format!("{name}-1"), "%G-W%V-%u") {
+            (start, start.checked_add_signed(Duration::weeks(1))?)
+        } else {
+            let start = NaiveDate::parse_from_str(&format!("{name}-01"), "%Y-%m-%d").ok()?;
+            (start, start.checked_add_months(Months::new(1))?)
+        };
+    let to_datetime = |date: NaiveDate| {
+        Utc.with_ymd_and_hms(date.year(), date.month(), date.day(), 0, 0, 0)
+            .single()
+    };
+
+    Some((to_datetime(start)?, to_datetime(end)?))
+}
+
+/// File of a sharded database holding the snapshots taken between `start` (included) and `end` (excluded).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shard {
+    pub path: PathBuf,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Database split in one file per period, shards are only read when a range needs them.
+#[derive(Debug, Clone)]
+pub struct ShardedDatabase {
+    dir: PathBuf,
+    /// Sorted by start.
+    shards: Vec<Shard>,
+}
+
+impl Database {
+    /// List the shards of the directory without reading them, the directory may not exist yet.
+    #[tracing::instrument]
+    pub fn open_sharded(dir: &Path) -> Result<ShardedDatabase> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(ShardedDatabase {
+                    dir: dir.to_path_buf(),
+                    shards: Vec::new(),
+                })
+            }
+            Err(err) => return Err(Error::FailedToReadFile(err)),
+        };
+        // NOTE: Lock files and backups do not have the extension so they are never taken for shards
+        let mut shards = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|extension| extension == SHARD_EXTENSION)
+            })
+            .filter_map(|path| {
+                let (start, end) = shard_span(&path.file_stem()?.to_string_lossy())?;
+                Some(Shard { path, start, end })
+            })
+            .collect::<Vec<_>>();
+        shards.sort_by_key(|shard| shard.start);
+        debug!("Found {} shards in {dir:?}", shards.len());
+
+        Ok(ShardedDatabase {
+            dir: dir.to_path_buf(),
+            shards,
+        })
+    }
+
+    /// Carry the hostname and the host facts of the previous shard so changes across shards are annotated.
+    pub fn continue_from(&mut self, previous: &Database) {
+        if self.hostname.is_none() {
+            self.hostname.clone_from(&previous.hostname);
+        }
+        self.previous_facts = previous
+            .snapshots
+            .iter()
+            .rev()
+            .find_map(|snap| snap.host_facts.clone());
+    }
+}
+
+impl ShardedDatabase {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    /// Path of the shard `time` belongs to, the file may not exist yet.
+    pub fn shard_path(&self, period: ShardPeriod, time: DateTime<Utc>) -> PathBuf {
+        self.dir
+            .join(format!("{}.{SHARD_EXTENSION}", period.shard_name(time)))
+    }
+
+    /// Last shard ending before `time`.
+    pub fn shard_before(&self, time: DateTime<Utc>) -> Option<&Shard> {
+        self.shards.iter().rev().find(|shard| shard.end <= time)
+    }
+
+    /// Shards holding snapshots taken between `from` and `to` (both included).
+    pub fn shards_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> impl Iterator<Item = &Shard> {
+        self.shards
+            .iter()
+            .filter(move |shard| shard.start <= to && shard.end > from)
+    }
+
+    /// Merge the shards holding snapshots between `from` and `to`, they are kept whole.
+    #[tracing::instrument(skip(self, key), fields(dir = ?self.dir))]
+    pub fn load_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        key: Option<&DatabaseKey>,
+    ) -> Result<Database> {
+        let mut result = Database::default();
+        for shard in self.shards_between(from, to) {
+            let shard = Database::from_file(&shard.path.to_string_lossy(), key)?;
+            result.hostname = shard.hostname.or(result.hostname);
+            result.snapshots.extend(shard.snapshots);
+            result.annotations.extend(shard.annotations);
+            result.skipped_records += shard.skipped_records;
+            result.last_write = shard.last_write.or(result.last_write);
+        }
+        result.key = key.cloned();
+        debug!(
+            "Loaded {} snapshots between {from} and {to}",
+            result.snapshots.len()
+        );
+
+        Ok(result)
+    }
+
+    /// Remove the shards only holding snapshots older than `older_than`, backing them up first with a policy.
+    ///
+    /// With `keep_annotations` the annotations of the removed shards are moved to the oldest shard left, the
+    /// shards being read and written with `key`.
+    #[tracing::instrument(skip(self, key), fields(dir = ?self.dir))]
+    pub fn remove_older(
+        &mut self,
+        older_than: DateTime<Utc>,
+        policy: Option<&BackupPolicy>,
+        keep_annotations: bool,
+        key: Option<&DatabaseKey>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut kept_annotations = Vec::new();
+        let mut removed = Vec::new();
+        for shard in self.shards.iter().filter(|shard| shard.end <= older_than) {
+            if keep_annotations {
+                kept_annotations
+                    .extend(Database::from_file(&shard.path.to_string_lossy(), key)?.annotations);
+            }
+            if let Some(policy) = policy {
+                create_backup(&shard.path, policy)?;
+            }
+            fs::remove_file(&shard.path).map_err(Error::FailedToRemoveFile)?;
+            info!("Removed the shard {:?}", shard.path);
+            removed.push(shard.path.clone());
+        }
+        self.shards.retain(|shard| !removed.contains(&shard.path));
+
+        if !kept_annotations.is_empty() {
+            if let Some(oldest) = self.shards.first() {
+                let (mut database, file, path) =
+                    Database::from_file_with_write(&oldest.path.to_string_lossy(), key)?;
+                let count = kept_annotations.len();
+                database.annotations.extend(kept_annotations);
+                database
+                    .annotations
+                    .sort_by_key(|annotation| annotation.time);
+                database.write_and_close_file(file, &path)?;
+                info!("Moved {count} annotations of the removed shards to {path:?}");
+            } else {
+                warn!(
+                    "No shard left to keep the {} annotations of the removed shards",
+                    kept_annotations.len()
+                );
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Write the snapshots of `database` to one new shard per period, existing shards are never overwritten.
+    #[tracing::instrument(skip(database))]
+    pub fn split(database: &Database, dir: &Path, period: ShardPeriod) -> Result<ShardedDatabase> {
+        fs::create_dir_all(dir).map_err(Error::FailedToWriteFile)?;
+        let mut sharded = Database::open_sharded(dir)?;
+
+        let mut names = database
+            .snapshots
+            .iter()
+            .map(|snap| period.shard_name(snap.time))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let path = dir.join(format!("{name}.{SHARD_EXTENSION}"));
+            if path.exists() {
+                return Err(Error::ShardAlreadyExists(path));
+            }
+            let (start, end) =
+                shard_span(&name).ok_or_else(|| Error::InvalidDatabasePath(path.clone()))?;
+
+            let mut shard = Database {
+                version: database.version.clone(),
+                hostname: database.hostname.clone(),
+                snapshots: database
+                    .snapshots
+                    .iter()
+                    .filter(|snap| (start..end).contains(&snap.time))
+                    .cloned()
+                    .collect(),
+                annotations: database
+                    .annotations
+                    .iter()
+                    .filter(|annotation| (start..end).contains(&annotation.time))
+                    .cloned()
+                    .collect(),
+                key: database.key.clone(),
+                checksums: database.checksums,
+                skipped_records: 0,
+                previous_facts: None,
+                last_write: None,
+                writer: database.writer.clone(),
+            };
+            shard.write_to_file(&path.to_string_lossy())?;
+            info!("Wrote {} snapshots to {path:?}", shard.snapshots.len());
+            sharded.shards.push(Shard { path, start, end });
+        }
+        sharded.shards.sort_by_key(|shard| shard.start);
+
+        Ok(sharded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SnapshotBuilder;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 30, 0).unwrap()
+    }
+
+    /// Monthly shards of April, May and June 2024, with an annotation in April.
+    fn sharded(dir: &Path) -> ShardedDatabase {
+        let database = Database {
+            snapshots: [at(2024, 4, 30, 23), at(2024, 5, 1, 0), at(2024, 6, 1, 0)]
+                .into_iter()
+                .map(|time| SnapshotBuilder::at(time).build())
+                .collect(),
+            annotations: vec![Annotation {
+                time: at(2024, 4, 30, 22),
+                kind: AnnotationKind::Reboot,
+                text: "rebooted".to_string(),
+            }],
+            ..Database::default()
+        };
+        ShardedDatabase::split(&database, dir, ShardPeriod::Monthly).unwrap()
+    }
+
+    #[test]
+    fn ranges_spanning_a_boundary_load_both_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let sharded = sharded(dir.path());
+        assert_eq!(sharded.shards().len(), 3);
+
+        let database = sharded
+            .load_between(at(2024, 4, 30, 23), at(2024, 5, 1, 0), None)
+            .unwrap();
+        let times = database
+            .snapshots()
+            .iter()
+            .map(|snap| snap.time)
+            .collect::<Vec<_>>();
+        assert_eq!(times, [at(2024, 4, 30, 23), at(2024, 5, 1, 0)]);
+        assert_eq!(database.annotations.len(), 1);
+    }
+
+    #[test]
+    fn boundaries_belong_to_the_shard_starting_there() {
+        let dir = tempfile::tempdir().unwrap();
+        let sharded = sharded(dir.path());
+        let may = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+
+        let names = |from, to| {
+            sharded
+                .shards_between(from, to)
+                .map(|shard| {
+                    shard
+                        .path
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(may, at(2024, 5, 20, 0)), ["2024-05"]);
+        assert_eq!(names(at(2024, 4, 2, 0), may), ["2024-04", "2024-05"]);
+        assert_eq!(
+            names(at(2024, 7, 1, 0), at(2024, 8, 1, 0)),
+            Vec::<String>::new()
+        );
+        assert_eq!(sharded.shard_before(may).unwrap().start.month(), 4);
+    }
+
+    #[test]
+    fn retention_deletes_the_shards_before_the_cutoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sharded = sharded(dir.path());
+        let june = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+
+        let removed = sharded.remove_older(june, None, true, None).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(removed.iter().all(|path| !path.exists()));
+
+        let reopened = Database::open_sharded(dir.path()).unwrap();
+        assert_eq!(reopened.shards(), sharded.shards());
+        let left = reopened
+            .load_between(DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC, None)
+            .unwrap();
+        assert_eq!(left.len(), 1);
+        // NOTE: The annotation of April is kept by the oldest shard left
+        assert_eq!(left.annotations.len(), 1);
+        assert_eq!(left.annotations[0].text, "rebooted");
+    }
+
+    #[test]
+    fn retention_keeps_the_shard_of_the_cutoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sharded = sharded(dir.path());
+
+        let removed = sharded
+            .remove_older(at(2024, 5, 15, 0), None, false, None)
+            .unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(sharded.shards().len(), 2);
+        assert_eq!(sharded.shards()[0].start.month(), 5);
+        // NOTE: Without keeping them, the annotations go away with their shard
+        let left = sharded
+            .load_between(DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC, None)
+            .unwrap();
+        assert_eq!(left.len(), 2);
+        assert!(left.annotations.is_empty());
+    }
+}
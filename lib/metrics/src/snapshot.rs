@@ -1,18 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
+#[cfg(target_os = "linux")]
+use ::psutil::cpu::os::linux::CpuTimesExt;
 use ::psutil::{
     cpu::{cpu_times_percpu, CpuTimes},
     disk::{disk_usage, partitions_physical, DiskIoCounters, DiskIoCountersCollector},
     memory::{swap_memory, virtual_memory, SwapMemory, VirtualMemory},
     network::{NetIoCounters, NetIoCountersCollector},
-    sensors::{temperatures, TemperatureSensor},
+    sensors::TemperatureSensor,
 };
 use chrono::{DateTime, Utc};
 use log::{debug, tracing};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::Result;
+use crate::{collectors::CollectorRegistry, Result};
+
+/// Space of a filesystem in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DiskUsageSample {
+    pub total: u64,
+    pub used: u64,
+    /// Available to unprivileged users, less than `total - used` on filesystems reserving blocks for root.
+    pub free: u64,
+    pub percent: f32,
+}
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -22,45 +35,165 @@ pub struct SnapShot {
     pub swap: SwapMemory,
     pub networks: Vec<NetIoCounters>,
     pub disks_io: HashMap<String, DiskIoCounters>,
+    /// Used space in percent by mountpoint, kept along `disks_usage` for the readers of older databases.
     pub disks_memory: HashMap<String, f32>,
+    /// Space in bytes by mountpoint, empty for snapshots taken before it was recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub disks_usage: HashMap<String, DiskUsageSample>,
     pub temps: Vec<TemperatureSensor>,
     pub load_avgs: crate::psutil::LoadAvg,
     pub time: DateTime<Utc>,
+    // NOTE: Missing from snapshots taken before it was introduced
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub host_facts: Option<crate::psutil::HostFacts>,
+    /// The clock jumped since the previous snapshot, the interval ending at this one is not trusted.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub clock_jump: bool,
+    /// The host was suspended during the interval ending at this one, see `suspend::interval_gap`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub suspended: bool,
+    /// Values of the registered collectors by key, see `SnapShot::new_with_collectors`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub custom: HashMap<String, f64>,
+    /// `None` when the platform doesn't report the CPU frequency.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cpu_freq: Option<crate::psutil::CpuFreq>,
+    /// Wall-clock time taken to collect the snapshot, `None` for snapshots taken before it was recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub collection_duration_ms: Option<u64>,
+    /// Time taken by each part of the collection (e.g. `disks_memory`, `custom gpu`), see `slowest_collector`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub collection_breakdown_ms: HashMap<String, u64>,
+    /// `None` outside of Linux and for snapshots taken before they were recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub kernel: Option<crate::psutil::KernelGauges>,
+    /// Link of each network interface not ignored by name, empty outside of Linux and for snapshots taken
+    /// before it was recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub nic_info: HashMap<String, crate::psutil::NicInfo>,
+    /// Taken in a container, the memory and CPU usages being relative to its cgroup limits when known.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub container: bool,
+    /// Limits of the cgroup of the container, `None` outside of one or with `--no-cgroup-limits`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cgroup: Option<crate::psutil::CgroupLimits>,
+}
+
+/// Run `collect`, recording how long it took in `breakdown` under `name`.
+fn timed<T>(
+    breakdown: &mut HashMap<String, u64>,
+    name: &str,
+    collect: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let started = Instant::now();
+    let result = collect();
+    breakdown.insert(name.to_string(), started.elapsed().as_millis() as u64);
+    result
+}
+
+#[cfg(feature = "sensors")]
+fn read_temperatures() -> Result<Vec<TemperatureSensor>> {
+    Ok(::psutil::sensors::temperatures()
+        .into_iter()
+        .collect::<std::result::Result<Vec<TemperatureSensor>, _>>()?)
+}
+
+// NOTE: The snapshots keep their list of sensors so the databases are the same with or without the feature
+#[cfg(not(feature = "sensors"))]
+fn read_temperatures() -> Result<Vec<TemperatureSensor>> {
+    Ok(Vec::new())
 }
 
 impl SnapShot {
     #[tracing::instrument]
     pub fn new(networks_to_ignore: &[&str]) -> Result<Self> {
-        let result = Self {
-            cpus: cpu_times_percpu()?,
-            memory: virtual_memory()?,
-            swap: swap_memory()?,
-            networks: NetIoCountersCollector::default()
-                .net_io_counters_pernic()?
-                .into_iter()
-                .filter_map(|(k, v)| {
-                    if networks_to_ignore.contains(&k.as_str()) {
-                        None
-                    } else {
-                        Some(v)
-                    }
-                })
-                .collect(),
-            disks_io: DiskIoCountersCollector::default().disk_io_counters_per_partition()?,
-            disks_memory: partitions_physical()?
+        Self::new_at(Utc::now(), networks_to_ignore)
+    }
+
+    /// Snapshot of the current values stamped with `time`, e.g. to backfill or to test, rather than now.
+    #[tracing::instrument]
+    pub fn new_at(time: DateTime<Utc>, networks_to_ignore: &[&str]) -> Result<Self> {
+        let started = Instant::now();
+        // NOTE: Only timed with `Instant` so measuring doesn't slow the collection down
+        let mut breakdown = HashMap::new();
+        let container = timed(&mut breakdown, "container", || {
+            Ok(crate::psutil::in_container())
+        })?;
+        let disks_usage = timed(&mut breakdown, "disks_memory", || {
+            partitions_physical()?
                 .iter()
-                .map(|part| -> crate::Result<(String, f32)> {
+                // NOTE: The layers and scratch mounts of a container only mirror the disks of the host
+                .filter(|part| {
+                    !(container && matches!(part.filesystem().as_str(), "overlay" | "tmpfs"))
+                })
+                .map(|part| -> crate::Result<(String, DiskUsageSample)> {
+                    let usage = disk_usage(part.mountpoint())?;
                     Ok((
                         part.mountpoint().to_string_lossy().to_string(),
-                        disk_usage(part.mountpoint())?.percent(),
+                        DiskUsageSample {
+                            total: usage.total(),
+                            used: usage.used(),
+                            free: usage.free(),
+                            percent: usage.percent(),
+                        },
                     ))
                 })
-                .collect::<std::result::Result<HashMap<_, _>, _>>()?,
-            temps: temperatures()
-                .into_iter()
-                .collect::<std::result::Result<Vec<TemperatureSensor>, _>>()?,
-            load_avgs: crate::psutil::LoadAvg::new()?,
-            time: Utc::now(),
+                .collect::<std::result::Result<HashMap<_, _>, _>>()
+        })?;
+        let result = Self {
+            cpus: timed(&mut breakdown, "cpus", || Ok(cpu_times_percpu()?))?,
+            memory: timed(&mut breakdown, "memory", || Ok(virtual_memory()?))?,
+            swap: timed(&mut breakdown, "swap", || Ok(swap_memory()?))?,
+            networks: timed(&mut breakdown, "networks", || {
+                Ok(NetIoCountersCollector::default()
+                    .net_io_counters_pernic()?
+                    .into_iter()
+                    .filter_map(|(k, v)| {
+                        if networks_to_ignore.contains(&k.as_str()) {
+                            None
+                        } else {
+                            Some(v)
+                        }
+                    })
+                    .collect())
+            })?,
+            disks_io: timed(&mut breakdown, "disks_io", || {
+                Ok(DiskIoCountersCollector::default().disk_io_counters_per_partition()?)
+            })?,
+            disks_memory: disks_usage
+                .iter()
+                .map(|(mountpoint, usage)| (mountpoint.clone(), usage.percent))
+                .collect(),
+            disks_usage,
+            temps: timed(&mut breakdown, "temps", read_temperatures)?,
+            load_avgs: timed(&mut breakdown, "load_avgs", crate::psutil::LoadAvg::new)?,
+            time,
+            host_facts: timed(&mut breakdown, "host_facts", || {
+                Ok(Some(crate::psutil::HostFacts::current()))
+            })?,
+            clock_jump: false,
+            suspended: false,
+            custom: HashMap::new(),
+            cpu_freq: timed(&mut breakdown, "cpu_freq", || {
+                Ok(crate::psutil::CpuFreq::current())
+            })?,
+            kernel: timed(&mut breakdown, "kernel", || {
+                Ok(crate::psutil::KernelGauges::current())
+            })?,
+            nic_info: timed(&mut breakdown, "nic_info", || {
+                Ok(crate::psutil::NicInfo::current()
+                    .into_iter()
+                    .filter(|(name, _)| !networks_to_ignore.contains(&name.as_str()))
+                    .collect())
+            })?,
+            container,
+            cgroup: timed(&mut breakdown, "cgroup", || {
+                Ok(container
+                    .then(crate::psutil::CgroupLimits::current)
+                    .flatten())
+            })?,
+            collection_duration_ms: Some(started.elapsed().as_millis() as u64),
+            collection_breakdown_ms: breakdown,
         };
 
         log::trace!("Snapshot taken with data\n{:#?}", result);
@@ -68,6 +201,50 @@ impl SnapShot {
         Ok(result)
     }
 
+    /// Snapshot with the values of the collectors, run after the built-in collection, the usages of a
+    /// container being relative to the host without `cgroup_limits`.
+    #[tracing::instrument]
+    pub fn new_with_collectors(
+        networks_to_ignore: &[&str],
+        collectors: &CollectorRegistry,
+        cgroup_limits: bool,
+    ) -> Result<Self> {
+        Self::new_with_collectors_at(Utc::now(), networks_to_ignore, collectors, cgroup_limits)
+    }
+
+    /// Same as `new_with_collectors`, stamped with `time` rather than now.
+    #[tracing::instrument]
+    pub fn new_with_collectors_at(
+        time: DateTime<Utc>,
+        networks_to_ignore: &[&str],
+        collectors: &CollectorRegistry,
+        cgroup_limits: bool,
+    ) -> Result<Self> {
+        let started = Instant::now();
+        let mut result = Self::new_at(time, networks_to_ignore)?;
+        if !cgroup_limits {
+            result.cgroup = None;
+        }
+        result.custom = collectors.collect_timed(&mut result.collection_breakdown_ms);
+        result.collection_duration_ms = Some(started.elapsed().as_millis() as u64);
+
+        Ok(result)
+    }
+
+    /// Part of the collection that took the longest as (name, milliseconds).
+    pub fn slowest_collector(&self) -> Option<(&str, u64)> {
+        self.collection_breakdown_ms
+            .iter()
+            .max_by_key(|(_, duration)| **duration)
+            .map(|(name, duration)| (name.as_str(), *duration))
+    }
+
+    /// The rates of the interval ending at this snapshot are not computed, the clock having jumped or the
+    /// host having been suspended during it.
+    pub fn interval_untrusted(&self) -> bool {
+        self.clock_jump || self.suspended
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn get_cpu_count(&self) -> usize {
         self.cpus.len()
@@ -85,9 +262,48 @@ impl SnapShot {
         result
     }
 
+    /// CPU times in seconds summed over every CPU as (busy, iowait, steal, idle).
+    ///
+    /// `busy` excludes steal and `idle` excludes iowait so the four parts add up to the total time,
+    /// iowait and steal are `None` on platforms that don't report them.
+    #[tracing::instrument(skip(self))]
+    pub fn get_cpu_time_breakdown(&self) -> (f64, Option<f64>, Option<f64>, f64) {
+        let result = self.cpus.iter().fold(
+            (0.0, None, None, 0.0),
+            |(busy, iowait, steal, idle): (f64, Option<f64>, Option<f64>, f64), cpu| {
+                #[cfg(target_os = "linux")]
+                let (cpu_iowait, cpu_steal) = (
+                    Some(cpu.iowait().as_secs_f64()),
+                    cpu.steal().map(|steal| steal.as_secs_f64()),
+                );
+                #[cfg(not(target_os = "linux"))]
+                let (cpu_iowait, cpu_steal): (Option<f64>, Option<f64>) = (None, None);
+
+                (
+                    busy + cpu.busy().as_secs_f64() - cpu_steal.unwrap_or_default(),
+                    cpu_iowait.map(|value| iowait.unwrap_or_default() + value),
+                    cpu_steal.map(|value| steal.unwrap_or_default() + value),
+                    idle + cpu.idle().as_secs_f64() - cpu_iowait.unwrap_or_default(),
+                )
+            },
+        );
+        debug!(
+            busy_time = result.0,
+            iowait_time = ?result.1,
+            steal_time = ?result.2,
+            idle_time = result.3
+        );
+        result
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn get_ram_usage(&self) -> (f64, f64) {
-        let result = (self.memory.percent() as f64, self.swap.percent() as f64);
+        // NOTE: A container without memory limit can use the whole memory of the host
+        let ram = self
+            .cgroup
+            .and_then(|cgroup| cgroup.memory_percent())
+            .unwrap_or(self.memory.percent() as f64);
+        let result = (ram, self.swap.percent() as f64);
         debug!(
             ram_percent_usage = result.0,
             ram_total = self.memory.total(),
@@ -97,6 +313,21 @@ impl SnapShot {
         result
     }
 
+    /// Pages swapped (in, out) since boot, `None` on platforms that don't report them.
+    #[tracing::instrument(skip(self))]
+    pub fn get_swap_activity(&self) -> Option<(f64, f64)> {
+        // NOTE: Linux reports page counts from /proc/vmstat while other platforms use other units or nothing
+        #[cfg(target_os = "linux")]
+        let result = Some((
+            self.swap.swapped_in() as f64,
+            self.swap.swapped_out() as f64,
+        ));
+        #[cfg(not(target_os = "linux"))]
+        let result = None;
+        debug!(swap_activity = ?result);
+        result
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn get_load(&self) -> (f64, f64, f64) {
         let result = (
@@ -133,14 +364,52 @@ impl SnapShot {
         result
     }
 
+    /// Used space in percent of each mountpoint.
     #[tracing::instrument(skip(self))]
     pub fn get_disks_size_usage(&self) -> Vec<(String, f64)> {
+        // NOTE: Snapshots taken before the bytes were recorded only have the percent
+        let result = if self.disks_usage.is_empty() {
+            self.disks_memory
+                .iter()
+                .map(|(name, usage)| (name.clone(), *usage as f64))
+                .collect()
+        } else {
+            self.disks_usage
+                .iter()
+                .map(|(name, usage)| (name.clone(), usage.percent as f64))
+                .collect()
+        };
+        debug!(disks_size_usage = ?result);
+        result
+    }
+
+    /// Space in bytes of each mountpoint, empty for snapshots taken before it was recorded.
+    #[tracing::instrument(skip(self))]
+    pub fn get_disks_bytes_usage(&self) -> Vec<(String, DiskUsageSample)> {
         let result = self
-            .disks_memory
+            .disks_usage
             .iter()
-            .map(|(name, usage)| (name.clone(), *usage as f64))
+            .map(|(name, usage)| (name.clone(), *usage))
             .collect();
-        debug!(disks_size_usage = ?result);
+        debug!(disks_bytes_usage = ?result);
+        result
+    }
+
+    /// Current temperature in degrees Celsius of each sensor, named by unit then label, e.g. `coretemp Core 0`.
+    #[tracing::instrument(skip(self))]
+    pub fn get_temperatures(&self) -> Vec<(String, f64)> {
+        let result = self
+            .temps
+            .iter()
+            .map(|sensor| {
+                let name = match sensor.label() {
+                    Some(label) => format!("{} {label}", sensor.unit()),
+                    None => sensor.unit().to_string(),
+                };
+                (name, sensor.current().celsius())
+            })
+            .collect();
+        debug!(temperatures = ?result);
         result
     }
 
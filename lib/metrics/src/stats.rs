@@ -0,0 +1,540 @@
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+    str::FromStr,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{trace, tracing};
+
+use crate::{errors::Error, Result};
+
+// NOTE: Relative to the mean, below it the deviation is only floating point noise of a constant series
+const MIN_RELATIVE_STD_DEV: f64 = 1e-9;
+
+/// How the samples of a window are averaged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Averaging {
+    /// Each sample weighted by the time it covers, so the periods collected more often don't outweigh the
+    /// others, see `time_weighted_mean`.
+    #[default]
+    TimeWeighted,
+    /// Arithmetic mean of the samples.
+    Plain,
+}
+
+impl FromStr for Averaging {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "time-weighted" => Ok(Averaging::TimeWeighted),
+            "plain" => Ok(Averaging::Plain),
+            _ => Err(Error::InvalidAveraging(value.to_string())),
+        }
+    }
+}
+
+impl Display for Averaging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Averaging::TimeWeighted => write!(f, "time-weighted"),
+            Averaging::Plain => write!(f, "plain"),
+        }
+    }
+}
+
+impl Averaging {
+    /// Average of the samples sorted by time, `window` being the range they are averaged over.
+    pub fn mean(
+        self,
+        samples: &[(f64, DateTime<Utc>)],
+        window: Option<Range<DateTime<Utc>>>,
+    ) -> Option<f64> {
+        match self {
+            Averaging::TimeWeighted => time_weighted_mean(samples, window),
+            Averaging::Plain => {
+                mean_std_dev(&samples.iter().map(|(value, _)| *value).collect::<Vec<_>>())
+                    .map(|(mean, _)| mean)
+            }
+        }
+    }
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds().max(0) as f64 / 1000.0
+}
+
+/// Mean of the samples sorted by time, integrated with the trapezoidal rule so each sample weighs the time
+/// up to its neighbours.
+///
+/// The part of `window` before the first sample and after the last one holds their values, a single sample
+/// then covers the whole window. Duplicate timestamps add nothing to the integral, samples all taken at
+/// the same time are averaged plainly.
+pub fn time_weighted_mean(
+    samples: &[(f64, DateTime<Utc>)],
+    window: Option<Range<DateTime<Utc>>>,
+) -> Option<f64> {
+    let (first_value, first_time) = *samples.first()?;
+    let (last_value, last_time) = *samples.last()?;
+
+    let mut area = samples
+        .windows(2)
+        .map(|pair| {
+            let ((previous, previous_time), (value, time)) = (pair[0], pair[1]);
+            (previous + value) / 2.0 * seconds_between(previous_time, time)
+        })
+        .sum::<f64>();
+    let mut duration = seconds_between(first_time, last_time);
+    if let Some(window) = window {
+        let (leading, trailing) = (
+            seconds_between(window.start, first_time),
+            seconds_between(last_time, window.end),
+        );
+        area += first_value * leading + last_value * trailing;
+        duration += leading + trailing;
+    }
+
+    if duration > 0.0 {
+        Some(area / duration)
+    } else {
+        Averaging::Plain.mean(samples, None)
+    }
+}
+
+/// Mean and standard deviation of the values.
+pub fn mean_std_dev(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let len = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / len;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / len;
+
+    Some((mean, variance.sqrt()))
+}
+
+/// `percentile` (0 to 100) of the values, interpolated between the two closest ranks, `None` without values.
+pub fn percentile(values: &[f64], percentile: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+    let rank = (percentile.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
+
+/// Centered moving average over `window` values, shrinking at both ends of the series so each value
+/// keeps its position.
+///
+/// Even windows take one more value before the averaged one than after it, a window of 0 or 1 or a window
+/// larger than the series averages what is available.
+pub fn moving_average(values: &[f64], window: usize) -> Vec<f64> {
+    if window <= 1 {
+        return values.to_vec();
+    }
+
+    let before = window / 2;
+    let after = (window - 1) / 2;
+    let mut sums = Vec::with_capacity(values.len() + 1);
+    sums.push(0.0);
+    for value in values {
+        sums.push(sums[sums.len() - 1] + value);
+    }
+
+    (0..values.len())
+        .map(|idx| {
+            let from = idx.saturating_sub(before);
+            let to = (idx + after + 1).min(values.len());
+            (sums[to] - sums[from]) / (to - from) as f64
+        })
+        .collect()
+}
+
+/// Indexes of the samples above the mean plus `sigmas` standard deviations of the samples
+/// taken in the `window` preceding them.
+///
+/// Samples must be sorted by time. Nothing is flagged until a whole window of samples precedes
+/// a sample (warm-up) nor when the window is constant.
+#[tracing::instrument(level = "trace", skip(samples))]
+pub fn rolling_outliers(
+    samples: &[(f64, DateTime<Utc>)],
+    window: Duration,
+    sigmas: f64,
+) -> Vec<usize> {
+    let Some((_, first_time)) = samples.first() else {
+        return Vec::new();
+    };
+
+    let mut window_start = 0;
+    let result = samples
+        .iter()
+        .enumerate()
+        .filter(|(idx, (value, time))| {
+            let from = *time - window;
+            if from < *first_time {
+                return false;
+            }
+            while samples[window_start].1 < from {
+                window_start += 1;
+            }
+
+            let window_values = samples[window_start..*idx]
+                .iter()
+                .map(|(value, _)| *value)
+                .collect::<Vec<_>>();
+            // NOTE: A single value has no meaningful deviation
+            if window_values.len() < 2 {
+                return false;
+            }
+
+            mean_std_dev(&window_values).is_some_and(|(mean, std_dev)| {
+                std_dev > MIN_RELATIVE_STD_DEV * mean.abs().max(1.0)
+                    && *value > mean + sigmas * std_dev
+            })
+        })
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+
+    trace!(outliers = ?result);
+    result
+}
+
+/// Slice being averaged as (index, sum of the values, sum of the timestamps, count, (min, max)).
+type Bucket = (usize, f64, i64, i64, (f64, f64));
+
+/// Average of the samples of each of the `buckets` equal slices of the `start..=end` timestamps, with
+/// the (min, max) of the slice, slices without samples being skipped.
+///
+/// Samples must be sorted by time, they are returned as is when there are no more than `buckets`.
+#[tracing::instrument(level = "trace", skip(samples))]
+pub fn downsample(
+    samples: &[(f64, i64)],
+    start: i64,
+    end: i64,
+    buckets: usize,
+) -> Vec<(f64, i64, (f64, f64))> {
+    if samples.len() <= buckets || buckets == 0 {
+        return samples
+            .iter()
+            .map(|&(value, timestamp)| (value, timestamp, (value, value)))
+            .collect();
+    }
+
+    // NOTE: Computed with floats so short ranges still get `buckets` slices
+    let bucket_duration = (end - start).max(1) as f64 / buckets as f64;
+    let bucket_of =
+        |timestamp: i64| (((timestamp - start) as f64 / bucket_duration) as usize).min(buckets - 1);
+    let to_point = |(_, sum, timestamps_sum, count, extremes): Bucket| {
+        (sum / count as f64, timestamps_sum / count, extremes)
+    };
+
+    let mut result = Vec::with_capacity(buckets);
+    let mut current: Option<Bucket> = None;
+    for &(value, timestamp) in samples {
+        let bucket = bucket_of(timestamp);
+        match &mut current {
+            Some((current_bucket, sum, timestamps_sum, count, (min, max)))
+                if *current_bucket == bucket =>
+            {
+                *sum += value;
+                *timestamps_sum += timestamp;
+                *count += 1;
+                *min = min.min(value);
+                *max = max.max(value);
+            }
+            _ => {
+                if let Some(current) = current {
+                    result.push(to_point(current));
+                }
+                current = Some((bucket, value, timestamp, 1, (value, value)));
+            }
+        }
+    }
+    if let Some(current) = current {
+        result.push(to_point(current));
+    }
+
+    trace!(before = samples.len(), after = result.len());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn time(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    /// Sample every minute of a series wavering between 50 and 54, with the given spikes.
+    fn series(len: usize, spikes: &[usize]) -> Vec<(f64, DateTime<Utc>)> {
+        (0..len)
+            .map(|idx| {
+                let value = if spikes.contains(&idx) {
+                    100.0
+                } else {
+                    50.0 + (idx % 5) as f64
+                };
+                (value, time(idx as i64))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn injected_spikes_are_flagged() {
+        let samples = series(200, &[90, 150, 151]);
+
+        let outliers = rolling_outliers(&samples, Duration::hours(1), 3.0);
+
+        // NOTE: A single spike in the window of the next one widens it, but not enough to hide it
+        assert_eq!(outliers, [90, 150, 151]);
+        assert!(rolling_outliers(&series(200, &[]), Duration::hours(1), 3.0).is_empty());
+    }
+
+    #[test]
+    fn nothing_is_flagged_during_the_warm_up() {
+        let samples = series(200, &[30, 59, 60]);
+
+        let outliers = rolling_outliers(&samples, Duration::hours(1), 3.0);
+
+        // NOTE: The window of the sample an hour after the first one is the first one to be whole
+        assert_eq!(outliers, [60]);
+    }
+
+    #[test]
+    fn constant_series_are_never_flagged() {
+        let constant = (0..200).map(|idx| (42.0, time(idx))).collect::<Vec<_>>();
+        assert!(rolling_outliers(&constant, Duration::hours(1), 3.0).is_empty());
+
+        // NOTE: A jump after a constant window has no deviation to compare with
+        let mut jump = constant.clone();
+        jump[100].0 = 1000.0;
+        assert!(rolling_outliers(&jump, Duration::hours(1), 3.0).is_empty());
+
+        // NOTE: Floating point noise of large values is not a deviation either
+        let noisy = (0..200)
+            .map(|idx| (1e12 + if idx % 2 == 0 { 1e-4 } else { 0.0 }, time(idx)))
+            .collect::<Vec<_>>();
+        assert!(rolling_outliers(&noisy, Duration::hours(1), 3.0).is_empty());
+    }
+
+    #[test]
+    fn sparse_windows_are_not_flagged() {
+        // NOTE: A gap leaves a single sample in the window of the spike
+        let samples = [
+            (1.0, time(0)),
+            (2.0, time(61)),
+            (1.0, time(200)),
+            (100.0, time(201)),
+        ];
+
+        assert!(rolling_outliers(&samples, Duration::hours(1), 3.0).is_empty());
+        assert!(rolling_outliers(&[], Duration::hours(1), 3.0).is_empty());
+    }
+
+    #[test]
+    fn means_are_weighted_by_time() {
+        // NOTE: 10 for a minute then 20 for nine minutes
+        let samples = [
+            (10.0, time(0)),
+            (10.0, time(1)),
+            (20.0, time(1)),
+            (20.0, time(10)),
+        ];
+        assert_eq!(time_weighted_mean(&samples, None), Some(19.0));
+        assert_eq!(Averaging::Plain.mean(&samples, None), Some(15.0));
+
+        let window = time(0)..time(20);
+        assert_eq!(
+            time_weighted_mean(&[(5.0, time(5))], Some(window.clone())),
+            Some(5.0)
+        );
+        assert_eq!(
+            time_weighted_mean(&[(10.0, time(10)), (20.0, time(10))], None),
+            Some(15.0)
+        );
+        assert_eq!(time_weighted_mean(&[], Some(window)), None);
+    }
+
+    #[test]
+    fn averagings_are_parsed() {
+        for averaging in [Averaging::TimeWeighted, Averaging::Plain] {
+            assert_eq!(
+                averaging.to_string().parse::<Averaging>().unwrap(),
+                averaging
+            );
+        }
+        assert_eq!(" Plain ".parse::<Averaging>().unwrap(), Averaging::Plain);
+        assert!(matches!(
+            "median".parse::<Averaging>(),
+            Err(Error::InvalidAveraging(_))
+        ));
+    }
+
+    #[test]
+    fn percentiles_are_interpolated() {
+        let values = [4.0, 1.0, 3.0, 2.0, 5.0];
+        assert_eq!(percentile(&values, 50.0), Some(3.0));
+        assert_eq!(percentile(&values, 90.0), Some(4.6));
+        assert_eq!(percentile(&values, 150.0), Some(5.0));
+        assert_eq!(percentile(&[], 50.0), None);
+        assert_eq!(
+            mean_std_dev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]),
+            Some((5.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn moving_averages_keep_the_positions() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(moving_average(&values, 3), [1.5, 2.0, 3.0, 4.0, 4.5]);
+        assert_eq!(moving_average(&values, 2), [1.0, 1.5, 2.5, 3.5, 4.5]);
+        assert_eq!(moving_average(&values, 1), values);
+        assert_eq!(moving_average(&values, 100), [3.0; 5]);
+    }
+
+    #[test]
+    fn even_moving_averages_take_one_more_value_before() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+
+        // NOTE: Two values before and one after, shrinking at both ends
+        assert_eq!(moving_average(&values, 4), [1.5, 2.0, 2.5, 3.5, 4.5, 5.0]);
+        assert_eq!(moving_average(&values, 6), [2.0, 2.5, 3.0, 3.5, 4.0, 4.5]);
+        assert_eq!(moving_average(&values, 0), values);
+        assert!(moving_average(&[], 5).is_empty());
+        assert_eq!(moving_average(&[7.0], 5), [7.0]);
+    }
+
+    #[test]
+    fn downsampling_averages_each_slice() {
+        let samples = (0..100).map(|idx| (idx as f64, idx)).collect::<Vec<_>>();
+
+        let downsampled = downsample(&samples, 0, 100, 10);
+
+        assert_eq!(downsampled.len(), 10);
+        assert_eq!(downsampled[0], (4.5, 4, (0.0, 9.0)));
+        assert_eq!(downsampled[9], (94.5, 94, (90.0, 99.0)));
+        assert_eq!(downsample(&samples[..5], 0, 100, 10).len(), 5);
+    }
+
+    /// A value of 100 sampled every minute for 10 minutes, then 0 every 5 minutes for the next 50 minutes.
+    fn irregular() -> Vec<(f64, DateTime<Utc>)> {
+        (0..=10)
+            .map(|minute| (100.0, time(minute)))
+            .chain((3..=12).map(|step| (0.0, time(step * 5))))
+            .collect()
+    }
+
+    #[test]
+    fn time_weighted_means_discount_the_dense_periods() {
+        let samples = irregular();
+
+        let plain = Averaging::Plain.mean(&samples, None).unwrap();
+        let weighted = Averaging::TimeWeighted.mean(&samples, None).unwrap();
+
+        // NOTE: 11 samples of 100 out of 21, while 100 only lasts 10 of the 60 minutes and half of the next 5
+        assert!((plain - 1100.0 / 21.0).abs() < 1e-9, "{plain}");
+        assert!((weighted - 75_000.0 / 3600.0).abs() < 1e-9, "{weighted}");
+        assert_eq!(
+            time_weighted_mean(&samples, Some(time(0)..time(60))),
+            Some(weighted)
+        );
+    }
+
+    #[test]
+    fn single_samples_cover_the_whole_window() {
+        let samples = [(42.0, time(5))];
+
+        assert_eq!(
+            time_weighted_mean(&samples, Some(time(0)..time(10))),
+            Some(42.0)
+        );
+        assert_eq!(time_weighted_mean(&samples, None), Some(42.0));
+        assert_eq!(time_weighted_mean(&[], Some(time(0)..time(10))), None);
+        assert_eq!(Averaging::Plain.mean(&[], None), None);
+    }
+
+    #[test]
+    fn window_edges_hold_the_nearest_values() {
+        let samples = [(10.0, time(10)), (20.0, time(20))];
+
+        // NOTE: 10 for the first 10 minutes, 15 on average over the next 10, then 20 for the last 10
+        assert_eq!(
+            time_weighted_mean(&samples, Some(time(0)..time(30))),
+            Some(15.0)
+        );
+        assert_eq!(
+            time_weighted_mean(&samples, Some(time(0)..time(20))),
+            Some(12.5)
+        );
+        assert_eq!(time_weighted_mean(&samples, None), Some(15.0));
+    }
+
+    #[test]
+    fn duplicate_timestamps_add_no_time() {
+        let duplicated = [(0.0, time(0)), (100.0, time(0)), (100.0, time(1))];
+        assert_eq!(time_weighted_mean(&duplicated, None), Some(100.0));
+
+        // NOTE: Without any time covered the samples are averaged plainly
+        let simultaneous = [(1.0, time(0)), (3.0, time(0))];
+        assert_eq!(time_weighted_mean(&simultaneous, None), Some(2.0));
+        assert_eq!(
+            time_weighted_mean(&simultaneous, Some(time(0)..time(0))),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn averagings_round_trip_through_their_names() {
+        for averaging in [Averaging::TimeWeighted, Averaging::Plain] {
+            assert_eq!(
+                averaging.to_string().parse::<Averaging>().unwrap(),
+                averaging
+            );
+        }
+        assert_eq!(" Plain ".parse::<Averaging>().unwrap(), Averaging::Plain);
+        assert_eq!(Averaging::default(), Averaging::TimeWeighted);
+        assert!(matches!(
+            "median".parse::<Averaging>(),
+            Err(Error::InvalidAveraging(value)) if value == "median"
+        ));
+    }
+
+    #[test]
+    fn percentiles_interpolate_between_ranks() {
+        let values = [5.0, 1.0, 4.0, 2.0, 3.0];
+
+        assert_eq!(percentile(&values, 0.0), Some(1.0));
+        assert_eq!(percentile(&values, 50.0), Some(3.0));
+        assert_eq!(percentile(&values, 100.0), Some(5.0));
+        assert_eq!(percentile(&values, 62.5), Some(3.5));
+        // NOTE: Out of bounds percentiles are the extremes
+        assert_eq!(percentile(&values, 150.0), Some(5.0));
+        assert_eq!(percentile(&[7.0], 99.5), Some(7.0));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn single_outliers_stay_above_the_high_percentiles() {
+        let mut values = (0..1000)
+            .map(|idx| 50.0 + (idx % 5) as f64)
+            .collect::<Vec<_>>();
+        values[500] = 4000.0;
+
+        let bulk = percentile(&values, 99.5).unwrap();
+
+        assert!((50.0..=54.0).contains(&bulk), "{bulk}");
+    }
+}
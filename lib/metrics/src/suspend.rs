@@ -0,0 +1,188 @@
+//! Heuristics telling the suspensions of the host (suspend to RAM or hibernation) apart from the other long
+//! intervals between two snapshots, the CPU times, idle included, not advancing while it sleeps.
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    annotations::{host_changes, AnnotationKind},
+    clock::{median_interval, MAX_INTERVAL_RATIO},
+    rates::cpu_count_changed,
+    snapshot::SnapShot,
+};
+
+/// Fraction of a long interval the CPUs ran for under which the host is considered suspended, their times
+/// advancing with the wall clock while it is awake even when idle.
+pub const SUSPENDED_MAX_CPU_RATIO: f64 = 0.5;
+
+/// Kind of an interval between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapKind {
+    /// Close to the usual interval.
+    Regular,
+    /// The host slept for about this long.
+    Suspended(Duration),
+    /// The CPUs ran through the gap, e.g. the collector was stopped on an idle host.
+    Awake,
+    /// The host rebooted during the gap, its CPU times restarting from zero.
+    Reboot,
+}
+
+/// Kind of an interval of `wall` while the snapshots are usually `expected` apart, each CPU having run for
+/// `cpu_elapsed` seconds during it (the delta of their total time divided by their number).
+pub fn classify_gap(
+    wall: Duration,
+    expected: Duration,
+    cpu_elapsed: f64,
+    rebooted: bool,
+) -> GapKind {
+    if expected <= Duration::zero() || wall <= expected * MAX_INTERVAL_RATIO {
+        return GapKind::Regular;
+    }
+    if rebooted || cpu_elapsed < 0.0 {
+        return GapKind::Reboot;
+    }
+
+    let wall_secs = wall.num_milliseconds() as f64 / 1000.0;
+    if cpu_elapsed < wall_secs * SUSPENDED_MAX_CPU_RATIO {
+        GapKind::Suspended(wall - Duration::milliseconds((cpu_elapsed * 1000.0) as i64))
+    } else {
+        GapKind::Awake
+    }
+}
+
+/// Kind of the interval between `previous` and `current`, `recent` being the times of the snapshots up to
+/// `previous` the usual interval is taken from.
+///
+/// An interval during which the number of CPUs changed can't be told apart and is `Awake`.
+pub fn interval_gap(recent: &[DateTime<Utc>], previous: &SnapShot, current: &SnapShot) -> GapKind {
+    let Some(expected) = median_interval(recent) else {
+        return GapKind::Regular;
+    };
+    let wall = current.time - previous.time;
+    if wall <= expected * MAX_INTERVAL_RATIO {
+        return GapKind::Regular;
+    }
+    if cpu_count_changed(previous, current) || current.get_cpu_count() == 0 {
+        return GapKind::Awake;
+    }
+
+    let rebooted = previous
+        .host_facts
+        .as_ref()
+        .zip(current.host_facts.as_ref())
+        .is_some_and(|(previous, current)| {
+            host_changes(previous, current)
+                .iter()
+                .any(|(kind, _)| *kind == AnnotationKind::Reboot)
+        });
+    let cpu_elapsed =
+        (current.get_cpu_time().1 - previous.get_cpu_time().1) / current.get_cpu_count() as f64;
+    classify_gap(wall, expected, cpu_elapsed, rebooted)
+}
+
+/// Description of a suspension, e.g. "suspended ~9h".
+pub fn suspension_text(duration: Duration) -> String {
+    if duration < Duration::hours(2) {
+        format!("suspended ~{} min", duration.num_minutes())
+    } else {
+        format!(
+            "suspended ~{}h",
+            (duration.num_minutes() as f64 / 60.0).round()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NINE_HOURS: i64 = 9 * 60 * 60;
+
+    #[test]
+    fn gaps_are_told_apart_by_the_cpu_time_elapsed() {
+        let expected = Duration::minutes(1);
+        let wall = Duration::seconds(NINE_HOURS);
+
+        // NOTE: A minute of CPU time over 9 hours, the host slept
+        assert_eq!(
+            classify_gap(wall, expected, 60.0, false),
+            GapKind::Suspended(wall - Duration::minutes(1))
+        );
+        // NOTE: Idle time counts, an idle host that stayed awake ran through the whole gap
+        assert_eq!(
+            classify_gap(wall, expected, NINE_HOURS as f64, false),
+            GapKind::Awake
+        );
+        assert_eq!(classify_gap(wall, expected, 60.0, true), GapKind::Reboot);
+        assert_eq!(
+            classify_gap(wall, expected, -1000.0, false),
+            GapKind::Reboot
+        );
+        assert_eq!(
+            classify_gap(expected * 2, expected, 0.0, false),
+            GapKind::Regular
+        );
+        assert_eq!(
+            classify_gap(wall, Duration::zero(), 0.0, false),
+            GapKind::Regular
+        );
+    }
+
+    #[test]
+    fn suspensions_are_described_in_minutes_then_hours() {
+        assert_eq!(suspension_text(Duration::minutes(45)), "suspended ~45 min");
+        assert_eq!(
+            suspension_text(Duration::seconds(NINE_HOURS - 60)),
+            "suspended ~9h"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    mod snapshots {
+        use super::*;
+        use crate::testing::{time, SnapshotBuilder};
+
+        /// Snapshots of a single CPU every minute, busy for a tenth of it, as (times, last snapshot).
+        fn every_minute() -> (Vec<DateTime<Utc>>, SnapShot) {
+            let times = (0..10).map(|idx| time(idx * 60)).collect::<Vec<_>>();
+            let last = SnapshotBuilder::at(time(9 * 60)).cpu(54.0, 486.0).build();
+            (times, last)
+        }
+
+        #[test]
+        fn suspensions_are_found_between_snapshots() {
+            let (recent, previous) = every_minute();
+            let resumed = time(9 * 60 + NINE_HOURS);
+
+            // NOTE: A minute of CPU time over 9 hours
+            let suspended = SnapshotBuilder::at(resumed).cpu(60.0, 540.0).build();
+            assert_eq!(
+                interval_gap(&recent, &previous, &suspended),
+                GapKind::Suspended(Duration::seconds(NINE_HOURS - 60))
+            );
+
+            // NOTE: Idle through the 9 hours
+            let idle = SnapshotBuilder::at(resumed)
+                .cpu(55.0, 486.0 + NINE_HOURS as f64)
+                .build();
+            assert_eq!(interval_gap(&recent, &previous, &idle), GapKind::Awake);
+
+            // NOTE: The CPU times restarted from zero
+            let rebooted = SnapshotBuilder::at(resumed).cpu(3.0, 20.0).build();
+            assert_eq!(interval_gap(&recent, &previous, &rebooted), GapKind::Reboot);
+
+            let next = SnapshotBuilder::at(time(10 * 60)).cpu(60.0, 540.0).build();
+            assert_eq!(interval_gap(&recent, &previous, &next), GapKind::Regular);
+        }
+
+        #[test]
+        fn gaps_of_a_changing_cpu_count_are_not_suspensions() {
+            let (recent, previous) = every_minute();
+
+            let unplugged = SnapshotBuilder::at(time(9 * 60 + NINE_HOURS))
+                .cpus(&[(30.0, 240.0), (30.0, 240.0)])
+                .build();
+
+            assert_eq!(interval_gap(&recent, &previous, &unplugged), GapKind::Awake);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Snapshots and databases of the tests, built from given counters instead of the running host.
+// NOTE: Each helper is only used by the tests of some of the features
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    psutil::{CgroupLimits, KernelGauge, KernelGauges, LoadAvg},
+    snapshot::{DiskUsageSample, SnapShot},
+};
+
+pub(crate) const DISK: &str = "sda";
+pub(crate) const MOUNTPOINT: &str = "/";
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Time of the first snapshot of the tests, `time(0)`.
+pub(crate) fn time(seconds: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap() + Duration::seconds(seconds)
+}
+
+fn duration(seconds: f64) -> Value {
+    let duration = std::time::Duration::from_secs_f64(seconds.max(0.0));
+    json!({ "secs": duration.as_secs(), "nanos": duration.subsec_nanos() })
+}
+
+/// Snapshot with counters chosen by the test, the ones left out being zero.
+#[derive(Debug, Clone)]
+pub(crate) struct SnapshotBuilder {
+    time: DateTime<Utc>,
+    /// Seconds since boot of each CPU as (busy, idle).
+    cpus: Vec<(f64, f64)>,
+    /// Seconds since boot of each CPU as (iowait, steal).
+    waits: (f64, f64),
+    /// Bytes as (used, total).
+    memory: (u64, u64),
+    /// Bytes since boot as (received, sent).
+    network: (u64, u64),
+    /// Bytes since boot as (read, written).
+    disk: (u64, u64),
+    /// Pages since boot as (swapped in, swapped out).
+    swapped: (u64, u64),
+    /// Percent used of the root filesystem.
+    disk_usage: f32,
+    load: f64,
+    custom: HashMap<String, f64>,
+    kernel: Option<KernelGauges>,
+    /// Limits of the container the snapshot was taken in, `None` outside of one.
+    cgroup: Option<CgroupLimits>,
+}
+
+impl SnapshotBuilder {
+    pub fn at(time: DateTime<Utc>) -> Self {
+        Self {
+            time,
+            cpus: vec![(0.0, 0.0)],
+            waits: (0.0, 0.0),
+            memory: (0, 16 * GIB),
+            network: (0, 0),
+            disk: (0, 0),
+            swapped: (0, 0),
+            disk_usage: 0.0,
+            load: 0.0,
+            custom: HashMap::new(),
+            kernel: None,
+            cgroup: None,
+        }
+    }
+
+    pub fn cpu(mut self, busy: f64, idle: f64) -> Self {
+        self.cpus = vec![(busy, idle)];
+        self
+    }
+
+    /// CPUs of the snapshot as (busy, idle), their number being the one of the host.
+    pub fn cpus(mut self, cpus: &[(f64, f64)]) -> Self {
+        self.cpus = cpus.to_vec();
+        self
+    }
+
+    /// Time of each CPU waiting for IO and stolen by the hypervisor, on top of its busy and idle times.
+    pub fn waits(mut self, iowait: f64, steal: f64) -> Self {
+        self.waits = (iowait, steal);
+        self
+    }
+
+    pub fn memory(mut self, used: u64, total: u64) -> Self {
+        self.memory = (used, total);
+        self
+    }
+
+    pub fn network(mut self, received: u64, sent: u64) -> Self {
+        self.network = (received, sent);
+        self
+    }
+
+    pub fn disk(mut self, read: u64, written: u64) -> Self {
+        self.disk = (read, written);
+        self
+    }
+
+    pub fn swapped(mut self, swapped_in: u64, swapped_out: u64) -> Self {
+        self.swapped = (swapped_in, swapped_out);
+        self
+    }
+
+    pub fn disk_usage(mut self, percent: f32) -> Self {
+        self.disk_usage = percent;
+        self
+    }
+
+    pub fn load(mut self, one: f64) -> Self {
+        self.load = one;
+        self
+    }
+
+    pub fn custom(mut self, key: &str, value: f64) -> Self {
+        self.custom.insert(key.to_string(), value);
+        self
+    }
+
+    /// Kernel gauge `name` at `value` out of `max`.
+    pub fn kernel(mut self, name: &str, value: f64, max: Option<f64>) -> Self {
+        self.kernel
+            .get_or_insert_with(KernelGauges::default)
+            .0
+            .insert(name.to_string(), KernelGauge { value, max });
+        self
+    }
+
+    /// Taken in a container with the cgroup `limits`.
+    pub fn cgroup(mut self, limits: CgroupLimits) -> Self {
+        self.cgroup = Some(limits);
+        self
+    }
+
+    pub fn build(self) -> SnapShot {
+        let (used, total) = self.memory;
+        let (received, sent) = self.network;
+        let (read, written) = self.disk;
+        let (swapped_in, swapped_out) = self.swapped;
+        let (iowait, steal) = self.waits;
+        let root = DiskUsageSample {
+            total: 100 * GIB,
+            used: (100.0 * GIB as f64 * f64::from(self.disk_usage) / 100.0) as u64,
+            free: (100.0 * GIB as f64 * (1.0 - f64::from(self.disk_usage) / 100.0)) as u64,
+            percent: self.disk_usage,
+        };
+
+        SnapShot {
+            cpus: self
+                .cpus
+                .iter()
+                .map(|(busy, idle)| {
+                    serde_json::from_value(json!({
+                        "user": duration(*busy),
+                        "system": duration(0.0),
+                        "idle": duration(*idle),
+                        "nice": duration(0.0),
+                        "iowait": duration(iowait),
+                        "irq": duration(0.0),
+                        "softirq": duration(0.0),
+                        "steal": duration(steal),
+                        "guest": null,
+                        "guest_nice": null,
+                    }))
+                    .unwrap()
+                })
+                .collect(),
+            memory: serde_json::from_value(json!({
+                "total": total,
+                "available": total - used,
+                "used": used,
+                "free": total - used,
+                "percent": used as f64 / total as f64 * 100.0,
+                "active": 0,
+                "inactive": 0,
+                "buffers": 0,
+                "cached": 0,
+                "shared": 0,
+                "slab": 0,
+            }))
+            .unwrap(),
+            swap: serde_json::from_value(json!({
+                "total": 0,
+                "used": 0,
+                "free": 0,
+                "percent": 0.0,
+                "swapped_in": swapped_in,
+                "swapped_out": swapped_out,
+            }))
+            .unwrap(),
+            networks: vec![serde_json::from_value(json!({
+                "bytes_sent": sent,
+                "bytes_recv": received,
+                "packets_sent": 0,
+                "packets_recv": 0,
+                "err_in": 0,
+                "err_out": 0,
+                "drop_in": 0,
+                "drop_out": 0,
+            }))
+            .unwrap()],
+            disks_io: HashMap::from([(
+                DISK.to_string(),
+                serde_json::from_value(json!({
+                    "read_count": 0,
+                    "write_count": 0,
+                    "read_bytes": read,
+                    "write_bytes": written,
+                    "read_time": duration(0.0),
+                    "write_time": duration(0.0),
+                    "busy_time": duration(0.0),
+                    "read_merged_count": 0,
+                    "write_merged_count": 0,
+                }))
+                .unwrap(),
+            )]),
+            disks_memory: HashMap::from([(MOUNTPOINT.to_string(), self.disk_usage)]),
+            disks_usage: HashMap::from([(MOUNTPOINT.to_string(), root)]),
+            temps: Vec::new(),
+            load_avgs: LoadAvg {
+                one: self.load,
+                five: self.load,
+                fifteen: self.load,
+            },
+            time: self.time,
+            host_facts: None,
+            clock_jump: false,
+            suspended: false,
+            custom: self.custom,
+            cpu_freq: None,
+            collection_duration_ms: None,
+            collection_breakdown_ms: HashMap::new(),
+            kernel: self.kernel,
+            nic_info: HashMap::new(),
+            container: self.cgroup.is_some(),
+            cgroup: self.cgroup,
+        }
+    }
+}
+
+/// Snapshots every `interval` seconds from `time(0)`, the CPU being half busy.
+pub(crate) fn snapshots(count: usize, interval: i64) -> Vec<SnapShot> {
+    (0..count)
+        .map(|idx| {
+            let seconds = idx as i64 * interval;
+            SnapshotBuilder::at(time(seconds))
+                .cpu(seconds as f64 / 2.0, seconds as f64 / 2.0)
+                .build()
+        })
+        .collect()
+}
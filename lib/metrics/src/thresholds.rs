@@ -5,10 +5,14 @@ use crate::{psutil::LoadAvg, Result};
 use log::{debug, trace, tracing};
 use psutil::{
     cpu::{cpu_count, CpuPercentCollector},
-    disk::disk_usage,
+    disk::{disk_usage, partitions},
     memory::{swap_memory, virtual_memory},
+    network::NetIoCountersCollector,
 };
 
+#[cfg(feature = "serde")]
+pub mod state;
+
 #[tracing::instrument(level = "debug")]
 pub fn load_avg_percent() -> Result<(f32, f32, f32)> {
     let cpu_count = cpu_count() as f64;
@@ -77,3 +81,25 @@ pub fn disk_usage_percent() -> Result<f32> {
     debug!(disk_usage_percent = result, "Calculated disk usage");
     Ok(result)
 }
+
+/// Mountpoints of every mounted filesystem, network shares included.
+#[tracing::instrument(level = "debug")]
+pub fn mountpoints() -> Result<Vec<String>> {
+    let result = partitions()?
+        .iter()
+        .map(|partition| partition.mountpoint().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    debug!(?result, "Listed mountpoints");
+    Ok(result)
+}
+
+/// Names of every network interface, down ones included.
+#[tracing::instrument(level = "debug")]
+pub fn network_interfaces() -> Result<Vec<String>> {
+    let result = NetIoCountersCollector::default()
+        .net_io_counters_pernic()?
+        .into_keys()
+        .collect::<Vec<_>>();
+    debug!(?result, "Listed network interfaces");
+    Ok(result)
+}
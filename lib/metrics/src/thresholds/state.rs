@@ -0,0 +1,227 @@
+//! State `sysmet-notify` keeps between its checks, also read by `sysmet-http` to show why an alert was or
+//! wasn't sent.
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const NOTIFY_STATE_VERSION: u32 = 1;
+
+/// Whole state file, one entry per host checked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotifyState {
+    pub schema_version: u32,
+    /// Time of the last check, even one skipped by the cooldown.
+    pub checked_at: Option<DateTime<Utc>>,
+    /// Cooldown of the last check, the time after a sent mail before the next one can be.
+    pub cooldown_seconds: u64,
+    /// By hostname.
+    #[serde(default)]
+    pub hosts: BTreeMap<String, HostState>,
+}
+
+impl Default for NotifyState {
+    fn default() -> Self {
+        Self {
+            schema_version: NOTIFY_STATE_VERSION,
+            checked_at: None,
+            cooldown_seconds: 0,
+            hosts: BTreeMap::new(),
+        }
+    }
+}
+
+impl NotifyState {
+    /// State of the files written before it was structured, only holding the last mail of each host.
+    pub fn from_cooldowns(last_sent: BTreeMap<String, DateTime<Utc>>) -> Self {
+        Self {
+            hosts: last_sent
+                .into_iter()
+                .map(|(hostname, last_sent)| {
+                    let host = HostState {
+                        last_sent: Some(last_sent),
+                        ..Default::default()
+                    };
+                    (hostname, host)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_std(std::time::Duration::from_secs(self.cooldown_seconds))
+            .unwrap_or(Duration::max_value())
+    }
+
+    /// End of the cooldown of `hostname`, `None` when an alert can be sent right away.
+    pub fn next_eligible(&self, hostname: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let last_sent = self.hosts.get(hostname)?.last_sent?;
+        // NOTE: A cooldown too long to be added never ends
+        Some(
+            last_sent
+                .checked_add_signed(self.cooldown())
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        )
+        .filter(|end_of_cooldown| *end_of_cooldown > now)
+    }
+
+    /// The last check is older than `max_age`, e.g. `sysmet-notify` no longer runs.
+    pub fn is_stale(&self, now: DateTime<Utc>, max_age: Duration) -> bool {
+        self.checked_at
+            .is_none_or(|checked_at| now - checked_at > max_age)
+    }
+}
+
+/// State of a host at its last check.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostState {
+    /// Time of the last mail sent about the host, which starts its cooldown.
+    pub last_sent: Option<DateTime<Utc>>,
+    /// Checked metrics by identifier, e.g. `cpu` or `mount`.
+    #[serde(default)]
+    pub metrics: BTreeMap<String, MetricState>,
+    /// Reason the host could not be checked, e.g. a stale database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+    /// Last attempt to send a mail about the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt: Option<SendAttempt>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Ok,
+    Alert,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricState {
+    pub status: AlertStatus,
+    /// Time the metric last crossed its threshold or went missing.
+    pub last_alert: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendAttempt {
+    pub at: DateTime<Utc>,
+    pub result: SendResult,
+}
+
+/// Outcome of sending a mail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum SendResult {
+    Sent,
+    Failed { error: String },
+}
+
+impl SendResult {
+    pub fn is_sent(&self) -> bool {
+        matches!(self, SendResult::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    fn time(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn sent_at(last_sent: Option<DateTime<Utc>>) -> NotifyState {
+        NotifyState {
+            checked_at: Some(time(0)),
+            cooldown_seconds: 3600,
+            hosts: BTreeMap::from([(
+                "web".to_string(),
+                HostState {
+                    last_sent,
+                    ..HostState::default()
+                },
+            )]),
+            ..NotifyState::default()
+        }
+    }
+
+    #[test]
+    fn alerts_are_eligible_once_the_cooldown_ends() {
+        let state = sent_at(Some(time(0)));
+        assert_eq!(state.next_eligible("web", time(30)), Some(time(60)));
+        assert_eq!(state.next_eligible("web", time(60)), None);
+        assert_eq!(state.next_eligible("db", time(30)), None);
+        assert_eq!(sent_at(None).next_eligible("web", time(30)), None);
+
+        let endless = NotifyState {
+            cooldown_seconds: u64::MAX,
+            ..sent_at(Some(time(0)))
+        };
+        assert_eq!(
+            endless.next_eligible("web", time(30)),
+            Some(DateTime::<Utc>::MAX_UTC)
+        );
+    }
+
+    #[test]
+    fn states_without_a_recent_check_are_stale() {
+        let state = sent_at(None);
+        assert!(!state.is_stale(time(60), Duration::hours(1)));
+        assert!(state.is_stale(time(61), Duration::hours(1)));
+        assert!(NotifyState::default().is_stale(time(0), Duration::hours(1)));
+    }
+
+    #[test]
+    fn older_files_only_give_the_cooldowns() {
+        let state = NotifyState::from_cooldowns(BTreeMap::from([("web".to_string(), time(0))]));
+        assert_eq!(state.schema_version, NOTIFY_STATE_VERSION);
+        assert_eq!(state.checked_at, None);
+        assert_eq!(
+            state.hosts["web"],
+            HostState {
+                last_sent: Some(time(0)),
+                ..HostState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn states_are_written_in_the_shape_read_by_the_dashboard() {
+        let mut state = sent_at(Some(time(0)));
+        let host = state.hosts.get_mut("web").unwrap();
+        host.metrics.insert(
+            "cpu".to_string(),
+            MetricState {
+                status: AlertStatus::Alert,
+                last_alert: Some(time(0)),
+            },
+        );
+        host.last_attempt = Some(SendAttempt {
+            at: time(0),
+            result: SendResult::Failed {
+                error: "connection refused".to_string(),
+            },
+        });
+
+        let value = serde_json::to_value(&state).unwrap();
+        assert_eq!(value["hosts"]["web"]["metrics"]["cpu"]["status"], "alert");
+        assert_eq!(
+            value["hosts"]["web"]["last_attempt"]["result"],
+            json!({ "status": "failed", "error": "connection refused" })
+        );
+        assert!(value["hosts"]["web"].get("problem").is_none());
+        assert_eq!(serde_json::from_value::<NotifyState>(value).unwrap(), state);
+
+        let without_hosts = serde_json::from_value::<NotifyState>(json!({
+            "schema_version": 1,
+            "checked_at": null,
+            "cooldown_seconds": 60,
+        }))
+        .unwrap();
+        assert!(without_hosts.hosts.is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! Peak memory of loading a large database, streamed or read whole into memory first.
+//!
+//! Each load runs in a child process as the peak resident size only grows within one.
+#![cfg(all(feature = "streaming", feature = "demo", target_os = "linux"))]
+
+use std::{env, fs, process::Command};
+
+use chrono::{Duration, TimeZone, Utc};
+use metrics::{
+    demo::{DemoOptions, DEFAULT_DEMO_SEED},
+    prelude::*,
+};
+
+const DATABASE_SIZE: u64 = 200 * 1024 * 1024;
+/// Selects the load done by the child process, as `<streamed|whole>:<path>`.
+const LOAD_ENV: &str = "SYSMET_PEAK_RSS_LOAD";
+const TEST_NAME: &str = "streamed_load_peak_rss_stays_below_the_whole_file_load";
+
+/// Peak resident size of the process in bytes, from `VmHWM` of `/proc/self/status`.
+fn peak_rss() -> u64 {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .expect("VmHWM missing from /proc/self/status");
+    kib * 1024
+}
+
+fn demo(snapshots: i32) -> Database {
+    let mut database = Database::demo(&DemoOptions {
+        end: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        span: Duration::seconds(10) * (snapshots - 1),
+        interval: Duration::seconds(10),
+        seed: DEFAULT_DEMO_SEED,
+    })
+    .unwrap();
+    database.set_checksums(true);
+    database
+}
+
+/// Peak resident size of a child process loading the database of `path` with `load`.
+fn child_peak_rss(load: &str, path: &str) -> u64 {
+    let output = Command::new(env::current_exe().unwrap())
+        .args([TEST_NAME, "--exact", "--ignored", "--nocapture"])
+        .env(LOAD_ENV, format!("{load}:{path}"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.split_once("peak_rss=").map(|(_, peak)| peak))
+        .and_then(|peak| peak.parse().ok())
+        .unwrap_or_else(|| panic!("no peak reported by the {load} load: {output:?}"))
+}
+
+#[test]
+#[ignore = "writes a 200MB database, run it with --release"]
+fn streamed_load_peak_rss_stays_below_the_whole_file_load() {
+    if let Ok(load) = env::var(LOAD_ENV) {
+        let (load, path) = load.split_once(':').unwrap();
+        let database = match load {
+            "streamed" => Database::open_readonly_streamed(path, None),
+            _ => Database::from_file(path, None),
+        }
+        .unwrap();
+        assert!(!database.is_empty());
+        println!("peak_rss={}", peak_rss());
+        return;
+    }
+
+    let dir = env::temp_dir().join(format!("sysmet-peak-rss-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sysmet.db").to_string_lossy().into_owned();
+
+    // NOTE: The size of a sample gives the number of snapshots making the database about 200MB
+    demo(1000).write_to_file(&path).unwrap();
+    let per_snapshot = fs::metadata(&path).unwrap().len() / 1000;
+    let snapshots = i32::try_from(DATABASE_SIZE / per_snapshot).unwrap();
+    demo(snapshots).write_to_file(&path).unwrap();
+    let size = fs::metadata(&path).unwrap().len();
+
+    let whole = child_peak_rss("whole", &path);
+    let streamed = child_peak_rss("streamed", &path);
+    fs::remove_dir_all(&dir).unwrap();
+    println!(
+        "database of {size} bytes, peak RSS {whole} bytes read whole and {streamed} bytes streamed"
+    );
+
+    // NOTE: Streaming saves the copy of the file, half of it leaves room for the allocator
+    assert!(
+        streamed + size / 2 < whole,
+        "streamed peak {streamed} not below whole peak {whole} by half the database size {size}"
+    );
+}
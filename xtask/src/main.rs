@@ -1,9 +1,60 @@
-use std::process::ExitStatus;
+use std::{fs, process::ExitStatus};
 
 use clap::Parser;
+use color_eyre::eyre;
+
+mod release;
 
 type Result<T> = color_eyre::Result<T>;
 
+/// Features of the smallest build of each binary, built with `--no-default-features`.
+const SLIM_FEATURES: &[(&str, &str)] = &[
+    ("sysmet-update", ""),
+    ("sysmet-http", ""),
+    ("sysmet-notify", ""),
+];
+
+/// Documented feature combinations as (package, features), each checked with `--no-default-features`.
+const FEATURE_MATRIX: &[(&str, &str)] = &[
+    ("log", ""),
+    ("log", "hierarchical"),
+    ("log", "honeycomb"),
+    ("metrics", ""),
+    ("metrics", "serde"),
+    ("metrics", "sensors"),
+    ("metrics", "database"),
+    ("metrics", "database,streaming"),
+    ("metrics", "diff"),
+    ("metrics", "demo"),
+    ("metrics", "thresholds"),
+    ("metrics", "import"),
+    ("metrics", "schema"),
+    ("sysmet-update", ""),
+    ("sysmet-update", "sensors"),
+    ("sysmet-update", "import"),
+    ("sysmet-update", "export"),
+    ("sysmet-update", "diff"),
+    ("sysmet-update", "demo"),
+    ("sysmet-update", "tree-logs"),
+    ("sysmet-update", "sensors,import,export,diff,demo,tree-logs"),
+    ("sysmet-http", ""),
+    ("sysmet-http", "tree-logs"),
+    ("sysmet-http", "streaming"),
+    ("sysmet-notify", ""),
+];
+
+/// Directory of each package of the matrix, from the root of the workspace.
+///
+/// The packages are checked by their manifest as a name can be ambiguous, e.g. `log` is both the workspace
+/// crate and the one of crates.io.
+const MANIFESTS: &[(&str, &str)] = &[
+    ("log", "lib/log"),
+    ("metrics", "lib/metrics"),
+    ("sysmet-update", "bin/sysmet-update"),
+    ("sysmet-http", "bin/sysmet-http"),
+    ("sysmet-notify", "bin/sysmet-notify"),
+];
+
 #[derive(clap::Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
@@ -24,6 +75,22 @@ enum Command {
         /// The name of the app
         name: String,
     },
+    /// Build a binary in release with its smallest feature set and print its size
+    BuildSlim {
+        /// The name of the binary, e.g. sysmet-update
+        binary: String,
+    },
+    /// Check that each documented feature combination compiles
+    FeatureMatrix,
+    /// Build the binaries in release, smoke test them then package them with their checksums in target/dist
+    Release {
+        /// Also build and package for this target triple, can be repeated
+        #[clap(long)]
+        target: Vec<String>,
+        /// Print the commands instead of running them
+        #[clap(long)]
+        dry_run: bool,
+    },
 }
 
 #[tokio::main]
@@ -42,6 +109,95 @@ async fn main() {
         ))
         .await
         .unwrap(),
+        Command::BuildSlim { binary } => build_slim(&workspace_root, &binary).await.unwrap(),
+        Command::FeatureMatrix => feature_matrix(&release::Shell, &workspace_root)
+            .await
+            .unwrap(),
+        Command::Release { target, dry_run } => if dry_run {
+            release::release(&release::Printer, &workspace_root, &target).await
+        } else {
+            release::release(&release::Shell, &workspace_root, &target).await
+        }
+        .unwrap(),
+    }
+}
+
+/// `--no-default-features` and the `features` given, if any.
+fn features_args(features: &str) -> String {
+    if features.is_empty() {
+        "--no-default-features".to_string()
+    } else {
+        format!("--no-default-features --features {features}")
+    }
+}
+
+async fn build_slim(workspace_root: &str, binary: &str) -> Result<()> {
+    let Some((_, features)) = SLIM_FEATURES.iter().find(|(name, _)| *name == binary) else {
+        return Err(eyre::eyre!(
+            "Unknown binary {binary}, expected one of {}",
+            SLIM_FEATURES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    };
+    exec(&format!(
+        "cargo build --release --package {binary} {}",
+        features_args(features)
+    ))
+    .await?;
+
+    let size = fs::metadata(format!("{workspace_root}target/release/{binary}"))?.len();
+    println!(
+        "{binary} ({}): {size} bytes ({:.2} MiB)",
+        if features.is_empty() {
+            "no features"
+        } else {
+            features
+        },
+        size as f64 / (1024.0 * 1024.0)
+    );
+    Ok(())
+}
+
+/// `cargo check` of `package` with `features`, by the path of its manifest.
+fn check_command(workspace_root: &str, package: &str, features: &str) -> Result<String> {
+    let (_, dir) = MANIFESTS
+        .iter()
+        .find(|(name, _)| *name == package)
+        .ok_or_else(|| eyre::eyre!("No manifest known for the package {package}"))?;
+    Ok(format!(
+        "cargo check --manifest-path {workspace_root}{dir}/Cargo.toml {}",
+        features_args(features)
+    ))
+}
+
+/// Check every combination of `FEATURE_MATRIX`, reporting all of those that failed.
+pub(crate) async fn feature_matrix(
+    runner: &impl release::Runner,
+    workspace_root: &str,
+) -> Result<()> {
+    let mut failed = Vec::new();
+    for (package, features) in FEATURE_MATRIX {
+        println!("Checking {package} with [{features}]");
+        if let Err(err) = runner
+            .exec(&check_command(workspace_root, package, features)?)
+            .await
+        {
+            eprintln!("{err}");
+            failed.push(format!("{package} [{features}]"));
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre::eyre!(
+            "{} combinations failed to compile: {}",
+            failed.len(),
+            failed.join(", ")
+        ))
     }
 }
 
@@ -95,7 +251,6 @@ async fn exec(command: &str) -> Result<()> {
     }
 }
 
-#[allow(dead_code)]
 async fn exec_script(command: &str) -> eyre::Result<()> {
     let output = tokio::process::Command::new("bash")
         .args(["-c", command])
@@ -116,3 +271,86 @@ async fn exec_script(command: &str) -> eyre::Result<()> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Features declared in `[features]` of the manifest of `dir`, without `default`.
+    fn declared_features(dir: &str) -> Vec<String> {
+        let path = format!("{}/../{dir}/Cargo.toml", env!("CARGO_MANIFEST_DIR"));
+        let manifest = fs::read_to_string(&path).unwrap();
+        manifest
+            .lines()
+            .skip_while(|line| line.trim() != "[features]")
+            .skip(1)
+            .take_while(|line| !line.trim_start().starts_with('['))
+            .filter_map(|line| {
+                let (key, _) = line.split_once('=')?;
+                let key = key.trim();
+                (!key.starts_with('#') && key != "default").then(|| key.to_string())
+            })
+            .collect()
+    }
+
+    fn in_matrix(package: &str, feature: &str) -> bool {
+        FEATURE_MATRIX.iter().any(|(name, features)| {
+            *name == package && features.split(',').any(|checked| checked == feature)
+        })
+    }
+
+    #[test]
+    fn every_declared_feature_is_in_the_matrix() {
+        let missing: Vec<_> = MANIFESTS
+            .iter()
+            .flat_map(|(package, dir)| {
+                declared_features(dir)
+                    .into_iter()
+                    .filter(|feature| !in_matrix(package, feature))
+                    .map(move |feature| format!("{package}/{feature}"))
+            })
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "missing from FEATURE_MATRIX: {missing:?}"
+        );
+    }
+
+    #[test]
+    fn slim_builds_are_in_the_matrix() {
+        for slim in SLIM_FEATURES {
+            assert!(
+                FEATURE_MATRIX.contains(slim),
+                "{slim:?} missing from FEATURE_MATRIX"
+            );
+        }
+    }
+
+    #[test]
+    fn matrix_packages_have_a_manifest() {
+        for (package, _) in FEATURE_MATRIX {
+            assert!(
+                MANIFESTS.iter().any(|(name, _)| name == package),
+                "{package} has no manifest"
+            );
+        }
+    }
+
+    #[test]
+    fn packages_are_checked_by_their_manifest() {
+        assert_eq!(
+            check_command("/src/sysmet/", "log", "").unwrap(),
+            "cargo check --manifest-path /src/sysmet/lib/log/Cargo.toml --no-default-features"
+        );
+        assert_eq!(
+            check_command("", "metrics", "database,streaming").unwrap(),
+            "cargo check --manifest-path lib/metrics/Cargo.toml --no-default-features --features database,streaming"
+        );
+        assert!(check_command("", "portfolio", "").is_err());
+
+        for (package, features) in FEATURE_MATRIX {
+            let command = check_command("", package, features).unwrap();
+            assert!(!command.contains("--package"), "{command}");
+        }
+    }
+}
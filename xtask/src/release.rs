@@ -0,0 +1,439 @@
+//! `cargo xtask release`: build, smoke test, package and checksum the binaries.
+use std::{fs, net::TcpListener};
+
+use color_eyre::eyre::{self, WrapErr};
+
+use crate::{exec, exec_script, feature_matrix, Result};
+
+/// Binaries shipped in each archive.
+const BINARIES: [&str; 3] = ["sysmet-update", "sysmet-http", "sysmet-notify"];
+/// Database path of the sample unit and cron line.
+const SAMPLE_DATABASE: &str = "/var/lib/sysmet/db";
+
+/// Runs the commands of a release, the stages only going through it so they can be stubbed.
+pub(crate) trait Runner {
+    /// Run a program with its arguments split on whitespace, see `exec`.
+    async fn exec(&self, command: &str) -> Result<()>;
+    /// Run a bash script, see `exec_script`.
+    async fn exec_script(&self, script: &str) -> Result<()>;
+    fn write(&self, path: &str, contents: &str) -> Result<()>;
+}
+
+/// Runs everything for real.
+pub(crate) struct Shell;
+
+impl Runner for Shell {
+    async fn exec(&self, command: &str) -> Result<()> {
+        exec(command).await
+    }
+
+    async fn exec_script(&self, script: &str) -> Result<()> {
+        exec_script(script).await
+    }
+
+    fn write(&self, path: &str, contents: &str) -> Result<()> {
+        Ok(fs::write(path, contents)?)
+    }
+}
+
+/// Only prints what would be run, for `--dry-run`.
+pub(crate) struct Printer;
+
+impl Runner for Printer {
+    async fn exec(&self, command: &str) -> Result<()> {
+        println!("$ {command}");
+        Ok(())
+    }
+
+    async fn exec_script(&self, script: &str) -> Result<()> {
+        println!("$ bash -c '{script}'");
+        Ok(())
+    }
+
+    fn write(&self, path: &str, contents: &str) -> Result<()> {
+        println!("# {path}\n{contents}");
+        Ok(())
+    }
+}
+
+/// Check the feature matrix, build the binaries for the host and each of `targets`, smoke test those of the
+/// host then package each target in `target/dist` with its checksum.
+pub(crate) async fn release(
+    runner: &impl Runner,
+    workspace_root: &str,
+    targets: &[String],
+) -> Result<()> {
+    let version = workspace_version(workspace_root)?;
+    feature_matrix(runner, workspace_root)
+        .await
+        .wrap_err("Feature matrix failed")?;
+    let packages = BINARIES
+        .map(|binary| format!("--package {binary}"))
+        .join(" ");
+    let dist = format!("{workspace_root}target/dist");
+
+    // NOTE: The host is built without --target so its binaries are the ones `cargo run` uses
+    let mut builds = vec![(
+        host_target(),
+        format!("{workspace_root}target/release"),
+        None,
+    )];
+    builds.extend(targets.iter().map(|target| {
+        (
+            target.clone(),
+            format!("{workspace_root}target/{target}/release"),
+            Some(target),
+        )
+    }));
+
+    for (name, _, target) in &builds {
+        let target_arg = target.map_or_else(String::new, |target| format!("--target {target}"));
+        runner
+            .exec(&format!("cargo build --release {packages} {target_arg}"))
+            .await
+            .wrap_err_with(|| format!("Build for {name} failed"))?;
+    }
+
+    let (_, host_dir, _) = &builds[0];
+    smoke_test(runner, workspace_root, host_dir).await?;
+
+    runner
+        .exec_script(&format!("rm -rf {dist} && mkdir -p {dist}"))
+        .await
+        .wrap_err("Failed to prepare target/dist")?;
+    let mut archives = Vec::new();
+    for (name, dir, _) in &builds {
+        let archive = package(runner, &dist, &version, name, dir)
+            .await
+            .wrap_err_with(|| format!("Packaging for {name} failed"))?;
+        archives.push(archive);
+    }
+
+    runner
+        .exec_script(&format!(
+            "cd {dist} && sha256sum {} | tee SHA256SUMS",
+            archives.join(" ")
+        ))
+        .await
+        .wrap_err("Failed to compute the checksums")?;
+    Ok(())
+}
+
+/// Take a snapshot into a new database, serve it and check it with the binaries of `dir`.
+async fn smoke_test(runner: &impl Runner, workspace_root: &str, dir: &str) -> Result<()> {
+    let smoke = format!("{workspace_root}target/release-smoke");
+    let database = format!("{smoke}/sysmet.db");
+    runner
+        .exec_script(&format!("rm -rf {smoke} && mkdir -p {smoke}"))
+        .await
+        .wrap_err("Smoke test failed: could not prepare target/release-smoke")?;
+
+    runner
+        .exec(&format!("{dir}/sysmet-update --database {database}"))
+        .await
+        .wrap_err("Smoke test failed: sysmet-update could not take a snapshot")?;
+
+    let port = free_port()?;
+    // NOTE: The server is killed however the requests end, it is retried until it listens
+    runner
+        .exec_script(&format!(
+            "{dir}/sysmet-http --database {database} --listen 127.0.0.1:{port} & server=$!; \
+             trap 'kill $server' EXIT; \
+             curl --fail --silent --show-error --retry 10 --retry-connrefused --retry-delay 1 \
+             http://127.0.0.1:{port}/health > /dev/null \
+             && curl --fail --silent --show-error http://127.0.0.1:{port}/ > /dev/null"
+        ))
+        .await
+        .wrap_err("Smoke test failed: sysmet-http did not serve /health and /")?;
+
+    runner
+        .exec(&format!(
+            "{dir}/sysmet-notify --dry-run --database {database} --state-path {smoke}/notify-state.json \
+             --last-sent-path {smoke}/last-mail.txt"
+        ))
+        .await
+        .wrap_err("Smoke test failed: sysmet-notify --dry-run failed")?;
+    Ok(())
+}
+
+/// Archive the binaries of `dir` with the sample unit and cron line, returning the name of the archive.
+async fn package(
+    runner: &impl Runner,
+    dist: &str,
+    version: &str,
+    target: &str,
+    dir: &str,
+) -> Result<String> {
+    let name = format!("sysmet-{version}-{target}");
+    let staging = format!("{dist}/{name}");
+    runner
+        .exec_script(&format!(
+            "mkdir -p {staging} && cp {} {staging}/",
+            BINARIES.map(|binary| format!("{dir}/{binary}")).join(" ")
+        ))
+        .await?;
+    runner.write(&format!("{staging}/sysmet-http.service"), &systemd_unit())?;
+    runner.write(&format!("{staging}/sysmet-update.cron"), &cron_line())?;
+
+    let archive = format!("{name}.tar.gz");
+    runner
+        .exec(&format!("tar -czf {dist}/{archive} -C {dist} {name}"))
+        .await?;
+    Ok(archive)
+}
+
+fn systemd_unit() -> String {
+    format!(
+        "[Unit]
+Description=sysmet dashboard
+After=network.target
+
+[Service]
+ExecStart=/usr/local/bin/sysmet-http --database {SAMPLE_DATABASE} --listen 127.0.0.1:8080
+User=sysmet
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+"
+    )
+}
+
+fn cron_line() -> String {
+    format!(
+        "# Snapshot every 5 minutes keeping 30 days, e.g. in /etc/cron.d/sysmet\n\
+         */5 * * * * sysmet /usr/local/bin/sysmet-update --database {SAMPLE_DATABASE} --cleanup-older 30\n"
+    )
+}
+
+/// Version of `[workspace.package]` in the workspace manifest.
+fn workspace_version(workspace_root: &str) -> Result<String> {
+    let manifest = fs::read_to_string(format!("{workspace_root}Cargo.toml"))
+        .wrap_err("Failed to read the workspace manifest")?;
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[workspace.package]")
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "version").then(|| value.trim().trim_matches('"').to_string())
+        })
+        .ok_or_else(|| eyre::eyre!("No version in [workspace.package] of the workspace manifest"))
+}
+
+/// Name of the host in the archives, e.g. `x86_64-linux`.
+fn host_target() -> String {
+    format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// Port free on the loopback right now, the OS picking it.
+fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").wrap_err("No free port for the smoke test")?;
+    Ok(listener.local_addr()?.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    const TARGET: &str = "aarch64-unknown-linux-gnu";
+
+    /// Records the commands and the written paths, failing the commands containing `fail_on`.
+    #[derive(Default)]
+    struct Recorder {
+        fail_on: Option<&'static str>,
+        commands: RefCell<Vec<String>>,
+        written: RefCell<Vec<(String, String)>>,
+    }
+
+    impl Recorder {
+        fn failing_on(command: &'static str) -> Self {
+            Self {
+                fail_on: Some(command),
+                ..Self::default()
+            }
+        }
+
+        fn run(&self, command: &str) -> Result<()> {
+            self.commands.borrow_mut().push(command.to_string());
+            match self.fail_on {
+                Some(fail_on) if command.contains(fail_on) => Err(eyre::eyre!(
+                    "Error, command {command} exited with status code 1"
+                )),
+                _ => Ok(()),
+            }
+        }
+
+        /// Position of the first command containing `part`.
+        fn position(&self, part: &str) -> Option<usize> {
+            self.commands
+                .borrow()
+                .iter()
+                .position(|command| command.contains(part))
+        }
+    }
+
+    impl Runner for Recorder {
+        async fn exec(&self, command: &str) -> Result<()> {
+            self.run(command)
+        }
+
+        async fn exec_script(&self, script: &str) -> Result<()> {
+            self.run(script)
+        }
+
+        fn write(&self, path: &str, contents: &str) -> Result<()> {
+            self.written
+                .borrow_mut()
+                .push((path.to_string(), contents.to_string()));
+            Ok(())
+        }
+    }
+
+    fn workspace_root() -> String {
+        format!("{}/../", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    async fn release_with(runner: &Recorder) -> Result<()> {
+        release(runner, &workspace_root(), &[TARGET.to_string()]).await
+    }
+
+    #[tokio::test]
+    async fn releases_check_build_smoke_test_then_package() {
+        let runner = Recorder::default();
+
+        release_with(&runner).await.unwrap();
+
+        let version = workspace_version(&workspace_root()).unwrap();
+        let stages = [
+            "cargo check --manifest-path",
+            "cargo build --release --package sysmet-update --package sysmet-http --package sysmet-notify",
+            &format!("--target {TARGET}"),
+            "sysmet-update --database",
+            "/health",
+            "sysmet-notify --dry-run",
+            &format!("tar -czf {}target/dist/sysmet-{version}-{}.tar.gz", workspace_root(), host_target()),
+            &format!("sysmet-{version}-{TARGET}.tar.gz -C"),
+            "sha256sum",
+        ];
+        let positions = stages
+            .iter()
+            .map(|stage| {
+                runner
+                    .position(stage)
+                    .unwrap_or_else(|| panic!("{stage} not run"))
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            positions.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:#?}",
+            runner.commands.borrow()
+        );
+        // NOTE: Only the binaries of the host can be run
+        assert_eq!(
+            runner
+                .commands
+                .borrow()
+                .iter()
+                .filter(|command| command.contains("sysmet-update --database"))
+                .count(),
+            1
+        );
+        assert!(
+            runner.commands.borrow()[runner.position("sysmet-update --database").unwrap()]
+                .starts_with(&format!("{}target/release/", workspace_root()))
+        );
+        let written = runner.written.borrow();
+        assert_eq!(written.len(), 4);
+        assert!(written
+            .iter()
+            .all(|(path, contents)| contents.contains(SAMPLE_DATABASE) && path.contains(&version)));
+    }
+
+    #[tokio::test]
+    async fn every_combination_is_checked_by_manifest_before_building() {
+        let runner = Recorder::default();
+
+        release_with(&runner).await.unwrap();
+
+        let commands = runner.commands.borrow();
+        let build = runner.position("cargo build").unwrap();
+        let checks = commands
+            .iter()
+            .enumerate()
+            .filter(|(_, command)| command.starts_with("cargo check"))
+            .collect::<Vec<_>>();
+        assert_eq!(checks.len(), crate::FEATURE_MATRIX.len());
+        assert!(checks.iter().all(|(idx, _)| *idx < build));
+        let log = format!(
+            "cargo check --manifest-path {}lib/log/Cargo.toml --no-default-features",
+            workspace_root()
+        );
+        assert_eq!(checks[0].1, &log);
+        assert!(
+            checks
+                .iter()
+                .all(|(_, command)| !command.contains("--package")),
+            "{checks:#?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_stages_abort_the_release() {
+        for (fail_on, message, not_run) in [
+            ("cargo check", "Feature matrix failed", "cargo build"),
+            (
+                TARGET,
+                &*format!("Build for {TARGET} failed"),
+                "sysmet-update --database",
+            ),
+            (
+                "sysmet-update --database",
+                "Smoke test failed: sysmet-update could not take a snapshot",
+                "/health",
+            ),
+            (
+                "/health",
+                "Smoke test failed: sysmet-http did not serve /health and /",
+                "tar -czf",
+            ),
+            (
+                "sysmet-notify --dry-run",
+                "Smoke test failed: sysmet-notify --dry-run failed",
+                "tar -czf",
+            ),
+            (
+                "tar -czf",
+                &*format!("Packaging for {} failed", host_target()),
+                "sha256sum",
+            ),
+        ] {
+            let runner = Recorder::failing_on(fail_on);
+
+            let err = release_with(&runner).await.unwrap_err();
+
+            assert_eq!(err.to_string(), message);
+            assert_eq!(runner.position(not_run), None, "{fail_on}");
+        }
+    }
+
+    #[test]
+    fn versions_are_read_from_the_workspace_package() {
+        let dir = std::env::temp_dir().join(format!("xtask-release-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let root = format!("{}/", dir.display());
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[workspace]\nmembers = []\n\n[workspace.package]\nedition = \"2021\"\nversion = \"1.2.3\"\n\n[profile.release]\nversion = \"9\"\n",
+        )
+        .unwrap();
+        assert_eq!(workspace_version(&root).unwrap(), "1.2.3");
+
+        fs::write(dir.join("Cargo.toml"), "[workspace]\nmembers = []\n").unwrap();
+        assert!(workspace_version(&root).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}